@@ -0,0 +1,6 @@
+-- Short codes that redirect to long chart URLs
+CREATE TABLE short_links (
+    code TEXT PRIMARY KEY NOT NULL,
+    target TEXT NOT NULL UNIQUE,
+    created_at INTEGER NOT NULL
+);
@@ -0,0 +1,8 @@
+-- Storage precision applied to incoming values, per metric
+CREATE TABLE metric_precision (
+    namespace TEXT NOT NULL,
+    id TEXT NOT NULL,
+    decimals INTEGER,
+    quantum REAL,
+    PRIMARY KEY (namespace, id)
+);
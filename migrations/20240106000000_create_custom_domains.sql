@@ -0,0 +1,7 @@
+-- Hostnames that serve a single namespace at their root
+CREATE TABLE custom_domains (
+    host TEXT PRIMARY KEY NOT NULL,
+    namespace TEXT NOT NULL,
+    title TEXT,
+    theme TEXT NOT NULL DEFAULT 'light'
+);
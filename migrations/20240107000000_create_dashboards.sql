@@ -0,0 +1,17 @@
+-- Named pages pinning charts from any namespace
+CREATE TABLE dashboards (
+    slug TEXT PRIMARY KEY NOT NULL,
+    title TEXT NOT NULL,
+    edit_token TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+
+-- The charts on each dashboard, in display order
+CREATE TABLE dashboard_charts (
+    slug TEXT NOT NULL REFERENCES dashboards (slug) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    namespace TEXT NOT NULL,
+    id TEXT NOT NULL,
+    PRIMARY KEY (slug, position)
+);
@@ -0,0 +1,7 @@
+-- How a metric is displayed by default; query parameters override these
+CREATE TABLE metric_meta (
+    namespace TEXT NOT NULL,
+    id TEXT NOT NULL,
+    scale TEXT,
+    PRIMARY KEY (namespace, id)
+);
@@ -0,0 +1,11 @@
+-- Namespace-wide events such as deploys and releases, drawn on every chart
+CREATE TABLE markers (
+    marker_id INTEGER PRIMARY KEY,
+    namespace TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    label TEXT NOT NULL,
+    url TEXT,
+    source TEXT NOT NULL
+);
+
+CREATE INDEX idx_markers_namespace_timestamp ON markers (namespace, timestamp);
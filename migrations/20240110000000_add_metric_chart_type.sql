@@ -0,0 +1,2 @@
+-- Default chart type per metric, such as bars for discrete daily counts
+ALTER TABLE metric_meta ADD COLUMN chart_type TEXT;
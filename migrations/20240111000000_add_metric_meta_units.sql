@@ -0,0 +1,4 @@
+-- How a metric's values read: a unit suffix, a description and display decimals
+ALTER TABLE metric_meta ADD COLUMN unit TEXT;
+ALTER TABLE metric_meta ADD COLUMN description TEXT;
+ALTER TABLE metric_meta ADD COLUMN decimals INTEGER;
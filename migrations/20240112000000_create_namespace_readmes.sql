@@ -0,0 +1,7 @@
+-- Markdown shown at the top of a namespace page; whoever writes it first gets the edit token
+CREATE TABLE namespace_readmes (
+    namespace TEXT PRIMARY KEY NOT NULL,
+    body TEXT NOT NULL,
+    edit_token TEXT NOT NULL,
+    updated_at INTEGER NOT NULL
+);
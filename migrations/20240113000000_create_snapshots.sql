@@ -0,0 +1,11 @@
+-- Frozen copies of a series, served at /s/{token} alongside short links
+CREATE TABLE snapshots (
+    token TEXT PRIMARY KEY NOT NULL,
+    namespace TEXT NOT NULL,
+    id TEXT NOT NULL,
+    -- JSON array of {timestamp, value}, exactly as the chart page had it
+    points TEXT NOT NULL,
+    scale TEXT NOT NULL,
+    chart_type TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
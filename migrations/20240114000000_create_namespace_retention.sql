@@ -0,0 +1,6 @@
+-- Per-namespace overrides of the global retention window; NULL days keeps
+-- points forever
+CREATE TABLE namespace_retention (
+    namespace TEXT PRIMARY KEY NOT NULL,
+    days INTEGER
+);
@@ -0,0 +1,21 @@
+-- Hourly and daily aggregates of closed time buckets, served in place of
+-- raw points for old parts of a chart
+CREATE TABLE metric_rollups (
+    namespace TEXT NOT NULL,
+    id TEXT NOT NULL,
+    -- Bucket width in seconds
+    resolution INTEGER NOT NULL,
+    -- Start of the bucket
+    bucket INTEGER NOT NULL,
+    count INTEGER NOT NULL,
+    sum REAL NOT NULL,
+    min REAL NOT NULL,
+    max REAL NOT NULL,
+    PRIMARY KEY (namespace, id, resolution, bucket)
+);
+
+-- Buckets starting before `through` have been rolled up
+CREATE TABLE rollup_progress (
+    resolution INTEGER PRIMARY KEY NOT NULL,
+    through INTEGER NOT NULL
+);
@@ -0,0 +1,13 @@
+-- Most points a series keeps; the oldest are trimmed as new ones arrive
+CREATE TABLE namespace_point_caps (
+    namespace TEXT PRIMARY KEY NOT NULL,
+    max_points INTEGER NOT NULL
+);
+
+-- Per-metric caps, which take precedence over the namespace's
+CREATE TABLE metric_point_caps (
+    namespace TEXT NOT NULL,
+    id TEXT NOT NULL,
+    max_points INTEGER NOT NULL,
+    PRIMARY KEY (namespace, id)
+);
@@ -0,0 +1,17 @@
+-- Metrics deleted through the API, kept until their grace period runs out
+CREATE TABLE deleted_metrics (
+    namespace TEXT NOT NULL,
+    id TEXT NOT NULL,
+    deleted_at INTEGER NOT NULL,
+    PRIMARY KEY (namespace, id)
+);
+
+-- Their points, moved out of metrics so reads never see them
+CREATE TABLE deleted_points (
+    namespace TEXT NOT NULL,
+    id TEXT NOT NULL,
+    value REAL NOT NULL,
+    timestamp INTEGER NOT NULL
+);
+
+CREATE INDEX idx_deleted_points_namespace_id ON deleted_points (namespace, id);
@@ -0,0 +1,2 @@
+-- Lets retention find expired points without walking every series
+CREATE INDEX idx_metrics_timestamp ON metrics (timestamp);
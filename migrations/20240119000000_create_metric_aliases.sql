@@ -0,0 +1,8 @@
+-- Old ids of renamed metrics, whose chart and badge URLs redirect to `target`
+CREATE TABLE metric_aliases (
+    namespace TEXT NOT NULL,
+    id TEXT NOT NULL,
+    target TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    PRIMARY KEY (namespace, id)
+);
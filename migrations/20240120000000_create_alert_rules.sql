@@ -0,0 +1,15 @@
+-- Threshold rules checked as points arrive; `firing` is whether the last
+-- point checked broke the rule, so each change is only notified once
+CREATE TABLE alert_rules (
+    rule_id INTEGER PRIMARY KEY,
+    namespace TEXT NOT NULL,
+    metric TEXT NOT NULL,
+    condition TEXT NOT NULL,
+    threshold REAL NOT NULL,
+    webhook_url TEXT NOT NULL,
+    firing INTEGER NOT NULL DEFAULT 0,
+    changed_at INTEGER,
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX idx_alert_rules_namespace ON alert_rules (namespace);
@@ -0,0 +1,3 @@
+-- How a rule's notifications are formatted: `webhook` for the generic JSON
+-- payload, or `slack` / `discord` for their incoming-webhook formats
+ALTER TABLE alert_rules ADD COLUMN channel TEXT NOT NULL DEFAULT 'webhook';
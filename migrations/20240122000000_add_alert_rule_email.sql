@@ -0,0 +1,23 @@
+-- Email rules have a recipient instead of a webhook, so `webhook_url` can
+-- be empty. SQLite can't relax NOT NULL in place, so the table is rebuilt.
+CREATE TABLE alert_rules_new (
+    rule_id INTEGER PRIMARY KEY,
+    namespace TEXT NOT NULL,
+    metric TEXT NOT NULL,
+    condition TEXT NOT NULL,
+    threshold REAL NOT NULL,
+    channel TEXT NOT NULL DEFAULT 'webhook',
+    webhook_url TEXT,
+    email TEXT,
+    firing INTEGER NOT NULL DEFAULT 0,
+    changed_at INTEGER,
+    created_at INTEGER NOT NULL
+);
+
+INSERT INTO alert_rules_new (rule_id, namespace, metric, condition, threshold, channel, webhook_url, firing, changed_at, created_at)
+SELECT rule_id, namespace, metric, condition, threshold, channel, webhook_url, firing, changed_at, created_at FROM alert_rules;
+
+DROP TABLE alert_rules;
+ALTER TABLE alert_rules_new RENAME TO alert_rules;
+
+CREATE INDEX idx_alert_rules_namespace ON alert_rules (namespace);
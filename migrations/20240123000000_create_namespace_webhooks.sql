@@ -0,0 +1,11 @@
+-- URLs a namespace's new points are sent to, each write as it happens or,
+-- with `batch_seconds`, gathered into one request per window
+CREATE TABLE namespace_webhooks (
+    webhook_id INTEGER PRIMARY KEY,
+    namespace TEXT NOT NULL,
+    url TEXT NOT NULL,
+    batch_seconds INTEGER NOT NULL DEFAULT 0,
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX idx_namespace_webhooks_namespace ON namespace_webhooks (namespace);
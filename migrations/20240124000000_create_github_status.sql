@@ -0,0 +1,20 @@
+-- The repository a namespace's results are reported to as commit statuses;
+-- `token` falls back to the server's GITHUB_TOKEN when unset
+CREATE TABLE github_repos (
+    namespace TEXT PRIMARY KEY,
+    repo TEXT NOT NULL,
+    default_branch TEXT NOT NULL,
+    token TEXT,
+    created_at INTEGER NOT NULL
+);
+
+-- Each metric's latest value on the default branch, which results from
+-- other commits are compared against
+CREATE TABLE github_baselines (
+    namespace TEXT NOT NULL,
+    id TEXT NOT NULL,
+    value REAL NOT NULL,
+    commit_sha TEXT NOT NULL,
+    recorded_at INTEGER NOT NULL,
+    PRIMARY KEY (namespace, id)
+);
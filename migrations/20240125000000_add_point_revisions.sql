@@ -0,0 +1,6 @@
+-- The commit and branch a point was measured at, when the writer said;
+-- trashed points keep theirs for when they are restored
+ALTER TABLE metrics ADD COLUMN sha TEXT;
+ALTER TABLE metrics ADD COLUMN branch TEXT;
+ALTER TABLE deleted_points ADD COLUMN sha TEXT;
+ALTER TABLE deleted_points ADD COLUMN branch TEXT;
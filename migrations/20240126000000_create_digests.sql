@@ -0,0 +1,28 @@
+-- Alert rules starting and stopping firing, kept for digests
+CREATE TABLE alert_events (
+    event_id INTEGER PRIMARY KEY,
+    namespace TEXT NOT NULL,
+    rule_id INTEGER NOT NULL,
+    metric TEXT NOT NULL,
+    firing INTEGER NOT NULL,
+    value REAL NOT NULL,
+    summary TEXT NOT NULL,
+    at INTEGER NOT NULL
+);
+
+CREATE INDEX idx_alert_events_namespace_at ON alert_events (namespace, at);
+
+-- Daily or weekly summaries of a namespace. `sent_through` is the end of
+-- the last period a digest went out for.
+CREATE TABLE namespace_digests (
+    digest_id INTEGER PRIMARY KEY,
+    namespace TEXT NOT NULL,
+    frequency TEXT NOT NULL,
+    channel TEXT NOT NULL DEFAULT 'webhook',
+    webhook_url TEXT,
+    email TEXT,
+    sent_through INTEGER NOT NULL,
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX idx_namespace_digests_namespace ON namespace_digests (namespace);
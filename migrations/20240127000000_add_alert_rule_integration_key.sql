@@ -0,0 +1,3 @@
+-- The routing key of a PagerDuty service or the API key of an Opsgenie
+-- integration, for rules on those channels
+ALTER TABLE alert_rules ADD COLUMN integration_key TEXT;
@@ -0,0 +1,22 @@
+-- Points the background detector found far off their series' baseline
+CREATE TABLE anomalies (
+    namespace TEXT NOT NULL,
+    metric TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    value REAL NOT NULL,
+    baseline REAL NOT NULL,
+    deviations REAL NOT NULL,
+    model TEXT NOT NULL,
+    detected_at INTEGER NOT NULL,
+    PRIMARY KEY (namespace, metric, timestamp)
+);
+
+CREATE INDEX idx_anomalies_namespace_detected_at ON anomalies (namespace, detected_at);
+
+-- How far through each series the detector has got
+CREATE TABLE anomaly_scans (
+    namespace TEXT NOT NULL,
+    metric TEXT NOT NULL,
+    scanned_through INTEGER NOT NULL,
+    PRIMARY KEY (namespace, metric)
+);
@@ -0,0 +1,9 @@
+-- Owner tokens are issued by the operator; README edit tokens were handed to
+-- whoever wrote a README first, so they are revoked rather than carried over
+CREATE TABLE namespace_owners (
+    namespace TEXT PRIMARY KEY NOT NULL,
+    token TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+
+ALTER TABLE namespace_readmes DROP COLUMN edit_token;
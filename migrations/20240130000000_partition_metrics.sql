@@ -0,0 +1,15 @@
+-- Points move into a table per month (points_YYYYMM), created as writes
+-- need them, so retention can drop a whole month instead of deleting it
+-- row by row. The server moves existing points there on startup; the old
+-- table stays behind, empty, as the first part of the `metrics` view.
+ALTER TABLE metrics RENAME TO unpartitioned_points;
+
+-- Point ids are handed out here, since each month's table would otherwise
+-- number its rows on its own
+CREATE TABLE point_sequence (last_id INTEGER NOT NULL);
+INSERT INTO point_sequence (last_id) SELECT COALESCE(MAX(rowid), 0) FROM unpartitioned_points;
+
+-- Every point, whichever table it is in, for the SQL query API; the server
+-- recreates it whenever a month's table comes or goes
+CREATE VIEW metrics AS
+SELECT rowid AS point_id, namespace, id, value, timestamp, sha, branch FROM unpartitioned_points;
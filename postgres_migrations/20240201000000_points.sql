@@ -0,0 +1,31 @@
+-- Points and the trash for a server whose DATABASE_URL is PostgreSQL.
+-- Everything else stays in the SQLite settings database. Namespaces and ids
+-- compare bytewise, as they do in SQLite, so listings sort the same.
+CREATE TABLE points (
+    point_id BIGSERIAL PRIMARY KEY,
+    namespace TEXT COLLATE "C" NOT NULL,
+    id TEXT COLLATE "C" NOT NULL,
+    value DOUBLE PRECISION NOT NULL,
+    timestamp BIGINT NOT NULL,
+    sha TEXT,
+    branch TEXT
+);
+CREATE INDEX points_series ON points (namespace, id, timestamp);
+
+CREATE TABLE deleted_metrics (
+    namespace TEXT COLLATE "C" NOT NULL,
+    id TEXT COLLATE "C" NOT NULL,
+    deleted_at BIGINT NOT NULL,
+    PRIMARY KEY (namespace, id)
+);
+
+CREATE TABLE deleted_points (
+    point_id BIGSERIAL PRIMARY KEY,
+    namespace TEXT COLLATE "C" NOT NULL,
+    id TEXT COLLATE "C" NOT NULL,
+    value DOUBLE PRECISION NOT NULL,
+    timestamp BIGINT NOT NULL,
+    sha TEXT,
+    branch TEXT
+);
+CREATE INDEX deleted_points_series ON deleted_points (namespace, id);
@@ -0,0 +1,724 @@
+//! Threshold alerts. A rule watches one metric for values above or below a
+//! threshold and `POST`s to a webhook or sends an email when a new point
+//! breaks it, then once more when a point is back within it. Rules are checked as points are
+//! recorded, against each new value; nothing is polled.
+//!
+//! Rules live in the database and are mirrored in memory, since every write
+//! has to be checked against them. Notifications go out in the background,
+//! so a slow webhook never holds up a write; one that fails is logged and
+//! not retried. Whether a rule is firing survives restarts.
+//!
+//! A rule's `channel` says where notifications go: a webhook expecting the
+//! generic JSON payload, a Slack or Discord incoming webhook, an `email`
+//! address, which needs the `SMTP_*` settings, or a PagerDuty or Opsgenie
+//! incident through the `integration_key` of a service there; see
+//! [`notifiers`].
+//!
+//! A `silent` rule makes its metric a heartbeat: its threshold (or
+//! `interval`) is the number of seconds the metric may go without a point,
+//! and it fires once that passes and resolves with the next point. Those are
+//! the one thing checked in the background, every half minute. A cron job
+//! that checks in hourly wants an interval with some slack, such as 3900.
+//!
+//! An `anomaly` rule passes on what background anomaly detection finds:
+//! its threshold is the standard deviations off the baseline a recorded
+//! anomaly has to be, and it resolves once a scan of new points finds none
+//! that far off. It needs `ANOMALY_DETECTION`, and thresholds below
+//! `ANOMALY_SIGMAS` act as that, since nothing closer is recorded.
+//!
+//! Rules make the server send requests and emails wherever they say, so only
+//! the admin token may list, create or delete them.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::Ordering;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::sqlite::SqlitePool;
+use tokio::time::{self, MissedTickBehavior};
+
+use crate::{
+    anomaly::Anomaly,
+    auth, client,
+    ids::NamespacePath,
+    mail, meta,
+    notifiers::{self, Alert, Channel},
+    store::{MetricStore, StoreError},
+    AppState, MetricPoint,
+};
+
+const MAX_RULES_PER_NAMESPACE: usize = 100;
+const MAX_URL_LENGTH: usize = 2048;
+const MAX_INTEGRATION_KEY_LENGTH: usize = 128;
+/// How often heartbeats are checked for having gone quiet
+const HEARTBEAT_TICK: Duration = Duration::from_secs(30);
+const MIN_HEARTBEAT_SECONDS: f64 = 60.0;
+const MAX_HEARTBEAT_SECONDS: f64 = 366.0 * 86400.0;
+/// How long rules starting and stopping firing are remembered for digests
+const EVENT_RETENTION_SECONDS: i64 = 90 * 86400;
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(json!({ "error": message })))
+}
+
+fn database_error<E: std::fmt::Display>(err: E) -> ApiError {
+    crate::errors::report(&err);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+/// Which side of the threshold breaks a rule.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Condition {
+    #[serde(alias = ">")]
+    Above,
+    #[serde(alias = "<")]
+    Below,
+    /// No point for longer than the threshold, in seconds
+    #[serde(alias = "heartbeat")]
+    Silent,
+    /// Recorded anomalies at least the threshold of standard deviations off
+    Anomaly,
+}
+
+impl Condition {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Condition::Above => "above",
+            Condition::Below => "below",
+            Condition::Silent => "silent",
+            Condition::Anomaly => "anomaly",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "above" => Some(Condition::Above),
+            "below" => Some(Condition::Below),
+            "silent" => Some(Condition::Silent),
+            "anomaly" => Some(Condition::Anomaly),
+            _ => None,
+        }
+    }
+
+    /// Whether a point with `value` breaks the rule; a point never breaks
+    /// a `silent` or `anomaly` one.
+    fn broken_by(self, value: f64, threshold: f64) -> bool {
+        match self {
+            Condition::Above => value > threshold,
+            Condition::Below => value < threshold,
+            Condition::Silent | Condition::Anomaly => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Rule {
+    id: i64,
+    metric: String,
+    condition: Condition,
+    threshold: f64,
+    channel: Channel,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    webhook_url: Option<String>,
+    /// Where `email` rules send to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email: Option<String>,
+    /// The PagerDuty routing key or Opsgenie API key of incident rules
+    #[serde(skip_serializing_if = "Option::is_none")]
+    integration_key: Option<String>,
+    /// Whether the latest point checked broke the rule
+    firing: bool,
+    /// When the rule last started or stopped firing
+    changed_at: Option<i64>,
+    /// When the metric last got a point, for `silent` rules
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_seen: Option<i64>,
+    #[serde(skip)]
+    created_at: i64,
+}
+
+/// `{"metric": "disk_used", "condition": "above", "threshold": 90,
+/// "webhook_url": "https://hooks.slack.com/...", "channel": "slack"}`, or
+/// `"channel": "email"` with `"email": "ops@example.com"` instead of a URL,
+/// or `"channel": "pagerduty"` or `"opsgenie"` with an `"integration_key"`
+#[derive(Deserialize)]
+pub struct RuleRequest {
+    metric: String,
+    condition: Condition,
+    #[serde(alias = "interval")]
+    threshold: f64,
+    #[serde(default)]
+    channel: Channel,
+    webhook_url: Option<String>,
+    email: Option<String>,
+    #[serde(alias = "routing_key", alias = "api_key")]
+    integration_key: Option<String>,
+}
+
+impl Rule {
+    /// Where notifications go, for logs.
+    fn destination(&self) -> &str {
+        match self.channel {
+            Channel::PagerDuty => "PagerDuty",
+            Channel::Opsgenie => "Opsgenie",
+            _ => self.webhook_url.as_deref().or(self.email.as_deref()).unwrap_or_default(),
+        }
+    }
+
+    /// Seconds since a `silent` rule's metric last got a point, or since the
+    /// rule was made if it never has.
+    fn quiet_for(&self, now: i64) -> i64 {
+        now - self.last_seen.unwrap_or(self.created_at)
+    }
+}
+
+/// Rules by namespace.
+#[derive(Default)]
+pub struct AlertRules {
+    rules: RwLock<HashMap<String, Vec<Rule>>>,
+}
+
+impl AlertRules {
+    pub async fn load(pool: &SqlitePool, store: &dyn MetricStore) -> Result<Self, StoreError> {
+        let rules = AlertRules::default();
+        rules.reload(pool, store).await?;
+        Ok(rules)
+    }
+
+    pub async fn reload(&self, pool: &SqlitePool, store: &dyn MetricStore) -> Result<(), StoreError> {
+        let rows = sqlx::query!(
+            r#"SELECT rule_id as "rule_id!", namespace, metric, condition, threshold, channel,
+                      webhook_url, email, integration_key, firing as "firing: bool", changed_at, created_at
+               FROM alert_rules ORDER BY rule_id"#
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let mut rules: HashMap<String, Vec<Rule>> = HashMap::new();
+        for row in rows {
+            let (Some(condition), Some(channel)) = (Condition::parse(&row.condition), Channel::parse(&row.channel))
+            else {
+                continue;
+            };
+            let last_seen = match condition {
+                Condition::Silent => store.latest(&row.namespace, &row.metric).await?.map(|point| point.timestamp),
+                _ => None,
+            };
+            rules.entry(row.namespace).or_default().push(Rule {
+                id: row.rule_id,
+                metric: row.metric,
+                condition,
+                threshold: row.threshold,
+                channel,
+                webhook_url: row.webhook_url,
+                email: row.email,
+                integration_key: row.integration_key,
+                firing: row.firing,
+                changed_at: row.changed_at,
+                last_seen,
+                created_at: row.created_at,
+            });
+        }
+        *self.rules.write().unwrap() = rules;
+        Ok(())
+    }
+
+    fn list(&self, namespace: &str) -> Vec<Rule> {
+        self.rules.read().unwrap().get(namespace).cloned().unwrap_or_default()
+    }
+
+    /// Checks new points against the namespace's rules, flipping those whose
+    /// state changed, and returns them with the point that changed them.
+    fn check(&self, namespace: &str, points: &[(&str, f64)], now: i64) -> Vec<(Rule, f64)> {
+        if !self.rules.read().unwrap().contains_key(namespace) {
+            return Vec::new();
+        }
+        let mut rules = self.rules.write().unwrap();
+        let Some(rules) = rules.get_mut(namespace) else {
+            return Vec::new();
+        };
+        let mut changed = Vec::new();
+        for &(id, value) in points {
+            for rule in rules.iter_mut().filter(|rule| rule.metric == id) {
+                // A heartbeat's notifications carry how long it was quiet
+                if rule.condition == Condition::Silent {
+                    let quiet_for = rule.quiet_for(now);
+                    rule.last_seen = Some(now);
+                    if rule.firing {
+                        rule.firing = false;
+                        rule.changed_at = Some(now);
+                        changed.push((rule.clone(), quiet_for as f64));
+                    }
+                    continue;
+                }
+                // Those wait for the detector's verdict
+                if rule.condition == Condition::Anomaly {
+                    continue;
+                }
+                let broken = rule.condition.broken_by(value, rule.threshold);
+                if broken != rule.firing {
+                    rule.firing = broken;
+                    rule.changed_at = Some(now);
+                    changed.push((rule.clone(), value));
+                }
+            }
+        }
+        changed
+    }
+
+    /// Fires `silent` rules whose metric has been quiet for longer than
+    /// their interval, returning them by namespace with how long that's been.
+    fn overdue(&self, now: i64) -> Vec<(String, Rule, f64)> {
+        let mut rules = self.rules.write().unwrap();
+        let mut fired = Vec::new();
+        for (namespace, rules) in rules.iter_mut() {
+            for rule in rules.iter_mut().filter(|rule| rule.condition == Condition::Silent && !rule.firing) {
+                let quiet_for = rule.quiet_for(now);
+                if quiet_for as f64 > rule.threshold {
+                    rule.firing = true;
+                    rule.changed_at = Some(now);
+                    fired.push((namespace.clone(), rule.clone(), quiet_for as f64));
+                }
+            }
+        }
+        fired
+    }
+
+    /// Flips `anomaly` rules on a metric the detector has just scanned:
+    /// those newly past their threshold fire with the latest anomaly that is,
+    /// and firing ones with no anomaly that far off resolve with the latest
+    /// point.
+    fn detected(
+        &self,
+        namespace: &str,
+        metric: &str,
+        anomalies: &[Anomaly],
+        latest: &MetricPoint,
+        now: i64,
+    ) -> Vec<(Rule, f64)> {
+        let mut rules = self.rules.write().unwrap();
+        let Some(rules) = rules.get_mut(namespace) else {
+            return Vec::new();
+        };
+        let mut changed = Vec::new();
+        for rule in rules
+            .iter_mut()
+            .filter(|rule| rule.condition == Condition::Anomaly && rule.metric == metric)
+        {
+            let found = anomalies.iter().rfind(|anomaly| anomaly.deviations.abs() >= rule.threshold);
+            let value = match (found, rule.firing) {
+                (Some(anomaly), false) => anomaly.value,
+                (None, true) => latest.value,
+                _ => continue,
+            };
+            rule.firing = found.is_some();
+            rule.changed_at = Some(now);
+            changed.push((rule.clone(), value));
+        }
+        changed
+    }
+
+    /// Metrics in `namespace` whose heartbeat has stopped.
+    pub fn silent(&self, namespace: &str) -> HashSet<String> {
+        self.rules
+            .read()
+            .unwrap()
+            .get(namespace)
+            .into_iter()
+            .flatten()
+            .filter(|rule| rule.condition == Condition::Silent && rule.firing)
+            .map(|rule| rule.metric.clone())
+            .collect()
+    }
+}
+
+/// Checks freshly recorded points against the namespace's rules and sends
+/// notifications for any that started or stopped firing.
+pub async fn evaluate(state: &AppState, namespace: &str, points: &[(&str, f64)], timestamp: i64) {
+    if !state.config().features.alerts {
+        return;
+    }
+    let changed = state.alerts.check(namespace, points, timestamp);
+    for (rule, value) in changed {
+        announce(state, namespace, rule, value, timestamp).await;
+    }
+}
+
+/// Sends notifications for `anomaly` rules that the detector's scan of a
+/// metric started or stopped firing.
+pub async fn detected(state: &AppState, namespace: &str, metric: &str, anomalies: &[Anomaly], latest: &MetricPoint) {
+    if !state.config().features.alerts {
+        return;
+    }
+    let now = Utc::now().timestamp();
+    let changed = state.alerts.detected(namespace, metric, anomalies, latest, now);
+    for (rule, value) in changed {
+        announce(state, namespace, rule, value, now).await;
+    }
+}
+
+/// Fires heartbeats that have gone quiet, for as long as the server runs.
+pub fn spawn(state: AppState) {
+    if !state.config().features.alerts {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut ticks = time::interval(HEARTBEAT_TICK);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            let now = Utc::now().timestamp();
+            for (namespace, rule, quiet_for) in state.alerts.overdue(now) {
+                announce(&state, &namespace, rule, quiet_for, now).await;
+            }
+        }
+    });
+}
+
+/// Forgets rules starting and stopping firing longer ago than any digest
+/// looks back, returning how many were removed.
+pub async fn prune_events(state: &AppState) -> Result<u64, sqlx::Error> {
+    let cutoff = Utc::now().timestamp() - EVENT_RETENTION_SECONDS;
+    let pool = &state.pool;
+    let result = state
+        .write(|| async move {
+            sqlx::query!("DELETE FROM alert_events WHERE at < ?", cutoff)
+                .execute(pool)
+                .await
+        })
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// Saves that a rule started or stopped firing, for the rule and for
+/// digests, and notifies its channel in the background.
+async fn announce(state: &AppState, namespace: &str, rule: Rule, value: f64, timestamp: i64) {
+    let meta = meta::load(&state.pool, namespace, &rule.metric).await.unwrap_or_default();
+    let summary = alert(state, namespace, &rule, value, timestamp, &meta).summary();
+    let pool = &state.pool;
+    let (rule_id, firing, metric, summary) = (rule.id, rule.firing, &rule.metric, &summary);
+    let saved = state
+        .write(|| async move {
+            let mut tx = pool.begin().await?;
+            sqlx::query!(
+                "UPDATE alert_rules SET firing = ?, changed_at = ? WHERE rule_id = ?",
+                firing,
+                timestamp,
+                rule_id
+            )
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query!(
+                "INSERT INTO alert_events (namespace, rule_id, metric, firing, value, summary, at)
+                 VALUES (?, ?, ?, ?, ?, ?, ?)",
+                namespace,
+                rule_id,
+                metric,
+                firing,
+                value,
+                summary,
+                timestamp
+            )
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await
+        })
+        .await;
+    if let Err(err) = saved {
+        tracing::warn!("Saving the state of alert rule {} failed: {}", rule.id, err);
+    }
+
+    let state = state.clone();
+    let namespace = namespace.to_string();
+    tokio::spawn(async move {
+        let counter = match notify(&state, &namespace, &rule, value, timestamp, &meta).await {
+            Ok(()) => &state.metrics.alert_notifications,
+            Err(err) => {
+                tracing::warn!("Notifying {} about alert rule {} failed: {}", rule.destination(), rule.id, err);
+                &state.metrics.alert_notification_failures
+            }
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    });
+}
+
+fn alert<'a>(
+    state: &AppState,
+    namespace: &'a str,
+    rule: &'a Rule,
+    value: f64,
+    timestamp: i64,
+    meta: &meta::MetricMeta,
+) -> Alert<'a> {
+    let (shown_value, shown_threshold) = match rule.condition {
+        Condition::Silent => (notifiers::format_duration(value), notifiers::format_duration(rule.threshold)),
+        Condition::Anomaly => (meta.format(value), format!("{}σ", rule.threshold)),
+        _ => (meta.format(value), meta.format(rule.threshold)),
+    };
+    Alert {
+        namespace,
+        metric: &rule.metric,
+        rule_id: rule.id,
+        condition: rule.condition,
+        threshold: rule.threshold,
+        firing: rule.firing,
+        value,
+        timestamp,
+        shown_value,
+        shown_threshold,
+        chart_url: state
+            .config()
+            .public_url
+            .as_deref()
+            .map(|url| notifiers::chart_url(url, namespace, &rule.metric)),
+    }
+}
+
+async fn notify(
+    state: &AppState,
+    namespace: &str,
+    rule: &Rule,
+    value: f64,
+    timestamp: i64,
+    meta: &meta::MetricMeta,
+) -> Result<(), client::ClientError> {
+    let alert = alert(state, namespace, rule, value, timestamp, meta);
+    match rule.channel {
+        Channel::Email => {
+            let config = state.config();
+            let smtp = config.smtp.as_ref().ok_or("SMTP isn't configured")?;
+            let to = rule.email.as_deref().ok_or("rule has no email address")?;
+            let (subject, body) = notifiers::email(&alert)?;
+            mail::send(smtp, to, &subject, &body).await
+        }
+        Channel::PagerDuty => {
+            let key = rule.integration_key.as_deref().ok_or("rule has no routing key")?;
+            let event = notifiers::pagerduty(&alert, key);
+            client::post_json(&state.config().pagerduty_events_url, &[], &serde_json::to_vec(&event)?).await
+        }
+        Channel::Opsgenie => {
+            let key = rule.integration_key.as_deref().ok_or("rule has no API key")?;
+            let (path, body) = notifiers::opsgenie(&alert);
+            let url = format!("{}{}", state.config().opsgenie_api_url, path);
+            let authorization = format!("GenieKey {}", key);
+            let headers = [("Authorization", authorization.as_str())];
+            client::post_json(&url, &headers, &serde_json::to_vec(&body)?).await
+        }
+        Channel::Webhook | Channel::Slack | Channel::Discord => {
+            let payload = notifiers::render(rule.channel, &alert).ok_or("channel has no webhook format")?;
+            let url = rule.webhook_url.as_deref().ok_or("rule has no webhook URL")?;
+            let allow_private = state.config().allow_private_webhook_urls;
+            client::post_webhook(url, &serde_json::to_vec(&payload)?, allow_private).await
+        }
+    }
+}
+
+/// Checks a new rule, returning its normalized metric id.
+async fn validate(state: &AppState, request: &RuleRequest) -> Result<String, ApiError> {
+    let metric = state.config().id_policy.normalize(&request.metric);
+    if metric.is_empty() {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "metric must name a metric"));
+    }
+    if !request.threshold.is_finite() {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "threshold must be a finite number"));
+    }
+    if request.condition == Condition::Silent
+        && !(MIN_HEARTBEAT_SECONDS..=MAX_HEARTBEAT_SECONDS).contains(&request.threshold)
+    {
+        return Err(error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "a silent rule's interval must be between 60 seconds and 366 days",
+        ));
+    }
+    if request.condition == Condition::Anomaly {
+        if state.config().anomaly_detection.is_none() {
+            return Err(error(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "anomaly rules need anomaly detection, which this server doesn't run",
+            ));
+        }
+        if request.threshold <= 0.0 {
+            return Err(error(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "an anomaly rule's threshold is a positive number of standard deviations",
+            ));
+        }
+    }
+    let destination = Destination {
+        webhook_url: request.webhook_url.as_deref(),
+        email: request.email.as_deref(),
+        integration_key: request.integration_key.as_deref(),
+    };
+    check_destination(state, request.channel, destination).await?;
+    Ok(metric)
+}
+
+/// Whichever of a channel's destinations a request gave.
+#[derive(Clone, Copy, Default)]
+pub struct Destination<'a> {
+    pub webhook_url: Option<&'a str>,
+    pub email: Option<&'a str>,
+    pub integration_key: Option<&'a str>,
+}
+
+/// Checks that a channel has the one destination it needs: an address for
+/// `email`, when there's a mail server to send through, a key for the
+/// incident channels, and a webhook URL for the rest. Digests are sent the
+/// same ways.
+pub async fn check_destination(state: &AppState, channel: Channel, destination: Destination<'_>) -> Result<(), ApiError> {
+    let Destination {
+        webhook_url,
+        email,
+        integration_key,
+    } = destination;
+    if channel.is_incident() {
+        let key = match (webhook_url, email, integration_key) {
+            (None, None, Some(key)) => key,
+            _ => {
+                return Err(error(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "the pagerduty and opsgenie channels take an integration_key and no webhook_url or email",
+                ))
+            }
+        };
+        if key.is_empty() || key.len() > MAX_INTEGRATION_KEY_LENGTH || !key.bytes().all(|byte| byte.is_ascii_graphic()) {
+            return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "integration_key isn't a PagerDuty or Opsgenie key"));
+        }
+        return Ok(());
+    }
+    if integration_key.is_some() {
+        return Err(error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "integration_key is only for the pagerduty and opsgenie channels",
+        ));
+    }
+    match (channel, webhook_url, email) {
+        (Channel::Email, None, Some(email)) => {
+            if state.config().smtp.is_none() {
+                return Err(error(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "email needs a mail server; this instance has no SMTP_HOST configured",
+                ));
+            }
+            if !mail::valid_address(email) {
+                return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "email must be an address like ops@example.com"));
+            }
+        }
+        (Channel::Email, _, _) => {
+            return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "the email channel takes an email address and no webhook_url"));
+        }
+        (_, Some(url), None) => {
+            if url.len() > MAX_URL_LENGTH {
+                return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "webhook_url must be at most 2048 characters"));
+            }
+            client::check_public(url, state.config().allow_private_webhook_urls)
+                .await
+                .map_err(|err| error(StatusCode::UNPROCESSABLE_ENTITY, &err))?;
+        }
+        _ => {
+            return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "webhook channels take a webhook_url and no email"));
+        }
+    }
+    Ok(())
+}
+
+/// Webhook URLs often carry a secret, and email addresses are personal, so
+/// only the admin token sees them.
+pub async fn list_rules(
+    NamespacePath(namespace): NamespacePath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+    Ok(Json(json!({ "rules": state.alerts.list(&namespace) })))
+}
+
+pub async fn create_rule(
+    NamespacePath(namespace): NamespacePath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<RuleRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+    let metric = validate(&state, &request).await?;
+    if state.alerts.list(&namespace).len() >= MAX_RULES_PER_NAMESPACE {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "a namespace can have at most 100 alert rules"));
+    }
+
+    let now = Utc::now().timestamp();
+    let condition = request.condition.as_str();
+    let (pool, namespace_ref, metric_ref) = (&state.pool, &namespace, &metric);
+    let (url, email, key) = (&request.webhook_url, &request.email, &request.integration_key);
+    let threshold = request.threshold;
+    let channel = request.channel.as_str();
+    let id = state
+        .write(|| async move {
+            sqlx::query_scalar!(
+                r#"INSERT INTO alert_rules (namespace, metric, condition, threshold, channel, webhook_url, email, integration_key, created_at)
+                   VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+                   RETURNING rule_id as "rule_id!""#,
+                namespace_ref,
+                metric_ref,
+                condition,
+                threshold,
+                channel,
+                url,
+                email,
+                key,
+                now
+            )
+            .fetch_one(pool)
+            .await
+        })
+        .await
+        .map_err(database_error)?;
+    state.alerts.reload(pool, state.store.as_ref()).await.map_err(database_error)?;
+
+    let rule = state
+        .alerts
+        .list(&namespace)
+        .into_iter()
+        .find(|rule| rule.id == id)
+        .ok_or_else(|| error(StatusCode::INTERNAL_SERVER_ERROR, "alert rule vanished"))?;
+    Ok((StatusCode::CREATED, Json(rule)))
+}
+
+pub async fn delete_rule(
+    Path((namespace, rule_id)): Path<(String, i64)>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let namespace = state.config().id_policy.normalize(&namespace);
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+
+    let (pool, namespace_ref) = (&state.pool, &namespace);
+    let result = state
+        .write(|| async move {
+            sqlx::query!(
+                "DELETE FROM alert_rules WHERE namespace = ? AND rule_id = ?",
+                namespace_ref,
+                rule_id
+            )
+            .execute(pool)
+            .await
+        })
+        .await
+        .map_err(database_error)?;
+    state.alerts.reload(pool, state.store.as_ref()).await.map_err(database_error)?;
+    if result.rows_affected() == 0 {
+        return Err(error(StatusCode::NOT_FOUND, "no alert rule with that id"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,345 @@
+//! Renaming metrics without breaking links to them. `POST
+//! /{namespace}/{id}/rename` moves a series and its settings to a new id and
+//! leaves the old id behind as an alias, so chart, badge and embed URLs that
+//! are already out in READMEs and wikis redirect to the new id. Since that
+//! takes over where those URLs lead, only the admin token may rename or
+//! drop an alias.
+//!
+//! Aliases live in the database and are mirrored in memory, since every
+//! chart and badge request has to be checked against them. Recording points
+//! under an aliased id again makes it a metric of its own, so its alias is
+//! dropped.
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+    Json,
+};
+use chrono::Utc;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::sqlite::SqlitePool;
+
+use crate::{
+    auth,
+    ids::{NamespacePath, SeriesPath},
+    AppState,
+};
+
+/// Characters left alone in a redirected path segment
+const SEGMENT_CHARACTERS: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+/// `/{namespace}/{id}/...` pages that follow an alias
+const SERIES_PAGES: &[&str] = &[
+    "ascii",
+    "heatmap",
+    "daily",
+    "badge.png",
+    "badge.svg",
+    "chart.png",
+    "chart.svg",
+    "og.png",
+];
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(json!({ "error": message })))
+}
+
+fn database_error<E: std::fmt::Display>(err: E) -> ApiError {
+    crate::errors::report(&err);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+/// `{"to": "build_time"}`
+#[derive(Deserialize)]
+pub struct RenameRequest {
+    to: String,
+}
+
+#[derive(Serialize)]
+struct Alias {
+    id: String,
+    target: String,
+    created_at: i64,
+}
+
+/// Aliases by `(namespace, old id)`, pointing at the id now in use.
+#[derive(Default)]
+pub struct AliasMap {
+    aliases: RwLock<HashMap<(String, String), String>>,
+}
+
+impl AliasMap {
+    pub async fn load(pool: &SqlitePool) -> Result<Self, sqlx::Error> {
+        let map = AliasMap::default();
+        map.reload(pool).await?;
+        Ok(map)
+    }
+
+    pub async fn reload(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        let rows = sqlx::query!("SELECT namespace, id, target FROM metric_aliases")
+            .fetch_all(pool)
+            .await?;
+        let aliases = rows
+            .into_iter()
+            .map(|row| ((row.namespace, row.id), row.target))
+            .collect();
+        *self.aliases.write().unwrap() = aliases;
+        Ok(())
+    }
+
+    fn get(&self, namespace: &str, id: &str) -> Option<String> {
+        let aliases = self.aliases.read().unwrap();
+        if aliases.is_empty() {
+            return None;
+        }
+        aliases.get(&(namespace.to_string(), id.to_string())).cloned()
+    }
+}
+
+pub async fn post_rename(
+    SeriesPath(namespace, id): SeriesPath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<RenameRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+
+    let to = state.config().id_policy.normalize(&request.to);
+    if to.is_empty() {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "to must name a metric"));
+    }
+    state
+        .config()
+        .id_policy
+        .check(&to)
+        .map_err(|message| error(StatusCode::UNPROCESSABLE_ENTITY, &message))?;
+    if to == id {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "the metric already has that id"));
+    }
+
+    // Settings live apart from the points and follow them once they've moved
+    let now = Utc::now().timestamp();
+    let (pool, namespace, id, to) = (&state.pool, &namespace, &id, &to);
+    let points = match state.write_store(|| state.store.rename(namespace, id, to)).await {
+        Ok(Some(0)) => return Err(error(StatusCode::NOT_FOUND, "no metric with that id")),
+        Ok(Some(points)) => points,
+        Ok(None) => {
+            return Err(error(
+                StatusCode::CONFLICT,
+                "a metric with that id already exists; merge into it instead",
+            ))
+        }
+        Err(err) => return Err(database_error(err)),
+    };
+    state
+        .write(|| async move {
+            let mut tx = pool.begin().await?;
+            // Whatever settings the new id had left over from an earlier life give way
+            sqlx::query!("DELETE FROM metric_meta WHERE namespace = ? AND id = ?", namespace, to)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query!("UPDATE metric_meta SET id = ? WHERE namespace = ? AND id = ?", to, namespace, id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query!("DELETE FROM metric_precision WHERE namespace = ? AND id = ?", namespace, to)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query!("UPDATE metric_precision SET id = ? WHERE namespace = ? AND id = ?", to, namespace, id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query!("DELETE FROM metric_rollups WHERE namespace = ? AND id = ?", namespace, to)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query!("UPDATE metric_rollups SET id = ? WHERE namespace = ? AND id = ?", to, namespace, id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query!("DELETE FROM metric_point_caps WHERE namespace = ? AND id = ?", namespace, to)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query!("UPDATE metric_point_caps SET id = ? WHERE namespace = ? AND id = ?", to, namespace, id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query!("DELETE FROM github_baselines WHERE namespace = ? AND id = ?", namespace, to)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query!("UPDATE github_baselines SET id = ? WHERE namespace = ? AND id = ?", to, namespace, id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query!("UPDATE alert_rules SET metric = ? WHERE namespace = ? AND metric = ?", to, namespace, id)
+                .execute(&mut *tx)
+                .await?;
+            // Earlier names follow straight to the new one rather than chaining
+            sqlx::query!(
+                "UPDATE metric_aliases SET target = ? WHERE namespace = ? AND target = ?",
+                to,
+                namespace,
+                id
+            )
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query!("DELETE FROM metric_aliases WHERE namespace = ? AND id = ?", namespace, to)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query!(
+                "INSERT INTO metric_aliases (namespace, id, target, created_at) VALUES (?, ?, ?, ?)
+                 ON CONFLICT (namespace, id) DO UPDATE SET target = excluded.target, created_at = excluded.created_at",
+                namespace,
+                id,
+                to,
+                now
+            )
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await
+        })
+        .await
+        .map_err(database_error)?;
+    state.aliases.reload(&state.pool).await.map_err(database_error)?;
+    state.alerts.reload(&state.pool, state.store.as_ref()).await.map_err(database_error)?;
+    state.invalidate_series(namespace, id);
+    state.invalidate_series(namespace, to);
+
+    Ok(Json(json!({ "namespace": namespace, "from": id, "to": to, "points": points })))
+}
+
+/// Points `id`'s URLs at `target`, after its points were folded into it.
+pub async fn record(state: &AppState, namespace: &str, id: &str, target: &str) -> Result<(), sqlx::Error> {
+    let now = Utc::now().timestamp();
+    let pool = &state.pool;
+    state
+        .write(|| async move {
+            let mut tx = pool.begin().await?;
+            sqlx::query!(
+                "UPDATE metric_aliases SET target = ? WHERE namespace = ? AND target = ?",
+                target,
+                namespace,
+                id
+            )
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query!("DELETE FROM metric_aliases WHERE namespace = ? AND id = ?", namespace, target)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query!(
+                "INSERT INTO metric_aliases (namespace, id, target, created_at) VALUES (?, ?, ?, ?)
+                 ON CONFLICT (namespace, id) DO UPDATE SET target = excluded.target, created_at = excluded.created_at",
+                namespace,
+                id,
+                target,
+                now
+            )
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await
+        })
+        .await?;
+    state.aliases.reload(pool).await
+}
+
+/// Drops the aliases of any of `ids` that were just written to. The write
+/// has already succeeded by now, so a failure here is only logged.
+pub async fn forget_written(state: &AppState, namespace: &str, ids: &[&str]) {
+    let written: Vec<&str> = ids
+        .iter()
+        .copied()
+        .filter(|id| state.aliases.get(namespace, id).is_some())
+        .collect();
+    if written.is_empty() {
+        return;
+    }
+    let pool = &state.pool;
+    for id in written {
+        let result = state
+            .write(|| async move {
+                sqlx::query!("DELETE FROM metric_aliases WHERE namespace = ? AND id = ?", namespace, id)
+                    .execute(pool)
+                    .await
+            })
+            .await;
+        if let Err(err) = result {
+            tracing::warn!("Dropping the alias of {}/{} failed: {}", namespace, id, err);
+        }
+    }
+    if let Err(err) = state.aliases.reload(pool).await {
+        tracing::warn!("Reloading metric aliases failed: {}", err);
+    }
+}
+
+pub async fn list_aliases(
+    NamespacePath(namespace): NamespacePath,
+    State(pool): State<SqlitePool>,
+) -> Result<impl IntoResponse, ApiError> {
+    let aliases = sqlx::query_as!(
+        Alias,
+        "SELECT id, target, created_at FROM metric_aliases WHERE namespace = ? ORDER BY id",
+        namespace
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(database_error)?;
+    Ok(Json(json!({ "aliases": aliases })))
+}
+
+pub async fn delete_alias(
+    SeriesPath(namespace, id): SeriesPath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+
+    let (pool, namespace, id) = (&state.pool, &namespace, &id);
+    let result = state
+        .write(|| async move {
+            sqlx::query!("DELETE FROM metric_aliases WHERE namespace = ? AND id = ?", namespace, id)
+                .execute(pool)
+                .await
+        })
+        .await
+        .map_err(database_error)?;
+    state.aliases.reload(pool).await.map_err(database_error)?;
+    if result.rows_affected() == 0 {
+        return Err(error(StatusCode::NOT_FOUND, "no alias with that id"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Redirects chart, badge and embed requests for an aliased id to the id it
+/// was renamed to, keeping the rest of the URL.
+pub async fn redirect(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if !matches!(*request.method(), Method::GET | Method::HEAD) {
+        return next.run(request).await;
+    }
+    let path = request.uri().path();
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    // Index of the namespace segment; the id follows it
+    let at = match segments.as_slice() {
+        [_, _] => 0,
+        [_, _, page] if SERIES_PAGES.contains(page) => 0,
+        ["embed", _, _] => 1,
+        _ => return next.run(request).await,
+    };
+    let policy = &state.config().id_policy;
+    let decode = |segment: &str| policy.normalize(&percent_decode_str(segment).decode_utf8_lossy());
+    let Some(target) = state.aliases.get(&decode(segments[at]), &decode(segments[at + 1])) else {
+        return next.run(request).await;
+    };
+
+    let mut segments: Vec<String> = segments.iter().map(|segment| segment.to_string()).collect();
+    segments[at + 1] = utf8_percent_encode(&target, SEGMENT_CHARACTERS).to_string();
+    let mut location = format!("{}/{}", state.base_path(), segments.join("/"));
+    if let Some(query) = request.uri().query() {
+        location.push('?');
+        location.push_str(query);
+    }
+    // Temporary, so browsers and caches ask again: the alias goes away once
+    // it's dropped or the old id is written to
+    Redirect::temporary(&location).into_response()
+}
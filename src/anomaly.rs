@@ -0,0 +1,145 @@
+//! Outlier detection against a baseline: each point is compared with the
+//! mean and standard deviation of the points just before it, or, for the
+//! seasonal models, of the points at the same time in earlier days or weeks.
+
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::MetricPoint;
+
+/// How many preceding points form the rolling baseline.
+pub const BASELINE_POINTS: usize = 20;
+/// Points with a shorter history than this are never flagged.
+const MIN_BASELINE_POINTS: usize = 5;
+const DAY: i64 = 86400;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Anomaly {
+    pub timestamp: i64,
+    pub value: f64,
+    /// Mean of the baseline the point was compared with
+    pub baseline: f64,
+    /// Distance from the baseline in standard deviations, signed
+    pub deviations: f64,
+}
+
+/// What a point is held against.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Model {
+    /// The points just before it, as on charts with `?anomalies=`
+    ZScore,
+    /// Points at the same time of day over the week before
+    Daily,
+    /// Points at the same time of week over the four weeks before
+    Weekly,
+}
+
+impl Model {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Model::ZScore => "zscore",
+            Model::Daily => "daily",
+            Model::Weekly => "weekly",
+        }
+    }
+
+    /// The period and how many of them a seasonal model looks back over.
+    fn seasons(self) -> Option<(i64, i64)> {
+        match self {
+            Model::ZScore => None,
+            Model::Daily => Some((DAY, 7)),
+            Model::Weekly => Some((7 * DAY, 4)),
+        }
+    }
+
+    /// How far before a point its seasonal baseline reaches, or `None` when
+    /// the baseline is a number of points rather than a span of time.
+    pub fn lookback(self) -> Option<i64> {
+        self.seasons().map(|(period, count)| period * count + period / 48)
+    }
+}
+
+impl FromStr for Model {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, ()> {
+        match value.to_ascii_lowercase().as_str() {
+            "zscore" | "z-score" => Ok(Model::ZScore),
+            "daily" | "seasonal" => Ok(Model::Daily),
+            "weekly" => Ok(Model::Weekly),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The point, if it's more than `sigmas` standard deviations from a
+/// baseline of `count` values with the given sums. Flat baselines never flag
+/// anything, since any change at all would be infinitely many deviations
+/// away.
+fn flag(point: &MetricPoint, (sum, sum_squares, count): (f64, f64, usize), sigmas: f64) -> Option<Anomaly> {
+    if count < MIN_BASELINE_POINTS {
+        return None;
+    }
+    let mean = sum / count as f64;
+    let stddev = (sum_squares / count as f64 - mean * mean).max(0.0).sqrt();
+    let deviations = (point.value - mean) / stddev;
+    (stddev > f64::EPSILON * mean.abs().max(1.0) && deviations.abs() > sigmas).then_some(Anomaly {
+        timestamp: point.timestamp,
+        value: point.value,
+        baseline: mean,
+        deviations,
+    })
+}
+
+/// Points more than `sigmas` standard deviations from their trailing
+/// baseline.
+pub fn detect(data: &[MetricPoint], sigmas: f64) -> Vec<Anomaly> {
+    let (mut sum, mut sum_squares) = (0.0, 0.0);
+    let mut anomalies = Vec::new();
+    for (i, point) in data.iter().enumerate() {
+        let count = i.min(BASELINE_POINTS);
+        anomalies.extend(flag(point, (sum, sum_squares, count), sigmas));
+
+        // Slide the window forward over this point
+        sum += point.value;
+        sum_squares += point.value * point.value;
+        if i >= BASELINE_POINTS {
+            let old = data[i - BASELINE_POINTS].value;
+            sum -= old;
+            sum_squares -= old * old;
+        }
+    }
+    anomalies
+}
+
+/// Points after `after` that `model` flags, judged against the rest of
+/// `data`, which is in time order and has to reach back far enough for it.
+pub fn detect_after(model: Model, data: &[MetricPoint], after: i64, sigmas: f64) -> Vec<Anomaly> {
+    let Some((period, count)) = model.seasons() else {
+        let mut anomalies = detect(data, sigmas);
+        anomalies.retain(|anomaly| anomaly.timestamp > after);
+        return anomalies;
+    };
+    // Half an hour either side for a daily model, three and a half hours
+    // for a weekly one
+    let tolerance = period / 48;
+    let start = data.partition_point(|point| point.timestamp <= after);
+    data[start..]
+        .iter()
+        .filter_map(|point| {
+            let mut baseline = (0.0, 0.0, 0);
+            for season in 1..=count {
+                let center = point.timestamp - season * period;
+                let from = data.partition_point(|other| other.timestamp < center - tolerance);
+                let to = data.partition_point(|other| other.timestamp <= center + tolerance);
+                for other in &data[from..to] {
+                    baseline.0 += other.value;
+                    baseline.1 += other.value * other.value;
+                    baseline.2 += 1;
+                }
+            }
+            flag(point, baseline, sigmas)
+        })
+        .collect()
+}
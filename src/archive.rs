@@ -0,0 +1,262 @@
+//! Archiving points to S3-compatible object storage before retention prunes
+//! them, so an "ephemeral" chart doesn't mean the raw data is gone for good.
+//! Each prune uploads one gzipped CSV per namespace under
+//! `ARCHIVE_S3_PREFIX{namespace}/`, holding `namespace,id,timestamp,value`
+//! rows; if an upload fails the points stay where they are and the next
+//! prune tries again.
+//!
+//! Uploads go to URLs presigned with AWS Signature Version 4 by
+//! [`rusty_s3`], path-style (`{endpoint}/{bucket}/{key}`), which AWS, MinIO,
+//! R2 and most other S3-compatible stores accept.
+
+use std::io::{BufWriter, Write};
+use std::time::Duration;
+
+use chrono::Utc;
+use flate2::{write::GzEncoder, Compression};
+use futures_util::TryStreamExt;
+use rusty_s3::{Bucket, Credentials, S3Action, UrlStyle};
+use crate::{
+    auth,
+    client::{self, Endpoint},
+    store::MetricStore,
+};
+
+pub type ArchiveError = client::ClientError;
+
+/// Rows are gathered this many bytes at a time before being compressed
+const BUFFER_BYTES: usize = 64 * 1024;
+/// How long an upload's presigned URL stays good; it's used straight away
+const SIGNATURE_LIFETIME: Duration = Duration::from_secs(300);
+
+/// Where archives go, from the `ARCHIVE_S3_*` settings.
+#[derive(Clone, Debug)]
+pub struct ArchiveConfig {
+    pub endpoint: Endpoint,
+    pub bucket: String,
+    /// Prepended to every object key, e.g. `somnial/`
+    pub prefix: String,
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+}
+
+/// Uploads the points of `namespace` older than `cutoff` with ids up to
+/// `through`, and returns how many there were. Pruning takes `through` from
+/// [`MetricStore::watermark`] before archiving, so an old point backfilled
+/// after its archive was written is kept for the next round rather than
+/// deleted unarchived.
+pub async fn archive_namespace(
+    store: &dyn MetricStore,
+    archive: &ArchiveConfig,
+    namespace: &str,
+    cutoff: i64,
+    through: i64,
+) -> Result<u64, ArchiveError> {
+    let mut rows = store.scan(namespace, cutoff, through);
+    let mut objects = Objects::new(archive);
+    while let Some((id, point)) = rows.try_next().await? {
+        objects.push(namespace, &id, point.timestamp, point.value).await?;
+    }
+    objects.finish().await
+}
+
+/// Rows sorted by namespace, cut into one CSV object per namespace.
+struct Objects<'a> {
+    archive: &'a ArchiveConfig,
+    current: Option<(String, BufWriter<GzEncoder<Vec<u8>>>)>,
+    points: u64,
+}
+
+impl<'a> Objects<'a> {
+    fn new(archive: &'a ArchiveConfig) -> Self {
+        Objects {
+            archive,
+            current: None,
+            points: 0,
+        }
+    }
+
+    async fn push(&mut self, namespace: &str, id: &str, timestamp: i64, value: f64) -> Result<(), ArchiveError> {
+        if self.current.as_ref().is_some_and(|(current, _)| current != namespace) {
+            self.upload().await?;
+        }
+        let (_, csv) = self.current.get_or_insert_with(|| {
+            let mut csv = BufWriter::with_capacity(BUFFER_BYTES, GzEncoder::new(Vec::new(), Compression::default()));
+            let _ = csv.write_all(b"namespace,id,timestamp,value\n");
+            (namespace.to_string(), csv)
+        });
+        writeln!(csv, "{},{},{},{}", csv_field(namespace), csv_field(id), timestamp, value)?;
+        self.points += 1;
+        Ok(())
+    }
+
+    async fn upload(&mut self) -> Result<(), ArchiveError> {
+        let Some((namespace, csv)) = self.current.take() else {
+            return Ok(());
+        };
+        let key = format!(
+            "{}{}/{}-{}.csv.gz",
+            self.archive.prefix,
+            namespace,
+            Utc::now().format("%Y%m%dT%H%M%SZ"),
+            auth::random_string(6)
+        );
+        let csv = csv.into_inner().map_err(|err| err.into_error())?;
+        put_object(self.archive, &key, csv.finish()?).await
+    }
+
+    async fn finish(mut self) -> Result<u64, ArchiveError> {
+        self.upload().await?;
+        Ok(self.points)
+    }
+}
+
+/// Quotes a CSV field when it needs it.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// `PUT`s one object through a presigned URL.
+async fn put_object(archive: &ArchiveConfig, key: &str, body: Vec<u8>) -> Result<(), ArchiveError> {
+    let bucket = Bucket::new(
+        archive.endpoint.url().clone(),
+        UrlStyle::Path,
+        archive.bucket.clone(),
+        archive.region.clone(),
+    )?;
+    let credentials = Credentials::new(&archive.access_key_id, &archive.secret_access_key);
+    let url = bucket.put_object(Some(&credentials), key).sign(SIGNATURE_LIFETIME);
+    let request = client::http().put(url).header("Content-Type", "application/gzip").body(body);
+    let response = client::send(request, None).await?;
+    if !(200..300).contains(&response.status) {
+        return Err(format!("object store answered {} for {}", response.status, key).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::sync::{Arc, Mutex};
+
+    use axum::{
+        body::Bytes,
+        extract::{Path, RawQuery, State},
+        http::{HeaderMap, StatusCode},
+        routing::put,
+        Router,
+    };
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    /// What a request to the fake object store carried.
+    struct Upload {
+        key: String,
+        query: String,
+        content_type: String,
+        csv: String,
+    }
+
+    type Uploads = Arc<Mutex<Vec<Upload>>>;
+
+    /// An object store on a local port that keeps what's put in it, except
+    /// in the bucket `locked`.
+    async fn object_store() -> (ArchiveConfig, Uploads) {
+        async fn put_object(
+            State(uploads): State<Uploads>,
+            Path(key): Path<String>,
+            RawQuery(query): RawQuery,
+            headers: HeaderMap,
+            body: Bytes,
+        ) -> StatusCode {
+            if key.starts_with("locked/") {
+                return StatusCode::FORBIDDEN;
+            }
+            let mut csv = String::new();
+            GzDecoder::new(&body[..]).read_to_string(&mut csv).unwrap();
+            uploads.lock().unwrap().push(Upload {
+                key,
+                query: query.unwrap_or_default(),
+                content_type: headers["content-type"].to_str().unwrap().to_string(),
+                csv,
+            });
+            StatusCode::OK
+        }
+
+        let uploads = Uploads::default();
+        let app = Router::new().route("/{*key}", put(put_object)).with_state(uploads.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let archive = ArchiveConfig {
+            endpoint: Endpoint::parse(&format!("http://{}", address)).unwrap(),
+            bucket: "points".to_string(),
+            prefix: "somnial/".to_string(),
+            region: "eu-west-1".to_string(),
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "secret".to_string(),
+        };
+        (archive, uploads)
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_they_need_it() {
+        assert_eq!(csv_field("build_time"), "build_time");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[tokio::test]
+    async fn uploads_one_signed_csv_per_namespace() {
+        let (archive, uploads) = object_store().await;
+        let mut objects = Objects::new(&archive);
+        objects.push("ci", "build_time", 1_700_000_000, 41.5).await.unwrap();
+        objects.push("ci", "size,x86", 1_700_000_060, 3.0).await.unwrap();
+        objects.push("web", "p99", 1_700_000_000, 120.0).await.unwrap();
+        assert_eq!(objects.finish().await.unwrap(), 3);
+
+        let uploads = uploads.lock().unwrap();
+        assert_eq!(uploads.len(), 2);
+        assert!(uploads[0].key.starts_with("points/somnial/ci/"), "{}", uploads[0].key);
+        assert!(uploads[0].key.ends_with(".csv.gz"));
+        assert_eq!(
+            uploads[0].csv,
+            "namespace,id,timestamp,value\nci,build_time,1700000000,41.5\nci,\"size,x86\",1700000060,3\n"
+        );
+        assert!(uploads[1].key.starts_with("points/somnial/web/"));
+        assert_eq!(uploads[1].csv, "namespace,id,timestamp,value\nweb,p99,1700000000,120\n");
+
+        let upload = &uploads[0];
+        assert_eq!(upload.content_type, "application/gzip");
+        assert!(upload.query.contains("X-Amz-Algorithm=AWS4-HMAC-SHA256"), "{}", upload.query);
+        assert!(upload.query.contains("X-Amz-Credential=AKIDEXAMPLE%2F"), "{}", upload.query);
+        assert!(upload.query.contains("%2Feu-west-1%2Fs3%2Faws4_request"), "{}", upload.query);
+        assert!(upload.query.contains("X-Amz-Expires=300"));
+        assert!(upload.query.contains("X-Amz-Signature="));
+    }
+
+    #[tokio::test]
+    async fn nothing_is_uploaded_without_points() {
+        let (archive, uploads) = object_store().await;
+        assert_eq!(Objects::new(&archive).finish().await.unwrap(), 0);
+        assert!(uploads.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_uploads_are_errors() {
+        let (mut archive, uploads) = object_store().await;
+        archive.bucket = "locked".to_string();
+        let mut objects = Objects::new(&archive);
+        objects.push("ci", "build_time", 1_700_000_000, 1.0).await.unwrap();
+        let err = objects.finish().await.unwrap_err();
+        assert!(err.to_string().starts_with("object store answered 403 for somnial/ci/"), "{}", err);
+        assert!(uploads.lock().unwrap().is_empty());
+    }
+}
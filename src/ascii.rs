@@ -0,0 +1,131 @@
+//! Plain-text charts for terminals: a braille plot plus summary figures,
+//! so `curl`ing a chart URL shows something readable.
+
+use chrono::{DateTime, Utc};
+
+use crate::{badge::format_value, MetricPoint};
+
+/// Plot size in characters; each braille character is a 2x4 grid of dots.
+const COLUMNS: usize = 60;
+const ROWS: usize = 4;
+
+/// Bit for the dot at (`column`, `row`) within one braille character.
+fn dot(column: usize, row: usize) -> u32 {
+    match (column, row) {
+        (0, 3) => 0x40,
+        (1, 3) => 0x80,
+        (0, r) => 1 << r,
+        (_, r) => 1 << (r + 3),
+    }
+}
+
+/// Draws `data` as `ROWS` lines of braille. Points are placed by time and
+/// joined, so gaps in the series show as straight runs.
+fn plot(data: &[MetricPoint]) -> Vec<String> {
+    let (width, height) = (COLUMNS * 2, ROWS * 4);
+    let t_min = data.iter().map(|p| p.timestamp).min().unwrap_or(0);
+    let t_max = data.iter().map(|p| p.timestamp).max().unwrap_or(0);
+    let v_min = data.iter().map(|p| p.value).fold(f64::INFINITY, f64::min);
+    let v_max = data.iter().map(|p| p.value).fold(f64::NEG_INFINITY, f64::max);
+
+    // Average the points that land in each dot column
+    let mut sums = vec![(0.0, 0usize); width];
+    for point in data {
+        let x = if t_max > t_min {
+            ((point.timestamp - t_min) as f64 / (t_max - t_min) as f64 * (width - 1) as f64).round() as usize
+        } else {
+            width / 2
+        };
+        sums[x].0 += point.value;
+        sums[x].1 += 1;
+    }
+    let level = |value: f64| {
+        if v_max > v_min {
+            ((value - v_min) / (v_max - v_min) * (height - 1) as f64).round() as usize
+        } else {
+            height / 2
+        }
+    };
+    let occupied: Vec<(usize, usize)> = sums
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, count))| *count > 0)
+        .map(|(x, (sum, count))| (x, level(sum / *count as f64)))
+        .collect();
+
+    let mut cells = vec![vec![0u32; COLUMNS]; ROWS];
+    let mut set = |x: usize, level: usize| {
+        let row = height - 1 - level;
+        cells[row / 4][x / 2] |= dot(x % 2, row % 4);
+    };
+    if let [(x, y)] = occupied.as_slice() {
+        set(*x, *y);
+    }
+    for pair in occupied.windows(2) {
+        let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+        let mut previous = y0;
+        for x in x0..=x1 {
+            let y = (y0 as f64 + (y1 as f64 - y0 as f64) * (x - x0) as f64 / (x1 - x0) as f64).round() as usize;
+            // Fill the vertical step so steep changes stay connected
+            for level in previous.min(y)..=previous.max(y) {
+                set(x, level);
+            }
+            previous = y;
+        }
+    }
+
+    cells
+        .into_iter()
+        .map(|row| row.into_iter().map(|bits| char::from_u32(0x2800 + bits).unwrap()).collect())
+        .collect()
+}
+
+fn timestamp_label(timestamp: i64) -> String {
+    DateTime::<Utc>::from_timestamp(timestamp, 0)
+        .map(|time| time.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_default()
+}
+
+/// The full text response for one series.
+pub fn render(namespace: &str, id: &str, data: &[MetricPoint]) -> String {
+    let mut out = format!("{}/{}\n\n", namespace, id);
+    let (Some(first), Some(latest)) = (data.first(), data.last()) else {
+        out.push_str("No data points yet.\n");
+        return out;
+    };
+
+    let min = data.iter().map(|p| p.value).fold(f64::INFINITY, f64::min);
+    let max = data.iter().map(|p| p.value).fold(f64::NEG_INFINITY, f64::max);
+    let avg = data.iter().map(|p| p.value).sum::<f64>() / data.len() as f64;
+
+    // Scale labels on the top and bottom lines
+    let lines = plot(data);
+    for (i, line) in lines.iter().enumerate() {
+        let label = match i {
+            0 => format_value(max),
+            i if i == lines.len() - 1 => format_value(min),
+            _ => String::new(),
+        };
+        out.push_str(format!("{} {}", line, label).trim_end());
+        out.push('\n');
+    }
+
+    let (start, end) = (timestamp_label(first.timestamp), timestamp_label(latest.timestamp));
+    if start == end {
+        out.push_str(&format!("{}\n\n", start));
+    } else {
+        // Start and end times under the plot's left and right edges
+        let gap = (COLUMNS + 1).saturating_sub(start.len() + end.len()).max(1);
+        out.push_str(&format!("{}{}{}\n\n", start, " ".repeat(gap), end));
+    }
+    out.push_str(&format!(
+        "latest {}  min {}  avg {}  max {}  ({} point{})\n",
+        format_value(latest.value),
+        format_value(min),
+        format_value(avg),
+        format_value(max),
+        data.len(),
+        if data.len() == 1 { "" } else { "s" }
+    ));
+    out
+}
@@ -0,0 +1,45 @@
+use axum::http::{HeaderMap, StatusCode};
+use rand::{distributions::Alphanumeric, Rng};
+
+use crate::config::Config;
+
+/// Extracts the token from an `Authorization: Bearer <token>` header.
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// Admin endpoints are closed entirely unless `ADMIN_TOKEN` is configured.
+pub fn require_admin(config: &Config, headers: &HeaderMap) -> Result<(), StatusCode> {
+    match (config.admin_token.as_deref(), bearer_token(headers)) {
+        (Some(expected), Some(given)) if constant_time_eq(expected.as_bytes(), given.as_bytes()) => {
+            Ok(())
+        }
+        _ => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+/// Accepts either the bearer token issued with a resource or, when
+/// configured, the admin token.
+pub fn require_owner_or_admin(config: &Config, headers: &HeaderMap, owner_token: &str) -> Result<(), StatusCode> {
+    match bearer_token(headers) {
+        Some(given) if constant_time_eq(owner_token.as_bytes(), given.as_bytes()) => Ok(()),
+        _ => require_admin(config, headers),
+    }
+}
+
+/// Random letters and digits, for edit tokens and generated slugs.
+pub fn random_string(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,239 @@
+//! `GET /admin/backup` and `POST /admin/restore`: whole-database backups over
+//! HTTP, so operators don't need a shell on the host to take one.
+//!
+//! A backup is `VACUUM INTO` a temporary file, which SQLite guarantees is a
+//! consistent snapshot even while writes carry on, streamed back as a plain
+//! SQLite database. Restoring takes such a file, checks it was written by a
+//! server at the same migration version, and replaces every table's rows with
+//! its own in one transaction. The only schema it changes is which months of
+//! points have tables, to match the backup's.
+
+use std::path::{Path, PathBuf};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use futures_util::StreamExt;
+use serde_json::{json, Value};
+use sqlx::{sqlite::SqliteConnection, Connection, Row};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::{auth, store, AppState};
+
+const CHUNK_BYTES: usize = 64 * 1024;
+/// Every SQLite database file starts with this
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(json!({ "error": message })))
+}
+
+fn database_error<E: std::fmt::Display>(err: E) -> ApiError {
+    crate::errors::report(&err);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+/// A file in the temp directory that is removed once this is dropped.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(purpose: &str) -> Self {
+        let name = format!("somnial-{}-{}.db", purpose, auth::random_string(12));
+        TempFile(std::env::temp_dir().join(name))
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// `path` as a SQLite URI. `VACUUM INTO` and `ATTACH` open files the way
+/// the database was opened, which for an in-memory one would put them in
+/// memory too, unless the URI says they're files on disk.
+fn file_uri(path: &str) -> String {
+    let path = path.replace('%', "%25").replace('?', "%3F").replace('#', "%23");
+    format!("file:{}?mode=rwc", path)
+}
+
+pub async fn get_backup(State(state): State<AppState>, headers: HeaderMap) -> Result<Response, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+
+    let snapshot = TempFile::new("backup");
+    let path = snapshot
+        .path()
+        .to_str()
+        .ok_or_else(|| error(StatusCode::INTERNAL_SERVER_ERROR, "temp directory path isn't UTF-8"))?;
+    sqlx::query("VACUUM INTO ?")
+        .bind(file_uri(path))
+        .execute(&state.pool)
+        .await
+        .map_err(database_error)?;
+
+    let file = tokio::fs::File::open(snapshot.path()).await.map_err(database_error)?;
+    let length = file.metadata().await.map_err(database_error)?.len();
+    // The snapshot goes with the stream, so it is deleted once sent or abandoned
+    let chunks = futures_util::stream::try_unfold((file, snapshot), |(mut file, snapshot)| async move {
+        let mut chunk = vec![0; CHUNK_BYTES];
+        let read = file.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok::<_, std::io::Error>(None);
+        }
+        chunk.truncate(read);
+        Ok(Some((Bytes::from(chunk), (file, snapshot))))
+    });
+
+    let filename = format!("somnial-{}.db", Utc::now().format("%Y%m%dT%H%M%SZ"));
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/vnd.sqlite3".to_string()),
+            (header::CONTENT_LENGTH, length.to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+        ],
+        Body::from_stream(chunks),
+    )
+        .into_response())
+}
+
+pub async fn post_restore(State(state): State<AppState>, headers: HeaderMap, body: Body) -> Result<Response, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+
+    // Uploads can be as large as the database, so they go to disk rather than memory
+    let upload = TempFile::new("restore");
+    let mut file = tokio::fs::File::create(upload.path()).await.map_err(database_error)?;
+    let mut chunks = body.into_data_stream();
+    let mut written = 0;
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.map_err(|_| error(StatusCode::BAD_REQUEST, "upload was interrupted"))?;
+        if written < SQLITE_HEADER.len() {
+            let expected = &SQLITE_HEADER[written..SQLITE_HEADER.len().min(written + chunk.len())];
+            if !chunk.starts_with(expected) {
+                return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "upload isn't a SQLite database"));
+            }
+        }
+        file.write_all(&chunk).await.map_err(database_error)?;
+        written += chunk.len();
+    }
+    if written < SQLITE_HEADER.len() {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "upload isn't a SQLite database"));
+    }
+    file.sync_all().await.map_err(database_error)?;
+    drop(file);
+
+    let path = upload
+        .path()
+        .to_str()
+        .ok_or_else(|| error(StatusCode::INTERNAL_SERVER_ERROR, "temp directory path isn't UTF-8"))?;
+    let mut conn = state.pool.acquire().await.map_err(database_error)?;
+    sqlx::query("ATTACH DATABASE ? AS restore")
+        .bind(file_uri(path))
+        .execute(&mut *conn)
+        .await
+        .map_err(|_| error(StatusCode::UNPROCESSABLE_ENTITY, "upload isn't a SQLite database"))?;
+    let restored = restore_attached(&mut conn).await;
+    // The connection goes back to the pool, so it mustn't keep the upload attached
+    sqlx::query("DETACH DATABASE restore")
+        .execute(&mut *conn)
+        .await
+        .map_err(database_error)?;
+    let (tables, rows) = restored?;
+
+    state.invalidate_all();
+    state.domains.reload(&state.pool).await.map_err(database_error)?;
+    state.aliases.reload(&state.pool).await.map_err(database_error)?;
+    state.alerts.reload(&state.pool, state.store.as_ref()).await.map_err(database_error)?;
+    state.webhooks.reload(&state.pool).await.map_err(database_error)?;
+
+    Ok(Json(json!({ "tables": tables, "rows": rows })).into_response())
+}
+
+/// Replaces the contents of every table in `main` with the same table in the
+/// attached `restore` database, returning how many tables and rows it copied.
+async fn restore_attached(conn: &mut SqliteConnection) -> Result<(usize, u64), ApiError> {
+    let not_ours = || {
+        error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "upload isn't a somnial database at this server's migration version",
+        )
+    };
+    let check: String = sqlx::query_scalar("PRAGMA restore.quick_check")
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|_| not_ours())?;
+    if check != "ok" {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "upload is corrupt"));
+    }
+    let ours: Vec<i64> = sqlx::query_scalar("SELECT version FROM main._sqlx_migrations ORDER BY version")
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(database_error)?;
+    let theirs: Vec<i64> = sqlx::query_scalar("SELECT version FROM restore._sqlx_migrations ORDER BY version")
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(|_| not_ours())?;
+    if ours != theirs {
+        return Err(not_ours());
+    }
+
+    let mut tx = conn.begin().await.map_err(database_error)?;
+    // The backup may have points in months we have no table for, or the
+    // other way round
+    store::restore_months(&mut tx).await.map_err(database_error)?;
+    let tables: Vec<String> = sqlx::query_scalar(
+        "SELECT name FROM main.sqlite_master
+         WHERE type = 'table' AND name NOT LIKE 'sqlite_%' AND name != '_sqlx_migrations'
+         ORDER BY name",
+    )
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(database_error)?;
+
+    // Rows go in table by table, so references are only checked at the end
+    sqlx::query("PRAGMA defer_foreign_keys = ON")
+        .execute(&mut *tx)
+        .await
+        .map_err(database_error)?;
+    // Every delete comes first, so cascades can't reach rows already copied
+    for table in &tables {
+        sqlx::query(&format!("DELETE FROM main.\"{}\"", table))
+            .execute(&mut *tx)
+            .await
+            .map_err(database_error)?;
+    }
+    let mut rows = 0;
+    for table in &tables {
+        let columns: Vec<String> = sqlx::query("SELECT name FROM pragma_table_info(?, 'main')")
+            .bind(table)
+            .fetch_all(&mut *tx)
+            .await
+            .map_err(database_error)?
+            .iter()
+            .map(|row| format!("\"{}\"", row.get::<String, _>(0)))
+            .collect();
+        // Copying the rowid too keeps point ids the same as in the backup
+        let columns = columns.join(", ");
+        let result = sqlx::query(&format!(
+            "INSERT INTO main.\"{table}\" (rowid, {columns}) SELECT rowid, {columns} FROM restore.\"{table}\""
+        ))
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| not_ours())?;
+        rows += result.rows_affected();
+    }
+    tx.commit().await.map_err(database_error)?;
+
+    Ok((tables.len(), rows))
+}
@@ -0,0 +1,906 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+
+use crate::{
+    cache::Weighted,
+    errors,
+    ids::{NamespacePath, SeriesPath},
+    meta::{self, MetricMeta},
+    store::{MetricStore, StoreError},
+    traces, AppState, MetricPoint,
+};
+
+// Badge dimensions
+const WIDTH: i32 = 240;
+const HEIGHT: i32 = 40;
+const CORNER_RADIUS: i32 = 6;
+const PADDING: i32 = 8;
+
+/// Dark palette, matching GitHub's dark mode background and text colours.
+const DARK_CSS: &str = r#"
+      .badge-fill { fill: #0d1117; }
+      .badge-bg { fill: #0d1117; stroke: #30363d; }
+      .badge-text, .badge-value, .trend { fill: #e6edf3; }
+      .sparkline { stroke: #e6edf3; }
+      .sparkline-area, .sparkline-bar, .sparkline-dot { fill: #e6edf3; }
+      .separator { stroke: #30363d; }"#;
+
+/// Pure black on white with a heavier outline.
+const HIGH_CONTRAST_CSS: &str = r#"
+      .badge-fill { fill: #ffffff; }
+      .badge-bg { fill: #ffffff; stroke: #000000; stroke-width: 2; }
+      .badge-text, .badge-value, .trend { fill: #000000; }
+      .sparkline { stroke: #000000; }
+      .sparkline-area, .sparkline-bar, .sparkline-dot { fill: #000000; }
+      .separator { stroke: #000000; }"#;
+
+/// `?theme=` on badge URLs.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Theme {
+    /// SVG badges follow the viewer's colour scheme; PNGs are light
+    #[default]
+    Auto,
+    Light,
+    Dark,
+    HighContrast,
+}
+
+/// `?style=` on badge URLs.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Style {
+    #[default]
+    Sparkline,
+    /// Min, average and max over the last week in place of the sparkline
+    Stats,
+}
+
+/// `?spark=`: how the sparkline is drawn.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Spark {
+    #[default]
+    Line,
+    /// Line with the space below it filled, for cumulative series
+    Area,
+    Bars,
+    Dots,
+}
+
+/// `?window=`: the time span a badge covers, instead of the last 50 points.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum Window {
+    #[serde(rename = "24h")]
+    Day,
+    #[serde(rename = "7d")]
+    Week,
+    #[serde(rename = "30d")]
+    Month,
+}
+
+impl Window {
+    fn label(self) -> &'static str {
+        match self {
+            Window::Day => "24h",
+            Window::Week => "7d",
+            Window::Month => "30d",
+        }
+    }
+
+    fn seconds(self) -> i64 {
+        match self {
+            Window::Day => 86400,
+            Window::Week => 7 * 86400,
+            Window::Month => 30 * 86400,
+        }
+    }
+}
+
+/// Reads a badge option as its query parameter is written, for the
+/// `BADGE_DEFAULT_*` settings.
+fn parse_option<T: for<'de> Deserialize<'de>>(value: &str) -> Result<T, ()> {
+    use serde::de::{value::Error, IntoDeserializer};
+    T::deserialize(IntoDeserializer::<Error>::into_deserializer(value)).map_err(|_| ())
+}
+
+impl std::str::FromStr for Theme {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, ()> {
+        parse_option(value)
+    }
+}
+
+impl std::str::FromStr for Style {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, ()> {
+        parse_option(value)
+    }
+}
+
+impl std::str::FromStr for Spark {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, ()> {
+        parse_option(value)
+    }
+}
+
+impl std::str::FromStr for Window {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, ()> {
+        parse_option(value)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct BadgeQuery {
+    pub theme: Option<Theme>,
+    pub style: Option<Style>,
+    pub spark: Option<Spark>,
+    pub window: Option<Window>,
+    /// Comma-separated series for a combined namespace badge
+    pub ids: Option<String>,
+}
+
+/// How a badge is drawn: what its URL asks for, and the server's badge
+/// defaults for whatever it leaves out.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BadgeOptions {
+    pub theme: Theme,
+    pub style: Style,
+    pub spark: Spark,
+    /// `None` draws the last 50 points
+    pub window: Option<Window>,
+}
+
+impl BadgeQuery {
+    fn options(&self, defaults: &BadgeOptions) -> BadgeOptions {
+        BadgeOptions {
+            theme: self.theme.unwrap_or(defaults.theme),
+            style: self.style.unwrap_or(defaults.style),
+            spark: self.spark.unwrap_or(defaults.spark),
+            window: self.window.or(defaults.window),
+        }
+    }
+}
+
+/// How far back `?style=stats` looks when no window is given.
+const SUMMARY_WINDOW: Window = Window::Week;
+
+/// Windowed sparklines are averaged down to at most this many points.
+const MAX_SPARKLINE_POINTS: usize = 120;
+
+/// Aggregates shown by `?style=stats`.
+pub struct Summary {
+    pub min: f64,
+    pub avg: f64,
+    pub max: f64,
+    pub count: i64,
+}
+
+/// Extra stylesheet rules for `theme`. Only SVG can honour `Auto`, since a
+/// rasterized badge has no viewer to ask.
+fn theme_css(theme: Theme, svg: bool) -> String {
+    match theme {
+        Theme::Dark => DARK_CSS.to_string(),
+        Theme::HighContrast => HIGH_CONTRAST_CSS.to_string(),
+        Theme::Auto if svg => format!("\n      @media (prefers-color-scheme: dark) {{{}\n      }}", DARK_CSS),
+        _ => String::new(),
+    }
+}
+
+/// Sparkline drawing area within a badge row.
+const CHART_TOP: i32 = 20; // Below text
+const CHART_HEIGHT: i32 = 16;
+
+/// Sparkline positions for `data`. `span` pins the x axis to a time range;
+/// by default it fits the data.
+fn sparkline_points(data: &[MetricPoint], span: Option<(i64, i64)>) -> Vec<(i32, i32)> {
+    if data.is_empty() {
+        return Vec::new();
+    }
+
+    let values: Vec<f64> = data.iter().map(|p| p.value).collect();
+    let min_val = values.iter().fold(f64::INFINITY, |a, &b| a.min(b));
+    let max_val = values.iter().fold(f64::NEG_INFINITY, |a, &b| a.max(b));
+
+    let chart_width = WIDTH - 2 * PADDING;
+
+    if data.len() == 1 {
+        // Single point - centred
+        return vec![(WIDTH / 2, CHART_TOP + CHART_HEIGHT / 2)];
+    }
+
+    let timestamps: Vec<i64> = data.iter().map(|p| p.timestamp).collect();
+    let (min_time, max_time) = span.unwrap_or((
+        *timestamps.iter().min().unwrap(),
+        *timestamps.iter().max().unwrap(),
+    ));
+    let time_range = (max_time - min_time).max(1); // Avoid division by zero
+
+    data.iter()
+        .map(|point| {
+            let x = PADDING + ((point.timestamp - min_time) as f64 / time_range as f64 * chart_width as f64) as i32;
+            // A flat series sits in the middle of the chart
+            let y = if max_val == min_val {
+                CHART_TOP + CHART_HEIGHT / 2
+            } else {
+                CHART_TOP + CHART_HEIGHT - ((point.value - min_val) / (max_val - min_val) * CHART_HEIGHT as f64) as i32
+            };
+            (x, y)
+        })
+        .collect()
+}
+
+fn line_path(points: &[(i32, i32)]) -> String {
+    match points {
+        // Single point - draw a small horizontal line
+        [(x, y)] => format!("M{} {} L{} {}", x - 5, y, x + 5, y),
+        _ => points
+            .iter()
+            .enumerate()
+            .map(|(i, (x, y))| format!("{}{} {}", if i == 0 { "M" } else { "L" }, x, y))
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+/// The sparkline drawn in `spark` style, or nothing for an empty series.
+fn sparkline_svg(data: &[MetricPoint], span: Option<(i64, i64)>, spark: Spark) -> String {
+    let points = sparkline_points(data, span);
+    if points.is_empty() {
+        return String::new();
+    }
+    let baseline = CHART_TOP + CHART_HEIGHT;
+
+    match spark {
+        Spark::Line => format!(r#"<path d="{}" class="sparkline"/>"#, line_path(&points)),
+        Spark::Area => {
+            let line = line_path(&points);
+            let (first, last) = match points.as_slice() {
+                [(x, _)] => (x - 5, x + 5),
+                _ => (points[0].0, points[points.len() - 1].0),
+            };
+            format!(
+                r#"<path d="{} L{} {} L{} {} Z" class="sparkline-area"/><path d="{}" class="sparkline"/>"#,
+                line, last, baseline, first, baseline, line
+            )
+        }
+        Spark::Bars => {
+            // Bars share the width evenly, with a pixel's gap when they fit
+            let slot = (WIDTH - 2 * PADDING) as f64 / points.len() as f64;
+            let width = if slot >= 3.0 { slot - 1.0 } else { slot.max(1.0) };
+            points
+                .iter()
+                .map(|(x, y)| {
+                    let height = (baseline - y).max(1);
+                    let left = (*x as f64 - width / 2.0).clamp(PADDING as f64, (WIDTH - PADDING) as f64 - width);
+                    format!(
+                        r#"<rect x="{:.1}" y="{}" width="{:.1}" height="{}" class="sparkline-bar"/>"#,
+                        left,
+                        baseline - height,
+                        width,
+                        height
+                    )
+                })
+                .collect()
+        }
+        Spark::Dots => points
+            .iter()
+            .map(|(x, y)| format!(r#"<circle cx="{}" cy="{}" r="1.5" class="sparkline-dot"/>"#, x, y))
+            .collect(),
+    }
+}
+
+/// Compact number formatting for the badge, e.g. `12.4`, `1.05k`, `3.2M`.
+pub fn format_value(value: f64) -> String {
+    let (scaled, suffix) = match value.abs() {
+        v if v >= 1e9 => (value / 1e9, "G"),
+        v if v >= 1e6 => (value / 1e6, "M"),
+        v if v >= 1e4 => (value / 1e3, "k"),
+        _ => (value, ""),
+    };
+
+    // Three significant digits is plenty at badge size
+    let decimals = match scaled.abs() {
+        v if v >= 100.0 => 0,
+        v if v >= 10.0 => 1,
+        _ => 2,
+    };
+    let formatted = format!("{:.*}", decimals, scaled);
+    let trimmed = if formatted.contains('.') {
+        formatted.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        &formatted
+    };
+    format!("{}{}", trimmed, suffix)
+}
+
+/// Latest value right-aligned on the title line, followed by an arrow
+/// pointing the way it moved since the previous point.
+fn latest_value_svg(data: &[MetricPoint], meta: &MetricMeta) -> String {
+    let Some(latest) = data.last() else {
+        return String::new();
+    };
+
+    let arrow_x = WIDTH - PADDING - 7;
+    let arrow = match data.len().checked_sub(2).map(|i| data[i].value) {
+        Some(previous) if latest.value > previous => {
+            format!(r#"<path d="M{} 12 L{} 5 L{} 12 Z" class="trend"/>"#, arrow_x, arrow_x as f64 + 3.5, arrow_x + 7)
+        }
+        Some(previous) if latest.value < previous => {
+            format!(r#"<path d="M{} 5 L{} 12 L{} 5 Z" class="trend"/>"#, arrow_x, arrow_x as f64 + 3.5, arrow_x + 7)
+        }
+        _ => String::new(),
+    };
+    let text_x = if arrow.is_empty() { WIDTH - PADDING } else { arrow_x - 3 };
+
+    format!(
+        r#"<text x="{}" y="13" text-anchor="end" class="badge-value">{}</text>{}"#,
+        text_x,
+        escape_xml(&meta.format(latest.value)),
+        arrow
+    )
+}
+
+fn summary_svg(summary: &Summary, meta: &MetricMeta) -> String {
+    format!(
+        r#"<text x="{}" y="32" class="badge-value">{}</text>"#,
+        PADDING,
+        escape_xml(&format!(
+            "min {}  avg {}  max {}",
+            meta.format(summary.min),
+            meta.format(summary.avg),
+            meta.format(summary.max)
+        ))
+    )
+}
+
+/// One series' line in a badge; combined badges stack several.
+struct BadgeRow<'a> {
+    name: &'a str,
+    data: &'a [MetricPoint],
+    /// Replaces the sparkline with min/avg/max figures
+    summary: Option<&'a Summary>,
+    /// Unit and decimals for the figures
+    meta: &'a MetricMeta,
+}
+
+fn badge_row_svg(row: &BadgeRow, span: Option<(i64, i64)>, spark: Spark) -> String {
+    let body = match row.summary {
+        Some(summary) => summary_svg(summary, row.meta),
+        None => sparkline_svg(row.data, span, spark),
+    };
+
+    format!(
+        r#"<!-- Metric name -->
+  <text x="{}" y="13" class="badge-text">{}</text>
+
+  <!-- Latest value and trend -->
+  {}
+
+  <!-- Sparkline or summary -->
+  {}"#,
+        PADDING,
+        escape_xml(row.name),
+        latest_value_svg(row.data, row.meta),
+        body
+    )
+}
+
+/// Renders `rows` one above the other, `HEIGHT` pixels apart.
+fn badge_svg(rows: &[BadgeRow], extra_css: &str, span: Option<(i64, i64)>, spark: Spark) -> String {
+    let content = rows
+        .iter()
+        .enumerate()
+        .map(|(i, row)| {
+            let separator = if i == 0 {
+                String::new()
+            } else {
+                format!(r#"<line x1="{}" y1="0" x2="{}" y2="0" class="separator"/>"#, PADDING, WIDTH - PADDING)
+            };
+            format!(
+                "<g transform=\"translate(0 {})\">\n  {}{}\n  </g>",
+                i as i32 * HEIGHT,
+                separator,
+                badge_row_svg(row, span, spark)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n  ");
+
+    badge_frame(HEIGHT * rows.len().max(1) as i32, extra_css, &content)
+}
+
+/// Most characters that fit on one line of a badge.
+const LINE_CHARS: usize = 34;
+
+/// Shortens `text` to [`LINE_CHARS`], marking the cut with an ellipsis.
+fn fit_line(text: &str) -> String {
+    if text.chars().count() <= LINE_CHARS {
+        return text.to_string();
+    }
+    let mut line: String = text.chars().take(LINE_CHARS - 1).collect();
+    line.push('…');
+    line
+}
+
+/// How many series a namespace tracks, and which one was written last.
+fn namespace_summary_svg(namespace: &str, count: i64, latest: Option<(&str, i64)>, extra_css: &str) -> String {
+    let noun = if count == 1 { "metric" } else { "metrics" };
+    let updated = match latest.and_then(|(id, ts)| Some((id, chrono::DateTime::<chrono::Utc>::from_timestamp(ts, 0)?))) {
+        Some((id, time)) => format!("{} · {}", time.format("%b %d %H:%M"), id),
+        None => "no data yet".to_string(),
+    };
+    let content = format!(
+        r#"<text x="{}" y="13" class="badge-text">{}</text>
+  <text x="{}" y="13" text-anchor="end" class="badge-value">{} {}</text>
+  <text x="{}" y="32" class="badge-value">{}</text>"#,
+        PADDING,
+        escape_xml(&fit_line(namespace)),
+        WIDTH - PADDING,
+        count,
+        noun,
+        PADDING,
+        escape_xml(&fit_line(&updated))
+    );
+    badge_frame(HEIGHT, extra_css, &content)
+}
+
+/// The badge background and stylesheet around `content`.
+fn badge_frame(height: i32, extra_css: &str, content: &str) -> String {
+    format!(
+        r#"<svg width="{}" height="{}" xmlns="http://www.w3.org/2000/svg">
+  <defs>
+    <style>
+      .badge-fill {{ fill: white; }}
+      .badge-bg {{ fill: white; stroke: black; stroke-width: 1; }}
+      .badge-text {{ font-family: monospace; font-size: 11px; fill: black; font-weight: bold; }}
+      .badge-value {{ font-family: monospace; font-size: 11px; fill: black; }}
+      .trend {{ fill: black; }}
+      .separator {{ stroke: #d0d7de; stroke-width: 1; }}
+      .sparkline {{ fill: none; stroke: black; stroke-width: 1.5; stroke-linecap: round; stroke-linejoin: round; }}
+      .sparkline-area {{ fill: black; fill-opacity: 0.15; }}
+      .sparkline-bar, .sparkline-dot {{ fill: black; }}{}
+    </style>
+  </defs>
+
+  <!-- White background -->
+  <rect x="0" y="0" width="{}" height="{}" class="badge-fill"/>
+
+  <!-- Background rounded rectangle with border -->
+  <rect x="0.5" y="0.5" width="{}" height="{}" rx="{}" ry="{}" class="badge-bg"/>
+
+  {}
+
+</svg>"#,
+        WIDTH, height,
+        extra_css,
+        WIDTH, height,
+        WIDTH - 1, height - 1, CORNER_RADIUS, CORNER_RADIUS,
+        content
+    )
+}
+
+/// System fonts, loaded once on first render; without them text is dropped.
+fn font_database() -> Arc<usvg::fontdb::Database> {
+    static FONTS: OnceLock<Arc<usvg::fontdb::Database>> = OnceLock::new();
+    FONTS
+        .get_or_init(|| {
+            let mut fonts = usvg::fontdb::Database::new();
+            fonts.load_system_fonts();
+
+            // The generic `monospace` family defaults to Courier New; point it
+            // at whichever monospaced face this host actually has.
+            let monospace = fonts
+                .faces()
+                .find(|face| face.monospaced)
+                .and_then(|face| face.families.first())
+                .map(|(family, _)| family.clone());
+            if let Some(family) = monospace {
+                fonts.set_monospace_family(family);
+            }
+            Arc::new(fonts)
+        })
+        .clone()
+}
+
+/// Rasterizes an SVG document to PNG with resvg.
+pub fn render_png(svg: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let _span = traces::span("resvg.render").entered();
+    let opt = usvg::Options {
+        fontdb: font_database(),
+        ..Default::default()
+    };
+    let tree = usvg::Tree::from_str(svg, &opt)?;
+
+    let pixmap_size = tree.size().to_int_size();
+    let mut pixmap = resvg::tiny_skia::Pixmap::new(pixmap_size.width(), pixmap_size.height())
+        .ok_or("Failed to create pixmap")?;
+
+    resvg::render(&tree, usvg::Transform::default(), &mut pixmap.as_mut());
+
+    // Convert to PNG
+    let png_data = pixmap.encode_png()?;
+    Ok(png_data)
+}
+
+pub fn escape_xml(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '&' => "&amp;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&#39;".to_string(),
+            _ => c.to_string(),
+        })
+        .collect()
+}
+
+/// Averages consecutive runs of points so at most `max` remain. The newest
+/// point is kept as is, since the badge shows it as the latest value.
+pub fn downsample(mut data: Vec<MetricPoint>, max: usize) -> Vec<MetricPoint> {
+    if data.len() <= max || max < 2 {
+        return data;
+    }
+    let latest = data.pop().unwrap();
+    let mut sampled: Vec<MetricPoint> = data
+        .chunks(data.len().div_ceil(max - 1))
+        .map(|chunk| MetricPoint {
+            timestamp: chunk.iter().map(|p| p.timestamp).sum::<i64>() / chunk.len() as i64,
+            value: chunk.iter().map(|p| p.value).sum::<f64>() / chunk.len() as f64,
+            sha: None,
+            branch: None,
+        })
+        .collect();
+    sampled.push(latest);
+    sampled
+}
+
+/// Loads the sparkline points in chronological order: everything since
+/// `since` when a window is set, otherwise the last 50 points.
+async fn load_badge_points(
+    store: &dyn MetricStore,
+    namespace: &str,
+    id: &str,
+    since: Option<i64>,
+) -> Result<Vec<MetricPoint>, StoreError> {
+    if let Some(since) = since {
+        let data = store.range(namespace, id, since, i64::MAX, i64::MAX).await?;
+        return Ok(downsample(data, MAX_SPARKLINE_POINTS));
+    }
+    store.recent(namespace, id, i64::MAX, 50).await
+}
+
+/// Min/avg/max since `since`, or `None` when no points are that recent.
+async fn load_badge_summary(
+    store: &dyn MetricStore,
+    namespace: &str,
+    id: &str,
+    since: i64,
+) -> Result<Option<Summary>, StoreError> {
+    let aggregate = store.aggregate(namespace, id, since, i64::MAX).await?;
+    Ok(aggregate.map(|aggregate| Summary {
+        min: aggregate.min,
+        avg: aggregate.mean,
+        max: aggregate.max,
+        count: aggregate.count,
+    }))
+}
+
+/// ETag component for one series, from its latest timestamp and data count,
+/// plus a hash of its unit and decimals so reformatting changes the tag.
+fn series_tag(data: &[MetricPoint], summary: Option<&Summary>, meta: &MetricMeta) -> String {
+    let Some(latest) = data.last() else {
+        return "empty".to_string();
+    };
+    let mut hasher = DefaultHasher::new();
+    (&meta.unit, meta.decimals).hash(&mut hasher);
+    let format = hasher.finish() as u32;
+    match summary {
+        Some(summary) => format!("{}:{}:s{}:{:x}", latest.timestamp, data.len(), summary.count, format),
+        None => format!("{}:{}:{:x}", latest.timestamp, data.len(), format),
+    }
+}
+
+/// Generate ETag from every row's series. Windowed and summary badges also
+/// change as old points age out, so `drift` (the window and current hour) is
+/// part of their tag.
+fn badge_etag(series: &[LoadedBadge], drift: Option<&str>) -> String {
+    let mut etag = series
+        .iter()
+        .map(|(data, summary, meta)| series_tag(data, summary.as_ref(), meta))
+        .collect::<Vec<_>>()
+        .join("|");
+    if let Some(drift) = drift {
+        etag.push_str(&format!(":{}", drift));
+    }
+    format!("\"{}\"", etag)
+}
+
+/// A series' points, optional summary and display formatting.
+type LoadedBadge = (Vec<MetricPoint>, Option<Summary>, MetricMeta);
+
+/// The sparkline points plus, for `?style=stats`, the summary to show instead,
+/// and the metric's formatting.
+async fn load_badge(
+    state: &AppState,
+    namespace: &str,
+    id: &str,
+    options: &BadgeOptions,
+    now: i64,
+) -> Result<LoadedBadge, StoreError> {
+    let store = state.store.as_ref();
+    let since = options.window.map(|window| now - window.seconds());
+    let data = load_badge_points(store, namespace, id, since).await?;
+    let summary = match options.style {
+        Style::Stats => {
+            let since = since.unwrap_or(now - SUMMARY_WINDOW.seconds());
+            load_badge_summary(store, namespace, id, since).await?
+        }
+        Style::Sparkline => None,
+    };
+    let meta = meta::load(&state.pool, namespace, id).await?;
+    Ok((data, summary, meta))
+}
+
+/// A rendered badge along with the ETag it was served under.
+#[derive(Clone)]
+pub struct RenderedBadge {
+    etag: Arc<str>,
+    body: Bytes,
+}
+
+impl Weighted for RenderedBadge {
+    fn weight(&self) -> usize {
+        self.etag.len() + self.body.len()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Format {
+    Png,
+    Svg,
+}
+
+impl Format {
+    fn content_type(self) -> &'static str {
+        match self {
+            Format::Png => "image/png",
+            Format::Svg => "image/svg+xml",
+        }
+    }
+}
+
+fn not_modified(etag: &str) -> Response {
+    Response::builder()
+        .status(StatusCode::NOT_MODIFIED)
+        .header("etag", etag)
+        .header("cache-control", "public, max-age=300")
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn badge_body(format: Format, badge: RenderedBadge) -> Response {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", format.content_type())
+        .header("etag", &*badge.etag)
+        .header("cache-control", "public, max-age=300")
+        .body(Body::from(badge.body))
+        .unwrap()
+}
+
+/// Cache slot for combined badges; any write in the namespace clears it.
+pub const COMBINED_BADGE_KEY: &str = "";
+
+/// Most series a combined badge will stack.
+const MAX_COMBINED: usize = 3;
+
+/// Serves a badge for `ids` from the render cache, rendering it on a miss,
+/// or answers 304 when the client already has the current version.
+async fn serve_badge(
+    state: &AppState,
+    headers: &HeaderMap,
+    namespace: &str,
+    ids: &[String],
+    cache_key: &str,
+    options: &BadgeOptions,
+    format: Format,
+) -> Result<Response, StatusCode> {
+    let if_none_match = headers.get("if-none-match").and_then(|v| v.to_str().ok());
+
+    // Windows and stats drift as points age out, so they only stay cached for an hour
+    let now = chrono::Utc::now().timestamp();
+    let drift = (options.window.is_some() || options.style == Style::Stats)
+        .then(|| format!("{}@{}", options.window.map_or("all", Window::label), now / 3600));
+    let variant = format!(
+        "{:?}:{:?}:{:?}:{:?}:{}:{}",
+        format,
+        options.theme,
+        options.style,
+        options.spark,
+        drift.as_deref().unwrap_or(""),
+        ids.join(",")
+    );
+    let generation = match state.badge_cache.get(namespace, cache_key, &variant) {
+        Ok(badge) if if_none_match == Some(&*badge.etag) => return Ok(not_modified(&badge.etag)),
+        Ok(badge) => return Ok(badge_body(format, badge)),
+        Err(generation) => generation,
+    };
+
+    let mut series = Vec::with_capacity(ids.len());
+    for id in ids {
+        series.push(
+            load_badge(state, namespace, id, options, now)
+                .await
+                .map_err(errors::internal)?,
+        );
+    }
+    let etag = badge_etag(&series, drift.as_deref());
+    if if_none_match == Some(etag.as_str()) {
+        return Ok(not_modified(&etag));
+    }
+
+    let rows: Vec<BadgeRow> = ids
+        .iter()
+        .zip(&series)
+        .map(|(id, (data, summary, meta))| BadgeRow {
+            name: id,
+            data,
+            summary: summary.as_ref(),
+            meta,
+        })
+        .collect();
+    let span = options.window.map(|window| (now - window.seconds(), now));
+    let started = Instant::now();
+    let trace = traces::span("badge.render").entered();
+    let body = match format {
+        Format::Png => render_png(&badge_svg(&rows, &theme_css(options.theme, false), span, options.spark))
+            .map_err(errors::internal)?,
+        Format::Svg => badge_svg(&rows, &theme_css(options.theme, true), span, options.spark).into_bytes(),
+    };
+    drop(trace);
+    state.metrics.badge_render.observe(started.elapsed());
+    let badge = RenderedBadge {
+        etag: etag.into(),
+        body: body.into(),
+    };
+    state
+        .badge_cache
+        .insert(namespace, cache_key, &variant, badge.clone(), generation);
+    Ok(badge_body(format, badge))
+}
+
+pub async fn get_badge_png(
+    SeriesPath(namespace, id): SeriesPath,
+    Query(query): Query<BadgeQuery>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    let options = query.options(&state.config().badge_defaults);
+    serve_badge(&state, &headers, &namespace, std::slice::from_ref(&id), &id, &options, Format::Png).await
+}
+
+pub async fn get_badge_svg(
+    SeriesPath(namespace, id): SeriesPath,
+    Query(query): Query<BadgeQuery>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    let options = query.options(&state.config().badge_defaults);
+    serve_badge(&state, &headers, &namespace, std::slice::from_ref(&id), &id, &options, Format::Svg).await
+}
+
+/// The `?ids=a,b` list for a combined badge, normalized and bounded, or
+/// `None` when the namespace summary was asked for instead.
+fn combined_ids(state: &AppState, query: &BadgeQuery) -> Result<Option<Vec<String>>, StatusCode> {
+    let Some(ids) = query.ids.as_deref() else {
+        return Ok(None);
+    };
+    let ids: Vec<String> = ids
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| state.config().id_policy.normalize(id))
+        .collect();
+    if ids.is_empty() || ids.len() > MAX_COMBINED {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(Some(ids))
+}
+
+/// Serves the namespace's metric count and latest update, sharing the
+/// combined badges' cache slot.
+async fn serve_namespace_summary(
+    state: &AppState,
+    headers: &HeaderMap,
+    namespace: &str,
+    theme: Theme,
+    format: Format,
+) -> Result<Response, StatusCode> {
+    let if_none_match = headers.get("if-none-match").and_then(|v| v.to_str().ok());
+    let variant = format!("summary:{:?}:{:?}", format, theme);
+    let generation = match state.badge_cache.get(namespace, COMBINED_BADGE_KEY, &variant) {
+        Ok(badge) if if_none_match == Some(&*badge.etag) => return Ok(not_modified(&badge.etag)),
+        Ok(badge) => return Ok(badge_body(format, badge)),
+        Err(generation) => generation,
+    };
+
+    let summary = state.store.namespace_summary(namespace).await.map_err(errors::internal)?;
+    let (count, latest) = (summary.metrics, summary.latest);
+    let etag = match &latest {
+        Some(latest) => format!("\"ns:{}:{}\"", count, latest.point_id),
+        None => "\"ns:empty\"".to_string(),
+    };
+    if if_none_match == Some(etag.as_str()) {
+        return Ok(not_modified(&etag));
+    }
+
+    let latest = latest.as_ref().map(|latest| (latest.id.as_str(), latest.timestamp));
+    let started = Instant::now();
+    let trace = traces::span("badge.render").entered();
+    let body = match format {
+        Format::Png => render_png(&namespace_summary_svg(namespace, count, latest, &theme_css(theme, false)))
+            .map_err(errors::internal)?,
+        Format::Svg => namespace_summary_svg(namespace, count, latest, &theme_css(theme, true)).into_bytes(),
+    };
+    drop(trace);
+    state.metrics.badge_render.observe(started.elapsed());
+    let badge = RenderedBadge {
+        etag: etag.into(),
+        body: body.into(),
+    };
+    state
+        .badge_cache
+        .insert(namespace, COMBINED_BADGE_KEY, &variant, badge.clone(), generation);
+    Ok(badge_body(format, badge))
+}
+
+async fn serve_namespace_badge(
+    state: &AppState,
+    headers: &HeaderMap,
+    namespace: &str,
+    query: &BadgeQuery,
+    format: Format,
+) -> Result<Response, StatusCode> {
+    let options = query.options(&state.config().badge_defaults);
+    match combined_ids(state, query)? {
+        Some(ids) => serve_badge(state, headers, namespace, &ids, COMBINED_BADGE_KEY, &options, format).await,
+        None => serve_namespace_summary(state, headers, namespace, options.theme, format).await,
+    }
+}
+
+/// With `?ids=a,b` up to three series stacked; otherwise a summary of the
+/// whole namespace.
+pub async fn get_namespace_badge_png(
+    NamespacePath(namespace): NamespacePath,
+    Query(query): Query<BadgeQuery>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    serve_namespace_badge(&state, &headers, &namespace, &query, Format::Png).await
+}
+
+pub async fn get_namespace_badge_svg(
+    NamespacePath(namespace): NamespacePath,
+    Query(query): Query<BadgeQuery>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    serve_namespace_badge(&state, &headers, &namespace, &query, Format::Svg).await
+}
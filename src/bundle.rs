@@ -0,0 +1,387 @@
+use std::fmt;
+use std::io::Read;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use futures_util::TryStreamExt;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dashboards::{self, NamespaceDashboard},
+    errors,
+    ids::{IdPolicy, NamespacePath},
+    meta::{self, MetricMeta},
+    rollup,
+    store::StoreError,
+    AppState, MetricPoint,
+};
+
+const BUNDLE_FORMAT: &str = "somnial-bundle";
+/// Version 2 added display defaults, markers, the README and dashboards;
+/// version 1 bundles still restore, with points alone.
+const BUNDLE_VERSION: u32 = 2;
+
+// Upper bound on the decompressed size of an uploaded bundle, so a small
+// gzip bomb can't exhaust memory.
+const MAX_BUNDLE_BYTES: u64 = 256 * 1024 * 1024;
+
+/// A self-contained archive of everything stored for one namespace: its
+/// points, each metric's display defaults, its markers and README, and the
+/// dashboards pinning its charts alone. Dashboards travel without their edit
+/// tokens; `somnial token create --dashboard` issues a restored one a new one.
+#[derive(Serialize, Deserialize)]
+pub struct Bundle {
+    format: String,
+    version: u32,
+    namespace: String,
+    exported_at: i64,
+    metrics: Vec<BundleMetric>,
+    #[serde(default)]
+    markers: Vec<BundleMarker>,
+    #[serde(default)]
+    readme: Option<BundleReadme>,
+    #[serde(default)]
+    dashboards: Vec<NamespaceDashboard>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BundleMetric {
+    id: String,
+    #[serde(default)]
+    meta: MetricMeta,
+    points: Vec<MetricPoint>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BundleMarker {
+    timestamp: i64,
+    label: String,
+    url: Option<String>,
+    source: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BundleReadme {
+    body: String,
+    updated_at: i64,
+}
+
+impl Bundle {
+    pub async fn export(state: &AppState, namespace: &str) -> Result<Self, StoreError> {
+        let pool = &state.pool;
+        let mut metas = meta::load_namespace(pool, namespace).await?;
+        let mut rows = state.store.scan(namespace, i64::MAX, i64::MAX);
+        let mut metrics: Vec<BundleMetric> = Vec::new();
+        while let Some((id, point)) = rows.try_next().await? {
+            match metrics.last_mut() {
+                Some(metric) if metric.id == id => metric.points.push(point),
+                _ => metrics.push(BundleMetric {
+                    meta: metas.remove(&id).unwrap_or_default(),
+                    id,
+                    points: vec![point],
+                }),
+            }
+        }
+        drop(rows);
+
+        let markers = sqlx::query_as!(
+            BundleMarker,
+            "SELECT timestamp, label, url, source FROM markers WHERE namespace = ? ORDER BY timestamp, marker_id",
+            namespace
+        )
+        .fetch_all(pool)
+        .await?;
+        let readme = sqlx::query_as!(
+            BundleReadme,
+            "SELECT body, updated_at FROM namespace_readmes WHERE namespace = ?",
+            namespace
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(Bundle {
+            format: BUNDLE_FORMAT.to_string(),
+            version: BUNDLE_VERSION,
+            namespace: namespace.to_string(),
+            exported_at: Utc::now().timestamp(),
+            metrics,
+            markers,
+            readme,
+            dashboards: dashboards::pinning_only(pool, namespace).await?,
+        })
+    }
+
+    /// How many series the bundle holds.
+    pub fn metric_count(&self) -> usize {
+        self.metrics.len()
+    }
+
+    /// Puts every metric id through `policy`, for a bundle from an instance
+    /// that may normalize ids differently.
+    pub fn normalize_ids(&mut self, policy: &IdPolicy) {
+        for metric in &mut self.metrics {
+            metric.id = policy.normalize(&metric.id);
+        }
+        for dashboard in &mut self.dashboards {
+            for id in &mut dashboard.ids {
+                *id = policy.normalize(id);
+            }
+        }
+    }
+
+    /// The first metric id, charted or pinned, that `policy` doesn't allow.
+    pub fn check_ids(&self, policy: &IdPolicy) -> Result<(), String> {
+        let charted = self.metrics.iter().map(|metric| &metric.id);
+        let pinned = self.dashboards.iter().flat_map(|dashboard| &dashboard.ids);
+        charted.chain(pinned).try_for_each(|id| policy.check(id))
+    }
+
+    /// Every point in the bundle as `(id, point)` pairs, ready to store.
+    pub fn points(&self) -> Vec<(&str, MetricPoint)> {
+        self.metrics
+            .iter()
+            .flat_map(|metric| {
+                metric.points.iter().map(|point| {
+                    (
+                        metric.id.as_str(),
+                        MetricPoint {
+                            timestamp: point.timestamp,
+                            value: point.value,
+                            sha: point.sha.clone(),
+                            branch: point.branch.clone(),
+                        },
+                    )
+                })
+            })
+            .collect()
+    }
+
+    pub fn to_gzip(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        serde_json::to_writer(&mut encoder, self)?;
+        Ok(encoder.finish()?)
+    }
+
+    pub fn from_gzip(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut json = Vec::new();
+        GzDecoder::new(data)
+            .take(MAX_BUNDLE_BYTES + 1)
+            .read_to_end(&mut json)?;
+        if json.len() as u64 > MAX_BUNDLE_BYTES {
+            return Err("bundle exceeds maximum decompressed size".into());
+        }
+
+        let bundle: Bundle = serde_json::from_slice(&json)?;
+        if bundle.format != BUNDLE_FORMAT {
+            return Err("not a somnial bundle".into());
+        }
+        if bundle.version > BUNDLE_VERSION {
+            return Err(format!("unsupported bundle version {}", bundle.version).into());
+        }
+        Ok(bundle)
+    }
+}
+
+pub async fn export_bundle(
+    NamespacePath(namespace): NamespacePath,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let bundle = Bundle::export(&state, &namespace)
+        .await
+        .map_err(errors::internal)?;
+
+    if bundle.metrics.is_empty() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let data = bundle
+        .to_gzip()
+        .map_err(errors::internal)?;
+
+    // Keep the filename header-safe regardless of what the namespace contains
+    let filename: String = namespace
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/gzip")
+        .header(
+            "content-disposition",
+            format!("attachment; filename=\"{}.somnial.json.gz\"", filename),
+        )
+        .body(Body::from(data))
+        .unwrap())
+}
+
+/// Why a bundle wasn't restored.
+#[derive(Debug)]
+pub enum RestoreError {
+    /// A metric id the configured policy doesn't allow
+    InvalidId(String),
+    /// The namespace already has metrics of its own
+    NamespaceInUse,
+    Database(StoreError),
+}
+
+impl From<StoreError> for RestoreError {
+    fn from(err: StoreError) -> Self {
+        RestoreError::Database(err)
+    }
+}
+
+impl fmt::Display for RestoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RestoreError::InvalidId(message) => f.write_str(message),
+            RestoreError::NamespaceInUse => f.write_str("the namespace already has metrics; bundles only restore into empty ones"),
+            RestoreError::Database(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for RestoreError {}
+
+/// Writes `bundle` into `namespace`, returning the number of points
+/// inserted.
+pub async fn restore(state: &AppState, namespace: &str, bundle: &Bundle) -> Result<u64, RestoreError> {
+    bundle.check_ids(&state.config().id_policy).map_err(RestoreError::InvalidId)?;
+
+    // Importing is for restoring into a fresh namespace, never for merging
+    // into one that is already collecting data.
+    if state.store.namespace_summary(namespace).await?.points > 0 {
+        return Err(RestoreError::NamespaceInUse);
+    }
+
+    // One transaction, so a failed import leaves nothing behind
+    let points = bundle.points();
+    let inserted = state
+        .write_store(|| state.store.insert(namespace, &points))
+        .await?;
+    restore_settings(state, namespace, bundle).await.map_err(StoreError::from)?;
+    state.invalidate_namespace(namespace);
+    // Bundles carry old points, which the rollup job has already gone past
+    rollup::rebuild_namespace(state, namespace).await?;
+    Ok(inserted)
+}
+
+/// Writes what `bundle` carries besides points into `namespace`, in one
+/// transaction after the points. Whatever the namespace already has stays:
+/// its own display defaults and README win, markers it already shows aren't
+/// added twice, and dashboards whose slugs are taken are left out.
+pub async fn restore_settings(state: &AppState, namespace: &str, bundle: &Bundle) -> Result<(), sqlx::Error> {
+    let now = Utc::now().timestamp();
+    let pool = &state.pool;
+    state
+        .write(|| async move {
+            let mut tx = pool.begin().await?;
+            for metric in &bundle.metrics {
+                // Display defaults the API wouldn't take stay behind
+                if metric.meta == MetricMeta::default() || metric.meta.validate().is_err() {
+                    continue;
+                }
+                let scale = metric.meta.scale.map(meta::Scale::as_str);
+                let chart_type = metric.meta.chart_type.map(meta::ChartType::as_str);
+                sqlx::query!(
+                    "INSERT OR IGNORE INTO metric_meta (namespace, id, scale, chart_type, unit, description, decimals)
+                     VALUES (?, ?, ?, ?, ?, ?, ?)",
+                    namespace,
+                    metric.id,
+                    scale,
+                    chart_type,
+                    metric.meta.unit,
+                    metric.meta.description,
+                    metric.meta.decimals
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            for marker in &bundle.markers {
+                sqlx::query!(
+                    "INSERT INTO markers (namespace, timestamp, label, url, source)
+                     SELECT ?, ?, ?, ?, ? WHERE NOT EXISTS (
+                         SELECT 1 FROM markers WHERE namespace = ? AND timestamp = ? AND label = ?
+                     )",
+                    namespace,
+                    marker.timestamp,
+                    marker.label,
+                    marker.url,
+                    marker.source,
+                    namespace,
+                    marker.timestamp,
+                    marker.label
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            if let Some(readme) = &bundle.readme {
+                sqlx::query!(
+                    "INSERT OR IGNORE INTO namespace_readmes (namespace, body, updated_at) VALUES (?, ?, ?)",
+                    namespace,
+                    readme.body,
+                    readme.updated_at
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            for dashboard in &bundle.dashboards {
+                dashboards::restore(&mut tx, namespace, dashboard, now).await?;
+            }
+            tx.commit().await
+        })
+        .await
+}
+
+pub async fn import_bundle(
+    NamespacePath(namespace): NamespacePath,
+    State(state): State<AppState>,
+    body: Bytes,
+) -> Result<Response, StatusCode> {
+    let bundle = Bundle::from_gzip(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let inserted = match restore(&state, &namespace, &bundle).await {
+        Ok(inserted) => inserted,
+        Err(RestoreError::InvalidId(message)) => return Ok((StatusCode::UNPROCESSABLE_ENTITY, message).into_response()),
+        Err(RestoreError::NamespaceInUse) => return Err(StatusCode::CONFLICT),
+        Err(RestoreError::Database(err)) => return Err(errors::internal(err)),
+    };
+
+    Ok(axum::Json(serde_json::json!({
+        "namespace": namespace,
+        "metrics": bundle.metrics.len(),
+        "points": inserted,
+    }))
+    .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn gzip(json: &str) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(json.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn version_one_bundles_hold_points_alone() {
+        let json = r#"{"format": "somnial-bundle", "version": 1, "namespace": "ci", "exported_at": 0,
+            "metrics": [{"id": "build_time", "points": [{"timestamp": 1, "value": 2.0}]}]}"#;
+        let bundle = Bundle::from_gzip(&gzip(json)).unwrap();
+        assert_eq!(bundle.points().len(), 1);
+        assert_eq!(bundle.metrics[0].meta, MetricMeta::default());
+        assert!(bundle.markers.is_empty() && bundle.readme.is_none() && bundle.dashboards.is_empty());
+
+        let newer = json.replace(r#""version": 1"#, r#""version": 3"#);
+        assert!(Bundle::from_gzip(&gzip(&newer)).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+type SeriesKey = (String, String);
+
+/// Something the cache can hold; `weight` counts against its byte budget.
+pub trait Weighted: Clone {
+    fn weight(&self) -> usize;
+}
+
+impl Weighted for Arc<str> {
+    fn weight(&self) -> usize {
+        self.len()
+    }
+}
+
+/// In-memory cache of data derived from a series, keyed by series plus a
+/// variant string describing the query (range, downsampling, ...).
+///
+/// Writes to a series drop all of its entries. Entries filled by a read that
+/// raced with a write are discarded, using per-series generation counters.
+/// When the total size exceeds `max_bytes` the least recently used entries
+/// are evicted.
+pub struct SeriesCache<V = Arc<str>> {
+    max_bytes: usize,
+    inner: Mutex<Inner<V>>,
+    pub hits: AtomicU64,
+    pub misses: AtomicU64,
+    pub evictions: AtomicU64,
+}
+
+struct Inner<V> {
+    entries: HashMap<SeriesKey, HashMap<String, Entry<V>>>,
+    series_generations: HashMap<SeriesKey, u64>,
+    namespace_generations: HashMap<String, u64>,
+    /// Bumped by [`SeriesCache::clear`], which touches every series at once
+    generation: u64,
+    bytes: usize,
+    tick: u64,
+}
+
+impl<V> Default for Inner<V> {
+    fn default() -> Self {
+        Inner {
+            entries: HashMap::new(),
+            series_generations: HashMap::new(),
+            namespace_generations: HashMap::new(),
+            generation: 0,
+            bytes: 0,
+            tick: 0,
+        }
+    }
+}
+
+struct Entry<V> {
+    value: V,
+    last_used: u64,
+}
+
+/// Snapshot of a series' generation taken before reading it from the database.
+pub struct Generation(u64);
+
+impl<V: Weighted> Inner<V> {
+    fn generation(&self, key: &SeriesKey) -> u64 {
+        self.series_generations.get(key).copied().unwrap_or(0)
+            + self.namespace_generations.get(&key.0).copied().unwrap_or(0)
+            + self.generation
+    }
+
+    fn evict_one(&mut self) -> bool {
+        let oldest = self
+            .entries
+            .iter()
+            .flat_map(|(key, variants)| {
+                variants
+                    .iter()
+                    .map(move |(variant, entry)| (entry.last_used, key, variant))
+            })
+            .min_by_key(|(last_used, _, _)| *last_used)
+            .map(|(_, key, variant)| (key.clone(), variant.clone()));
+
+        let Some((key, variant)) = oldest else {
+            return false;
+        };
+        if let Some(variants) = self.entries.get_mut(&key) {
+            if let Some(entry) = variants.remove(&variant) {
+                self.bytes -= entry.value.weight();
+            }
+            if variants.is_empty() {
+                self.entries.remove(&key);
+            }
+        }
+        true
+    }
+}
+
+impl<V: Weighted> SeriesCache<V> {
+    pub fn new(max_bytes: usize) -> Self {
+        SeriesCache {
+            max_bytes,
+            inner: Mutex::new(Inner::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.inner.lock().unwrap().bytes
+    }
+
+    /// Returns the cached value, or the generation to pass to [`insert`](Self::insert)
+    /// once the caller has computed it.
+    pub fn get(&self, namespace: &str, id: &str, variant: &str) -> Result<V, Generation> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.tick += 1;
+        let tick = inner.tick;
+        let key = (namespace.to_string(), id.to_string());
+
+        if let Some(entry) = inner
+            .entries
+            .get_mut(&key)
+            .and_then(|variants| variants.get_mut(variant))
+        {
+            entry.last_used = tick;
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(entry.value.clone());
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        Err(Generation(inner.generation(&key)))
+    }
+
+    pub fn insert(&self, namespace: &str, id: &str, variant: &str, value: V, seen: Generation) {
+        if self.max_bytes == 0 || value.weight() > self.max_bytes {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        let key = (namespace.to_string(), id.to_string());
+        if inner.generation(&key) != seen.0 {
+            // A write landed while the value was being computed
+            return;
+        }
+
+        inner.tick += 1;
+        let entry = Entry {
+            last_used: inner.tick,
+            value,
+        };
+        inner.bytes += entry.value.weight();
+        let replaced = inner
+            .entries
+            .entry(key)
+            .or_default()
+            .insert(variant.to_string(), entry);
+        if let Some(old) = replaced {
+            inner.bytes -= old.value.weight();
+        }
+
+        while inner.bytes > self.max_bytes && inner.evict_one() {
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn invalidate(&self, namespace: &str, id: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        let key = (namespace.to_string(), id.to_string());
+        if let Some(variants) = inner.entries.remove(&key) {
+            inner.bytes -= variants.values().map(|e| e.value.weight()).sum::<usize>();
+        }
+        *inner.series_generations.entry(key).or_default() += 1;
+    }
+
+    pub fn invalidate_namespace(&self, namespace: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        let keys: Vec<SeriesKey> = inner
+            .entries
+            .keys()
+            .filter(|(ns, _)| ns == namespace)
+            .cloned()
+            .collect();
+        for key in keys {
+            if let Some(variants) = inner.entries.remove(&key) {
+                inner.bytes -= variants.values().map(|e| e.value.weight()).sum::<usize>();
+            }
+        }
+        *inner
+            .namespace_generations
+            .entry(namespace.to_string())
+            .or_default() += 1;
+    }
+
+    /// Drops every entry, for when the whole database has changed underneath.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.entries.clear();
+        inner.bytes = 0;
+        inner.generation += 1;
+    }
+}
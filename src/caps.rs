@@ -0,0 +1,259 @@
+//! Ring-buffer style caps on how many points a series keeps, for "last 1000
+//! samples" metrics that should never grow without bound. A cap can be set
+//! for a whole namespace or for one metric, which takes precedence. Each
+//! write to a capped series trims its oldest points once it is over; setting
+//! a cap doesn't trim anything until the next write.
+
+use std::collections::HashMap;
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::sqlite::SqlitePool;
+
+use crate::{
+    auth, errors,
+    ids::{NamespacePath, SeriesPath},
+    rollup, AppState,
+};
+
+const MAX_CAP: i64 = 10_000_000;
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(json!({ "error": message })))
+}
+
+fn database_error<E: std::fmt::Display>(err: E) -> ApiError {
+    crate::errors::report(&err);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+/// `{"max_points": 1000}`
+#[derive(Deserialize)]
+pub struct CapRequest {
+    max_points: i64,
+}
+
+#[derive(Serialize)]
+struct NamespaceCap {
+    /// Most points each series keeps, or `null` for no cap
+    max_points: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct MetricCap {
+    /// Most points the series keeps, or `null` for no cap
+    max_points: Option<i64>,
+    /// Whether the cap comes from the namespace rather than the metric
+    inherited: bool,
+}
+
+/// The caps in force across one namespace.
+#[derive(Debug, Default)]
+pub struct Caps {
+    namespace: Option<i64>,
+    metrics: HashMap<String, i64>,
+}
+
+impl Caps {
+    pub fn get(&self, id: &str) -> Option<i64> {
+        self.metrics.get(id).copied().or(self.namespace)
+    }
+}
+
+pub async fn load_namespace(pool: &SqlitePool, namespace: &str) -> Result<Caps, sqlx::Error> {
+    let namespace_cap = sqlx::query_scalar!(
+        "SELECT max_points FROM namespace_point_caps WHERE namespace = ?",
+        namespace
+    )
+    .fetch_optional(pool)
+    .await?;
+    let metrics = sqlx::query!(
+        "SELECT id, max_points FROM metric_point_caps WHERE namespace = ?",
+        namespace
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(Caps {
+        namespace: namespace_cap,
+        metrics: metrics.into_iter().map(|row| (row.id, row.max_points)).collect(),
+    })
+}
+
+/// The metric's own cap, if it has one.
+async fn load_metric(pool: &SqlitePool, namespace: &str, id: &str) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT max_points FROM metric_point_caps WHERE namespace = ? AND id = ?",
+        namespace,
+        id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Trims a series down to `cap` points after a write. The write has already
+/// succeeded by now, so a failure here is only logged; the next write trims
+/// again.
+pub async fn enforce(state: &AppState, namespace: &str, id: &str, cap: i64) {
+    let trimmed = match state.write_store(|| state.store.trim(namespace, id, cap)).await {
+        Ok(trimmed) => trimmed,
+        Err(err) => {
+            tracing::warn!("Trimming {}/{} to {} points failed: {}", namespace, id, cap, err);
+            return;
+        }
+    };
+    if trimmed.is_empty() {
+        return;
+    }
+    state.invalidate_series(namespace, id);
+    if let Err(err) = rollup::rebuild_buckets(state, namespace, id, &trimmed).await {
+        tracing::warn!("Rebuilding rollups for {}/{} after trimming failed: {}", namespace, id, err);
+    }
+}
+
+fn validate(request: &CapRequest) -> Result<(), ApiError> {
+    if !(1..=MAX_CAP).contains(&request.max_points) {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "max_points must be between 1 and 10000000"));
+    }
+    Ok(())
+}
+
+pub async fn get_namespace_cap(
+    NamespacePath(namespace): NamespacePath,
+    State(pool): State<SqlitePool>,
+) -> Result<impl IntoResponse, ApiError> {
+    let caps = load_namespace(&pool, &namespace).await.map_err(database_error)?;
+    Ok(Json(NamespaceCap {
+        max_points: caps.namespace,
+    }))
+}
+
+pub async fn put_namespace_cap(
+    NamespacePath(namespace): NamespacePath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<CapRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+    validate(&request)?;
+
+    let (pool, namespace, max_points) = (&state.pool, &namespace, request.max_points);
+    state
+        .write(|| async move {
+            sqlx::query!(
+                "INSERT INTO namespace_point_caps (namespace, max_points) VALUES (?, ?)
+                 ON CONFLICT (namespace) DO UPDATE SET max_points = excluded.max_points",
+                namespace,
+                max_points
+            )
+            .execute(pool)
+            .await
+        })
+        .await
+        .map_err(database_error)?;
+
+    Ok(Json(NamespaceCap {
+        max_points: Some(max_points),
+    }))
+}
+
+pub async fn delete_namespace_cap(
+    NamespacePath(namespace): NamespacePath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&state.config(), &headers)?;
+
+    let (pool, namespace) = (&state.pool, &namespace);
+    state
+        .write(|| async move {
+            sqlx::query!("DELETE FROM namespace_point_caps WHERE namespace = ?", namespace)
+                .execute(pool)
+                .await
+        })
+        .await
+        .map_err(errors::internal)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn get_metric_cap(
+    SeriesPath(namespace, id): SeriesPath,
+    State(pool): State<SqlitePool>,
+) -> Result<impl IntoResponse, ApiError> {
+    let cap = match load_metric(&pool, &namespace, &id).await.map_err(database_error)? {
+        Some(max_points) => MetricCap {
+            max_points: Some(max_points),
+            inherited: false,
+        },
+        None => MetricCap {
+            max_points: load_namespace(&pool, &namespace).await.map_err(database_error)?.namespace,
+            inherited: true,
+        },
+    };
+    Ok(Json(cap))
+}
+
+pub async fn put_metric_cap(
+    SeriesPath(namespace, id): SeriesPath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<CapRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+    validate(&request)?;
+
+    let (pool, namespace, id, max_points) = (&state.pool, &namespace, &id, request.max_points);
+    state
+        .write(|| async move {
+            sqlx::query!(
+                "INSERT INTO metric_point_caps (namespace, id, max_points) VALUES (?, ?, ?)
+                 ON CONFLICT (namespace, id) DO UPDATE SET max_points = excluded.max_points",
+                namespace,
+                id,
+                max_points
+            )
+            .execute(pool)
+            .await
+        })
+        .await
+        .map_err(database_error)?;
+
+    Ok(Json(MetricCap {
+        max_points: Some(max_points),
+        inherited: false,
+    }))
+}
+
+/// Drops the metric's own cap, leaving it under the namespace's, if any.
+pub async fn delete_metric_cap(
+    SeriesPath(namespace, id): SeriesPath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&state.config(), &headers)?;
+
+    let (pool, namespace, id) = (&state.pool, &namespace, &id);
+    state
+        .write(|| async move {
+            sqlx::query!(
+                "DELETE FROM metric_point_caps WHERE namespace = ? AND id = ?",
+                namespace,
+                id
+            )
+            .execute(pool)
+            .await
+        })
+        .await
+        .map_err(errors::internal)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,426 @@
+//! Server-side rendering of full-size charts, for places that can't run the
+//! interactive page's JavaScript.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::{
+    badge::{downsample, escape_xml, format_value, render_png},
+    config::Config,
+    errors,
+    ids::SeriesPath,
+    limits::{self, ReadError},
+    store::MetricStore,
+    MetricPoint,
+};
+
+const DEFAULT_WIDTH: f64 = 800.0;
+const DEFAULT_HEIGHT: f64 = 400.0;
+/// `?width=` and `?height=` are clamped to these bounds.
+const MIN_WIDTH: f64 = 240.0;
+const MAX_WIDTH: f64 = 2400.0;
+const MIN_HEIGHT: f64 = 160.0;
+const MAX_HEIGHT: f64 = 1600.0;
+const MARGIN_LEFT: f64 = 64.0;
+const MARGIN_RIGHT: f64 = 24.0;
+const MARGIN_TOP: f64 = 40.0;
+const MARGIN_BOTTOM: f64 = 36.0;
+/// Link preview cards use the size Open Graph consumers crop to.
+const CARD_WIDTH: f64 = 1200.0;
+const CARD_HEIGHT: f64 = 630.0;
+/// Most points drawn in a card's chart
+const CARD_POINTS: usize = 300;
+
+/// Extra room under the time axis for a caption.
+const CAPTION_HEIGHT: f64 = 22.0;
+
+/// Candidate spacings for time-axis ticks, in seconds.
+const TIME_STEPS: &[i64] = &[
+    1, 5, 15, 30, 60, 300, 900, 1800, 3600, 3 * 3600, 6 * 3600, 12 * 3600,
+    86400, 2 * 86400, 7 * 86400, 30 * 86400, 91 * 86400, 365 * 86400,
+];
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ChartQuery {
+    /// Start of the plotted window, as a Unix timestamp
+    pub from: Option<i64>,
+    /// End of the plotted window, as a Unix timestamp
+    pub to: Option<i64>,
+    /// Image width in pixels
+    pub width: Option<f64>,
+    /// Image height in pixels
+    pub height: Option<f64>,
+}
+
+impl ChartQuery {
+    /// Requested image size, defaulted and clamped to what renders sensibly.
+    pub fn size(&self) -> (f64, f64) {
+        let clamp = |value: Option<f64>, default: f64, min: f64, max: f64| {
+            value.filter(|v| v.is_finite()).map_or(default, |v| v.round().clamp(min, max))
+        };
+        (
+            clamp(self.width, DEFAULT_WIDTH, MIN_WIDTH, MAX_WIDTH),
+            clamp(self.height, DEFAULT_HEIGHT, MIN_HEIGHT, MAX_HEIGHT),
+        )
+    }
+}
+
+/// A 1, 2 or 5 times power-of-ten step giving roughly `target` ticks.
+fn nice_step(range: f64, target: f64) -> f64 {
+    let raw = range / target;
+    let magnitude = 10f64.powf(raw.log10().floor());
+    let nice = match raw / magnitude {
+        r if r <= 1.0 => 1.0,
+        r if r <= 2.0 => 2.0,
+        r if r <= 5.0 => 5.0,
+        _ => 10.0,
+    };
+    nice * magnitude
+}
+
+fn time_label(timestamp: i64, step: i64, span: i64) -> String {
+    let Some(time) = DateTime::<Utc>::from_timestamp(timestamp, 0) else {
+        return String::new();
+    };
+    let format = match step {
+        s if s >= 30 * 86400 => "%b %Y",
+        s if s >= 86400 => "%b %d",
+        _ if span > 86400 => "%b %d %H:%M",
+        s if s < 60 => "%H:%M:%S",
+        _ => "%H:%M",
+    };
+    time.format(format).to_string()
+}
+
+/// Renders `data` as a `size` pixel line chart with value and time axes,
+/// and `caption` underneath if given. `range` fixes the time window;
+/// otherwise it spans the data.
+pub fn chart_svg(
+    title: &str,
+    caption: Option<&str>,
+    data: &[MetricPoint],
+    range: (Option<i64>, Option<i64>),
+    (width, height): (f64, f64),
+) -> String {
+    let plot_left = MARGIN_LEFT;
+    let plot_right = width - MARGIN_RIGHT;
+    let plot_top = MARGIN_TOP;
+    let plot_bottom = height - MARGIN_BOTTOM - if caption.is_some() { CAPTION_HEIGHT } else { 0.0 };
+
+    let mut body = String::new();
+
+    if data.is_empty() {
+        body.push_str(&format!(
+            r#"<text x="{}" y="{}" text-anchor="middle" class="label">No data</text>"#,
+            (plot_left + plot_right) / 2.0,
+            (plot_top + plot_bottom) / 2.0
+        ));
+    } else {
+        // Time domain, padded so a single instant still has some width
+        let mut t_min = range.0.unwrap_or_else(|| data.iter().map(|p| p.timestamp).min().unwrap());
+        let mut t_max = range.1.unwrap_or_else(|| data.iter().map(|p| p.timestamp).max().unwrap());
+        if t_max <= t_min {
+            t_min -= 30;
+            t_max = t_min + 60;
+        }
+        let span = t_max - t_min;
+
+        // Value domain, widened to whole tick steps
+        let v_min = data.iter().map(|p| p.value).fold(f64::INFINITY, f64::min);
+        let v_max = data.iter().map(|p| p.value).fold(f64::NEG_INFINITY, f64::max);
+        let (v_min, v_max) = if v_max > v_min {
+            (v_min, v_max)
+        } else {
+            let pad = (v_min.abs() * 0.1).max(1.0);
+            (v_min - pad, v_max + pad)
+        };
+        // Roughly one value label per 65px and one time label per 130px
+        let v_step = nice_step(v_max - v_min, ((plot_bottom - plot_top) / 65.0).max(2.0));
+        let v_low = (v_min / v_step).floor() * v_step;
+        let v_high = (v_max / v_step).ceil() * v_step;
+
+        let x = |t: i64| plot_left + (t - t_min) as f64 / span as f64 * (plot_right - plot_left);
+        let y = |v: f64| plot_bottom - (v - v_low) / (v_high - v_low) * (plot_bottom - plot_top);
+
+        // Horizontal grid lines with value labels
+        let mut v = v_low;
+        while v <= v_high + v_step / 2.0 {
+            let py = y(v);
+            body.push_str(&format!(
+                r#"<line x1="{:.1}" y1="{:.1}" x2="{:.1}" y2="{:.1}" class="grid"/><text x="{:.1}" y="{:.1}" text-anchor="end" class="label">{}</text>"#,
+                plot_left, py, plot_right, py,
+                plot_left - 8.0, py + 4.0,
+                escape_xml(&format_value(v))
+            ));
+            v += v_step;
+        }
+
+        // Time ticks at round multiples of the chosen step
+        let max_ticks = (((plot_right - plot_left) / 130.0) as i64).max(2);
+        let t_step = TIME_STEPS
+            .iter()
+            .copied()
+            .find(|step| span / step <= max_ticks)
+            .unwrap_or(365 * 86400 * ((span / (365 * 86400 * max_ticks)) + 1));
+        let mut t = (t_min + t_step - 1).div_euclid(t_step) * t_step;
+        while t <= t_max {
+            let px = x(t);
+            body.push_str(&format!(
+                r#"<line x1="{:.1}" y1="{:.1}" x2="{:.1}" y2="{:.1}" class="tick"/><text x="{:.1}" y="{:.1}" text-anchor="middle" class="label">{}</text>"#,
+                px, plot_bottom, px, plot_bottom + 4.0,
+                px, plot_bottom + 18.0,
+                escape_xml(&time_label(t, t_step, span))
+            ));
+            t += t_step;
+        }
+
+        let visible: Vec<&MetricPoint> = data
+            .iter()
+            .filter(|p| p.timestamp >= t_min && p.timestamp <= t_max)
+            .collect();
+        if visible.len() == 1 {
+            body.push_str(&format!(
+                r#"<circle cx="{:.1}" cy="{:.1}" r="3" class="point"/>"#,
+                x(visible[0].timestamp),
+                y(visible[0].value)
+            ));
+        } else if !visible.is_empty() {
+            let path: Vec<String> = visible
+                .iter()
+                .enumerate()
+                .map(|(i, p)| format!("{}{:.1} {:.1}", if i == 0 { "M" } else { "L" }, x(p.timestamp), y(p.value)))
+                .collect();
+            body.push_str(&format!(r#"<path d="{}" class="line"/>"#, path.join(" ")));
+        }
+    }
+
+    if let Some(caption) = caption {
+        body.push_str(&format!(
+            r#"<text x="{:.1}" y="{:.1}" class="caption">{}</text>"#,
+            plot_left,
+            height - 10.0,
+            escape_xml(caption)
+        ));
+    }
+
+    format!(
+        r#"<svg width="{w}" height="{h}" viewBox="0 0 {w} {h}" xmlns="http://www.w3.org/2000/svg">
+  <defs>
+    <style>
+      .background {{ fill: white; }}
+      .title {{ font-family: monospace; font-size: 14px; font-weight: bold; fill: hsl(220, 9%, 18%); }}
+      .label {{ font-family: monospace; font-size: 11px; fill: hsl(220, 9%, 46%); }}
+      .caption {{ font-family: monospace; font-size: 11px; fill: hsl(220, 9%, 46%); font-style: italic; }}
+      .grid {{ stroke: hsl(220, 13%, 91%); stroke-width: 1; }}
+      .axis, .tick {{ stroke: hsl(220, 9%, 46%); stroke-width: 1; }}
+      .line {{ fill: none; stroke: hsl(220, 9%, 18%); stroke-width: 2; stroke-linecap: round; stroke-linejoin: round; }}
+      .point {{ fill: hsl(220, 9%, 18%); }}
+    </style>
+  </defs>
+  <rect x="0" y="0" width="{w}" height="{h}" class="background"/>
+  <text x="{left}" y="24" class="title">{title}</text>
+  {body}
+  <line x1="{left}" y1="{bottom}" x2="{right}" y2="{bottom}" class="axis"/>
+</svg>"#,
+        w = width,
+        h = height,
+        left = plot_left,
+        right = plot_right,
+        bottom = plot_bottom,
+        title = escape_xml(title),
+        body = body,
+    )
+}
+
+async fn load_chart_points(
+    store: &dyn MetricStore,
+    namespace: &str,
+    id: &str,
+    query: &ChartQuery,
+    max_points: usize,
+) -> Result<Vec<MetricPoint>, ReadError> {
+    let from = query.from.unwrap_or(i64::MIN);
+    let to = query.to.unwrap_or(i64::MAX);
+    let fetch_limit = limits::fetch_limit(max_points);
+    let points = store.range(namespace, id, from, to, fetch_limit).await?;
+    limits::within(points, max_points)
+}
+
+/// Source and freshness line under an embedded chart, e.g.
+/// `ci/build-time · 42 points · latest 3.1 on Oct 14 2026`.
+fn caption(namespace: &str, id: &str, data: &[MetricPoint]) -> String {
+    let mut caption = format!("{}/{} · {} point{}", namespace, id, data.len(), if data.len() == 1 { "" } else { "s" });
+    if let Some(latest) = data.last()
+        && let Some(time) = DateTime::<Utc>::from_timestamp(latest.timestamp, 0)
+    {
+        caption.push_str(&format!(" · latest {} on {}", format_value(latest.value), time.format("%b %d %Y")));
+    }
+    caption
+}
+
+pub async fn get_chart_png(
+    SeriesPath(namespace, id): SeriesPath,
+    Query(query): Query<ChartQuery>,
+    State(store): State<Arc<dyn MetricStore>>,
+    State(config): State<Arc<Config>>,
+) -> Result<Response, StatusCode> {
+    let data = match load_chart_points(store.as_ref(), &namespace, &id, &query, config.limits.max_query_points).await {
+        Ok(data) => data,
+        Err(err) => return err.respond(),
+    };
+
+    let png = render_png(&chart_svg(&id, None, &data, (query.from, query.to), query.size()))
+        .map_err(errors::internal)?;
+
+    Ok((
+        StatusCode::OK,
+        [("content-type", "image/png"), ("cache-control", "public, max-age=300")],
+        png,
+    )
+        .into_response())
+}
+
+/// The same chart as [`get_chart_png`] as a standalone SVG, captioned with
+/// where it came from, for README embedding.
+pub async fn get_chart_svg(
+    SeriesPath(namespace, id): SeriesPath,
+    Query(query): Query<ChartQuery>,
+    State(store): State<Arc<dyn MetricStore>>,
+    State(config): State<Arc<Config>>,
+) -> Result<Response, StatusCode> {
+    let data = match load_chart_points(store.as_ref(), &namespace, &id, &query, config.limits.max_query_points).await {
+        Ok(data) => data,
+        Err(err) => return err.respond(),
+    };
+
+    let caption = caption(&namespace, &id, &data);
+    let svg = chart_svg(&id, Some(&caption), &data, (query.from, query.to), query.size());
+
+    Ok((
+        StatusCode::OK,
+        [("content-type", "image/svg+xml"), ("cache-control", "public, max-age=300")],
+        svg,
+    )
+        .into_response())
+}
+
+/// Cuts `text` to `max` characters, marking the cut with an ellipsis.
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let mut short: String = text.chars().take(max - 1).collect();
+    short.push('…');
+    short
+}
+
+/// A link preview card: the metric's name, its latest value and an area
+/// chart of its history.
+fn og_card_svg(namespace: &str, id: &str, data: &[MetricPoint]) -> String {
+    let (left, right) = (72.0, CARD_WIDTH - 72.0);
+    let (chart_top, chart_bottom) = (384.0, CARD_HEIGHT - 56.0);
+
+    let (value, updated) = match data.last() {
+        Some(latest) => (
+            format_value(latest.value),
+            DateTime::<Utc>::from_timestamp(latest.timestamp, 0)
+                .map(|time| format!("latest · {}", time.format("%b %d %Y %H:%M UTC")))
+                .unwrap_or_default(),
+        ),
+        None => ("–".to_string(), "no data yet".to_string()),
+    };
+
+    let mut chart = String::new();
+    if data.len() > 1 {
+        let t_min = data[0].timestamp;
+        let t_span = (data[data.len() - 1].timestamp - t_min).max(1) as f64;
+        let v_min = data.iter().map(|p| p.value).fold(f64::INFINITY, f64::min);
+        let v_max = data.iter().map(|p| p.value).fold(f64::NEG_INFINITY, f64::max);
+        let v_span = v_max - v_min;
+        let points: Vec<(f64, f64)> = data
+            .iter()
+            .map(|p| {
+                let x = left + (p.timestamp - t_min) as f64 / t_span * (right - left);
+                let y = if v_span > 0.0 {
+                    chart_bottom - (p.value - v_min) / v_span * (chart_bottom - chart_top)
+                } else {
+                    (chart_top + chart_bottom) / 2.0
+                };
+                (x, y)
+            })
+            .collect();
+        let line = points
+            .iter()
+            .enumerate()
+            .map(|(i, (x, y))| format!("{}{:.1} {:.1}", if i == 0 { "M" } else { "L" }, x, y))
+            .collect::<Vec<_>>()
+            .join(" ");
+        chart = format!(
+            r#"<path d="{line} L{:.1} {bottom:.1} L{:.1} {bottom:.1} Z" class="area"/><path d="{line}" class="line"/>"#,
+            points[points.len() - 1].0,
+            points[0].0,
+            line = line,
+            bottom = chart_bottom,
+        );
+    }
+
+    format!(
+        r#"<svg width="{w}" height="{h}" viewBox="0 0 {w} {h}" xmlns="http://www.w3.org/2000/svg">
+  <defs>
+    <style>
+      .background {{ fill: white; }}
+      .namespace {{ font-family: monospace; font-size: 30px; fill: hsl(220, 9%, 46%); }}
+      .id {{ font-family: monospace; font-size: 56px; font-weight: bold; fill: hsl(220, 9%, 18%); }}
+      .value {{ font-family: monospace; font-size: 110px; font-weight: bold; fill: hsl(220, 9%, 18%); }}
+      .updated {{ font-family: monospace; font-size: 26px; fill: hsl(220, 9%, 46%); }}
+      .area {{ fill: hsl(220, 9%, 18%); fill-opacity: 0.08; }}
+      .line {{ fill: none; stroke: hsl(220, 9%, 18%); stroke-width: 4; stroke-linecap: round; stroke-linejoin: round; }}
+    </style>
+  </defs>
+  <rect x="0" y="0" width="{w}" height="{h}" class="background"/>
+  <text x="{left}" y="96" class="namespace">{namespace}</text>
+  <text x="{left}" y="164" class="id">{id}</text>
+  <text x="{left}" y="292" class="value">{value}</text>
+  <text x="{left}" y="334" class="updated">{updated}</text>
+  {chart}
+</svg>"#,
+        w = CARD_WIDTH,
+        h = CARD_HEIGHT,
+        left = left,
+        namespace = escape_xml(&truncate(namespace, 60)),
+        id = escape_xml(&truncate(id, 31)),
+        value = escape_xml(&value),
+        updated = escape_xml(&updated),
+        chart = chart,
+    )
+}
+
+/// Preview image referenced by the chart page's Open Graph tags, so shared
+/// links unfurl with the metric's current state.
+pub async fn get_og_png(
+    SeriesPath(namespace, id): SeriesPath,
+    State(store): State<Arc<dyn MetricStore>>,
+    State(config): State<Arc<Config>>,
+) -> Result<Response, StatusCode> {
+    let data = match load_chart_points(store.as_ref(), &namespace, &id, &ChartQuery::default(), config.limits.max_query_points).await {
+        Ok(data) => data,
+        Err(err) => return err.respond(),
+    };
+
+    let png = render_png(&og_card_svg(&namespace, &id, &downsample(data, CARD_POINTS)))
+        .map_err(errors::internal)?;
+
+    Ok((
+        StatusCode::OK,
+        [("content-type", "image/png"), ("cache-control", "public, max-age=300")],
+        png,
+    )
+        .into_response())
+}
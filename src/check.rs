@@ -0,0 +1,347 @@
+//! `/{namespace}/{id}/check`: a regression gate for CI. The series' latest
+//! point is compared with a baseline worked out from the points before it,
+//! such as their median over the last 30 days, and the answer is `200` when
+//! it's within the allowed change and `409` when it isn't, so a job can
+//! fail its build with `curl --fail`. Either way the body says why.
+//!
+//! With `?branch=` only points recorded on that branch count towards the
+//! baseline, so a pull request's result is held against the default
+//! branch's rather than other pull requests'.
+//!
+//! Baselines are worked out from the raw points, never the rollups, so a
+//! 30-day median is the median of what was recorded rather than of hourly
+//! averages.
+
+use std::str::FromStr;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    daily,
+    ids::SeriesPath,
+    limits::{self, ReadError},
+    meta,
+    store::StoredPoint,
+    AppState, MetricPoint,
+};
+
+/// The longest window a baseline can be taken over
+const MAX_WINDOW_SECONDS: i64 = 366 * 86400;
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(json!({ "error": message })))
+}
+
+fn database_error<E: std::fmt::Display>(err: E) -> ApiError {
+    crate::errors::report(&err);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+/// `?max_increase=5%&baseline=median_30d`; `max_decrease` is for metrics
+/// where higher is better, and either or both can be given.
+#[derive(Deserialize)]
+pub struct CheckQuery {
+    max_increase: Option<String>,
+    max_decrease: Option<String>,
+    baseline: Option<String>,
+    branch: Option<String>,
+}
+
+/// How far the latest point may move from the baseline, either relative to
+/// it (`5%`) or in the metric's own units (`0.2`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Limit {
+    Percent(f64),
+    Absolute(f64),
+}
+
+impl Limit {
+    fn allowed(self, baseline: f64) -> f64 {
+        match self {
+            Limit::Percent(percent) => baseline.abs() * percent / 100.0,
+            Limit::Absolute(amount) => amount,
+        }
+    }
+
+    fn describe(self, meta: &meta::MetricMeta) -> String {
+        match self {
+            Limit::Percent(percent) => format!("{}%", percent),
+            Limit::Absolute(amount) => meta.format(amount),
+        }
+    }
+}
+
+impl FromStr for Limit {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, ()> {
+        let value = value.trim();
+        let (number, percent) = match value.strip_suffix('%') {
+            Some(number) => (number, true),
+            None => (value, false),
+        };
+        let number: f64 = number.trim().parse().map_err(|_| ())?;
+        if !number.is_finite() || number < 0.0 {
+            return Err(());
+        }
+        Ok(if percent { Limit::Percent(number) } else { Limit::Absolute(number) })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Statistic {
+    Median,
+    Mean,
+    Min,
+    Max,
+}
+
+/// What the latest point is compared with: the point before it, or a
+/// statistic over the window before it, written `median_30d` or `mean_12h`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Baseline {
+    Previous,
+    Window(Statistic, i64),
+}
+
+impl FromStr for Baseline {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, ()> {
+        if value == "previous" {
+            return Ok(Baseline::Previous);
+        }
+        let (statistic, window) = value.split_once('_').ok_or(())?;
+        let statistic = match statistic {
+            "median" => Statistic::Median,
+            "mean" => Statistic::Mean,
+            "min" => Statistic::Min,
+            "max" => Statistic::Max,
+            _ => return Err(()),
+        };
+        let unit = match window.chars().last().ok_or(())? {
+            'h' => 3600,
+            'd' => 86400,
+            _ => return Err(()),
+        };
+        let count: i64 = window[..window.len() - 1].parse().map_err(|_| ())?;
+        let seconds = count.checked_mul(unit).ok_or(())?;
+        if seconds <= 0 || seconds > MAX_WINDOW_SECONDS {
+            return Err(());
+        }
+        Ok(Baseline::Window(statistic, seconds))
+    }
+}
+
+impl Baseline {
+    /// The baseline value over `points`, which are in time order, and how
+    /// many of them it came from, or `None` when there are none.
+    fn compute(self, points: &[StoredPoint]) -> Option<(f64, usize)> {
+        let mut values: Vec<f64> = points.iter().map(|point| point.value).filter(|value| value.is_finite()).collect();
+        if values.is_empty() {
+            return None;
+        }
+        let statistic = match self {
+            Baseline::Previous => return values.last().map(|&value| (value, 1)),
+            Baseline::Window(statistic, _) => statistic,
+        };
+        let value = match statistic {
+            Statistic::Mean => values.iter().sum::<f64>() / values.len() as f64,
+            Statistic::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+            Statistic::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            Statistic::Median => {
+                values.sort_by(f64::total_cmp);
+                daily::quantile(&values, 0.5)
+            }
+        };
+        Some((value, values.len()))
+    }
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Verdict {
+    Pass,
+    Fail,
+    /// Nothing to compare with yet, which doesn't fail the build
+    NoBaseline,
+}
+
+#[derive(Serialize)]
+struct BaselineSummary {
+    method: String,
+    value: f64,
+    /// How many points it was worked out from
+    points: usize,
+}
+
+#[derive(Serialize)]
+struct CheckResponse {
+    namespace: String,
+    id: String,
+    verdict: Verdict,
+    /// One line for the CI log
+    message: String,
+    latest: MetricPoint,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    baseline: Option<BaselineSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    change: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    change_percent: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_increase: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_decrease: Option<String>,
+}
+
+fn parse_limit(value: Option<&str>, name: &str) -> Result<Option<Limit>, ApiError> {
+    value
+        .map(|value| {
+            value.parse().map_err(|_| {
+                error(
+                    StatusCode::BAD_REQUEST,
+                    &format!("{} must be a percentage like 5% or an amount like 0.2", name),
+                )
+            })
+        })
+        .transpose()
+}
+
+pub async fn get_check(
+    SeriesPath(namespace, id): SeriesPath,
+    Query(query): Query<CheckQuery>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, ApiError> {
+    let max_increase = parse_limit(query.max_increase.as_deref(), "max_increase")?;
+    let max_decrease = parse_limit(query.max_decrease.as_deref(), "max_decrease")?;
+    if max_increase.is_none() && max_decrease.is_none() {
+        return Err(error(StatusCode::BAD_REQUEST, "give max_increase, max_decrease or both"));
+    }
+    let method = query.baseline.unwrap_or_else(|| "median_30d".to_string());
+    let baseline: Baseline = method.parse().map_err(|_| {
+        error(
+            StatusCode::BAD_REQUEST,
+            "baseline must be previous or a statistic and window like median_30d, mean_12h, min_7d or max_7d",
+        )
+    })?;
+    let branch = query.branch.filter(|branch| !branch.is_empty());
+
+    let not_found = || error(StatusCode::NOT_FOUND, "series has no points");
+    let until = state
+        .store
+        .latest(&namespace, &id)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(not_found)?
+        .timestamp;
+    let since = match baseline {
+        Baseline::Previous => i64::MIN,
+        Baseline::Window(_, seconds) => until.saturating_sub(seconds),
+    };
+    let max_points = state.config().limits.max_query_points;
+    let points = state
+        .store
+        .points(&namespace, &id, (since, until), limits::fetch_limit(max_points))
+        .await
+        .map_err(database_error)?;
+    let mut history = limits::within(points, max_points).map_err(ReadError::api)?;
+    // The point judged is the newest, the later-inserted one on a tied
+    // timestamp, and it isn't part of its own baseline
+    let newest = history
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, point)| (point.timestamp, point.point_id))
+        .map(|(index, _)| index)
+        .ok_or_else(not_found)?;
+    let latest = history.remove(newest);
+    let latest = MetricPoint {
+        timestamp: latest.timestamp,
+        value: latest.value,
+        sha: latest.sha,
+        branch: latest.branch,
+    };
+    if let Some(branch) = &branch {
+        history.retain(|point| point.branch.as_deref() == Some(branch.as_str()));
+    }
+    let meta = meta::load(&state.pool, &namespace, &id).await.map_err(database_error)?;
+    let limits = (
+        max_increase.map(|limit| limit.describe(&meta)),
+        max_decrease.map(|limit| limit.describe(&meta)),
+    );
+
+    let Some((value, count)) = baseline.compute(&history) else {
+        let response = CheckResponse {
+            namespace: namespace.clone(),
+            id: id.clone(),
+            verdict: Verdict::NoBaseline,
+            message: format!(
+                "{}/{} is {}, with nothing {}to compare it with",
+                namespace,
+                id,
+                meta.format(latest.value),
+                branch.as_deref().map(|branch| format!("on {} ", branch)).unwrap_or_default()
+            ),
+            latest,
+            baseline: None,
+            change: None,
+            change_percent: None,
+            max_increase: limits.0,
+            max_decrease: limits.1,
+        };
+        return Ok((StatusCode::OK, Json(response)));
+    };
+
+    let change = latest.value - value;
+    let change_percent = (value != 0.0).then(|| change / value.abs() * 100.0);
+    let rose_too_far = max_increase.is_some_and(|limit| change > limit.allowed(value));
+    let fell_too_far = max_decrease.is_some_and(|limit| -change > limit.allowed(value));
+    let verdict = if rose_too_far || fell_too_far { Verdict::Fail } else { Verdict::Pass };
+
+    let direction = if change < 0.0 { "fell" } else { "rose" };
+    let percent = change_percent.map(|percent| format!(" ({:.1}%)", percent.abs())).unwrap_or_default();
+    let mut message = format!(
+        "{}/{} {} by {}{} to {}, against {} {}",
+        namespace,
+        id,
+        direction,
+        meta.format(change.abs()),
+        percent,
+        meta.format(latest.value),
+        method,
+        meta.format(value)
+    );
+    match (rose_too_far, fell_too_far) {
+        (true, _) => message.push_str(&format!(", more than the {} allowed", limits.0.as_deref().unwrap_or_default())),
+        (_, true) => message.push_str(&format!(", more than the {} allowed", limits.1.as_deref().unwrap_or_default())),
+        _ => message.push_str(", within limits"),
+    }
+
+    let status = if verdict == Verdict::Fail { StatusCode::CONFLICT } else { StatusCode::OK };
+    let response = CheckResponse {
+        namespace,
+        id,
+        verdict,
+        message,
+        latest,
+        baseline: Some(BaselineSummary {
+            method,
+            value,
+            points: count,
+        }),
+        change: Some(change),
+        change_percent,
+        max_increase: limits.0,
+        max_decrease: limits.1,
+    };
+    Ok((status, Json(response)))
+}
@@ -0,0 +1,141 @@
+//! The commands besides `serve`, for looking after a server's database from
+//! a shell instead of with raw SQL: pruning on demand, moving a namespace
+//! between servers as a bundle, and issuing tokens. Each opens the database
+//! itself, so a server can keep running on it meanwhile; reload that server
+//! afterwards so it drops what it had cached.
+
+use std::error::Error;
+use std::io::{IsTerminal, Read, Write};
+use std::path::PathBuf;
+
+use crate::{
+    alerts, auth,
+    bundle::{self, Bundle},
+    config::{Config, Sources},
+    dashboards, detection, owners, retention, trash, AppState,
+};
+
+/// Length of generated admin tokens
+const ADMIN_TOKEN_LENGTH: usize = 32;
+
+pub enum Command {
+    /// One round of the pruning the server does on its own every
+    /// `RETENTION_PRUNE_INTERVAL_SECS`
+    Prune,
+    /// A namespace's bundle, to `output` or stdout
+    Export { namespace: String, output: Option<PathBuf> },
+    /// A bundle restored into an empty namespace, from `input` or, for `-`,
+    /// stdin
+    Import { namespace: String, input: PathBuf },
+    CreateToken(TokenFor),
+}
+
+/// What `token create` issues a token for.
+pub enum TokenFor {
+    /// A new `ADMIN_TOKEN`, which only takes effect once it's configured
+    Admin,
+    /// An owner token for a namespace, replacing any it had
+    Namespace(String),
+    /// A replacement edit token for a dashboard
+    Dashboard(String),
+}
+
+/// Runs `command` against the configured database.
+pub async fn run(command: Command, config: Config, sources: Sources) -> Result<(), Box<dyn Error>> {
+    // A new admin token has nothing to do with the database
+    if let Command::CreateToken(TokenFor::Admin) = command {
+        println!("{}", auth::random_string(ADMIN_TOKEN_LENGTH));
+        eprintln!("Set it as ADMIN_TOKEN, or auth.admin_token in the config file, then reload the server");
+        return Ok(());
+    }
+
+    let state = AppState::connect(config, sources).await?;
+    let result = match command {
+        Command::Prune => prune(&state).await,
+        Command::Export { namespace, output } => export(&state, &namespace, output).await,
+        Command::Import { namespace, input } => import(&state, &namespace, input).await,
+        Command::CreateToken(owner) => create_token(&state, owner).await,
+    };
+    // Closing the pool checkpoints the write-ahead log
+    state.pool.close().await;
+    result
+}
+
+fn normalize_namespace(state: &AppState, namespace: &str) -> Result<String, Box<dyn Error>> {
+    let namespace = state.config().id_policy.normalize(namespace);
+    if namespace.is_empty() {
+        return Err("the namespace can't be empty".into());
+    }
+    Ok(namespace)
+}
+
+async fn prune(state: &AppState) -> Result<(), Box<dyn Error>> {
+    let pruned = retention::prune(state).await?;
+    println!("Pruned {} points past their retention", pruned);
+    let expired = retention::prune_inactive(state).await?;
+    println!("Deleted {} metrics with no recent writes", expired);
+    let purged = trash::empty(state).await?;
+    println!("Purged {} deleted metrics past their grace period", purged);
+    alerts::prune_events(state).await?;
+    detection::prune(state).await?;
+    Ok(())
+}
+
+async fn export(state: &AppState, namespace: &str, output: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
+    if output.is_none() && std::io::stdout().is_terminal() {
+        return Err("bundles are gzip; pass --output or redirect stdout to a file".into());
+    }
+    let namespace = normalize_namespace(state, namespace)?;
+    let bundle = Bundle::export(state, &namespace).await?;
+    if bundle.metric_count() == 0 {
+        return Err(format!("{} has no metrics to export", namespace).into());
+    }
+    let data = bundle.to_gzip()?;
+
+    match output {
+        Some(path) => {
+            std::fs::write(&path, &data).map_err(|err| format!("couldn't write {}: {}", path.display(), err))?;
+            eprintln!("Exported {} metrics from {} to {}", bundle.metric_count(), namespace, path.display());
+        }
+        None => {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(&data)?;
+            stdout.flush()?;
+        }
+    }
+    Ok(())
+}
+
+async fn import(state: &AppState, namespace: &str, input: PathBuf) -> Result<(), Box<dyn Error>> {
+    let namespace = normalize_namespace(state, namespace)?;
+    let data = if input.as_os_str() == "-" {
+        let mut data = Vec::new();
+        std::io::stdin().lock().read_to_end(&mut data)?;
+        data
+    } else {
+        std::fs::read(&input).map_err(|err| format!("couldn't read {}: {}", input.display(), err))?
+    };
+    let bundle = Bundle::from_gzip(&data).map_err(|err| format!("{} isn't a bundle: {}", input.display(), err))?;
+
+    let inserted = bundle::restore(state, &namespace, &bundle)
+        .await
+        .map_err(|err| format!("couldn't import into {}: {}", namespace, err))?;
+    println!("Imported {} points in {} metrics into {}", inserted, bundle.metric_count(), namespace);
+    Ok(())
+}
+
+async fn create_token(state: &AppState, owner: TokenFor) -> Result<(), Box<dyn Error>> {
+    let token = match owner {
+        TokenFor::Admin => unreachable!("admin tokens are made without the database"),
+        TokenFor::Namespace(namespace) => {
+            let namespace = normalize_namespace(state, &namespace)?;
+            owners::issue_token(state, &namespace).await?
+        }
+        TokenFor::Dashboard(slug) => dashboards::replace_token(state, &slug)
+            .await?
+            .ok_or_else(|| format!("there's no dashboard {}", slug))?,
+    };
+    println!("{}", token);
+    eprintln!("The previous token, if there was one, no longer works");
+    Ok(())
+}
@@ -0,0 +1,370 @@
+//! The outbound requests the server makes: uploading archives to an object
+//! store, pulling namespaces from other instances and calling alert
+//! webhooks. They share one [`reqwest`] client, so connections to the same
+//! host are kept alive between requests; redirects aren't followed, the way
+//! a webhook's answer only counts for its status.
+//!
+//! URLs that come in through the API, for webhooks, alert rules and digests,
+//! go through a second client that won't connect to loopback, private,
+//! link-local (where cloud metadata services such as 169.254.169.254 live)
+//! or other non-public addresses, unless `ALLOW_PRIVATE_WEBHOOK_URLS` is
+//! set. They're checked when they're registered and again on every
+//! connection, since a name can resolve somewhere else later.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::{redirect, RequestBuilder, Url};
+
+pub type ClientError = Box<dyn std::error::Error + Send + Sync>;
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Most response bytes read back after a `POST`, whose answer only needs a status
+const MAX_POST_RESPONSE_BYTES: usize = 64 * 1024;
+
+/// Scheme, host and port of a server.
+#[derive(Clone, Debug)]
+pub struct Endpoint {
+    url: Url,
+}
+
+impl Endpoint {
+    /// A base URL such as `https://example.com:8443`, with no path.
+    pub fn parse(url: &str) -> Result<Self, String> {
+        match Self::split(url)? {
+            (endpoint, path) if path == "/" => Ok(endpoint),
+            _ => Err(format!("`{}` has a path, but only a scheme, host and port are expected", url)),
+        }
+    }
+
+    /// Splits a full URL into its endpoint and its path plus query, which is
+    /// `/` when the URL has none.
+    pub fn split(url: &str) -> Result<(Self, String), String> {
+        let invalid = || format!("`{}` isn't an http:// or https:// URL", url);
+        let parsed = Url::parse(url).map_err(|_| invalid())?;
+        if !matches!(parsed.scheme(), "http" | "https")
+            || parsed.host_str().is_none_or(str::is_empty)
+            || !parsed.username().is_empty()
+            || parsed.password().is_some()
+            || parsed.fragment().is_some()
+        {
+            return Err(invalid());
+        }
+        let path = match parsed.query() {
+            Some(query) => format!("{}?{}", parsed.path(), query),
+            None => parsed.path().to_string(),
+        };
+        let mut base = parsed;
+        base.set_path("/");
+        base.set_query(None);
+        Ok((Endpoint { url: base }, path))
+    }
+
+    /// The `Host` header value, which only carries the port when it isn't
+    /// the scheme's default. IPv6 addresses keep their brackets.
+    pub fn authority(&self) -> String {
+        let host = self.url.host_str().unwrap_or_default();
+        match self.url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        }
+    }
+
+    /// The base URL, ending in `/`.
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// The URL of `path`, which starts with `/`, on this server.
+    pub fn join(&self, path: &str) -> String {
+        format!("{}{}", self.url.as_str().trim_end_matches('/'), path)
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+fn builder() -> reqwest::ClientBuilder {
+    reqwest::Client::builder()
+        .user_agent("somnial")
+        .timeout(TIMEOUT)
+        .connect_timeout(CONNECT_TIMEOUT)
+        .redirect(redirect::Policy::none())
+}
+
+/// The client for requests to where the operator configured, which may well
+/// be on a private network.
+pub fn http() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| builder().build().expect("the HTTP client's settings are valid"))
+}
+
+/// The client for URLs from API users, which only connects to public
+/// addresses.
+fn public_http() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        builder()
+            .dns_resolver(Arc::new(PublicResolver))
+            // A proxy would do the resolving, out of our sight
+            .no_proxy()
+            .build()
+            .expect("the HTTP client's settings are valid")
+    })
+}
+
+/// Resolves names the usual way, leaving out non-public addresses.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str();
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host, 0))
+                .await?
+                .filter(|address| is_public(address.ip()))
+                .collect();
+            if addresses.is_empty() {
+                return Err(format!("{} has no public address", host).into());
+            }
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Whether `ip` is on the public internet, rather than the loopback, a
+/// private (RFC 1918 or unique local) or shared (RFC 6598) network, a
+/// link-local one, or unspecified, multicast or broadcast. IPv6 addresses
+/// that carry an IPv4 one are judged by the address they carry.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || first == 0
+                || (first == 100 && (64..128).contains(&second)))
+        }
+        IpAddr::V6(ip) => match embedded_ipv4(ip) {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// The IPv4 address inside an IPv4-mapped (`::ffff:a.b.c.d`), IPv4-compatible
+/// (`::a.b.c.d`), NAT64 (`64:ff9b::/96`) or 6to4 (`2002::/16`) address, any
+/// of which can end up delivered to it.
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let segments = ip.segments();
+    let [.., high, low] = segments;
+    match segments {
+        [0, 0, 0, 0, 0, 0xffff, ..] | [0, 0, 0, 0, 0, 0, ..] | [0x64, 0xff9b, 0, 0, 0, 0, ..] => {
+            Some(Ipv4Addr::from((u32::from(high) << 16) | u32::from(low)))
+        }
+        [0x2002, high, low, ..] => Some(Ipv4Addr::from((u32::from(high) << 16) | u32::from(low))),
+        _ => None,
+    }
+}
+
+/// The address in a URL's host, when it's an IP address rather than a name.
+fn literal_address(url: &Url) -> Option<IpAddr> {
+    url.host_str()?.trim_start_matches('[').trim_end_matches(']').parse().ok()
+}
+
+/// Checks a URL an API user gave is one the server can be made to send to:
+/// an http:// or https:// URL whose host is a public address, or a name
+/// that resolves only to public ones. With `allow_private`, anything that
+/// parses will do.
+pub async fn check_public(url: &str, allow_private: bool) -> Result<(), String> {
+    Endpoint::split(url)?;
+    if allow_private {
+        return Ok(());
+    }
+    let url = Url::parse(url).map_err(|err| err.to_string())?;
+    let private = || format!("`{}` points at a private address, which webhooks can't be sent to", url);
+    if let Some(ip) = literal_address(&url) {
+        return if is_public(ip) { Ok(()) } else { Err(private()) };
+    }
+    let host = url.host_str().unwrap_or_default();
+    let port = url.port_or_known_default().unwrap_or(443);
+    let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|_| format!("`{}` has a host that doesn't resolve", url))?
+        .collect();
+    if addresses.iter().any(|address| !is_public(address.ip())) {
+        return Err(private());
+    }
+    Ok(())
+}
+
+/// Sends `request` and returns the response. With `max_body` of `None` the
+/// body is left unread; otherwise a body longer than `max_body` bytes is an
+/// error.
+pub async fn send(request: RequestBuilder, max_body: Option<usize>) -> Result<Response, ClientError> {
+    let mut response = request.send().await?;
+    let status = response.status().as_u16();
+    let Some(max_body) = max_body else {
+        return Ok(Response { status, body: Vec::new() });
+    };
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > max_body {
+            return Err(format!("response is over {} bytes", max_body).into());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(Response { status, body })
+}
+
+/// `POST`s a JSON `body` to `url`, which only counts as delivered if the
+/// server answers with a 2xx status.
+pub async fn post_json(url: &str, headers: &[(&str, &str)], body: &[u8]) -> Result<(), ClientError> {
+    post_json_with(http(), url, headers, body).await
+}
+
+/// [`post_json`] for a URL an API user gave, which mustn't reach a private
+/// address unless `allow_private`.
+pub async fn post_webhook(url: &str, body: &[u8], allow_private: bool) -> Result<(), ClientError> {
+    if allow_private {
+        return post_json_with(http(), url, &[], body).await;
+    }
+    // Addresses in the URL itself never reach the resolver
+    if literal_address(&Url::parse(url)?).is_some_and(|ip| !is_public(ip)) {
+        return Err(format!("{} is a private address", url).into());
+    }
+    post_json_with(public_http(), url, &[], body).await
+}
+
+async fn post_json_with(
+    client: &reqwest::Client,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<(), ClientError> {
+    let mut request = client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .body(body.to_vec());
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let response = send(request, Some(MAX_POST_RESPONSE_BYTES)).await?;
+    if !(200..300).contains(&response.status) {
+        return Err(format!("server answered {}", response.status).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// A server on a local port that answers one request with `response`.
+    async fn answer_once(response: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 4096];
+            let _ = stream.read(&mut request).await;
+            stream.write_all(response.as_bytes()).await.unwrap();
+        });
+        format!("http://{}/hook", address)
+    }
+
+    #[test]
+    fn endpoints_split_off_the_path() {
+        let (endpoint, path) = Endpoint::split("https://example.com:8443/api/v1?since=7d").unwrap();
+        assert_eq!(endpoint.url().as_str(), "https://example.com:8443/");
+        assert_eq!(endpoint.authority(), "example.com:8443");
+        assert_eq!(path, "/api/v1?since=7d");
+        assert_eq!(endpoint.join("/ci"), "https://example.com:8443/ci");
+
+        let (endpoint, path) = Endpoint::split("http://[::1]:8080").unwrap();
+        assert_eq!(endpoint.authority(), "[::1]:8080");
+        assert_eq!(path, "/");
+        // The scheme's own port is left out of the Host header
+        assert_eq!(Endpoint::parse("https://example.com:443").unwrap().authority(), "example.com");
+
+        assert!(Endpoint::parse("https://example.com/path").is_err());
+        for url in ["ftp://example.com", "example.com", "https://user:pw@example.com", "https://example.com/#top"] {
+            assert!(Endpoint::split(url).is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn public_addresses() {
+        for ip in [
+            "93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946", "::ffff:8.8.8.8", "64:ff9b::8.8.8.8",
+            "2002:808:808::1",
+        ] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0",
+            "0.1.2.3", "255.255.255.255", "224.0.0.1", "::1", "::", "fd00::1", "fe80::1", "ff02::1",
+            "::ffff:127.0.0.1", "::127.0.0.1", "::10.0.0.1", "64:ff9b::169.254.169.254", "64:ff9b::a00:1",
+            "2002:7f00:1::", "2002:a9fe:a9fe::1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn private_urls_are_refused_unless_allowed() {
+        assert!(check_public("https://93.184.216.34/hook", false).await.is_ok());
+        assert!(check_public("http://[fe80::1]:8080/hook", false).await.is_err());
+        assert!(check_public("http://169.254.169.254/latest/meta-data", false).await.is_err());
+        assert!(check_public("http://localhost:3000/hook", false).await.is_err());
+        assert!(check_public("http://localhost:3000/hook", true).await.is_ok());
+        assert!(check_public("javascript:alert(1)", true).await.is_err());
+        assert!(post_webhook("http://10.0.0.1/hook", b"{}", false).await.is_err());
+        // Nor through an IPv6 address wrapping a private one
+        assert!(check_public("http://[::ffff:127.0.0.1]/hook", false).await.is_err());
+        assert!(check_public("http://[64:ff9b::a9fe:a9fe]/latest/meta-data", false).await.is_err());
+        assert!(post_webhook("http://[2002:a00:1::]/hook", b"{}", false).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn reads_chunked_bodies_up_to_a_limit() {
+        let chunked = "HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n";
+        let url = answer_once(chunked).await;
+        let response = send(http().get(&url), Some(64)).await.unwrap();
+        assert_eq!((response.status, response.body.as_slice()), (200, &b"hello world"[..]));
+
+        let url = answer_once(chunked).await;
+        assert!(send(http().get(&url), Some(8)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn posts_only_count_when_answered_with_success() {
+        let url = answer_once("HTTP/1.1 204 No Content\r\n\r\n").await;
+        post_webhook(&url, b"{}", true).await.unwrap();
+        let url = answer_once("HTTP/1.1 500 Internal Server Error\r\ncontent-length: 0\r\n\r\n").await;
+        let err = post_json(&url, &[], b"{}").await.unwrap_err();
+        assert_eq!(err.to_string(), "server answered 500");
+        // Redirects aren't followed
+        let url = answer_once("HTTP/1.1 302 Found\r\nlocation: http://example.com/\r\ncontent-length: 0\r\n\r\n").await;
+        assert!(post_json(&url, &[], b"{}").await.is_err());
+    }
+}
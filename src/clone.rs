@@ -0,0 +1,139 @@
+//! `POST /api/v1/namespaces/{namespace}/clone`: copies a namespace into a new
+//! one, for forking a project's tracking or seeding a staging sandbox with
+//! realistic data. With `days` only that many days of points and markers
+//! come along.
+//!
+//! Per-metric settings and the namespace's retention and point caps are
+//! copied too. The README and owner token are not: the new namespace starts
+//! unowned, and custom domains, dashboards and aliases stay with the
+//! original. Only the admin token may clone, since cloning creates a
+//! namespace and can copy a lot of points.
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{auth, ids::NamespacePath, rollup, AppState};
+
+const DAY: i64 = 86400;
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(json!({ "error": message })))
+}
+
+fn database_error<E: std::fmt::Display>(err: E) -> ApiError {
+    crate::errors::report(&err);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+/// `{"to": "staging", "days": 30}`
+#[derive(Deserialize)]
+pub struct CloneRequest {
+    to: String,
+    /// Only copy points and markers from the last this many days
+    days: Option<u32>,
+}
+
+pub async fn post_clone(
+    NamespacePath(namespace): NamespacePath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<CloneRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+
+    let to = state.config().id_policy.normalize(&request.to);
+    if to.is_empty() {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "to must name a namespace"));
+    }
+    if to == namespace {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "a namespace can't be cloned into itself"));
+    }
+    if request.days == Some(0) {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "days must be at least 1"));
+    }
+    let since = match request.days {
+        Some(days) => Utc::now().timestamp() - i64::from(days) * DAY,
+        None => i64::MIN,
+    };
+
+    // Settings live apart from the points and are copied once they're in
+    let (pool, namespace, to) = (&state.pool, &namespace, &to);
+    let points = match state.write_store(|| state.store.copy_namespace(namespace, to, since)).await {
+        Ok(Some(0)) => return Err(error(StatusCode::NOT_FOUND, "no points to copy in that namespace")),
+        Ok(Some(points)) => points,
+        Ok(None) => return Err(error(StatusCode::CONFLICT, "a namespace with that name already has data")),
+        Err(err) => return Err(database_error(err)),
+    };
+    state
+        .write(|| async move {
+            let mut tx = pool.begin().await?;
+            // Leftover settings from an earlier namespace of that name give way
+            sqlx::query!(
+                "INSERT OR REPLACE INTO metric_meta (namespace, id, scale, chart_type, unit, description, decimals)
+                 SELECT ?, id, scale, chart_type, unit, description, decimals FROM metric_meta WHERE namespace = ?",
+                to,
+                namespace
+            )
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query!(
+                "INSERT OR REPLACE INTO metric_precision (namespace, id, decimals, quantum)
+                 SELECT ?, id, decimals, quantum FROM metric_precision WHERE namespace = ?",
+                to,
+                namespace
+            )
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query!(
+                "INSERT OR REPLACE INTO metric_point_caps (namespace, id, max_points)
+                 SELECT ?, id, max_points FROM metric_point_caps WHERE namespace = ?",
+                to,
+                namespace
+            )
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query!(
+                "INSERT OR REPLACE INTO namespace_point_caps (namespace, max_points)
+                 SELECT ?, max_points FROM namespace_point_caps WHERE namespace = ?",
+                to,
+                namespace
+            )
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query!(
+                "INSERT OR REPLACE INTO namespace_retention (namespace, days)
+                 SELECT ?, days FROM namespace_retention WHERE namespace = ?",
+                to,
+                namespace
+            )
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query!(
+                "INSERT INTO markers (namespace, timestamp, label, url, source)
+                 SELECT ?, timestamp, label, url, source FROM markers WHERE namespace = ? AND timestamp >= ?
+                 ORDER BY marker_id",
+                to,
+                namespace,
+                since
+            )
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await
+        })
+        .await
+        .map_err(database_error)?;
+    state.invalidate_namespace(to);
+    // The copied points are old, so the rollup job has already gone past them
+    rollup::rebuild_namespace(&state, to).await.map_err(database_error)?;
+
+    Ok(Json(json!({ "namespace": to, "from": namespace, "points": points })))
+}
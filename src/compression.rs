@@ -0,0 +1,50 @@
+//! Gzip and Brotli for pages and API responses, which run to megabytes when
+//! a chart embeds tens of thousands of points and shrink to a fraction of
+//! that. tower-http's [`CompressionLayer`] picks the encoding from
+//! `Accept-Encoding`; [`Compressible`] decides which responses it may
+//! touch. Only whole bodies of text and JSON qualify:
+//! images are compressed already, streams such as backups go out as
+//! they're produced, and responses with an `ETag` are left as they are so
+//! their validators keep matching.
+
+use axum::{
+    body::HttpBody,
+    http::{header, Response, StatusCode},
+};
+use tower_http::compression::{predicate::Predicate, CompressionLayer};
+
+/// Smaller bodies gain too little to be worth it
+const MIN_SIZE: u64 = 1024;
+
+fn compressible_type(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    (essence.starts_with("text/") && essence != "text/event-stream")
+        || essence == "application/json"
+        || essence.ends_with("+json")
+        || essence == "application/javascript"
+        || essence == "image/svg+xml"
+}
+
+/// Whether the response is a whole body of a type worth compressing.
+#[derive(Clone, Copy)]
+pub struct Compressible;
+
+impl Predicate for Compressible {
+    fn should_compress<B: HttpBody>(&self, response: &Response<B>) -> bool {
+        let headers = response.headers();
+        let empty_or_partial = [StatusCode::NO_CONTENT, StatusCode::NOT_MODIFIED, StatusCode::PARTIAL_CONTENT];
+        !empty_or_partial.contains(&response.status())
+            && !headers.contains_key(header::ETAG)
+            && headers
+                .get(header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(compressible_type)
+            && response.body().size_hint().exact().is_some_and(|size| size >= MIN_SIZE)
+    }
+}
+
+/// Compresses what [`Compressible`] lets through, with whichever of gzip
+/// and Brotli the client prefers.
+pub fn layer() -> CompressionLayer<Compressible> {
+    CompressionLayer::new().gzip(true).br(true).compress_when(Compressible)
+}
@@ -0,0 +1,762 @@
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config_file;
+
+pub use crate::archive::ArchiveConfig;
+pub use crate::badge::BadgeOptions;
+pub use crate::client::Endpoint;
+pub use crate::db::{RetryPolicy, SqliteTuning};
+pub use crate::detection::AnomalyPolicy;
+pub use crate::errors::ErrorReporting;
+pub use sentry::types::Dsn as SentryDsn;
+pub use crate::ids::IdPolicy;
+pub use crate::limits::Limits;
+pub use crate::mail::{SmtpConfig, SmtpSecurity};
+pub use crate::rollup::RollupPolicy;
+pub use crate::theme::PageTheme;
+pub use crate::tls::TlsFiles;
+pub use crate::traces::TraceExport;
+
+/// Runtime configuration, read at startup from the environment, the config
+/// file and flags.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Where points are kept: a `sqlite:` file, which holds everything
+    /// else too, or a `postgres:` database
+    pub database_url: String,
+    /// The SQLite file for everything but points when `database_url` is a
+    /// Postgres one. Backups and `/api/v1/query` only cover this file.
+    pub settings_database_url: String,
+    /// Address the server listens on; `::` takes IPv6 as well
+    pub bind_address: IpAddr,
+    pub port: String,
+    /// Prefix every page is served and linked under when the server is
+    /// mounted on part of another site, such as `/metrics`; empty at the root
+    pub base_path: String,
+    /// Socket file to listen on instead of a TCP port
+    pub unix_socket: Option<PathBuf>,
+    /// Permissions for the socket file, such as `0o660` to let a proxy in
+    /// the same group connect; `None` leaves them to the umask
+    pub unix_socket_mode: Option<u32>,
+    /// Certificate and key to serve HTTPS with; `None` serves plain HTTP
+    pub tls: Option<TlsFiles>,
+    pub features: Features,
+    pub busy_retry: RetryPolicy,
+    pub sqlite: SqliteTuning,
+    /// Bearer token for admin endpoints; they reject every request when unset
+    pub admin_token: Option<String>,
+    /// Shared secret GitHub signs marker webhooks with. Once set, markers
+    /// need a signature or an owner or admin token; anyone may post them
+    /// when unset
+    pub github_webhook_secret: Option<String>,
+    /// Token namespaces post commit statuses with unless they have their own
+    pub github_token: Option<String>,
+    /// Base of the GitHub REST API, which differs on GitHub Enterprise
+    pub github_api_url: String,
+    /// Where `pagerduty` alert rules send events
+    pub pagerduty_events_url: String,
+    /// Base of the Opsgenie API, `https://api.eu.opsgenie.com` for accounts
+    /// in the EU
+    pub opsgenie_api_url: String,
+    /// Lets webhooks, alert rules and digests send to loopback, private and
+    /// link-local addresses, for servers whose receivers are all in-house
+    pub allow_private_webhook_urls: bool,
+    /// Where the server is reached from outside, such as
+    /// `https://metrics.example.com`, for links in notifications
+    pub public_url: Option<String>,
+    /// Mail server for alert rules that notify by email; `None` leaves
+    /// email rules unavailable
+    pub smtp: Option<SmtpConfig>,
+    /// Memory budget for cached chart data; 0 disables the cache
+    pub chart_cache_bytes: usize,
+    /// Memory budget for rendered badges; 0 disables the cache
+    pub badge_cache_bytes: usize,
+    /// How badges are drawn when their URL doesn't say
+    pub badge_defaults: BadgeOptions,
+    pub limits: Limits,
+    pub id_policy: IdPolicy,
+    /// Page theme for viewers who haven't picked one
+    pub default_theme: PageTheme,
+    /// Days points are kept in namespaces without their own retention;
+    /// `None` keeps them forever
+    pub retention_days: Option<u32>,
+    /// How often points past their retention are deleted
+    pub retention_interval: Duration,
+    /// Where points are uploaded before retention deletes them; `None`
+    /// deletes them outright
+    pub archive: Option<ArchiveConfig>,
+    /// Days without a write after which a metric is deleted; `None` keeps
+    /// idle metrics forever
+    pub inactive_expiry_days: Option<u32>,
+    /// Days before that deletion during which the metric's pages warn of it
+    pub inactive_grace_days: u32,
+    pub rollups: RollupPolicy,
+    /// Days a deleted metric can still be restored; `None` deletes at once
+    pub delete_grace_days: Option<u32>,
+    /// How often the database is vacuumed and analyzed; `None` leaves it to
+    /// admins calling the endpoint
+    pub maintenance_interval: Option<Duration>,
+    /// How long a shutdown waits for requests in flight before closing
+    /// their connections anyway
+    pub shutdown_timeout: Duration,
+    /// How the background job looks for anomalies; `None` doesn't run it
+    pub anomaly_detection: Option<AnomalyPolicy>,
+    /// Where traces are exported over OTLP; `None` doesn't trace
+    pub trace_export: Option<TraceExport>,
+    /// Where the errors behind `500`s are reported; `None` only logs them
+    pub error_reporting: Option<ErrorReporting>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            database_url: "sqlite:somnial.db".to_string(),
+            settings_database_url: "sqlite:somnial.db".to_string(),
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: "3000".to_string(),
+            base_path: String::new(),
+            unix_socket: None,
+            unix_socket_mode: None,
+            tls: None,
+            features: Features::default(),
+            busy_retry: RetryPolicy {
+                budget: Duration::from_millis(2000),
+            },
+            sqlite: SqliteTuning::default(),
+            admin_token: None,
+            github_webhook_secret: None,
+            github_token: None,
+            github_api_url: "https://api.github.com".to_string(),
+            pagerduty_events_url: "https://events.pagerduty.com/v2/enqueue".to_string(),
+            opsgenie_api_url: "https://api.opsgenie.com".to_string(),
+            allow_private_webhook_urls: false,
+            public_url: None,
+            smtp: None,
+            chart_cache_bytes: 64 * 1024 * 1024,
+            badge_cache_bytes: 16 * 1024 * 1024,
+            badge_defaults: BadgeOptions::default(),
+            limits: Limits::default(),
+            id_policy: IdPolicy::default(),
+            default_theme: PageTheme::default(),
+            retention_days: None,
+            retention_interval: Duration::from_secs(3600),
+            archive: None,
+            inactive_expiry_days: None,
+            inactive_grace_days: 7,
+            rollups: RollupPolicy::default(),
+            delete_grace_days: Some(7),
+            maintenance_interval: Some(Duration::from_secs(86400)),
+            // Inside Kubernetes' default 30 second grace period, leaving time
+            // to flush queues and close the database
+            shutdown_timeout: Duration::from_secs(25),
+            anomaly_detection: None,
+            trace_export: None,
+            error_reporting: None,
+        }
+    }
+}
+
+impl Config {
+    /// The database points go in, when it's Postgres rather than SQLite.
+    pub fn postgres_url(&self) -> Option<&str> {
+        ["postgres:", "postgresql:"]
+            .iter()
+            .any(|scheme| self.database_url.starts_with(scheme))
+            .then_some(self.database_url.as_str())
+    }
+
+    /// Starts from the defaults and applies any environment overrides.
+    pub fn from_env() -> Result<Self, String> {
+        Config::load(&Sources::default())
+    }
+
+    /// Starts from the defaults and applies whatever `sources` set, saying
+    /// where a bad value came from when it wasn't the environment.
+    pub fn load(sources: &Sources) -> Result<Self, String> {
+        Config::from_sources(sources).map_err(|err| {
+            // Errors start with the variable they're about
+            let name = err.split(|c: char| !(c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')).next();
+            match name.filter(|name| config_file::key_for(name).is_some()) {
+                Some(name) => format!("{}{}", err, sources.origin(name)),
+                None => err,
+            }
+        })
+    }
+
+    fn from_sources(sources: &Sources) -> Result<Self, String> {
+        let mut config = Config::default();
+
+        if let Ok(database_url) = sources.var("DATABASE_URL") {
+            config.database_url = database_url;
+        }
+        if let Ok(settings_database_url) = sources.var("SETTINGS_DATABASE_URL") {
+            config.settings_database_url = settings_database_url;
+        }
+        config.bind_address = env_parse(sources, "BIND_ADDRESS", config.bind_address)?;
+        let port: u16 = env_parse(sources, "PORT", 3000)?;
+        config.port = port.to_string();
+        config.tls = tls_from_env(sources)?;
+        if let Some(path) = sources.var("UNIX_SOCKET").ok().filter(|path| !path.is_empty()) {
+            if cfg!(not(unix)) {
+                return Err("UNIX_SOCKET only works on Unix systems".to_string());
+            }
+            if config.tls.is_some() {
+                return Err("UNIX_SOCKET can't be used with TLS_CERT_FILE; leave TLS to the proxy in front".to_string());
+            }
+            config.unix_socket = Some(PathBuf::from(path));
+        }
+        if let Some(mode) = sources.var("UNIX_SOCKET_MODE").ok().filter(|mode| !mode.is_empty()) {
+            let parsed = u32::from_str_radix(mode.trim(), 8).ok().filter(|&mode| mode <= 0o777);
+            config.unix_socket_mode = Some(parsed.ok_or_else(|| {
+                format!("UNIX_SOCKET_MODE has an invalid value `{}`; write octal permissions like 660", mode)
+            })?);
+        }
+
+        if let Ok(enabled) = sources.var("ENABLED_FEATURES") {
+            for name in enabled.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+                config.features.enable(name)?;
+            }
+        }
+        if let Ok(disabled) = sources.var("DISABLED_FEATURES") {
+            for name in disabled.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+                config.features.disable(name)?;
+            }
+        }
+
+        let budget_ms = env_parse(sources, "DB_BUSY_RETRY_BUDGET_MS", config.busy_retry.budget.as_millis() as u64)?;
+        config.busy_retry.budget = Duration::from_millis(budget_ms);
+        config.sqlite.journal_mode = env_parse(sources, "SQLITE_JOURNAL_MODE", config.sqlite.journal_mode)?;
+        config.sqlite.synchronous = env_parse(sources, "SQLITE_SYNCHRONOUS", config.sqlite.synchronous)?;
+        let timeout_ms = env_parse(sources, "SQLITE_BUSY_TIMEOUT_MS", config.sqlite.busy_timeout.as_millis() as u64)?;
+        config.sqlite.busy_timeout = Duration::from_millis(timeout_ms);
+
+        config.admin_token = sources.var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty());
+        config.github_webhook_secret = sources.var("GITHUB_WEBHOOK_SECRET").ok().filter(|s| !s.is_empty());
+        config.github_token = sources.var("GITHUB_TOKEN").ok().map(|t| t.trim().to_string()).filter(|t| !t.is_empty());
+        if let Some(url) = sources.var("GITHUB_API_URL").ok().filter(|url| !url.is_empty()) {
+            Endpoint::split(&url).map_err(|err| format!("GITHUB_API_URL: {}", err))?;
+            config.github_api_url = url.trim_end_matches('/').to_string();
+        }
+        if let Some(url) = sources.var("PAGERDUTY_EVENTS_URL").ok().filter(|url| !url.is_empty()) {
+            Endpoint::split(&url).map_err(|err| format!("PAGERDUTY_EVENTS_URL: {}", err))?;
+            config.pagerduty_events_url = url;
+        }
+        if let Some(url) = sources.var("OPSGENIE_API_URL").ok().filter(|url| !url.is_empty()) {
+            Endpoint::split(&url).map_err(|err| format!("OPSGENIE_API_URL: {}", err))?;
+            config.opsgenie_api_url = url.trim_end_matches('/').to_string();
+        }
+        config.allow_private_webhook_urls =
+            env_parse(sources, "ALLOW_PRIVATE_WEBHOOK_URLS", config.allow_private_webhook_urls)?;
+        if let Some(path) = sources.var("BASE_PATH").ok().filter(|path| !path.is_empty()) {
+            let path = path.trim_end_matches('/');
+            let valid = path.starts_with('/')
+                && !path.contains("//")
+                && path.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte));
+            if !valid && !path.is_empty() {
+                return Err(format!("BASE_PATH has an invalid value `{}`; write a path like /metrics", path));
+            }
+            config.base_path = path.to_string();
+        }
+        if let Some(url) = sources.var("PUBLIC_URL").ok().filter(|url| !url.is_empty()) {
+            Endpoint::split(&url).map_err(|err| format!("PUBLIC_URL: {}", err))?;
+            let mut url = url.trim_end_matches('/').to_string();
+            // Links in notifications are built on it, so the prefix is
+            // added when it was left out
+            if !url.ends_with(&config.base_path) {
+                url.push_str(&config.base_path);
+            }
+            config.public_url = Some(url);
+        }
+        config.smtp = smtp_from_env(sources)?;
+        config.chart_cache_bytes = env_parse(sources, "CHART_CACHE_BYTES", config.chart_cache_bytes)?;
+        config.badge_cache_bytes = env_parse(sources, "BADGE_CACHE_BYTES", config.badge_cache_bytes)?;
+        config.badge_defaults = badge_defaults_from_env(sources)?;
+        config.id_policy.case_insensitive =
+            env_parse(sources, "ID_CASE_INSENSITIVE", config.id_policy.case_insensitive)?;
+        config.id_policy.fold_separators =
+            env_parse(sources, "ID_FOLD_SEPARATORS", config.id_policy.fold_separators)?;
+        config.id_policy.max_length = env_parse(sources, "MAX_ID_LENGTH", config.id_policy.max_length)?;
+        if config.id_policy.max_length == 0 {
+            return Err("MAX_ID_LENGTH must be at least 1".to_string());
+        }
+        config.limits = limits_from_env(sources)?;
+        config.default_theme = env_parse(sources, "DEFAULT_THEME", config.default_theme)?;
+        // 0, like leaving it unset, keeps points forever
+        config.retention_days = Some(env_parse(sources, "RETENTION_DAYS", 0u32)?).filter(|&days| days > 0);
+        let interval_secs = env_parse(sources, "RETENTION_PRUNE_INTERVAL_SECS", config.retention_interval.as_secs())?;
+        if interval_secs == 0 {
+            return Err("RETENTION_PRUNE_INTERVAL_SECS must be at least 1".to_string());
+        }
+        config.retention_interval = Duration::from_secs(interval_secs);
+        config.archive = archive_from_env(sources)?;
+        config.inactive_expiry_days = Some(env_parse(sources, "INACTIVE_EXPIRY_DAYS", 0u32)?).filter(|&days| days > 0);
+        config.inactive_grace_days = env_parse(sources, "INACTIVE_GRACE_DAYS", config.inactive_grace_days)?;
+        if let Some(days) = config.inactive_expiry_days
+            && config.inactive_grace_days >= days
+        {
+            return Err("INACTIVE_GRACE_DAYS must be shorter than INACTIVE_EXPIRY_DAYS".to_string());
+        }
+        // 0 makes deletes immediate and final
+        let grace = env_parse(sources, "DELETE_GRACE_DAYS", config.delete_grace_days.unwrap_or(0))?;
+        config.delete_grace_days = Some(grace).filter(|&days| days > 0);
+        // 0 switches a tier off
+        let days = |name, default: Option<Duration>| -> Result<Option<Duration>, String> {
+            let default = default.map_or(0, |age| age.as_secs() / 86400);
+            Ok(Some(env_parse(sources, name, default)?).filter(|&days| days > 0).map(|days| Duration::from_secs(days * 86400)))
+        };
+        config.rollups.hourly_after = days("ROLLUP_HOURLY_AFTER_DAYS", config.rollups.hourly_after)?;
+        config.rollups.daily_after = days("ROLLUP_DAILY_AFTER_DAYS", config.rollups.daily_after)?;
+        if let (Some(hourly), Some(daily)) = (config.rollups.hourly_after, config.rollups.daily_after)
+            && daily <= hourly
+        {
+            return Err("ROLLUP_DAILY_AFTER_DAYS must be longer than ROLLUP_HOURLY_AFTER_DAYS".to_string());
+        }
+        // 0 turns the schedule off
+        let default_secs = config.maintenance_interval.map_or(0, |interval| interval.as_secs());
+        config.maintenance_interval = Some(env_parse(sources, "MAINTENANCE_INTERVAL_SECS", default_secs)?)
+            .filter(|&secs| secs > 0)
+            .map(Duration::from_secs);
+        config.shutdown_timeout = Duration::from_secs(env_parse(sources, 
+            "SHUTDOWN_TIMEOUT_SECS",
+            config.shutdown_timeout.as_secs(),
+        )?);
+        config.anomaly_detection = anomaly_detection_from_env(sources)?;
+        config.trace_export = trace_export_from_env(sources)?;
+        config.error_reporting = error_reporting_from_env(sources)?;
+
+        Ok(config)
+    }
+}
+
+/// The `ARCHIVE_S3_*` settings, which only count once a bucket is named.
+fn archive_from_env(sources: &Sources) -> Result<Option<ArchiveConfig>, String> {
+    let var = |name: &str| sources.var(name).ok().filter(|value| !value.is_empty());
+    let Some(bucket) = var("ARCHIVE_S3_BUCKET") else {
+        return Ok(None);
+    };
+    let region = var("ARCHIVE_S3_REGION").unwrap_or_else(|| "us-east-1".to_string());
+    let endpoint = var("ARCHIVE_S3_ENDPOINT").unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", region));
+    // The standard AWS variables work too
+    let credential = |name: &str, fallback: &str| {
+        var(name)
+            .or_else(|| var(fallback))
+            .ok_or_else(|| format!("ARCHIVE_S3_BUCKET is set but {} isn't", name))
+    };
+    Ok(Some(ArchiveConfig {
+        endpoint: Endpoint::parse(&endpoint).map_err(|err| format!("ARCHIVE_S3_ENDPOINT: {}", err))?,
+        bucket,
+        prefix: var("ARCHIVE_S3_PREFIX").unwrap_or_default(),
+        region,
+        access_key_id: credential("ARCHIVE_S3_ACCESS_KEY_ID", "AWS_ACCESS_KEY_ID")?,
+        secret_access_key: credential("ARCHIVE_S3_SECRET_ACCESS_KEY", "AWS_SECRET_ACCESS_KEY")?,
+    }))
+}
+
+/// `TLS_CERT_FILE` and `TLS_KEY_FILE`, which go together, loaded once here
+/// so a bad certificate stops startup.
+fn tls_from_env(sources: &Sources) -> Result<Option<TlsFiles>, String> {
+    let var = |name: &str| sources.var(name).ok().filter(|value| !value.is_empty());
+    let files = match (var("TLS_CERT_FILE"), var("TLS_KEY_FILE")) {
+        (None, None) => return Ok(None),
+        (Some(cert_file), Some(key_file)) => TlsFiles {
+            cert_file: cert_file.into(),
+            key_file: key_file.into(),
+        },
+        (Some(_), None) => return Err("TLS_CERT_FILE is set but TLS_KEY_FILE isn't".to_string()),
+        (None, Some(_)) => return Err("TLS_KEY_FILE is set but TLS_CERT_FILE isn't".to_string()),
+    };
+    files.load().map_err(|err| format!("TLS_CERT_FILE and TLS_KEY_FILE: {}", err))?;
+    Ok(Some(files))
+}
+
+/// The `ANOMALY_*` settings, which only count once `ANOMALY_DETECTION`
+/// names a model.
+fn anomaly_detection_from_env(sources: &Sources) -> Result<Option<AnomalyPolicy>, String> {
+    let model = match sources.var("ANOMALY_DETECTION").ok().filter(|model| !model.is_empty()) {
+        None => return Ok(None),
+        Some(model) if model == "off" => return Ok(None),
+        Some(model) => model
+            .parse()
+            .map_err(|_| format!("ANOMALY_DETECTION has an invalid value `{}`; use zscore, daily or weekly", model))?,
+    };
+    let mut policy = AnomalyPolicy::new(model);
+    policy.sigmas = env_parse(sources, "ANOMALY_SIGMAS", policy.sigmas)?;
+    if !(policy.sigmas.is_finite() && policy.sigmas > 0.0) {
+        return Err("ANOMALY_SIGMAS must be a positive number".to_string());
+    }
+    let interval_secs = env_parse(sources, "ANOMALY_INTERVAL_SECS", policy.interval.as_secs())?;
+    if interval_secs == 0 {
+        return Err("ANOMALY_INTERVAL_SECS must be at least 1".to_string());
+    }
+    policy.interval = Duration::from_secs(interval_secs);
+    Ok(Some(policy))
+}
+
+/// The standard `OTEL_*` settings, which only count once an OTLP endpoint
+/// is named.
+fn trace_export_from_env(sources: &Sources) -> Result<Option<TraceExport>, String> {
+    let var = |name: &str| sources.var(name).ok().filter(|value| !value.is_empty());
+    let url = match (var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT"), var("OTEL_EXPORTER_OTLP_ENDPOINT")) {
+        (Some(url), _) => url,
+        (None, Some(base)) => format!("{}/v1/traces", base.trim_end_matches('/')),
+        (None, None) => return Ok(None),
+    };
+    Endpoint::split(&url).map_err(|err| format!("OTEL_EXPORTER_OTLP_ENDPOINT: {}", err))?;
+    let mut headers = Vec::new();
+    for header in var("OTEL_EXPORTER_OTLP_HEADERS").unwrap_or_default().split(',') {
+        if header.trim().is_empty() {
+            continue;
+        }
+        let invalid = || "OTEL_EXPORTER_OTLP_HEADERS must be written name=value,name=value".to_string();
+        let (name, value) = header.split_once('=').ok_or_else(invalid)?;
+        let name = name.trim();
+        // Values may be percent-encoded, as the OpenTelemetry spec allows
+        let value = percent_encoding::percent_decode_str(value.trim())
+            .decode_utf8()
+            .map_err(|_| invalid())?;
+        if name.is_empty() || !name.bytes().all(|byte| byte.is_ascii_graphic() && byte != b':') || value.contains(['\r', '\n']) {
+            return Err(invalid());
+        }
+        headers.push((name.to_string(), value.into_owned()));
+    }
+    let sample_ratio = env_parse(sources, "OTEL_TRACES_SAMPLER_ARG", 1.0)?;
+    if !(0.0..=1.0).contains(&sample_ratio) {
+        return Err("OTEL_TRACES_SAMPLER_ARG must be between 0 and 1".to_string());
+    }
+    Ok(Some(TraceExport {
+        url,
+        headers,
+        service_name: var("OTEL_SERVICE_NAME").unwrap_or_else(|| "somnial".to_string()),
+        sample_ratio,
+    }))
+}
+
+/// `SENTRY_DSN` and `ERROR_WEBHOOK_URL`, either or both of which turn on
+/// error reporting.
+fn error_reporting_from_env(sources: &Sources) -> Result<Option<ErrorReporting>, String> {
+    let var = |name: &str| sources.var(name).ok().filter(|value| !value.is_empty());
+    let sentry: Option<SentryDsn> = var("SENTRY_DSN")
+        .map(|dsn| {
+            dsn.parse()
+                .map_err(|err| format!("SENTRY_DSN must look like https://<key>@<host>/<project id>: {}", err))
+        })
+        .transpose()?;
+    let webhook_url = var("ERROR_WEBHOOK_URL");
+    if let Some(url) = &webhook_url {
+        Endpoint::split(url).map_err(|err| format!("ERROR_WEBHOOK_URL: {}", err))?;
+    }
+    if sentry.is_none() && webhook_url.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(ErrorReporting {
+        sentry,
+        webhook_url,
+        environment: var("SENTRY_ENVIRONMENT"),
+    }))
+}
+
+/// The `SMTP_*` settings, which only count once a host is named.
+fn limits_from_env(sources: &Sources) -> Result<Limits, String> {
+    let defaults = Limits::default();
+    let limits = Limits {
+        max_body_bytes: env_parse(sources, "MAX_BODY_BYTES", defaults.max_body_bytes)?,
+        max_import_bytes: env_parse(sources, "MAX_IMPORT_BYTES", defaults.max_import_bytes)?,
+        max_query_points: env_parse(sources, "MAX_QUERY_POINTS", defaults.max_query_points)?,
+    };
+    for (name, value) in [
+        ("MAX_BODY_BYTES", limits.max_body_bytes),
+        ("MAX_IMPORT_BYTES", limits.max_import_bytes),
+        ("MAX_QUERY_POINTS", limits.max_query_points),
+    ] {
+        if value == 0 {
+            return Err(format!("{} must be at least 1", name));
+        }
+    }
+    Ok(limits)
+}
+
+fn badge_defaults_from_env(sources: &Sources) -> Result<BadgeOptions, String> {
+    let defaults = BadgeOptions::default();
+    let window = sources.var("BADGE_DEFAULT_WINDOW").unwrap_or_default();
+    Ok(BadgeOptions {
+        theme: env_parse(sources, "BADGE_DEFAULT_THEME", defaults.theme)
+            .map_err(|err| format!("{}; use auto, light, dark or high-contrast", err))?,
+        style: env_parse(sources, "BADGE_DEFAULT_STYLE", defaults.style)
+            .map_err(|err| format!("{}; use sparkline or stats", err))?,
+        spark: env_parse(sources, "BADGE_DEFAULT_SPARK", defaults.spark)
+            .map_err(|err| format!("{}; use line, area, bars or dots", err))?,
+        // Unset, badges show the last 50 points whatever their age
+        window: match window.trim() {
+            "" | "all" => None,
+            window => Some(window.parse().map_err(|_| {
+                format!("BADGE_DEFAULT_WINDOW has an invalid value `{}`; use 24h, 7d, 30d or all", window)
+            })?),
+        },
+    })
+}
+
+fn smtp_from_env(sources: &Sources) -> Result<Option<SmtpConfig>, String> {
+    let var = |name: &str| sources.var(name).ok().filter(|value| !value.is_empty());
+    let Some(host) = var("SMTP_HOST") else {
+        return Ok(None);
+    };
+    let security = env_parse(sources, "SMTP_SECURITY", SmtpSecurity::StartTls)
+        .map_err(|err| format!("{}; use tls, starttls or none", err))?;
+    let from = var("SMTP_FROM").ok_or("SMTP_HOST is set but SMTP_FROM isn't")?;
+    if !crate::mail::valid_address(crate::mail::envelope_address(&from)) || from.contains(['\r', '\n']) {
+        return Err(format!("SMTP_FROM has an invalid address `{}`", from));
+    }
+    let username = var("SMTP_USERNAME");
+    let password = var("SMTP_PASSWORD");
+    if password.is_some() && username.is_none() {
+        return Err("SMTP_PASSWORD is set but SMTP_USERNAME isn't".to_string());
+    }
+    Ok(Some(SmtpConfig {
+        host,
+        port: env_parse(sources, "SMTP_PORT", security.default_port())?,
+        security,
+        username,
+        password,
+        from,
+    }))
+}
+
+/// Where settings come from, other than the defaults: `--set` flags first,
+/// then the environment, then the config file.
+#[derive(Clone, Debug, Default)]
+pub struct Sources {
+    flags: BTreeMap<&'static str, String>,
+    file: BTreeMap<&'static str, String>,
+    file_path: Option<String>,
+}
+
+impl Sources {
+    /// Adds the settings in a config file, which the environment overrides.
+    pub fn read_file(&mut self, path: &Path) -> Result<(), String> {
+        self.file = config_file::load(path)?;
+        self.file_path = Some(path.display().to_string());
+        Ok(())
+    }
+
+    /// The config file settings are read from, if there is one.
+    pub fn file_path(&self) -> Option<&Path> {
+        self.file_path.as_deref().map(Path::new)
+    }
+
+    /// The same settings with the config file read again, for a reload.
+    pub fn reread(&self) -> Result<Sources, String> {
+        let mut sources = self.clone();
+        if let Some(path) = self.file_path() {
+            sources.read_file(path)?;
+        }
+        Ok(sources)
+    }
+
+    /// Sets `key`, a setting as it's named in the config file, from a flag.
+    pub fn set(&mut self, key: &str, value: String) -> Result<(), String> {
+        let name = config_file::env_name(key).ok_or_else(|| format!("--set {}", config_file::unknown(key)))?;
+        self.flags.insert(name, value);
+        Ok(())
+    }
+
+    /// A setting by its environment variable's name, with the same errors
+    /// as `std::env::var`.
+    pub fn var(&self, name: &str) -> Result<String, std::env::VarError> {
+        if let Some(value) = self.flags.get(name) {
+            return Ok(value.clone());
+        }
+        match std::env::var(name) {
+            Err(std::env::VarError::NotPresent) => self.file.get(name).cloned().ok_or(std::env::VarError::NotPresent),
+            found => found,
+        }
+    }
+
+    /// ` (from ...)` when `name`'s value came from a flag or the file.
+    fn origin(&self, name: &str) -> String {
+        let key = config_file::key_for(name).unwrap_or(name);
+        if self.flags.contains_key(name) {
+            format!(" (from --set {})", key)
+        } else if std::env::var_os(name).is_none()
+            && self.file.contains_key(name)
+            && let Some(path) = &self.file_path
+        {
+            format!(" (from `{}` in {})", key, path)
+        } else {
+            String::new()
+        }
+    }
+}
+
+fn env_parse<T: std::str::FromStr>(sources: &Sources, name: &str, default: T) -> Result<T, String> {
+    match sources.var(name) {
+        Ok(raw) => raw
+            .trim()
+            .parse()
+            .map_err(|_| format!("{} has an invalid value `{}`", name, raw)),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Optional parts of the HTTP surface. Operators switch pieces off with
+/// `DISABLED_FEATURES=badges,bundle-import`, and the corresponding routes
+/// are never registered. Everything is on by default except what exposes
+/// the server itself, backups, self-metrics and SQL queries, which
+/// `ENABLED_FEATURES=self-metrics` switches on; a feature in both is off.
+#[derive(Clone, Debug)]
+pub struct Features {
+    /// `POST /{namespace}/{id}` for recording new points
+    pub ingest: bool,
+    /// `/{namespace}/{id}/badge.png`
+    pub badges: bool,
+    /// Downloading a namespace as a bundle or a zip archive
+    pub bundle_export: bool,
+    /// Restoring a namespace from an uploaded bundle, or importing one from
+    /// another instance
+    pub bundle_import: bool,
+    /// Whole-database backup and restore under `/admin`; off by default
+    pub backups: bool,
+    /// The server's own counters at `/internal/metrics`, which anyone can
+    /// read; off by default
+    pub self_metrics: bool,
+    /// Ad-hoc read-only SQL at `POST /api/v1/query`; off by default
+    pub query: bool,
+    /// Creating and following `/s/{code}` short links, and the chart
+    /// snapshots served alongside them
+    pub short_links: bool,
+    /// Latest values per namespace for scraping at `/prom/{namespace}`
+    pub prometheus: bool,
+    /// Server-rendered `/{namespace}/{id}/chart.png`, `chart.svg` and the
+    /// `og.png` link preview
+    pub chart_images: bool,
+    /// Serving namespaces on their own hostnames
+    pub custom_domains: bool,
+    /// Graphite-compatible reads under `/graphite`
+    pub graphite: bool,
+    /// Live WebSocket feed of new points at `/api/v1/firehose`
+    pub firehose: bool,
+    /// `/d/{slug}` pages pinning charts from any namespaces
+    pub dashboards: bool,
+    /// `/embed/{namespace}/{id}` iframe pages and the `/oembed` endpoint
+    pub embeds: bool,
+    /// Deploy and release markers posted to `/{namespace}/markers`
+    pub markers: bool,
+    /// Markdown READMEs at the top of namespace pages
+    pub readmes: bool,
+    /// Threshold alert rules and the webhooks they call
+    pub alerts: bool,
+    /// Outgoing webhooks that are sent each namespace's new points
+    pub webhooks: bool,
+    /// GitHub commit statuses for points recorded with `?sha=`
+    pub github_status: bool,
+    /// Daily and weekly summaries of a namespace
+    pub digests: bool,
+    /// Compressing pages and JSON with gzip or Brotli for clients that accept it
+    pub compression: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Features {
+            ingest: true,
+            badges: true,
+            bundle_export: true,
+            bundle_import: true,
+            backups: false,
+            self_metrics: false,
+            query: false,
+            short_links: true,
+            prometheus: true,
+            chart_images: true,
+            custom_domains: true,
+            graphite: true,
+            firehose: true,
+            dashboards: true,
+            embeds: true,
+            markers: true,
+            readmes: true,
+            alerts: true,
+            webhooks: true,
+            github_status: true,
+            digests: true,
+            compression: true,
+        }
+    }
+}
+
+impl Features {
+    const NAMES: &'static [&'static str] = &[
+        "ingest",
+        "badges",
+        "bundle-export",
+        "bundle-import",
+        "backups",
+        "self-metrics",
+        "query",
+        "short-links",
+        "prometheus",
+        "chart-images",
+        "custom-domains",
+        "graphite",
+        "firehose",
+        "dashboards",
+        "embeds",
+        "markers",
+        "readmes",
+        "alerts",
+        "webhooks",
+        "github-status",
+        "digests",
+        "compression",
+    ];
+
+    /// Switches on a feature by its `ENABLED_FEATURES` name.
+    pub fn enable(&mut self, name: &str) -> Result<(), String> {
+        *self.flag(name, "ENABLED_FEATURES")? = true;
+        Ok(())
+    }
+
+    /// Switches off a feature by its `DISABLED_FEATURES` name.
+    pub fn disable(&mut self, name: &str) -> Result<(), String> {
+        *self.flag(name, "DISABLED_FEATURES")? = false;
+        Ok(())
+    }
+
+    /// Whether the feature `name` is on, as `setting` named it.
+    fn flag(&mut self, name: &str, setting: &str) -> Result<&mut bool, String> {
+        Ok(match name {
+            "ingest" => &mut self.ingest,
+            "badges" => &mut self.badges,
+            "bundle-export" => &mut self.bundle_export,
+            "bundle-import" => &mut self.bundle_import,
+            "backups" => &mut self.backups,
+            "self-metrics" => &mut self.self_metrics,
+            "query" => &mut self.query,
+            "short-links" => &mut self.short_links,
+            "prometheus" => &mut self.prometheus,
+            "chart-images" => &mut self.chart_images,
+            "custom-domains" => &mut self.custom_domains,
+            "graphite" => &mut self.graphite,
+            "firehose" => &mut self.firehose,
+            "dashboards" => &mut self.dashboards,
+            "embeds" => &mut self.embeds,
+            "markers" => &mut self.markers,
+            "readmes" => &mut self.readmes,
+            "alerts" => &mut self.alerts,
+            "webhooks" => &mut self.webhooks,
+            "github-status" => &mut self.github_status,
+            "digests" => &mut self.digests,
+            "compression" => &mut self.compression,
+            _ => {
+                return Err(format!(
+                    "unknown feature `{}` in {} (expected one of: {})",
+                    name,
+                    setting,
+                    Self::NAMES.join(", ")
+                ))
+            }
+        })
+    }
+}
@@ -0,0 +1,293 @@
+//! The `--config` file, in TOML. Every setting in it stands for one of the
+//! environment variables, with the same meaning and defaults, so the file
+//! only changes where values come from: `--set` flags win over the
+//! environment, which wins over the file.
+//!
+//! ```toml
+//! port = 8080
+//! disabled_features = ["badges", "bundle-import"]
+//!
+//! [database]
+//! url = "sqlite:/var/lib/somnial/somnial.db"
+//!
+//! [retention]
+//! days = 90
+//!
+//! [badges]
+//! default_theme = "dark"
+//! ```
+//!
+//! Values are strings, numbers or booleans as the variable would hold
+//! them; an array becomes a comma-separated list.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::Deserialize;
+use toml::Value;
+
+/// Declares the settings, at the top of the file or under a `[table]`,
+/// with the environment variable each stands for. The file is read into
+/// structs following the same layout, so a setting that isn't declared
+/// here is an error.
+macro_rules! settings {
+    (
+        $($key:ident: $env:literal,)*
+        $([$table:ident] $($table_key:ident: $table_env:literal,)*)*
+    ) => {
+        /// Each setting's name in the file, and the environment variable it
+        /// stands for.
+        pub const SETTINGS: &[(&str, &str)] = &[
+            $((stringify!($key), $env),)*
+            $($((concat!(stringify!($table), ".", stringify!($table_key)), $table_env),)*)*
+        ];
+
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct File {
+            $($key: Option<Value>,)*
+            $($table: Option<tables::$table::Table>,)*
+        }
+
+        mod tables {
+            $(pub mod $table {
+                #[derive(serde::Deserialize)]
+                #[serde(deny_unknown_fields)]
+                pub struct Table {
+                    $(pub $table_key: Option<toml::Value>,)*
+                }
+            })*
+        }
+
+        impl File {
+            /// The values set, with each one's name in the file and the
+            /// variable it stands for.
+            fn values(self) -> Vec<(&'static str, &'static str, Value)> {
+                let mut values = Vec::new();
+                $(values.extend(self.$key.map(|value| (stringify!($key), $env, value)));)*
+                $(if let Some(table) = self.$table {
+                    $(values.extend(table.$table_key.map(|value| {
+                        (concat!(stringify!($table), ".", stringify!($table_key)), $table_env, value)
+                    }));)*
+                })*
+                values
+            }
+        }
+    };
+}
+
+settings! {
+    port: "PORT",
+    bind_address: "BIND_ADDRESS",
+    base_path: "BASE_PATH",
+    unix_socket: "UNIX_SOCKET",
+    unix_socket_mode: "UNIX_SOCKET_MODE",
+    public_url: "PUBLIC_URL",
+    enabled_features: "ENABLED_FEATURES",
+    disabled_features: "DISABLED_FEATURES",
+    default_theme: "DEFAULT_THEME",
+    shutdown_timeout_secs: "SHUTDOWN_TIMEOUT_SECS",
+    [tls]
+    cert_file: "TLS_CERT_FILE",
+    key_file: "TLS_KEY_FILE",
+    [log]
+    level: "RUST_LOG",
+    format: "LOG_FORMAT",
+    [database]
+    url: "DATABASE_URL",
+    settings_url: "SETTINGS_DATABASE_URL",
+    busy_retry_budget_ms: "DB_BUSY_RETRY_BUDGET_MS",
+    journal_mode: "SQLITE_JOURNAL_MODE",
+    synchronous: "SQLITE_SYNCHRONOUS",
+    busy_timeout_ms: "SQLITE_BUSY_TIMEOUT_MS",
+    maintenance_interval_secs: "MAINTENANCE_INTERVAL_SECS",
+    [auth]
+    admin_token: "ADMIN_TOKEN",
+    [ids]
+    case_insensitive: "ID_CASE_INSENSITIVE",
+    fold_separators: "ID_FOLD_SEPARATORS",
+    max_length: "MAX_ID_LENGTH",
+    [badges]
+    default_theme: "BADGE_DEFAULT_THEME",
+    default_style: "BADGE_DEFAULT_STYLE",
+    default_spark: "BADGE_DEFAULT_SPARK",
+    default_window: "BADGE_DEFAULT_WINDOW",
+    [limits]
+    chart_cache_bytes: "CHART_CACHE_BYTES",
+    badge_cache_bytes: "BADGE_CACHE_BYTES",
+    max_body_bytes: "MAX_BODY_BYTES",
+    max_import_bytes: "MAX_IMPORT_BYTES",
+    max_query_points: "MAX_QUERY_POINTS",
+    [retention]
+    days: "RETENTION_DAYS",
+    prune_interval_secs: "RETENTION_PRUNE_INTERVAL_SECS",
+    inactive_expiry_days: "INACTIVE_EXPIRY_DAYS",
+    inactive_grace_days: "INACTIVE_GRACE_DAYS",
+    delete_grace_days: "DELETE_GRACE_DAYS",
+    [rollups]
+    hourly_after_days: "ROLLUP_HOURLY_AFTER_DAYS",
+    daily_after_days: "ROLLUP_DAILY_AFTER_DAYS",
+    [archive]
+    bucket: "ARCHIVE_S3_BUCKET",
+    region: "ARCHIVE_S3_REGION",
+    endpoint: "ARCHIVE_S3_ENDPOINT",
+    prefix: "ARCHIVE_S3_PREFIX",
+    access_key_id: "ARCHIVE_S3_ACCESS_KEY_ID",
+    secret_access_key: "ARCHIVE_S3_SECRET_ACCESS_KEY",
+    [github]
+    webhook_secret: "GITHUB_WEBHOOK_SECRET",
+    token: "GITHUB_TOKEN",
+    api_url: "GITHUB_API_URL",
+    [alerts]
+    pagerduty_events_url: "PAGERDUTY_EVENTS_URL",
+    opsgenie_api_url: "OPSGENIE_API_URL",
+    allow_private_webhook_urls: "ALLOW_PRIVATE_WEBHOOK_URLS",
+    [smtp]
+    host: "SMTP_HOST",
+    port: "SMTP_PORT",
+    security: "SMTP_SECURITY",
+    username: "SMTP_USERNAME",
+    password: "SMTP_PASSWORD",
+    from: "SMTP_FROM",
+    [anomalies]
+    detection: "ANOMALY_DETECTION",
+    sigmas: "ANOMALY_SIGMAS",
+    interval_secs: "ANOMALY_INTERVAL_SECS",
+    [tracing]
+    otlp_endpoint: "OTEL_EXPORTER_OTLP_ENDPOINT",
+    otlp_traces_endpoint: "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+    otlp_headers: "OTEL_EXPORTER_OTLP_HEADERS",
+    service_name: "OTEL_SERVICE_NAME",
+    sample_ratio: "OTEL_TRACES_SAMPLER_ARG",
+    [errors]
+    sentry_dsn: "SENTRY_DSN",
+    sentry_environment: "SENTRY_ENVIRONMENT",
+    webhook_url: "ERROR_WEBHOOK_URL",
+}
+
+/// The environment variable a setting stands for.
+pub fn env_name(key: &str) -> Option<&'static str> {
+    SETTINGS.iter().find(|(setting, _)| *setting == key).map(|(_, name)| *name)
+}
+
+/// A setting's name in the file, from the environment variable.
+pub fn key_for(name: &str) -> Option<&'static str> {
+    SETTINGS.iter().find(|(_, env)| *env == name).map(|(key, _)| *key)
+}
+
+/// `isn't a setting`, with the likeliest one meant when there is one.
+pub fn unknown(key: &str) -> String {
+    let last = key.rsplit('.').next().unwrap_or(key);
+    let guess = SETTINGS
+        .iter()
+        .map(|(setting, _)| *setting)
+        .find(|setting| setting.rsplit('.').next() == Some(last));
+    match guess {
+        Some(guess) => format!("`{}` isn't a setting; did you mean `{}`?", key, guess),
+        None => format!("`{}` isn't a setting", key),
+    }
+}
+
+/// Reads a config file into values by the environment variable each
+/// stands for, as that variable would hold them.
+pub fn load(path: &Path) -> Result<BTreeMap<&'static str, String>, String> {
+    let text = std::fs::read_to_string(path).map_err(|err| format!("couldn't read {}: {}", path.display(), err))?;
+    parse(&text).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+fn parse(text: &str) -> Result<BTreeMap<&'static str, String>, String> {
+    let file: File = toml::from_str(text).map_err(|err| err.to_string().trim_end().to_string())?;
+    file.values()
+        .into_iter()
+        .map(|(key, name, value)| Ok((name, text_value(value).map_err(|message| format!("`{}` {}", key, message))?)))
+        .collect()
+}
+
+/// A value as the environment variable would hold it.
+fn text_value(value: Value) -> Result<String, String> {
+    match value {
+        Value::String(text) => Ok(text),
+        Value::Integer(number) => Ok(number.to_string()),
+        Value::Float(number) => Ok(number.to_string()),
+        Value::Boolean(flag) => Ok(flag.to_string()),
+        Value::Array(items) => {
+            let mut values = Vec::with_capacity(items.len());
+            for item in items {
+                if matches!(item, Value::Array(_) | Value::Table(_)) {
+                    return Err("can only hold strings, numbers and booleans".to_string());
+                }
+                values.push(text_value(item)?);
+            }
+            Ok(values.join(","))
+        }
+        Value::Datetime(_) => Err("can't be a date; quote it if it's meant as text".to_string()),
+        Value::Table(_) => Err("is a setting, not a table".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_tables_arrays_and_dotted_keys() {
+        let settings = parse(
+            r#"
+            port = 8080 # a comment
+            disabled_features = ["badges", "bundle-import"]
+            database.url = "sqlite:/var/lib/somnial/somnial.db"
+
+            [retention]
+            days = 90
+
+            [badges]
+            default_window = "7d"
+
+            [tracing]
+            sample_ratio = 0.25
+            "#,
+        )
+        .unwrap();
+        assert_eq!(settings["PORT"], "8080");
+        assert_eq!(settings["DISABLED_FEATURES"], "badges,bundle-import");
+        assert_eq!(settings["DATABASE_URL"], "sqlite:/var/lib/somnial/somnial.db");
+        assert_eq!(settings["RETENTION_DAYS"], "90");
+        assert_eq!(settings["BADGE_DEFAULT_WINDOW"], "7d");
+        assert_eq!(settings["OTEL_TRACES_SAMPLER_ARG"], "0.25");
+        assert_eq!(settings.len(), 6);
+    }
+
+    #[test]
+    fn reads_multi_line_and_literal_strings() {
+        let settings = parse("[auth]\nadmin_token = '''\nsecret'''\n[github]\ntoken = 'a\\b'\n").unwrap();
+        assert_eq!(settings["ADMIN_TOKEN"], "secret");
+        assert_eq!(settings["GITHUB_TOKEN"], "a\\b");
+    }
+
+    #[test]
+    fn rejects_settings_it_doesnt_know() {
+        let err = parse("prot = 8080").unwrap_err();
+        assert!(err.contains("unknown field `prot`"), "{}", err);
+        let err = parse("[retention]\nweeks = 2").unwrap_err();
+        assert!(err.contains("unknown field `weeks`"), "{}", err);
+        assert!(parse("[[retention]]\ndays = 2").is_err());
+    }
+
+    #[test]
+    fn rejects_values_settings_cant_hold() {
+        let err = parse("[retention]\ndays = [[1], [2]]").unwrap_err();
+        assert!(err.starts_with("`retention.days` can only hold"), "{}", err);
+        let err = parse("port = { number = 1 }").unwrap_err();
+        assert!(err.contains("is a setting, not a table"), "{}", err);
+        assert!(parse("port = 1\nport = 2").is_err());
+        assert!(parse("port = \"unclosed").is_err());
+    }
+
+    #[test]
+    fn every_setting_has_one_variable() {
+        for (index, (key, name)) in SETTINGS.iter().enumerate() {
+            assert_eq!(env_name(key), Some(*name));
+            assert!(SETTINGS[index + 1..].iter().all(|(_, other)| other != name), "{} is listed twice", name);
+        }
+    }
+}
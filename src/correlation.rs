@@ -0,0 +1,211 @@
+//! `/api/v1/namespaces/{namespace}/correlation?ids=a,b,c`: how closely
+//! metrics move together, such as binary size and compile time. Points are
+//! rarely recorded at the same instants, so each series is first averaged
+//! into buckets of `step`, and every pair is compared over the buckets both
+//! have a value in.
+//!
+//! `method=pearson`, the default, measures how linear the relationship is;
+//! `spearman` compares ranks instead, so it also catches relationships that
+//! are consistent but curved, and shrugs off outliers.
+
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{ids::NamespacePath, limits::ReadError, parse_span, parse_time_bound, rollup, AppState, MetricPoint};
+
+/// Most series one matrix compares.
+const MAX_SERIES: usize = 20;
+/// Most buckets a window is cut into.
+const MAX_BUCKETS: i64 = 10_000;
+const MIN_STEP_SECONDS: i64 = 60;
+/// Fewer shared buckets than this don't make a correlation.
+const MIN_SAMPLES: usize = 3;
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(json!({ "error": message })))
+}
+
+/// `?ids=binary_size,compile_time&since=30d&step=1h&method=spearman`.
+#[derive(Deserialize)]
+pub struct CorrelationQuery {
+    /// Comma-separated metric ids
+    ids: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+    step: Option<String>,
+    method: Option<Method>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Method {
+    #[default]
+    Pearson,
+    Spearman,
+}
+
+#[derive(Serialize)]
+struct CorrelationResponse {
+    namespace: String,
+    ids: Vec<String>,
+    method: Method,
+    since: i64,
+    until: i64,
+    step: i64,
+    /// `matrix[i][j]` is the correlation between `ids[i]` and `ids[j]`,
+    /// or `null` when they share too few buckets or one of them is flat
+    matrix: Vec<Vec<Option<f64>>>,
+    /// How many buckets each pair was compared over
+    samples: Vec<Vec<usize>>,
+}
+
+/// Each bucket's mean, keyed by the bucket's start.
+fn resample(points: &[MetricPoint], step: i64) -> BTreeMap<i64, f64> {
+    let mut sums: BTreeMap<i64, (f64, usize)> = BTreeMap::new();
+    for point in points.iter().filter(|point| point.value.is_finite()) {
+        let bucket = sums.entry(point.timestamp.div_euclid(step) * step).or_default();
+        bucket.0 += point.value;
+        bucket.1 += 1;
+    }
+    sums.into_iter().map(|(bucket, (sum, count))| (bucket, sum / count as f64)).collect()
+}
+
+/// Ranks from 1, with tied values sharing the mean of their ranks.
+fn ranks(values: &[f64]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|&a, &b| values[a].total_cmp(&values[b]));
+    let mut ranks = vec![0.0; values.len()];
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start + 1;
+        while end < order.len() && values[order[end]] == values[order[start]] {
+            end += 1;
+        }
+        let rank = (start + end + 1) as f64 / 2.0;
+        for &index in &order[start..end] {
+            ranks[index] = rank;
+        }
+        start = end;
+    }
+    ranks
+}
+
+fn pearson(xs: &[f64], ys: &[f64]) -> Option<f64> {
+    let n = xs.len() as f64;
+    let (mean_x, mean_y) = (xs.iter().sum::<f64>() / n, ys.iter().sum::<f64>() / n);
+    let (mut covariance, mut variance_x, mut variance_y) = (0.0, 0.0, 0.0);
+    for (x, y) in xs.iter().zip(ys) {
+        covariance += (x - mean_x) * (y - mean_y);
+        variance_x += (x - mean_x) * (x - mean_x);
+        variance_y += (y - mean_y) * (y - mean_y);
+    }
+    if variance_x == 0.0 || variance_y == 0.0 {
+        return None;
+    }
+    Some((covariance / (variance_x * variance_y).sqrt()).clamp(-1.0, 1.0))
+}
+
+/// The correlation over the buckets both series have, and how many that is.
+fn correlate(a: &BTreeMap<i64, f64>, b: &BTreeMap<i64, f64>, method: Method) -> (Option<f64>, usize) {
+    let (xs, ys): (Vec<f64>, Vec<f64>) = a
+        .iter()
+        .filter_map(|(bucket, &x)| b.get(bucket).map(|&y| (x, y)))
+        .unzip();
+    if xs.len() < MIN_SAMPLES {
+        return (None, xs.len());
+    }
+    let correlation = match method {
+        Method::Pearson => pearson(&xs, &ys),
+        Method::Spearman => pearson(&ranks(&xs), &ranks(&ys)),
+    };
+    (correlation, xs.len())
+}
+
+pub async fn get_correlation(
+    NamespacePath(namespace): NamespacePath,
+    Query(query): Query<CorrelationQuery>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut ids: Vec<String> = Vec::new();
+    for id in query.ids.as_deref().unwrap_or("").split(',').map(str::trim) {
+        let id = state.config().id_policy.normalize(id);
+        if !id.is_empty() && !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+    if ids.len() < 2 || ids.len() > MAX_SERIES {
+        return Err(error(
+            StatusCode::BAD_REQUEST,
+            "give between 2 and 20 comma-separated ids in ?ids=",
+        ));
+    }
+
+    let now = Utc::now().timestamp();
+    let bound = |value: Option<&str>, default: i64| match value {
+        None => Ok(default),
+        Some(value) => parse_time_bound(value, now).ok_or_else(|| {
+            error(
+                StatusCode::BAD_REQUEST,
+                "since and until take a Unix timestamp or a span like 24h or 7d",
+            )
+        }),
+    };
+    let since = bound(query.since.as_deref(), now - 30 * 86400)?;
+    let until = bound(query.until.as_deref(), now)?;
+    if since >= until {
+        return Err(error(StatusCode::BAD_REQUEST, "since must be before until"));
+    }
+    let step = match query.step.as_deref() {
+        None => 3600,
+        Some(step) => parse_span(step)
+            .filter(|&step| step >= MIN_STEP_SECONDS)
+            .ok_or_else(|| error(StatusCode::BAD_REQUEST, "step takes a span of at least a minute, like 1h or 1d"))?,
+    };
+    if (until - since) / step > MAX_BUCKETS {
+        return Err(error(
+            StatusCode::BAD_REQUEST,
+            "that step cuts the window into more than 10000 buckets; use a longer one",
+        ));
+    }
+    let method = query.method.unwrap_or_default();
+
+    let mut series = Vec::with_capacity(ids.len());
+    for id in &ids {
+        let points = rollup::load_points(&state, &namespace, id, since, until)
+            .await
+            .map_err(ReadError::api)?;
+        series.push(resample(&points, step));
+    }
+
+    let mut matrix = vec![vec![None; ids.len()]; ids.len()];
+    let mut samples = vec![vec![0; ids.len()]; ids.len()];
+    for i in 0..ids.len() {
+        for j in i..ids.len() {
+            let (correlation, count) = correlate(&series[i], &series[j], method);
+            (matrix[i][j], matrix[j][i]) = (correlation, correlation);
+            (samples[i][j], samples[j][i]) = (count, count);
+        }
+    }
+
+    Ok(Json(CorrelationResponse {
+        namespace,
+        ids,
+        method,
+        since,
+        until,
+        step,
+        matrix,
+        samples,
+    }))
+}
@@ -0,0 +1,196 @@
+//! `/{namespace}/{id}/daily`: one box plot per day (min, quartiles, median,
+//! max), for metrics sampled many times a day where a raw line hides the
+//! spread. Days start at midnight in the viewer's chosen offset, else UTC.
+//! Browsers get the page; API clients asking for JSON get the figures.
+
+use askama::Template;
+use axum::{
+    extract::{Extension, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+    Json,
+};
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    domains::Domain,
+    errors,
+    ids::SeriesPath,
+    load_window_json, meta,
+    meta::Scale,
+    negotiate,
+    theme::ViewerTheme,
+    traces,
+    tz::{ViewerTz, Zone},
+    AppState, BasePath, ChartPageQuery, MetricPoint, ViewLink,
+};
+
+const DAY: i64 = 86400;
+
+/// The chart page's `?since=`, `?until=`, `?scale=` and `?tz=`.
+#[derive(Deserialize)]
+pub struct DailyQuery {
+    since: Option<String>,
+    until: Option<String>,
+    scale: Option<Scale>,
+    tz: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DayBox {
+    /// `YYYY-MM-DD` in the zone the days were split in
+    pub day: String,
+    /// Unix time of that day's midnight
+    pub start: i64,
+    pub count: usize,
+    pub min: f64,
+    pub p25: f64,
+    pub median: f64,
+    pub p75: f64,
+    pub max: f64,
+}
+
+/// The `q` quantile of sorted `values`, interpolating between neighbours.
+pub fn quantile(values: &[f64], q: f64) -> f64 {
+    let position = q * (values.len() - 1) as f64;
+    let (lower, upper) = (position.floor() as usize, position.ceil() as usize);
+    values[lower] + (values[upper] - values[lower]) * (position - lower as f64)
+}
+
+/// Groups `data` (in time order) into days by `zone`'s calendar and
+/// summarises each.
+pub fn boxes(data: &[MetricPoint], zone: Zone) -> Vec<DayBox> {
+    let mut boxes = Vec::new();
+    let mut values: Vec<f64> = Vec::new();
+    let mut current = None;
+    let mut flush = |day: i64, values: &mut Vec<f64>| {
+        if values.is_empty() {
+            return;
+        }
+        values.sort_by(f64::total_cmp);
+        let start = zone.day_start(day);
+        boxes.push(DayBox {
+            day: DateTime::from_timestamp(day * DAY, 0)
+                .map(|time| time.format("%Y-%m-%d").to_string())
+                .unwrap_or_default(),
+            start,
+            count: values.len(),
+            min: values[0],
+            p25: quantile(values, 0.25),
+            median: quantile(values, 0.5),
+            p75: quantile(values, 0.75),
+            max: values[values.len() - 1],
+        });
+        values.clear();
+    };
+
+    for point in data.iter().filter(|point| point.value.is_finite()) {
+        let day = zone.local_day(point.timestamp);
+        if let Some(previous) = current
+            && previous != day
+        {
+            flush(previous, &mut values);
+        }
+        current = Some(day);
+        values.push(point.value);
+    }
+    if let Some(day) = current {
+        flush(day, &mut values);
+    }
+    boxes
+}
+
+#[derive(Serialize)]
+struct DailyResponse<'a> {
+    namespace: &'a str,
+    id: &'a str,
+    days: &'a [DayBox],
+}
+
+#[derive(Template)]
+#[template(path = "daily.html")]
+struct DailyTemplate {
+    base_path: BasePath,
+    namespace: String,
+    id: String,
+    days_json: String,
+    /// Query string for the line chart over the same window
+    chart_query: String,
+    windows: Vec<ViewLink>,
+    scales: Vec<ViewLink>,
+    /// Y-axis scale, `linear` or `log`
+    scale: &'static str,
+    /// Site title when served from a custom domain
+    brand: Option<String>,
+    theme: &'static str,
+    tz_label: String,
+}
+
+pub async fn get_daily(
+    SeriesPath(namespace, id): SeriesPath,
+    Query(query): Query<DailyQuery>,
+    State(state): State<AppState>,
+    domain: Option<Extension<Domain>>,
+    ViewerTheme(theme): ViewerTheme,
+    tz: ViewerTz,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let view = ChartPageQuery {
+        since: query.since,
+        until: query.until,
+        scale: query.scale,
+        tz: query.tz,
+        ..ChartPageQuery::default()
+    };
+    let bounds = match view.resolve() {
+        Ok(bounds) => bounds,
+        Err(rejection) => return Ok(rejection.into_response()),
+    };
+
+    let data_json = match load_window_json(&state, &namespace, &id, bounds).await {
+        Ok(data_json) => data_json,
+        Err(err) => return err.respond(),
+    };
+    let data: Vec<MetricPoint> = serde_json::from_str(&data_json).map_err(errors::internal)?;
+    let days = boxes(&data, tz.0.unwrap_or(Zone::UTC));
+
+    if negotiate(&headers, &["text/html", "application/json"]) == "application/json" {
+        let mut response = Json(DailyResponse {
+            namespace: &namespace,
+            id: &id,
+            days: &days,
+        })
+        .into_response();
+        response.headers_mut().insert("vary", "accept".parse().unwrap());
+        return Ok(response);
+    }
+
+    let scale = match view.scale {
+        Some(scale) => scale,
+        None => meta::load(&state.pool, &namespace, &id)
+            .await
+            .map_err(errors::internal)?
+            .scale
+            .unwrap_or_default(),
+    };
+    let template = DailyTemplate {
+        base_path: state.base_path.clone(),
+        chart_query: view.href(),
+        windows: view.window_links(),
+        scales: view.scale_links(scale),
+        scale: scale.as_str(),
+        namespace,
+        id,
+        days_json: serde_json::to_string(&days).unwrap_or_default(),
+        brand: domain.map(|d| d.title.clone().unwrap_or_else(|| d.namespace.clone())),
+        theme: theme.as_str(),
+        tz_label: tz.label_or("UTC"),
+    };
+    let mut response = traces::render(&template)
+        .map(Html)
+        .map_err(errors::internal)?
+        .into_response();
+    response.headers_mut().insert("vary", "accept".parse().unwrap());
+    Ok(response)
+}
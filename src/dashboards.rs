@@ -0,0 +1,459 @@
+//! User-defined dashboards: `/d/{slug}` pages pinning charts from any
+//! namespaces, for a "morning glance" view across projects.
+//!
+//! Anyone can create a dashboard; the response carries an edit token, and
+//! only that token (or the admin token) can change or delete it afterwards.
+
+use std::sync::Arc;
+
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse},
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::sqlite::SqlitePool;
+
+use crate::{auth, errors, store::MetricStore, theme::ViewerTheme, traces, AppState, BasePath, MetricPoint};
+
+const MAX_CHARTS: usize = 24;
+const MAX_SLUG_LENGTH: usize = 64;
+const MAX_TITLE_LENGTH: usize = 200;
+const TOKEN_LENGTH: usize = 32;
+/// Each chart on the page shows at most this many recent points.
+const POINTS_PER_CHART: i64 = 500;
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(json!({ "error": message })))
+}
+
+fn database_error<E: std::fmt::Display>(err: E) -> ApiError {
+    crate::errors::report(&err);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PinnedChart {
+    pub namespace: String,
+    pub id: String,
+}
+
+#[derive(Deserialize)]
+pub struct DashboardRequest {
+    /// Chosen when creating; a random one is picked if absent
+    #[serde(default)]
+    slug: Option<String>,
+    title: String,
+    charts: Vec<PinnedChart>,
+}
+
+#[derive(Serialize)]
+pub struct Dashboard {
+    slug: String,
+    title: String,
+    charts: Vec<PinnedChart>,
+    path: String,
+}
+
+/// Only returned to whoever created the dashboard.
+#[derive(Serialize)]
+pub struct CreatedDashboard {
+    #[serde(flatten)]
+    dashboard: Dashboard,
+    edit_token: String,
+}
+
+fn valid_slug(slug: &str) -> bool {
+    !slug.is_empty()
+        && slug.len() <= MAX_SLUG_LENGTH
+        && slug.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Trims and normalizes the request, rejecting anything the page can't show.
+fn validate(state: &AppState, request: DashboardRequest) -> Result<(String, Vec<PinnedChart>), ApiError> {
+    let title = request.title.trim().to_string();
+    if title.is_empty() || title.len() > MAX_TITLE_LENGTH {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "title must be 1 to 200 bytes"));
+    }
+    if request.charts.len() > MAX_CHARTS {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "a dashboard holds at most 24 charts"));
+    }
+    let policy = &state.config().id_policy;
+    let charts = request
+        .charts
+        .into_iter()
+        .map(|chart| PinnedChart {
+            namespace: policy.normalize(&chart.namespace),
+            id: policy.normalize(&chart.id),
+        })
+        .collect::<Vec<_>>();
+    if charts.iter().any(|chart| chart.namespace.is_empty() || chart.id.is_empty()) {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "charts need a namespace and an id"));
+    }
+    Ok((title, charts))
+}
+
+/// Gives a dashboard a new edit token, for an owner who lost theirs;
+/// `None` when there's no such dashboard.
+pub async fn replace_token(state: &AppState, slug: &str) -> Result<Option<String>, sqlx::Error> {
+    let edit_token = auth::random_string(TOKEN_LENGTH);
+    let (pool, token_ref) = (&state.pool, &edit_token);
+    let replaced = state
+        .write(|| async move {
+            sqlx::query!("UPDATE dashboards SET edit_token = ? WHERE slug = ?", token_ref, slug)
+                .execute(pool)
+                .await
+        })
+        .await?
+        .rows_affected();
+    Ok((replaced > 0).then_some(edit_token))
+}
+
+/// The dashboard and its edit token, with its path under `base_path`.
+async fn load(pool: &SqlitePool, base_path: &str, slug: &str) -> Result<Option<(Dashboard, String)>, sqlx::Error> {
+    let Some(row) = sqlx::query!("SELECT title, edit_token FROM dashboards WHERE slug = ?", slug)
+        .fetch_optional(pool)
+        .await?
+    else {
+        return Ok(None);
+    };
+    let charts = sqlx::query!(
+        "SELECT namespace, id FROM dashboard_charts WHERE slug = ? ORDER BY position",
+        slug
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|chart| PinnedChart {
+        namespace: chart.namespace,
+        id: chart.id,
+    })
+    .collect();
+
+    Ok(Some((
+        Dashboard {
+            slug: slug.to_string(),
+            title: row.title,
+            charts,
+            path: format!("{}/d/{}", base_path, slug),
+        },
+        row.edit_token,
+    )))
+}
+
+/// A dashboard whose charts all come from one namespace, so it can move
+/// with that namespace in a bundle.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct NamespaceDashboard {
+    pub slug: String,
+    pub title: String,
+    /// The charts' metric ids, in display order
+    pub ids: Vec<String>,
+}
+
+/// The dashboards pinning charts from `namespace` and no other.
+pub async fn pinning_only(pool: &SqlitePool, namespace: &str) -> Result<Vec<NamespaceDashboard>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT d.slug, d.title, c.id FROM dashboards d JOIN dashboard_charts c ON c.slug = d.slug
+         WHERE NOT EXISTS (SELECT 1 FROM dashboard_charts o WHERE o.slug = d.slug AND o.namespace != ?)
+         ORDER BY d.slug, c.position",
+        namespace
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut dashboards: Vec<NamespaceDashboard> = Vec::new();
+    for row in rows {
+        match dashboards.last_mut() {
+            Some(dashboard) if dashboard.slug == row.slug => dashboard.ids.push(row.id),
+            _ => dashboards.push(NamespaceDashboard {
+                slug: row.slug,
+                title: row.title,
+                ids: vec![row.id],
+            }),
+        }
+    }
+    Ok(dashboards)
+}
+
+/// Creates `dashboard` with its charts in `namespace` and a fresh edit token,
+/// inside the caller's transaction. A dashboard whose slug is taken, or that
+/// the API wouldn't have accepted, is left out; returns whether it was made.
+pub async fn restore(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    namespace: &str,
+    dashboard: &NamespaceDashboard,
+    now: i64,
+) -> Result<bool, sqlx::Error> {
+    let title = dashboard.title.trim();
+    if !valid_slug(&dashboard.slug)
+        || title.is_empty()
+        || title.len() > MAX_TITLE_LENGTH
+        || dashboard.ids.is_empty()
+        || dashboard.ids.len() > MAX_CHARTS
+        || dashboard.ids.iter().any(String::is_empty)
+    {
+        return Ok(false);
+    }
+
+    let edit_token = auth::random_string(TOKEN_LENGTH);
+    let created = sqlx::query!(
+        "INSERT OR IGNORE INTO dashboards (slug, title, edit_token, created_at, updated_at) VALUES (?, ?, ?, ?, ?)",
+        dashboard.slug,
+        title,
+        edit_token,
+        now,
+        now
+    )
+    .execute(&mut **tx)
+    .await?
+    .rows_affected()
+        > 0;
+    if created {
+        let charts: Vec<PinnedChart> = dashboard
+            .ids
+            .iter()
+            .map(|id| PinnedChart {
+                namespace: namespace.to_string(),
+                id: id.clone(),
+            })
+            .collect();
+        write_charts(tx, &dashboard.slug, &charts).await?;
+    }
+    Ok(created)
+}
+
+/// Replaces a dashboard's charts inside the caller's transaction.
+async fn write_charts(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    slug: &str,
+    charts: &[PinnedChart],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM dashboard_charts WHERE slug = ?", slug)
+        .execute(&mut **tx)
+        .await?;
+    for (position, chart) in charts.iter().enumerate() {
+        let position = position as i64;
+        sqlx::query!(
+            "INSERT INTO dashboard_charts (slug, position, namespace, id) VALUES (?, ?, ?, ?)",
+            slug,
+            position,
+            chart.namespace,
+            chart.id
+        )
+        .execute(&mut **tx)
+        .await?;
+    }
+    Ok(())
+}
+
+pub async fn create_dashboard(
+    State(state): State<AppState>,
+    Json(request): Json<DashboardRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let chosen = request.slug.clone();
+    if let Some(slug) = &chosen
+        && !valid_slug(slug)
+    {
+        return Err(error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "slug must be up to 64 letters, digits, - or _",
+        ));
+    }
+    let (title, charts) = validate(&state, request)?;
+    let edit_token = auth::random_string(TOKEN_LENGTH);
+    let now = Utc::now().timestamp();
+
+    // Random slugs are retried on the rare collision; chosen ones report it
+    let mut attempts = 0;
+    let slug = loop {
+        let slug = chosen.clone().unwrap_or_else(|| auth::random_string(8).to_lowercase());
+        let (pool, slug_ref, title, edit_token, charts) = (&state.pool, &slug, &title, &edit_token, &charts);
+        let result = state
+            .write(|| async move {
+                let mut tx = pool.begin().await?;
+                sqlx::query!(
+                    "INSERT INTO dashboards (slug, title, edit_token, created_at, updated_at) VALUES (?, ?, ?, ?, ?)",
+                    slug_ref,
+                    title,
+                    edit_token,
+                    now,
+                    now
+                )
+                .execute(&mut *tx)
+                .await?;
+                write_charts(&mut tx, slug_ref, charts).await?;
+                tx.commit().await
+            })
+            .await;
+
+        match result {
+            Ok(_) => break slug,
+            Err(sqlx::Error::Database(err)) if err.is_unique_violation() => {
+                if chosen.is_some() {
+                    return Err(error(StatusCode::CONFLICT, "a dashboard with that slug already exists"));
+                }
+                attempts += 1;
+                if attempts > 3 {
+                    return Err(database_error(err));
+                }
+            }
+            Err(err) => return Err(database_error(err)),
+        }
+    };
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedDashboard {
+            dashboard: Dashboard {
+                path: format!("{}/d/{}", state.base_path(), slug),
+                slug,
+                title,
+                charts,
+            },
+            edit_token,
+        }),
+    ))
+}
+
+pub async fn get_dashboard(
+    Path(slug): Path<String>,
+    State(pool): State<SqlitePool>,
+    State(base_path): State<BasePath>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (dashboard, _) = load(&pool, &base_path, &slug)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "no such dashboard"))?;
+    Ok(Json(dashboard))
+}
+
+/// Replaces the title and charts; the slug and edit token stay the same.
+pub async fn update_dashboard(
+    Path(slug): Path<String>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<DashboardRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (_, edit_token) = load(&state.pool, state.base_path(), &slug)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "no such dashboard"))?;
+    auth::require_owner_or_admin(&state.config(), &headers, &edit_token)
+        .map_err(|status| error(status, "unauthorized"))?;
+    let (title, charts) = validate(&state, request)?;
+    let now = Utc::now().timestamp();
+
+    let (pool, slug_ref, title_ref, charts_ref) = (&state.pool, &slug, &title, &charts);
+    state
+        .write(|| async move {
+            let mut tx = pool.begin().await?;
+            sqlx::query!(
+                "UPDATE dashboards SET title = ?, updated_at = ? WHERE slug = ?",
+                title_ref,
+                now,
+                slug_ref
+            )
+            .execute(&mut *tx)
+            .await?;
+            write_charts(&mut tx, slug_ref, charts_ref).await?;
+            tx.commit().await
+        })
+        .await
+        .map_err(database_error)?;
+
+    Ok(Json(Dashboard {
+        path: format!("{}/d/{}", state.base_path(), slug),
+        slug,
+        title,
+        charts,
+    }))
+}
+
+pub async fn delete_dashboard(
+    Path(slug): Path<String>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let (_, edit_token) = load(&state.pool, state.base_path(), &slug)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "no such dashboard"))?;
+    auth::require_owner_or_admin(&state.config(), &headers, &edit_token)
+        .map_err(|status| error(status, "unauthorized"))?;
+
+    let (pool, slug) = (&state.pool, &slug);
+    state
+        .write(|| async move {
+            let mut tx = pool.begin().await?;
+            sqlx::query!("DELETE FROM dashboard_charts WHERE slug = ?", slug)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query!("DELETE FROM dashboards WHERE slug = ?", slug)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await
+        })
+        .await
+        .map_err(database_error)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Serialize)]
+struct ChartData {
+    namespace: String,
+    id: String,
+    points: Vec<MetricPoint>,
+}
+
+#[derive(Template)]
+#[template(path = "dashboard.html")]
+struct DashboardTemplate {
+    base_path: BasePath,
+    title: String,
+    charts: Vec<PinnedChart>,
+    charts_json: String,
+    theme: &'static str,
+}
+
+pub async fn get_dashboard_page(
+    Path(slug): Path<String>,
+    State(pool): State<SqlitePool>,
+    State(store): State<Arc<dyn MetricStore>>,
+    State(base_path): State<BasePath>,
+    ViewerTheme(theme): ViewerTheme,
+) -> Result<impl IntoResponse, StatusCode> {
+    let (dashboard, _) = load(&pool, &base_path, &slug)
+        .await
+        .map_err(errors::internal)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let mut data = Vec::with_capacity(dashboard.charts.len());
+    for chart in &dashboard.charts {
+        data.push(ChartData {
+            namespace: chart.namespace.clone(),
+            id: chart.id.clone(),
+            points: store
+                .recent(&chart.namespace, &chart.id, i64::MAX, POINTS_PER_CHART)
+                .await
+                .map_err(errors::internal)?,
+        });
+    }
+
+    let template = DashboardTemplate {
+        base_path,
+        title: dashboard.title,
+        charts: dashboard.charts,
+        // Names are user-supplied, so keep them from closing the script element
+        charts_json: serde_json::to_string(&data).unwrap_or_default().replace('<', "\\u003c"),
+        theme: theme.as_str(),
+    };
+    traces::render(&template).map(Html).map_err(errors::internal)
+}
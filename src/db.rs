@@ -0,0 +1,90 @@
+use std::future::Future;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use sqlx::sqlite::{SqliteJournalMode, SqliteSynchronous};
+
+use crate::stats::SelfMetrics;
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_BACKOFF: Duration = Duration::from_millis(250);
+
+/// How long a write keeps retrying while SQLite reports the database as busy.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub budget: Duration,
+}
+
+/// Pragmas every pooled connection opens with.
+#[derive(Clone, Debug)]
+pub struct SqliteTuning {
+    /// WAL lets badge and chart reads carry on while a write is in progress
+    pub journal_mode: SqliteJournalMode,
+    /// `NORMAL` is safe under WAL and skips an fsync on every commit
+    pub synchronous: SqliteSynchronous,
+    /// How long a statement waits on a locked database before failing as busy
+    pub busy_timeout: Duration,
+}
+
+impl Default for SqliteTuning {
+    fn default() -> Self {
+        SqliteTuning {
+            journal_mode: SqliteJournalMode::Wal,
+            synchronous: SqliteSynchronous::Normal,
+            busy_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// True for `SQLITE_BUSY` / `SQLITE_LOCKED` and their extended variants, which
+/// mean another connection or process briefly holds the lock.
+pub fn is_busy(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Database(db_err) => db_err
+            .code()
+            .and_then(|code| code.parse::<i32>().ok())
+            .is_some_and(|code| matches!(code & 0xff, 5 | 6)),
+        _ => false,
+    }
+}
+
+/// Errors that can say whether the database was only busy.
+pub trait Retryable {
+    fn is_busy(&self) -> bool;
+}
+
+impl Retryable for sqlx::Error {
+    fn is_busy(&self) -> bool {
+        is_busy(self)
+    }
+}
+
+/// Runs a write, retrying with exponential backoff while the database is busy.
+/// The budget starts counting at the first busy error (each attempt may itself
+/// block for the connection's busy timeout); once it is spent the last error
+/// is returned.
+pub async fn retry_busy<T, E, F, Fut>(policy: &RetryPolicy, metrics: &SelfMetrics, mut op: F) -> Result<T, E>
+where
+    E: Retryable,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut first_busy: Option<Instant> = None;
+    let mut backoff = INITIAL_BACKOFF;
+
+    loop {
+        match op().await {
+            Err(err) if err.is_busy() => {
+                let since = *first_busy.get_or_insert_with(Instant::now);
+                if since.elapsed() + backoff > policy.budget {
+                    metrics.db_busy_failures.fetch_add(1, Ordering::Relaxed);
+                    return Err(err);
+                }
+                metrics.db_busy_retries.fetch_add(1, Ordering::Relaxed);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            result => return result,
+        }
+    }
+}
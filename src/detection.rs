@@ -0,0 +1,331 @@
+//! Background anomaly detection. With `ANOMALY_DETECTION` set to a model,
+//! a job goes over every series' new points every few minutes and records
+//! the ones far off the series' baseline: the points just before them for
+//! `zscore`, or the same time of day or week for `daily` and `weekly`,
+//! which suit metrics with a regular rhythm such as traffic.
+//!
+//! Recorded anomalies are listed at `/api/v1/namespaces/{namespace}/anomalies`
+//! and drawn on chart pages, and `anomaly` alert rules pass them on to an
+//! alert channel. A series is only ever scanned back a day, so the first
+//! scan, or one after downtime, doesn't flag its whole history, and points
+//! backfilled behind what's been scanned aren't looked at. Retention takes
+//! anomalies with the points they were found at.
+
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::sqlite::SqlitePool;
+use tokio::time::{self, MissedTickBehavior};
+
+use crate::{
+    alerts,
+    anomaly::{self, Anomaly, Model},
+    ids::NamespacePath,
+    store::{ListFrom, StoreError},
+    AppState, Bounds, MetricPoint,
+};
+
+/// How far back a scan can start from a series' latest point.
+const MAX_SCAN_SECONDS: i64 = 86400;
+/// Charts draw at most this many anomalies.
+const MAX_CHART_ANOMALIES: i64 = 500;
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+/// Which model the background job holds series against, and how strictly.
+#[derive(Clone, Debug)]
+pub struct AnomalyPolicy {
+    pub model: Model,
+    /// Standard deviations from the baseline a point has to be to count
+    pub sigmas: f64,
+    /// How often new points are scanned
+    pub interval: Duration,
+}
+
+impl AnomalyPolicy {
+    pub fn new(model: Model) -> Self {
+        AnomalyPolicy {
+            model,
+            sigmas: 3.0,
+            interval: Duration::from_secs(300),
+        }
+    }
+}
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(json!({ "error": message })))
+}
+
+fn database_error<E: std::fmt::Display>(err: E) -> ApiError {
+    crate::errors::report(&err);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+/// Scans for anomalies on the configured schedule, for as long as the
+/// server runs.
+pub fn spawn(state: AppState) {
+    let Some(policy) = state.config().anomaly_detection.clone() else {
+        return;
+    };
+    tokio::spawn(async move {
+        let mut ticks = time::interval(policy.interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            match scan(&state, &policy).await {
+                Ok(0) => {}
+                Ok(found) => tracing::info!("Detected {} anomalies", found),
+                Err(err) => tracing::warn!("Anomaly detection failed: {}", err),
+            }
+        }
+    });
+}
+
+/// Runs every series' new points past the model and returns how many
+/// anomalies it found.
+async fn scan(state: &AppState, policy: &AnomalyPolicy) -> Result<usize, StoreError> {
+    let scanned: HashMap<(String, String), i64> =
+        sqlx::query!("SELECT namespace, metric, scanned_through FROM anomaly_scans")
+            .fetch_all(&state.pool)
+            .await?
+            .into_iter()
+            .map(|row| ((row.namespace, row.metric), row.scanned_through))
+            .collect();
+
+    let mut found = 0;
+    for namespace in state.store.namespaces().await? {
+        for metric in state.store.list(&namespace, ListFrom::Start, i64::MAX).await? {
+            let Some(latest) = metric.last_timestamp else { continue };
+            let scanned_through = scanned.get(&(namespace.clone(), metric.id.clone())).copied();
+            if scanned_through.is_some_and(|through| through >= latest) {
+                continue;
+            }
+            let after = scanned_through.unwrap_or(i64::MIN).max(latest - MAX_SCAN_SECONDS);
+            let points = load_points(state, policy.model, &namespace, &metric.id, after, latest).await?;
+            let anomalies = anomaly::detect_after(policy.model, &points, after, policy.sigmas);
+            save(state, policy.model, &namespace, &metric.id, &anomalies, latest).await?;
+            found += anomalies.len();
+            state.metrics.anomalies_detected.fetch_add(anomalies.len() as u64, Ordering::Relaxed);
+            if let Some(latest) = points.last() {
+                alerts::detected(state, &namespace, &metric.id, &anomalies, latest).await;
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// The points after `after` along with the history the model needs
+/// before them, oldest first.
+async fn load_points(
+    state: &AppState,
+    model: Model,
+    namespace: &str,
+    id: &str,
+    after: i64,
+    until: i64,
+) -> Result<Vec<MetricPoint>, StoreError> {
+    if let Some(lookback) = model.lookback() {
+        return state.store.range(namespace, id, after.saturating_sub(lookback), until, i64::MAX).await;
+    }
+    let limit = anomaly::BASELINE_POINTS as i64;
+    let mut points = state.store.recent(namespace, id, after, limit).await?;
+    // The scan reads what's new since the last one, however much that is
+    points.extend(state.store.range(namespace, id, after.saturating_add(1), until, i64::MAX).await?);
+    Ok(points)
+}
+
+/// Records a series' anomalies and how far it's been scanned, together.
+async fn save(
+    state: &AppState,
+    model: Model,
+    namespace: &str,
+    id: &str,
+    anomalies: &[Anomaly],
+    scanned_through: i64,
+) -> Result<(), sqlx::Error> {
+    let pool = &state.pool;
+    let now = Utc::now().timestamp();
+    let model = model.as_str();
+    state
+        .write(|| async move {
+            let mut tx = pool.begin().await?;
+            for anomaly in anomalies {
+                sqlx::query!(
+                    "INSERT OR IGNORE INTO anomalies
+                         (namespace, metric, timestamp, value, baseline, deviations, model, detected_at)
+                     VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+                    namespace,
+                    id,
+                    anomaly.timestamp,
+                    anomaly.value,
+                    anomaly.baseline,
+                    anomaly.deviations,
+                    model,
+                    now
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            sqlx::query!(
+                "INSERT INTO anomaly_scans (namespace, metric, scanned_through) VALUES (?, ?, ?)
+                 ON CONFLICT (namespace, metric) DO UPDATE SET scanned_through = excluded.scanned_through",
+                namespace,
+                id,
+                scanned_through
+            )
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await
+        })
+        .await
+}
+
+/// Forgets anomalies whose point is gone, which retention, deletes and
+/// merges all do, and the scan progress of series that are gone entirely.
+/// Returns how many anomalies were removed.
+pub async fn prune(state: &AppState) -> Result<u64, StoreError> {
+    let pool = &state.pool;
+    let recorded = sqlx::query!("SELECT namespace, metric, timestamp FROM anomalies ORDER BY namespace, metric")
+        .fetch_all(pool)
+        .await?;
+    let mut removed = 0;
+    for row in &recorded {
+        let (namespace, id, timestamp) = (&row.namespace, &row.metric, row.timestamp);
+        if !state.store.range(namespace, id, timestamp, timestamp, 1).await?.is_empty() {
+            continue;
+        }
+        removed += state
+            .write(|| async move {
+                sqlx::query!(
+                    "DELETE FROM anomalies WHERE namespace = ? AND metric = ? AND timestamp = ?",
+                    namespace,
+                    id,
+                    timestamp
+                )
+                .execute(pool)
+                .await
+            })
+            .await?
+            .rows_affected();
+    }
+
+    let scans = sqlx::query!("SELECT namespace, metric FROM anomaly_scans").fetch_all(pool).await?;
+    for row in &scans {
+        let (namespace, id) = (&row.namespace, &row.metric);
+        if state.store.latest(namespace, id).await?.is_some() {
+            continue;
+        }
+        state
+            .write(|| async move {
+                sqlx::query!("DELETE FROM anomaly_scans WHERE namespace = ? AND metric = ?", namespace, id)
+                    .execute(pool)
+                    .await
+            })
+            .await?;
+    }
+    Ok(removed)
+}
+
+/// A series' recorded anomalies in the window, for its chart.
+pub async fn load(
+    pool: &SqlitePool,
+    namespace: &str,
+    id: &str,
+    (since, until): Bounds,
+) -> Result<Vec<Anomaly>, sqlx::Error> {
+    let (since, until) = (since.unwrap_or(i64::MIN), until.unwrap_or(i64::MAX));
+    let mut anomalies: Vec<Anomaly> = sqlx::query!(
+        "SELECT timestamp, value, baseline, deviations FROM anomalies
+         WHERE namespace = ? AND metric = ? AND timestamp BETWEEN ? AND ?
+         ORDER BY timestamp DESC LIMIT ?",
+        namespace,
+        id,
+        since,
+        until,
+        MAX_CHART_ANOMALIES
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| Anomaly {
+        timestamp: row.timestamp,
+        value: row.value,
+        baseline: row.baseline,
+        deviations: row.deviations,
+    })
+    .collect();
+    anomalies.reverse();
+    Ok(anomalies)
+}
+
+/// `?metric=latency&since=1700000000&limit=50`, all optional.
+#[derive(Deserialize)]
+pub struct AnomalyQuery {
+    metric: Option<String>,
+    since: Option<i64>,
+    until: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct RecordedAnomaly {
+    metric: String,
+    timestamp: i64,
+    value: f64,
+    baseline: f64,
+    deviations: f64,
+    model: String,
+    detected_at: i64,
+}
+
+/// A namespace's recorded anomalies, newest first.
+pub async fn list_anomalies(
+    NamespacePath(namespace): NamespacePath,
+    Query(query): Query<AnomalyQuery>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, ApiError> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(error(StatusCode::BAD_REQUEST, "limit must be between 1 and 1000"));
+    }
+    let metric = query.metric.map(|metric| state.config().id_policy.normalize(&metric));
+    let (since, until) = (query.since.unwrap_or(i64::MIN), query.until.unwrap_or(i64::MAX));
+    let anomalies: Vec<RecordedAnomaly> = sqlx::query!(
+        r#"SELECT metric, timestamp, value, baseline, deviations, model, detected_at FROM anomalies
+           WHERE namespace = ? AND (? IS NULL OR metric = ?) AND timestamp BETWEEN ? AND ?
+           ORDER BY timestamp DESC, metric LIMIT ?"#,
+        namespace,
+        metric,
+        metric,
+        since,
+        until,
+        limit
+    )
+    .fetch_all(&state.pool)
+    .await
+    .map_err(database_error)?
+    .into_iter()
+    .map(|row| RecordedAnomaly {
+        metric: row.metric,
+        timestamp: row.timestamp,
+        value: row.value,
+        baseline: row.baseline,
+        deviations: row.deviations,
+        model: row.model,
+        detected_at: row.detected_at,
+    })
+    .collect();
+    Ok(Json(json!({ "anomalies": anomalies })))
+}
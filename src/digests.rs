@@ -0,0 +1,519 @@
+//! Digests: a daily or weekly summary of a namespace sent to a webhook,
+//! Slack, Discord or an email address, for keeping an eye on it without
+//! visiting. Each says how many points came in, which metrics moved most
+//! between the end of the previous period and the end of this one, and
+//! which alert rules fired.
+//!
+//! Periods are UTC days and ISO weeks, starting at midnight and on Mondays,
+//! and a digest goes out shortly after each one ends, starting with the one
+//! it was made in. If the server was down when one was due, only the latest
+//! period is sent. Delivery is best effort, like alert notifications;
+//! `POST .../digests/{digest}/send` sends one covering the last day or week
+//! straight away, to try a destination out. Only the admin token may
+//! manage or send digests, since they go wherever they say.
+
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::time::{self, MissedTickBehavior};
+
+use crate::{
+    alerts, auth, client,
+    ids::NamespacePath,
+    mail, meta,
+    notifiers::{self, Channel},
+    store::StoreError,
+    AppState,
+};
+
+const MAX_DIGESTS_PER_NAMESPACE: usize = 10;
+/// How often digests are checked for being due
+const TICK: Duration = Duration::from_secs(60);
+const DAY: i64 = 86400;
+/// Metrics listed as the biggest movers
+const MOVERS: usize = 5;
+/// Fired alerts listed, newest last
+const MAX_ALERTS: i64 = 50;
+/// Discord refuses longer messages
+const DISCORD_LIMIT: usize = 2000;
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(json!({ "error": message })))
+}
+
+fn database_error<E: std::fmt::Display>(err: E) -> ApiError {
+    crate::errors::report(&err);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Frequency {
+    Daily,
+    Weekly,
+}
+
+impl Frequency {
+    fn as_str(self) -> &'static str {
+        match self {
+            Frequency::Daily => "daily",
+            Frequency::Weekly => "weekly",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "daily" => Some(Frequency::Daily),
+            "weekly" => Some(Frequency::Weekly),
+            _ => None,
+        }
+    }
+
+    fn length(self) -> i64 {
+        match self {
+            Frequency::Daily => DAY,
+            Frequency::Weekly => 7 * DAY,
+        }
+    }
+
+    /// When the period `now` falls in began. The Unix epoch was a Thursday,
+    /// so weeks are counted from the Monday after it.
+    fn period_start(self, now: i64) -> i64 {
+        match self {
+            Frequency::Daily => now - now.rem_euclid(DAY),
+            Frequency::Weekly => now - (now - 4 * DAY).rem_euclid(7 * DAY),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Digest {
+    id: i64,
+    frequency: Frequency,
+    channel: Channel,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    webhook_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email: Option<String>,
+    /// The end of the last period sent
+    sent_through: i64,
+    created_at: i64,
+}
+
+/// `{"frequency": "weekly", "channel": "slack", "webhook_url": "https://hooks.slack.com/..."}`,
+/// or `"channel": "email"` with `"email": "me@example.com"` instead of a URL
+#[derive(Deserialize)]
+pub struct DigestRequest {
+    frequency: Frequency,
+    #[serde(default)]
+    channel: Channel,
+    webhook_url: Option<String>,
+    email: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Mover {
+    id: String,
+    points: i64,
+    /// The value at the end of the previous period, or the period's first
+    from: f64,
+    to: f64,
+    change: f64,
+    change_percent: Option<f64>,
+    /// `latency: 10 s -> 12 s (+20.0%)`, for the text
+    #[serde(skip)]
+    line: String,
+}
+
+#[derive(Serialize)]
+struct FiredAlert {
+    rule: i64,
+    metric: String,
+    value: f64,
+    timestamp: i64,
+    text: String,
+    #[serde(skip)]
+    time: String,
+}
+
+/// What a digest says about a namespace over `since..until`.
+struct Report {
+    namespace: String,
+    since: i64,
+    until: i64,
+    points: i64,
+    /// Metrics with at least one new point
+    metrics: usize,
+    movers: Vec<Mover>,
+    alerts: Vec<FiredAlert>,
+}
+
+async fn report(state: &AppState, namespace: &str, since: i64, until: i64) -> Result<Report, StoreError> {
+    let pool = &state.pool;
+    let (mut points, mut metrics, mut movers) = (0, 0, Vec::new());
+    for id in state.store.ids(namespace).await? {
+        let Some(aggregate) = state.store.aggregate(namespace, &id, since, until - 1).await? else { continue };
+        points += aggregate.count;
+        metrics += 1;
+        // The change is measured from the last point before the digest's
+        // period, or the first in it for a series that started during it
+        let before = state.store.recent(namespace, &id, since - 1, 1).await?;
+        let from = match before.first() {
+            Some(point) => point.value,
+            None => match state.store.range(namespace, &id, since, until - 1, 1).await?.first() {
+                Some(point) => point.value,
+                None => continue,
+            },
+        };
+        let to = aggregate.latest;
+        let change = to - from;
+        if change != 0.0 && change.is_finite() {
+            movers.push(Mover {
+                id,
+                points: aggregate.count,
+                from,
+                to,
+                change,
+                change_percent: (from != 0.0).then(|| change / from.abs() * 100.0),
+                line: String::new(),
+            });
+        }
+    }
+    // Relative changes compare across units; those from zero have none and go last
+    movers.sort_by(|a, b| {
+        let key = |mover: &Mover| mover.change_percent.map(f64::abs).unwrap_or(-1.0);
+        key(b).total_cmp(&key(a))
+    });
+    movers.truncate(MOVERS);
+    for mover in &mut movers {
+        let meta = meta::load(pool, namespace, &mover.id).await?;
+        let percent = mover
+            .change_percent
+            .map(|percent| format!(" ({}{:.1}%)", if percent < 0.0 { "" } else { "+" }, percent))
+            .unwrap_or_default();
+        mover.line = format!("{}: {} -> {}{}", mover.id, meta.format(mover.from), meta.format(mover.to), percent);
+    }
+
+    let alerts = sqlx::query!(
+        r#"SELECT rule_id, metric, value, summary, at FROM alert_events
+           WHERE namespace = ? AND firing = 1 AND at >= ? AND at < ?
+           ORDER BY at DESC LIMIT ?"#,
+        namespace,
+        since,
+        until,
+        MAX_ALERTS
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .rev()
+    .map(|row| FiredAlert {
+        rule: row.rule_id,
+        metric: row.metric,
+        value: row.value,
+        timestamp: row.at,
+        text: row.summary,
+        time: notifiers::utc_time(row.at),
+    })
+    .collect();
+
+    Ok(Report {
+        namespace: namespace.to_string(),
+        since,
+        until,
+        points,
+        metrics,
+        movers,
+        alerts,
+    })
+}
+
+#[derive(Template)]
+#[template(path = "digest.txt")]
+struct DigestTemplate<'a> {
+    title: String,
+    since: String,
+    until: String,
+    report: &'a Report,
+    url: Option<String>,
+    digest_id: i64,
+}
+
+fn title(frequency: Frequency, namespace: &str) -> String {
+    let frequency = match frequency {
+        Frequency::Daily => "Daily",
+        Frequency::Weekly => "Weekly",
+    };
+    format!("{} digest for {}", frequency, namespace)
+}
+
+async fn send(state: &AppState, digest: &Digest, namespace: &str, since: i64, until: i64) -> Result<(), client::ClientError> {
+    let report = report(state, namespace, since, until).await?;
+    let url = state
+        .config()
+        .public_url
+        .as_deref()
+        .map(|url| notifiers::namespace_url(url, namespace));
+    let title = title(digest.frequency, namespace);
+    let text = DigestTemplate {
+        title: title.clone(),
+        since: notifiers::utc_time(since),
+        until: notifiers::utc_time(until),
+        report: &report,
+        url: url.clone(),
+        digest_id: digest.id,
+    }
+    .render()?;
+
+    let payload = match digest.channel {
+        Channel::Email => {
+            let config = state.config();
+            let smtp = config.smtp.as_ref().ok_or("SMTP isn't configured")?;
+            let to = digest.email.as_deref().ok_or("digest has no email address")?;
+            return mail::send(smtp, to, &format!("[somnial] {}", title), &text).await;
+        }
+        Channel::Webhook => json!({
+            "namespace": report.namespace,
+            "digest": digest.id,
+            "frequency": digest.frequency,
+            "since": report.since,
+            "until": report.until,
+            "points": report.points,
+            "metrics": report.metrics,
+            "movers": report.movers,
+            "alerts": report.alerts,
+            "url": url,
+            "text": text,
+        }),
+        Channel::Slack => json!({ "text": notifiers::slack_escape(&text) }),
+        Channel::Discord => {
+            let content: String = notifiers::discord_escape(&text).chars().take(DISCORD_LIMIT).collect();
+            json!({ "content": content })
+        }
+        Channel::PagerDuty | Channel::Opsgenie => return Err("digests can't be sent to incident channels".into()),
+    };
+    let webhook_url = digest.webhook_url.as_deref().ok_or("digest has no webhook URL")?;
+    let allow_private = state.config().allow_private_webhook_urls;
+    client::post_webhook(webhook_url, &serde_json::to_vec(&payload)?, allow_private).await
+}
+
+/// Sends a digest in the background, counting how that went.
+fn dispatch(state: &AppState, digest: Digest, namespace: String, since: i64, until: i64) {
+    let state = state.clone();
+    tokio::spawn(async move {
+        let counter = match send(&state, &digest, &namespace, since, until).await {
+            Ok(()) => &state.metrics.digest_deliveries,
+            Err(err) => {
+                tracing::warn!("Sending digest {} of namespace {} failed: {}", digest.id, namespace, err);
+                &state.metrics.digest_delivery_failures
+            }
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    });
+}
+
+async fn load(state: &AppState, namespace: Option<&str>) -> Result<Vec<(String, Digest)>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"SELECT digest_id as "digest_id!", namespace, frequency, channel, webhook_url, email, sent_through, created_at
+           FROM namespace_digests WHERE ?1 IS NULL OR namespace = ?1 ORDER BY digest_id"#,
+        namespace
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            let digest = Digest {
+                id: row.digest_id,
+                frequency: Frequency::parse(&row.frequency)?,
+                channel: Channel::parse(&row.channel)?,
+                webhook_url: row.webhook_url,
+                email: row.email,
+                sent_through: row.sent_through,
+                created_at: row.created_at,
+            };
+            Some((row.namespace, digest))
+        })
+        .collect())
+}
+
+/// Sends the digests whose period has ended, marking them sent first so a
+/// failing one isn't tried again every tick.
+async fn send_due(state: &AppState) -> Result<(), sqlx::Error> {
+    let now = Utc::now().timestamp();
+    for (namespace, digest) in load(state, None).await? {
+        let until = digest.frequency.period_start(now);
+        if digest.sent_through >= until {
+            continue;
+        }
+        let (pool, digest_id) = (&state.pool, digest.id);
+        state
+            .write(|| async move {
+                sqlx::query!(
+                    "UPDATE namespace_digests SET sent_through = ? WHERE digest_id = ?",
+                    until,
+                    digest_id
+                )
+                .execute(pool)
+                .await
+            })
+            .await?;
+        let since = until - digest.frequency.length();
+        dispatch(state, digest, namespace, since, until);
+    }
+    Ok(())
+}
+
+/// Sends digests as their periods end, for as long as the server runs.
+pub fn spawn(state: AppState) {
+    if !state.config().features.digests {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut ticks = time::interval(TICK);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            if let Err(err) = send_due(&state).await {
+                tracing::warn!("Sending digests failed: {}", err);
+            }
+        }
+    });
+}
+
+/// Webhook URLs often carry a secret, and email addresses are personal, so
+/// only the admin token sees them.
+pub async fn list_digests(
+    NamespacePath(namespace): NamespacePath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+    let digests: Vec<Digest> = load(&state, Some(&namespace))
+        .await
+        .map_err(database_error)?
+        .into_iter()
+        .map(|(_, digest)| digest)
+        .collect();
+    Ok(Json(json!({ "digests": digests })))
+}
+
+pub async fn create_digest(
+    NamespacePath(namespace): NamespacePath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<DigestRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+    if request.channel.is_incident() {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "digests can't be sent to incident channels"));
+    }
+    let destination = alerts::Destination {
+        webhook_url: request.webhook_url.as_deref(),
+        email: request.email.as_deref(),
+        ..Default::default()
+    };
+    alerts::check_destination(&state, request.channel, destination).await?;
+    let existing = load(&state, Some(&namespace)).await.map_err(database_error)?;
+    if existing.len() >= MAX_DIGESTS_PER_NAMESPACE {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "a namespace can have at most 10 digests"));
+    }
+
+    let now = Utc::now().timestamp();
+    let sent_through = request.frequency.period_start(now);
+    let (pool, namespace_ref) = (&state.pool, &namespace);
+    let (frequency, channel) = (request.frequency.as_str(), request.channel.as_str());
+    let (url, email) = (&request.webhook_url, &request.email);
+    let id = state
+        .write(|| async move {
+            sqlx::query_scalar!(
+                r#"INSERT INTO namespace_digests (namespace, frequency, channel, webhook_url, email, sent_through, created_at)
+                   VALUES (?, ?, ?, ?, ?, ?, ?)
+                   RETURNING digest_id as "digest_id!""#,
+                namespace_ref,
+                frequency,
+                channel,
+                url,
+                email,
+                sent_through,
+                now
+            )
+            .fetch_one(pool)
+            .await
+        })
+        .await
+        .map_err(database_error)?;
+
+    let digest = Digest {
+        id,
+        frequency: request.frequency,
+        channel: request.channel,
+        webhook_url: request.webhook_url,
+        email: request.email,
+        sent_through,
+        created_at: now,
+    };
+    Ok((StatusCode::CREATED, Json(digest)))
+}
+
+/// Sends a digest covering the last day or week now, without moving its
+/// schedule.
+pub async fn send_digest(
+    Path((namespace, digest_id)): Path<(String, i64)>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let namespace = state.config().id_policy.normalize(&namespace);
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+    let digest = load(&state, Some(&namespace))
+        .await
+        .map_err(database_error)?
+        .into_iter()
+        .map(|(_, digest)| digest)
+        .find(|digest| digest.id == digest_id)
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "no digest with that id"))?;
+    // Periods end exclusively, so this one runs to the end of this second
+    let until = Utc::now().timestamp() + 1;
+    let since = until - digest.frequency.length();
+    dispatch(&state, digest, namespace, since, until);
+    Ok(StatusCode::ACCEPTED)
+}
+
+pub async fn delete_digest(
+    Path((namespace, digest_id)): Path<(String, i64)>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let namespace = state.config().id_policy.normalize(&namespace);
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+
+    let (pool, namespace_ref) = (&state.pool, &namespace);
+    let result = state
+        .write(|| async move {
+            sqlx::query!(
+                "DELETE FROM namespace_digests WHERE namespace = ? AND digest_id = ?",
+                namespace_ref,
+                digest_id
+            )
+            .execute(pool)
+            .await
+        })
+        .await
+        .map_err(database_error)?;
+    if result.rows_affected() == 0 {
+        return Err(error(StatusCode::NOT_FOUND, "no digest with that id"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,188 @@
+//! Custom hostnames that serve one namespace as their own site, so
+//! `stats.myproject.dev` shows that namespace's dashboard at its root.
+//!
+//! Mappings live in the database and are mirrored in memory, since every
+//! request has to be checked against them.
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use axum::{
+    extract::{Path, Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+
+use crate::{auth, errors, theme::PageTheme, AppState};
+
+/// How a custom host is served; handlers find it in the request extensions.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Domain {
+    pub namespace: String,
+    /// Shown in place of the namespace name
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub theme: PageTheme,
+}
+
+#[derive(Default)]
+pub struct DomainMap {
+    hosts: RwLock<HashMap<String, Domain>>,
+}
+
+/// Lowercases a `Host` value and drops any port and trailing dot.
+fn normalize_host(host: &str) -> String {
+    let host = host.trim();
+    let name = match host.strip_prefix('[').and_then(|rest| rest.find(']')) {
+        Some(end) => &host[..end + 2],
+        None => host.split(':').next().unwrap_or(host),
+    };
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+impl DomainMap {
+    pub async fn load(pool: &SqlitePool) -> Result<Self, sqlx::Error> {
+        let map = DomainMap::default();
+        map.reload(pool).await?;
+        Ok(map)
+    }
+
+    pub async fn reload(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        let rows = sqlx::query!("SELECT host, namespace, title, theme FROM custom_domains")
+            .fetch_all(pool)
+            .await?;
+
+        let hosts = rows
+            .into_iter()
+            .map(|row| {
+                (
+                    row.host,
+                    Domain {
+                        namespace: row.namespace,
+                        title: row.title,
+                        theme: PageTheme::parse(&row.theme).unwrap_or_default(),
+                    },
+                )
+            })
+            .collect();
+        *self.hosts.write().unwrap() = hosts;
+        Ok(())
+    }
+
+    pub fn get(&self, host: &str) -> Option<Domain> {
+        self.hosts.read().unwrap().get(&normalize_host(host)).cloned()
+    }
+
+    fn list(&self) -> HashMap<String, Domain> {
+        self.hosts.read().unwrap().clone()
+    }
+}
+
+/// On a custom host only its own namespace is reachable, plus the few global
+/// paths its pages link to.
+fn allowed_on(domain: &Domain, path: &str, state: &AppState) -> bool {
+    let segment = path.trim_start_matches('/').split('/').next().unwrap_or("");
+    let segment = percent_decode_str(segment).decode_utf8_lossy();
+    matches!(segment.as_ref(), "" | "favicon.svg" | "s" | "preferences" | "healthz" | "readyz")
+        || state.config().id_policy.normalize(&segment) == domain.namespace
+}
+
+/// Tags requests for a mapped host with its [`Domain`], and hides every other
+/// namespace from it.
+pub async fn resolve(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+    let domain = request
+        .headers()
+        .get("host")
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| state.domains.get(host));
+
+    if let Some(domain) = domain {
+        if !allowed_on(&domain, request.uri().path(), &state) {
+            return StatusCode::NOT_FOUND.into_response();
+        }
+        request.extensions_mut().insert(domain);
+    }
+    next.run(request).await
+}
+
+pub async fn list_domains(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&state.config(), &headers)?;
+    Ok(Json(state.domains.list()))
+}
+
+pub async fn put_domain(
+    Path(host): Path<String>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(domain): Json<Domain>,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&state.config(), &headers)?;
+    let host = normalize_host(&host);
+    let namespace = state.config().id_policy.normalize(&domain.namespace);
+    if host.is_empty() || namespace.is_empty() {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    let theme = domain.theme.as_str();
+    let (pool, host_ref, namespace_ref, title) = (&state.pool, &host, &namespace, &domain.title);
+    state
+        .write(|| async move {
+            sqlx::query!(
+                "INSERT INTO custom_domains (host, namespace, title, theme) VALUES (?, ?, ?, ?)
+                 ON CONFLICT (host) DO UPDATE SET namespace = excluded.namespace, title = excluded.title, theme = excluded.theme",
+                host_ref,
+                namespace_ref,
+                title,
+                theme
+            )
+            .execute(pool)
+            .await
+        })
+        .await
+        .map_err(errors::internal)?;
+
+    state
+        .domains
+        .reload(&state.pool)
+        .await
+        .map_err(errors::internal)?;
+    Ok(Json(Domain { namespace, ..domain }))
+}
+
+pub async fn delete_domain(
+    Path(host): Path<String>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&state.config(), &headers)?;
+    let host = normalize_host(&host);
+
+    let (pool, host) = (&state.pool, &host);
+    let result = state
+        .write(|| async move {
+            sqlx::query!("DELETE FROM custom_domains WHERE host = ?", host)
+                .execute(pool)
+                .await
+        })
+        .await
+        .map_err(errors::internal)?;
+
+    state
+        .domains
+        .reload(&state.pool)
+        .await
+        .map_err(errors::internal)?;
+    if result.rows_affected() == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,167 @@
+//! Charts for other sites: a chrome-free `/embed/{namespace}/{id}` page for
+//! iframes, and the oEmbed endpoint that lets Notion, Ghost and friends turn
+//! a pasted chart link into that iframe.
+
+use askama::Template;
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+    Json,
+};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    errors,
+    ids::SeriesPath,
+    load_series_json, request_origin,
+    theme::{PageTheme, ViewerTheme},
+    traces, AppState,
+};
+
+const DEFAULT_WIDTH: u32 = 600;
+const DEFAULT_HEIGHT: u32 = 300;
+const MIN_SIZE: u32 = 100;
+const MAX_SIZE: u32 = 2000;
+
+#[derive(Deserialize)]
+pub struct EmbedQuery {
+    /// Overrides the viewer's theme, since an iframe seldom sees their cookie
+    theme: Option<PageTheme>,
+    /// Fixed chart size in pixels; by default it fills the frame
+    width: Option<u32>,
+    height: Option<u32>,
+}
+
+#[derive(Template)]
+#[template(path = "embed.html")]
+struct EmbedTemplate {
+    namespace: String,
+    id: String,
+    data_json: String,
+    theme: &'static str,
+    width: Option<u32>,
+    height: Option<u32>,
+    /// Link back to the full chart page
+    origin: String,
+}
+
+pub async fn get_embed(
+    SeriesPath(namespace, id): SeriesPath,
+    Query(query): Query<EmbedQuery>,
+    State(state): State<AppState>,
+    ViewerTheme(viewer_theme): ViewerTheme,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let data_json = match load_series_json(&state, &namespace, &id).await {
+        Ok(data_json) => data_json,
+        Err(err) => return err.respond(),
+    };
+
+    let template = EmbedTemplate {
+        namespace,
+        id,
+        data_json: data_json.to_string(),
+        theme: query.theme.unwrap_or(viewer_theme).as_str(),
+        width: query.width.map(|w| w.clamp(MIN_SIZE, MAX_SIZE)),
+        height: query.height.map(|h| h.clamp(MIN_SIZE, MAX_SIZE)),
+        origin: request_origin(&headers, state.base_path()),
+    };
+    traces::render(&template)
+        .map(|html| Html(html).into_response())
+        .map_err(errors::internal)
+}
+
+#[derive(Deserialize)]
+pub struct OEmbedQuery {
+    url: String,
+    maxwidth: Option<u32>,
+    maxheight: Option<u32>,
+    format: Option<String>,
+}
+
+/// A `rich` oEmbed response, per https://oembed.com.
+#[derive(Serialize)]
+struct OEmbed {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    version: &'static str,
+    title: String,
+    provider_name: &'static str,
+    provider_url: String,
+    html: String,
+    width: u32,
+    height: u32,
+}
+
+/// The namespace and id a chart or embed URL on this server, at `host` and
+/// under `base_path`, points at. The scheme is ignored, since links are
+/// pasted as either.
+fn series_from_url(url: &str, host: &str, base_path: &str) -> Option<(String, String)> {
+    let rest = url.strip_prefix("https://").or_else(|| url.strip_prefix("http://"))?;
+    let path = rest.strip_prefix(host)?.strip_prefix(base_path)?;
+    if !path.is_empty() && !path.starts_with('/') {
+        return None;
+    }
+    let path = path.split(['?', '#']).next().unwrap_or("");
+    let (namespace, id) = match path.trim_start_matches('/').split('/').collect::<Vec<_>>()[..] {
+        ["embed", namespace, id] | [namespace, id] => (namespace, id),
+        _ => return None,
+    };
+    if namespace.is_empty() || id.is_empty() {
+        return None;
+    }
+    let decode = |segment: &str| percent_decode_str(segment).decode_utf8().ok().map(|s| s.into_owned());
+    Some((decode(namespace)?, decode(id)?))
+}
+
+fn escape_attribute(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+pub async fn get_oembed(
+    Query(query): Query<OEmbedQuery>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    // Only JSON is offered; the spec asks for 501 on other formats
+    if query.format.as_deref().is_some_and(|format| format != "json") {
+        return Err(StatusCode::NOT_IMPLEMENTED);
+    }
+    let origin = request_origin(&headers, state.base_path());
+    let host = headers.get("host").and_then(|v| v.to_str().ok()).unwrap_or("");
+    let (namespace, id) = series_from_url(&query.url, host, state.base_path()).ok_or(StatusCode::NOT_FOUND)?;
+    let policy = &state.config().id_policy;
+    let (namespace, id) = (policy.normalize(&namespace), policy.normalize(&id));
+
+    let width = query.maxwidth.map_or(DEFAULT_WIDTH, |max| DEFAULT_WIDTH.min(max)).max(MIN_SIZE);
+    let height = query.maxheight.map_or(DEFAULT_HEIGHT, |max| DEFAULT_HEIGHT.min(max)).max(MIN_SIZE);
+    let src = format!(
+        "{}/embed/{}/{}",
+        origin,
+        utf8_percent_encode(&namespace, NON_ALPHANUMERIC),
+        utf8_percent_encode(&id, NON_ALPHANUMERIC)
+    );
+    let title = format!("{}/{}", namespace, id);
+
+    Ok(Json(OEmbed {
+        kind: "rich",
+        version: "1.0",
+        html: format!(
+            r#"<iframe src="{}" width="{}" height="{}" title="{}" frameborder="0" loading="lazy"></iframe>"#,
+            escape_attribute(&src),
+            width,
+            height,
+            escape_attribute(&title)
+        ),
+        title,
+        provider_name: "somnial",
+        provider_url: origin,
+        width,
+        height,
+    }))
+}
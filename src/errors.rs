@@ -0,0 +1,136 @@
+//! Where the errors behind `500` responses end up. Handlers pass whatever
+//! failed, a sqlx, askama or usvg error, to [`internal`], which logs it and,
+//! when reporting is configured, sends it on: to Sentry, through the
+//! `sentry` crate, when `SENTRY_DSN` is set, and as JSON to
+//! `ERROR_WEBHOOK_URL` for anything else. Each report carries the request it
+//! happened in, without its query string.
+//!
+//! Reports are queued and sent in the background, so a failing request
+//! isn't held up by a slow reporting service, and ones that pile up behind
+//! an unreachable one are dropped.
+
+use std::fmt::Display;
+use std::sync::OnceLock;
+
+use axum::http::StatusCode;
+use chrono::Utc;
+use sentry::{
+    protocol::{Event, Exception},
+    types::Dsn,
+    ClientInitGuard, ClientOptions, Level,
+};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+use crate::{client, request_log, AppState};
+
+/// Reports waiting to be sent to the webhook at most
+const MAX_QUEUED_REPORTS: usize = 256;
+
+/// Where errors are reported.
+#[derive(Clone, Debug)]
+pub struct ErrorReporting {
+    pub sentry: Option<Dsn>,
+    pub webhook_url: Option<String>,
+    /// Sent as Sentry's `environment`, such as `production`
+    pub environment: Option<String>,
+}
+
+/// An error as it's sent on.
+struct Report {
+    message: String,
+    kind: &'static str,
+    request: Option<request_log::CurrentRequest>,
+    at: chrono::DateTime<Utc>,
+}
+
+static QUEUE: OnceLock<mpsc::Sender<Report>> = OnceLock::new();
+/// The Sentry client, which sends events from a thread of its own
+static SENTRY: OnceLock<ClientInitGuard> = OnceLock::new();
+
+/// Logs `err` and queues it to be reported.
+pub fn report<E: Display + ?Sized>(err: &E) {
+    let kind = std::any::type_name::<E>();
+    tracing::error!("Request failed: {}", err);
+    let report = Report {
+        message: err.to_string(),
+        kind,
+        request: request_log::current(),
+        at: Utc::now(),
+    };
+    if SENTRY.get().is_some() {
+        sentry::capture_event(sentry_event(&report));
+    }
+    if let Some(queue) = QUEUE.get() {
+        let _ = queue.try_send(report);
+    }
+}
+
+/// Reports `err` and answers with the `500` it becomes, for `map_err`.
+pub fn internal<E: Display>(err: E) -> StatusCode {
+    report(&err);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+fn sentry_event(report: &Report) -> Event<'static> {
+    let mut event = Event {
+        level: Level::Error,
+        logger: Some("somnial".to_string()),
+        timestamp: report.at.into(),
+        exception: vec![Exception {
+            ty: report.kind.to_string(),
+            value: Some(report.message.clone()),
+            ..Default::default()
+        }]
+        .into(),
+        ..Default::default()
+    };
+    if let Some(request) = &report.request {
+        event.transaction = Some(format!("{} {}", request.method, request.path));
+        event.tags.insert("request_id".to_string(), request.id.clone());
+    }
+    event
+}
+
+fn webhook_body(report: &Report) -> Value {
+    json!({
+        "error": report.message,
+        "type": report.kind,
+        "timestamp": report.at.timestamp(),
+        "request_id": report.request.as_ref().map(|request| &request.id),
+        "method": report.request.as_ref().map(|request| &request.method),
+        "path": report.request.as_ref().map(|request| &request.path),
+    })
+}
+
+/// Starts the Sentry client and sends queued webhook reports, for as long
+/// as the server runs.
+pub fn spawn(state: AppState) {
+    let Some(config) = state.config().error_reporting.clone() else {
+        return;
+    };
+    if let Some(dsn) = config.sentry.clone() {
+        let _ = SENTRY.set(sentry::init(ClientOptions {
+            dsn: Some(dsn),
+            release: Some(concat!("somnial@", env!("CARGO_PKG_VERSION")).into()),
+            environment: config.environment.clone().map(Into::into),
+            ..Default::default()
+        }));
+    }
+
+    let Some(url) = config.webhook_url else {
+        return;
+    };
+    let (sender, mut reports) = mpsc::channel(MAX_QUEUED_REPORTS);
+    if QUEUE.set(sender).is_err() {
+        return;
+    }
+    tokio::spawn(async move {
+        while let Some(report) = reports.recv().await {
+            let body = webhook_body(&report).to_string();
+            if let Err(err) = client::post_json(&url, &[], body.as_bytes()).await {
+                tracing::warn!("Couldn't report an error to {}: {}", url, err);
+            }
+        }
+    });
+}
@@ -0,0 +1,361 @@
+//! `GET /{namespace}/export.zip`: everything a namespace holds in one zip, for
+//! taking data elsewhere or archiving a finished project. Unlike a bundle,
+//! which exists to be imported again, the archive is meant to be opened by
+//! people and spreadsheets:
+//!
+//! - `metrics/{id}.csv` and `metrics/{id}.json` with each metric's points,
+//!   oldest first. Ids that aren't safe as file names are rewritten, so
+//!   `metadata.json` says which files belong to which metric.
+//! - `metadata.json` with each metric's display and precision settings, and
+//!   the namespace's markers.
+//! - `README.md`, if the namespace has one.
+
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use flate2::{write::DeflateEncoder, Compression};
+use futures_util::TryStreamExt;
+use serde::Serialize;
+
+use crate::{
+    errors,
+    ids::NamespacePath,
+    meta::{self, MetricMeta},
+    precision::{self, Precision},
+    AppState, MetricPoint,
+};
+
+const EXPORT_FORMAT: &str = "somnial-export";
+const EXPORT_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct Metadata {
+    format: &'static str,
+    version: u32,
+    namespace: String,
+    exported_at: i64,
+    metrics: Vec<MetricEntry>,
+    markers: Vec<MarkerEntry>,
+}
+
+#[derive(Serialize)]
+struct MetricEntry {
+    id: String,
+    /// Paths of the metric's files within the archive
+    csv: String,
+    json: String,
+    points: u64,
+    first_timestamp: i64,
+    last_timestamp: i64,
+    meta: MetricMeta,
+    precision: Precision,
+}
+
+#[derive(Serialize)]
+struct MarkerEntry {
+    timestamp: i64,
+    label: String,
+    url: Option<String>,
+    source: String,
+}
+
+/// One metric's files as they are written.
+struct MetricFiles {
+    id: String,
+    csv: Vec<u8>,
+    json: Vec<u8>,
+    points: u64,
+    first_timestamp: i64,
+    last_timestamp: i64,
+}
+
+impl MetricFiles {
+    fn new(id: String, timestamp: i64) -> Self {
+        MetricFiles {
+            id,
+            csv: b"timestamp,value\n".to_vec(),
+            json: b"[".to_vec(),
+            points: 0,
+            first_timestamp: timestamp,
+            last_timestamp: timestamp,
+        }
+    }
+
+    fn push(&mut self, point: &MetricPoint) -> Result<(), serde_json::Error> {
+        let _ = writeln!(self.csv, "{},{}", point.timestamp, point.value);
+        if self.points > 0 {
+            self.json.push(b',');
+        }
+        serde_json::to_writer(&mut self.json, point)?;
+        self.points += 1;
+        self.last_timestamp = point.timestamp;
+        Ok(())
+    }
+}
+
+/// A file name for `id` that no earlier metric's file differs from only by
+/// case, since plenty of filesystems ignore it.
+fn file_stem(id: &str, taken: &mut HashSet<String>) -> String {
+    let mut stem: String = id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+        .collect();
+    if stem.trim_matches('.').is_empty() {
+        stem = format!("_{}", stem);
+    }
+    let mut candidate = stem.clone();
+    let mut suffix = 2;
+    while !taken.insert(candidate.to_ascii_lowercase()) {
+        candidate = format!("{}-{}", stem, suffix);
+        suffix += 1;
+    }
+    candidate
+}
+
+/// Writes a zip archive entry by entry. Entries are deflated in memory, so
+/// their sizes are known before the header goes out.
+struct ZipWriter {
+    out: Vec<u8>,
+    central: Vec<u8>,
+    entries: u16,
+    /// Modification time and date of every entry, in MS-DOS form
+    modified: (u16, u16),
+}
+
+impl ZipWriter {
+    fn new(now: DateTime<Utc>) -> Self {
+        // MS-DOS dates start in 1980 and count seconds in twos
+        let time = ((now.hour() << 11) | (now.minute() << 5) | (now.second() / 2)) as u16;
+        let date = (((now.year().clamp(1980, 2107) - 1980) as u32) << 9) | (now.month() << 5) | now.day();
+        ZipWriter {
+            out: Vec::new(),
+            central: Vec::new(),
+            entries: 0,
+            modified: (time, date as u16),
+        }
+    }
+
+    fn add(&mut self, name: &str, data: &[u8]) -> Result<(), &'static str> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).map_err(|_| "compression failed")?;
+        let compressed = encoder.finish().map_err(|_| "compression failed")?;
+
+        let too_large = "namespace is too large for a zip archive";
+        let compressed_size = u32::try_from(compressed.len()).map_err(|_| too_large)?;
+        let size = u32::try_from(data.len()).map_err(|_| too_large)?;
+        let offset = u32::try_from(self.out.len()).map_err(|_| too_large)?;
+        self.entries = self.entries.checked_add(1).ok_or(too_large)?;
+        let crc = crc32fast::hash(data);
+        let (time, date) = self.modified;
+        let name_length = name.len() as u16;
+
+        // Version 2.0, UTF-8 names, deflated
+        let mut fields = Vec::with_capacity(26);
+        for part in [20u16, 1 << 11, 8, time, date] {
+            fields.extend_from_slice(&part.to_le_bytes());
+        }
+        for part in [crc, compressed_size, size] {
+            fields.extend_from_slice(&part.to_le_bytes());
+        }
+        fields.extend_from_slice(&name_length.to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes());
+
+        self.out.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        self.out.extend_from_slice(&fields);
+        self.out.extend_from_slice(name.as_bytes());
+        self.out.extend_from_slice(&compressed);
+
+        self.central.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        self.central.extend_from_slice(&20u16.to_le_bytes());
+        self.central.extend_from_slice(&fields);
+        // No comment, first disk, no attributes
+        for part in [0u16, 0, 0] {
+            self.central.extend_from_slice(&part.to_le_bytes());
+        }
+        self.central.extend_from_slice(&0u32.to_le_bytes());
+        self.central.extend_from_slice(&offset.to_le_bytes());
+        self.central.extend_from_slice(name.as_bytes());
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>, &'static str> {
+        let too_large = "namespace is too large for a zip archive";
+        let central_size = u32::try_from(self.central.len()).map_err(|_| too_large)?;
+        let central_offset = u32::try_from(self.out.len()).map_err(|_| too_large)?;
+        self.out.append(&mut self.central);
+        self.out.extend_from_slice(&0x06054b50u32.to_le_bytes());
+        for part in [0u16, 0, self.entries, self.entries] {
+            self.out.extend_from_slice(&part.to_le_bytes());
+        }
+        self.out.extend_from_slice(&central_size.to_le_bytes());
+        self.out.extend_from_slice(&central_offset.to_le_bytes());
+        self.out.extend_from_slice(&0u16.to_le_bytes());
+        Ok(self.out)
+    }
+}
+
+async fn build(state: &AppState, namespace: &str) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+    let (pool, store) = (&state.pool, state.store.as_ref());
+    let now = Utc::now();
+    let metas = meta::load_namespace(pool, namespace).await?;
+    let precisions: HashMap<String, Precision> = precision::load_namespace(pool, namespace).await?;
+    let mut zip = ZipWriter::new(now);
+    let mut entries = Vec::new();
+    let mut taken = HashSet::new();
+
+    let mut finish = |zip: &mut ZipWriter, files: MetricFiles| -> Result<(), Box<dyn std::error::Error>> {
+        let MetricFiles { id, csv, mut json, .. } = files;
+        json.extend_from_slice(b"]\n");
+        let stem = file_stem(&id, &mut taken);
+        let (csv_path, json_path) = (format!("metrics/{}.csv", stem), format!("metrics/{}.json", stem));
+        zip.add(&csv_path, &csv)?;
+        zip.add(&json_path, &json)?;
+        entries.push(MetricEntry {
+            meta: metas.get(&id).cloned().unwrap_or_default(),
+            precision: precisions.get(&id).copied().unwrap_or_default(),
+            id,
+            csv: csv_path,
+            json: json_path,
+            points: files.points,
+            first_timestamp: files.first_timestamp,
+            last_timestamp: files.last_timestamp,
+        });
+        Ok(())
+    };
+
+    // Streamed a metric at a time, so only one metric's points are held at once
+    let mut rows = store.scan(namespace, i64::MAX, i64::MAX);
+    let mut current: Option<MetricFiles> = None;
+    while let Some((id, point)) = rows.try_next().await? {
+        if current.as_ref().is_some_and(|files| files.id != id)
+            && let Some(files) = current.take()
+        {
+            finish(&mut zip, files)?;
+        }
+        current.get_or_insert_with(|| MetricFiles::new(id, point.timestamp)).push(&point)?;
+    }
+    drop(rows);
+    match current {
+        Some(files) => finish(&mut zip, files)?,
+        None => return Ok(None),
+    }
+
+    let markers = sqlx::query_as!(
+        MarkerEntry,
+        "SELECT timestamp, label, url, source FROM markers WHERE namespace = ? ORDER BY timestamp, marker_id",
+        namespace
+    )
+    .fetch_all(pool)
+    .await?;
+    let readme = sqlx::query_scalar!("SELECT body FROM namespace_readmes WHERE namespace = ?", namespace)
+        .fetch_optional(pool)
+        .await?;
+
+    let metadata = Metadata {
+        format: EXPORT_FORMAT,
+        version: EXPORT_VERSION,
+        namespace: namespace.to_string(),
+        exported_at: now.timestamp(),
+        metrics: entries,
+        markers,
+    };
+    zip.add("metadata.json", &serde_json::to_vec_pretty(&metadata)?)?;
+    if let Some(readme) = readme {
+        zip.add("README.md", readme.as_bytes())?;
+    }
+    Ok(Some(zip.finish()?))
+}
+
+pub async fn get_export(
+    NamespacePath(namespace): NamespacePath,
+    State(state): State<AppState>,
+) -> Result<Response, StatusCode> {
+    let archive = build(&state, &namespace)
+        .await
+        .map_err(|err| {
+            tracing::warn!("Exporting namespace {} failed", namespace);
+            errors::internal(err)
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    // Keep the filename header-safe regardless of what the namespace contains
+    let filename: String = namespace
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/zip".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}-{}.zip\"", filename, Utc::now().format("%Y%m%d")),
+            ),
+        ],
+        Body::from(archive),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn zip_archives_open_with_other_readers() {
+        let now = Utc.with_ymd_and_hms(2024, 7, 15, 13, 45, 31).unwrap();
+        let mut zip = ZipWriter::new(now);
+        zip.add("metrics/build_time.csv", b"timestamp,value\n1,2\n").unwrap();
+        zip.add("metrics/ünïcode.json", &[b'x'; 10_000]).unwrap();
+        zip.add("README.md", b"").unwrap();
+        let bytes = zip.finish().unwrap();
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(archive.len(), 3);
+        let mut csv = String::new();
+        archive.by_name("metrics/build_time.csv").unwrap().read_to_string(&mut csv).unwrap();
+        assert_eq!(csv, "timestamp,value\n1,2\n");
+
+        let mut json = archive.by_name("metrics/ünïcode.json").unwrap();
+        assert_eq!(json.compression(), zip::CompressionMethod::Deflated);
+        assert!(json.compressed_size() < 100);
+        let modified = json.last_modified().unwrap();
+        assert_eq!((modified.year(), modified.month(), modified.day()), (2024, 7, 15));
+        // Stored to the even second below
+        assert_eq!((modified.hour(), modified.minute(), modified.second()), (13, 45, 30));
+        let mut data = Vec::new();
+        json.read_to_end(&mut data).unwrap();
+        assert_eq!(data, [b'x'; 10_000]);
+        drop(json);
+
+        assert_eq!(archive.by_name("README.md").unwrap().size(), 0);
+    }
+
+    #[test]
+    fn empty_zip_archives_are_valid() {
+        let bytes = ZipWriter::new(Utc::now()).finish().unwrap();
+        assert!(zip::ZipArchive::new(Cursor::new(bytes)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn file_stems_are_safe_and_distinct_ignoring_case() {
+        let mut taken = HashSet::new();
+        assert_eq!(file_stem("build_time", &mut taken), "build_time");
+        assert_eq!(file_stem("Build_Time", &mut taken), "Build_Time-2");
+        assert_eq!(file_stem("BUILD_TIME", &mut taken), "BUILD_TIME-3");
+        assert_eq!(file_stem("size/x86 64", &mut taken), "size_x86_64");
+        assert_eq!(file_stem("..", &mut taken), "_..");
+        assert_eq!(file_stem("", &mut taken), "_");
+        assert_eq!(file_stem("café", &mut taken), "caf_");
+    }
+}
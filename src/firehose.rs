@@ -0,0 +1,155 @@
+//! Live feed of ingested points over WebSocket, for downstream processors
+//! and wallboards that want every write as it happens.
+//!
+//! Subscribers pick series with `?match=` patterns and get each point as a
+//! JSON text message. The feed is best effort: a subscriber that falls too
+//! far behind is told how many points it missed rather than slowing writers.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        RawQuery, State,
+    },
+    http::StatusCode,
+    response::IntoResponse,
+};
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::AppState;
+
+/// Points buffered per subscriber before it starts missing them.
+const BUFFER: usize = 1024;
+/// Most patterns one subscription may list.
+const MAX_PATTERNS: usize = 32;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Point {
+    pub namespace: String,
+    pub id: String,
+    pub value: f64,
+    pub timestamp: i64,
+}
+
+pub struct Firehose {
+    sender: broadcast::Sender<Arc<Point>>,
+}
+
+impl Default for Firehose {
+    fn default() -> Self {
+        Firehose {
+            sender: broadcast::channel(BUFFER).0,
+        }
+    }
+}
+
+impl Firehose {
+    /// Hands a stored point to every subscriber; free when nobody listens.
+    pub fn publish(&self, namespace: &str, id: &str, value: f64, timestamp: i64) {
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+        let _ = self.sender.send(Arc::new(Point {
+            namespace: namespace.to_string(),
+            id: id.to_string(),
+            value,
+            timestamp,
+        }));
+    }
+}
+
+/// A `namespace/id` pattern where `*` matches any run of characters. A
+/// pattern without a `/` matches every id in the namespace.
+struct Pattern {
+    namespace: String,
+    id: String,
+}
+
+impl Pattern {
+    fn parse(raw: &str, state: &AppState) -> Option<Self> {
+        let policy = &state.config().id_policy;
+        let (namespace, id) = raw.split_once('/').unwrap_or((raw, "*"));
+        (!namespace.is_empty() && !id.is_empty()).then(|| Pattern {
+            namespace: policy.normalize(namespace),
+            id: policy.normalize(id),
+        })
+    }
+
+    fn matches(&self, point: &Point) -> bool {
+        glob_match(&self.namespace, &point.namespace) && glob_match(&self.id, &point.id)
+    }
+}
+
+/// `*`-only glob matching, backtracking to the most recent star.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let (pattern, text): (Vec<char>, Vec<char>) = (pattern.chars().collect(), text.chars().collect());
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == text[t] {
+            p += 1;
+            t += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// `GET /api/v1/firehose?match=ci/*&match=web/latency` upgrades to a
+/// WebSocket streaming matching points.
+pub async fn subscribe(
+    State(state): State<AppState>,
+    RawQuery(query): RawQuery,
+    upgrade: WebSocketUpgrade,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    let mut patterns = Vec::new();
+    for (key, value) in form_urlencoded::parse(query.as_deref().unwrap_or("").as_bytes()) {
+        if key != "match" {
+            continue;
+        }
+        let pattern = Pattern::parse(&value, &state)
+            .ok_or((StatusCode::BAD_REQUEST, "match patterns look like namespace/id, with * wildcards"))?;
+        patterns.push(pattern);
+    }
+    if patterns.is_empty() || patterns.len() > MAX_PATTERNS {
+        return Err((StatusCode::BAD_REQUEST, "give between 1 and 32 match= patterns"));
+    }
+
+    let receiver = state.firehose.sender.subscribe();
+    Ok(upgrade.on_upgrade(move |socket| stream(socket, receiver, patterns)))
+}
+
+async fn stream(mut socket: WebSocket, mut receiver: broadcast::Receiver<Arc<Point>>, patterns: Vec<Pattern>) {
+    loop {
+        tokio::select! {
+            point = receiver.recv() => {
+                let message = match point {
+                    Ok(point) if patterns.iter().any(|p| p.matches(&point)) => {
+                        serde_json::to_string(&*point).unwrap_or_default()
+                    }
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(missed)) => format!(r#"{{"missed":{}}}"#, missed),
+                    Err(RecvError::Closed) => break,
+                };
+                if socket.send(Message::Text(message.into())).await.is_err() {
+                    break;
+                }
+            }
+            // Client messages are ignored; the stream ends when it hangs up
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
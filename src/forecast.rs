@@ -0,0 +1,287 @@
+//! `/{namespace}/{id}/forecast`: a series projected forward from its latest
+//! point, for questions like "when does the disk fill up?". A straight line
+//! is fitted to the recent history by least squares, with
+//! `?seasonality=weekly` adding the average departure from it in each hour
+//! of the week, and every projected value comes with a 95% prediction
+//! interval that widens the further out it is.
+//!
+//! `?target=100` also says when the line reaches that value, however far
+//! off that is. The weekly pattern is left out of that answer, since it
+//! would only move it by a few hours either way.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    ids::SeriesPath, limits::ReadError, meta, notifiers, parse_span, rollup, trend::Line, AppState, MetricPoint,
+};
+
+const HOUR: i64 = 3600;
+const WEEK: i64 = 7 * 86400;
+/// The furthest ahead a forecast goes, and the furthest back it fits
+const MAX_SPAN_SECONDS: i64 = 366 * 86400;
+/// A weekly pattern needs every hour of the week seen at least twice
+const MIN_WEEKLY_HISTORY: i64 = 2 * WEEK;
+/// How many projected values a forecast has at most
+const MAX_STEPS: i64 = 100;
+/// Prediction intervals are two-sided 95% ones
+const CONFIDENCE: f64 = 0.95;
+const Z_SCORE: f64 = 1.96;
+/// Crossings further off than this aren't worth a date
+const MAX_REACH_SECONDS: i64 = 100 * 365 * 86400;
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(json!({ "error": message })))
+}
+
+fn database_error<E: std::fmt::Display>(err: E) -> ApiError {
+    crate::errors::report(&err);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+/// `?horizon=7d&history=30d&seasonality=weekly&target=100`, all optional.
+#[derive(Deserialize)]
+pub struct ForecastQuery {
+    horizon: Option<String>,
+    history: Option<String>,
+    seasonality: Option<Seasonality>,
+    target: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Seasonality {
+    #[default]
+    None,
+    Weekly,
+}
+
+/// A fitted line, the weekly pattern around it if there is one, and how
+/// far the history strays from both.
+struct Model {
+    line: Line,
+    /// Average residual in each hour of the week, in UTC
+    weekly: Option<Vec<f64>>,
+    points: usize,
+    /// Standard deviation of what the model doesn't explain
+    residual: f64,
+}
+
+fn hour_of_week(timestamp: i64) -> usize {
+    (timestamp.rem_euclid(WEEK) / HOUR) as usize
+}
+
+impl Model {
+    fn fit(points: &[MetricPoint], seasonality: Seasonality) -> Option<Model> {
+        let line = Line::fit(points)?;
+        let residuals: Vec<f64> = points.iter().map(|point| point.value - line.at(point.timestamp)).collect();
+        let mut parameters = 2;
+        let weekly = (seasonality == Seasonality::Weekly).then(|| {
+            let (mut sums, mut counts) = (vec![0.0; 168], vec![0usize; 168]);
+            for (point, residual) in points.iter().zip(&residuals) {
+                sums[hour_of_week(point.timestamp)] += residual;
+                counts[hour_of_week(point.timestamp)] += 1;
+            }
+            parameters += counts.iter().filter(|&&count| count > 0).count();
+            sums.iter()
+                .zip(&counts)
+                .map(|(&sum, &count)| if count > 0 { sum / count as f64 } else { 0.0 })
+                .collect::<Vec<f64>>()
+        });
+        if points.len() <= parameters {
+            return None;
+        }
+        let unexplained: f64 = points
+            .iter()
+            .zip(&residuals)
+            .map(|(point, residual)| {
+                let seasonal = weekly.as_ref().map_or(0.0, |weekly| weekly[hour_of_week(point.timestamp)]);
+                (residual - seasonal).powi(2)
+            })
+            .sum();
+        Some(Model {
+            line,
+            weekly,
+            points: points.len(),
+            residual: (unexplained / (points.len() - parameters) as f64).sqrt(),
+        })
+    }
+
+    fn predict(&self, timestamp: i64) -> ProjectedPoint {
+        let seasonal = self.weekly.as_ref().map_or(0.0, |weekly| weekly[hour_of_week(timestamp)]);
+        let value = self.line.at(timestamp) + seasonal;
+        let distance = timestamp as f64 - self.line.mean_t;
+        let spread = self.residual
+            * (1.0 + 1.0 / self.points as f64 + distance * distance / self.line.variance).sqrt()
+            * Z_SCORE;
+        ProjectedPoint {
+            timestamp,
+            value,
+            lower: value - spread,
+            upper: value + spread,
+        }
+    }
+
+    /// When the line reaches `target` after `from`, if it's heading there.
+    fn reaches(&self, target: f64, from: i64) -> Option<i64> {
+        if self.line.slope == 0.0 || (target - self.line.at(from)).signum() != self.line.slope.signum() {
+            return None;
+        }
+        let at = self.line.mean_t + (target - self.line.mean_v) / self.line.slope;
+        (at.is_finite() && at - (from as f64) <= MAX_REACH_SECONDS as f64).then_some(at.ceil() as i64)
+    }
+}
+
+#[derive(Serialize)]
+struct ProjectedPoint {
+    timestamp: i64,
+    value: f64,
+    lower: f64,
+    upper: f64,
+}
+
+#[derive(Serialize)]
+struct History {
+    from: i64,
+    to: i64,
+    points: usize,
+}
+
+#[derive(Serialize)]
+struct Target {
+    value: f64,
+    /// When the trend line gets there, or `null` if it's heading away
+    reaches_at: Option<i64>,
+    /// One line answer, with the values as the chart shows them
+    message: String,
+}
+
+#[derive(Serialize)]
+struct ForecastResponse {
+    namespace: String,
+    id: String,
+    model: &'static str,
+    /// Seconds beyond the latest point the forecast covers
+    horizon: i64,
+    history: History,
+    /// The trend line's change per day
+    slope_per_day: f64,
+    confidence: f64,
+    points: Vec<ProjectedPoint>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<Target>,
+}
+
+fn parse_limited_span(value: Option<&str>, default: i64, name: &str) -> Result<i64, ApiError> {
+    let Some(value) = value else {
+        return Ok(default);
+    };
+    parse_span(value)
+        .filter(|&seconds| seconds > 0 && seconds <= MAX_SPAN_SECONDS)
+        .ok_or_else(|| {
+            error(
+                StatusCode::BAD_REQUEST,
+                &format!("{} takes a span like 12h or 7d, up to 366 days", name),
+            )
+        })
+}
+
+pub async fn get_forecast(
+    SeriesPath(namespace, id): SeriesPath,
+    Query(query): Query<ForecastQuery>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, ApiError> {
+    let horizon = parse_limited_span(query.horizon.as_deref(), 7 * 86400, "horizon")?;
+    let history = parse_limited_span(query.history.as_deref(), 30 * 86400, "history")?;
+    let seasonality = query.seasonality.unwrap_or_default();
+    if query.target.is_some_and(|target| !target.is_finite()) {
+        return Err(error(StatusCode::BAD_REQUEST, "target must be a number"));
+    }
+
+    let latest = state
+        .store
+        .latest(&namespace, &id)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "series has no points"))?;
+    let origin = latest.timestamp;
+    let mut points = rollup::load_points(&state, &namespace, &id, origin.saturating_sub(history), origin)
+        .await
+        .map_err(ReadError::api)?;
+    points.retain(|point| point.value.is_finite());
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return Err(error(StatusCode::NOT_FOUND, "series has no points"));
+    };
+    let (from, to) = (first.timestamp, last.timestamp);
+    if seasonality == Seasonality::Weekly && to - from < MIN_WEEKLY_HISTORY {
+        return Err(error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "weekly seasonality needs at least two weeks of history",
+        ));
+    }
+    let model = Model::fit(&points, seasonality).ok_or_else(|| {
+        error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "not enough points spread over time in the history to fit a forecast",
+        )
+    })?;
+
+    let steps = MAX_STEPS.min(horizon / 60).max(1);
+    let projected = (1..=steps)
+        .map(|step| model.predict(origin + horizon * step / steps))
+        .collect();
+
+    let target = match query.target {
+        Some(value) => {
+            let reaches_at = model.reaches(value, origin);
+            let meta = meta::load(&state.pool, &namespace, &id).await.map_err(database_error)?;
+            let message = match reaches_at {
+                Some(at) => format!(
+                    "{}/{} is projected to reach {} at {}, in {}",
+                    namespace,
+                    id,
+                    meta.format(value),
+                    notifiers::utc_time(at),
+                    notifiers::format_duration((at - origin) as f64)
+                ),
+                None if model.line.slope != 0.0
+                    && (value - model.line.at(origin)).signum() == model.line.slope.signum() =>
+                {
+                    format!("{}/{} won't reach {} for over a century at this rate", namespace, id, meta.format(value))
+                }
+                None => format!("{}/{} isn't trending towards {}", namespace, id, meta.format(value)),
+            };
+            Some(Target {
+                value,
+                reaches_at,
+                message,
+            })
+        }
+        None => None,
+    };
+
+    let response = ForecastResponse {
+        namespace,
+        id,
+        model: if model.weekly.is_some() { "linear+weekly" } else { "linear" },
+        horizon,
+        history: History {
+            from,
+            to,
+            points: model.points,
+        },
+        slope_per_day: model.line.slope * 86400.0,
+        confidence: CONFIDENCE,
+        points: projected,
+        target,
+    };
+    Ok(Json(response))
+}
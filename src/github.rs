@@ -0,0 +1,310 @@
+//! Commit statuses on GitHub for benchmark results. Once a namespace is
+//! linked to a repository with `PUT /api/v1/namespaces/{namespace}/github`,
+//! every point recorded with `?sha=<commit>` puts a status on that commit:
+//! one per metric, under the `somnial/{namespace}/{id}` context, giving the
+//! value and how it compares with the default branch, and linking to the
+//! chart when `PUBLIC_URL` is set.
+//!
+//! The default branch's value is the latest one recorded with
+//! `&branch=<default branch>`; results from other branches are compared with
+//! it but never replace it. Statuses only inform, so they are always
+//! `success`, and one GitHub refuses is logged and not retried.
+//!
+//! Links without a token of their own use the server's `GITHUB_TOKEN`, so
+//! only the admin token may link a namespace to a repository.
+
+use std::sync::atomic::Ordering;
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{auth, client, ids::NamespacePath, meta, notifiers, AppState};
+
+const MAX_BRANCH_LENGTH: usize = 255;
+const MAX_TOKEN_LENGTH: usize = 255;
+/// GitHub cuts status descriptions off beyond this
+const MAX_DESCRIPTION_LENGTH: usize = 140;
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(json!({ "error": message })))
+}
+
+fn database_error<E: std::fmt::Display>(err: E) -> ApiError {
+    crate::errors::report(&err);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+/// `{"repo": "octo/bench", "default_branch": "main", "token": "ghp_..."}`;
+/// without a token the server's `GITHUB_TOKEN` is used.
+#[derive(Deserialize)]
+pub struct RepoRequest {
+    repo: String,
+    #[serde(default = "default_branch")]
+    default_branch: String,
+    token: Option<String>,
+}
+
+fn default_branch() -> String {
+    "main".to_string()
+}
+
+/// A namespace's link as it is shown; the token itself never is.
+#[derive(Serialize)]
+struct RepoLink {
+    repo: String,
+    default_branch: String,
+    /// Whether the namespace has a token of its own
+    token_set: bool,
+}
+
+/// Whether `commit` looks like a full or abbreviated commit SHA.
+pub fn valid_commit(commit: &str) -> bool {
+    (7..=40).contains(&commit.len()) && commit.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+/// Whether `branch` could be a git branch name.
+pub fn valid_branch(branch: &str) -> bool {
+    !branch.is_empty()
+        && branch.len() <= MAX_BRANCH_LENGTH
+        && !branch.chars().any(|c| c.is_whitespace() || c.is_control() || "~^:?*[\\<>\"".contains(c))
+}
+
+/// `owner/name`, as GitHub allows them.
+fn valid_repo(repo: &str) -> bool {
+    let allowed = |part: &str| {
+        !part.is_empty()
+            && part.len() <= 100
+            && part.bytes().all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.'))
+    };
+    repo.split_once('/').is_some_and(|(owner, name)| allowed(owner) && allowed(name))
+}
+
+/// What a status says about `value`, given the default branch's.
+fn describe(meta: &meta::MetricMeta, value: f64, baseline: Option<f64>, branch: &str) -> String {
+    let shown = meta.format(value);
+    let description = match baseline {
+        None => format!("{}; no {} result to compare with yet", shown, branch),
+        Some(baseline) => {
+            let delta = value - baseline;
+            let sign = if delta < 0.0 { "-" } else { "+" };
+            let percent = if baseline != 0.0 {
+                format!(" ({}{:.1}%)", sign, (delta / baseline * 100.0).abs())
+            } else {
+                String::new()
+            };
+            format!("{}, {}{}{} vs {}", shown, sign, meta.format(delta.abs()), percent, branch)
+        }
+    };
+    description.chars().take(MAX_DESCRIPTION_LENGTH).collect()
+}
+
+/// Posts commit statuses for freshly recorded points if the namespace is
+/// linked to a repository, and moves the baselines along when they came
+/// from the default branch.
+pub async fn report(state: &AppState, namespace: &str, points: &[(&str, f64)], commit: &str, branch: Option<&str>) {
+    if !state.config().features.github_status {
+        return;
+    }
+    let pool = &state.pool;
+    let link = match sqlx::query!(
+        "SELECT repo, default_branch, token FROM github_repos WHERE namespace = ?",
+        namespace
+    )
+    .fetch_optional(pool)
+    .await
+    {
+        Ok(Some(link)) => link,
+        Ok(None) => return,
+        Err(err) => {
+            tracing::warn!("Loading the GitHub link of namespace {} failed: {}", namespace, err);
+            return;
+        }
+    };
+    let Some(token) = link.token.or_else(|| state.config().github_token.clone()) else {
+        return;
+    };
+    let on_default_branch = branch == Some(link.default_branch.as_str());
+
+    let now = Utc::now().timestamp();
+    let mut statuses = Vec::new();
+    for &(id, value) in points {
+        let baseline = sqlx::query_scalar!(
+            "SELECT value FROM github_baselines WHERE namespace = ? AND id = ?",
+            namespace,
+            id
+        )
+        .fetch_optional(pool)
+        .await;
+        let meta = meta::load(pool, namespace, id).await;
+        let (Ok(baseline), Ok(meta)) = (baseline, meta) else {
+            tracing::warn!("Loading the GitHub baseline of {}/{} failed", namespace, id);
+            continue;
+        };
+        statuses.push(json!({
+            "state": "success",
+            "context": format!("somnial/{}/{}", namespace, id),
+            "description": describe(&meta, value, baseline, &link.default_branch),
+            "target_url": state
+                .config()
+                .public_url
+                .as_deref()
+                .map(|url| notifiers::chart_url(url, namespace, id)),
+        }));
+
+        if on_default_branch {
+            let saved = state
+                .write(|| async move {
+                    sqlx::query!(
+                        "INSERT OR REPLACE INTO github_baselines (namespace, id, value, commit_sha, recorded_at)
+                         VALUES (?, ?, ?, ?, ?)",
+                        namespace,
+                        id,
+                        value,
+                        commit,
+                        now
+                    )
+                    .execute(pool)
+                    .await
+                })
+                .await;
+            if let Err(err) = saved {
+                tracing::warn!("Saving the GitHub baseline of {}/{} failed: {}", namespace, id, err);
+            }
+        }
+    }
+
+    let url = format!(
+        "{}/repos/{}/statuses/{}",
+        state.config().github_api_url,
+        link.repo,
+        commit.to_ascii_lowercase()
+    );
+    let state = state.clone();
+    tokio::spawn(async move {
+        let authorization = format!("Bearer {}", token);
+        let headers = [
+            ("Authorization", authorization.as_str()),
+            ("Accept", "application/vnd.github+json"),
+            ("X-GitHub-Api-Version", "2022-11-28"),
+        ];
+        for status in statuses {
+            let sent = match serde_json::to_vec(&status) {
+                Ok(body) => client::post_json(&url, &headers, &body).await,
+                Err(err) => Err(err.into()),
+            };
+            let counter = match sent {
+                Ok(()) => &state.metrics.github_statuses,
+                Err(err) => {
+                    tracing::warn!("Posting a commit status to {} failed: {}", link.repo, err);
+                    &state.metrics.github_status_failures
+                }
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+}
+
+pub async fn get_repo(
+    NamespacePath(namespace): NamespacePath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+    let link = sqlx::query!(
+        "SELECT repo, default_branch, token FROM github_repos WHERE namespace = ?",
+        namespace
+    )
+    .fetch_optional(&state.pool)
+    .await
+    .map_err(database_error)?
+    .ok_or_else(|| error(StatusCode::NOT_FOUND, "namespace isn't linked to a GitHub repository"))?;
+    Ok(Json(RepoLink {
+        repo: link.repo,
+        default_branch: link.default_branch,
+        token_set: link.token.is_some(),
+    }))
+}
+
+pub async fn put_repo(
+    NamespacePath(namespace): NamespacePath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<RepoRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+    if !valid_repo(&request.repo) {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "repo must look like owner/name"));
+    }
+    let branch = request.default_branch.trim();
+    if !valid_branch(branch) {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "default_branch must be a branch name"));
+    }
+    let token = request.token.filter(|token| !token.is_empty());
+    if let Some(token) = &token
+        && (token.len() > MAX_TOKEN_LENGTH || !token.bytes().all(|byte| byte.is_ascii_graphic()))
+    {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "token isn't a GitHub token"));
+    }
+    if token.is_none() && state.config().github_token.is_none() {
+        return Err(error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "a token is needed, since this instance has no GITHUB_TOKEN configured",
+        ));
+    }
+
+    let now = Utc::now().timestamp();
+    let (pool, namespace_ref, repo, token_ref) = (&state.pool, &namespace, &request.repo, &token);
+    state
+        .write(|| async move {
+            sqlx::query!(
+                "INSERT OR REPLACE INTO github_repos (namespace, repo, default_branch, token, created_at)
+                 VALUES (?, ?, ?, ?, ?)",
+                namespace_ref,
+                repo,
+                branch,
+                token_ref,
+                now
+            )
+            .execute(pool)
+            .await
+        })
+        .await
+        .map_err(database_error)?;
+
+    Ok(Json(RepoLink {
+        repo: request.repo,
+        default_branch: branch.to_string(),
+        token_set: token.is_some(),
+    }))
+}
+
+/// Unlinks the namespace. Its baselines stay, in case it's linked again.
+pub async fn delete_repo(
+    NamespacePath(namespace): NamespacePath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+    let (pool, namespace_ref) = (&state.pool, &namespace);
+    let result = state
+        .write(|| async move {
+            sqlx::query!("DELETE FROM github_repos WHERE namespace = ?", namespace_ref)
+                .execute(pool)
+                .await
+        })
+        .await
+        .map_err(database_error)?;
+    if result.rows_affected() == 0 {
+        return Err(error(StatusCode::NOT_FOUND, "namespace isn't linked to a GitHub repository"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
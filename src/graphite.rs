@@ -0,0 +1,649 @@
+//! A read-only subset of Graphite's HTTP API, mounted under `/graphite`, so
+//! dashboards built for Graphite (Grafana's Graphite data source, for one)
+//! can chart data stored here.
+//!
+//! A target path is `<namespace>.<id>`: the first dot separates the two and
+//! the rest belongs to the id. `*` in the id matches any run of characters.
+//! Supported functions: `alias`, `aliasByNode`, `scale`, `offset`,
+//! `absolute`, `derivative`, `movingAverage`, `sumSeries`, `averageSeries`,
+//! `minSeries` and `maxSeries`.
+
+use std::collections::{BTreeMap, HashMap};
+
+use axum::{
+    extract::{RawForm, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{
+    errors,
+    limits::{self, ReadError},
+    store::{MetricStore, StoreError},
+    AppState,
+};
+
+const DEFAULT_FROM: &str = "-24h";
+const MAX_TARGETS: usize = 32;
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Path(String),
+    Call(String, Vec<Arg>),
+}
+
+#[derive(Clone, Debug)]
+enum Arg {
+    Series(Expr),
+    Number(f64),
+    Text(String),
+}
+
+#[derive(Clone)]
+struct Series {
+    name: String,
+    points: Vec<(i64, Option<f64>)>,
+}
+
+type Error = (StatusCode, String);
+
+fn bad_request(message: impl Into<String>) -> Error {
+    (StatusCode::BAD_REQUEST, message.into())
+}
+
+/// Recursive-descent parser for target expressions.
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn parse(input: &'a str) -> Result<Expr, String> {
+        let mut parser = Parser { input, pos: 0 };
+        let expr = parser.expr()?;
+        parser.skip_whitespace();
+        if parser.pos != input.len() {
+            return Err(format!("unexpected `{}` in target", &input[parser.pos..]));
+        }
+        Ok(expr)
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.take_while(char::is_whitespace);
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let start = self.pos;
+        while let Some(c) = self.peek().filter(|&c| f(c)) {
+            self.pos += c.len_utf8();
+        }
+        &self.input[start..self.pos]
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        self.skip_whitespace();
+        let word = self.take_while(|c| !matches!(c, '(' | ')' | ',' | '"' | '\'') && !c.is_whitespace());
+        if word.is_empty() {
+            return Err("expected a series path or function".to_string());
+        }
+        self.skip_whitespace();
+        if self.peek() != Some('(') {
+            return Ok(Expr::Path(word.to_string()));
+        }
+
+        self.pos += 1;
+        let mut args = Vec::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some(')') if args.is_empty() => break,
+                Some(quote @ ('"' | '\'')) => {
+                    self.pos += 1;
+                    let text = self.take_while(|c| c != quote).to_string();
+                    if self.peek() != Some(quote) {
+                        return Err("unterminated string".to_string());
+                    }
+                    self.pos += 1;
+                    args.push(Arg::Text(text));
+                }
+                Some(c) if c.is_ascii_digit() || c == '-' => {
+                    let number = self.take_while(|c| c.is_ascii_digit() || matches!(c, '-' | '.' | 'e' | 'E'));
+                    args.push(Arg::Number(
+                        number.parse().map_err(|_| format!("invalid number `{}`", number))?,
+                    ));
+                }
+                _ => args.push(Arg::Series(self.expr()?)),
+            }
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(')') => break,
+                _ => return Err(format!("expected `,` or `)` after argument to {}", word)),
+            }
+        }
+        self.pos += 1;
+        Ok(Expr::Call(word.to_string(), args))
+    }
+}
+
+fn collect_paths<'e>(expr: &'e Expr, paths: &mut Vec<&'e str>) {
+    match expr {
+        Expr::Path(path) => paths.push(path),
+        Expr::Call(_, args) => {
+            for arg in args {
+                if let Arg::Series(expr) = arg {
+                    collect_paths(expr, paths);
+                }
+            }
+        }
+    }
+}
+
+/// Whether `text` matches `pattern`, where `*` stands for any run of
+/// characters and everything else for itself.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else { return false };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else { return rest.is_empty() };
+    for part in parts {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+async fn fetch_path(
+    state: &AppState,
+    path: &str,
+    from: i64,
+    until: i64,
+) -> Result<Vec<Series>, Error> {
+    let (namespace, id) = path
+        .split_once('.')
+        .ok_or_else(|| bad_request(format!("target `{}` is not of the form namespace.id", path)))?;
+    let policy = &state.config().id_policy;
+    let (namespace, pattern) = (policy.normalize(namespace), policy.normalize(id));
+
+    // Every series the pattern matches counts towards the limit together
+    let max_points = state.config().limits.max_query_points;
+    let mut remaining = limits::fetch_limit(max_points);
+    let ids = state.store.ids(&namespace).await.map_err(|err| ReadError::from(err).text())?;
+    let mut series: Vec<Series> = Vec::new();
+    let mut fetched = 0;
+    for id in ids.into_iter().filter(|id| wildcard_match(&pattern, id)) {
+        if remaining == 0 {
+            break;
+        }
+        let points = state
+            .store
+            .range(&namespace, &id, from, until, remaining)
+            .await
+            .map_err(|err| ReadError::from(err).text())?;
+        remaining -= points.len() as i64;
+        fetched += points.len();
+        if !points.is_empty() {
+            series.push(Series {
+                name: format!("{}.{}", namespace, id),
+                points: points.into_iter().map(|point| (point.timestamp, Some(point.value))).collect(),
+            });
+        }
+    }
+    if fetched > max_points {
+        return Err(ReadError::TooManyPoints(max_points).text());
+    }
+    Ok(series)
+}
+
+fn series_args(
+    name: &str,
+    args: &[Arg],
+    fetched: &HashMap<&str, Vec<Series>>,
+) -> Result<Vec<Series>, Error> {
+    let mut series = Vec::new();
+    for arg in args {
+        if let Arg::Series(expr) = arg {
+            series.extend(evaluate(expr, fetched)?);
+        }
+    }
+    if series.is_empty() && !args.iter().any(|a| matches!(a, Arg::Series(_))) {
+        return Err(bad_request(format!("{} needs a series argument", name)));
+    }
+    Ok(series)
+}
+
+fn number_arg(name: &str, args: &[Arg], index: usize) -> Result<f64, Error> {
+    match args.get(index) {
+        Some(Arg::Number(n)) => Ok(*n),
+        _ => Err(bad_request(format!("{} expects a number as argument {}", name, index + 1))),
+    }
+}
+
+fn map_values(series: Vec<Series>, label: impl Fn(&str) -> String, f: impl Fn(f64) -> f64) -> Vec<Series> {
+    series
+        .into_iter()
+        .map(|s| Series {
+            name: label(&s.name),
+            points: s.points.into_iter().map(|(t, v)| (t, v.map(&f))).collect(),
+        })
+        .collect()
+}
+
+/// Combines series point-by-point over the union of their timestamps.
+fn combine(label: String, series: &[Series], reduce: impl Fn(&[f64]) -> f64) -> Series {
+    let mut buckets: BTreeMap<i64, Vec<f64>> = BTreeMap::new();
+    for s in series {
+        for (t, v) in &s.points {
+            let bucket = buckets.entry(*t).or_default();
+            if let Some(v) = v {
+                bucket.push(*v);
+            }
+        }
+    }
+    Series {
+        name: label,
+        points: buckets
+            .into_iter()
+            .map(|(t, values)| (t, (!values.is_empty()).then(|| reduce(&values))))
+            .collect(),
+    }
+}
+
+fn evaluate(expr: &Expr, fetched: &HashMap<&str, Vec<Series>>) -> Result<Vec<Series>, Error> {
+    let (name, args) = match expr {
+        Expr::Path(path) => return Ok(fetched[path.as_str()].clone()),
+        Expr::Call(name, args) => (name.as_str(), args.as_slice()),
+    };
+
+    let series = series_args(name, args, fetched)?;
+    let names = || series.iter().map(|s| s.name.as_str()).collect::<Vec<_>>().join(",");
+    Ok(match name {
+        "alias" => {
+            let Some(Arg::Text(alias)) = args.get(1) else {
+                return Err(bad_request("alias expects a name as argument 2"));
+            };
+            map_values(series, |_| alias.clone(), |v| v)
+        }
+        "aliasByNode" => {
+            let nodes = args[1..]
+                .iter()
+                .map(|arg| match arg {
+                    Arg::Number(n) => Ok(*n as i64),
+                    _ => Err(bad_request("aliasByNode expects node indices")),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            map_values(
+                series,
+                |label| {
+                    // Nodes count within the innermost path, e.g. `ci.size` in `scale(ci.size,2)`
+                    let path = label.rsplit('(').next().unwrap_or(label);
+                    let path = path.split([',', ')']).next().unwrap_or(path);
+                    let parts: Vec<&str> = path.split('.').collect();
+                    nodes
+                        .iter()
+                        .filter_map(|&n| {
+                            let index = if n < 0 { parts.len() as i64 + n } else { n };
+                            parts.get(usize::try_from(index).ok()?).copied()
+                        })
+                        .collect::<Vec<_>>()
+                        .join(".")
+                },
+                |v| v,
+            )
+        }
+        "scale" => {
+            let factor = number_arg(name, args, 1)?;
+            map_values(series, |l| format!("scale({},{})", l, factor), |v| v * factor)
+        }
+        "offset" => {
+            let amount = number_arg(name, args, 1)?;
+            map_values(series, |l| format!("offset({},{})", l, amount), |v| v + amount)
+        }
+        "absolute" => map_values(series, |l| format!("absolute({})", l), f64::abs),
+        "derivative" => series
+            .into_iter()
+            .map(|s| {
+                let mut previous = None;
+                Series {
+                    name: format!("derivative({})", s.name),
+                    points: s
+                        .points
+                        .into_iter()
+                        .map(|(t, v)| {
+                            let delta = match (previous, v) {
+                                (Some(p), Some(v)) => Some(v - p),
+                                _ => None,
+                            };
+                            previous = v;
+                            (t, delta)
+                        })
+                        .collect(),
+                }
+            })
+            .collect(),
+        "movingAverage" => {
+            let window = number_arg(name, args, 1)?.max(1.0) as usize;
+            series
+                .into_iter()
+                .map(|s| {
+                    let points = (0..s.points.len())
+                        .map(|i| {
+                            let values: Vec<f64> = s.points[i.saturating_sub(window - 1)..=i]
+                                .iter()
+                                .filter_map(|(_, v)| *v)
+                                .collect();
+                            let mean = (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64);
+                            (s.points[i].0, mean)
+                        })
+                        .collect();
+                    Series {
+                        name: format!("movingAverage({},{})", s.name, window),
+                        points,
+                    }
+                })
+                .collect()
+        }
+        "sumSeries" => vec![combine(format!("sumSeries({})", names()), &series, |v| v.iter().sum())],
+        "averageSeries" => vec![combine(format!("averageSeries({})", names()), &series, |v| {
+            v.iter().sum::<f64>() / v.len() as f64
+        })],
+        "minSeries" => vec![combine(format!("minSeries({})", names()), &series, |v| {
+            v.iter().copied().fold(f64::INFINITY, f64::min)
+        })],
+        "maxSeries" => vec![combine(format!("maxSeries({})", names()), &series, |v| {
+            v.iter().copied().fold(f64::NEG_INFINITY, f64::max)
+        })],
+        _ => return Err(bad_request(format!("unsupported function `{}`", name))),
+    })
+}
+
+/// Parses Graphite's `from`/`until` forms: `now`, `-<n><unit>` or a Unix timestamp.
+fn parse_time(raw: &str, now: i64) -> Option<i64> {
+    if raw == "now" {
+        return Some(now);
+    }
+    let Some(relative) = raw.strip_prefix('-') else {
+        return raw.parse().ok();
+    };
+    let split = relative.find(|c: char| !c.is_ascii_digit())?;
+    let (amount, unit) = relative.split_at(split);
+    let amount: i64 = amount.parse().ok()?;
+    let seconds = match unit {
+        "s" | "sec" | "secs" | "second" | "seconds" => 1,
+        "m" | "min" | "mins" | "minute" | "minutes" => 60,
+        "h" | "hour" | "hours" => 3600,
+        "d" | "day" | "days" => 86400,
+        "w" | "week" | "weeks" => 7 * 86400,
+        "mon" | "month" | "months" => 30 * 86400,
+        "y" | "year" | "years" => 365 * 86400,
+        _ => return None,
+    };
+    now.checked_sub(amount.checked_mul(seconds)?)
+}
+
+#[derive(Serialize)]
+struct RenderedSeries {
+    target: String,
+    datapoints: Vec<(Option<f64>, i64)>,
+}
+
+/// `GET|POST /graphite/render?target=...&from=...&until=...&format=json|csv`
+pub async fn render(State(state): State<AppState>, RawForm(form): RawForm) -> Result<Response, Error> {
+    let mut targets = Vec::new();
+    let (mut from, mut until, mut format) = (DEFAULT_FROM.to_string(), "now".to_string(), "json".to_string());
+    for (key, value) in form_urlencoded::parse(&form) {
+        match key.as_ref() {
+            "target" => targets.push(value.into_owned()),
+            "from" => from = value.into_owned(),
+            "until" => until = value.into_owned(),
+            "format" => format = value.into_owned(),
+            _ => {}
+        }
+    }
+    if targets.len() > MAX_TARGETS {
+        return Err(bad_request(format!("at most {} targets per request", MAX_TARGETS)));
+    }
+
+    let now = Utc::now().timestamp();
+    let from = parse_time(&from, now).ok_or_else(|| bad_request(format!("invalid from `{}`", from)))?;
+    let until = parse_time(&until, now).ok_or_else(|| bad_request(format!("invalid until `{}`", until)))?;
+
+    let exprs = targets
+        .iter()
+        .map(|target| Parser::parse(target).map_err(bad_request))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut paths = Vec::new();
+    for expr in &exprs {
+        collect_paths(expr, &mut paths);
+    }
+    let mut fetched = HashMap::new();
+    for path in paths {
+        if !fetched.contains_key(path) {
+            fetched.insert(path, fetch_path(&state, path, from, until).await?);
+        }
+    }
+
+    let mut results = Vec::new();
+    for expr in &exprs {
+        results.extend(evaluate(expr, &fetched)?);
+    }
+
+    match format.as_str() {
+        "json" => Ok(Json(
+            results
+                .into_iter()
+                .map(|s| RenderedSeries {
+                    target: s.name,
+                    datapoints: s.points.into_iter().map(|(t, v)| (v, t)).collect(),
+                })
+                .collect::<Vec<_>>(),
+        )
+        .into_response()),
+        "csv" => {
+            let mut out = String::new();
+            for s in &results {
+                for (t, v) in &s.points {
+                    let time = DateTime::<Utc>::from_timestamp(*t, 0)
+                        .map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string())
+                        .unwrap_or_default();
+                    let value = v.map(|v| v.to_string()).unwrap_or_default();
+                    out.push_str(&format!("{},{},{}\n", s.name, time, value));
+                }
+            }
+            Ok(([("content-type", "text/csv")], out).into_response())
+        }
+        other => Err(bad_request(format!("unsupported format `{}` (expected json or csv)", other))),
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Node {
+    text: String,
+    id: String,
+    leaf: u8,
+    expandable: u8,
+    allow_children: u8,
+}
+
+async fn find_nodes(store: &dyn MetricStore, query: &str, policy: &crate::ids::IdPolicy) -> Result<Vec<Node>, StoreError> {
+    let Some((namespace, id)) = query.split_once('.') else {
+        // Top level: namespaces are the branches
+        let pattern = policy.normalize(query);
+        return Ok(store
+            .namespaces()
+            .await?
+            .into_iter()
+            .filter(|namespace| wildcard_match(&pattern, namespace))
+            .map(|namespace| Node {
+                text: namespace.clone(),
+                id: namespace,
+                leaf: 0,
+                expandable: 1,
+                allow_children: 1,
+            })
+            .collect());
+    };
+
+    let (namespace, pattern) = (policy.normalize(namespace), policy.normalize(id));
+    Ok(store
+        .ids(&namespace)
+        .await?
+        .into_iter()
+        .filter(|id| wildcard_match(&pattern, id))
+        .map(|id| Node {
+            id: format!("{}.{}", namespace, id),
+            text: id,
+            leaf: 1,
+            expandable: 0,
+            allow_children: 0,
+        })
+        .collect())
+}
+
+/// `GET /graphite/metrics/find?query=...`, used by dashboards to browse paths.
+pub async fn find(State(state): State<AppState>, RawForm(form): RawForm) -> Result<impl IntoResponse, Error> {
+    let query = form_urlencoded::parse(&form)
+        .find(|(key, _)| key == "query")
+        .map(|(_, value)| value.into_owned())
+        .unwrap_or_else(|| "*".to_string());
+
+    let nodes = find_nodes(state.store.as_ref(), &query, &state.config().id_policy)
+        .await
+        .map_err(|err| (errors::internal(err), "database error".to_string()))?;
+    Ok(Json(nodes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn series(name: &str, points: &[(i64, Option<f64>)]) -> Series {
+        Series {
+            name: name.to_string(),
+            points: points.to_vec(),
+        }
+    }
+
+    type Points = Vec<(i64, Option<f64>)>;
+
+    /// Evaluates `target` against `data`, as `(name, points)` pairs.
+    fn render(target: &str, data: &[(&str, Vec<Series>)]) -> Result<Vec<(String, Points)>, Error> {
+        let expr = Parser::parse(target).map_err(bad_request)?;
+        let fetched: HashMap<&str, Vec<Series>> = data.iter().map(|(path, series)| (*path, series.clone())).collect();
+        Ok(evaluate(&expr, &fetched)?.into_iter().map(|s| (s.name, s.points)).collect())
+    }
+
+    #[test]
+    fn parses_nested_calls() {
+        let expr = Parser::parse(" alias( scale(ci.build_time, -1.5e1) , 'build time') ").unwrap();
+        let Expr::Call(name, args) = &expr else { panic!("{:?}", expr) };
+        assert_eq!(name, "alias");
+        assert!(matches!(&args[1], Arg::Text(text) if text == "build time"));
+        let Arg::Series(Expr::Call(inner, inner_args)) = &args[0] else { panic!("{:?}", args) };
+        assert_eq!(inner, "scale");
+        assert!(matches!(&inner_args[0], Arg::Series(Expr::Path(path)) if path == "ci.build_time"));
+        assert!(matches!(inner_args[1], Arg::Number(n) if n == -15.0));
+
+        // Any whitespace separates arguments, not just ASCII
+        let expr = Parser::parse("sumSeries(ci.a,\u{a0}maxSeries(ci.b, web.*), \"ci.c\")").unwrap();
+        let mut paths = Vec::new();
+        collect_paths(&expr, &mut paths);
+        assert_eq!(paths, ["ci.a", "ci.b", "web.*"]);
+        assert!(matches!(Parser::parse("f()"), Ok(Expr::Call(_, args)) if args.is_empty()));
+    }
+
+    #[test]
+    fn rejects_malformed_targets() {
+        for (target, message) in [
+            ("", "expected a series path or function"),
+            ("scale(ci.a, 2", "expected `,` or `)` after argument to scale"),
+            ("alias(ci.a, 'open)", "unterminated string"),
+            ("scale(ci.a, 1-2)", "invalid number `1-2`"),
+            ("ci.a ci.b", "unexpected `ci.b` in target"),
+            ("scale(ci.a,)", "expected a series path or function"),
+        ] {
+            assert_eq!(Parser::parse(target).unwrap_err(), message, "{}", target);
+        }
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard_match("build_time", "build_time"));
+        assert!(!wildcard_match("build_time", "build_time_ms"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("build.*", "build.linux"));
+        assert!(wildcard_match("*.p99", "api.p99"));
+        assert!(wildcard_match("a*b*c", "a-b-b-c"));
+        assert!(!wildcard_match("a*b*c", "a-c-b"));
+        // The start and end can't overlap
+        assert!(!wildcard_match("ab*ba", "aba"));
+    }
+
+    #[test]
+    fn times() {
+        let now = 1_700_000_000;
+        assert_eq!(parse_time("now", now), Some(now));
+        assert_eq!(parse_time("1600000000", now), Some(1_600_000_000));
+        assert_eq!(parse_time("-30s", now), Some(now - 30));
+        assert_eq!(parse_time("-5min", now), Some(now - 300));
+        assert_eq!(parse_time("-24h", now), Some(now - 86400));
+        assert_eq!(parse_time("-2weeks", now), Some(now - 14 * 86400));
+        assert_eq!(parse_time("-1y", now), Some(now - 365 * 86400));
+        for raw in ["-24", "-h", "-1fortnight", "yesterday", "-99999999999999999y"] {
+            assert_eq!(parse_time(raw, now), None, "{}", raw);
+        }
+    }
+
+    #[test]
+    fn evaluates_functions() {
+        let data = [
+            ("ci.a", vec![series("ci.a", &[(1, Some(1.0)), (2, Some(-3.0)), (3, None), (4, Some(2.0))])]),
+            ("ci.b", vec![series("ci.b", &[(2, Some(10.0)), (5, Some(20.0))])]),
+        ];
+        let values = |target: &str| render(target, &data).unwrap().remove(0).1;
+        assert_eq!(values("scale(ci.a,2)"), [(1, Some(2.0)), (2, Some(-6.0)), (3, None), (4, Some(4.0))]);
+        assert_eq!(values("offset(ci.b,-10)"), [(2, Some(0.0)), (5, Some(10.0))]);
+        assert_eq!(values("absolute(ci.a)")[1], (2, Some(3.0)));
+        assert_eq!(values("derivative(ci.a)"), [(1, None), (2, Some(-4.0)), (3, None), (4, None)]);
+        assert_eq!(values("movingAverage(ci.a,2)"), [(1, Some(1.0)), (2, Some(-1.0)), (3, Some(-3.0)), (4, Some(2.0))]);
+        assert_eq!(values("sumSeries(ci.a,ci.b)"), [(1, Some(1.0)), (2, Some(7.0)), (3, None), (4, Some(2.0)), (5, Some(20.0))]);
+        assert_eq!(values("averageSeries(ci.a,ci.b)")[1], (2, Some(3.5)));
+        assert_eq!(values("minSeries(ci.a,ci.b)")[1], (2, Some(-3.0)));
+        assert_eq!(values("maxSeries(ci.a,ci.b)")[1], (2, Some(10.0)));
+    }
+
+    #[test]
+    fn names_results_like_graphite() {
+        let data = [("ci.*", vec![series("ci.build.linux", &[]), series("ci.build.mac", &[])])];
+        let names = |target: &str| render(target, &data).unwrap().into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+        assert_eq!(names("scale(offset(ci.*,1),2)"), ["scale(offset(ci.build.linux,1),2)", "scale(offset(ci.build.mac,1),2)"]);
+        assert_eq!(names("sumSeries(ci.*)"), ["sumSeries(ci.build.linux,ci.build.mac)"]);
+        assert_eq!(names("alias(ci.*, \"builds\")"), ["builds", "builds"]);
+        assert_eq!(names("aliasByNode(scale(ci.*,2),2)"), ["linux", "mac"]);
+        assert_eq!(names("aliasByNode(ci.*,0,-1)"), ["ci.linux", "ci.mac"]);
+    }
+
+    #[test]
+    fn rejects_bad_calls() {
+        let data = [("ci.a", vec![series("ci.a", &[(1, Some(1.0))])])];
+        let message = |target: &str| render(target, &data).unwrap_err().1;
+        assert_eq!(message("median(ci.a)"), "unsupported function `median`");
+        assert_eq!(message("scale(ci.a)"), "scale expects a number as argument 2");
+        assert_eq!(message("scale(ci.a,'2')"), "scale expects a number as argument 2");
+        assert_eq!(message("alias(ci.a)"), "alias expects a name as argument 2");
+        assert_eq!(message("aliasByNode(ci.a,'x')"), "aliasByNode expects node indices");
+        assert_eq!(message("sumSeries()"), "sumSeries needs a series argument");
+    }
+}
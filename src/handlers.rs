@@ -0,0 +1,17 @@
+//! The main handlers, for routing some of them in another axum application
+//! rather than taking the whole of [`router`](crate::router). Each takes an
+//! [`AppState`](crate::AppState) as its state, so routes using them need
+//! `with_state` with one.
+
+/// Writing points, one at a time or several series at once
+pub use crate::{post_metric, post_metrics};
+
+/// The index, namespace and chart pages
+pub use crate::{delete_metric, get_chart, get_chart_ascii, get_favicon, get_index, get_namespace, head_chart};
+
+pub use crate::badge::{get_badge_png, get_badge_svg, get_namespace_badge_png, get_namespace_badge_svg};
+pub use crate::bundle::{export_bundle, import_bundle};
+pub use crate::chart::{get_chart_png, get_chart_svg, get_og_png};
+pub use crate::embed::{get_embed, get_oembed};
+pub use crate::health::{get_healthz, get_readyz};
+pub use crate::prom::get_prometheus;
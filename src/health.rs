@@ -0,0 +1,36 @@
+//! Probes for Kubernetes and load balancers. `/healthz` answers whenever the
+//! process can serve a request at all, so a failing liveness probe means a
+//! restart is due; `/readyz` also runs a trivial query, so an instance whose
+//! database is locked up or gone is taken out of rotation without being
+//! killed.
+
+use std::time::Duration;
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde_json::json;
+use sqlx::sqlite::SqlitePool;
+
+/// A database that takes longer than this to answer `SELECT 1` isn't ready.
+const READY_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub async fn get_healthz() -> impl IntoResponse {
+    Json(json!({ "status": "ok" }))
+}
+
+pub async fn get_readyz(State(pool): State<SqlitePool>) -> impl IntoResponse {
+    let check = tokio::time::timeout(READY_TIMEOUT, sqlx::query_scalar!("SELECT 1").fetch_one(&pool)).await;
+    match check {
+        Ok(Ok(_)) => (StatusCode::OK, Json(json!({ "status": "ok" }))),
+        Ok(Err(err)) => {
+            tracing::warn!("Readiness check failed: {}", err);
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(json!({ "status": "unavailable", "error": "database error" })),
+            )
+        }
+        Err(_) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "unavailable", "error": "database timed out" })),
+        ),
+    }
+}
@@ -0,0 +1,209 @@
+//! `/{namespace}/{id}/heatmap`: a time-bucketed 2D histogram of a series, for
+//! metrics with so many points (request latency samples, say) that a line
+//! chart only shows an overplotted smear. Browsers get the heatmap page; API
+//! clients asking for JSON get the histogram itself.
+
+use askama::Template;
+use axum::{
+    extract::{Extension, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    domains::Domain,
+    errors,
+    ids::SeriesPath,
+    load_window_json, meta,
+    meta::Scale,
+    negotiate,
+    theme::ViewerTheme,
+    traces,
+    tz::ViewerTz,
+    AppState, BasePath, ChartPageQuery, MetricPoint, ViewLink,
+};
+
+const DEFAULT_COLUMNS: usize = 60;
+const DEFAULT_ROWS: usize = 30;
+const MAX_COLUMNS: usize = 500;
+const MAX_ROWS: usize = 200;
+
+/// The chart page's `?since=`, `?until=` and `?scale=`, plus bucket counts.
+#[derive(Deserialize)]
+pub struct HeatmapQuery {
+    since: Option<String>,
+    until: Option<String>,
+    scale: Option<Scale>,
+    tz: Option<String>,
+    /// Number of time buckets
+    columns: Option<usize>,
+    /// Number of value buckets
+    rows: Option<usize>,
+}
+
+/// Point counts per time and value bucket. Value buckets are spaced evenly
+/// on the series' scale, so on a log scale each row covers the same ratio.
+#[derive(Serialize)]
+pub struct Histogram {
+    /// First and last timestamps covered
+    pub start: i64,
+    pub end: i64,
+    /// Lowest and highest values covered
+    pub min: f64,
+    pub max: f64,
+    pub scale: Scale,
+    /// `counts[column][row]`, with row 0 holding the lowest values
+    pub counts: Vec<Vec<u32>>,
+    pub max_count: u32,
+    /// Points left out because a log scale can't place them
+    pub skipped: usize,
+}
+
+/// Buckets `data` into `columns` by `rows`; `None` when there is nothing to
+/// place.
+pub fn bin(data: &[MetricPoint], columns: usize, rows: usize, scale: Scale) -> Option<Histogram> {
+    let position = |value: f64| match scale {
+        Scale::Linear => Some(value),
+        Scale::Log => (value > 0.0).then(|| value.log10()),
+    };
+    let placed: Vec<(i64, f64)> = data
+        .iter()
+        .filter(|point| point.value.is_finite())
+        .filter_map(|point| Some((point.timestamp, position(point.value)?)))
+        .collect();
+
+    let start = placed.iter().map(|&(t, _)| t).min()?;
+    let end = placed.iter().map(|&(t, _)| t).max()?;
+    let low = placed.iter().map(|&(_, v)| v).fold(f64::INFINITY, f64::min);
+    let high = placed.iter().map(|&(_, v)| v).fold(f64::NEG_INFINITY, f64::max);
+
+    let span = (end - start + 1) as f64;
+    let mut counts = vec![vec![0u32; rows]; columns];
+    for &(timestamp, value) in &placed {
+        let column = ((timestamp - start) as f64 / span * columns as f64) as usize;
+        // A flat series still needs a row to land in
+        let row = if high > low {
+            ((value - low) / (high - low) * rows as f64) as usize
+        } else {
+            0
+        };
+        counts[column.min(columns - 1)][row.min(rows - 1)] += 1;
+    }
+
+    let unplace = |value: f64| match scale {
+        Scale::Linear => value,
+        Scale::Log => 10f64.powf(value),
+    };
+    Some(Histogram {
+        start,
+        end,
+        min: unplace(low),
+        max: unplace(high),
+        scale,
+        max_count: counts.iter().flatten().copied().max().unwrap_or(0),
+        counts,
+        skipped: data.len() - placed.len(),
+    })
+}
+
+#[derive(Serialize)]
+struct HeatmapResponse<'a> {
+    namespace: &'a str,
+    id: &'a str,
+    /// `null` when the window has no points
+    histogram: Option<&'a Histogram>,
+}
+
+#[derive(Template)]
+#[template(path = "heatmap.html")]
+struct HeatmapTemplate {
+    base_path: BasePath,
+    namespace: String,
+    id: String,
+    histogram_json: String,
+    /// Query string for the line chart over the same window
+    chart_query: String,
+    windows: Vec<ViewLink>,
+    scales: Vec<ViewLink>,
+    /// Site title when served from a custom domain
+    brand: Option<String>,
+    theme: &'static str,
+    /// Minutes east of UTC to label times in, or `null` for the browser's zone
+    tz_json: String,
+    tz_label: String,
+}
+
+pub async fn get_heatmap(
+    SeriesPath(namespace, id): SeriesPath,
+    Query(query): Query<HeatmapQuery>,
+    State(state): State<AppState>,
+    domain: Option<Extension<Domain>>,
+    ViewerTheme(theme): ViewerTheme,
+    tz: ViewerTz,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let view = ChartPageQuery {
+        since: query.since,
+        until: query.until,
+        scale: query.scale,
+        tz: query.tz,
+        ..ChartPageQuery::default()
+    };
+    let bounds = match view.resolve() {
+        Ok(bounds) => bounds,
+        Err(rejection) => return Ok(rejection.into_response()),
+    };
+    let columns = query.columns.unwrap_or(DEFAULT_COLUMNS);
+    let rows = query.rows.unwrap_or(DEFAULT_ROWS);
+    if !(1..=MAX_COLUMNS).contains(&columns) || !(1..=MAX_ROWS).contains(&rows) {
+        return Ok((StatusCode::BAD_REQUEST, "columns must be 1 to 500 and rows 1 to 200").into_response());
+    }
+
+    let data_json = match load_window_json(&state, &namespace, &id, bounds).await {
+        Ok(data_json) => data_json,
+        Err(err) => return err.respond(),
+    };
+    let data: Vec<MetricPoint> = serde_json::from_str(&data_json).map_err(errors::internal)?;
+    let scale = match view.scale {
+        Some(scale) => scale,
+        None => meta::load(&state.pool, &namespace, &id)
+            .await
+            .map_err(errors::internal)?
+            .scale
+            .unwrap_or_default(),
+    };
+    let histogram = bin(&data, columns, rows, scale);
+
+    if negotiate(&headers, &["text/html", "application/json"]) == "application/json" {
+        let mut response = Json(HeatmapResponse {
+            namespace: &namespace,
+            id: &id,
+            histogram: histogram.as_ref(),
+        })
+        .into_response();
+        response.headers_mut().insert("vary", "accept".parse().unwrap());
+        return Ok(response);
+    }
+
+    let template = HeatmapTemplate {
+        base_path: state.base_path.clone(),
+        chart_query: view.href(),
+        windows: view.window_links(),
+        scales: view.scale_links(scale),
+        namespace,
+        id,
+        histogram_json: serde_json::to_string(&histogram).unwrap_or_default(),
+        brand: domain.map(|d| d.title.clone().unwrap_or_else(|| d.namespace.clone())),
+        theme: theme.as_str(),
+        tz_json: tz.script_json(),
+        tz_label: tz.label_or("your browser's timezone"),
+    };
+    let mut response = traces::render(&template)
+        .map(Html)
+        .map_err(errors::internal)?
+        .into_response();
+    response.headers_mut().insert("vary", "accept".parse().unwrap());
+    Ok(response)
+}
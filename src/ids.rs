@@ -0,0 +1,101 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{FromRef, FromRequestParts, Path},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::config::Config;
+
+/// How namespace and metric ids are normalized before they reach storage.
+/// The same rules run on every write and every read, so `CI/Build-Time` and
+/// `ci/build_time` can be made to address one series.
+///
+/// Changing the policy does not rewrite points already stored under their
+/// old spelling.
+#[derive(Clone, Debug)]
+pub struct IdPolicy {
+    /// Lowercase ids so matching ignores case
+    pub case_insensitive: bool,
+    /// Treat `-` and `_` as the same character (stored as `_`)
+    pub fold_separators: bool,
+    /// Longest metric id accepted, in characters
+    pub max_length: usize,
+}
+
+impl Default for IdPolicy {
+    fn default() -> Self {
+        IdPolicy {
+            case_insensitive: false,
+            fold_separators: false,
+            max_length: 256,
+        }
+    }
+}
+
+impl IdPolicy {
+    /// Why a metric id can't be used, if it's longer than `max_length`.
+    pub fn check(&self, id: &str) -> Result<(), String> {
+        if id.chars().count() > self.max_length {
+            return Err(format!("metric ids can be at most {} characters", self.max_length));
+        }
+        Ok(())
+    }
+
+    pub fn normalize(&self, raw: &str) -> String {
+        let mut normalized = String::with_capacity(raw.len());
+        for c in raw.chars() {
+            let c = if self.fold_separators && c == '-' { '_' } else { c };
+            if self.case_insensitive {
+                normalized.extend(c.to_lowercase());
+            } else {
+                normalized.push(c);
+            }
+        }
+        normalized
+    }
+}
+
+/// `/{namespace}/...` path parameter, normalized by the configured [`IdPolicy`].
+pub struct NamespacePath(pub String);
+
+/// `/{namespace}/{id}/...` path parameters, normalized by the configured [`IdPolicy`];
+/// ids longer than it allows are turned away with a `422`.
+pub struct SeriesPath(pub String, pub String);
+
+impl<S> FromRequestParts<S> for NamespacePath
+where
+    S: Send + Sync,
+    Arc<Config>: FromRef<S>,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(namespace) = Path::<String>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let policy = &Arc::<Config>::from_ref(state).id_policy;
+        Ok(NamespacePath(policy.normalize(&namespace)))
+    }
+}
+
+impl<S> FromRequestParts<S> for SeriesPath
+where
+    S: Send + Sync,
+    Arc<Config>: FromRef<S>,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path((namespace, id)) = Path::<(String, String)>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let policy = &Arc::<Config>::from_ref(state).id_policy;
+        let id = policy.normalize(&id);
+        policy
+            .check(&id)
+            .map_err(|message| (StatusCode::UNPROCESSABLE_ENTITY, message).into_response())?;
+        Ok(SeriesPath(policy.normalize(&namespace), id))
+    }
+}
@@ -0,0 +1,120 @@
+//! `POST /api/v1/namespaces/{namespace}/import`: pulls a namespace from
+//! another somnial instance into this one, for moving between hosts or
+//! folding several instances into one. The other instance is asked for the
+//! namespace's bundle (`GET /api/v1/namespaces/{namespace}/bundle`), so it
+//! needs bundle exports switched on.
+//!
+//! Unlike uploading a bundle, importing may land in a namespace that already
+//! has data. A point is skipped when its series already has one with the
+//! same timestamp and value, so running the same import again only brings
+//! over what was written since. Display defaults, markers, the README and
+//! dashboards come along too, without replacing what the namespace has.
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    auth,
+    bundle::{self, Bundle},
+    client::{self, Endpoint},
+    ids::NamespacePath,
+    rollup, AppState,
+};
+
+/// Characters left alone in the remote namespace path segment
+const SEGMENT_CHARACTERS: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(json!({ "error": message })))
+}
+
+fn database_error<E: std::fmt::Display>(err: E) -> ApiError {
+    crate::errors::report(&err);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+/// `{"source": "https://old.example.com", "namespace": "builds"}`
+#[derive(Deserialize)]
+pub struct ImportRequest {
+    /// Base URL of the instance to pull from
+    source: String,
+    /// Namespace to pull there; the one being imported into by default
+    namespace: Option<String>,
+}
+
+pub async fn post_import(
+    NamespacePath(namespace): NamespacePath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<ImportRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+
+    let endpoint = Endpoint::parse(&request.source).map_err(|err| error(StatusCode::UNPROCESSABLE_ENTITY, &err))?;
+    let remote = request.namespace.as_deref().unwrap_or(&namespace);
+    // Pulled bundles are held to the same limit as uploaded ones
+    let max_bytes = state.config().limits.max_import_bytes;
+    let mut bundle = fetch_bundle(&request.source, &endpoint, remote, max_bytes).await?;
+
+    // The other instance may normalize ids differently, so they go through ours
+    let policy = &state.config().id_policy;
+    bundle.normalize_ids(policy);
+    bundle
+        .check_ids(policy)
+        .map_err(|message| error(StatusCode::UNPROCESSABLE_ENTITY, &message))?;
+    let points = bundle.points();
+
+    let inserted = state
+        .write_store(|| state.store.insert_new(&namespace, &points))
+        .await
+        .map_err(database_error)?;
+    bundle::restore_settings(&state, &namespace, &bundle)
+        .await
+        .map_err(database_error)?;
+    state.invalidate_namespace(&namespace);
+    if inserted > 0 {
+        rollup::rebuild_namespace(&state, &namespace)
+            .await
+            .map_err(database_error)?;
+    }
+
+    Ok(Json(json!({
+        "namespace": namespace,
+        "source": request.source,
+        "points": points.len(),
+        "inserted": inserted,
+    })))
+}
+
+async fn fetch_bundle(
+    source: &str,
+    endpoint: &Endpoint,
+    namespace: &str,
+    max_bytes: usize,
+) -> Result<Bundle, ApiError> {
+    let unreachable = |err: client::ClientError| {
+        tracing::warn!("Fetching namespace {} from {} failed: {}", namespace, source, err);
+        error(StatusCode::BAD_GATEWAY, "couldn't fetch the namespace from the source")
+    };
+    let url = endpoint.join(&format!(
+        "/api/v1/namespaces/{}/bundle",
+        utf8_percent_encode(namespace, SEGMENT_CHARACTERS)
+    ));
+    let request = client::http().get(url).header("Accept", "application/gzip");
+    let response = client::send(request, Some(max_bytes)).await.map_err(unreachable)?;
+    match response.status {
+        200 => {}
+        404 => return Err(error(StatusCode::NOT_FOUND, "the source has no such namespace, or doesn't export bundles")),
+        status => return Err(unreachable(format!("it answered {}", status).into())),
+    }
+    Bundle::from_gzip(&response.body).map_err(|err| unreachable(err.to_string().into()))
+}
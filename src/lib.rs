@@ -0,0 +1,1858 @@
+//! Metrics collection and charts over HTTP. The `somnial` binary serves
+//! them on their own; [`build_router`] gives the same routes to an existing
+//! axum application, and [`test::TestServer`] drives them in tests.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! use somnial::{build_router, Config};
+//! use sqlx::sqlite::SqlitePool;
+//!
+//! let pool = SqlitePool::connect("sqlite:metrics.db?mode=rwc").await?;
+//! // Routes and links all start with the base path, so the router is
+//! // merged rather than nested
+//! let config = Config { base_path: "/metrics".to_string(), ..Config::default() };
+//! let app = axum::Router::new()
+//!     .route("/", axum::routing::get(|| async { "my app" }))
+//!     .merge(build_router(pool, config).await?);
+//! # let _ = app;
+//! # Ok(())
+//! # }
+//! ```
+
+mod alerts;
+mod aliases;
+mod anomaly;
+mod archive;
+mod ascii;
+mod auth;
+mod backup;
+mod badge;
+mod bundle;
+mod cache;
+mod caps;
+mod chart;
+mod client;
+mod compression;
+mod check;
+mod clone;
+pub mod cli;
+pub mod config;
+mod config_file;
+mod correlation;
+mod daily;
+mod digests;
+mod dashboards;
+mod db;
+mod detection;
+mod domains;
+mod embed;
+mod errors;
+mod export;
+mod firehose;
+mod forecast;
+mod github;
+mod graphite;
+pub mod handlers;
+mod health;
+mod heatmap;
+mod ids;
+mod import;
+mod limits;
+mod mail;
+mod maintenance;
+mod markdown;
+mod markers;
+mod memory_store;
+mod merge;
+mod meta;
+mod notifiers;
+mod overlay;
+mod owners;
+mod points;
+mod postgres_store;
+mod precision;
+mod prom;
+mod purge;
+mod query;
+mod readme;
+mod reload;
+mod retention;
+mod request_log;
+mod rollup;
+pub mod runtime;
+mod shortlink;
+mod snapshot;
+mod stats;
+mod status;
+pub mod store;
+mod suggest;
+pub mod test;
+mod theme;
+mod tls;
+mod tokens;
+mod traces;
+mod trash;
+mod trend;
+mod tz;
+mod webhooks;
+
+use std::future::{Future, IntoFuture};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use askama::Template;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use axum::{
+    body::Body,
+    extract::{DefaultBodyLimit, Extension, FromRef, Query, RawQuery, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect, Response},
+    middleware,
+    routing::{delete, get, post, put},
+    Router,
+};
+use chrono::Utc;
+pub use config::{Config, Sources};
+use ids::{NamespacePath, SeriesPath};
+use limits::ReadError;
+use reload::LiveConfig;
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{SqliteAutoVacuum, SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use alerts::AlertRules;
+use aliases::AliasMap;
+use cache::SeriesCache;
+use domains::{Domain, DomainMap};
+use firehose::Firehose;
+use meta::{ChartType, Scale};
+use serde_json::value::RawValue;
+use stats::SelfMetrics;
+use store::{ListFrom, ListPosition, MetricListing, MetricStore, PostgresStore, SqliteStore, StoreError};
+use theme::{PageTheme, ViewerTheme};
+use tz::ViewerTz;
+use trend::Trend;
+use tokens::TokenLog;
+use webhooks::Webhooks;
+
+/// Everything handlers share: database pools, configuration and caches.
+#[derive(Clone)]
+pub struct AppState {
+    pool: SqlitePool,
+    /// Points themselves; everything else still reads `pool`
+    store: Arc<dyn MetricStore>,
+    /// Connections opened with `SQLITE_OPEN_READONLY`, for user-supplied SQL
+    read_only_pool: SqlitePool,
+    /// Read through [`AppState::config`], since a reload can replace it
+    settings: Arc<LiveConfig>,
+    metrics: Arc<SelfMetrics>,
+    chart_cache: Arc<SeriesCache>,
+    badge_cache: Arc<SeriesCache<badge::RenderedBadge>>,
+    domains: Arc<DomainMap>,
+    aliases: Arc<AliasMap>,
+    alerts: Arc<AlertRules>,
+    webhooks: Arc<Webhooks>,
+    tokens: Arc<TokenLog>,
+    firehose: Arc<Firehose>,
+    pending_deletions: Arc<purge::PendingDeletions>,
+    /// Kept apart from the settings, since only a restart changes it
+    base_path: BasePath,
+}
+
+impl AppState {
+    /// Opens (creating if needed) the configured database and runs migrations.
+    pub async fn connect(config: Config, sources: Sources) -> Result<Self, Box<dyn std::error::Error>> {
+        // With points in Postgres, SQLite only keeps the settings
+        let sqlite_url = match config.postgres_url() {
+            Some(_) => &config.settings_database_url,
+            None => &config.database_url,
+        };
+        let options = sqlite_url
+            .parse::<SqliteConnectOptions>()?
+            .create_if_missing(true)
+            .synchronous(config.sqlite.synchronous)
+            .busy_timeout(config.sqlite.busy_timeout);
+        
+        // The journal and vacuum modes are stored in the file, so only the
+        // writable pool sets them
+        let writable = options
+            .clone()
+            .journal_mode(config.sqlite.journal_mode)
+            .auto_vacuum(SqliteAutoVacuum::Incremental);
+        let pool = SqlitePool::connect_with(writable).await?;
+        let read_only_pool = SqlitePoolOptions::new()
+            .max_connections(2)
+            .connect_with(options.read_only(true))
+            .await?;
+        
+        let postgres_url = config.postgres_url().map(str::to_string);
+        let state = Self::new(pool, read_only_pool, config, sources).await?;
+        match postgres_url {
+            Some(url) => Ok(state.with_store(Arc::new(PostgresStore::connect(&url).await?)).await?),
+            None => Ok(state),
+        }
+    }
+    
+    /// Builds the state around a pool the caller opened, running migrations
+    /// on it; the read-only connections for user-supplied SQL are opened
+    /// with the same options. Settings come from `config` alone, though a
+    /// reload reads them again from the environment.
+    pub async fn from_pool(pool: SqlitePool, config: Config) -> Result<Self, Box<dyn std::error::Error>> {
+        let options = pool.connect_options().as_ref().clone().read_only(true);
+        let read_only_pool = SqlitePoolOptions::new()
+            .max_connections(2)
+            .connect_with(options)
+            .await?;
+        Self::new(pool, read_only_pool, config, Sources::default()).await
+    }
+    
+    /// Builds the state around existing pools, running migrations on `pool`.
+    pub(crate) async fn new(
+        pool: SqlitePool,
+        read_only_pool: SqlitePool,
+        config: Config,
+        sources: Sources,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        // Run migrations
+        sqlx::migrate!("./migrations").run(&pool).await?;
+        let domains = Arc::new(DomainMap::load(&pool).await?);
+        let aliases = Arc::new(AliasMap::load(&pool).await?);
+        let store = SqliteStore::new(pool.clone());
+        let moved = store.move_unpartitioned().await?;
+        if moved > 0 {
+            tracing::info!("Moved {} points into their months' tables", moved);
+        }
+        let store: Arc<dyn MetricStore> = Arc::new(store);
+        let alerts = Arc::new(AlertRules::load(&pool, store.as_ref()).await?);
+        let webhooks = Arc::new(Webhooks::load(&pool).await?);
+        
+        Ok(AppState {
+            base_path: BasePath(config.base_path.as_str().into()),
+            store,
+            pool,
+            read_only_pool,
+            domains,
+            aliases,
+            alerts,
+            webhooks,
+            chart_cache: Arc::new(SeriesCache::new(config.chart_cache_bytes)),
+            badge_cache: Arc::new(SeriesCache::new(config.badge_cache_bytes)),
+            settings: Arc::new(LiveConfig::new(config, sources)),
+            metrics: Arc::new(SelfMetrics::default()),
+            tokens: Arc::new(TokenLog::default()),
+            firehose: Arc::new(Firehose::default()),
+            pending_deletions: Arc::new(purge::PendingDeletions::default()),
+        })
+    }
+    
+    /// Keeps points in `store` instead of the database, which still holds
+    /// everything else. Alert state that depends on points is worked out
+    /// again from the new store.
+    pub async fn with_store(mut self, store: Arc<dyn MetricStore>) -> Result<Self, StoreError> {
+        self.alerts.reload(&self.pool, store.as_ref()).await?;
+        self.store = store;
+        Ok(self)
+    }
+    
+    /// The settings in effect; a reload replaces them, so they're taken
+    /// afresh rather than kept.
+    pub(crate) fn config(&self) -> Arc<Config> {
+        self.settings.get()
+    }
+    
+    /// The `BASE_PATH` every link starts with, such as `/metrics`, or empty.
+    pub(crate) fn base_path(&self) -> &str {
+        &self.base_path.0
+    }
+    
+    /// Runs a write statement under the configured busy-retry policy.
+    async fn write<T, F, Fut>(&self, op: F) -> Result<T, sqlx::Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, sqlx::Error>>,
+    {
+        db::retry_busy(&self.config().busy_retry, &self.metrics, op).await
+    }
+
+    /// Runs a point store write under the same busy-retry policy.
+    async fn write_store<T, F, Fut>(&self, op: F) -> Result<T, StoreError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, StoreError>>,
+    {
+        db::retry_busy(&self.config().busy_retry, &self.metrics, op).await
+    }
+    
+    /// Drops everything cached for a series after it was written to.
+    fn invalidate_series(&self, namespace: &str, id: &str) {
+        self.chart_cache.invalidate(namespace, id);
+        self.badge_cache.invalidate(namespace, id);
+        self.badge_cache.invalidate(namespace, badge::COMBINED_BADGE_KEY);
+    }
+    
+    fn invalidate_namespace(&self, namespace: &str) {
+        self.chart_cache.invalidate_namespace(namespace);
+        self.badge_cache.invalidate_namespace(namespace);
+    }
+
+    /// Drops everything cached, after the database was replaced wholesale.
+    fn invalidate_all(&self) {
+        self.chart_cache.clear();
+        self.badge_cache.clear();
+    }
+}
+
+impl FromRef<AppState> for SqlitePool {
+    fn from_ref(state: &AppState) -> Self {
+        state.pool.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<dyn MetricStore> {
+    fn from_ref(state: &AppState) -> Self {
+        state.store.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Config> {
+    fn from_ref(state: &AppState) -> Self {
+        state.config()
+    }
+}
+
+/// The `BASE_PATH` every link starts with, such as `/metrics`, or empty,
+/// for handlers and the templates they fill in.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct BasePath(Arc<str>);
+
+impl std::fmt::Display for BasePath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::ops::Deref for BasePath {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromRef<AppState> for BasePath {
+    fn from_ref(state: &AppState) -> Self {
+        state.base_path.clone()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PostMetricQuery {
+    value: f64,
+    /// The commit the value was measured at, shown with the point and used
+    /// for GitHub commit statuses
+    #[serde(alias = "commit")]
+    sha: Option<String>,
+    branch: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PaginationQuery {
+    after: Option<String>,
+    before: Option<String>,
+    /// Page numbers from before cursors, redirected to the cursor for them
+    page: Option<i64>,
+}
+
+/// One point of a series, as the JSON APIs send and take it.
+#[derive(Serialize, Deserialize)]
+pub struct MetricPoint {
+    pub timestamp: i64,
+    pub value: f64,
+    /// The commit and branch the point was measured at, if it was sent with them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+}
+
+/// Checks the `sha` and `branch` a point was sent with, treating empty ones
+/// as absent.
+fn revision(sha: Option<String>, branch: Option<String>) -> Result<(Option<String>, Option<String>), &'static str> {
+    let sha = sha.filter(|sha| !sha.is_empty()).map(|sha| sha.to_ascii_lowercase());
+    let branch = branch.filter(|branch| !branch.is_empty());
+    if sha.as_deref().is_some_and(|sha| !github::valid_commit(sha)) {
+        return Err("sha must be a commit SHA");
+    }
+    if branch.as_deref().is_some_and(|branch| !github::valid_branch(branch)) {
+        return Err("branch must be a branch name");
+    }
+    Ok((sha, branch))
+}
+
+#[derive(Template)]
+#[template(path = "index.html")]
+struct IndexTemplate {
+    base_path: BasePath,
+    theme: &'static str,
+}
+
+#[derive(Template)]
+#[template(path = "chart.html")]
+struct ChartTemplate {
+    base_path: BasePath,
+    namespace: String,
+    id: String,
+    /// What the metric measures, from its metadata
+    description: Option<String>,
+    /// When the metric will be deleted for inactivity, once that's near
+    expires: Option<String>,
+    data_json: String,
+    /// `{unit, decimals}` for axis and tooltip labels
+    format_json: String,
+    /// Figures over the plotted window, if it has any points
+    stats: Option<SeriesStats>,
+    /// Namespace-wide deploy and release markers in the plotted window
+    markers_json: String,
+    chart_images: bool,
+    /// Advertise the oEmbed endpoint for this chart
+    embeds: bool,
+    windows: Vec<ViewLink>,
+    scales: Vec<ViewLink>,
+    types: Vec<ViewLink>,
+    /// Query string for the heatmap and daily views over the same window
+    view_query: String,
+    trends: Vec<ViewLink>,
+    /// `{label, points}` for the trend series, or `null`
+    trend_json: String,
+    outliers: Vec<ViewLink>,
+    /// Flagged points, or `null` when not asked for
+    anomalies_json: String,
+    /// Anomalies background detection recorded in the window, or `null`
+    /// when it doesn't run
+    detected_json: String,
+    /// Y-axis scale, `linear` or `log`
+    scale: &'static str,
+    /// How the points are drawn: `line`, `area`, `step`, `bar` or `scatter`
+    chart_type: &'static str,
+    /// Scheme and host the page was requested on, for absolute preview URLs
+    origin: String,
+    /// Site title when served from a custom domain
+    brand: Option<String>,
+    theme: &'static str,
+    /// Minutes east of UTC to draw the axis in, or `null` for the browser's zone
+    tz_json: String,
+    tz_label: String,
+}
+
+#[derive(Template)]
+#[template(path = "namespace.html")]
+struct NamespaceTemplate {
+    base_path: BasePath,
+    namespace: String,
+    /// The namespace's README, already rendered and escaped
+    readme_html: Option<String>,
+    charts: Vec<ChartInfo>,
+    prev_cursor: Option<String>,
+    next_cursor: Option<String>,
+    page: i64,
+    /// Site title when served from a custom domain
+    brand: Option<String>,
+    theme: &'static str,
+    tz_label: String,
+}
+
+#[derive(Serialize)]
+struct ChartInfo {
+    id: String,
+    point_count: i64,
+    last_updated: String,
+    /// When the metric will be deleted for inactivity, once that's near
+    expires: Option<String>,
+    /// Whether the metric's heartbeat rule is firing
+    silent: bool,
+}
+
+pub async fn post_metric(
+    SeriesPath(namespace, id): SeriesPath,
+    Query(params): Query<PostMetricQuery>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let (sha, branch) = revision(params.sha, params.branch).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let timestamp = Utc::now().timestamp();
+    let value = precision::load(&state.pool, &namespace, &id)
+        .await
+        .map_err(errors::internal)?
+        .apply(params.value);
+    let cap = caps::load_namespace(&state.pool, &namespace)
+        .await
+        .map_err(errors::internal)?
+        .get(&id);
+    
+    let (namespace, id) = (&namespace, &id);
+    let points = [(
+        id.as_str(),
+        MetricPoint {
+            timestamp,
+            value,
+            sha: sha.clone(),
+            branch: branch.clone(),
+        },
+    )];
+    let result = state.write_store(|| state.store.insert(namespace, &points)).await;
+    
+    match result {
+        Ok(inserted) => {
+            state.metrics.ingest_points.fetch_add(inserted, Ordering::Relaxed);
+            state.invalidate_series(namespace, id);
+            state.firehose.publish(namespace, id, value, timestamp);
+            aliases::forget_written(&state, namespace, &[id.as_str()]).await;
+            webhooks::publish(&state, namespace, &[(id.as_str(), value)], timestamp);
+            alerts::evaluate(&state, namespace, &[(id.as_str(), value)], timestamp).await;
+            if let Some(sha) = &sha {
+                github::report(&state, namespace, &[(id.as_str(), value)], sha, branch.as_deref()).await;
+            }
+            if let Some(cap) = cap {
+                caps::enforce(&state, namespace, id, cap).await;
+            }
+            Ok(StatusCode::OK)
+        }
+        Err(err) => Err(errors::internal(err)),
+    }
+}
+
+/// Parses repeated `m=<id>:<value>` pairs; the value follows the last colon.
+fn parse_fan_out(query: &str, policy: &ids::IdPolicy) -> Option<Vec<(String, f64)>> {
+    let mut points = Vec::new();
+    for (key, pair) in form_urlencoded::parse(query.as_bytes()) {
+        if key != "m" {
+            continue;
+        }
+        let (id, value) = pair.rsplit_once(':')?;
+        if id.is_empty() {
+            return None;
+        }
+        points.push((policy.normalize(id), value.trim().parse().ok()?));
+    }
+    (!points.is_empty()).then_some(points)
+}
+
+/// Records several series in one request, all at the same timestamp and
+/// in a single transaction.
+pub async fn post_metrics(
+    NamespacePath(namespace): NamespacePath,
+    RawQuery(query): RawQuery,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let mut points = parse_fan_out(query.as_deref().unwrap_or(""), &state.config().id_policy)
+        .ok_or((StatusCode::BAD_REQUEST, "expected one or more m=<id>:<value> pairs".to_string()))?;
+    for (id, _) in &points {
+        state
+            .config()
+            .id_policy
+            .check(id)
+            .map_err(|message| (StatusCode::UNPROCESSABLE_ENTITY, message))?;
+    }
+    let (mut sha, mut branch) = (None, None);
+    for (key, value) in form_urlencoded::parse(query.as_deref().unwrap_or("").as_bytes()) {
+        match &*key {
+            "sha" | "commit" => sha = Some(value.into_owned()),
+            "branch" => branch = Some(value.into_owned()),
+            _ => {}
+        }
+    }
+    let (sha, branch) = revision(sha, branch).map_err(|message| (StatusCode::BAD_REQUEST, message.to_string()))?;
+    let timestamp = Utc::now().timestamp();
+
+    let precisions = precision::load_namespace(&state.pool, &namespace)
+        .await
+        .map_err(|err| (errors::internal(err), "database error".to_string()))?;
+    for (id, value) in &mut points {
+        if let Some(precision) = precisions.get(id) {
+            *value = precision.apply(*value);
+        }
+    }
+    let caps = caps::load_namespace(&state.pool, &namespace)
+        .await
+        .map_err(|err| (errors::internal(err), "database error".to_string()))?;
+    
+    let namespace = &namespace;
+    let rows: Vec<_> = points
+        .iter()
+        .map(|(id, value)| {
+            let point = MetricPoint {
+                timestamp,
+                value: *value,
+                sha: sha.clone(),
+                branch: branch.clone(),
+            };
+            (id.as_str(), point)
+        })
+        .collect();
+    let result = state.write_store(|| state.store.insert(namespace, &rows)).await;
+    
+    match result {
+        Ok(inserted) => {
+            state.metrics.ingest_points.fetch_add(inserted, Ordering::Relaxed);
+            for (id, value) in &points {
+                state.invalidate_series(namespace, id);
+                state.firehose.publish(namespace, id, *value, timestamp);
+            }
+            for (id, _) in &points {
+                if let Some(cap) = caps.get(id) {
+                    caps::enforce(&state, namespace, id, cap).await;
+                }
+            }
+            let ids: Vec<&str> = points.iter().map(|(id, _)| id.as_str()).collect();
+            aliases::forget_written(&state, namespace, &ids).await;
+            let values: Vec<(&str, f64)> = points.iter().map(|(id, value)| (id.as_str(), *value)).collect();
+            webhooks::publish(&state, namespace, &values, timestamp);
+            alerts::evaluate(&state, namespace, &values, timestamp).await;
+            if let Some(sha) = &sha {
+                github::report(&state, namespace, &values, sha, branch.as_deref()).await;
+            }
+            Ok(StatusCode::OK)
+        }
+        Err(err) => Err((errors::internal(err), "database error".to_string())),
+    }
+}
+
+#[derive(Serialize)]
+struct SeriesResponse<'a> {
+    namespace: &'a str,
+    id: &'a str,
+    points: &'a RawValue,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trend: Option<TrendSeries>,
+    /// Points flagged by `?anomalies=`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    anomalies: Option<Vec<anomaly::Anomaly>>,
+}
+
+/// A trend drawn alongside the series, as asked for with `?trend=`.
+#[derive(Serialize)]
+struct TrendSeries {
+    label: String,
+    points: Vec<MetricPoint>,
+}
+
+/// Picks the first of `offered` that the request's Accept header rates highest.
+/// A type's quality comes from the most specific range matching it, so
+/// `text/plain;q=0, */*` rules out plain text; exact media types win over
+/// `type/*` and `*/*` ranges of the same quality, and a missing or
+/// unparseable header selects the first offered type.
+fn negotiate<'a>(headers: &axum::http::HeaderMap, offered: &[&'a str]) -> &'a str {
+    let accept = match headers.get("accept").and_then(|v| v.to_str().ok()) {
+        Some(accept) if !accept.trim().is_empty() => accept,
+        _ => return offered[0],
+    };
+
+    let mut best: Option<(&str, f32, u8)> = None;
+    for candidate in offered {
+        let (main_type, _) = candidate.split_once('/').unwrap_or((candidate, ""));
+        let mut rating: Option<(f32, u8)> = None;
+        for range in accept.split(',') {
+            let mut parts = range.split(';');
+            let media = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+            let quality = parts
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            let specificity = if media == *candidate {
+                2
+            } else if media == format!("{}/*", main_type) {
+                1
+            } else if media == "*/*" {
+                0
+            } else {
+                continue;
+            };
+
+            if rating.is_none_or(|(q, s)| specificity > s || (specificity == s && quality > q)) {
+                rating = Some((quality, specificity));
+            }
+        }
+        if let Some((quality, specificity)) = rating
+            && quality > 0.0
+            && best.is_none_or(|(_, q, s)| quality > q || (quality == q && specificity > s))
+        {
+            best = Some((candidate, quality, specificity));
+        }
+    }
+
+    best.map(|(candidate, _, _)| candidate).unwrap_or(offered[0])
+}
+
+/// The chart page's query: `?since=` and `?until=`, each a Unix timestamp
+/// or a span back from now such as `90m`, `24h` or `7d`, plus `?scale=`
+/// and `?type=` to override the metric's stored display defaults, and
+/// `?trend=` for a fitted line or rolling median over the series, and
+/// `?anomalies=N` to flag points N standard deviations off their baseline.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ChartPageQuery {
+    since: Option<String>,
+    until: Option<String>,
+    scale: Option<Scale>,
+    #[serde(rename = "type")]
+    chart_type: Option<ChartType>,
+    trend: Option<Trend>,
+    anomalies: Option<f64>,
+    /// Read by [`ViewerTz`]; kept here so view links carry it along
+    tz: Option<String>,
+}
+
+/// Spans offered as buttons on the chart page.
+const WINDOW_PRESETS: &[&str] = &["24h", "7d", "30d"];
+
+/// Start and end of a window; `None` leaves that side open.
+type Bounds = (Option<i64>, Option<i64>);
+
+impl ChartPageQuery {
+    fn since(&self) -> Option<&str> {
+        self.since.as_deref().filter(|v| !v.is_empty())
+    }
+
+    fn until(&self) -> Option<&str> {
+        self.until.as_deref().filter(|v| !v.is_empty())
+    }
+
+    /// Resolves both bounds against the current time.
+    fn resolve(&self) -> Result<Bounds, (StatusCode, &'static str)> {
+        let now = Utc::now().timestamp();
+        let bound = |value: Option<&str>| match value {
+            None => Ok(None),
+            Some(value) => parse_time_bound(value, now).map(Some).ok_or((
+                StatusCode::BAD_REQUEST,
+                "since and until take a Unix timestamp or a span like 24h or 7d",
+            )),
+        };
+        Ok((bound(self.since())?, bound(self.until())?))
+    }
+
+    /// A link back to the chart page with this query.
+    fn href(&self) -> String {
+        let mut query = form_urlencoded::Serializer::new(String::new());
+        if let Some(since) = self.since() {
+            query.append_pair("since", since);
+        }
+        if let Some(until) = self.until() {
+            query.append_pair("until", until);
+        }
+        if let Some(scale) = self.scale {
+            query.append_pair("scale", scale.as_str());
+        }
+        if let Some(chart_type) = self.chart_type {
+            query.append_pair("type", chart_type.as_str());
+        }
+        if let Some(trend) = self.trend {
+            query.append_pair("trend", trend.as_str());
+        }
+        if let Some(sigmas) = self.anomalies {
+            query.append_pair("anomalies", &sigmas.to_string());
+        }
+        if let Some(tz) = self.tz.as_deref().filter(|v| !v.is_empty()) {
+            query.append_pair("tz", tz);
+        }
+        format!("?{}", query.finish())
+    }
+
+    /// The chart page's window buttons, with the one matching this query
+    /// highlighted; a hand-written range highlights none.
+    fn window_links(&self) -> Vec<ViewLink> {
+        let (since, until) = (self.since(), self.until());
+        let window = |since: Option<&str>| ChartPageQuery {
+            since: since.map(str::to_string),
+            until: None,
+            ..self.clone()
+        };
+        WINDOW_PRESETS
+            .iter()
+            .map(|preset| ViewLink {
+                label: preset,
+                href: window(Some(preset)).href(),
+                active: until.is_none() && since == Some(*preset),
+            })
+            .chain([ViewLink {
+                label: "all",
+                href: window(None).href(),
+                active: since.is_none() && until.is_none(),
+            }])
+            .collect()
+    }
+
+    /// Linear and log axis buttons that keep the rest of the view.
+    fn scale_links(&self, scale: Scale) -> Vec<ViewLink> {
+        [Scale::Linear, Scale::Log]
+            .into_iter()
+            .map(|option| ViewLink {
+                label: option.as_str(),
+                href: ChartPageQuery {
+                    scale: Some(option),
+                    ..self.clone()
+                }
+                .href(),
+                active: option == scale,
+            })
+            .collect()
+    }
+
+    /// One button per chart type that keeps the rest of the view.
+    fn type_links(&self, chart_type: ChartType) -> Vec<ViewLink> {
+        ChartType::ALL
+            .iter()
+            .map(|&option| ViewLink {
+                label: option.as_str(),
+                href: ChartPageQuery {
+                    chart_type: Some(option),
+                    ..self.clone()
+                }
+                .href(),
+                active: option == chart_type,
+            })
+            .collect()
+    }
+}
+
+impl ChartPageQuery {
+    /// Trend buttons, starting with one to turn it off.
+    fn trend_links(&self) -> Vec<ViewLink> {
+        let link = |label, trend| ViewLink {
+            label,
+            href: ChartPageQuery { trend, ..self.clone() }.href(),
+            active: self.trend == trend,
+        };
+        [link("no trend", None)]
+            .into_iter()
+            .chain(Trend::ALL.iter().map(|&trend| link(trend.as_str(), Some(trend))))
+            .collect()
+    }
+
+    /// Outlier flagging at a few common thresholds, or off.
+    fn anomaly_links(&self) -> Vec<ViewLink> {
+        [("no outliers", None), ("2σ", Some(2.0)), ("3σ", Some(3.0))]
+            .into_iter()
+            .map(|(label, anomalies)| ViewLink {
+                label,
+                href: ChartPageQuery { anomalies, ..self.clone() }.href(),
+                active: self.anomalies == anomalies,
+            })
+            .collect()
+    }
+}
+
+struct ViewLink {
+    label: &'static str,
+    href: String,
+    active: bool,
+}
+
+fn parse_time_bound(value: &str, now: i64) -> Option<i64> {
+    if let Ok(timestamp) = value.parse::<i64>() {
+        return Some(timestamp);
+    }
+    Some(now - parse_span(value)?)
+}
+
+/// A span such as `90m`, `24h` or `7d` in seconds.
+fn parse_span(value: &str) -> Option<i64> {
+    let unit = value.chars().last()?;
+    let seconds = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86400,
+        'w' => 7 * 86400,
+        _ => return None,
+    };
+    let count: i64 = value[..value.len() - 1].parse::<u32>().ok()?.into();
+    Some(count * seconds)
+}
+
+/// Loads the serialized points of a series within a window. The full series
+/// goes through the chart cache; windows move with the clock, so they are
+/// always read fresh, and the index keeps that cheap.
+async fn load_window_json(
+    state: &AppState,
+    namespace: &str,
+    id: &str,
+    (since, until): Bounds,
+) -> Result<Arc<str>, ReadError> {
+    if since.is_none() && until.is_none() {
+        return load_series_json(state, namespace, id).await;
+    }
+    let (since, until) = (since.unwrap_or(i64::MIN), until.unwrap_or(i64::MAX));
+    let data = rollup::load_points(state, namespace, id, since, until).await?;
+    Ok(serde_json::to_string(&data).unwrap_or_default().into())
+}
+
+/// Summary figures for the chart page's stats card.
+struct SeriesStats {
+    count: i64,
+    min: String,
+    max: String,
+    mean: String,
+    stddev: String,
+    latest: String,
+}
+
+/// Aggregates a series over a window; `None` when the window
+/// holds no points. The deviation is the population one, from the mean of
+/// squares, since SQLite has no `STDDEV`. Figures are formatted with the
+/// metric's unit and decimals.
+async fn load_series_stats(
+    store: &dyn MetricStore,
+    namespace: &str,
+    id: &str,
+    (since, until): Bounds,
+    meta: &meta::MetricMeta,
+) -> Result<Option<SeriesStats>, StoreError> {
+    let (since, until) = (since.unwrap_or(i64::MIN), until.unwrap_or(i64::MAX));
+    let Some(aggregate) = store.aggregate(namespace, id, since, until).await? else {
+        return Ok(None);
+    };
+    let mean = aggregate.mean;
+    Ok(Some(SeriesStats {
+        count: aggregate.count,
+        min: meta.format(aggregate.min),
+        max: meta.format(aggregate.max),
+        mean: meta.format(mean),
+        // Rounding can leave the variance a hair below zero for flat series
+        stddev: meta.format((aggregate.mean_square - mean * mean).max(0.0).sqrt()),
+        latest: meta.format(aggregate.latest),
+    }))
+}
+
+/// Loads the serialized points of a series, going through the chart cache.
+async fn load_series_json(state: &AppState, namespace: &str, id: &str) -> Result<Arc<str>, ReadError> {
+    let generation = match state.chart_cache.get(namespace, id, "all") {
+        Ok(cached) => return Ok(cached),
+        Err(generation) => generation,
+    };
+    
+    let data = rollup::load_points(state, namespace, id, i64::MIN, i64::MAX).await?;
+    let data_json: Arc<str> = serde_json::to_string(&data).unwrap_or_default().into();
+    state
+        .chart_cache
+        .insert(namespace, id, "all", data_json.clone(), generation);
+    Ok(data_json)
+}
+
+pub async fn get_chart(
+    SeriesPath(namespace, id): SeriesPath,
+    Query(view): Query<ChartPageQuery>,
+    State(state): State<AppState>,
+    domain: Option<Extension<Domain>>,
+    ViewerTheme(theme): ViewerTheme,
+    tz: ViewerTz,
+    headers: axum::http::HeaderMap,
+) -> Result<Response, StatusCode> {
+    let bounds = match view.resolve() {
+        Ok(bounds) => bounds,
+        Err(rejection) => return Ok(rejection.into_response()),
+    };
+    if view.anomalies.is_some_and(|sigmas| !(sigmas.is_finite() && sigmas > 0.0)) {
+        return Ok((StatusCode::BAD_REQUEST, "anomalies takes a positive number of standard deviations").into_response());
+    }
+    let data_json = match load_window_json(&state, &namespace, &id, bounds).await {
+        Ok(data_json) => data_json,
+        Err(err) => return err.respond(),
+    };
+    let expires_at = match state.config().inactive_expiry_days {
+        Some(_) => retention::last_write(state.store.as_ref(), &namespace, &id)
+            .await
+            .map_err(errors::internal)?
+            .and_then(|last_write| retention::expiry_warning(&state.config(), last_write)),
+        None => None,
+    };
+    
+    // Browsers get the chart page; API clients asking for JSON get the series
+    // itself, and terminals a text plot
+    let media = match negotiate(&headers, &["text/html", "application/json", "text/plain"]) {
+        "text/html" if is_terminal_client(&headers) => "text/plain",
+        media => media,
+    };
+    if media == "text/plain" {
+        let mut response = ascii_response(&namespace, &id, &data_json)?;
+        if let Some(expires_at) = expires_at {
+            retention::insert_sunset(response.headers_mut(), expires_at);
+        }
+        return Ok(response);
+    }
+    // Derived series need the points themselves, not just their JSON
+    let mut trend = None;
+    let mut anomalies = None;
+    if view.trend.is_some() || view.anomalies.is_some() {
+        let data: Vec<MetricPoint> =
+            serde_json::from_str(&data_json).map_err(errors::internal)?;
+        trend = view
+            .trend
+            .and_then(|trend| trend.compute(&data))
+            .map(|(label, points)| TrendSeries { label, points });
+        anomalies = view.anomalies.map(|sigmas| anomaly::detect(&data, sigmas));
+    }
+    if media == "application/json" {
+        let points: &RawValue =
+            serde_json::from_str(&data_json).map_err(errors::internal)?;
+        let mut response = axum::Json(SeriesResponse {
+            namespace: &namespace,
+            id: &id,
+            points,
+            trend,
+            anomalies,
+        })
+        .into_response();
+        response.headers_mut().insert("vary", "accept, user-agent".parse().unwrap());
+        if let Some(expires_at) = expires_at {
+            retention::insert_sunset(response.headers_mut(), expires_at);
+        }
+        return Ok(response);
+    }
+    
+    let meta = meta::load(&state.pool, &namespace, &id)
+        .await
+        .map_err(errors::internal)?;
+    let scale = view.scale.or(meta.scale).unwrap_or_default();
+    let chart_type = view.chart_type.or(meta.chart_type).unwrap_or_default();
+    
+    let stats = load_series_stats(state.store.as_ref(), &namespace, &id, bounds, &meta)
+        .await
+        .map_err(errors::internal)?;
+    let detected = match state.config().anomaly_detection {
+        Some(_) => Some(
+            detection::load(&state.pool, &namespace, &id, bounds)
+                .await
+                .map_err(errors::internal)?,
+        ),
+        None => None,
+    };
+    let markers = if state.config().features.markers {
+        markers::load(&state.pool, &namespace, bounds)
+            .await
+            .map_err(errors::internal)?
+    } else {
+        Vec::new()
+    };
+    
+    let format = serde_json::json!({ "unit": meta.unit, "decimals": meta.decimals });
+    let template = ChartTemplate {
+        base_path: state.base_path.clone(),
+        namespace,
+        id,
+        description: meta.description,
+        expires: expires_at.and_then(|expires_at| tz.format_timestamp(expires_at)),
+        data_json: data_json.replace('<', "\\u003c"),
+        format_json: format.to_string().replace('<', "\\u003c"),
+        stats,
+        // Labels come from webhooks, so keep them from closing the script element
+        markers_json: serde_json::to_string(&markers).unwrap_or_default().replace('<', "\\u003c"),
+        chart_images: state.config().features.chart_images,
+        embeds: state.config().features.embeds,
+        windows: view.window_links(),
+        scales: view.scale_links(scale),
+        types: view.type_links(chart_type),
+        view_query: ChartPageQuery {
+            since: view.since.clone(),
+            until: view.until.clone(),
+            scale: view.scale,
+            tz: view.tz.clone(),
+            ..ChartPageQuery::default()
+        }
+        .href(),
+        trends: view.trend_links(),
+        outliers: view.anomaly_links(),
+        anomalies_json: serde_json::to_string(&anomalies).unwrap_or_default(),
+        detected_json: serde_json::to_string(&detected).unwrap_or_default(),
+        trend_json: serde_json::to_string(&trend).unwrap_or_default().replace('<', "\\u003c"),
+        scale: scale.as_str(),
+        chart_type: chart_type.as_str(),
+        origin: request_origin(&headers, state.base_path()),
+        brand: domain.map(|d| d.title.clone().unwrap_or_else(|| d.namespace.clone())),
+        theme: theme.as_str(),
+        tz_json: tz.script_json(),
+        tz_label: tz.label_or("your browser's timezone"),
+    };
+    
+    match traces::render(&template) {
+        Ok(html) => {
+            let mut response = Html(html).into_response();
+            response.headers_mut().insert("vary", "accept, user-agent".parse().unwrap());
+            if let Some(expires_at) = expires_at {
+                retention::insert_sunset(response.headers_mut(), expires_at);
+            }
+            Ok(response)
+        }
+        Err(err) => Err(errors::internal(err)),
+    }
+}
+
+/// `scheme://host` of the current request and `base_path`, where this
+/// server's pages start, trusting the proxy's `X-Forwarded-Proto` for the
+/// scheme.
+fn request_origin(headers: &axum::http::HeaderMap, base_path: &str) -> String {
+    let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+    format!(
+        "{}://{}{}",
+        header("x-forwarded-proto").unwrap_or("http"),
+        header("host").unwrap_or("localhost"),
+        base_path
+    )
+}
+
+/// Command-line HTTP clients that accept anything are shown the text plot:
+/// nobody running `curl` wants the page's HTML.
+fn is_terminal_client(headers: &axum::http::HeaderMap) -> bool {
+    let accepts_anything = headers
+        .get("accept")
+        .and_then(|v| v.to_str().ok())
+        .is_none_or(|accept| accept.trim().is_empty() || accept.trim() == "*/*");
+    let user_agent = headers.get("user-agent").and_then(|v| v.to_str().ok()).unwrap_or("");
+    accepts_anything && ["curl/", "Wget/", "HTTPie/", "xh/"].iter().any(|client| user_agent.starts_with(client))
+}
+
+fn ascii_response(namespace: &str, id: &str, data_json: &str) -> Result<Response, StatusCode> {
+    let data: Vec<MetricPoint> = serde_json::from_str(data_json).map_err(errors::internal)?;
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "text/plain; charset=utf-8")
+        .header("vary", "accept, user-agent")
+        .body(Body::from(ascii::render(namespace, id, &data)))
+        .unwrap())
+}
+
+/// The text plot regardless of the Accept header.
+pub async fn get_chart_ascii(
+    SeriesPath(namespace, id): SeriesPath,
+    State(state): State<AppState>,
+) -> Result<Response, StatusCode> {
+    let data_json = match load_series_json(&state, &namespace, &id).await {
+        Ok(data_json) => data_json,
+        Err(err) => return err.respond(),
+    };
+    ascii_response(&namespace, &id, &data_json)
+}
+
+/// Deletes a metric: into the trash while `DELETE_GRACE_DAYS` is set,
+/// otherwise for good along with its settings.
+pub async fn delete_metric(
+    SeriesPath(namespace, id): SeriesPath,
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+) -> Result<StatusCode, StatusCode> {
+    auth::require_admin(&state.config(), &headers)?;
+    
+    if state.config().delete_grace_days.is_some() {
+        let trashed = trash::trash_metric(&state, &namespace, &id)
+            .await
+            .map_err(errors::internal)?;
+        if trashed == 0 {
+            return Err(StatusCode::NOT_FOUND);
+        }
+        return Ok(StatusCode::NO_CONTENT);
+    }
+    
+    let (pool, namespace, id) = (&state.pool, &namespace, &id);
+    let deleted = state
+        .write_store(|| state.store.delete(namespace, id))
+        .await
+        .map_err(errors::internal)?;
+    state
+        .write(|| async move {
+            let mut tx = pool.begin().await?;
+            sqlx::query!("DELETE FROM metric_meta WHERE namespace = ? AND id = ?", namespace, id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query!("DELETE FROM metric_precision WHERE namespace = ? AND id = ?", namespace, id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query!("DELETE FROM metric_rollups WHERE namespace = ? AND id = ?", namespace, id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query!("DELETE FROM metric_point_caps WHERE namespace = ? AND id = ?", namespace, id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query!("DELETE FROM github_baselines WHERE namespace = ? AND id = ?", namespace, id)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await
+        })
+        .await
+        .map_err(errors::internal)?;
+    state.invalidate_series(namespace, id);
+    
+    if deleted == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Answers HEAD for a chart with freshness headers computed by a single
+/// aggregate over the series, so pollers never pay for loading it.
+pub async fn head_chart(
+    SeriesPath(namespace, id): SeriesPath,
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+) -> Result<Response, StatusCode> {
+    let summary = state
+        .store
+        .aggregate(&namespace, &id, i64::MIN, i64::MAX)
+        .await
+        .map_err(errors::internal)?;
+    
+    let content_type = match negotiate(&headers, &["text/html", "application/json", "text/plain"]) {
+        "application/json" => "application/json",
+        "text/html" if !is_terminal_client(&headers) => "text/html; charset=utf-8",
+        _ => "text/plain; charset=utf-8",
+    };
+    
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", content_type)
+        .header("vary", "accept, user-agent")
+        .header("x-point-count", summary.as_ref().map_or(0, |summary| summary.count));
+    if let Some(summary) = &summary {
+        response = response
+            .header("x-last-timestamp", summary.last_timestamp)
+            .header("x-latest-value", summary.latest.to_string());
+    }
+    
+    Ok(response.body(Body::empty()).unwrap())
+}
+
+/// The landing page, or on a custom domain that domain's namespace.
+pub async fn get_index(
+    domain: Option<Extension<Domain>>,
+    ViewerTheme(theme): ViewerTheme,
+    tz: ViewerTz,
+    Query(pagination): Query<PaginationQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, StatusCode> {
+    if let Some(Extension(domain)) = domain {
+        return render_namespace(&state, domain.namespace.clone(), pagination, Some(&domain), theme, tz).await;
+    }
+    
+    let template = IndexTemplate {
+        base_path: state.base_path.clone(),
+        theme: theme.as_str(),
+    };
+    match traces::render(&template) {
+        Ok(html) => Ok(Html(html).into_response()),
+        Err(err) => Err(errors::internal(err)),
+    }
+}
+
+pub async fn get_favicon() -> impl IntoResponse {
+    const FAVICON_SVG: &str = include_str!("../favicon.svg");
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "image/svg+xml")
+        .body(Body::from(FAVICON_SVG.to_string()))
+        .unwrap()
+}
+
+/// A page of a namespace listing, as a cursor: the metric it starts next
+/// to and the page number it leads to, so pages can still be numbered.
+struct Cursor {
+    page: i64,
+    last_timestamp: i64,
+    id: String,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}:{}:{}", self.page, self.last_timestamp, self.id))
+    }
+
+    fn decode(cursor: &str) -> Option<Self> {
+        let decoded = String::from_utf8(URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+        let mut parts = decoded.splitn(3, ':');
+        Some(Cursor {
+            page: parts.next()?.parse().ok()?,
+            last_timestamp: parts.next()?.parse().ok()?,
+            id: parts.next()?.to_string(),
+        })
+    }
+
+    fn position(&self) -> ListPosition<'_> {
+        ListPosition {
+            last_timestamp: self.last_timestamp,
+            id: &self.id,
+        }
+    }
+}
+
+pub async fn get_namespace(
+    NamespacePath(namespace): NamespacePath,
+    Query(pagination): Query<PaginationQuery>,
+    State(state): State<AppState>,
+    domain: Option<Extension<Domain>>,
+    ViewerTheme(theme): ViewerTheme,
+    tz: ViewerTz,
+) -> Result<Response, StatusCode> {
+    render_namespace(&state, namespace, pagination, domain.as_deref(), theme, tz).await
+}
+
+async fn render_namespace(
+    state: &AppState,
+    namespace: String,
+    pagination: PaginationQuery,
+    domain: Option<&Domain>,
+    theme: PageTheme,
+    tz: ViewerTz,
+) -> Result<Response, StatusCode> {
+    let pool = &state.pool;
+    let per_page: i64 = 12; // Show 12 charts per page (nice grid layout)
+    if let Some(page) = pagination.page {
+        return redirect_to_page(state, &namespace, page, per_page).await;
+    }
+    let after = pagination.after.as_deref().map(Cursor::decode);
+    let before = pagination.before.as_deref().map(Cursor::decode);
+    
+    // Keyset pagination over (last write, id), so the most recently written
+    // metrics come first; one extra row tells us whether there is another
+    // page in the direction we're walking.
+    let limit = per_page + 1;
+    let (from, backwards, page) = match (&after, &before) {
+        (Some(None), _) | (_, Some(None)) => return Err(StatusCode::BAD_REQUEST),
+        (_, Some(Some(before))) => (ListFrom::Before(before.position()), true, before.page),
+        (Some(Some(after)), None) => (ListFrom::After(after.position()), false, after.page),
+        (None, None) => (ListFrom::Start, false, 1),
+    };
+    let mut rows = state
+        .store
+        .list(&namespace, from, limit)
+        .await
+        .map_err(errors::internal)?;
+    
+    let has_more = rows.len() as i64 > per_page;
+    rows.truncate(per_page as usize);
+    if backwards {
+        rows.reverse();
+    }
+    
+    // Walking backwards, "more" lies before us and we came from a later page;
+    // walking forwards it's the other way round.
+    let (has_prev, has_next) = if backwards {
+        (has_more, true)
+    } else {
+        (pagination.after.is_some(), has_more)
+    };
+    let cursor = |listing: &MetricListing, page: i64| Cursor {
+        page,
+        last_timestamp: listing.last_timestamp.unwrap_or_default(),
+        id: listing.id.clone(),
+    }
+    .encode();
+    let prev_cursor = rows.first().filter(|_| has_prev).map(|listing| cursor(listing, page - 1));
+    let next_cursor = rows.last().filter(|_| has_next).map(|listing| cursor(listing, page + 1));
+    
+    let silent = state.alerts.silent(&namespace);
+    let charts = rows
+        .into_iter()
+        .map(|MetricListing { id, point_count, last_timestamp }| ChartInfo {
+            point_count,
+            last_updated: last_timestamp
+                .and_then(|ts| tz.format_timestamp(ts))
+                .unwrap_or_else(|| "Unknown".to_string()),
+            expires: last_timestamp
+                .and_then(|ts| retention::expiry_warning(&state.config(), ts))
+                .and_then(|expires_at| tz.format_timestamp(expires_at)),
+            silent: silent.contains(&id),
+            id,
+        })
+        .collect::<Vec<_>>();
+    
+    // Only the first page carries the README, so paging stays compact
+    let readme_html = if state.config().features.readmes && !has_prev {
+        readme::load_html(pool, state.base_path(), &namespace)
+            .await
+            .map_err(errors::internal)?
+    } else {
+        None
+    };
+    
+    let template = NamespaceTemplate {
+        base_path: state.base_path.clone(),
+        namespace,
+        readme_html,
+        prev_cursor,
+        next_cursor,
+        page,
+        charts,
+        brand: domain.map(|d| d.title.clone().unwrap_or_else(|| d.namespace.clone())),
+        theme: theme.as_str(),
+        tz_label: tz.label_or("UTC"),
+    };
+    
+    match traces::render(&template) {
+        Ok(html) => Ok(Html(html).into_response()),
+        Err(err) => Err(errors::internal(err)),
+    }
+}
+
+/// Sends a `?page=N` link from before cursors to the cursor for that page.
+async fn redirect_to_page(state: &AppState, namespace: &str, page: i64, per_page: i64) -> Result<Response, StatusCode> {
+    let first_page = format!("{}/{}", state.base_path(), namespace);
+    if page <= 1 {
+        return Ok(Redirect::to(&first_page).into_response());
+    }
+    // The metric just before the page starts
+    let before_page = state
+        .store
+        .list(namespace, ListFrom::Offset((page - 1) * per_page - 1), 1)
+        .await
+        .map_err(errors::internal)?;
+    let Some(listing) = before_page.first() else {
+        return Ok(Redirect::to(&first_page).into_response());
+    };
+    let cursor = Cursor {
+        page,
+        last_timestamp: listing.last_timestamp.unwrap_or_default(),
+        id: listing.id.clone(),
+    };
+    Ok(Redirect::to(&format!("{}?after={}", first_page, cursor.encode())).into_response())
+}
+
+
+/// Builds the application router, leaving out anything the operator disabled.
+pub fn router(state: AppState) -> Router {
+    let features = &state.config().features;
+    let mut app = Router::new()
+        .route("/", get(get_index))
+        .route("/favicon.svg", get(get_favicon))
+        .route("/healthz", get(health::get_healthz))
+        .route("/readyz", get(health::get_readyz))
+        .route("/preferences", post(theme::post_preferences))
+        .route("/{namespace}", get(get_namespace).delete(purge::delete_namespace))
+        .route("/{namespace}/{id}", get(get_chart).head(head_chart).delete(delete_metric))
+        .route("/{namespace}/{id}/ascii", get(get_chart_ascii))
+        .route("/{namespace}/{id}/heatmap", get(heatmap::get_heatmap))
+        .route("/{namespace}/{id}/daily", get(daily::get_daily))
+        .route("/{namespace}/{id}/check", get(check::get_check))
+        .route("/{namespace}/{id}/status", get(status::get_status))
+        .route("/{namespace}/{id}/forecast", get(forecast::get_forecast))
+        .route("/{namespace}/{id}/rename", post(aliases::post_rename))
+        .route("/{namespace}/overlay", get(overlay::get_overlay))
+        .route("/{namespace}/suggest", get(suggest::get_suggestions))
+        .route(
+            "/api/v1/namespaces/{namespace}/metrics/{id}/meta",
+            get(meta::get_meta).put(meta::put_meta).delete(meta::delete_meta),
+        )
+        .route(
+            "/api/v1/namespaces/{namespace}/retention",
+            get(retention::get_retention)
+                .put(retention::put_retention)
+                .delete(retention::delete_retention),
+        )
+        .route(
+            "/api/v1/namespaces/{namespace}/point-cap",
+            get(caps::get_namespace_cap)
+                .put(caps::put_namespace_cap)
+                .delete(caps::delete_namespace_cap),
+        )
+        .route(
+            "/api/v1/namespaces/{namespace}/metrics/{id}/point-cap",
+            get(caps::get_metric_cap).put(caps::put_metric_cap).delete(caps::delete_metric_cap),
+        )
+        .route("/api/v1/namespaces/{namespace}/metrics/{id}/merge", post(merge::post_merge))
+        .route("/api/v1/namespaces/{namespace}/aliases", get(aliases::list_aliases))
+        .route("/api/v1/namespaces/{namespace}/correlation", get(correlation::get_correlation))
+        .route("/api/v1/namespaces/{namespace}/clone", post(clone::post_clone))
+        .route("/api/v1/namespaces/{namespace}/aliases/{id}", delete(aliases::delete_alias))
+        .route("/admin/maintenance", post(maintenance::post_maintenance))
+        .route("/admin/reload", post(reload::post_reload))
+        .route("/admin/deleted", get(trash::list_deleted))
+        .route("/admin/deleted/{namespace}/{id}/restore", post(trash::restore_deleted))
+        .route(
+            "/admin/namespaces/{namespace}/owner-token",
+            post(owners::post_owner_token).delete(owners::delete_owner_token),
+        );
+    
+    if features.ingest {
+        let track = || middleware::from_fn_with_state(state.clone(), tokens::track);
+        app = app
+            .route("/{namespace}", post(post_metrics).layer(track()))
+            .route("/{namespace}/{id}", post(post_metric).layer(track()))
+            .route("/api/v1/tokens/self/status", get(tokens::get_self_status))
+            .route(
+                "/api/v1/namespaces/{namespace}/metrics/{id}/precision",
+                get(precision::get_precision)
+                    .put(precision::put_precision)
+                    .delete(precision::delete_precision),
+            )
+            .route(
+                "/api/v1/namespaces/{namespace}/metrics/{id}/points",
+                get(points::get_points).put(points::put_points).delete(points::delete_points),
+            );
+    }
+    if features.badges {
+        app = app
+            .route("/{namespace}/{id}/badge.png", get(badge::get_badge_png))
+            .route("/{namespace}/{id}/badge.svg", get(badge::get_badge_svg))
+            .route("/{namespace}/badge.png", get(badge::get_namespace_badge_png))
+            .route("/{namespace}/badge.svg", get(badge::get_namespace_badge_svg));
+    }
+    if features.bundle_export {
+        app = app
+            .route("/api/v1/namespaces/{namespace}/bundle", get(bundle::export_bundle))
+            .route("/{namespace}/export.zip", get(export::get_export));
+    }
+    if features.bundle_import {
+        app = app
+            .route(
+                "/api/v1/namespaces/{namespace}/bundle",
+                post(bundle::import_bundle)
+                    .layer(DefaultBodyLimit::max(state.config().limits.max_import_bytes))
+                    .layer(middleware::from_fn_with_state(
+                        limits::BodyLimit {
+                            bytes: state.config().limits.max_import_bytes,
+                            setting: "MAX_IMPORT_BYTES",
+                        },
+                        limits::explain_oversized,
+                    )),
+            )
+            .route("/api/v1/namespaces/{namespace}/import", post(import::post_import));
+    }
+    if features.backups {
+        app = app
+            .route("/admin/backup", get(backup::get_backup))
+            .route("/admin/restore", post(backup::post_restore).layer(DefaultBodyLimit::disable()));
+    }
+    if features.self_metrics {
+        app = app.route("/internal/metrics", get(stats::get_self_metrics));
+    }
+    if features.query {
+        app = app.route("/api/v1/query", post(query::post_query));
+    }
+    if features.short_links {
+        app = app
+            .route("/s/{code}", get(shortlink::follow_short_link))
+            .route("/api/v1/short-links", post(shortlink::create_short_link))
+            .route("/{namespace}/{id}/snapshot", post(snapshot::post_snapshot));
+    }
+    if features.chart_images {
+        app = app
+            .route("/{namespace}/{id}/chart.png", get(chart::get_chart_png))
+            .route("/{namespace}/{id}/chart.svg", get(chart::get_chart_svg))
+            .route("/{namespace}/{id}/og.png", get(chart::get_og_png));
+    }
+    if features.custom_domains {
+        app = app
+            .route("/api/v1/domains", get(domains::list_domains))
+            .route(
+                "/api/v1/domains/{host}",
+                put(domains::put_domain).delete(domains::delete_domain),
+            );
+    }
+    if features.prometheus {
+        app = app.route("/prom/{namespace}", get(prom::get_prometheus));
+    }
+    if features.graphite {
+        app = app
+            .route("/graphite/render", get(graphite::render).post(graphite::render))
+            .route("/graphite/metrics/find", get(graphite::find).post(graphite::find));
+    }
+    if features.firehose {
+        app = app.route("/api/v1/firehose", get(firehose::subscribe));
+    }
+    if features.dashboards {
+        app = app
+            .route("/d/{slug}", get(dashboards::get_dashboard_page))
+            .route("/api/v1/dashboards", post(dashboards::create_dashboard))
+            .route(
+                "/api/v1/dashboards/{slug}",
+                get(dashboards::get_dashboard)
+                    .put(dashboards::update_dashboard)
+                    .delete(dashboards::delete_dashboard),
+            );
+    }
+    if features.embeds {
+        app = app
+            .route("/embed/{namespace}/{id}", get(embed::get_embed))
+            .route("/oembed", get(embed::get_oembed));
+    }
+    if features.markers {
+        app = app
+            .route("/{namespace}/markers", get(markers::list_markers).post(markers::post_marker))
+            .route(
+                "/api/v1/namespaces/{namespace}/markers/{marker}",
+                delete(markers::delete_marker),
+            );
+    }
+    
+    if features.alerts {
+        app = app
+            .route(
+                "/api/v1/namespaces/{namespace}/alerts",
+                get(alerts::list_rules).post(alerts::create_rule),
+            )
+            .route("/api/v1/namespaces/{namespace}/alerts/{rule}", delete(alerts::delete_rule));
+    }
+
+    if features.webhooks {
+        app = app
+            .route(
+                "/api/v1/namespaces/{namespace}/webhooks",
+                get(webhooks::list_webhooks).post(webhooks::create_webhook),
+            )
+            .route(
+                "/api/v1/namespaces/{namespace}/webhooks/{webhook}",
+                delete(webhooks::delete_webhook),
+            );
+    }
+
+    if features.github_status {
+        app = app.route(
+            "/api/v1/namespaces/{namespace}/github",
+            get(github::get_repo).put(github::put_repo).delete(github::delete_repo),
+        );
+    }
+
+    if features.digests {
+        app = app
+            .route(
+                "/api/v1/namespaces/{namespace}/digests",
+                get(digests::list_digests).post(digests::create_digest),
+            )
+            .route(
+                "/api/v1/namespaces/{namespace}/digests/{digest}",
+                delete(digests::delete_digest),
+            )
+            .route(
+                "/api/v1/namespaces/{namespace}/digests/{digest}/send",
+                post(digests::send_digest),
+            );
+    }
+
+    if state.config().anomaly_detection.is_some() {
+        app = app.route("/api/v1/namespaces/{namespace}/anomalies", get(detection::list_anomalies));
+    }
+
+    if features.readmes {
+        app = app.route(
+            "/api/v1/namespaces/{namespace}/readme",
+            get(readme::get_readme).put(readme::put_readme).delete(readme::delete_readme),
+        );
+    }
+    
+    // Routes with a limit of their own, like bundle uploads, set it inside
+    // this one
+    let max_body_bytes = state.config().limits.max_body_bytes;
+    app = app.layer(DefaultBodyLimit::max(max_body_bytes)).layer(middleware::from_fn_with_state(
+        limits::BodyLimit {
+            bytes: max_body_bytes,
+            setting: "MAX_BODY_BYTES",
+        },
+        limits::explain_oversized,
+    ));
+    
+    // Renamed metrics redirect from any page of theirs, so this wraps every
+    // route too, inside host routing
+    app = app.layer(middleware::from_fn_with_state(state.clone(), aliases::redirect));
+    
+    // Inside host routing, so requests are counted by the route that
+    // answered them
+    if features.self_metrics {
+        app = app.layer(middleware::from_fn_with_state(state.clone(), stats::track));
+    }
+    if state.config().trace_export.is_some() {
+        app = app.layer(middleware::from_fn(traces::trace_request));
+    }
+    
+    // Host routing has to wrap every route, so it goes on after the rest
+    if features.custom_domains {
+        app = app.layer(middleware::from_fn_with_state(state.clone(), domains::resolve));
+    }
+    // Compression goes outside that, so it sees the response as it's sent
+    if features.compression {
+        app = app.layer(compression::layer());
+    }
+    
+    // Handlers see paths with the prefix taken off, and links get it put
+    // back from `AppState::base_path`
+    let mut app = app.with_state(state.clone());
+    if !state.config().base_path.is_empty() {
+        // Nesting only matches `/metrics` itself, but links home go to
+        // `/metrics/`, which has to be the index too
+        let index = tower::ServiceExt::map_request(app.clone(), |mut request: axum::extract::Request| {
+            let uri = match request.uri().query() {
+                Some(query) => format!("/?{}", query),
+                None => "/".to_string(),
+            };
+            if let Ok(uri) = uri.parse() {
+                *request.uri_mut() = uri;
+            }
+            request
+        });
+        let base = &state.config().base_path;
+        app = Router::new().route_service(&format!("{}/", base), index).nest(base, app);
+    }
+    
+    // Outside everything else, so each request is logged with the path it
+    // came in on and redirects are logged too
+    app.layer(middleware::from_fn(request_log::log_request))
+}
+
+/// The router for mounting in another axum application, around a pool the
+/// caller opened. Background jobs such as rollups and retention don't run
+/// unless started with [`spawn_background`] on an [`AppState`] passed to
+/// [`router`] instead.
+pub async fn build_router(pool: SqlitePool, config: Config) -> Result<Router, Box<dyn std::error::Error>> {
+    Ok(router(AppState::from_pool(pool, config).await?))
+}
+
+/// How long queued webhooks and spans get to go out at shutdown
+const FLUSH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Serves the router on `listener` until the process is asked to stop. A
+/// signal stops new connections; requests in flight get until the shutdown
+/// timeout to finish.
+async fn run_until_stopped<L>(listener: L, state: &AppState) -> std::io::Result<()>
+where
+    L: axum::serve::Listener,
+    L::Addr: std::fmt::Debug,
+{
+    let timeout = state.config().shutdown_timeout;
+    let (stopping, stopped) = tokio::sync::oneshot::channel();
+    let server = axum::serve(listener, router(state.clone()))
+        .with_graceful_shutdown(async move {
+            runtime::shutdown_signal().await;
+            tracing::info!("Shutting down; waiting up to {}s for requests in flight", timeout.as_secs());
+            let _ = stopping.send(());
+        })
+        .into_future();
+    tokio::select! {
+        result = server => result?,
+        _ = async {
+            if stopped.await.is_ok() {
+                tokio::time::sleep(timeout).await;
+            } else {
+                std::future::pending::<()>().await;
+            }
+        } => tracing::warn!("Requests were still running after {}s; closing their connections", timeout.as_secs()),
+    }
+    Ok(())
+}
+
+/// Starts the jobs that run alongside the server: reloading settings,
+/// retention, rollups, maintenance, webhook and alert delivery, digests,
+/// anomaly detection and error reporting.
+pub fn spawn_background(state: &AppState) {
+    reload::spawn(state.clone());
+    retention::spawn(state.clone());
+    rollup::spawn(state.clone());
+    maintenance::spawn(state.clone());
+    webhooks::spawn(state.clone());
+    alerts::spawn(state.clone());
+    digests::spawn(state.clone());
+    detection::spawn(state.clone());
+    errors::spawn(state.clone());
+}
+
+/// Connects to the configured database and serves the application until the
+/// listener fails or the process is asked to stop.
+pub async fn serve(config: Config, sources: Sources) -> Result<(), Box<dyn std::error::Error>> {
+    let addr = std::net::SocketAddr::new(config.bind_address, config.port.parse()?);
+    let state = AppState::connect(config, sources).await?;
+    spawn_background(&state);
+    
+    // Start server
+    #[cfg(unix)]
+    if let Some(path) = state.config().unix_socket.clone() {
+        let (_socket, listener) = runtime::UnixSocket::bind(&path, state.config().unix_socket_mode)?;
+        tracing::info!("Server running on unix:{}", path.display());
+        run_until_stopped(listener, &state).await?;
+        return finish(state).await;
+    }
+    let tcp = tokio::net::TcpListener::bind(&addr).await?;
+    match state.config().tls.clone() {
+        Some(files) => {
+            let listener = tls::TlsListener::new(tcp, files)?;
+            tracing::info!("Server running on https://{}", addr);
+            run_until_stopped(listener, &state).await?;
+        }
+        None => {
+            tracing::info!("Server running on {}", addr);
+            run_until_stopped(tcp, &state).await?;
+        }
+    }
+    
+    finish(state).await
+}
+
+/// Sends what's queued in memory and closes the pool, once the server has
+/// stopped taking requests.
+async fn finish(state: AppState) -> Result<(), Box<dyn std::error::Error>> {
+    if tokio::time::timeout(FLUSH_TIMEOUT, async {
+        webhooks::flush(&state).await;
+        traces::flush().await;
+    })
+    .await
+    .is_err()
+    {
+        tracing::warn!("Gave up flushing webhooks and traces after {}s", FLUSH_TIMEOUT.as_secs());
+    }
+    // Closing the pool checkpoints the write-ahead log
+    state.pool.close().await;
+    tracing::info!("Shut down");
+    
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderMap;
+
+    use super::*;
+
+    fn accepting(accept: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("accept", accept.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn negotiate_prefers_quality_then_specificity() {
+        let offered = ["text/plain", "text/html", "application/json"];
+        assert_eq!(negotiate(&HeaderMap::new(), &offered), "text/plain");
+        assert_eq!(negotiate(&accepting(" "), &offered), "text/plain");
+        assert_eq!(negotiate(&accepting("application/json"), &offered), "application/json");
+        assert_eq!(negotiate(&accepting("text/html;q=0.5, application/json;q=0.9"), &offered), "application/json");
+        assert_eq!(negotiate(&accepting("*/*, text/html"), &offered), "text/html");
+        assert_eq!(negotiate(&accepting("text/*;q=0.8, application/json;q=0.5"), &offered), "text/plain");
+        assert_eq!(negotiate(&accepting("Application/JSON"), &offered), "application/json");
+        assert_eq!(negotiate(&accepting("text/plain;q=0, */*;q=0.1"), &offered), "text/html");
+        assert_eq!(negotiate(&accepting("image/png"), &offered), "text/plain");
+    }
+
+    #[test]
+    fn fan_out_takes_the_value_after_the_last_colon() {
+        let policy = ids::IdPolicy { case_insensitive: true, ..Default::default() };
+        assert_eq!(
+            parse_fan_out("m=Build:12&sha=abc&m=size%3Ax86:3.5&m=warm:%201", &policy),
+            Some(vec![("build".to_string(), 12.0), ("size:x86".to_string(), 3.5), ("warm".to_string(), 1.0)])
+        );
+        assert_eq!(parse_fan_out("", &policy), None);
+        assert_eq!(parse_fan_out("sha=abc", &policy), None);
+        assert_eq!(parse_fan_out("m=build", &policy), None);
+        assert_eq!(parse_fan_out("m=:1", &policy), None);
+        assert_eq!(parse_fan_out("m=build:1&m=size:big", &policy), None);
+    }
+
+    #[test]
+    fn spans_and_time_bounds() {
+        assert_eq!(parse_span("30s"), Some(30));
+        assert_eq!(parse_span("90m"), Some(90 * 60));
+        assert_eq!(parse_span("24h"), Some(86400));
+        assert_eq!(parse_span("7d"), Some(7 * 86400));
+        assert_eq!(parse_span("2w"), Some(14 * 86400));
+        assert_eq!(parse_span(""), None);
+        assert_eq!(parse_span("h"), None);
+        assert_eq!(parse_span("-1h"), None);
+        assert_eq!(parse_span("1y"), None);
+        assert_eq!(parse_span("1é"), None);
+
+        let now = 1_700_000_000;
+        assert_eq!(parse_time_bound("1600000000", now), Some(1_600_000_000));
+        assert_eq!(parse_time_bound("-5", now), Some(-5));
+        assert_eq!(parse_time_bound("1h", now), Some(now - 3600));
+        assert_eq!(parse_time_bound("yesterday", now), None);
+    }
+}
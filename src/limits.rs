@@ -0,0 +1,142 @@
+//! Ceilings on what one request can send or get back, so an oversized
+//! upload or a read across millions of points is turned away with an error
+//! saying so instead of taking the server's memory. `MAX_BODY_BYTES` bounds
+//! request bodies, `MAX_IMPORT_BYTES` the bundles uploaded or pulled by
+//! imports, and `MAX_QUERY_POINTS` the points one read of a series returns.
+
+use std::fmt;
+
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::{json, Value};
+
+use crate::{errors, store::StoreError};
+
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// Largest request body most routes take
+    pub max_body_bytes: usize,
+    /// Largest bundle an upload or import takes, compressed
+    pub max_import_bytes: usize,
+    /// Most points one read of a series returns
+    pub max_query_points: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            // axum's own default
+            max_body_bytes: 2 * 1024 * 1024,
+            max_import_bytes: 64 * 1024 * 1024,
+            max_query_points: 1_000_000,
+        }
+    }
+}
+
+/// A body limit and the setting that raises it, for [`explain_oversized`].
+#[derive(Clone, Copy)]
+pub struct BodyLimit {
+    pub bytes: usize,
+    pub setting: &'static str,
+}
+
+/// Turns axum's plain-text rejection of a body over `DefaultBodyLimit`
+/// into one naming the limit. Routes with a limit of their own explain it
+/// inside this, and their answer is JSON by the time it comes back out.
+pub async fn explain_oversized(State(limit): State<BodyLimit>, request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let plain = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"text/plain"));
+    if response.status() != StatusCode::PAYLOAD_TOO_LARGE || !plain {
+        return response;
+    }
+    let message = format!(
+        "the request body is over the limit of {} bytes, set by {}",
+        limit.bytes, limit.setting
+    );
+    (StatusCode::PAYLOAD_TOO_LARGE, Json(json!({ "error": message }))).into_response()
+}
+
+/// Why a series couldn't be read.
+#[derive(Debug)]
+pub enum ReadError {
+    Database(StoreError),
+    /// More points matched than `MAX_QUERY_POINTS` lets one read return
+    TooManyPoints(usize),
+}
+
+impl From<StoreError> for ReadError {
+    fn from(err: StoreError) -> Self {
+        ReadError::Database(err)
+    }
+}
+
+impl From<sqlx::Error> for ReadError {
+    fn from(err: sqlx::Error) -> Self {
+        ReadError::Database(err.into())
+    }
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::Database(err) => err.fmt(f),
+            ReadError::TooManyPoints(limit) => write!(
+                f,
+                "more than {} points match, the most one request returns; ask for a shorter time range",
+                limit
+            ),
+        }
+    }
+}
+
+impl ReadError {
+    /// For handlers whose errors are bare statuses: the explanation as a
+    /// `422`, or the database failure as a `500`.
+    pub fn respond(self) -> Result<Response, StatusCode> {
+        match self {
+            ReadError::Database(err) => Err(errors::internal(err)),
+            too_many => Ok((StatusCode::UNPROCESSABLE_ENTITY, too_many.to_string()).into_response()),
+        }
+    }
+
+    /// For handlers that answer errors in plain text.
+    pub fn text(self) -> (StatusCode, String) {
+        match self {
+            ReadError::Database(err) => (errors::internal(err), "database error".to_string()),
+            too_many => (StatusCode::UNPROCESSABLE_ENTITY, too_many.to_string()),
+        }
+    }
+
+    /// For JSON APIs, as `{"error": ...}`.
+    pub fn api(self) -> (StatusCode, Json<Value>) {
+        match self {
+            ReadError::Database(err) => {
+                errors::report(&err);
+                (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": "database error" })))
+            }
+            too_many => (StatusCode::UNPROCESSABLE_ENTITY, Json(json!({ "error": too_many.to_string() }))),
+        }
+    }
+}
+
+/// The `LIMIT` for reading at most `limit` points: one more, so going over
+/// shows.
+pub fn fetch_limit(limit: usize) -> i64 {
+    i64::try_from(limit).unwrap_or(i64::MAX).saturating_add(1)
+}
+
+/// `points`, unless there are more than `limit` of them.
+pub fn within<T>(points: Vec<T>, limit: usize) -> Result<Vec<T>, ReadError> {
+    if points.len() > limit {
+        return Err(ReadError::TooManyPoints(limit));
+    }
+    Ok(points)
+}
@@ -0,0 +1,250 @@
+//! Email for alert rules and digests that notify by it, sent with
+//! [`lettre`]. Each message gets its own connection: TLS from the start on
+//! port 465, upgraded with `STARTTLS` otherwise unless that's switched off,
+//! and `AUTH PLAIN` or `AUTH LOGIN` when credentials are set.
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use lettre::message::header::{ContentType, Header, HeaderName, HeaderValue};
+use lettre::message::{Mailbox, Message};
+use lettre::transport::smtp::authentication::{Credentials, Mechanism};
+use lettre::transport::smtp::client::{Tls, TlsParameters};
+use lettre::transport::smtp::extension::ClientId;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+
+use crate::client::ClientError;
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+/// What we call ourselves in `EHLO`; servers only log it
+const CLIENT_NAME: &str = "somnial.localhost";
+const MAX_ADDRESS_LENGTH: usize = 254;
+
+/// How the connection to the SMTP server is protected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SmtpSecurity {
+    /// TLS from the first byte, usually on port 465
+    Tls,
+    /// Plain TCP upgraded with `STARTTLS`, usually on port 587
+    StartTls,
+    /// No encryption, for a relay on the same host or network
+    None,
+}
+
+impl SmtpSecurity {
+    pub fn default_port(self) -> u16 {
+        match self {
+            SmtpSecurity::Tls => 465,
+            SmtpSecurity::StartTls => 587,
+            SmtpSecurity::None => 25,
+        }
+    }
+}
+
+impl FromStr for SmtpSecurity {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, ()> {
+        match value.to_ascii_lowercase().as_str() {
+            "tls" => Ok(SmtpSecurity::Tls),
+            "starttls" => Ok(SmtpSecurity::StartTls),
+            "none" => Ok(SmtpSecurity::None),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The mail server alert emails go through, from the `SMTP_*` settings.
+#[derive(Clone, Debug)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// The `From` header, either an address or `Name <address>`
+    pub from: String,
+}
+
+/// Whether `address` is a plain `user@domain` we're willing to put in a
+/// header and an SMTP command.
+pub fn valid_address(address: &str) -> bool {
+    let Some((user, domain)) = address.rsplit_once('@') else {
+        return false;
+    };
+    address.len() <= MAX_ADDRESS_LENGTH
+        && !user.is_empty()
+        && !domain.is_empty()
+        && !address.chars().any(|c| c.is_whitespace() || c.is_control() || "<>,;\"()[]\\".contains(c))
+}
+
+/// The address within a `From` value such as `Somnial <alerts@example.com>`.
+pub fn envelope_address(from: &str) -> &str {
+    match (from.rfind('<'), from.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &from[start + 1..end],
+        _ => from.trim(),
+    }
+}
+
+/// `Auto-Submitted: auto-generated`, so vacation responders leave alerts be.
+#[derive(Clone)]
+struct AutoSubmitted;
+
+impl Header for AutoSubmitted {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("Auto-Submitted")
+    }
+
+    fn parse(_: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(AutoSubmitted)
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), "auto-generated".to_string())
+    }
+}
+
+/// Sends a plain-text email to `to`, which must pass [`valid_address`].
+pub async fn send(config: &SmtpConfig, to: &str, subject: &str, body: &str) -> Result<(), ClientError> {
+    let message = Message::builder()
+        .from(config.from.parse::<Mailbox>()?)
+        .to(to.parse::<Mailbox>()?)
+        .subject(subject)
+        .header(ContentType::TEXT_PLAIN)
+        .header(AutoSubmitted)
+        .body(body.to_string())?;
+
+    let parameters = || TlsParameters::new(config.host.clone());
+    let tls = match config.security {
+        SmtpSecurity::Tls => Tls::Wrapper(parameters()?),
+        SmtpSecurity::StartTls => Tls::Required(parameters()?),
+        SmtpSecurity::None => Tls::None,
+    };
+    let mut transport = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host)
+        .port(config.port)
+        .tls(tls)
+        .timeout(Some(TIMEOUT))
+        .hello_name(ClientId::Domain(CLIENT_NAME.to_string()));
+    if let Some(username) = &config.username {
+        let password = config.password.clone().unwrap_or_default();
+        transport = transport
+            .credentials(Credentials::new(username.clone(), password))
+            .authentication(vec![Mechanism::Plain, Mechanism::Login]);
+    }
+    transport.build().send(message).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// An SMTP server on a local port that takes one message, returning its
+    /// port and the commands and data it was sent.
+    async fn smtp_server() -> (u16, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let session = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (read, mut write) = stream.into_split();
+            let mut lines = BufReader::new(read).lines();
+            let mut received = Vec::new();
+            write.write_all(b"220 test ESMTP\r\n").await.unwrap();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                let reply: &[u8] = match line.split(' ').next().unwrap_or_default() {
+                    "EHLO" => b"250-test\r\n250 AUTH PLAIN LOGIN\r\n",
+                    "AUTH" => b"235 2.7.0 Authenticated\r\n",
+                    "DATA" => b"354 Go ahead\r\n",
+                    "QUIT" => {
+                        received.push(line);
+                        write.write_all(b"221 Bye\r\n").await.unwrap();
+                        break;
+                    }
+                    _ => b"250 OK\r\n",
+                };
+                let data = line == "DATA";
+                received.push(line);
+                write.write_all(reply).await.unwrap();
+                if data {
+                    let mut message = String::new();
+                    while let Some(line) = lines.next_line().await.unwrap() {
+                        if line == "." {
+                            break;
+                        }
+                        message.push_str(&line);
+                        message.push('\n');
+                    }
+                    received.push(message);
+                    write.write_all(b"250 Queued\r\n").await.unwrap();
+                }
+            }
+            received
+        });
+        (port, session)
+    }
+
+    fn config(port: u16) -> SmtpConfig {
+        SmtpConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            security: SmtpSecurity::None,
+            username: Some("alerts".to_string()),
+            password: Some("hunter2".to_string()),
+            from: "Somnial <alerts@example.com>".to_string(),
+        }
+    }
+
+    #[test]
+    fn addresses() {
+        assert!(valid_address("ops@example.com"));
+        assert!(valid_address("first.last+alerts@mail.example.com"));
+        for address in ["", "ops", "@example.com", "ops@", "o ps@example.com", "ops@example.com\r\nBcc: x@y.z"] {
+            assert!(!valid_address(address), "{:?}", address);
+        }
+        assert!(!valid_address("<ops@example.com>"));
+        assert!(!valid_address(&format!("{}@example.com", "a".repeat(250))));
+
+        assert_eq!(envelope_address("Somnial <alerts@example.com>"), "alerts@example.com");
+        assert_eq!(envelope_address(" alerts@example.com "), "alerts@example.com");
+        assert_eq!(envelope_address("broken> <"), "broken> <");
+    }
+
+    #[test]
+    fn security_settings() {
+        assert_eq!("STARTTLS".parse(), Ok(SmtpSecurity::StartTls));
+        assert_eq!("tls".parse::<SmtpSecurity>().map(SmtpSecurity::default_port), Ok(465));
+        assert_eq!("none".parse::<SmtpSecurity>().map(SmtpSecurity::default_port), Ok(25));
+        assert_eq!("ssl".parse::<SmtpSecurity>(), Err(()));
+    }
+
+    #[tokio::test]
+    async fn sends_through_smtp() {
+        let (port, session) = smtp_server().await;
+        send(&config(port), "ops@example.com", "build_time fired", "Over 60 for 5m").await.unwrap();
+        let received = session.await.unwrap();
+
+        assert_eq!(received[0], format!("EHLO {}", CLIENT_NAME));
+        // "\0alerts\0hunter2"
+        assert_eq!(received[1], "AUTH PLAIN AGFsZXJ0cwBodW50ZXIy");
+        assert_eq!(received[2], "MAIL FROM:<alerts@example.com>");
+        assert_eq!(received[3], "RCPT TO:<ops@example.com>");
+        assert_eq!(received[4], "DATA");
+        let message = &received[5];
+        assert!(message.contains("From: Somnial <alerts@example.com>\n"), "{}", message);
+        assert!(message.contains("To: ops@example.com\n"), "{}", message);
+        assert!(message.contains("Subject: build_time fired\n"), "{}", message);
+        assert!(message.contains("Auto-Submitted: auto-generated\n"), "{}", message);
+        assert!(message.ends_with("\n\nOver 60 for 5m\n"), "{}", message);
+        assert_eq!(received.last().map(String::as_str), Some("QUIT"));
+    }
+
+    #[tokio::test]
+    async fn bad_addresses_are_errors_before_connecting() {
+        let config = SmtpConfig { from: "not an address".to_string(), ..config(1) };
+        assert!(send(&config, "ops@example.com", "subject", "body").await.is_err());
+        assert!(send(&self::config(1), "ops", "subject", "body").await.is_err());
+    }
+}
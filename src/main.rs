@@ -1,424 +1,192 @@
-use askama::Template;
-use axum::{
-    body::Body,
-    extract::{Path, Query},
-    http::StatusCode,
-    response::{Html, IntoResponse, Response},
-    routing::{get, post},
-    Router,
-};
-use chrono::Utc;
-// Using resvg for high-quality SVG to PNG rendering
-use serde::{Deserialize, Serialize};
-use sqlx::{sqlite::SqlitePool, migrate::MigrateDatabase};
-
-#[derive(Deserialize)]
-struct PostMetricQuery {
-    value: f64,
-}
-
-#[derive(Deserialize)]
-struct PaginationQuery {
-    page: Option<u32>,
-}
-
-#[derive(Serialize)]
-struct MetricPoint {
-    timestamp: i64,
-    value: f64,
-}
-
-#[derive(Template)]
-#[template(path = "index.html")]
-struct IndexTemplate;
-
-#[derive(Template)]
-#[template(path = "chart.html")]
-struct ChartTemplate {
-    namespace: String,
-    id: String,
-    data_json: String,
-}
-
-#[derive(Template)]
-#[template(path = "namespace.html")]
-struct NamespaceTemplate {
-    namespace: String,
-    charts: Vec<ChartInfo>,
-    current_page: u32,
-    total_pages: u32,
-    has_prev: bool,
-    has_next: bool,
+use std::path::PathBuf;
+
+use somnial::config::{Config, Sources};
+use somnial::cli::{self, Command, TokenFor};
+use somnial::runtime::{self, LogOutput, PidFile, RotatingFile};
+
+const USAGE: &str = "\
+Usage: somnial [serve] [OPTIONS]
+       somnial prune [OPTIONS]
+       somnial export <NAMESPACE> [--output <PATH>] [OPTIONS]
+       somnial import <NAMESPACE> <PATH> [OPTIONS]
+       somnial token create [--namespace <NAMESPACE> | --dashboard <SLUG>] [OPTIONS]
+
+Commands:
+  serve                   Run the server; the default
+  prune                   Delete what retention, inactivity and the trash would, right away
+  export                  Write a namespace's bundle to PATH, or to stdout
+  import                  Restore a bundle from PATH, or - for stdin, into an empty namespace
+  token create            Print a new admin token, issue a namespace its owner token,
+                          or replace a dashboard's edit token
+
+Options:
+  --config <PATH>         Read settings from a TOML file [env: SOMNIAL_CONFIG]
+  --set <KEY=VALUE>       Set a config file setting, such as retention.days=90; repeatable
+  --database-url <URL>    Use the database at URL, like --set database.url=URL
+  --log-file <PATH>       Append logs to PATH instead of stdout, or stderr for commands
+  --log-max-size <BYTES>  Rotate the log file once it exceeds BYTES [default: 10485760]
+  --log-keep <N>          Number of rotated log files to keep [default: 5]
+  -h, --help              Print this help
+
+Serve options:
+  --bind <ADDRESS>        Listen on ADDRESS, like --set bind_address=ADDRESS [default: 0.0.0.0]
+  --port <PORT>           Listen on PORT, like --set port=PORT [default: 3000]
+  --unix-socket <PATH>    Listen on a Unix socket at PATH instead of a TCP port
+  --pid-file <PATH>       Write the process id to PATH while running
+  --detach                Start in the background and return immediately (requires --log-file)
+
+Every config file setting can also be set by the environment variable it
+stands for, such as RETENTION_DAYS for retention.days. Flags override the
+environment, which overrides the file. The other commands open the database
+themselves, so a server can keep running on it; reload it afterwards.";
+
+struct Args {
+    /// `None` to serve
+    command: Option<Command>,
+    sources: Sources,
+    pid_file: Option<PathBuf>,
+    log_file: Option<PathBuf>,
+    log_max_size: u64,
+    log_keep: usize,
+    detach: bool,
 }
 
-#[derive(Serialize)]
-struct ChartInfo {
-    id: String,
-    point_count: i64,
-    last_updated: String,
+/// The command named first, and the arguments only it takes.
+fn parse_command(args: &mut pico_args::Arguments) -> Result<Option<Command>, Box<dyn std::error::Error>> {
+    let command = match args.subcommand()?.as_deref() {
+        None | Some("serve") => return Ok(None),
+        Some("prune") => Command::Prune,
+        Some("export") => {
+            let output = args.opt_value_from_str(["-o", "--output"])?;
+            Command::Export {
+                namespace: args.free_from_str().map_err(|_| "export needs a NAMESPACE")?,
+                output,
+            }
+        }
+        Some("import") => Command::Import {
+            namespace: args.free_from_str().map_err(|_| "import needs a NAMESPACE and a PATH")?,
+            input: args.free_from_str().map_err(|_| "import needs a NAMESPACE and a PATH")?,
+        },
+        Some("token") => {
+            if args.subcommand()?.as_deref() != Some("create") {
+                return Err("the token command is token create".into());
+            }
+            let namespace = args.opt_value_from_str("--namespace")?;
+            let dashboard = args.opt_value_from_str("--dashboard")?;
+            Command::CreateToken(match (namespace, dashboard) {
+                (None, None) => TokenFor::Admin,
+                (Some(namespace), None) => TokenFor::Namespace(namespace),
+                (None, Some(slug)) => TokenFor::Dashboard(slug),
+                (Some(_), Some(_)) => return Err("token create takes --namespace or --dashboard, not both".into()),
+            })
+        }
+        Some(other) => return Err(format!("unknown command {:?}\n\n{}", other, USAGE).into()),
+    };
+    Ok(Some(command))
 }
 
-async fn post_metric(
-    Path((namespace, id)): Path<(String, String)>,
-    Query(params): Query<PostMetricQuery>,
-    pool: axum::extract::State<SqlitePool>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let timestamp = Utc::now().timestamp();
-    
-    let result = sqlx::query!(
-        "INSERT INTO metrics (namespace, id, value, timestamp) VALUES (?, ?, ?, ?)",
-        namespace,
-        id,
-        params.value,
-        timestamp
-    )
-    .execute(&*pool)
-    .await;
-    
-    match result {
-        Ok(_) => Ok(StatusCode::OK),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+fn parse_args() -> Result<Args, Box<dyn std::error::Error>> {
+    let mut args = pico_args::Arguments::from_env();
+    if args.contains(["-h", "--help"]) {
+        println!("{}", USAGE);
+        std::process::exit(0);
     }
-}
+    let command = parse_command(&mut args)?;
+    let serving = command.is_none();
 
-async fn get_chart(
-    Path((namespace, id)): Path<(String, String)>,
-    pool: axum::extract::State<SqlitePool>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let rows = sqlx::query!(
-        "SELECT value, timestamp FROM metrics WHERE namespace = ? AND id = ? ORDER BY timestamp ASC",
-        namespace,
-        id
-    )
-    .fetch_all(&*pool)
-    .await;
-    
-    let data = match rows {
-        Ok(rows) => rows
-            .into_iter()
-            .map(|row| MetricPoint {
-                timestamp: row.timestamp,
-                value: row.value,
-            })
-            .collect::<Vec<_>>(),
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    let mut sources = Sources::default();
+    let config_path: Option<PathBuf> = match args.opt_value_from_str("--config")? {
+        Some(path) => Some(path),
+        None => std::env::var_os("SOMNIAL_CONFIG").filter(|path| !path.is_empty()).map(PathBuf::from),
     };
-    
-    let data_json = serde_json::to_string(&data).unwrap_or_default();
-    
-    let template = ChartTemplate {
-        namespace,
-        id,
-        data_json,
-    };
-    
-    match template.render() {
-        Ok(html) => Ok(Html(html)),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    if let Some(path) = &config_path {
+        sources.read_file(path)?;
     }
-}
-
-async fn get_index() -> Result<impl IntoResponse, StatusCode> {
-    let template = IndexTemplate;
-    match template.render() {
-        Ok(html) => Ok(Html(html)),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    let mut flags: Vec<(String, String)> = Vec::new();
+    let listen_flags = [("--bind", "bind_address"), ("--port", "port"), ("--unix-socket", "unix_socket")];
+    let setting_flags = [("--database-url", "database.url")]
+        .into_iter()
+        .chain(listen_flags.into_iter().filter(|_| serving));
+    for (flag, key) in setting_flags {
+        if let Some(value) = args.opt_value_from_str::<_, String>(flag)? {
+            flags.push((key.to_string(), value));
+        }
+    }
+    for setting in args.values_from_str::<_, String>("--set")? {
+        let (key, value) = setting
+            .split_once('=')
+            .ok_or_else(|| format!("--set needs KEY=VALUE, not {:?}", setting))?;
+        flags.push((key.trim().to_string(), value.to_string()));
+    }
+    for (key, value) in flags {
+        sources.set(&key, value)?;
     }
-}
-
-async fn get_favicon() -> impl IntoResponse {
-    const FAVICON_SVG: &str = include_str!("../favicon.svg");
-    Response::builder()
-        .status(StatusCode::OK)
-        .header("content-type", "image/svg+xml")
-        .body(Body::from(FAVICON_SVG.to_string()))
-        .unwrap()
-}
 
-fn generate_sparkline_badge(data: &[MetricPoint], metric_name: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    // Badge dimensions
-    let width = 240;
-    let height = 40;
-    let corner_radius = 6;
-    let padding = 8;
-    
-    // Generate sparkline path if we have data
-    let sparkline_path = if !data.is_empty() {
-        let values: Vec<f64> = data.iter().map(|p| p.value).collect();
-        let min_val = values.iter().fold(f64::INFINITY, |a, &b| a.min(b));
-        let max_val = values.iter().fold(f64::NEG_INFINITY, |a, &b| a.max(b));
-        
-        let chart_width = width - 2 * padding;
-        let chart_height = 16; // Space for sparkline
-        let chart_y_start = 20; // Below text
-        
-        let mut path_data = String::new();
-        
-        if data.len() == 1 {
-            // Single point - draw a small horizontal line
-            let x = width / 2;
-            let y = chart_y_start + chart_height / 2;
-            path_data.push_str(&format!("M{} {} L{} {}", x - 5, y, x + 5, y));
-        } else if max_val == min_val {
-            // Flat line - all values the same
-            let y = chart_y_start + chart_height / 2;
-            path_data.push_str(&format!("M{} {} L{} {}", padding, y, width - padding, y));
-        } else {
-            // Normal sparkline with varying values - use timestamp-based X positioning
-            let timestamps: Vec<i64> = data.iter().map(|p| p.timestamp).collect();
-            let min_time = *timestamps.iter().min().unwrap();
-            let max_time = *timestamps.iter().max().unwrap();
-            let time_range = (max_time - min_time).max(1); // Avoid division by zero
-            
-            for (i, point) in data.iter().enumerate() {
-                let x = if time_range > 0 {
-                    padding + ((point.timestamp - min_time) as f64 / time_range as f64 * chart_width as f64) as i32
-                } else {
-                    padding + (i as i32 * chart_width / (data.len() - 1) as i32)
-                };
-                let y = chart_y_start + chart_height - ((point.value - min_val) / (max_val - min_val) * chart_height as f64) as i32;
-                
-                if i == 0 {
-                    path_data.push_str(&format!("M{} {}", x, y));
-                } else {
-                    path_data.push_str(&format!(" L{} {}", x, y));
-                }
-            }
-        }
-        
-        path_data
-    } else {
-        String::new()
+    let parsed = Args {
+        command,
+        sources,
+        pid_file: if serving { args.opt_value_from_str("--pid-file")? } else { None },
+        log_file: args.opt_value_from_str("--log-file")?,
+        log_max_size: args.opt_value_from_str("--log-max-size")?.unwrap_or(10 * 1024 * 1024),
+        log_keep: args.opt_value_from_str("--log-keep")?.unwrap_or(5),
+        detach: serving && args.contains("--detach"),
     };
-    
-    // Generate SVG
-    let svg = format!(
-        r#"<svg width="{}" height="{}" xmlns="http://www.w3.org/2000/svg">
-  <defs>
-    <style>
-      .badge-bg {{ fill: white; stroke: black; stroke-width: 1; }}
-      .badge-text {{ font-family: monospace; font-size: 11px; fill: black; font-weight: bold; }}
-      .sparkline {{ fill: none; stroke: black; stroke-width: 1.5; stroke-linecap: round; stroke-linejoin: round; }}
-    </style>
-  </defs>
-  
-  <!-- White background -->
-  <rect x="0" y="0" width="{}" height="{}" fill="white"/>
-  
-  <!-- Background rounded rectangle with border -->
-  <rect x="0.5" y="0.5" width="{}" height="{}" rx="{}" ry="{}" class="badge-bg"/>
-  
-  <!-- Metric name -->
-  <text x="{}" y="13" class="badge-text">{}</text>
-  
-  <!-- Sparkline -->
-  {}
-  
-</svg>"#,
-        width, height,
-        width, height,
-        width - 1, height - 1, corner_radius, corner_radius,
-        padding, 
-        escape_xml(metric_name),
-        if sparkline_path.is_empty() {
-            String::new()
-        } else {
-            format!(r#"<path d="{}" class="sparkline"/>"#, sparkline_path)
-        }
-    );
-    
-    // Parse SVG and render to PNG
-    let opt = usvg::Options::default();
-    let tree = usvg::Tree::from_str(&svg, &opt)?;
-    
-    let pixmap_size = tree.size().to_int_size();
-    let mut pixmap = resvg::tiny_skia::Pixmap::new(pixmap_size.width(), pixmap_size.height())
-        .ok_or("Failed to create pixmap")?;
-    
-    resvg::render(&tree, usvg::Transform::default(), &mut pixmap.as_mut());
-    
-    // Convert to PNG
-    let png_data = pixmap.encode_png()?;
-    Ok(png_data)
-}
 
-fn escape_xml(text: &str) -> String {
-    text.chars()
-        .map(|c| match c {
-            '<' => "&lt;".to_string(),
-            '>' => "&gt;".to_string(),
-            '&' => "&amp;".to_string(),
-            '"' => "&quot;".to_string(),
-            '\'' => "&#39;".to_string(),
-            _ => c.to_string(),
-        })
-        .collect()
+    let rest = args.finish();
+    if !rest.is_empty() {
+        return Err(format!("unexpected arguments: {:?}\n\n{}", rest, USAGE).into());
+    }
+    if parsed.detach && parsed.log_file.is_none() {
+        return Err("--detach needs --log-file, since a detached process has no terminal to log to".into());
+    }
+    Ok(parsed)
 }
 
-
-async fn get_badge(
-    Path((namespace, id)): Path<(String, String)>,
-    pool: axum::extract::State<SqlitePool>,
-    headers: axum::http::HeaderMap,
-) -> Result<impl IntoResponse, StatusCode> {
-    // Get last 50 points for the sparkline
-    let rows = sqlx::query!(
-        "SELECT value, timestamp FROM metrics WHERE namespace = ? AND id = ? ORDER BY timestamp DESC LIMIT 50",
-        namespace,
-        id
-    )
-    .fetch_all(&*pool)
-    .await;
-    
-    let mut data = match rows {
-        Ok(rows) => rows
-            .into_iter()
-            .map(|row| MetricPoint {
-                timestamp: row.timestamp,
-                value: row.value,
-            })
-            .collect::<Vec<_>>(),
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(2);
+        }
     };
     
-    // Reverse to get chronological order
-    data.reverse();
+    if args.detach {
+        let child_args: Vec<String> = std::env::args().skip(1).filter(|a| a != "--detach").collect();
+        let pid = runtime::spawn_detached(&child_args)?;
+        println!("Started somnial in the background (pid {})", pid);
+        return Ok(());
+    }
     
-    // Generate ETag based on latest timestamp and data count
-    let etag = if let Some(latest) = data.first() {
-        format!("\"{}:{}\"", latest.timestamp, data.len())
-    } else {
-        "\"empty\"".to_string()
+    // Commands may write what they produce to stdout, so their logs go
+    // to stderr
+    let log_output = match &args.log_file {
+        Some(path) => LogOutput::File(RotatingFile::open(path, args.log_max_size, args.log_keep)?),
+        None if args.command.is_some() => LogOutput::Stderr,
+        None => LogOutput::Stdout,
     };
-    
-    // Check if client has current version
-    if let Some(if_none_match) = headers.get("if-none-match") {
-        if if_none_match.to_str().unwrap_or("") == etag {
-            return Ok(Response::builder()
-                .status(StatusCode::NOT_MODIFIED)
-                .header("etag", &etag)
-                .header("cache-control", "public, max-age=300")
-                .body(Body::empty())
-                .unwrap());
+    let config = match Config::load(&args.sources) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("error: invalid configuration: {}", err);
+            std::process::exit(2);
         }
-    }
-    
-    match generate_sparkline_badge(&data, &id) {
-        Ok(png_data) => {
-            Ok(Response::builder()
-                .status(StatusCode::OK)
-                .header("content-type", "image/png")
-                .header("etag", etag)
-                .header("cache-control", "public, max-age=300")
-                .body(Body::from(png_data))
-                .unwrap())
+    };
+    runtime::init_logging(log_output, &args.sources, config.trace_export.as_ref())?;
+    if let Some(command) = args.command {
+        if let Err(err) = cli::run(command, config, args.sources).await {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
         }
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        return Ok(());
     }
-}
-
-async fn get_namespace(
-    Path(namespace): Path<String>,
-    Query(pagination): Query<PaginationQuery>,
-    pool: axum::extract::State<SqlitePool>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let page = pagination.page.unwrap_or(1).max(1);
-    let per_page = 12; // Show 12 charts per page (nice grid layout)
-    let offset = (page - 1) * per_page;
-    
-    // Get total count for pagination
-    let total_count = sqlx::query!(
-        "SELECT COUNT(DISTINCT id) as count FROM metrics WHERE namespace = ?",
-        namespace
-    )
-    .fetch_one(&*pool)
-    .await
-    .map(|row| row.count as u32)
-    .unwrap_or(0);
+    let pid_file = args.pid_file.as_deref().map(PidFile::create).transpose()?;
     
-    let total_pages = (total_count + per_page - 1) / per_page; // Ceiling division
-    
-    let rows = sqlx::query!(
-        r#"
-        SELECT 
-            id,
-            COUNT(*) as "point_count: i64",
-            MAX(timestamp) as "last_timestamp: i64"
-        FROM metrics 
-        WHERE namespace = ? 
-        GROUP BY id
-        ORDER BY MAX(timestamp) DESC
-        LIMIT ? OFFSET ?
-        "#,
-        namespace,
-        per_page,
-        offset
-    )
-    .fetch_all(&*pool)
-    .await;
-    
-    let charts = match rows {
-        Ok(rows) => rows
-            .into_iter()
-            .map(|row| ChartInfo {
-                id: row.id,
-                point_count: row.point_count.unwrap_or(0),
-                last_updated: row.last_timestamp
-                    .and_then(|ts| chrono::DateTime::from_timestamp(ts, 0))
-                    .map(|dt| dt.format("%Y-%m-%d %H:%M:%S UTC").to_string())
-                    .unwrap_or_else(|| "Unknown".to_string()),
-            })
-            .collect::<Vec<_>>(),
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-    };
-    
-    let template = NamespaceTemplate {
-        namespace,
-        charts,
-        current_page: page,
-        total_pages,
-        has_prev: page > 1,
-        has_next: page < total_pages,
-    };
-    
-    match template.render() {
-        Ok(html) => Ok(Html(html)),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    if let Err(err) = somnial::serve(config, args.sources).await {
+        tracing::error!("{}", err);
+        drop(pid_file);
+        std::process::exit(1);
     }
-}
-
-
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Create database connection pool
-    let database_url = std::env::var("DATABASE_URL")
-        .unwrap_or_else(|_| "sqlite:somnial.db".to_string());
-    
-    // Create database if it doesn't exist
-    sqlx::sqlite::Sqlite::create_database(&database_url).await.ok();
-    
-    let pool = SqlitePool::connect(&database_url).await?;
-    
-    // Run migrations
-    sqlx::migrate!("./migrations").run(&pool).await?;
-    
-    // Build application routes
-    let app = Router::new()
-        .route("/", get(get_index))
-        .route("/favicon.svg", get(get_favicon))
-        .route("/{namespace}", get(get_namespace))
-        .route("/{namespace}/{id}", post(post_metric))
-        .route("/{namespace}/{id}", get(get_chart))
-        .route("/{namespace}/{id}/badge.png", get(get_badge))
-        .with_state(pool);
-    
-    // Start server
-    let port = std::env::var("PORT").unwrap_or_else(|_| "3000".to_string());
-    let addr = format!("0.0.0.0:{}", port);
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-    println!("Server running on {}", addr);
-    
-    axum::serve(listener, app).await?;
-    
     Ok(())
 }
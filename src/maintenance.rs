@@ -0,0 +1,112 @@
+//! Keeping the database file in shape. SQLite doesn't hand the pages freed by
+//! retention, expiry and purges back to the filesystem on its own, so every
+//! `MAINTENANCE_INTERVAL_SECS` a background pass releases them with an
+//! incremental vacuum, refreshes the query planner's statistics and
+//! truncates the WAL. Admins can run the same pass with
+//! `POST /admin/maintenance`, say straight after a big delete.
+//!
+//! Incremental vacuuming needs `auto_vacuum = INCREMENTAL`. New databases
+//! are created with it; older ones only take it on through one full
+//! `VACUUM`, which the first pass on them does.
+
+use std::sync::atomic::Ordering;
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::time::{self, MissedTickBehavior};
+
+use crate::{auth, errors, AppState};
+
+/// `PRAGMA auto_vacuum` for incremental mode
+const AUTO_VACUUM_INCREMENTAL: i64 = 2;
+
+#[derive(Serialize)]
+pub struct Report {
+    /// Whether this pass switched the database to incremental vacuuming,
+    /// rewriting the whole file to do so
+    converted: bool,
+    /// Bytes the file shrank by
+    reclaimed_bytes: i64,
+    /// Size of the file afterwards, not counting the WAL
+    size_bytes: i64,
+}
+
+async fn pragma(state: &AppState, name: &str) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(&format!("PRAGMA {}", name)).fetch_one(&state.pool).await
+}
+
+/// Runs one maintenance pass.
+pub async fn run(state: &AppState) -> Result<Report, sqlx::Error> {
+    let pool = &state.pool;
+    let page_size = pragma(state, "page_size").await?;
+    let pages_before = pragma(state, "page_count").await?;
+
+    let converted = pragma(state, "auto_vacuum").await? != AUTO_VACUUM_INCREMENTAL;
+    // The mode only sticks if the VACUUM runs on the same connection, hence one query
+    let vacuum = if converted {
+        "PRAGMA auto_vacuum = INCREMENTAL; VACUUM"
+    } else {
+        "PRAGMA incremental_vacuum"
+    };
+    state.write(|| sqlx::query(vacuum).execute(pool)).await?;
+    state.write(|| sqlx::query("ANALYZE").execute(pool)).await?;
+    state
+        .write(|| sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)").execute(pool))
+        .await?;
+
+    let pages_after = pragma(state, "page_count").await?;
+    let reclaimed_bytes = (pages_before - pages_after).max(0) * page_size;
+    state
+        .metrics
+        .maintenance_reclaimed_bytes
+        .fetch_add(reclaimed_bytes as u64, Ordering::Relaxed);
+    Ok(Report {
+        converted,
+        reclaimed_bytes,
+        size_bytes: pages_after * page_size,
+    })
+}
+
+pub async fn post_maintenance(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    auth::require_admin(&state.config(), &headers)
+        .map_err(|status| (status, Json(json!({ "error": "unauthorized" }))))?;
+
+    let report = run(&state)
+        .await
+        .map_err(|err| (errors::internal(err), Json(json!({ "error": "database error" }))))?;
+    Ok(Json(report))
+}
+
+/// Runs a pass every configured interval, starting one interval after
+/// startup, unless the schedule is switched off.
+pub fn spawn(state: AppState) {
+    let Some(interval) = state.config().maintenance_interval else {
+        return;
+    };
+    tokio::spawn(async move {
+        let mut ticks = time::interval_at(time::Instant::now() + interval, interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            match run(&state).await {
+                Ok(report) if report.converted => {
+                    tracing::info!("Switched the database to incremental vacuuming; it is now {} bytes", report.size_bytes)
+                }
+                Ok(report) if report.reclaimed_bytes > 0 => {
+                    tracing::info!("Maintenance reclaimed {} bytes", report.reclaimed_bytes)
+                }
+                Ok(_) => {}
+                Err(err) => tracing::warn!("Database maintenance failed: {}", err),
+            }
+        }
+    });
+}
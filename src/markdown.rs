@@ -0,0 +1,216 @@
+//! A small Markdown renderer for namespace READMEs. It covers what a short
+//! "what these metrics are" note needs: headings, paragraphs, lists, code,
+//! emphasis and links. Raw HTML is never passed through; everything is
+//! escaped, and links only keep `http`, `https`, `mailto` and relative URLs.
+
+use crate::badge::escape_xml;
+
+/// Renders `source` to HTML that is safe to put on the page as is, with
+/// links to paths on this site put under `base_path`.
+///
+/// Headings are shifted down one level, since the page already has its `h1`.
+pub fn to_html(source: &str, base_path: &str) -> String {
+    let mut html = String::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut list: Option<&'static str> = None;
+    let mut lines = source.lines();
+
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim();
+
+        if trimmed.starts_with("```") {
+            flush_paragraph(&mut html, &mut paragraph, base_path);
+            close_list(&mut html, &mut list);
+            let mut code = String::new();
+            for line in lines.by_ref() {
+                if line.trim().starts_with("```") {
+                    break;
+                }
+                code.push_str(line);
+                code.push('\n');
+            }
+            html.push_str(&format!("<pre><code>{}</code></pre>\n", escape_xml(&code)));
+            continue;
+        }
+
+        if trimmed.is_empty() {
+            flush_paragraph(&mut html, &mut paragraph, base_path);
+            close_list(&mut html, &mut list);
+            continue;
+        }
+
+        if let Some((level, text)) = heading(trimmed) {
+            flush_paragraph(&mut html, &mut paragraph, base_path);
+            close_list(&mut html, &mut list);
+            let level = (level + 1).min(6);
+            html.push_str(&format!("<h{}>{}</h{}>\n", level, inline(text, base_path), level));
+            continue;
+        }
+
+        if let Some((tag, text)) = list_item(trimmed) {
+            flush_paragraph(&mut html, &mut paragraph, base_path);
+            if list != Some(tag) {
+                close_list(&mut html, &mut list);
+                html.push_str(&format!("<{}>\n", tag));
+                list = Some(tag);
+            }
+            html.push_str(&format!("<li>{}</li>\n", inline(text, base_path)));
+            continue;
+        }
+
+        close_list(&mut html, &mut list);
+        paragraph.push(trimmed);
+    }
+
+    flush_paragraph(&mut html, &mut paragraph, base_path);
+    close_list(&mut html, &mut list);
+    html
+}
+
+fn flush_paragraph(html: &mut String, paragraph: &mut Vec<&str>, base_path: &str) {
+    if !paragraph.is_empty() {
+        html.push_str(&format!("<p>{}</p>\n", inline(&paragraph.join(" "), base_path)));
+        paragraph.clear();
+    }
+}
+
+fn close_list(html: &mut String, list: &mut Option<&'static str>) {
+    if let Some(tag) = list.take() {
+        html.push_str(&format!("</{}>\n", tag));
+    }
+}
+
+/// `# Title` through `###### Title`.
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|&c| c == '#').count();
+    let text = line[level..].strip_prefix(' ')?;
+    (1..=6).contains(&level).then(|| (level, text.trim()))
+}
+
+/// `- item`, `* item`, `+ item` or `1. item`, with the list tag each belongs in.
+fn list_item(line: &str) -> Option<(&'static str, &str)> {
+    if let Some(text) = line.strip_prefix("- ").or_else(|| line.strip_prefix("* ")).or_else(|| line.strip_prefix("+ ")) {
+        return Some(("ul", text));
+    }
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    let text = line[digits..].strip_prefix(". ")?;
+    (digits > 0).then_some(("ol", text))
+}
+
+/// Code spans, `**strong**`, `*em*` or `_em_`, and `[text](url)` links.
+/// Underscores only emphasise at word edges, so ids like `build_time_ms`
+/// come through intact.
+fn inline(text: &str, base_path: &str) -> String {
+    let mut html = String::new();
+    let mut rest = text;
+    let mut prev: Option<char> = None;
+
+    while let Some(c) = rest.chars().next() {
+        if c == '`'
+            && let Some(end) = rest[1..].find('`')
+        {
+            html.push_str(&format!("<code>{}</code>", escape_xml(&rest[1..1 + end])));
+            rest = &rest[end + 2..];
+            continue;
+        }
+        if let Some(inner) = rest.strip_prefix("**")
+            && let Some(end) = inner.find("**")
+            && end > 0
+        {
+            html.push_str(&format!("<strong>{}</strong>", inline(&inner[..end], base_path)));
+            rest = &inner[end + 2..];
+            continue;
+        }
+        if (c == '*' || c == '_' && !prev.is_some_and(char::is_alphanumeric))
+            && let Some(end) = rest[1..].find(c)
+            && end > 0
+            && (c == '*' || !rest[end + 2..].starts_with(char::is_alphanumeric))
+        {
+            html.push_str(&format!("<em>{}</em>", inline(&rest[1..1 + end], base_path)));
+            rest = &rest[end + 2..];
+            continue;
+        }
+        if c == '['
+            && let Some((label, url, len)) = link(rest)
+        {
+            match safe_url(url) {
+                Some(url) => {
+                    // Paths on this site sit under its prefix
+                    let base = if url.starts_with('/') { base_path } else { "" };
+                    html.push_str(&format!(
+                        "<a href=\"{}{}\" rel=\"nofollow\">{}</a>",
+                        base,
+                        escape_xml(url),
+                        inline(label, base_path)
+                    ))
+                }
+                None => html.push_str(&inline(label, base_path)),
+            }
+            rest = &rest[len..];
+            continue;
+        }
+        html.push_str(&escape_xml(&rest[..c.len_utf8()]));
+        rest = &rest[c.len_utf8()..];
+        prev = Some(c);
+    }
+    html
+}
+
+/// Splits `[label](url)` off the front of `text`, with its length in bytes.
+fn link(text: &str) -> Option<(&str, &str, usize)> {
+    let label_end = text.find("](")?;
+    let url_end = text[label_end + 2..].find(')')? + label_end + 2;
+    Some((&text[1..label_end], text[label_end + 2..url_end].trim(), url_end + 1))
+}
+
+/// Keeps a link target only if it can't run script.
+fn safe_url(url: &str) -> Option<&str> {
+    let lower = url.to_ascii_lowercase();
+    let allowed = ["http://", "https://", "mailto:"].iter().any(|scheme| lower.starts_with(scheme))
+        || url.starts_with('/')
+        || url.starts_with('#');
+    allowed.then_some(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_blocks() {
+        let source = "# Build times\n\nHow long\nCI takes.\n\n- one\n- two\n1. first\n\n```\nlet x = <y>;\n```\n";
+        assert_eq!(
+            to_html(source, ""),
+            "<h2>Build times</h2>\n<p>How long CI takes.</p>\n<ul>\n<li>one</li>\n<li>two</li>\n</ul>\n\
+             <ol>\n<li>first</li>\n</ol>\n<pre><code>let x = &lt;y&gt;;\n</code></pre>\n"
+        );
+        assert_eq!(to_html("####### deep", ""), "<p>####### deep</p>\n");
+        assert_eq!(to_html("#tag", ""), "<p>#tag</p>\n");
+    }
+
+    #[test]
+    fn renders_inline_markup() {
+        assert_eq!(
+            inline("**bold** and *em* and _em_ and `a<b`", ""),
+            "<strong>bold</strong> and <em>em</em> and <em>em</em> and <code>a&lt;b</code>"
+        );
+        assert_eq!(inline("build_time_ms and snake_case_", ""), "build_time_ms and snake_case_");
+        assert_eq!(inline("** not bold", ""), "** not bold");
+        assert_eq!(inline("unclosed `code", ""), "unclosed `code");
+        assert_eq!(inline("café *ü*", ""), "café <em>ü</em>");
+    }
+
+    #[test]
+    fn escapes_html_and_unsafe_links() {
+        assert_eq!(to_html("<script>alert(1)</script>", ""), "<p>&lt;script&gt;alert(1)&lt;/script&gt;</p>\n");
+        assert_eq!(inline("[x](JavaScript:void)", ""), "x");
+        assert_eq!(inline("[x](data:text/html,hi)", ""), "x");
+        assert_eq!(
+            inline("[docs](https://example.com/?a=1&b=\"2\")", ""),
+            "<a href=\"https://example.com/?a=1&amp;b=&quot;2&quot;\" rel=\"nofollow\">docs</a>"
+        );
+        assert_eq!(inline("[**ci**](/ci)", "/somnial"), "<a href=\"/somnial/ci\" rel=\"nofollow\"><strong>ci</strong></a>");
+        assert_eq!(inline("[top](#top)", "/somnial"), "<a href=\"#top\" rel=\"nofollow\">top</a>");
+        assert_eq!(inline("[not a link]", ""), "[not a link]");
+    }
+}
@@ -0,0 +1,300 @@
+//! Namespace-wide markers such as deploys and releases, drawn as vertical
+//! lines on every chart in the namespace so a jump can be traced to its cause.
+//!
+//! `POST /{namespace}/markers` takes either a plain `{label, url, timestamp}`
+//! body or a GitHub webhook delivery: successful `deployment_status` events
+//! and published releases become markers, and every other event is ignored.
+//!
+//! Once `GITHUB_WEBHOOK_SECRET` is set, every request must be signed with
+//! it the way GitHub signs deliveries; plain bodies may instead carry the
+//! namespace's owner token or the admin token. Without a secret anyone can
+//! post markers.
+
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use sqlx::sqlite::SqlitePool;
+
+use crate::{auth, ids::NamespacePath, owners, AppState, Bounds};
+
+const MAX_LABEL_LENGTH: usize = 200;
+const MAX_URL_LENGTH: usize = 2048;
+/// Charts show at most this many markers, the most recent first.
+const MAX_MARKERS: i64 = 200;
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(json!({ "error": message })))
+}
+
+fn database_error<E: std::fmt::Display>(err: E) -> ApiError {
+    crate::errors::report(&err);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Marker {
+    pub id: i64,
+    pub timestamp: i64,
+    pub label: String,
+    pub url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MarkerRequest {
+    label: String,
+    url: Option<String>,
+    /// Unix timestamp; defaults to now
+    timestamp: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct GitHubRepository {
+    html_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GitHubRelease {
+    tag_name: String,
+    html_url: Option<String>,
+    published_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct ReleaseEvent {
+    action: String,
+    release: GitHubRelease,
+}
+
+#[derive(Deserialize)]
+struct GitHubDeployment {
+    #[serde(rename = "ref")]
+    git_ref: String,
+    environment: String,
+}
+
+#[derive(Deserialize)]
+struct GitHubDeploymentStatus {
+    state: String,
+    target_url: Option<String>,
+    created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct DeploymentStatusEvent {
+    deployment_status: GitHubDeploymentStatus,
+    deployment: GitHubDeployment,
+    repository: Option<GitHubRepository>,
+}
+
+/// Checks `X-Hub-Signature-256`, GitHub's HMAC of the body under the
+/// shared webhook secret.
+fn verify_signature(secret: &str, headers: &HeaderMap, body: &[u8]) -> bool {
+    let Some(signature) = headers
+        .get("x-hub-signature-256")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("sha256="))
+        .and_then(decode_hex)
+    else {
+        return false;
+    };
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+        return false;
+    };
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// The marker a GitHub delivery describes, or `None` for events that
+/// don't mark anything.
+fn from_github(event: &str, body: &[u8]) -> Result<Option<MarkerRequest>, ApiError> {
+    let invalid = |_| error(StatusCode::BAD_REQUEST, "unrecognized webhook payload");
+    match event {
+        "release" => {
+            let event: ReleaseEvent = serde_json::from_slice(body).map_err(invalid)?;
+            if event.action != "published" {
+                return Ok(None);
+            }
+            Ok(Some(MarkerRequest {
+                label: format!("release {}", event.release.tag_name),
+                url: event.release.html_url,
+                timestamp: event.release.published_at.map(|at| at.timestamp()),
+            }))
+        }
+        "deployment_status" => {
+            let event: DeploymentStatusEvent = serde_json::from_slice(body).map_err(invalid)?;
+            if event.deployment_status.state != "success" {
+                return Ok(None);
+            }
+            Ok(Some(MarkerRequest {
+                label: format!(
+                    "deploy {} to {}",
+                    event.deployment.git_ref, event.deployment.environment
+                ),
+                url: event
+                    .deployment_status
+                    .target_url
+                    .filter(|url| !url.is_empty())
+                    .or(event.repository.and_then(|repo| repo.html_url)),
+                timestamp: event.deployment_status.created_at.map(|at| at.timestamp()),
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn validate(request: &MarkerRequest) -> Result<(), ApiError> {
+    let label = request.label.trim();
+    if label.is_empty() || label.len() > MAX_LABEL_LENGTH {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "label must be 1 to 200 bytes"));
+    }
+    if let Some(url) = &request.url
+        && (url.len() > MAX_URL_LENGTH || !(url.starts_with("https://") || url.starts_with("http://")))
+    {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "url must be an http or https link"));
+    }
+    Ok(())
+}
+
+pub async fn post_marker(
+    NamespacePath(namespace): NamespacePath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    let event = headers.get("x-github-event").and_then(|v| v.to_str().ok());
+    // With a secret set nothing goes unauthenticated: GitHub deliveries must
+    // be signed, and plain bodies signed too or sent with a token
+    let signed = state
+        .config()
+        .github_webhook_secret
+        .as_deref()
+        .map(|secret| verify_signature(secret, &headers, &body));
+    match (signed, event) {
+        (Some(false), Some(_)) => return Err(error(StatusCode::UNAUTHORIZED, "invalid webhook signature")),
+        (Some(false), None) => owners::require_owner(&state, &headers, &namespace).await?,
+        _ => {}
+    }
+
+    let request = match event {
+        Some(event) => match from_github(event, &body)? {
+            Some(request) => request,
+            None => return Ok(StatusCode::NO_CONTENT.into_response()),
+        },
+        None => serde_json::from_slice(&body)
+            .map_err(|_| error(StatusCode::BAD_REQUEST, "expected a JSON body with a label"))?,
+    };
+    validate(&request)?;
+
+    let source = if event.is_some() { "github" } else { "api" };
+    let marker = Marker {
+        id: 0,
+        timestamp: request.timestamp.unwrap_or_else(|| Utc::now().timestamp()),
+        label: request.label.trim().to_string(),
+        url: request.url,
+    };
+    let (pool, namespace, marker_ref) = (&state.pool, &namespace, &marker);
+    let id = state
+        .write(|| async move {
+            sqlx::query!(
+                "INSERT INTO markers (namespace, timestamp, label, url, source) VALUES (?, ?, ?, ?, ?)",
+                namespace,
+                marker_ref.timestamp,
+                marker_ref.label,
+                marker_ref.url,
+                source
+            )
+            .execute(pool)
+            .await
+        })
+        .await
+        .map_err(database_error)?
+        .last_insert_rowid();
+
+    Ok((StatusCode::CREATED, Json(Marker { id, ..marker })).into_response())
+}
+
+/// The namespace's markers between `since` and `until`, oldest first.
+pub async fn load(
+    pool: &SqlitePool,
+    namespace: &str,
+    (since, until): Bounds,
+) -> Result<Vec<Marker>, sqlx::Error> {
+    let (since, until) = (since.unwrap_or(i64::MIN), until.unwrap_or(i64::MAX));
+    let mut markers: Vec<Marker> = sqlx::query!(
+        r#"SELECT marker_id as "marker_id!", timestamp, label, url FROM markers WHERE namespace = ? AND timestamp BETWEEN ? AND ? ORDER BY timestamp DESC, marker_id DESC LIMIT ?"#,
+        namespace,
+        since,
+        until,
+        MAX_MARKERS
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| Marker {
+        id: row.marker_id,
+        timestamp: row.timestamp,
+        label: row.label,
+        url: row.url,
+    })
+    .collect();
+    markers.reverse();
+    Ok(markers)
+}
+
+pub async fn list_markers(
+    NamespacePath(namespace): NamespacePath,
+    State(pool): State<SqlitePool>,
+) -> Result<impl IntoResponse, ApiError> {
+    let markers = load(&pool, &namespace, (None, None)).await.map_err(database_error)?;
+    Ok(Json(markers))
+}
+
+pub async fn delete_marker(
+    Path((namespace, marker)): Path<(String, i64)>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+    let namespace = state.config().id_policy.normalize(&namespace);
+
+    let (pool, namespace) = (&state.pool, &namespace);
+    let deleted = state
+        .write(|| async move {
+            sqlx::query!(
+                "DELETE FROM markers WHERE namespace = ? AND marker_id = ?",
+                namespace,
+                marker
+            )
+            .execute(pool)
+            .await
+        })
+        .await
+        .map_err(database_error)?
+        .rows_affected();
+
+    if deleted == 0 {
+        return Err(error(StatusCode::NOT_FOUND, "no such marker"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
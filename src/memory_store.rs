@@ -0,0 +1,555 @@
+//! A [`MetricStore`] that keeps points in memory, for tests that want the
+//! handlers without a point table behind them. It answers every query the
+//! way [`SqliteStore`](crate::store::SqliteStore) does, down to the order of
+//! tied timestamps, and nothing it holds survives the process.
+
+use std::collections::{BTreeMap, HashSet};
+use std::future;
+use std::sync::{Mutex, MutexGuard};
+
+use futures_util::{stream, StreamExt};
+
+use crate::{
+    store::{
+        Aggregate, Bucket, BucketScope, LatestWrite, ListFrom, MetricListing, MetricStore, NamespaceSummary,
+        PointSelector, PointStream, StoreFuture, StoredPoint, TrashedSeries,
+    },
+    MetricPoint,
+};
+
+/// Points by `(namespace, id)`.
+type Series = BTreeMap<(String, String), Vec<Row>>;
+
+#[derive(Clone, Debug)]
+struct Row {
+    point_id: i64,
+    timestamp: i64,
+    value: f64,
+    sha: Option<String>,
+    branch: Option<String>,
+}
+
+impl Row {
+    fn point(&self) -> MetricPoint {
+        MetricPoint {
+            timestamp: self.timestamp,
+            value: self.value,
+            sha: self.sha.clone(),
+            branch: self.branch.clone(),
+        }
+    }
+
+    fn matches(&self, selector: PointSelector) -> bool {
+        match selector {
+            PointSelector::Id(point_id) => self.point_id == point_id,
+            PointSelector::Timestamp(timestamp) => self.timestamp == timestamp,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Data {
+    /// The id the last stored point got
+    last_point_id: i64,
+    /// Each series' points by timestamp, then insertion
+    series: Series,
+    /// Deleted series with when they were deleted
+    trash: BTreeMap<(String, String), (i64, Vec<Row>)>,
+}
+
+impl Data {
+    fn store(&mut self, namespace: &str, id: &str, point: &MetricPoint) {
+        self.last_point_id += 1;
+        let row = Row {
+            point_id: self.last_point_id,
+            timestamp: point.timestamp,
+            value: point.value,
+            sha: point.sha.clone(),
+            branch: point.branch.clone(),
+        };
+        let rows = self.series.entry(key(namespace, id)).or_default();
+        // The new point has the highest id, so it goes after any at its timestamp
+        let at = rows.partition_point(|existing| existing.timestamp <= row.timestamp);
+        rows.insert(at, row);
+    }
+
+    fn rows(&self, namespace: &str, id: &str) -> &[Row] {
+        self.series.get(&key(namespace, id)).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Takes the rows of a series that `remove` picks, dropping the series
+    /// if none are left.
+    fn remove_where(&mut self, namespace: &str, id: &str, mut remove: impl FnMut(&Row) -> bool) -> Vec<Row> {
+        let key = key(namespace, id);
+        let Some(rows) = self.series.get_mut(&key) else {
+            return Vec::new();
+        };
+        let (removed, kept): (Vec<Row>, Vec<Row>) = rows.drain(..).partition(|row| remove(row));
+        *rows = kept;
+        if rows.is_empty() {
+            self.series.remove(&key);
+        }
+        removed
+    }
+
+    fn namespace(&self, namespace: &str) -> impl Iterator<Item = (&str, &[Row])> {
+        self.series
+            .iter()
+            .filter(move |((ns, _), _)| ns == namespace)
+            .map(|((_, id), rows)| (id.as_str(), rows.as_slice()))
+    }
+}
+
+fn key(namespace: &str, id: &str) -> (String, String) {
+    (namespace.to_string(), id.to_string())
+}
+
+fn ready<'a, T: Send + 'a>(value: T) -> StoreFuture<'a, T> {
+    Box::pin(future::ready(Ok(value)))
+}
+
+/// [`MetricStore`] over a map in memory.
+#[derive(Default)]
+pub struct MemoryStore {
+    data: Mutex<Data>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+
+    fn data(&self) -> MutexGuard<'_, Data> {
+        self.data.lock().unwrap()
+    }
+}
+
+impl MetricStore for MemoryStore {
+    fn insert<'a>(&'a self, namespace: &'a str, points: &'a [(&'a str, MetricPoint)]) -> StoreFuture<'a, u64> {
+        let mut data = self.data();
+        for (id, point) in points {
+            data.store(namespace, id, point);
+        }
+        ready(points.len() as u64)
+    }
+
+    fn insert_new<'a>(&'a self, namespace: &'a str, points: &'a [(&'a str, MetricPoint)]) -> StoreFuture<'a, u64> {
+        let mut data = self.data();
+        // Only points stored before this call count, so repeats within
+        // `points` are all kept
+        let before = data.last_point_id;
+        let mut inserted = 0;
+        for (id, point) in points {
+            let stored = data.rows(namespace, id).iter().any(|row| {
+                row.point_id <= before && row.timestamp == point.timestamp && row.value == point.value
+            });
+            if !stored {
+                data.store(namespace, id, point);
+                inserted += 1;
+            }
+        }
+        ready(inserted)
+    }
+
+    fn delete<'a>(&'a self, namespace: &'a str, id: &'a str) -> StoreFuture<'a, u64> {
+        let deleted = self.data().series.remove(&key(namespace, id)).map_or(0, |rows| rows.len());
+        ready(deleted as u64)
+    }
+
+    fn trash<'a>(&'a self, namespace: &'a str, id: &'a str, deleted_at: i64) -> StoreFuture<'a, u64> {
+        let mut data = self.data();
+        let Some(rows) = data.series.remove(&key(namespace, id)) else {
+            return ready(0);
+        };
+        let moved = rows.len() as u64;
+        let trashed = data.trash.entry(key(namespace, id)).or_insert((deleted_at, Vec::new()));
+        trashed.0 = deleted_at;
+        trashed.1.extend(rows);
+        ready(moved)
+    }
+
+    fn untrash<'a>(&'a self, namespace: &'a str, id: &'a str) -> StoreFuture<'a, Option<Vec<i64>>> {
+        let mut data = self.data();
+        let Some((_, rows)) = data.trash.remove(&key(namespace, id)) else {
+            return ready(None);
+        };
+        for row in &rows {
+            data.store(namespace, id, &row.point());
+        }
+        ready(Some(rows.iter().map(|row| row.timestamp).collect()))
+    }
+
+    fn trashed(&self) -> StoreFuture<'_, Vec<TrashedSeries>> {
+        let data = self.data();
+        let mut trashed: Vec<TrashedSeries> = data
+            .trash
+            .iter()
+            .map(|((namespace, id), (deleted_at, rows))| TrashedSeries {
+                namespace: namespace.clone(),
+                id: id.clone(),
+                points: rows.len() as i64,
+                deleted_at: *deleted_at,
+            })
+            .collect();
+        trashed.sort_by_key(|series| series.deleted_at);
+        ready(trashed)
+    }
+
+    fn empty_trash(&self, cutoff: i64) -> StoreFuture<'_, Vec<(String, String)>> {
+        let mut data = self.data();
+        let expired: Vec<(String, String)> = data
+            .trash
+            .iter()
+            .filter(|(_, (deleted_at, _))| *deleted_at < cutoff)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &expired {
+            data.trash.remove(key);
+        }
+        ready(expired)
+    }
+
+    fn range<'a>(
+        &'a self,
+        namespace: &'a str,
+        id: &'a str,
+        since: i64,
+        until: i64,
+        limit: i64,
+    ) -> StoreFuture<'a, Vec<MetricPoint>> {
+        let data = self.data();
+        let points = data
+            .rows(namespace, id)
+            .iter()
+            .filter(|row| (since..=until).contains(&row.timestamp))
+            .take(usize::try_from(limit).unwrap_or(0))
+            .map(Row::point)
+            .collect();
+        ready(points)
+    }
+
+    fn latest<'a>(&'a self, namespace: &'a str, id: &'a str) -> StoreFuture<'a, Option<MetricPoint>> {
+        ready(self.data().rows(namespace, id).last().map(Row::point))
+    }
+
+    fn points<'a>(
+        &'a self,
+        namespace: &'a str,
+        id: &'a str,
+        (since, until): (i64, i64),
+        limit: i64,
+    ) -> StoreFuture<'a, Vec<StoredPoint>> {
+        let data = self.data();
+        let points = data
+            .rows(namespace, id)
+            .iter()
+            .filter(|row| (since..=until).contains(&row.timestamp))
+            .take(usize::try_from(limit).unwrap_or(0))
+            .map(|row| StoredPoint {
+                point_id: row.point_id,
+                timestamp: row.timestamp,
+                value: row.value,
+                sha: row.sha.clone(),
+                branch: row.branch.clone(),
+            })
+            .collect();
+        ready(points)
+    }
+
+    fn delete_points<'a>(
+        &'a self,
+        namespace: &'a str,
+        id: &'a str,
+        selector: PointSelector,
+    ) -> StoreFuture<'a, Vec<i64>> {
+        let removed = self.data().remove_where(namespace, id, |row| row.matches(selector));
+        ready(removed.iter().map(|row| row.timestamp).collect())
+    }
+
+    fn update_points<'a>(
+        &'a self,
+        namespace: &'a str,
+        id: &'a str,
+        selector: PointSelector,
+        value: f64,
+    ) -> StoreFuture<'a, Vec<i64>> {
+        let mut data = self.data();
+        let mut updated = Vec::new();
+        if let Some(rows) = data.series.get_mut(&key(namespace, id)) {
+            for row in rows.iter_mut().filter(|row| row.matches(selector)) {
+                row.value = value;
+                updated.push(row.timestamp);
+            }
+        }
+        ready(updated)
+    }
+
+    fn trim<'a>(&'a self, namespace: &'a str, id: &'a str, keep: i64) -> StoreFuture<'a, Vec<i64>> {
+        let mut data = self.data();
+        let rows = data.rows(namespace, id);
+        let first_kept = rows.len().saturating_sub(usize::try_from(keep).unwrap_or(0));
+        let kept: HashSet<i64> = rows[first_kept..].iter().map(|row| row.point_id).collect();
+        let removed = data.remove_where(namespace, id, |row| !kept.contains(&row.point_id));
+        ready(removed.iter().map(|row| row.timestamp).collect())
+    }
+
+    fn merge<'a>(&'a self, namespace: &'a str, from: &'a str, into: &'a str) -> StoreFuture<'a, (Vec<i64>, u64)> {
+        let mut data = self.data();
+        let taken: HashSet<i64> = data.rows(namespace, into).iter().map(|row| row.timestamp).collect();
+        let Some(rows) = data.series.remove(&key(namespace, from)) else {
+            return ready((Vec::new(), 0));
+        };
+        let (moved, dropped): (Vec<Row>, Vec<Row>) = rows.into_iter().partition(|row| !taken.contains(&row.timestamp));
+        let timestamps = moved.iter().map(|row| row.timestamp).collect();
+        let target = data.series.entry(key(namespace, into)).or_default();
+        target.extend(moved);
+        target.sort_by_key(|row| (row.timestamp, row.point_id));
+        ready((timestamps, dropped.len() as u64))
+    }
+
+    fn list<'a>(&'a self, namespace: &'a str, from: ListFrom<'a>, limit: i64) -> StoreFuture<'a, Vec<MetricListing>> {
+        let data = self.data();
+        let mut listings: Vec<MetricListing> = data
+            .namespace(namespace)
+            .map(|(id, rows)| MetricListing {
+                id: id.to_string(),
+                point_count: rows.len() as i64,
+                last_timestamp: rows.last().map(|row| row.timestamp),
+            })
+            .collect();
+        // Newest first, then by id
+        listings.sort_by(|a, b| b.last_timestamp.cmp(&a.last_timestamp).then_with(|| a.id.cmp(&b.id)));
+        let limit = usize::try_from(limit).unwrap_or(0);
+        let page = match from {
+            ListFrom::Start => listings.into_iter().take(limit).collect(),
+            ListFrom::Offset(offset) => listings
+                .into_iter()
+                .skip(usize::try_from(offset).unwrap_or(0))
+                .take(limit)
+                .collect(),
+            ListFrom::After(after) => listings
+                .into_iter()
+                .filter(|listing| {
+                    let last = listing.last_timestamp.unwrap_or(i64::MIN);
+                    last < after.last_timestamp || (last == after.last_timestamp && listing.id.as_str() > after.id)
+                })
+                .take(limit)
+                .collect(),
+            ListFrom::Before(before) => listings
+                .into_iter()
+                .rev()
+                .filter(|listing| {
+                    let last = listing.last_timestamp.unwrap_or(i64::MIN);
+                    last > before.last_timestamp || (last == before.last_timestamp && listing.id.as_str() < before.id)
+                })
+                .take(limit)
+                .collect(),
+        };
+        ready(page)
+    }
+
+    fn aggregate<'a>(
+        &'a self,
+        namespace: &'a str,
+        id: &'a str,
+        since: i64,
+        until: i64,
+    ) -> StoreFuture<'a, Option<Aggregate>> {
+        let data = self.data();
+        let rows: Vec<&Row> = data
+            .rows(namespace, id)
+            .iter()
+            .filter(|row| (since..=until).contains(&row.timestamp))
+            .collect();
+        let Some(latest) = rows.last() else {
+            return ready(None);
+        };
+        let count = rows.len() as f64;
+        ready(Some(Aggregate {
+            count: rows.len() as i64,
+            min: rows.iter().map(|row| row.value).fold(f64::INFINITY, f64::min),
+            max: rows.iter().map(|row| row.value).fold(f64::NEG_INFINITY, f64::max),
+            mean: rows.iter().map(|row| row.value).sum::<f64>() / count,
+            mean_square: rows.iter().map(|row| row.value * row.value).sum::<f64>() / count,
+            latest: latest.value,
+            last_timestamp: latest.timestamp,
+        }))
+    }
+
+    fn recent<'a>(&'a self, namespace: &'a str, id: &'a str, until: i64, limit: i64) -> StoreFuture<'a, Vec<MetricPoint>> {
+        let data = self.data();
+        let rows = data.rows(namespace, id);
+        let end = rows.partition_point(|row| row.timestamp <= until);
+        let start = end.saturating_sub(usize::try_from(limit).unwrap_or(0));
+        ready(rows[start..end].iter().map(Row::point).collect())
+    }
+
+    fn namespaces(&self) -> StoreFuture<'_, Vec<String>> {
+        let mut namespaces: Vec<String> = self.data().series.keys().map(|(namespace, _)| namespace.clone()).collect();
+        namespaces.dedup();
+        ready(namespaces)
+    }
+
+    fn ids<'a>(&'a self, namespace: &'a str) -> StoreFuture<'a, Vec<String>> {
+        ready(self.data().namespace(namespace).map(|(id, _)| id.to_string()).collect())
+    }
+
+    fn namespace_summary<'a>(&'a self, namespace: &'a str) -> StoreFuture<'a, NamespaceSummary> {
+        let data = self.data();
+        let mut summary = NamespaceSummary {
+            metrics: 0,
+            points: 0,
+            latest: None,
+        };
+        for (id, rows) in data.namespace(namespace) {
+            summary.metrics += 1;
+            summary.points += rows.len() as i64;
+            let Some(last) = rows.last() else { continue };
+            let newer = summary
+                .latest
+                .as_ref()
+                .is_none_or(|latest| (last.timestamp, last.point_id) > (latest.timestamp, latest.point_id));
+            if newer {
+                summary.latest = Some(LatestWrite {
+                    point_id: last.point_id,
+                    id: id.to_string(),
+                    timestamp: last.timestamp,
+                });
+            }
+        }
+        ready(summary)
+    }
+
+    fn rename<'a>(&'a self, namespace: &'a str, from: &'a str, to: &'a str) -> StoreFuture<'a, Option<u64>> {
+        let mut data = self.data();
+        if data.series.contains_key(&key(namespace, to)) {
+            return ready(None);
+        }
+        let Some(rows) = data.series.remove(&key(namespace, from)) else {
+            return ready(Some(0));
+        };
+        let moved = rows.len() as u64;
+        data.series.insert(key(namespace, to), rows);
+        ready(Some(moved))
+    }
+
+    fn copy_namespace<'a>(&'a self, from: &'a str, to: &'a str, since: i64) -> StoreFuture<'a, Option<u64>> {
+        let mut data = self.data();
+        if data.namespace(to).next().is_some() {
+            return ready(None);
+        }
+        let mut rows: Vec<(String, Row)> = data
+            .namespace(from)
+            .flat_map(|(id, rows)| rows.iter().map(move |row| (id.to_string(), row.clone())))
+            .filter(|(_, row)| row.timestamp >= since)
+            .collect();
+        // Copies are stored in the order the originals were
+        rows.sort_by_key(|(_, row)| row.point_id);
+        for (id, row) in &rows {
+            data.store(to, id, &row.point());
+        }
+        ready(Some(rows.len() as u64))
+    }
+
+    fn delete_namespace<'a>(&'a self, namespace: &'a str) -> StoreFuture<'a, u64> {
+        let mut data = self.data();
+        let mut deleted = 0;
+        data.series.retain(|(ns, _), rows| {
+            if ns == namespace {
+                deleted += rows.len() as u64;
+            }
+            ns != namespace
+        });
+        data.trash.retain(|(ns, _), _| ns != namespace);
+        ready(deleted)
+    }
+
+    fn expire<'a>(&'a self, namespace: &'a str, id: &'a str, cutoff: i64) -> StoreFuture<'a, u64> {
+        let mut data = self.data();
+        let live = data.rows(namespace, id).iter().any(|row| row.timestamp >= cutoff);
+        if live {
+            return ready(0);
+        }
+        let expired = data.series.remove(&key(namespace, id)).map_or(0, |rows| rows.len());
+        ready(expired as u64)
+    }
+
+    fn watermark(&self) -> StoreFuture<'_, i64> {
+        ready(self.data().last_point_id)
+    }
+
+    fn scan<'a>(&'a self, namespace: &'a str, before: i64, through: i64) -> PointStream<'a> {
+        let data = self.data();
+        let points: Vec<_> = data
+            .namespace(namespace)
+            .flat_map(|(id, rows)| rows.iter().map(move |row| (id, row)))
+            .filter(|(_, row)| row.timestamp < before && row.point_id <= through)
+            .map(|(id, row)| Ok((id.to_string(), row.point())))
+            .collect();
+        stream::iter(points).boxed()
+    }
+
+    fn prune<'a>(&'a self, namespace: &'a str, before: i64, through: i64, limit: i64) -> StoreFuture<'a, u64> {
+        let mut data = self.data();
+        let ids: Vec<String> = data.namespace(namespace).map(|(id, _)| id.to_string()).collect();
+        let mut left = usize::try_from(limit).unwrap_or(0);
+        let mut pruned = 0;
+        for id in ids {
+            let removed = data.remove_where(namespace, &id, |row| {
+                let expired = left > 0 && row.timestamp < before && row.point_id <= through;
+                if expired {
+                    left -= 1;
+                }
+                expired
+            });
+            pruned += removed.len() as u64;
+        }
+        ready(pruned)
+    }
+
+    fn first_timestamp(&self) -> StoreFuture<'_, Option<i64>> {
+        let data = self.data();
+        ready(data.series.values().filter_map(|rows| rows.first()).map(|row| row.timestamp).min())
+    }
+
+    fn buckets<'a>(
+        &'a self,
+        scope: BucketScope<'a>,
+        resolution: i64,
+        since: i64,
+        until: i64,
+    ) -> StoreFuture<'a, Vec<Bucket>> {
+        let data = self.data();
+        let mut buckets: Vec<Bucket> = Vec::new();
+        for ((namespace, id), rows) in &data.series {
+            let in_scope = match scope {
+                BucketScope::All => true,
+                BucketScope::Namespace(scope) => namespace == scope,
+                BucketScope::Series(scope, scope_id) => namespace == scope && id == scope_id,
+            };
+            if !in_scope {
+                continue;
+            }
+            let mut series: BTreeMap<i64, Bucket> = BTreeMap::new();
+            for row in rows.iter().filter(|row| (since..until).contains(&row.timestamp)) {
+                // Truncating like SQLite's `%`, so both stores agree on negative timestamps
+                let start = row.timestamp - row.timestamp % resolution;
+                let bucket = series.entry(start).or_insert_with(|| Bucket {
+                    namespace: namespace.clone(),
+                    id: id.clone(),
+                    bucket: start,
+                    count: 0,
+                    sum: 0.0,
+                    min: f64::INFINITY,
+                    max: f64::NEG_INFINITY,
+                });
+                bucket.count += 1;
+                bucket.sum += row.value;
+                bucket.min = bucket.min.min(row.value);
+                bucket.max = bucket.max.max(row.value);
+            }
+            buckets.extend(series.into_values());
+        }
+        ready(buckets)
+    }
+}
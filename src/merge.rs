@@ -0,0 +1,101 @@
+//! `POST /api/v1/namespaces/{namespace}/metrics/{id}/merge`: folds one series
+//! into another, for when a metric was renamed and its history ended up
+//! split across two ids. Where both have a point at the same timestamp the
+//! target's is kept. The merged series is then gone, settings and all; the
+//! target keeps its own, and the old id's URLs redirect to it as after a
+//! rename.
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{aliases, auth, ids::SeriesPath, rollup, AppState};
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(json!({ "error": message })))
+}
+
+fn database_error<E: std::fmt::Display>(err: E) -> ApiError {
+    crate::errors::report(&err);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+/// `{"into": "build_time"}`
+#[derive(Deserialize)]
+pub struct MergeRequest {
+    into: String,
+}
+
+pub async fn post_merge(
+    SeriesPath(namespace, id): SeriesPath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<MergeRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+
+    let into = state.config().id_policy.normalize(&request.into);
+    if into.is_empty() {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "into must name a metric"));
+    }
+    state
+        .config()
+        .id_policy
+        .check(&into)
+        .map_err(|message| error(StatusCode::UNPROCESSABLE_ENTITY, &message))?;
+    if into == id {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "a metric can't be merged into itself"));
+    }
+
+    let (pool, namespace, id, into) = (&state.pool, &namespace, &id, &into);
+    let (moved, dropped) = state
+        .write_store(|| state.store.merge(namespace, id, into))
+        .await
+        .map_err(database_error)?;
+    if moved.is_empty() && dropped == 0 {
+        return Err(error(StatusCode::NOT_FOUND, "no metric with that id"));
+    }
+    state
+        .write(|| async move {
+            let mut tx = pool.begin().await?;
+            sqlx::query!("DELETE FROM metric_meta WHERE namespace = ? AND id = ?", namespace, id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query!("DELETE FROM metric_precision WHERE namespace = ? AND id = ?", namespace, id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query!("DELETE FROM metric_rollups WHERE namespace = ? AND id = ?", namespace, id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query!("DELETE FROM metric_point_caps WHERE namespace = ? AND id = ?", namespace, id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query!("DELETE FROM github_baselines WHERE namespace = ? AND id = ?", namespace, id)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await
+        })
+        .await
+        .map_err(database_error)?;
+    state.invalidate_series(namespace, id);
+    state.invalidate_series(namespace, into);
+    rollup::rebuild_buckets(&state, namespace, into, &moved)
+        .await
+        .map_err(database_error)?;
+    aliases::record(&state, namespace, id, into).await.map_err(database_error)?;
+
+    Ok(Json(json!({
+        "namespace": namespace,
+        "from": id,
+        "into": into,
+        "moved": moved.len(),
+        "dropped": dropped,
+    })))
+}
@@ -0,0 +1,260 @@
+//! Per-metric display defaults, such as the y-axis scale and chart type.
+//! Chart pages use them unless the query string asks for something else.
+//!
+//! A metric can also say how its values read: a unit (`ms`, `MB`, `%`), a
+//! description, and how many decimals to show. Chart pages and badges format
+//! values with these; stored points are unaffected (see `precision` for that).
+
+use std::collections::HashMap;
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::sqlite::SqlitePool;
+
+use crate::{auth, badge, errors, ids::SeriesPath, AppState};
+
+const MAX_UNIT_LEN: usize = 16;
+const MAX_DESCRIPTION_LEN: usize = 500;
+const MAX_DECIMALS: i64 = 10;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Scale {
+    #[default]
+    Linear,
+    /// For metrics spanning orders of magnitude; points at or below zero
+    /// can't be placed and are left out
+    Log,
+}
+
+impl Scale {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scale::Linear => "linear",
+            Scale::Log => "log",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "linear" => Some(Scale::Linear),
+            "log" => Some(Scale::Log),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChartType {
+    #[default]
+    Line,
+    Area,
+    /// Holds each value until the next point, for counters and settings
+    Step,
+    /// For discrete counts, such as one point per day
+    Bar,
+    Scatter,
+}
+
+impl ChartType {
+    pub const ALL: &'static [ChartType] = &[
+        ChartType::Line,
+        ChartType::Area,
+        ChartType::Step,
+        ChartType::Bar,
+        ChartType::Scatter,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChartType::Line => "line",
+            ChartType::Area => "area",
+            ChartType::Step => "step",
+            ChartType::Bar => "bar",
+            ChartType::Scatter => "scatter",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|chart_type| chart_type.as_str() == value)
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct MetricMeta {
+    pub scale: Option<Scale>,
+    #[serde(rename = "type")]
+    pub chart_type: Option<ChartType>,
+    /// Appended to every displayed value, such as `ms` or `%`
+    pub unit: Option<String>,
+    pub description: Option<String>,
+    /// Fixed places to show; unset picks a compact form like `1.05k`
+    pub decimals: Option<i64>,
+}
+
+impl MetricMeta {
+    /// A value as it should be shown for this metric.
+    pub fn format(&self, value: f64) -> String {
+        let number = match self.decimals {
+            Some(decimals) => format!("{:.*}", decimals as usize, value),
+            None => badge::format_value(value),
+        };
+        match self.unit.as_deref() {
+            None => number,
+            // Percent and degree signs sit against the number
+            Some(unit) if unit.starts_with(['%', '°']) => format!("{}{}", number, unit),
+            Some(unit) => format!("{} {}", number, unit),
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), &'static str> {
+        if self.unit.as_ref().is_some_and(|unit| unit.chars().count() > MAX_UNIT_LEN) {
+            return Err("unit must be at most 16 characters");
+        }
+        if self
+            .description
+            .as_ref()
+            .is_some_and(|description| description.chars().count() > MAX_DESCRIPTION_LEN)
+        {
+            return Err("description must be at most 500 characters");
+        }
+        if let Some(decimals) = self.decimals
+            && !(0..=MAX_DECIMALS).contains(&decimals)
+        {
+            return Err("decimals must be between 0 and 10");
+        }
+        Ok(())
+    }
+}
+
+/// The display defaults stored for one metric, or none at all.
+pub async fn load(pool: &SqlitePool, namespace: &str, id: &str) -> Result<MetricMeta, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT scale, chart_type, unit, description, decimals FROM metric_meta WHERE namespace = ? AND id = ?",
+        namespace,
+        id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row
+        .map(|row| MetricMeta {
+            scale: row.scale.as_deref().and_then(Scale::parse),
+            chart_type: row.chart_type.as_deref().and_then(ChartType::parse),
+            unit: row.unit,
+            description: row.description,
+            decimals: row.decimals,
+        })
+        .unwrap_or_default())
+}
+
+/// Every metric's stored display defaults in a namespace, keyed by id.
+pub async fn load_namespace(pool: &SqlitePool, namespace: &str) -> Result<HashMap<String, MetricMeta>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT id, scale, chart_type, unit, description, decimals FROM metric_meta WHERE namespace = ?",
+        namespace
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.id,
+                MetricMeta {
+                    scale: row.scale.as_deref().and_then(Scale::parse),
+                    chart_type: row.chart_type.as_deref().and_then(ChartType::parse),
+                    unit: row.unit,
+                    description: row.description,
+                    decimals: row.decimals,
+                },
+            )
+        })
+        .collect())
+}
+
+pub async fn get_meta(
+    SeriesPath(namespace, id): SeriesPath,
+    State(pool): State<SqlitePool>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let meta = load(&pool, &namespace, &id)
+        .await
+        .map_err(errors::internal)?;
+    Ok(Json(meta))
+}
+
+/// Replaces a metric's display defaults. Cached badges for the metric are
+/// dropped so they pick up the new formatting.
+pub async fn put_meta(
+    SeriesPath(namespace, id): SeriesPath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(meta): Json<MetricMeta>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    auth::require_admin(&state.config(), &headers)
+        .map_err(|status| (status, Json(serde_json::json!({ "error": "unauthorized" }))))?;
+    // Blank strings mean the same as leaving a field out
+    let meta = MetricMeta {
+        unit: meta.unit.filter(|unit| !unit.trim().is_empty()),
+        description: meta.description.filter(|description| !description.trim().is_empty()),
+        ..meta
+    };
+    meta.validate()
+        .map_err(|msg| (StatusCode::UNPROCESSABLE_ENTITY, Json(serde_json::json!({ "error": msg }))))?;
+
+    let (scale, chart_type) = (meta.scale.map(Scale::as_str), meta.chart_type.map(ChartType::as_str));
+    let (pool, namespace_ref, id_ref, unit, description, decimals) =
+        (&state.pool, &namespace, &id, &meta.unit, &meta.description, meta.decimals);
+    state
+        .write(|| async move {
+            sqlx::query!(
+                "INSERT INTO metric_meta (namespace, id, scale, chart_type, unit, description, decimals)
+                 VALUES (?, ?, ?, ?, ?, ?, ?)
+                 ON CONFLICT (namespace, id) DO UPDATE SET scale = excluded.scale, chart_type = excluded.chart_type,
+                     unit = excluded.unit, description = excluded.description, decimals = excluded.decimals",
+                namespace_ref,
+                id_ref,
+                scale,
+                chart_type,
+                unit,
+                description,
+                decimals
+            )
+            .execute(pool)
+            .await
+        })
+        .await
+        .map_err(|err| (errors::internal(err), Json(serde_json::json!({ "error": "database error" }))))?;
+    state.invalidate_series(&namespace, &id);
+
+    Ok(Json(meta))
+}
+
+pub async fn delete_meta(
+    SeriesPath(namespace, id): SeriesPath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&state.config(), &headers)?;
+
+    let (pool, namespace_ref, id_ref) = (&state.pool, &namespace, &id);
+    state
+        .write(|| async move {
+            sqlx::query!("DELETE FROM metric_meta WHERE namespace = ? AND id = ?", namespace_ref, id_ref)
+                .execute(pool)
+                .await
+        })
+        .await
+        .map_err(errors::internal)?;
+    state.invalidate_series(&namespace, &id);
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,383 @@
+//! What an alert notification looks like at each kind of destination. A
+//! plain webhook gets the alert as JSON fields to act on; Slack and Discord
+//! incoming webhooks, and email, get a message people can read, with the
+//! values shown the way the metric's charts show them and, when `PUBLIC_URL`
+//! is set, a link to the chart. PagerDuty and Opsgenie get an incident that
+//! is opened when the rule fires and closed when it resolves, keyed by the
+//! rule so repeats land on the same incident.
+
+use askama::Template;
+use chrono::DateTime;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::alerts::Condition;
+
+const SEGMENT_CHARACTERS: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+/// Embed colours, as Discord takes them
+const FIRING_COLOUR: u32 = 0xd0_3b_3b;
+const RESOLVED_COLOUR: u32 = 0x2e_a0_43;
+/// PagerDuty takes dedup keys up to 255 characters, and Opsgenie aliases up to 512
+const MAX_INCIDENT_KEY_LENGTH: usize = 255;
+const MAX_PAGERDUTY_SUMMARY_LENGTH: usize = 1024;
+const MAX_OPSGENIE_MESSAGE_LENGTH: usize = 130;
+
+/// Which format a rule's notifications are sent in.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    #[default]
+    Webhook,
+    Slack,
+    Discord,
+    /// Sent through the `SMTP_*` server rather than to a webhook
+    Email,
+    /// Incidents through the PagerDuty Events API, with a routing key
+    #[serde(rename = "pagerduty")]
+    PagerDuty,
+    /// Alerts through the Opsgenie Alert API, with an API key
+    Opsgenie,
+}
+
+impl Channel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Channel::Webhook => "webhook",
+            Channel::Slack => "slack",
+            Channel::Discord => "discord",
+            Channel::Email => "email",
+            Channel::PagerDuty => "pagerduty",
+            Channel::Opsgenie => "opsgenie",
+        }
+    }
+
+    /// Whether the channel opens incidents, which only alert rules can.
+    pub fn is_incident(self) -> bool {
+        matches!(self, Channel::PagerDuty | Channel::Opsgenie)
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "webhook" => Some(Channel::Webhook),
+            "slack" => Some(Channel::Slack),
+            "discord" => Some(Channel::Discord),
+            "email" => Some(Channel::Email),
+            "pagerduty" => Some(Channel::PagerDuty),
+            "opsgenie" => Some(Channel::Opsgenie),
+            _ => None,
+        }
+    }
+}
+
+/// A rule that started or stopped firing, with everything a message needs.
+pub struct Alert<'a> {
+    pub namespace: &'a str,
+    pub metric: &'a str,
+    pub rule_id: i64,
+    pub condition: Condition,
+    pub threshold: f64,
+    pub firing: bool,
+    pub value: f64,
+    pub timestamp: i64,
+    /// The value and threshold formatted with the metric's unit and decimals,
+    /// or as durations for a heartbeat, whose value is how long it was quiet
+    pub shown_value: String,
+    pub shown_threshold: String,
+    pub chart_url: Option<String>,
+}
+
+impl Alert<'_> {
+    fn status(&self) -> &'static str {
+        if self.firing { "firing" } else { "resolved" }
+    }
+
+    /// One line saying what happened, such as
+    /// `prod/disk_used is above 90 % at 93.5 %`.
+    pub fn summary(&self) -> String {
+        if self.condition == Condition::Silent {
+            return if self.firing {
+                format!(
+                    "{}/{} has had no points for {}, more than its {} interval",
+                    self.namespace, self.metric, self.shown_value, self.shown_threshold
+                )
+            } else {
+                format!("{}/{} got a point again after {}", self.namespace, self.metric, self.shown_value)
+            };
+        }
+        if self.condition == Condition::Anomaly {
+            return if self.firing {
+                format!(
+                    "{}/{} looks anomalous at {}, more than {} off its baseline",
+                    self.namespace, self.metric, self.shown_value, self.shown_threshold
+                )
+            } else {
+                format!(
+                    "{}/{} is back within {} of its baseline at {}",
+                    self.namespace, self.metric, self.shown_threshold, self.shown_value
+                )
+            };
+        }
+        format!(
+            "{}/{} is {}{} {} at {}",
+            self.namespace,
+            self.metric,
+            if self.firing { "" } else { "no longer " },
+            self.condition.as_str(),
+            self.shown_threshold,
+            self.shown_value
+        )
+    }
+}
+
+/// A number of seconds as the two largest units, such as `1h 5m`.
+pub fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.max(0.0) as u64;
+    let units = [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)];
+    let parts: Vec<String> = units
+        .iter()
+        .scan(seconds, |left, &(unit, size)| {
+            let count = *left / size;
+            *left %= size;
+            Some((count, unit))
+        })
+        .skip_while(|&(count, _)| count == 0)
+        .take(2)
+        .filter(|&(count, _)| count > 0)
+        .map(|(count, unit)| format!("{}{}", count, unit))
+        .collect();
+    if parts.is_empty() { "0s".to_string() } else { parts.join(" ") }
+}
+
+/// The address of a namespace's page under `public_url`.
+pub fn namespace_url(public_url: &str, namespace: &str) -> String {
+    format!("{}/{}", public_url, utf8_percent_encode(namespace, SEGMENT_CHARACTERS))
+}
+
+/// The address of a metric's chart page under `public_url`.
+pub fn chart_url(public_url: &str, namespace: &str, metric: &str) -> String {
+    format!(
+        "{}/{}",
+        namespace_url(public_url, namespace),
+        utf8_percent_encode(metric, SEGMENT_CHARACTERS)
+    )
+}
+
+/// The request body to send `alert` to a webhook of kind `channel`, or
+/// `None` for the channels that aren't plain webhooks.
+pub fn render(channel: Channel, alert: &Alert) -> Option<Value> {
+    match channel {
+        Channel::Webhook => Some(webhook(alert)),
+        Channel::Slack => Some(slack(alert)),
+        Channel::Discord => Some(discord(alert)),
+        Channel::Email | Channel::PagerDuty | Channel::Opsgenie => None,
+    }
+}
+
+pub fn utc_time(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .map(|time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_default()
+}
+
+fn webhook(alert: &Alert) -> Value {
+    json!({
+        "status": alert.status(),
+        "text": alert.summary(),
+        "namespace": alert.namespace,
+        "metric": alert.metric,
+        "value": alert.value,
+        "timestamp": alert.timestamp,
+        "url": alert.chart_url,
+        "rule": {
+            "id": alert.rule_id,
+            "condition": alert.condition,
+            "threshold": alert.threshold,
+        },
+    })
+}
+
+/// Slack's mrkdwn treats only these three specially in plain text.
+pub fn slack_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn slack(alert: &Alert) -> Value {
+    let heading = if alert.firing { ":rotating_light: *Firing*" } else { ":white_check_mark: *Resolved*" };
+    let mut message = format!("{} {}", heading, slack_escape(&alert.summary()));
+    if let Some(url) = &alert.chart_url {
+        message.push_str(&format!("\n<{}|View chart>", url));
+    }
+    // Slack shows the time in each reader's own timezone, falling back to UTC
+    let time = utc_time(alert.timestamp);
+    json!({
+        // Shown in notifications, where blocks aren't
+        "text": alert.summary(),
+        "blocks": [
+            {
+                "type": "section",
+                "text": { "type": "mrkdwn", "text": message },
+            },
+            {
+                "type": "context",
+                "elements": [{
+                    "type": "mrkdwn",
+                    "text": format!("Rule {} · <!date^{}^{{date_short_pretty}} {{time_secs}}|{}>",
+                        alert.rule_id, alert.timestamp, time),
+                }],
+            },
+        ],
+    })
+}
+
+/// Discord renders markdown in embeds, and ids are full of underscores.
+pub fn discord_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '~' | '`' | '|' | '>' | '#' | '[' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn discord(alert: &Alert) -> Value {
+    let title = format!(
+        "{}: {}/{}",
+        if alert.firing { "Firing" } else { "Resolved" },
+        alert.namespace,
+        alert.metric
+    );
+    let mut embed = json!({
+        "title": discord_escape(&title),
+        "description": discord_escape(&alert.summary()),
+        "color": if alert.firing { FIRING_COLOUR } else { RESOLVED_COLOUR },
+        "footer": { "text": format!("somnial alert rule {}", alert.rule_id) },
+    });
+    if let Some(time) = DateTime::from_timestamp(alert.timestamp, 0) {
+        embed["timestamp"] = json!(time.to_rfc3339());
+    }
+    if let Some(url) = &alert.chart_url {
+        embed["url"] = json!(url);
+    }
+    json!({ "embeds": [embed] })
+}
+
+/// What ties the notifications of one rule together as one incident.
+pub fn incident_key(alert: &Alert) -> String {
+    format!("somnial/{}/rule-{}", alert.namespace, alert.rule_id)
+        .chars()
+        .take(MAX_INCIDENT_KEY_LENGTH)
+        .collect()
+}
+
+/// Fields incident tools show alongside the summary.
+fn details(alert: &Alert) -> Value {
+    json!({
+        "namespace": alert.namespace,
+        "metric": alert.metric,
+        "value": alert.shown_value,
+        "condition": alert.condition.as_str(),
+        "threshold": alert.shown_threshold,
+        "rule": alert.rule_id.to_string(),
+    })
+}
+
+/// A PagerDuty Events API v2 event, triggering the rule's incident or
+/// resolving it.
+pub fn pagerduty(alert: &Alert, routing_key: &str) -> Value {
+    let dedup_key = incident_key(alert);
+    if !alert.firing {
+        return json!({
+            "routing_key": routing_key,
+            "event_action": "resolve",
+            "dedup_key": dedup_key,
+        });
+    }
+    let mut event = json!({
+        "routing_key": routing_key,
+        "event_action": "trigger",
+        "dedup_key": dedup_key,
+        "payload": {
+            "summary": alert.summary().chars().take(MAX_PAGERDUTY_SUMMARY_LENGTH).collect::<String>(),
+            "source": format!("somnial/{}", alert.namespace),
+            "severity": "error",
+            "component": alert.metric,
+            "group": alert.namespace,
+            "class": alert.condition.as_str(),
+            "custom_details": details(alert),
+        },
+        "client": "somnial",
+    });
+    if let Some(time) = DateTime::from_timestamp(alert.timestamp, 0) {
+        event["payload"]["timestamp"] = json!(time.to_rfc3339());
+    }
+    if let Some(url) = &alert.chart_url {
+        event["links"] = json!([{ "href": url, "text": "View chart" }]);
+        event["client_url"] = json!(url);
+    }
+    event
+}
+
+/// The path under the Opsgenie API and the body that opens the rule's alert
+/// there or closes it.
+pub fn opsgenie(alert: &Alert) -> (String, Value) {
+    let alias = incident_key(alert);
+    if !alert.firing {
+        let path = format!(
+            "/v2/alerts/{}/close?identifierType=alias",
+            utf8_percent_encode(&alias, SEGMENT_CHARACTERS)
+        );
+        return (path, json!({ "source": "somnial", "note": alert.summary() }));
+    }
+    let mut description = alert.summary();
+    if let Some(url) = &alert.chart_url {
+        description.push_str(&format!("\n\nChart: {}", url));
+    }
+    let body = json!({
+        "message": alert.summary().chars().take(MAX_OPSGENIE_MESSAGE_LENGTH).collect::<String>(),
+        "alias": alias,
+        "description": description,
+        "details": details(alert),
+        "entity": format!("{}/{}", alert.namespace, alert.metric),
+        "source": "somnial",
+        "tags": ["somnial", alert.namespace],
+    });
+    ("/v2/alerts".to_string(), body)
+}
+
+#[derive(Template)]
+#[template(path = "alert_email.txt")]
+struct EmailTemplate<'a> {
+    alert: &'a Alert<'a>,
+    summary: String,
+    time: String,
+}
+
+/// The subject and plain-text body of an alert email.
+pub fn email(alert: &Alert) -> Result<(String, String), askama::Error> {
+    let subject = if alert.firing && alert.condition == Condition::Silent {
+        format!(
+            "[somnial] Firing: {}/{} silent for {}",
+            alert.namespace, alert.metric, alert.shown_value
+        )
+    } else if alert.firing {
+        format!(
+            "[somnial] Firing: {}/{} {} {}",
+            alert.namespace,
+            alert.metric,
+            alert.condition.as_str(),
+            alert.shown_threshold
+        )
+    } else {
+        format!("[somnial] Resolved: {}/{}", alert.namespace, alert.metric)
+    };
+    let body = EmailTemplate {
+        alert,
+        summary: alert.summary(),
+        time: utc_time(alert.timestamp),
+    }
+    .render()?;
+    Ok((subject, body))
+}
@@ -0,0 +1,98 @@
+//! `/{namespace}/overlay?ids=a,b,c`: several series from one namespace on a
+//! shared chart, for comparing related metrics.
+
+use askama::Template;
+use axum::{
+    extract::{Extension, Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    domains::Domain,
+    errors,
+    ids::NamespacePath,
+    limits::{self, ReadError},
+    theme::ViewerTheme,
+    traces,
+    AppState, BasePath, MetricPoint,
+};
+
+/// Most series one overlay will draw.
+const MAX_SERIES: usize = 8;
+
+#[derive(Deserialize)]
+pub struct OverlayQuery {
+    /// Comma-separated metric ids
+    ids: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Series {
+    id: String,
+    points: Vec<MetricPoint>,
+}
+
+#[derive(Template)]
+#[template(path = "overlay.html")]
+struct OverlayTemplate {
+    base_path: BasePath,
+    namespace: String,
+    ids: Vec<String>,
+    series_json: String,
+    /// Site title when served from a custom domain
+    brand: Option<String>,
+    theme: &'static str,
+}
+
+/// Every point of each of `ids`, in the order given; the limit on points
+/// read at once counts all of them together.
+async fn load_series(state: &AppState, namespace: &str, ids: &[String]) -> Result<Vec<Series>, ReadError> {
+    let max_points = state.config().limits.max_query_points;
+    let mut remaining = limits::fetch_limit(max_points);
+    let mut series = Vec::with_capacity(ids.len());
+    for id in ids {
+        let points = state.store.range(namespace, id, i64::MIN, i64::MAX, remaining).await?;
+        remaining -= points.len() as i64;
+        series.push(Series { id: id.clone(), points });
+        if remaining == 0 {
+            return Err(ReadError::TooManyPoints(max_points));
+        }
+    }
+    Ok(series)
+}
+
+pub async fn get_overlay(
+    NamespacePath(namespace): NamespacePath,
+    Query(query): Query<OverlayQuery>,
+    State(state): State<AppState>,
+    domain: Option<Extension<Domain>>,
+    ViewerTheme(theme): ViewerTheme,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let mut ids: Vec<String> = Vec::new();
+    for id in query.ids.as_deref().unwrap_or("").split(',').map(str::trim) {
+        let id = state.config().id_policy.normalize(id);
+        if !id.is_empty() && !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+    if ids.is_empty() || ids.len() > MAX_SERIES {
+        return Err((StatusCode::BAD_REQUEST, "give between 1 and 8 comma-separated ids in ?ids=".to_string()));
+    }
+
+    let series = load_series(&state, &namespace, &ids).await.map_err(ReadError::text)?;
+
+    let template = OverlayTemplate {
+        base_path: state.base_path.clone(),
+        namespace,
+        ids,
+        // Ids are user-supplied, so keep them from closing the script element
+        series_json: serde_json::to_string(&series).unwrap_or_default().replace('<', "\\u003c"),
+        brand: domain.map(|d| d.title.clone().unwrap_or_else(|| d.namespace.clone())),
+        theme: theme.as_str(),
+    };
+    traces::render(&template)
+        .map(Html)
+        .map_err(|err| (errors::internal(err), "template error".to_string()))
+}
@@ -0,0 +1,105 @@
+//! Who may change a namespace's settings besides the operator. A namespace
+//! has an owner only once the operator issues it an owner token, with
+//! `POST /admin/namespaces/{namespace}/owner-token` or
+//! `somnial token create --namespace`; nobody can claim one by writing to
+//! it first. The owner token then works wherever the admin token would for
+//! that namespace's README. Everything that sends requests elsewhere,
+//! redirects links or creates and deletes namespaces takes the admin token
+//! itself.
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use chrono::Utc;
+use serde_json::{json, Value};
+
+use crate::{auth, ids::NamespacePath, AppState};
+
+const TOKEN_LENGTH: usize = 32;
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(json!({ "error": message })))
+}
+
+fn database_error<E: std::fmt::Display>(err: E) -> ApiError {
+    crate::errors::report(&err);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+/// Lets through the admin token, or the namespace's owner token once the
+/// operator has issued one.
+pub async fn require_owner(state: &AppState, headers: &HeaderMap, namespace: &str) -> Result<(), ApiError> {
+    let owner_token = sqlx::query_scalar!("SELECT token FROM namespace_owners WHERE namespace = ?", namespace)
+        .fetch_optional(&state.pool)
+        .await
+        .map_err(database_error)?;
+    match &owner_token {
+        Some(owner_token) => auth::require_owner_or_admin(&state.config(), headers, owner_token),
+        None => auth::require_admin(&state.config(), headers),
+    }
+    .map_err(|status| error(status, "unauthorized"))
+}
+
+/// Gives the namespace a new owner token, replacing any it had.
+pub async fn issue_token(state: &AppState, namespace: &str) -> Result<String, sqlx::Error> {
+    let token = auth::random_string(TOKEN_LENGTH);
+    let now = Utc::now().timestamp();
+    let (pool, token_ref) = (&state.pool, &token);
+    state
+        .write(|| async move {
+            sqlx::query!(
+                "INSERT INTO namespace_owners (namespace, token, created_at) VALUES (?, ?, ?)
+                 ON CONFLICT (namespace) DO UPDATE SET token = excluded.token, created_at = excluded.created_at",
+                namespace,
+                token_ref,
+                now
+            )
+            .execute(pool)
+            .await
+        })
+        .await?;
+    Ok(token)
+}
+
+/// Issues the namespace an owner token, or replaces its old one.
+pub async fn post_owner_token(
+    NamespacePath(namespace): NamespacePath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+    let token = issue_token(&state, &namespace).await.map_err(database_error)?;
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({ "namespace": namespace, "owner_token": token })),
+    ))
+}
+
+/// Takes the namespace's owner token away, leaving its settings to the
+/// operator.
+pub async fn delete_owner_token(
+    NamespacePath(namespace): NamespacePath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+    let (pool, namespace_ref) = (&state.pool, &namespace);
+    let removed = state
+        .write(|| async move {
+            sqlx::query!("DELETE FROM namespace_owners WHERE namespace = ?", namespace_ref)
+                .execute(pool)
+                .await
+        })
+        .await
+        .map_err(database_error)?
+        .rows_affected();
+    if removed == 0 {
+        return Err(error(StatusCode::NOT_FOUND, "this namespace has no owner token"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,152 @@
+//! Correcting individual points under
+//! `/api/v1/namespaces/{namespace}/metrics/{id}/points`, for the one absurd
+//! outlier that flattens an otherwise good chart.
+//!
+//! `GET` lists points with their `point_id`; `DELETE` and `PUT {"value": ..}`
+//! take either `?point_id=` for one point or `?timestamp=` for every point
+//! recorded at that second. All of it needs the admin token.
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    auth,
+    ids::SeriesPath,
+    precision, rollup,
+    store::{PointSelector, StoredPoint},
+    AppState,
+};
+
+const DEFAULT_LIMIT: i64 = 1000;
+const MAX_LIMIT: i64 = 10_000;
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(json!({ "error": message })))
+}
+
+fn database_error<E: std::fmt::Display>(err: E) -> ApiError {
+    crate::errors::report(&err);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+#[derive(Deserialize)]
+pub struct PointsQuery {
+    since: Option<i64>,
+    until: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct PointsResponse {
+    points: Vec<StoredPoint>,
+}
+
+/// `?point_id=` or `?timestamp=`; exactly one of them.
+#[derive(Deserialize)]
+pub struct SelectorQuery {
+    point_id: Option<i64>,
+    timestamp: Option<i64>,
+}
+
+impl SelectorQuery {
+    fn selector(&self) -> Result<PointSelector, ApiError> {
+        match (self.point_id, self.timestamp) {
+            (Some(point_id), None) => Ok(PointSelector::Id(point_id)),
+            (None, Some(timestamp)) => Ok(PointSelector::Timestamp(timestamp)),
+            _ => Err(error(StatusCode::BAD_REQUEST, "give exactly one of point_id and timestamp")),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CorrectionRequest {
+    value: f64,
+}
+
+pub async fn get_points(
+    SeriesPath(namespace, id): SeriesPath,
+    Query(query): Query<PointsQuery>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(error(StatusCode::BAD_REQUEST, "limit must be between 1 and 10000"));
+    }
+    let window = (query.since.unwrap_or(i64::MIN), query.until.unwrap_or(i64::MAX));
+    let points = state
+        .store
+        .points(&namespace, &id, window, limit)
+        .await
+        .map_err(database_error)?;
+    Ok(Json(PointsResponse { points }))
+}
+
+pub async fn delete_points(
+    SeriesPath(namespace, id): SeriesPath,
+    Query(query): Query<SelectorQuery>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+    let selector = query.selector()?;
+
+    let (namespace, id) = (&namespace, &id);
+    let timestamps = state
+        .write_store(|| state.store.delete_points(namespace, id, selector))
+        .await
+        .map_err(database_error)?;
+    settle(&state, namespace, id, &timestamps).await?;
+
+    Ok(Json(json!({ "deleted": timestamps.len() })))
+}
+
+/// Overwrites the selected points, snapping the value to the metric's
+/// storage precision as ingest would.
+pub async fn put_points(
+    SeriesPath(namespace, id): SeriesPath,
+    Query(query): Query<SelectorQuery>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<CorrectionRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+    let selector = query.selector()?;
+    if !request.value.is_finite() {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "value must be a finite number"));
+    }
+    let value = precision::load(&state.pool, &namespace, &id)
+        .await
+        .map_err(database_error)?
+        .apply(request.value);
+
+    let (namespace, id) = (&namespace, &id);
+    let timestamps = state
+        .write_store(|| state.store.update_points(namespace, id, selector, value))
+        .await
+        .map_err(database_error)?;
+    settle(&state, namespace, id, &timestamps).await?;
+
+    Ok(Json(json!({ "updated": timestamps.len(), "value": value })))
+}
+
+/// After a correction touching `timestamps`: 404 if it matched nothing,
+/// otherwise brings the caches and any affected rollups up to date.
+async fn settle(state: &AppState, namespace: &str, id: &str, timestamps: &[i64]) -> Result<(), ApiError> {
+    if timestamps.is_empty() {
+        return Err(error(StatusCode::NOT_FOUND, "no such point"));
+    }
+    state.invalidate_series(namespace, id);
+    rollup::rebuild_buckets(state, namespace, id, timestamps)
+        .await
+        .map_err(database_error)
+}
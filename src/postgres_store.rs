@@ -0,0 +1,773 @@
+//! A [`MetricStore`] on PostgreSQL, which the server uses when
+//! `DATABASE_URL` is a `postgres:` one. Points and the trash live there,
+//! any number of servers can write to it at once, and the rest (settings,
+//! dashboards, rollups) stays in the SQLite database at
+//! `SETTINGS_DATABASE_URL`, as it does with any other store.
+//!
+//! Steps that read before they write, such as a rename checking that the
+//! new id is free, hold an advisory lock on the namespace so two servers
+//! can't interleave them. Retention deletes in batches here; there are no
+//! month tables to drop.
+
+use std::future::Future;
+
+use futures_util::{stream, StreamExt, TryStreamExt};
+use sqlx::{
+    postgres::{PgConnection, PgPool, PgPoolOptions},
+    Postgres, Transaction,
+};
+use tracing::Instrument;
+
+use crate::{
+    store::{
+        Aggregate, Bucket, BucketScope, LatestWrite, ListFrom, MetricListing, MetricStore, NamespaceSummary,
+        PointSelector, PointStream, StoreError, StoreFuture, StoredPoint, TrashedSeries,
+    },
+    traces, MetricPoint,
+};
+
+/// Points one step of a [`MetricStore::scan`] reads
+const SCAN_PAGE: i64 = 1000;
+
+/// `(value, timestamp, sha, branch)`
+type PointRow = (f64, i64, Option<String>, Option<String>);
+/// `(point_id, timestamp, value, sha, branch)`
+type StoredRow = (i64, i64, f64, Option<String>, Option<String>);
+/// `(point_id, id, value, timestamp, sha, branch)`
+type ScanRow = (i64, String, f64, i64, Option<String>, Option<String>);
+/// `(count, min, max, mean, mean square, last timestamp, latest)`, all but
+/// the count `NULL` over no points
+type AggregateRow = (i64, Option<f64>, Option<f64>, Option<f64>, Option<f64>, Option<i64>, Option<f64>);
+
+fn to_point((value, timestamp, sha, branch): PointRow) -> MetricPoint {
+    MetricPoint {
+        timestamp,
+        value,
+        sha,
+        branch,
+    }
+}
+
+/// A limit as Postgres takes it: `NULL` for none, where SQLite takes a
+/// negative one.
+fn limit(limit: i64) -> Option<i64> {
+    (limit >= 0).then_some(limit)
+}
+
+/// Serialization failures and deadlocks only need the statement run again,
+/// like a busy SQLite database.
+fn store_error(err: sqlx::Error) -> StoreError {
+    let retry = matches!(
+        &err,
+        sqlx::Error::Database(db_err) if matches!(db_err.code().as_deref(), Some("40001" | "40P01"))
+    );
+    StoreError::new(err, retry)
+}
+
+/// Boxes a store method's query in a trace span named after the method.
+fn traced<'a, T>(
+    operation: &'static str,
+    query: impl Future<Output = Result<T, sqlx::Error>> + Send + 'a,
+) -> StoreFuture<'a, T> {
+    Box::pin(async move {
+        let span = traces::query("postgresql", operation);
+        let result = query.instrument(span.clone()).await;
+        if result.is_err() {
+            traces::fail(&span);
+        }
+        result.map_err(store_error)
+    })
+}
+
+/// Holds the namespace's advisory lock until the transaction ends.
+async fn lock_namespace(conn: &mut PgConnection, namespace: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+        .bind(namespace)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Writes `(id, point)` pairs in their order, so the ids they get do too.
+async fn insert_points(
+    tx: &mut Transaction<'_, Postgres>,
+    namespace: &str,
+    points: &[(&str, MetricPoint)],
+    only_new: bool,
+) -> Result<u64, sqlx::Error> {
+    // Existing points are read as of before the statement, so repeats
+    // within `points` are all kept
+    let only_new = if only_new {
+        "WHERE NOT EXISTS (
+             SELECT 1 FROM points
+             WHERE namespace = $1 AND id = p.id AND timestamp = p.timestamp AND value = p.value
+         )"
+    } else {
+        ""
+    };
+    let sql = format!(
+        "INSERT INTO points (namespace, id, value, timestamp, sha, branch)
+         SELECT $1, p.id, p.value, p.timestamp, p.sha, p.branch
+         FROM UNNEST($2::text[], $3::float8[], $4::int8[], $5::text[], $6::text[])
+             WITH ORDINALITY AS p (id, value, timestamp, sha, branch, position)
+         {only_new}
+         ORDER BY p.position"
+    );
+    let result = sqlx::query(&sql)
+        .bind(namespace)
+        .bind(points.iter().map(|(id, _)| *id).collect::<Vec<_>>())
+        .bind(points.iter().map(|(_, point)| point.value).collect::<Vec<_>>())
+        .bind(points.iter().map(|(_, point)| point.timestamp).collect::<Vec<_>>())
+        .bind(points.iter().map(|(_, point)| point.sha.as_deref()).collect::<Vec<_>>())
+        .bind(points.iter().map(|(_, point)| point.branch.as_deref()).collect::<Vec<_>>())
+        .execute(&mut **tx)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// [`MetricStore`] over a PostgreSQL database.
+pub struct PostgresStore {
+    pool: PgPool,
+}
+
+impl PostgresStore {
+    /// Connects to `url` and brings its tables up to date.
+    pub async fn connect(url: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let pool = PgPoolOptions::new().connect(url).await?;
+        sqlx::migrate!("./postgres_migrations").run(&pool).await?;
+        Ok(PostgresStore { pool })
+    }
+}
+
+impl MetricStore for PostgresStore {
+    fn insert<'a>(&'a self, namespace: &'a str, points: &'a [(&'a str, MetricPoint)]) -> StoreFuture<'a, u64> {
+        traced("insert", async move {
+            let mut tx = self.pool.begin().await?;
+            let inserted = insert_points(&mut tx, namespace, points, false).await?;
+            tx.commit().await?;
+            Ok(inserted)
+        })
+    }
+
+    fn insert_new<'a>(&'a self, namespace: &'a str, points: &'a [(&'a str, MetricPoint)]) -> StoreFuture<'a, u64> {
+        traced("insert_new", async move {
+            let mut tx = self.pool.begin().await?;
+            lock_namespace(&mut tx, namespace).await?;
+            let inserted = insert_points(&mut tx, namespace, points, true).await?;
+            tx.commit().await?;
+            Ok(inserted)
+        })
+    }
+
+    fn delete<'a>(&'a self, namespace: &'a str, id: &'a str) -> StoreFuture<'a, u64> {
+        traced("delete", async move {
+            let result = sqlx::query("DELETE FROM points WHERE namespace = $1 AND id = $2")
+                .bind(namespace)
+                .bind(id)
+                .execute(&self.pool)
+                .await?;
+            Ok(result.rows_affected())
+        })
+    }
+
+    fn trash<'a>(&'a self, namespace: &'a str, id: &'a str, deleted_at: i64) -> StoreFuture<'a, u64> {
+        traced("trash", async move {
+            let mut tx = self.pool.begin().await?;
+            let moved = sqlx::query(
+                "WITH moved AS (DELETE FROM points WHERE namespace = $1 AND id = $2 RETURNING *)
+                 INSERT INTO deleted_points (namespace, id, value, timestamp, sha, branch)
+                 SELECT namespace, id, value, timestamp, sha, branch FROM moved ORDER BY point_id",
+            )
+            .bind(namespace)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+            if moved > 0 {
+                sqlx::query(
+                    "INSERT INTO deleted_metrics (namespace, id, deleted_at) VALUES ($1, $2, $3)
+                     ON CONFLICT (namespace, id) DO UPDATE SET deleted_at = excluded.deleted_at",
+                )
+                .bind(namespace)
+                .bind(id)
+                .bind(deleted_at)
+                .execute(&mut *tx)
+                .await?;
+            }
+            tx.commit().await?;
+            Ok(moved)
+        })
+    }
+
+    fn untrash<'a>(&'a self, namespace: &'a str, id: &'a str) -> StoreFuture<'a, Option<Vec<i64>>> {
+        traced("untrash", async move {
+            let mut tx = self.pool.begin().await?;
+            let found = sqlx::query("DELETE FROM deleted_metrics WHERE namespace = $1 AND id = $2")
+                .bind(namespace)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+            if found == 0 {
+                return Ok(None);
+            }
+            let timestamps = sqlx::query_scalar(
+                "WITH restored AS (DELETE FROM deleted_points WHERE namespace = $1 AND id = $2 RETURNING *)
+                 INSERT INTO points (namespace, id, value, timestamp, sha, branch)
+                 SELECT namespace, id, value, timestamp, sha, branch FROM restored ORDER BY point_id
+                 RETURNING timestamp",
+            )
+            .bind(namespace)
+            .bind(id)
+            .fetch_all(&mut *tx)
+            .await?;
+            tx.commit().await?;
+            Ok(Some(timestamps))
+        })
+    }
+
+    fn trashed(&self) -> StoreFuture<'_, Vec<TrashedSeries>> {
+        traced("trashed", async move {
+            let rows: Vec<(String, String, i64, i64)> = sqlx::query_as(
+                "SELECT d.namespace, d.id, d.deleted_at,
+                     (SELECT COUNT(*) FROM deleted_points p WHERE p.namespace = d.namespace AND p.id = d.id)
+                 FROM deleted_metrics d
+                 ORDER BY d.deleted_at ASC",
+            )
+            .fetch_all(&self.pool)
+            .await?;
+            Ok(rows
+                .into_iter()
+                .map(|(namespace, id, deleted_at, points)| TrashedSeries {
+                    namespace,
+                    id,
+                    points,
+                    deleted_at,
+                })
+                .collect())
+        })
+    }
+
+    fn empty_trash(&self, cutoff: i64) -> StoreFuture<'_, Vec<(String, String)>> {
+        traced("empty_trash", async move {
+            let mut tx = self.pool.begin().await?;
+            let expired: Vec<(String, String)> =
+                sqlx::query_as("DELETE FROM deleted_metrics WHERE deleted_at < $1 RETURNING namespace, id")
+                    .bind(cutoff)
+                    .fetch_all(&mut *tx)
+                    .await?;
+            for (namespace, id) in &expired {
+                sqlx::query("DELETE FROM deleted_points WHERE namespace = $1 AND id = $2")
+                    .bind(namespace)
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            tx.commit().await?;
+            Ok(expired)
+        })
+    }
+
+    fn range<'a>(
+        &'a self,
+        namespace: &'a str,
+        id: &'a str,
+        since: i64,
+        until: i64,
+        count: i64,
+    ) -> StoreFuture<'a, Vec<MetricPoint>> {
+        traced("range", async move {
+            let rows: Vec<PointRow> = sqlx::query_as(
+                "SELECT value, timestamp, sha, branch FROM points
+                 WHERE namespace = $1 AND id = $2 AND timestamp BETWEEN $3 AND $4
+                 ORDER BY timestamp ASC, point_id ASC LIMIT $5",
+            )
+            .bind(namespace)
+            .bind(id)
+            .bind(since)
+            .bind(until)
+            .bind(limit(count))
+            .fetch_all(&self.pool)
+            .await?;
+            Ok(rows.into_iter().map(to_point).collect())
+        })
+    }
+
+    fn latest<'a>(&'a self, namespace: &'a str, id: &'a str) -> StoreFuture<'a, Option<MetricPoint>> {
+        traced("latest", async move {
+            let row: Option<PointRow> = sqlx::query_as(
+                "SELECT value, timestamp, sha, branch FROM points
+                 WHERE namespace = $1 AND id = $2 ORDER BY timestamp DESC, point_id DESC LIMIT 1",
+            )
+            .bind(namespace)
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+            Ok(row.map(to_point))
+        })
+    }
+
+    fn points<'a>(
+        &'a self,
+        namespace: &'a str,
+        id: &'a str,
+        (since, until): (i64, i64),
+        count: i64,
+    ) -> StoreFuture<'a, Vec<StoredPoint>> {
+        traced("points", async move {
+            let rows: Vec<StoredRow> = sqlx::query_as(
+                "SELECT point_id, timestamp, value, sha, branch FROM points
+                 WHERE namespace = $1 AND id = $2 AND timestamp BETWEEN $3 AND $4
+                 ORDER BY timestamp ASC, point_id ASC LIMIT $5",
+            )
+            .bind(namespace)
+            .bind(id)
+            .bind(since)
+            .bind(until)
+            .bind(limit(count))
+            .fetch_all(&self.pool)
+            .await?;
+            Ok(rows
+                .into_iter()
+                .map(|(point_id, timestamp, value, sha, branch)| StoredPoint {
+                    point_id,
+                    timestamp,
+                    value,
+                    sha,
+                    branch,
+                })
+                .collect())
+        })
+    }
+
+    fn delete_points<'a>(
+        &'a self,
+        namespace: &'a str,
+        id: &'a str,
+        selector: PointSelector,
+    ) -> StoreFuture<'a, Vec<i64>> {
+        traced("delete_points", async move {
+            let (point_id, timestamp) = selector.columns();
+            sqlx::query_scalar(
+                "DELETE FROM points WHERE namespace = $1 AND id = $2 AND (point_id = $3 OR timestamp = $4)
+                 RETURNING timestamp",
+            )
+            .bind(namespace)
+            .bind(id)
+            .bind(point_id)
+            .bind(timestamp)
+            .fetch_all(&self.pool)
+            .await
+        })
+    }
+
+    fn update_points<'a>(
+        &'a self,
+        namespace: &'a str,
+        id: &'a str,
+        selector: PointSelector,
+        value: f64,
+    ) -> StoreFuture<'a, Vec<i64>> {
+        traced("update_points", async move {
+            let (point_id, timestamp) = selector.columns();
+            sqlx::query_scalar(
+                "UPDATE points SET value = $1 WHERE namespace = $2 AND id = $3 AND (point_id = $4 OR timestamp = $5)
+                 RETURNING timestamp",
+            )
+            .bind(value)
+            .bind(namespace)
+            .bind(id)
+            .bind(point_id)
+            .bind(timestamp)
+            .fetch_all(&self.pool)
+            .await
+        })
+    }
+
+    fn trim<'a>(&'a self, namespace: &'a str, id: &'a str, keep: i64) -> StoreFuture<'a, Vec<i64>> {
+        traced("trim", async move {
+            let mut tx = self.pool.begin().await?;
+            lock_namespace(&mut tx, namespace).await?;
+            let trimmed = sqlx::query_scalar(
+                "DELETE FROM points WHERE namespace = $1 AND id = $2 AND point_id NOT IN (
+                     SELECT point_id FROM points WHERE namespace = $1 AND id = $2
+                     ORDER BY timestamp DESC, point_id DESC LIMIT $3
+                 )
+                 RETURNING timestamp",
+            )
+            .bind(namespace)
+            .bind(id)
+            .bind(limit(keep))
+            .fetch_all(&mut *tx)
+            .await?;
+            tx.commit().await?;
+            Ok(trimmed)
+        })
+    }
+
+    fn merge<'a>(&'a self, namespace: &'a str, from: &'a str, into: &'a str) -> StoreFuture<'a, (Vec<i64>, u64)> {
+        traced("merge", async move {
+            let mut tx = self.pool.begin().await?;
+            lock_namespace(&mut tx, namespace).await?;
+            let moved = sqlx::query_scalar(
+                "UPDATE points SET id = $3
+                 WHERE namespace = $1 AND id = $2
+                   AND timestamp NOT IN (SELECT timestamp FROM points WHERE namespace = $1 AND id = $3)
+                 RETURNING timestamp",
+            )
+            .bind(namespace)
+            .bind(from)
+            .bind(into)
+            .fetch_all(&mut *tx)
+            .await?;
+            let dropped = sqlx::query("DELETE FROM points WHERE namespace = $1 AND id = $2")
+                .bind(namespace)
+                .bind(from)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+            tx.commit().await?;
+            Ok((moved, dropped))
+        })
+    }
+
+    fn list<'a>(&'a self, namespace: &'a str, from: ListFrom<'a>, count: i64) -> StoreFuture<'a, Vec<MetricListing>> {
+        traced("list", async move {
+            let (position, offset) = match from {
+                ListFrom::Start => (None, 0),
+                ListFrom::Offset(offset) => (None, offset.max(0)),
+                ListFrom::After(position) | ListFrom::Before(position) => (Some(position), 0),
+            };
+            let (having, order) = match from {
+                ListFrom::Start | ListFrom::Offset(_) => ("", "DESC, id ASC"),
+                ListFrom::After(_) => ("HAVING MAX(timestamp) < $2 OR (MAX(timestamp) = $2 AND id > $3)", "DESC, id ASC"),
+                ListFrom::Before(_) => ("HAVING MAX(timestamp) > $2 OR (MAX(timestamp) = $2 AND id < $3)", "ASC, id DESC"),
+            };
+            let sql = format!(
+                "SELECT id, COUNT(*), MAX(timestamp) FROM points WHERE namespace = $1 GROUP BY id {having}
+                 ORDER BY MAX(timestamp) {order} LIMIT $4 OFFSET $5"
+            );
+            let rows: Vec<(String, i64, i64)> = sqlx::query_as(&sql)
+                .bind(namespace)
+                .bind(position.map(|position| position.last_timestamp))
+                .bind(position.map(|position| position.id))
+                .bind(limit(count))
+                .bind(offset)
+                .fetch_all(&self.pool)
+                .await?;
+            Ok(rows
+                .into_iter()
+                .map(|(id, point_count, last_timestamp)| MetricListing {
+                    id,
+                    point_count,
+                    last_timestamp: Some(last_timestamp),
+                })
+                .collect())
+        })
+    }
+
+    fn aggregate<'a>(
+        &'a self,
+        namespace: &'a str,
+        id: &'a str,
+        since: i64,
+        until: i64,
+    ) -> StoreFuture<'a, Option<Aggregate>> {
+        traced("aggregate", async move {
+            let row: AggregateRow = sqlx::query_as(
+                "SELECT COUNT(*), MIN(value), MAX(value), AVG(value), AVG(value * value), MAX(timestamp),
+                     (SELECT value FROM points WHERE namespace = $1 AND id = $2 AND timestamp BETWEEN $3 AND $4
+                      ORDER BY timestamp DESC, point_id DESC LIMIT 1)
+                 FROM points
+                 WHERE namespace = $1 AND id = $2 AND timestamp BETWEEN $3 AND $4",
+            )
+            .bind(namespace)
+            .bind(id)
+            .bind(since)
+            .bind(until)
+            .fetch_one(&self.pool)
+            .await?;
+            let (count, Some(min), Some(max), Some(mean), Some(mean_square), Some(last_timestamp), Some(latest)) = row
+            else {
+                return Ok(None);
+            };
+            Ok(Some(Aggregate {
+                count,
+                min,
+                max,
+                mean,
+                mean_square,
+                latest,
+                last_timestamp,
+            }))
+        })
+    }
+
+    fn recent<'a>(&'a self, namespace: &'a str, id: &'a str, until: i64, count: i64) -> StoreFuture<'a, Vec<MetricPoint>> {
+        traced("recent", async move {
+            let rows: Vec<PointRow> = sqlx::query_as(
+                "SELECT value, timestamp, sha, branch FROM points
+                 WHERE namespace = $1 AND id = $2 AND timestamp <= $3 ORDER BY timestamp DESC, point_id DESC LIMIT $4",
+            )
+            .bind(namespace)
+            .bind(id)
+            .bind(until)
+            .bind(limit(count))
+            .fetch_all(&self.pool)
+            .await?;
+            Ok(rows.into_iter().rev().map(to_point).collect())
+        })
+    }
+
+    fn namespaces(&self) -> StoreFuture<'_, Vec<String>> {
+        traced("namespaces", async move {
+            sqlx::query_scalar("SELECT DISTINCT namespace FROM points ORDER BY namespace")
+                .fetch_all(&self.pool)
+                .await
+        })
+    }
+
+    fn ids<'a>(&'a self, namespace: &'a str) -> StoreFuture<'a, Vec<String>> {
+        traced("ids", async move {
+            sqlx::query_scalar("SELECT DISTINCT id FROM points WHERE namespace = $1 ORDER BY id")
+                .bind(namespace)
+                .fetch_all(&self.pool)
+                .await
+        })
+    }
+
+    fn namespace_summary<'a>(&'a self, namespace: &'a str) -> StoreFuture<'a, NamespaceSummary> {
+        traced("namespace_summary", async move {
+            let (metrics, points): (i64, i64) =
+                sqlx::query_as("SELECT COUNT(DISTINCT id), COUNT(*) FROM points WHERE namespace = $1")
+                    .bind(namespace)
+                    .fetch_one(&self.pool)
+                    .await?;
+            let latest: Option<(i64, String, i64)> = sqlx::query_as(
+                "SELECT point_id, id, timestamp FROM points
+                 WHERE namespace = $1 ORDER BY timestamp DESC, point_id DESC LIMIT 1",
+            )
+            .bind(namespace)
+            .fetch_optional(&self.pool)
+            .await?;
+            Ok(NamespaceSummary {
+                metrics,
+                points,
+                latest: latest.map(|(point_id, id, timestamp)| LatestWrite { point_id, id, timestamp }),
+            })
+        })
+    }
+
+    fn rename<'a>(&'a self, namespace: &'a str, from: &'a str, to: &'a str) -> StoreFuture<'a, Option<u64>> {
+        traced("rename", async move {
+            let mut tx = self.pool.begin().await?;
+            lock_namespace(&mut tx, namespace).await?;
+            let taken: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM points WHERE namespace = $1 AND id = $2)")
+                .bind(namespace)
+                .bind(to)
+                .fetch_one(&mut *tx)
+                .await?;
+            if taken {
+                return Ok(None);
+            }
+            let moved = sqlx::query("UPDATE points SET id = $1 WHERE namespace = $2 AND id = $3")
+                .bind(to)
+                .bind(namespace)
+                .bind(from)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+            tx.commit().await?;
+            Ok(Some(moved))
+        })
+    }
+
+    fn copy_namespace<'a>(&'a self, from: &'a str, to: &'a str, since: i64) -> StoreFuture<'a, Option<u64>> {
+        traced("copy_namespace", async move {
+            let mut tx = self.pool.begin().await?;
+            lock_namespace(&mut tx, to).await?;
+            let taken: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM points WHERE namespace = $1)")
+                .bind(to)
+                .fetch_one(&mut *tx)
+                .await?;
+            if taken {
+                return Ok(None);
+            }
+            // Copies are numbered in the order the originals were stored
+            let copied = sqlx::query(
+                "INSERT INTO points (namespace, id, value, timestamp, sha, branch)
+                 SELECT $2, id, value, timestamp, sha, branch FROM points
+                 WHERE namespace = $1 AND timestamp >= $3
+                 ORDER BY point_id",
+            )
+            .bind(from)
+            .bind(to)
+            .bind(since)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+            tx.commit().await?;
+            Ok(Some(copied))
+        })
+    }
+
+    fn delete_namespace<'a>(&'a self, namespace: &'a str) -> StoreFuture<'a, u64> {
+        traced("delete_namespace", async move {
+            let mut tx = self.pool.begin().await?;
+            let deleted = sqlx::query("DELETE FROM points WHERE namespace = $1")
+                .bind(namespace)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+            sqlx::query("DELETE FROM deleted_points WHERE namespace = $1")
+                .bind(namespace)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("DELETE FROM deleted_metrics WHERE namespace = $1")
+                .bind(namespace)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            Ok(deleted)
+        })
+    }
+
+    fn expire<'a>(&'a self, namespace: &'a str, id: &'a str, cutoff: i64) -> StoreFuture<'a, u64> {
+        traced("expire", async move {
+            let mut tx = self.pool.begin().await?;
+            lock_namespace(&mut tx, namespace).await?;
+            let deleted = sqlx::query(
+                "DELETE FROM points WHERE namespace = $1 AND id = $2
+                   AND NOT EXISTS (SELECT 1 FROM points WHERE namespace = $1 AND id = $2 AND timestamp >= $3)",
+            )
+            .bind(namespace)
+            .bind(id)
+            .bind(cutoff)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+            tx.commit().await?;
+            Ok(deleted)
+        })
+    }
+
+    /// Ids come from a sequence, so a write still in flight may commit
+    /// with one below this; archiving only counts on points old enough
+    /// that nothing is still writing them.
+    fn watermark(&self) -> StoreFuture<'_, i64> {
+        traced("watermark", async move {
+            sqlx::query_scalar("SELECT COALESCE(MAX(point_id), 0) FROM points")
+                .fetch_one(&self.pool)
+                .await
+        })
+    }
+
+    fn scan<'a>(&'a self, namespace: &'a str, before: i64, through: i64) -> PointStream<'a> {
+        // Pages follow on from the last `(id, timestamp, point_id)` read
+        let start: Option<(String, i64, i64)> = None;
+        stream::try_unfold((start, false), move |(after, done)| async move {
+            if done {
+                return Ok(None);
+            }
+            let (id, timestamp, point_id) = after.clone().unwrap_or((String::new(), i64::MIN, i64::MIN));
+            let rows: Vec<ScanRow> = sqlx::query_as(
+                "SELECT point_id, id, value, timestamp, sha, branch FROM points
+                 WHERE namespace = $1 AND timestamp < $2 AND point_id <= $3
+                   AND (id, timestamp, point_id) > ($4, $5, $6)
+                 ORDER BY id, timestamp, point_id LIMIT $7",
+            )
+            .bind(namespace)
+            .bind(before)
+            .bind(through)
+            .bind(id)
+            .bind(timestamp)
+            .bind(point_id)
+            .bind(SCAN_PAGE)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(store_error)?;
+            if rows.is_empty() {
+                return Ok(None);
+            }
+            let done = (rows.len() as i64) < SCAN_PAGE;
+            let after = rows.last().map(|(point_id, id, _, timestamp, ..)| (id.clone(), *timestamp, *point_id));
+            let page: Vec<(String, MetricPoint)> = rows
+                .into_iter()
+                .map(|(_, id, value, timestamp, sha, branch)| (id, to_point((value, timestamp, sha, branch))))
+                .collect();
+            Ok::<_, StoreError>(Some((page, (after, done))))
+        })
+        .map_ok(|page| stream::iter(page.into_iter().map(Ok)))
+        .try_flatten()
+        .boxed()
+    }
+
+    fn prune<'a>(&'a self, namespace: &'a str, before: i64, through: i64, count: i64) -> StoreFuture<'a, u64> {
+        traced("prune", async move {
+            let result = sqlx::query(
+                "DELETE FROM points WHERE point_id IN (
+                     SELECT point_id FROM points WHERE namespace = $1 AND timestamp < $2 AND point_id <= $3 LIMIT $4
+                 )",
+            )
+            .bind(namespace)
+            .bind(before)
+            .bind(through)
+            .bind(limit(count))
+            .execute(&self.pool)
+            .await?;
+            Ok(result.rows_affected())
+        })
+    }
+
+    fn first_timestamp(&self) -> StoreFuture<'_, Option<i64>> {
+        traced("first_timestamp", async move {
+            sqlx::query_scalar("SELECT MIN(timestamp) FROM points")
+                .fetch_one(&self.pool)
+                .await
+        })
+    }
+
+    fn buckets<'a>(
+        &'a self,
+        scope: BucketScope<'a>,
+        resolution: i64,
+        since: i64,
+        until: i64,
+    ) -> StoreFuture<'a, Vec<Bucket>> {
+        traced("buckets", async move {
+            let series = match scope {
+                BucketScope::All => "",
+                BucketScope::Namespace(_) => "namespace = $4 AND",
+                BucketScope::Series(..) => "namespace = $4 AND id = $5 AND",
+            };
+            let sql = format!(
+                "SELECT namespace, id, timestamp - (timestamp % $1) AS bucket, COUNT(*), SUM(value), MIN(value), MAX(value)
+                 FROM points WHERE {series} timestamp >= $2 AND timestamp < $3
+                 GROUP BY namespace, id, bucket
+                 ORDER BY namespace, id, bucket"
+            );
+            let mut query = sqlx::query_as::<_, (String, String, i64, i64, f64, f64, f64)>(&sql)
+                .bind(resolution)
+                .bind(since)
+                .bind(until);
+            match scope {
+                BucketScope::All => {}
+                BucketScope::Namespace(namespace) => query = query.bind(namespace),
+                BucketScope::Series(namespace, id) => query = query.bind(namespace).bind(id),
+            }
+            let rows = query.fetch_all(&self.pool).await?;
+            Ok(rows
+                .into_iter()
+                .map(|(namespace, id, bucket, count, sum, min, max)| Bucket {
+                    namespace,
+                    id,
+                    bucket,
+                    count,
+                    sum,
+                    min,
+                    max,
+                })
+                .collect())
+        })
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::sqlite::SqlitePool;
+
+use crate::{auth, errors, ids::SeriesPath, AppState};
+
+const MAX_DECIMALS: i64 = 15;
+
+/// How finely a metric's values are stored. Incoming values are snapped to
+/// the nearest multiple of `quantum`, then rounded to `decimals` places, so
+/// sensor jitter never reaches the database.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct Precision {
+    pub decimals: Option<i64>,
+    pub quantum: Option<f64>,
+}
+
+impl Precision {
+    pub fn apply(&self, value: f64) -> f64 {
+        let mut value = value;
+        let mut decimals = self.decimals;
+        if let Some(quantum) = self.quantum {
+            value = (value / quantum).round() * quantum;
+            // 0.1 * 3 is 0.30000000000000004; trim back to the quantum's own places
+            let places = decimal_places(quantum);
+            decimals = Some(decimals.map_or(places, |d| d.min(places)));
+        }
+        match decimals {
+            Some(decimals) => {
+                let factor = 10f64.powi(decimals as i32);
+                (value * factor).round() / factor
+            }
+            None => value,
+        }
+    }
+
+    fn validate(&self) -> Result<(), &'static str> {
+        if let Some(decimals) = self.decimals
+            && !(0..=MAX_DECIMALS).contains(&decimals)
+        {
+            return Err("decimals must be between 0 and 15");
+        }
+        if let Some(quantum) = self.quantum
+            && !(quantum.is_finite() && quantum > 0.0)
+        {
+            return Err("quantum must be a positive number");
+        }
+        Ok(())
+    }
+}
+
+/// Smallest number of decimal places that represents `quantum` exactly enough.
+fn decimal_places(quantum: f64) -> i64 {
+    (0..=MAX_DECIMALS)
+        .find(|&places| {
+            let scaled = quantum * 10f64.powi(places as i32);
+            (scaled - scaled.round()).abs() < 1e-9 * scaled.abs().max(1.0)
+        })
+        .unwrap_or(MAX_DECIMALS)
+}
+
+/// The precision configured for one metric, or the identity policy.
+pub async fn load(pool: &SqlitePool, namespace: &str, id: &str) -> Result<Precision, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"SELECT decimals, quantum as "quantum: f64" FROM metric_precision WHERE namespace = ? AND id = ?"#,
+        namespace,
+        id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row
+        .map(|row| Precision {
+            decimals: row.decimals,
+            quantum: row.quantum,
+        })
+        .unwrap_or_default())
+}
+
+/// Every configured precision in a namespace, keyed by metric id.
+pub async fn load_namespace(pool: &SqlitePool, namespace: &str) -> Result<HashMap<String, Precision>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"SELECT id, decimals, quantum as "quantum: f64" FROM metric_precision WHERE namespace = ?"#,
+        namespace
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.id,
+                Precision {
+                    decimals: row.decimals,
+                    quantum: row.quantum,
+                },
+            )
+        })
+        .collect())
+}
+
+pub async fn get_precision(
+    SeriesPath(namespace, id): SeriesPath,
+    State(pool): State<SqlitePool>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let precision = load(&pool, &namespace, &id)
+        .await
+        .map_err(errors::internal)?;
+    Ok(Json(precision))
+}
+
+/// Replaces a metric's precision. Points already stored are left as they are.
+pub async fn put_precision(
+    SeriesPath(namespace, id): SeriesPath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(precision): Json<Precision>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    auth::require_admin(&state.config(), &headers)
+        .map_err(|status| (status, Json(serde_json::json!({ "error": "unauthorized" }))))?;
+    precision
+        .validate()
+        .map_err(|msg| (StatusCode::UNPROCESSABLE_ENTITY, Json(serde_json::json!({ "error": msg }))))?;
+
+    let (pool, namespace, id) = (&state.pool, &namespace, &id);
+    state
+        .write(|| async move {
+            sqlx::query!(
+                "INSERT INTO metric_precision (namespace, id, decimals, quantum) VALUES (?, ?, ?, ?)
+                 ON CONFLICT (namespace, id) DO UPDATE SET decimals = excluded.decimals, quantum = excluded.quantum",
+                namespace,
+                id,
+                precision.decimals,
+                precision.quantum
+            )
+            .execute(pool)
+            .await
+        })
+        .await
+        .map_err(|err| (errors::internal(err), Json(serde_json::json!({ "error": "database error" }))))?;
+
+    Ok(Json(precision))
+}
+
+pub async fn delete_precision(
+    SeriesPath(namespace, id): SeriesPath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&state.config(), &headers)?;
+
+    let (pool, namespace, id) = (&state.pool, &namespace, &id);
+    state
+        .write(|| async move {
+            sqlx::query!(
+                "DELETE FROM metric_precision WHERE namespace = ? AND id = ?",
+                namespace,
+                id
+            )
+            .execute(pool)
+            .await
+        })
+        .await
+        .map_err(errors::internal)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+};
+use crate::{errors, ids::NamespacePath, meta::{self, MetricMeta}, AppState};
+
+/// Every series family starts with this, so no id can come out as the name
+/// of a family the exporter emits itself
+const SERIES_PREFIX: &str = "somnial_metric_";
+const LAST_UPDATED: &str = "somnial_last_updated_seconds";
+
+const TEXT_FORMAT: &str = "text/plain";
+const OPENMETRICS: &str = "application/openmetrics-text";
+
+/// Maps text onto the Prometheus name alphabet `[a-zA-Z0-9_:]`.
+fn sanitize(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == ':' { c } else { '_' })
+        .collect()
+}
+
+/// A unit from a metric's metadata as a Prometheus name suffix, if it has
+/// any name characters at all.
+fn unit_suffix(unit: &str) -> Option<String> {
+    let unit = match unit.trim() {
+        "%" => "percent".to_string(),
+        "°C" => "celsius".to_string(),
+        "°F" => "fahrenheit".to_string(),
+        unit => sanitize(unit).trim_matches('_').to_ascii_lowercase(),
+    };
+    (!unit.is_empty()).then_some(unit)
+}
+
+/// The family a series goes in: its id, prefixed, with the unit on the end
+/// as Prometheus and OpenMetrics name them.
+fn metric_name(id: &str, unit: Option<&str>) -> String {
+    let mut name = format!("{}{}", SERIES_PREFIX, sanitize(id));
+    if let Some(unit) = unit
+        && !name.ends_with(&format!("_{}", unit))
+    {
+        name.push('_');
+        name.push_str(unit);
+    }
+    name
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn escape_help(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn format_sample(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// One family of samples, with the metadata of the first series in it.
+struct Family<'a> {
+    help: String,
+    unit: Option<String>,
+    samples: Vec<(&'a str, f64)>,
+}
+
+/// A series' newest point.
+struct Latest {
+    id: String,
+    value: f64,
+    timestamp: i64,
+}
+
+/// Latest value of every series in a namespace, in Prometheus text format,
+/// or OpenMetrics for scrapers that ask for it.
+///
+/// Each series becomes a gauge named after its id, behind
+/// `somnial_metric_` and with its unit as a suffix; HELP is its
+/// description. The original id is kept as a label, so ids that sanitize
+/// to the same name share one family. A companion
+/// `somnial_last_updated_seconds` gauge lets alerts spot series that have
+/// stopped reporting.
+pub async fn get_prometheus(
+    NamespacePath(namespace): NamespacePath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    let mut rows = Vec::new();
+    for id in state.store.ids(&namespace).await.map_err(errors::internal)? {
+        // Same tie-break as HEAD on a chart: the last point written wins
+        if let Some(point) = state.store.latest(&namespace, &id).await.map_err(errors::internal)? {
+            rows.push(Latest {
+                id,
+                value: point.value,
+                timestamp: point.timestamp,
+            });
+        }
+    }
+    let metas = meta::load_namespace(&state.pool, &namespace)
+        .await
+        .map_err(errors::internal)?;
+    let openmetrics = crate::negotiate(&headers, &[TEXT_FORMAT, OPENMETRICS]) == OPENMETRICS;
+
+    let no_meta = MetricMeta::default();
+    let mut families: BTreeMap<String, Family> = BTreeMap::new();
+    for row in &rows {
+        let meta = metas.get(&row.id).unwrap_or(&no_meta);
+        let unit = meta.unit.as_deref().and_then(unit_suffix);
+        families
+            .entry(metric_name(&row.id, unit.as_deref()))
+            .or_insert_with(|| Family {
+                help: match &meta.description {
+                    Some(description) => description.clone(),
+                    None => format!("Latest value of {}/{}", namespace, row.id),
+                },
+                unit,
+                samples: Vec::new(),
+            })
+            .samples
+            .push((&row.id, row.value));
+    }
+
+    let namespace_label = escape_label(&namespace);
+    let mut out = String::new();
+    for (name, family) in &families {
+        let _ = writeln!(out, "# HELP {} {}", name, escape_help(&family.help));
+        let _ = writeln!(out, "# TYPE {} gauge", name);
+        if openmetrics && let Some(unit) = &family.unit {
+            let _ = writeln!(out, "# UNIT {} {}", name, unit);
+        }
+        for (id, value) in &family.samples {
+            let _ = writeln!(
+                out,
+                "{}{{namespace=\"{}\",id=\"{}\"}} {}",
+                name,
+                namespace_label,
+                escape_label(id),
+                format_sample(*value)
+            );
+        }
+    }
+
+    if !rows.is_empty() {
+        let _ = writeln!(out, "# HELP {} Unix time of the latest point in each series", LAST_UPDATED);
+        let _ = writeln!(out, "# TYPE {} gauge", LAST_UPDATED);
+        if openmetrics {
+            let _ = writeln!(out, "# UNIT {} seconds", LAST_UPDATED);
+        }
+        for row in &rows {
+            let _ = writeln!(
+                out,
+                "{}{{namespace=\"{}\",id=\"{}\"}} {}",
+                LAST_UPDATED,
+                namespace_label,
+                escape_label(&row.id),
+                row.timestamp
+            );
+        }
+    }
+
+    let content_type = if openmetrics {
+        out.push_str("# EOF\n");
+        "application/openmetrics-text; version=1.0.0; charset=utf-8"
+    } else {
+        "text/plain; version=0.0.4"
+    };
+    Ok((StatusCode::OK, [("content-type", content_type)], out))
+}
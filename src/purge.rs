@@ -0,0 +1,219 @@
+//! `DELETE /{namespace}`: wiping a namespace takes two requests. The first
+//! says what would go and hands back a short-lived confirmation token; only
+//! repeating the request with `?confirm=<token>` deletes anything, so a
+//! stray `curl -X DELETE` can't empty a namespace on its own.
+//!
+//! Only the admin token may do this: an owner token looks after a
+//! namespace's settings, not whether it exists.
+//! Custom domains and dashboards are left alone, since they belong to the
+//! operator and to other namespaces' owners.
+//!
+//! With SQLite the points and everything kept for them go in one
+//! transaction. With Postgres the points go first and the settings in a
+//! second transaction, so a failure in between leaves the settings for a
+//! repeated deletion to finish.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::SqliteConnection;
+
+use crate::{auth, ids::NamespacePath, store, AppState};
+
+const CONFIRM_TOKEN_LENGTH: usize = 24;
+/// How long a confirmation token stays usable.
+const CONFIRM_SECONDS: i64 = 300;
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(json!({ "error": message })))
+}
+
+fn database_error<E: std::fmt::Display>(err: E) -> ApiError {
+    crate::errors::report(&err);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+/// Confirmation tokens handed out and not yet used, by namespace. Only the
+/// latest token for a namespace is valid; nothing survives a restart.
+#[derive(Default)]
+pub struct PendingDeletions {
+    tokens: Mutex<HashMap<String, (String, i64)>>,
+}
+
+impl PendingDeletions {
+    fn issue(&self, namespace: &str, now: i64) -> (String, i64) {
+        let token = auth::random_string(CONFIRM_TOKEN_LENGTH);
+        let expires_at = now + CONFIRM_SECONDS;
+        let mut tokens = self.tokens.lock().unwrap();
+        tokens.retain(|_, &mut (_, expires_at)| expires_at > now);
+        tokens.insert(namespace.to_string(), (token.clone(), expires_at));
+        (token, expires_at)
+    }
+
+    /// Uses up `token` if it is the current one for `namespace`.
+    fn redeem(&self, namespace: &str, token: &str, now: i64) -> bool {
+        let mut tokens = self.tokens.lock().unwrap();
+        match tokens.get(namespace) {
+            Some((expected, expires_at)) if expected == token && *expires_at > now => {
+                tokens.remove(namespace);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DeleteNamespaceQuery {
+    confirm: Option<String>,
+}
+
+#[derive(Serialize)]
+struct PendingDeletion {
+    namespace: String,
+    metrics: i64,
+    points: i64,
+    /// Repeat the request with `?confirm=` set to this to go ahead
+    confirm: String,
+    expires_at: i64,
+}
+
+pub async fn delete_namespace(
+    NamespacePath(namespace): NamespacePath,
+    Query(query): Query<DeleteNamespaceQuery>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+    let pool = &state.pool;
+
+    let now = Utc::now().timestamp();
+    let Some(confirm) = query.confirm.filter(|confirm| !confirm.is_empty()) else {
+        let counts = state.store.namespace_summary(&namespace).await.map_err(database_error)?;
+        let (confirm, expires_at) = state.pending_deletions.issue(&namespace, now);
+        return Ok(Json(PendingDeletion {
+            namespace,
+            metrics: counts.metrics,
+            points: counts.points,
+            confirm,
+            expires_at,
+        })
+        .into_response());
+    };
+    if !state.pending_deletions.redeem(&namespace, &confirm, now) {
+        return Err(error(
+            StatusCode::CONFLICT,
+            "confirmation token is wrong or has expired; repeat the request without confirm for a new one",
+        ));
+    }
+
+    // SQLite keeps the points beside the settings, so both go in one
+    // transaction. Postgres keeps them in another database: the points go
+    // first and the settings in a second transaction, and if that fails,
+    // repeating the deletion finishes it
+    let namespace_ref = &namespace;
+    match state.store.settings_pool() {
+        Some(store_pool) => state
+            .write(|| async move {
+                let mut tx = store_pool.begin().await?;
+                store::delete_namespace_points(&mut tx, namespace_ref).await?;
+                clear_settings(&mut tx, namespace_ref).await?;
+                tx.commit().await
+            })
+            .await
+            .map_err(database_error)?,
+        None => {
+            state
+                .write_store(|| state.store.delete_namespace(namespace_ref))
+                .await
+                .map_err(database_error)?;
+            state
+                .write(|| async move {
+                    let mut tx = pool.begin().await?;
+                    clear_settings(&mut tx, namespace_ref).await?;
+                    tx.commit().await
+                })
+                .await
+                .map_err(database_error)?;
+        }
+    }
+    state.invalidate_namespace(&namespace);
+    state.aliases.reload(pool).await.map_err(database_error)?;
+    state.alerts.reload(pool, state.store.as_ref()).await.map_err(database_error)?;
+    state.webhooks.reload(pool).await.map_err(database_error)?;
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// Removes everything kept for `namespace` besides its points.
+async fn clear_settings(conn: &mut SqliteConnection, namespace: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM metric_rollups WHERE namespace = ?", namespace)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!("DELETE FROM metric_meta WHERE namespace = ?", namespace)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!("DELETE FROM metric_precision WHERE namespace = ?", namespace)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!("DELETE FROM markers WHERE namespace = ?", namespace)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!("DELETE FROM snapshots WHERE namespace = ?", namespace)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!("DELETE FROM namespace_retention WHERE namespace = ?", namespace)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!("DELETE FROM namespace_point_caps WHERE namespace = ?", namespace)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!("DELETE FROM metric_point_caps WHERE namespace = ?", namespace)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!("DELETE FROM namespace_readmes WHERE namespace = ?", namespace)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!("DELETE FROM namespace_owners WHERE namespace = ?", namespace)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!("DELETE FROM metric_aliases WHERE namespace = ?", namespace)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!("DELETE FROM alert_rules WHERE namespace = ?", namespace)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!("DELETE FROM namespace_webhooks WHERE namespace = ?", namespace)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!("DELETE FROM github_repos WHERE namespace = ?", namespace)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!("DELETE FROM github_baselines WHERE namespace = ?", namespace)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!("DELETE FROM namespace_digests WHERE namespace = ?", namespace)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!("DELETE FROM alert_events WHERE namespace = ?", namespace)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!("DELETE FROM anomalies WHERE namespace = ?", namespace)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!("DELETE FROM anomaly_scans WHERE namespace = ?", namespace)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
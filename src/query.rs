@@ -0,0 +1,144 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use sqlx::{Column, Row, TypeInfo, ValueRef, sqlite::SqliteRow};
+
+use crate::{auth, AppState};
+
+const MAX_ROWS: usize = 10_000;
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+/// SQLite virtual machine steps between checks of the deadline
+const PROGRESS_OPS: i32 = 10_000;
+
+#[derive(Deserialize)]
+pub struct QueryRequest {
+    sql: String,
+    #[serde(default)]
+    params: Vec<Value>,
+}
+
+/// Only a single SELECT (optionally behind a CTE) is accepted. The statement
+/// also runs on a read-only connection, so this check is about giving a clear
+/// error rather than being the only line of defence.
+fn validate(sql: &str) -> Result<&str, String> {
+    let sql = sql.trim().trim_end_matches(';').trim();
+    if sql.contains(';') {
+        return Err("only a single statement is allowed".to_string());
+    }
+
+    let keyword = sql
+        .split(|c: char| !c.is_ascii_alphabetic())
+        .next()
+        .unwrap_or("")
+        .to_ascii_uppercase();
+    if keyword != "SELECT" && keyword != "WITH" {
+        return Err("only SELECT queries are allowed".to_string());
+    }
+
+    Ok(sql)
+}
+
+fn cell(row: &SqliteRow, index: usize) -> Value {
+    let raw = match row.try_get_raw(index) {
+        Ok(raw) if !raw.is_null() => raw,
+        _ => return Value::Null,
+    };
+
+    match raw.type_info().name() {
+        "INTEGER" => row.try_get::<i64, _>(index).map(Value::from),
+        "REAL" => row.try_get::<f64, _>(index).map(Value::from),
+        "BLOB" => row.try_get::<Vec<u8>, _>(index).map(|b| Value::from(format!("<{} bytes>", b.len()))),
+        _ => row.try_get::<String, _>(index).map(Value::from),
+    }
+    .unwrap_or(Value::Null)
+}
+
+pub async fn post_query(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<QueryRequest>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    auth::require_admin(&state.config(), &headers)
+        .map_err(|status| (status, Json(serde_json::json!({ "error": "unauthorized" }))))?;
+
+    let bad_request = |msg: String| (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": msg })));
+    let sql = validate(&request.sql).map_err(bad_request)?;
+
+    // Wrapping the statement bounds how many rows SQLite will produce
+    let limited = format!("SELECT * FROM ({}) LIMIT {}", sql, MAX_ROWS + 1);
+    let mut query = sqlx::query(&limited);
+    for param in &request.params {
+        query = match param {
+            Value::Null => query.bind(None::<String>),
+            Value::Bool(b) => query.bind(*b),
+            Value::Number(n) if n.is_i64() => query.bind(n.as_i64()),
+            Value::Number(n) => query.bind(n.as_f64()),
+            Value::String(s) => query.bind(s.clone()),
+            _ => return Err(bad_request("params must be scalars".to_string())),
+        };
+    }
+
+    // Dropping a timed-out future would leave SQLite stepping through the
+    // statement and holding the connection, so SQLite itself is told to
+    // stop once the deadline passes
+    let mut conn = state
+        .read_only_pool
+        .acquire()
+        .await
+        .map_err(|err| bad_request(err.to_string()))?;
+    let deadline = Instant::now() + QUERY_TIMEOUT;
+    let timed_out = Arc::new(AtomicBool::new(false));
+    let flag = timed_out.clone();
+    conn.lock_handle()
+        .await
+        .map_err(|err| bad_request(err.to_string()))?
+        .set_progress_handler(PROGRESS_OPS, move || {
+            let going = Instant::now() < deadline;
+            if !going {
+                flag.store(true, Ordering::Relaxed);
+            }
+            going
+        });
+    let rows = query.fetch_all(&mut *conn).await;
+    let removed = match conn.lock_handle().await {
+        Ok(mut handle) => {
+            handle.remove_progress_handler();
+            true
+        }
+        Err(_) => false,
+    };
+    if !removed {
+        // Not back into the pool with the handler still set
+        drop(conn.detach());
+    }
+    if timed_out.load(Ordering::Relaxed) {
+        return Err(bad_request("query timed out".to_string()));
+    }
+    let rows = rows.map_err(|err| bad_request(err.to_string()))?;
+
+    let columns: Vec<String> = rows
+        .first()
+        .map(|row| row.columns().iter().map(|c| c.name().to_string()).collect())
+        .unwrap_or_default();
+    let truncated = rows.len() > MAX_ROWS;
+    let rows: Vec<Vec<Value>> = rows
+        .iter()
+        .take(MAX_ROWS)
+        .map(|row| (0..row.columns().len()).map(|i| cell(row, i)).collect())
+        .collect();
+
+    Ok(Json(serde_json::json!({
+        "columns": columns,
+        "rows": rows,
+        "truncated": truncated,
+    })))
+}
@@ -0,0 +1,140 @@
+//! A Markdown note shown at the top of a namespace page, explaining what its
+//! metrics are and how they're collected.
+//!
+//! Only the namespace's owner (see [`owners`](crate::owners)) or the
+//! operator can write or delete it.
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::sqlite::SqlitePool;
+
+use crate::{ids::NamespacePath, markdown, owners, AppState};
+
+const MAX_BODY_LENGTH: usize = 20_000;
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(json!({ "error": message })))
+}
+
+fn database_error<E: std::fmt::Display>(err: E) -> ApiError {
+    crate::errors::report(&err);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+#[derive(Deserialize)]
+pub struct ReadmeRequest {
+    body: String,
+}
+
+#[derive(Serialize)]
+pub struct Readme {
+    body: String,
+    updated_at: i64,
+}
+
+async fn load(pool: &SqlitePool, namespace: &str) -> Result<Option<Readme>, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT body, updated_at FROM namespace_readmes WHERE namespace = ?",
+        namespace
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|row| Readme {
+        body: row.body,
+        updated_at: row.updated_at,
+    }))
+}
+
+/// The namespace's README rendered for its page, if it has one, with links
+/// on this site under `base_path`.
+pub async fn load_html(pool: &SqlitePool, base_path: &str, namespace: &str) -> Result<Option<String>, sqlx::Error> {
+    Ok(load(pool, namespace)
+        .await?
+        .map(|readme| markdown::to_html(&readme.body, base_path)))
+}
+
+pub async fn get_readme(
+    NamespacePath(namespace): NamespacePath,
+    State(pool): State<SqlitePool>,
+) -> Result<impl IntoResponse, ApiError> {
+    let readme = load(&pool, &namespace)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "this namespace has no readme"))?;
+    Ok(Json(readme))
+}
+
+/// Writes the README, answering 201 when it's the first one. Only the
+/// namespace's owner or the operator can.
+pub async fn put_readme(
+    NamespacePath(namespace): NamespacePath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<ReadmeRequest>,
+) -> Result<Response, ApiError> {
+    owners::require_owner(&state, &headers, &namespace).await?;
+    let body = request.body.trim().to_string();
+    if body.is_empty() || body.len() > MAX_BODY_LENGTH {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "body must be 1 to 20000 bytes"));
+    }
+    let now = Utc::now().timestamp();
+    let (pool, namespace, body_ref) = (&state.pool, &namespace, &body);
+    let existed = state
+        .write(|| async move {
+            let mut tx = pool.begin().await?;
+            let existed = sqlx::query_scalar!(
+                r#"SELECT EXISTS(SELECT 1 FROM namespace_readmes WHERE namespace = ?) as "exists: bool""#,
+                namespace
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            sqlx::query!(
+                "INSERT INTO namespace_readmes (namespace, body, updated_at) VALUES (?, ?, ?)
+                 ON CONFLICT (namespace) DO UPDATE SET body = excluded.body, updated_at = excluded.updated_at",
+                namespace,
+                body_ref,
+                now
+            )
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+            Ok(existed)
+        })
+        .await
+        .map_err(database_error)?;
+
+    let status = if existed { StatusCode::OK } else { StatusCode::CREATED };
+    Ok((status, Json(Readme { body, updated_at: now })).into_response())
+}
+
+pub async fn delete_readme(
+    NamespacePath(namespace): NamespacePath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    owners::require_owner(&state, &headers, &namespace).await?;
+    let (pool, namespace) = (&state.pool, &namespace);
+    let deleted = state
+        .write(|| async move {
+            sqlx::query!("DELETE FROM namespace_readmes WHERE namespace = ?", namespace)
+                .execute(pool)
+                .await
+        })
+        .await
+        .map_err(database_error)?
+        .rows_affected();
+    if deleted == 0 {
+        return Err(error(StatusCode::NOT_FOUND, "this namespace has no readme"));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
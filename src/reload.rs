@@ -0,0 +1,270 @@
+//! Reloading settings while the server runs, so changing retention, tokens
+//! or where alerts go doesn't mean a restart that drops the writes sent in
+//! the meantime. A reload reads the config file and `--set` flags again
+//! (the environment is fixed for the life of the process) on `SIGHUP`, on
+//! `POST /admin/reload`, or by itself when the config file is saved.
+//!
+//! Settings only read as the server starts, such as its address, the
+//! database and which features are on, keep their running values; a reload
+//! names the ones that changed, since they still need a restart. Alert
+//! rules, webhooks, custom domains and aliases are read from the database
+//! again at the same time, and rendered charts and badges are dropped, so
+//! pages pick up the new defaults.
+
+use std::fmt::Debug;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde_json::{json, Value};
+
+use crate::{
+    auth,
+    config::{Config, Sources},
+    store::StoreError,
+    AppState,
+};
+
+/// How often the config file is checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The settings in effect, and where to read them again from.
+pub struct LiveConfig {
+    current: RwLock<Arc<Config>>,
+    sources: Sources,
+}
+
+impl LiveConfig {
+    pub fn new(config: Config, sources: Sources) -> Self {
+        LiveConfig {
+            current: RwLock::new(Arc::new(config)),
+            sources,
+        }
+    }
+
+    pub fn get(&self) -> Arc<Config> {
+        self.current.read().unwrap().clone()
+    }
+}
+
+/// Keeps the running value of a setting only read at startup, noting it
+/// in `restart` when the reloaded one differs.
+fn keep<T: Clone + Debug>(restart: &mut Vec<&'static str>, name: &'static str, running: &T, loaded: &mut T) {
+    if format!("{:?}", running) != format!("{:?}", loaded) {
+        restart.push(name);
+        *loaded = running.clone();
+    }
+}
+
+/// Puts the startup-only settings of `running` back into `loaded`, and
+/// returns the ones that would have changed.
+fn keep_startup_settings(running: &Config, loaded: &mut Config) -> Vec<&'static str> {
+    let mut restart = Vec::new();
+    keep(&mut restart, "DATABASE_URL", &running.database_url, &mut loaded.database_url);
+    keep(&mut restart, "SETTINGS_DATABASE_URL", &running.settings_database_url, &mut loaded.settings_database_url);
+    keep(&mut restart, "BIND_ADDRESS", &running.bind_address, &mut loaded.bind_address);
+    keep(&mut restart, "PORT", &running.port, &mut loaded.port);
+    keep(&mut restart, "BASE_PATH", &running.base_path, &mut loaded.base_path);
+    keep(&mut restart, "UNIX_SOCKET", &running.unix_socket, &mut loaded.unix_socket);
+    keep(&mut restart, "UNIX_SOCKET_MODE", &running.unix_socket_mode, &mut loaded.unix_socket_mode);
+    // The certificate itself is reloaded on SIGHUP; its paths aren't
+    keep(&mut restart, "TLS_CERT_FILE", &running.tls, &mut loaded.tls);
+    keep(&mut restart, "*_FEATURES", &running.features, &mut loaded.features);
+    keep(&mut restart, "SQLITE_*", &running.sqlite, &mut loaded.sqlite);
+    keep(&mut restart, "CHART_CACHE_BYTES", &running.chart_cache_bytes, &mut loaded.chart_cache_bytes);
+    keep(&mut restart, "BADGE_CACHE_BYTES", &running.badge_cache_bytes, &mut loaded.badge_cache_bytes);
+    keep(&mut restart, "MAX_BODY_BYTES", &running.limits.max_body_bytes, &mut loaded.limits.max_body_bytes);
+    keep(&mut restart, "MAX_IMPORT_BYTES", &running.limits.max_import_bytes, &mut loaded.limits.max_import_bytes);
+    keep(&mut restart, "ID_*", &running.id_policy, &mut loaded.id_policy);
+    keep(
+        &mut restart,
+        "RETENTION_PRUNE_INTERVAL_SECS",
+        &running.retention_interval,
+        &mut loaded.retention_interval,
+    );
+    keep(&mut restart, "ROLLUP_*", &running.rollups, &mut loaded.rollups);
+    keep(
+        &mut restart,
+        "MAINTENANCE_INTERVAL_SECS",
+        &running.maintenance_interval,
+        &mut loaded.maintenance_interval,
+    );
+    keep(&mut restart, "SHUTDOWN_TIMEOUT_SECS", &running.shutdown_timeout, &mut loaded.shutdown_timeout);
+    keep(&mut restart, "ANOMALY_*", &running.anomaly_detection, &mut loaded.anomaly_detection);
+    keep(&mut restart, "OTEL_*", &running.trace_export, &mut loaded.trace_export);
+    // Reporting starts once, so each of its settings is named on its own
+    let reporting = |config: &Config| {
+        let reporting = config.error_reporting.as_ref();
+        (
+            reporting.and_then(|reporting| reporting.sentry.clone()),
+            reporting.and_then(|reporting| reporting.webhook_url.clone()),
+            reporting.and_then(|reporting| reporting.environment.clone()),
+        )
+    };
+    let (dsn, webhook_url, environment) = reporting(running);
+    let (mut loaded_dsn, mut loaded_webhook_url, mut loaded_environment) = reporting(loaded);
+    keep(&mut restart, "SENTRY_DSN", &dsn, &mut loaded_dsn);
+    keep(&mut restart, "ERROR_WEBHOOK_URL", &webhook_url, &mut loaded_webhook_url);
+    keep(&mut restart, "SENTRY_ENVIRONMENT", &environment, &mut loaded_environment);
+    loaded.error_reporting = running.error_reporting.clone();
+    restart
+}
+
+/// The logging settings that differ between `running` and `loaded`. Logging
+/// is set up from the sources before any config is read, and never again.
+fn changed_log_settings(running: &Sources, loaded: &Sources) -> Vec<&'static str> {
+    ["RUST_LOG", "LOG_FORMAT"]
+        .into_iter()
+        .filter(|name| running.var(name).ok() != loaded.var(name).ok())
+        .collect()
+}
+
+/// Reads the settings again and puts them in effect, returning the ones
+/// that changed but only take effect on a restart. Invalid settings leave
+/// the running ones as they are.
+pub async fn reload(state: &AppState) -> Result<Vec<&'static str>, String> {
+    let live = &state.settings;
+    let sources = live.sources.reread()?;
+    let mut loaded = Config::load(&sources)?;
+    let mut restart = keep_startup_settings(&live.get(), &mut loaded);
+    restart.extend(changed_log_settings(&live.sources, &sources));
+    *live.current.write().unwrap() = Arc::new(loaded);
+
+    let pool = &state.pool;
+    let reloaded = async {
+        state.domains.reload(pool).await?;
+        state.aliases.reload(pool).await?;
+        state.alerts.reload(pool, state.store.as_ref()).await?;
+        state.webhooks.reload(pool).await?;
+        Ok::<_, StoreError>(())
+    };
+    reloaded
+        .await
+        .map_err(|err| format!("the settings were reloaded, but not the alert rules and webhooks: {}", err))?;
+    state.invalidate_all();
+    Ok(restart)
+}
+
+/// Logs what a reload did.
+fn report(result: &Result<Vec<&'static str>, String>, trigger: &str) {
+    match result {
+        Ok(restart) if restart.is_empty() => tracing::info!("Reloaded the settings on {}", trigger),
+        Ok(restart) => tracing::warn!(
+            "Reloaded the settings on {}; changes to {} only take effect after a restart",
+            trigger,
+            restart.join(", ")
+        ),
+        Err(err) => tracing::error!("Kept the running settings: {}", err),
+    }
+}
+
+pub async fn post_reload(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    auth::require_admin(&state.config(), &headers)
+        .map_err(|status| (status, Json(json!({ "error": "unauthorized" }))))?;
+
+    let result = reload(&state).await;
+    report(&result, "request");
+    match result {
+        Ok(restart) => Ok(Json(json!({ "reloaded": true, "restart_needed": restart }))),
+        Err(err) => Err((StatusCode::UNPROCESSABLE_ENTITY, Json(json!({ "error": err })))),
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Reloads on `SIGHUP` and whenever the config file's modification time
+/// moves.
+pub fn spawn(state: AppState) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::hangup()) {
+            Ok(mut hangups) => {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    while hangups.recv().await.is_some() {
+                        report(&reload(&state).await, "SIGHUP");
+                    }
+                });
+            }
+            Err(err) => tracing::warn!("Couldn't listen for SIGHUP, so settings won't be reloaded on it: {}", err),
+        }
+    }
+
+    let Some(path) = state.settings.sources.file_path().map(Path::to_path_buf) else {
+        return;
+    };
+    tokio::spawn(async move {
+        let mut seen = modified(&path);
+        let mut ticks = tokio::time::interval(WATCH_INTERVAL);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            let now = modified(&path);
+            // A file being replaced is briefly missing; that isn't a change
+            if now.is_none() || now == seen {
+                continue;
+            }
+            seen = now;
+            report(&reload(&state).await, &format!("a change to {}", path.display()));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ErrorReporting;
+
+    fn reporting(environment: &str) -> Config {
+        Config {
+            error_reporting: Some(ErrorReporting {
+                sentry: None,
+                webhook_url: Some("https://errors.example.com/hook".to_string()),
+                environment: Some(environment.to_string()),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn startup_settings_keep_their_running_values() {
+        let running = Config {
+            port: "3000".to_string(),
+            ..reporting("production")
+        };
+        let mut loaded = Config {
+            port: "4000".to_string(),
+            retention_days: Some(30),
+            ..reporting("staging")
+        };
+        assert_eq!(keep_startup_settings(&running, &mut loaded), ["PORT", "SENTRY_ENVIRONMENT"]);
+        assert_eq!(loaded.port, "3000");
+        let environment = loaded.error_reporting.and_then(|reporting| reporting.environment);
+        assert_eq!(environment.as_deref(), Some("production"));
+        // Everything else takes the reloaded value
+        assert_eq!(loaded.retention_days, Some(30));
+    }
+
+    #[test]
+    fn log_settings_need_a_restart() {
+        let running = Sources::default();
+        let mut loaded = Sources::default();
+        assert!(changed_log_settings(&running, &loaded).is_empty());
+        loaded.set("log.format", "json".to_string()).unwrap();
+        loaded.set("log.level", "somnial=debug".to_string()).unwrap();
+        assert_eq!(changed_log_settings(&running, &loaded), ["RUST_LOG", "LOG_FORMAT"]);
+    }
+}
@@ -0,0 +1,106 @@
+//! One log line per request, with its method, path, status and latency,
+//! under the `somnial::request` target so `RUST_LOG=info,somnial::request=warn`
+//! keeps only the failures. Every request gets an id, taken from an
+//! incoming `X-Request-Id` when a proxy already set one, which is sent back
+//! in the response and recorded on the request's `tracing` span, so
+//! everything logged while handling it carries the id and a 500 can be
+//! traced to the error behind it.
+
+use std::time::Instant;
+
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use tracing::Instrument;
+
+static REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+/// Longer incoming ids are replaced rather than logged
+const MAX_ID_LENGTH: usize = 128;
+
+/// The request a task is handling.
+#[derive(Clone)]
+pub struct CurrentRequest {
+    pub id: String,
+    pub method: String,
+    pub path: String,
+}
+
+tokio::task_local! {
+    static CURRENT: CurrentRequest;
+}
+
+/// The request being handled by this task, if any.
+pub fn current() -> Option<CurrentRequest> {
+    CURRENT.try_with(CurrentRequest::clone).ok()
+}
+
+/// The id of the request being handled by this task, if any.
+pub fn current_id() -> Option<String> {
+    CURRENT.try_with(|request| request.id.clone()).ok()
+}
+
+fn incoming_id(request: &Request) -> Option<String> {
+    let id = request.headers().get(&REQUEST_ID)?.to_str().ok()?;
+    let valid = !id.is_empty()
+        && id.len() <= MAX_ID_LENGTH
+        && id.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"-_.".contains(&byte));
+    valid.then(|| id.to_string())
+}
+
+pub async fn log_request(request: Request, next: Next) -> Response {
+    let id = incoming_id(&request).unwrap_or_else(|| format!("{:016x}", rand::random::<u64>()));
+    // Only the path: query strings can carry tokens
+    let current = CurrentRequest {
+        id: id.clone(),
+        method: request.method().to_string(),
+        path: request.uri().path().to_string(),
+    };
+    let span = tracing::info_span!(
+        "request",
+        request_id = %current.id,
+        method = %current.method,
+        path = %current.path
+    );
+    let (method, path) = (current.method.clone(), current.path.clone());
+    let started = Instant::now();
+
+    let mut response = CURRENT
+        .scope(current, async move {
+            let response = next.run(request).await;
+            let status = response.status().as_u16();
+            let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+            if status >= 500 {
+                tracing::warn!(
+                    target: "somnial::request",
+                    status,
+                    latency_ms,
+                    "{} {} {} {:.1}ms",
+                    method,
+                    path,
+                    status,
+                    latency_ms
+                );
+            } else {
+                tracing::info!(
+                    target: "somnial::request",
+                    status,
+                    latency_ms,
+                    "{} {} {} {:.1}ms",
+                    method,
+                    path,
+                    status,
+                    latency_ms
+                );
+            }
+            response
+        })
+        .instrument(span)
+        .await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID.clone(), value);
+    }
+    response
+}
@@ -0,0 +1,453 @@
+//! How long points are kept. `RETENTION_DAYS` sets a default for every
+//! namespace, admins can override it per namespace (including "forever"), and
+//! a background task deletes whatever has aged out every
+//! `RETENTION_PRUNE_INTERVAL_SECS`. Points are stored a month to a table, so
+//! a month that has expired everywhere is dropped whole; the rest go a few
+//! thousand points at a time so writes can get in between.
+//!
+//! The same task removes whole metrics nobody has written to for
+//! `INACTIVE_EXPIRY_DAYS`, so throwaway experiments don't pile up on shared
+//! instances; a namespace goes with its last metric. For the final
+//! `INACTIVE_GRACE_DAYS` their pages carry a warning and a `Sunset` header.
+//!
+//! With `ARCHIVE_S3_BUCKET` set, points are uploaded to object storage
+//! before they are deleted (see [`archive`]). Rollups of pruned points go
+//! with them, and the task also empties the
+//! trash of deleted metrics whose grace period is over.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::Ordering;
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::sqlite::SqlitePool;
+use tokio::time::{self, MissedTickBehavior};
+
+use crate::{
+    alerts, archive, auth,
+    config::Config,
+    detection, errors,
+    ids::NamespacePath,
+    store::{ListFrom, MetricStore, StoreError},
+    trash, AppState,
+};
+
+const MAX_DAYS: i64 = 36_500;
+const DAY: i64 = 86400;
+/// Most points one pruning statement deletes
+const PRUNE_BATCH: i64 = 5000;
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(json!({ "error": message })))
+}
+
+fn database_error<E: std::fmt::Display>(err: E) -> ApiError {
+    crate::errors::report(&err);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+/// `{"days": 30}`, or `{"days": null}` to keep the namespace's points forever.
+#[derive(Deserialize)]
+pub struct RetentionRequest {
+    days: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct Retention {
+    /// Days points are kept, or `null` for forever
+    days: Option<i64>,
+    /// Whether this is the server default rather than a namespace override
+    default: bool,
+}
+
+/// The namespace's override: `None` when it has none, `Some(None)` when it
+/// keeps points forever.
+async fn load_override(pool: &SqlitePool, namespace: &str) -> Result<Option<Option<i64>>, sqlx::Error> {
+    let row = sqlx::query!("SELECT days FROM namespace_retention WHERE namespace = ?", namespace)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.map(|row| row.days))
+}
+
+pub async fn get_retention(
+    NamespacePath(namespace): NamespacePath,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, ApiError> {
+    let retention = match load_override(&state.pool, &namespace).await.map_err(database_error)? {
+        Some(days) => Retention { days, default: false },
+        None => Retention {
+            days: state.config().retention_days.map(i64::from),
+            default: true,
+        },
+    };
+    Ok(Json(retention))
+}
+
+/// Sets the namespace's own retention. Points older than it go at the next
+/// prune, not immediately.
+pub async fn put_retention(
+    NamespacePath(namespace): NamespacePath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<RetentionRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+    if let Some(days) = request.days
+        && !(1..=MAX_DAYS).contains(&days)
+    {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "days must be between 1 and 36500, or null"));
+    }
+
+    let (pool, namespace, days) = (&state.pool, &namespace, request.days);
+    state
+        .write(|| async move {
+            sqlx::query!(
+                "INSERT INTO namespace_retention (namespace, days) VALUES (?, ?)
+                 ON CONFLICT (namespace) DO UPDATE SET days = excluded.days",
+                namespace,
+                days
+            )
+            .execute(pool)
+            .await
+        })
+        .await
+        .map_err(database_error)?;
+
+    Ok(Json(Retention { days, default: false }))
+}
+
+/// Puts the namespace back on the server default.
+pub async fn delete_retention(
+    NamespacePath(namespace): NamespacePath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    auth::require_admin(&state.config(), &headers)?;
+
+    let (pool, namespace) = (&state.pool, &namespace);
+    state
+        .write(|| async move {
+            sqlx::query!("DELETE FROM namespace_retention WHERE namespace = ?", namespace)
+                .execute(pool)
+                .await
+        })
+        .await
+        .map_err(errors::internal)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Deletes every point older than its namespace's retention and returns how
+/// many went.
+pub async fn prune(state: &AppState) -> Result<u64, StoreError> {
+    let now = Utc::now().timestamp();
+    let pool = &state.pool;
+    let overrides: HashMap<String, Option<i64>> = sqlx::query!("SELECT namespace, days FROM namespace_retention")
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|row| (row.namespace, row.days))
+        .collect();
+    let default_days = state.config().retention_days.map(i64::from);
+
+    let config = state.config();
+    let archive = config.archive.as_ref();
+    let through = match archive {
+        Some(_) => state.store.watermark().await?,
+        None => i64::MAX,
+    };
+
+    // Cutoffs of the namespaces whose expired points may go, which are all
+    // of them unless archiving some failed
+    let mut cutoffs = HashMap::new();
+    for namespace in state.store.namespaces().await? {
+        let days = match overrides.get(&namespace) {
+            Some(days) => *days,
+            None => default_days,
+        };
+        let Some(days) = days else { continue };
+        let cutoff = now - days * DAY;
+        if let Some(archive) = archive {
+            let result = archive::archive_namespace(state.store.as_ref(), archive, &namespace, cutoff, through).await;
+            if !archived(state, &namespace, result) {
+                continue;
+            }
+        }
+        cutoffs.insert(namespace, cutoff);
+    }
+
+    // Whole months go at once where the store can drop them, and what's
+    // left a batch at a time
+    let cutoffs = &cutoffs;
+    let mut deleted = state.write_store(|| state.store.drop_expired(cutoffs, through)).await?;
+    for (namespace, &cutoff) in cutoffs {
+        let batched = in_batches(state, || state.store.prune(namespace, cutoff, through, PRUNE_BATCH)).await?;
+        *deleted.entry(namespace.clone()).or_insert(0) += batched;
+    }
+
+    let mut pruned = 0;
+    for (namespace, count) in deleted.into_iter().filter(|(_, count)| *count > 0) {
+        let Some(&cutoff) = cutoffs.get(&namespace) else { continue };
+        let namespace = &namespace;
+        state
+            .write(|| async move {
+                sqlx::query!(
+                    "DELETE FROM metric_rollups WHERE namespace = ? AND bucket + resolution <= ?",
+                    namespace,
+                    cutoff
+                )
+                .execute(pool)
+                .await
+            })
+            .await?;
+        state.invalidate_namespace(namespace);
+        pruned += count;
+    }
+
+    state.metrics.retention_pruned.fetch_add(pruned, Ordering::Relaxed);
+    Ok(pruned)
+}
+
+/// Counts an archive upload, or logs its failure; the points may only be
+/// deleted when this is true.
+fn archived(state: &AppState, namespace: &str, result: Result<u64, archive::ArchiveError>) -> bool {
+    match result {
+        Ok(points) => {
+            state.metrics.archived_points.fetch_add(points, Ordering::Relaxed);
+            true
+        }
+        Err(err) => {
+            tracing::warn!("Keeping expired points in {}, as archiving them failed: {}", namespace, err);
+            false
+        }
+    }
+}
+
+/// Repeats a `PRUNE_BATCH` delete until it comes up short, each batch on
+/// its own so ingest isn't locked out behind one giant delete, and returns
+/// the total removed.
+async fn in_batches<F, Fut>(state: &AppState, mut delete: F) -> Result<u64, StoreError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<u64, StoreError>>,
+{
+    let mut total = 0;
+    loop {
+        let deleted = state.write_store(&mut delete).await?;
+        total += deleted;
+        if deleted < PRUNE_BATCH as u64 {
+            return Ok(total);
+        }
+    }
+}
+
+/// When a metric last written at `last_write` will be deleted for
+/// inactivity, once that is within the grace period.
+pub fn expiry_warning(config: &Config, last_write: i64) -> Option<i64> {
+    let expires_at = last_write + i64::from(config.inactive_expiry_days?) * DAY;
+    let warn_from = expires_at - i64::from(config.inactive_grace_days) * DAY;
+    (Utc::now().timestamp() >= warn_from).then_some(expires_at)
+}
+
+/// Announces a pending deletion in the `Sunset` header (RFC 8594).
+pub fn insert_sunset(headers: &mut HeaderMap, expires_at: i64) {
+    let Some(time) = DateTime::from_timestamp(expires_at, 0) else {
+        return;
+    };
+    if let Ok(value) = time.format("%a, %d %b %Y %H:%M:%S GMT").to_string().parse() {
+        headers.insert("sunset", value);
+    }
+}
+
+/// The timestamp of a series' newest point, which stands in for its last
+/// write.
+pub async fn last_write(store: &dyn MetricStore, namespace: &str, id: &str) -> Result<Option<i64>, StoreError> {
+    Ok(store.latest(namespace, id).await?.map(|point| point.timestamp))
+}
+
+/// Deletes metrics whose newest point is older than `INACTIVE_EXPIRY_DAYS`,
+/// along with their settings, and returns how many went.
+pub async fn prune_inactive(state: &AppState) -> Result<u64, StoreError> {
+    let Some(days) = state.config().inactive_expiry_days else {
+        return Ok(0);
+    };
+    let cutoff = Utc::now().timestamp() - i64::from(days) * DAY;
+    let pool = &state.pool;
+    let mut inactive = Vec::new();
+    for namespace in state.store.namespaces().await? {
+        for metric in state.store.list(&namespace, ListFrom::Start, i64::MAX).await? {
+            if metric.last_timestamp.is_some_and(|last| last < cutoff) {
+                inactive.push((namespace.clone(), metric.id));
+            }
+        }
+    }
+
+    let mut expired = 0;
+    for (namespace, id) in &inactive {
+        // A write that landed since the scan keeps the metric alive
+        let deleted = state.write_store(|| state.store.expire(namespace, id, cutoff)).await?;
+        if deleted == 0 {
+            continue;
+        }
+        state
+            .write(|| async move {
+                let mut tx = pool.begin().await?;
+                sqlx::query!("DELETE FROM metric_meta WHERE namespace = ? AND id = ?", namespace, id)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query!("DELETE FROM metric_precision WHERE namespace = ? AND id = ?", namespace, id)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query!("DELETE FROM metric_rollups WHERE namespace = ? AND id = ?", namespace, id)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query!("DELETE FROM metric_point_caps WHERE namespace = ? AND id = ?", namespace, id)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query!("DELETE FROM github_baselines WHERE namespace = ? AND id = ?", namespace, id)
+                    .execute(&mut *tx)
+                    .await?;
+                tx.commit().await
+            })
+            .await?;
+        state.invalidate_series(namespace, id);
+        expired += 1;
+    }
+
+    state.metrics.inactive_expired.fetch_add(expired, Ordering::Relaxed);
+    Ok(expired)
+}
+
+/// Prunes on startup and then every configured interval, for as long as the
+/// server runs.
+pub fn spawn(state: AppState) {
+    tokio::spawn(async move {
+        let mut ticks = time::interval(state.config().retention_interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            match prune(&state).await {
+                Ok(0) => {}
+                Ok(pruned) => tracing::info!("Pruned {} points past their retention", pruned),
+                Err(err) => tracing::warn!("Retention pruning failed: {}", err),
+            }
+            match prune_inactive(&state).await {
+                Ok(0) => {}
+                Ok(expired) => tracing::info!("Deleted {} metrics with no recent writes", expired),
+                Err(err) => tracing::warn!("Expiring inactive metrics failed: {}", err),
+            }
+            match trash::empty(&state).await {
+                Ok(0) => {}
+                Ok(purged) => tracing::info!("Purged {} deleted metrics past their grace period", purged),
+                Err(err) => tracing::warn!("Emptying the trash failed: {}", err),
+            }
+            if let Err(err) = alerts::prune_events(&state).await {
+                tracing::warn!("Pruning old alert events failed: {}", err);
+            }
+            if let Err(err) = detection::prune(&state).await {
+                tracing::warn!("Pruning anomalies failed: {}", err);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Method};
+
+    use super::*;
+    use crate::test::TestServer;
+
+    const ADMIN_TOKEN: &str = "secret";
+
+    async fn server() -> TestServer {
+        TestServer::with_config(Config {
+            admin_token: Some(ADMIN_TOKEN.to_string()),
+            retention_days: Some(7),
+            ..Default::default()
+        })
+        .await
+    }
+
+    fn put_retention(days: Value) -> axum::http::Request<Body> {
+        axum::http::Request::builder()
+            .method(Method::PUT)
+            .uri("/api/v1/namespaces/ci/retention")
+            .header("authorization", format!("Bearer {}", ADMIN_TOKEN))
+            .header("content-type", "application/json")
+            .body(Body::from(json!({ "days": days }).to_string()))
+            .unwrap()
+    }
+
+    async fn timestamps(server: &TestServer, namespace: &str) -> Vec<i64> {
+        let points = server.store().range(namespace, "build_time", i64::MIN, i64::MAX, i64::MAX).await.unwrap();
+        points.iter().map(|point| point.timestamp).collect()
+    }
+
+    #[tokio::test]
+    async fn prune_keeps_each_namespace_for_its_own_days() {
+        let server = server().await;
+        let now = Utc::now().timestamp();
+        let (old, older) = (now - 10 * DAY, now - 40 * DAY);
+        for namespace in ["ci", "perf", "forever"] {
+            server.seed(namespace, "build_time", &[(older, 1.0), (old, 2.0), (now, 3.0)]).await;
+        }
+        assert_eq!(server.request(put_retention(json!(30))).await.status, 200);
+        sqlx::query("INSERT INTO namespace_retention (namespace, days) VALUES ('forever', NULL)")
+            .execute(server.pool())
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO metric_rollups VALUES ('ci', 'build_time', 3600, ?, 1, 1.0, 1.0, 1.0)")
+            .bind(older)
+            .execute(server.pool())
+            .await
+            .unwrap();
+
+        assert_eq!(prune(server.app_state()).await.unwrap(), 3);
+        assert_eq!(timestamps(&server, "ci").await, [old, now]);
+        assert_eq!(timestamps(&server, "perf").await, [now]);
+        assert_eq!(timestamps(&server, "forever").await, [older, old, now]);
+        let rollups: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM metric_rollups")
+            .fetch_one(server.pool())
+            .await
+            .unwrap();
+        assert_eq!(rollups, 0);
+        assert_eq!(prune(server.app_state()).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn overrides_fall_back_to_the_default_and_need_the_admin_token() {
+        let server = server().await;
+        let retention: Value = server.get("/api/v1/namespaces/ci/retention").await.json();
+        assert_eq!(retention, json!({ "days": 7, "default": true }));
+
+        assert_eq!(server.request(put_retention(json!(0))).await.status, 422);
+        let mut anonymous = put_retention(json!(30));
+        anonymous.headers_mut().remove("authorization");
+        assert_eq!(server.request(anonymous).await.status, 401);
+
+        assert_eq!(server.request(put_retention(Value::Null)).await.status, 200);
+        let retention: Value = server.get("/api/v1/namespaces/ci/retention").await.json();
+        assert_eq!(retention, json!({ "days": null, "default": false }));
+
+        let delete = axum::http::Request::builder()
+            .method(Method::DELETE)
+            .uri("/api/v1/namespaces/ci/retention")
+            .header("authorization", format!("Bearer {}", ADMIN_TOKEN))
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(server.request(delete).await.status, 204);
+        let retention: Value = server.get("/api/v1/namespaces/ci/retention").await.json();
+        assert_eq!(retention["default"], true);
+    }
+}
@@ -0,0 +1,326 @@
+//! Downsampling for old data. A background job keeps hourly and daily
+//! aggregates of every closed bucket, and chart reads swap raw points for
+//! bucket averages once they are older than the policy's thresholds, so a
+//! year of per-minute samples loads as a few thousand points.
+//!
+//! Rollups sit alongside the raw points rather than replacing them: exports,
+//! stats and badges still see every point, and retention prunes both.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::Utc;
+use sqlx::{sqlite::SqlitePool, Sqlite, Transaction};
+use tokio::time::{self, MissedTickBehavior};
+
+use crate::{
+    limits::{self, ReadError},
+    store::{Bucket, BucketScope, StoreError},
+    AppState, MetricPoint,
+};
+
+pub const HOUR: i64 = 3600;
+pub const DAY: i64 = 86400;
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+/// Buckets are only rolled up this long after they close, so a write that
+/// took its timestamp just before the boundary has landed.
+const SETTLE_SECONDS: i64 = 60;
+/// Buckets of each series one refresh step covers.
+const REFRESH_BUCKETS: i64 = 1000;
+
+/// How old points must be before charts read them from rollups.
+#[derive(Clone, Debug)]
+pub struct RollupPolicy {
+    /// Age past which hourly averages replace raw points; `None` never does
+    pub hourly_after: Option<Duration>,
+    /// Age past which daily averages replace hourly ones; `None` never does
+    pub daily_after: Option<Duration>,
+}
+
+impl Default for RollupPolicy {
+    fn default() -> Self {
+        RollupPolicy {
+            hourly_after: Some(Duration::from_secs(7 * DAY as u64)),
+            daily_after: Some(Duration::from_secs(90 * DAY as u64)),
+        }
+    }
+}
+
+impl RollupPolicy {
+    /// `(resolution, age)` for each enabled tier, coarsest first.
+    fn tiers(&self) -> Vec<(i64, i64)> {
+        [(DAY, self.daily_after), (HOUR, self.hourly_after)]
+            .into_iter()
+            .filter_map(|(resolution, after)| Some((resolution, after?.as_secs() as i64)))
+            .collect()
+    }
+}
+
+async fn load_progress(pool: &SqlitePool) -> Result<HashMap<i64, i64>, sqlx::Error> {
+    let rows = sqlx::query!("SELECT resolution, through FROM rollup_progress")
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.into_iter().map(|row| (row.resolution, row.through)).collect())
+}
+
+/// A series between `since` and `until` inclusive: bucket averages for the
+/// parts old enough to have been rolled up, raw points for the rest. More
+/// than `MAX_QUERY_POINTS` of them is an error.
+pub async fn load_points(
+    state: &AppState,
+    namespace: &str,
+    id: &str,
+    since: i64,
+    until: i64,
+) -> Result<Vec<MetricPoint>, ReadError> {
+    let max_points = state.config().limits.max_query_points;
+    let fetch_limit = limits::fetch_limit(max_points);
+    let now = Utc::now().timestamp();
+    let tiers = state.config().rollups.tiers();
+    let progress = if tiers.is_empty() {
+        HashMap::new()
+    } else {
+        load_progress(&state.pool).await?
+    };
+
+    let mut points = Vec::new();
+    let mut from = since;
+    for (resolution, after) in tiers {
+        let Some(&through) = progress.get(&resolution) else {
+            continue;
+        };
+        // Whole buckets old enough for this tier that the job has reached
+        let cut = (now - after).div_euclid(resolution) * resolution;
+        let cut = cut.min(through).min(until.saturating_add(1));
+        if cut <= from {
+            continue;
+        }
+        let remaining = fetch_limit - points.len() as i64;
+        let rows = sqlx::query!(
+            r#"SELECT bucket, sum / count as "value!: f64" FROM metric_rollups
+               WHERE namespace = ? AND id = ? AND resolution = ? AND bucket >= ? AND bucket < ?
+               ORDER BY bucket ASC LIMIT ?"#,
+            namespace,
+            id,
+            resolution,
+            from,
+            cut,
+            remaining
+        )
+        .fetch_all(&state.pool)
+        .await?;
+        points.extend(rows.into_iter().map(|row| MetricPoint {
+            timestamp: row.bucket,
+            value: row.value,
+            sha: None,
+            branch: None,
+        }));
+        from = cut;
+    }
+
+    let remaining = fetch_limit - points.len() as i64;
+    if remaining > 0 {
+        points.extend(state.store.range(namespace, id, from, until, remaining).await?);
+    }
+    limits::within(points, max_points)
+}
+
+async fn insert(tx: &mut Transaction<'_, Sqlite>, resolution: i64, bucket: &Bucket) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT OR REPLACE INTO metric_rollups (namespace, id, resolution, bucket, count, sum, min, max)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        bucket.namespace,
+        bucket.id,
+        resolution,
+        bucket.bucket,
+        bucket.count,
+        bucket.sum,
+        bucket.min,
+        bucket.max
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Stores rolled-up buckets of `resolution`, replacing any already there,
+/// and moves the job's progress to `through` with them if given.
+async fn save(state: &AppState, resolution: i64, buckets: &[Bucket], through: Option<i64>) -> Result<(), sqlx::Error> {
+    let pool = &state.pool;
+    state
+        .write(|| async move {
+            let mut tx = pool.begin().await?;
+            for bucket in buckets {
+                insert(&mut tx, resolution, bucket).await?;
+            }
+            if let Some(through) = through {
+                sqlx::query!(
+                    "INSERT INTO rollup_progress (resolution, through) VALUES (?, ?)
+                     ON CONFLICT (resolution) DO UPDATE SET through = excluded.through",
+                    resolution,
+                    through
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            tx.commit().await
+        })
+        .await
+}
+
+/// Rolls up every bucket that has closed since the last run.
+pub async fn refresh(state: &AppState) -> Result<(), StoreError> {
+    let progress = load_progress(&state.pool).await?;
+    let settled = Utc::now().timestamp() - SETTLE_SECONDS;
+
+    for (resolution, _) in state.config().rollups.tiers() {
+        let end = settled.div_euclid(resolution) * resolution;
+        let mut start = match progress.get(&resolution) {
+            Some(&through) => through,
+            None => {
+                // Nothing stored yet; start from the first point once there is one
+                let Some(first) = state.store.first_timestamp().await? else { continue };
+                first.div_euclid(resolution) * resolution
+            }
+        };
+        // A window at a time, so a first run over years of points doesn't
+        // hold all their buckets at once
+        while start < end {
+            let through = start.saturating_add(resolution * REFRESH_BUCKETS).min(end);
+            let buckets = state.store.buckets(BucketScope::All, resolution, start, through).await?;
+            save(state, resolution, &buckets, Some(through)).await?;
+            start = through;
+        }
+    }
+    Ok(())
+}
+
+/// Rolls up a namespace's points in buckets the job has already passed, for
+/// data written with old timestamps such as an imported bundle.
+pub async fn rebuild_namespace(state: &AppState, namespace: &str) -> Result<(), StoreError> {
+    for (resolution, through) in load_progress(&state.pool).await? {
+        let scope = BucketScope::Namespace(namespace);
+        let buckets = state.store.buckets(scope, resolution, i64::MIN, through).await?;
+        save(state, resolution, &buckets, None).await?;
+    }
+    Ok(())
+}
+
+/// Recomputes the rolled-up buckets of one series that cover `timestamps`,
+/// after points in them were corrected or removed.
+pub async fn rebuild_buckets(state: &AppState, namespace: &str, id: &str, timestamps: &[i64]) -> Result<(), StoreError> {
+    let pool = &state.pool;
+    for (resolution, through) in load_progress(pool).await? {
+        let mut buckets: Vec<i64> = timestamps
+            .iter()
+            .map(|timestamp| timestamp.div_euclid(resolution) * resolution)
+            .filter(|&bucket| bucket < through)
+            .collect();
+        buckets.dedup();
+        for bucket in buckets {
+            let scope = BucketScope::Series(namespace, id);
+            let rolled = state.store.buckets(scope, resolution, bucket, bucket + resolution).await?;
+            let rolled = &rolled;
+            state
+                .write(|| async move {
+                    let mut tx = pool.begin().await?;
+                    // A bucket left with no points goes, rather than keeping its old figures
+                    sqlx::query!(
+                        "DELETE FROM metric_rollups WHERE namespace = ? AND id = ? AND resolution = ? AND bucket = ?",
+                        namespace,
+                        id,
+                        resolution,
+                        bucket
+                    )
+                    .execute(&mut *tx)
+                    .await?;
+                    for rolled in rolled {
+                        insert(&mut tx, resolution, rolled).await?;
+                    }
+                    tx.commit().await
+                })
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Refreshes rollups on startup and every few minutes after.
+pub fn spawn(state: AppState) {
+    if state.config().rollups.tiers().is_empty() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut ticks = time::interval(REFRESH_INTERVAL);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            if let Err(err) = refresh(&state).await {
+                tracing::warn!("Refreshing rollups failed: {}", err);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, test::TestServer};
+
+    async fn server() -> TestServer {
+        TestServer::with_config(Config {
+            rollups: RollupPolicy {
+                hourly_after: Some(Duration::from_secs(DAY as u64)),
+                daily_after: None,
+            },
+            ..Default::default()
+        })
+        .await
+    }
+
+    fn values(points: &[MetricPoint]) -> Vec<(i64, f64)> {
+        points.iter().map(|point| (point.timestamp, point.value)).collect()
+    }
+
+    #[tokio::test]
+    async fn charts_read_old_points_as_hourly_averages() {
+        let server = server().await;
+        let state = server.app_state();
+        let now = Utc::now().timestamp();
+        let hour = (now - 3 * DAY).div_euclid(HOUR) * HOUR;
+        let points = [(hour, 1.0), (hour + 60, 3.0), (hour + HOUR, 5.0), (now - 60, 7.0)];
+        server.seed("ci", "build_time", &points).await;
+
+        // Before the job has run, every point is read raw
+        assert_eq!(values(&load_points(state, "ci", "build_time", hour, now).await.unwrap()).len(), 4);
+        refresh(state).await.unwrap();
+        let read = load_points(state, "ci", "build_time", hour, now).await.unwrap();
+        assert_eq!(values(&read), [(hour, 2.0), (hour + HOUR, 5.0), (now - 60, 7.0)]);
+        // Exports and the like still see every point
+        assert_eq!(state.store.range("ci", "build_time", hour, now, i64::MAX).await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn rebuilding_follows_removed_and_backfilled_points() {
+        let server = server().await;
+        let state = server.app_state();
+        let now = Utc::now().timestamp();
+        let hour = (now - 3 * DAY).div_euclid(HOUR) * HOUR;
+        server.seed("ci", "build_time", &[(hour, 1.0), (hour + 60, 3.0), (hour + HOUR, 5.0), (now, 7.0)]).await;
+        refresh(state).await.unwrap();
+
+        // A bucket left without points goes
+        let trimmed = state.store.trim("ci", "build_time", 2).await.unwrap();
+        rebuild_buckets(state, "ci", "build_time", &trimmed).await.unwrap();
+        let read = load_points(state, "ci", "build_time", hour, now).await.unwrap();
+        assert_eq!(values(&read), [(hour + HOUR, 5.0), (now, 7.0)]);
+
+        // Points written behind the job are rolled up on request
+        server.seed("perf", "build_time", &[(hour, 4.0), (hour + 60, 6.0)]).await;
+        assert_eq!(load_points(state, "perf", "build_time", hour, now).await.unwrap().len(), 0);
+        rebuild_namespace(state, "perf").await.unwrap();
+        let read = load_points(state, "perf", "build_time", hour, now).await.unwrap();
+        assert_eq!(values(&read), [(hour, 5.0)]);
+    }
+}
@@ -0,0 +1,276 @@
+//! Process-level plumbing for running outside containers and systemd: pid
+//! files, size-rotated log files and detaching from the terminal.
+//!
+//! The server always runs in the foreground, which is what launchd, NSSM and
+//! systemd expect. `--detach` is for plain shell setups: it re-launches the
+//! binary as a background process and exits once the child has started.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{
+    filter::{filter_fn, FilterExt},
+    fmt::writer::BoxMakeWriter,
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    EnvFilter, Layer,
+};
+
+use crate::config::{Sources, TraceExport};
+use crate::traces;
+
+/// Writes the current process id on creation and removes the file on drop.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        fs::write(path, format!("{}\n", std::process::id()))?;
+        Ok(PidFile {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// A listening Unix socket's file, removed on drop.
+#[cfg(unix)]
+pub struct UnixSocket {
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+impl UnixSocket {
+    /// Listens on `path`, replacing a socket file left behind by a server
+    /// that didn't shut down cleanly, with its permissions set to `mode`.
+    pub fn bind(path: &Path, mode: Option<u32>) -> io::Result<(Self, tokio::net::UnixListener)> {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and isn't a socket", path.display()),
+                ));
+            }
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("another server is listening on {}", path.display()),
+                ));
+            }
+            fs::remove_file(path)?;
+        }
+        let listener = tokio::net::UnixListener::bind(path)?;
+        let socket = UnixSocket {
+            path: path.to_path_buf(),
+        };
+        if let Some(mode) = mode {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        }
+        Ok((socket, listener))
+    }
+}
+
+#[cfg(unix)]
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// An append-only log file that rolls over once it exceeds `max_bytes`,
+/// keeping `keep` older generations as `<path>.1` (newest) to `<path>.<keep>`.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: File,
+    written: u64,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            max_bytes,
+            keep,
+            file,
+            written,
+        })
+    }
+
+    fn generation(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.generation(self.keep));
+            for n in (1..self.keep).rev() {
+                let _ = fs::rename(self.generation(n), self.generation(n + 1));
+            }
+            fs::rename(&self.path, self.generation(1))?;
+            self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        }
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.max_bytes > 0 && self.written > 0 && self.written + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// How log lines are written: `pretty` for people, `json` for log
+/// collectors, one object per line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    Pretty,
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        match value {
+            "pretty" | "text" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("LOG_FORMAT must be pretty or json, not {:?}", other)),
+        }
+    }
+}
+
+/// Where log lines go.
+pub enum LogOutput {
+    Stdout,
+    /// For commands whose output is on stdout
+    Stderr,
+    File(RotatingFile),
+}
+
+/// Installs the global `tracing` subscriber, which takes the `log` records
+/// of dependencies too. Levels come from `RUST_LOG`, a default level such as
+/// `info` or `debug` optionally followed by per-target ones like
+/// `sqlx=warn`, and default to `info`; `LOG_FORMAT` picks `pretty` lines,
+/// the default, or `json`. Either can be set in the config file too. Lines
+/// logged while handling a request carry its span's fields, such as the
+/// request id. With `trace_export`, the spans [`traces`](crate::traces)
+/// starts are exported there too, and kept out of the log lines.
+pub fn init_logging(
+    output: LogOutput,
+    sources: &Sources,
+    trace_export: Option<&TraceExport>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let directives = sources.var("RUST_LOG").unwrap_or_default();
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .parse(&directives)
+        .map_err(|err| format!("RUST_LOG can't be {:?}: {}", directives, err))?;
+    let format = match sources.var("LOG_FORMAT") {
+        Ok(format) => format.parse()?,
+        Err(_) => LogFormat::Pretty,
+    };
+
+    let ansi = matches!(output, LogOutput::Stdout) && io::stdout().is_terminal();
+    let writer = match output {
+        LogOutput::Stdout => BoxMakeWriter::new(io::stdout),
+        LogOutput::Stderr => BoxMakeWriter::new(io::stderr),
+        LogOutput::File(file) => BoxMakeWriter::new(Mutex::new(file)),
+    };
+    let lines = tracing_subscriber::fmt::layer().with_writer(writer).with_ansi(ansi);
+    let lines = match format {
+        LogFormat::Pretty => lines.with_target(false).boxed(),
+        LogFormat::Json => lines.json().flatten_event(true).with_current_span(true).with_span_list(false).boxed(),
+    };
+    let traces = trace_export.map(traces::layer).transpose()?;
+    let lines = lines.with_filter(filter.and(filter_fn(|metadata| !traces::is_trace_span(metadata))));
+    tracing_subscriber::registry().with(traces).with(lines).try_init()?;
+    Ok(())
+}
+
+/// Resolves once the process is asked to stop, by SIGTERM from a container
+/// runtime or systemd, or by ctrl-c.
+pub async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::warn!("Couldn't listen for ctrl-c: {}", err);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                tracing::warn!("Couldn't listen for SIGTERM: {}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+}
+
+/// Re-launches this binary in the background with `args` and returns the
+/// child's process id. The child is detached from the terminal's process
+/// group (or console, on Windows) so closing the shell doesn't stop it.
+pub fn spawn_detached(args: &[String]) -> io::Result<u32> {
+    let mut command = Command::new(std::env::current_exe()?);
+    command
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const DETACHED_PROCESS: u32 = 0x0000_0008;
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+        command.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+    }
+
+    Ok(command.spawn()?.id())
+}
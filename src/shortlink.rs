@@ -0,0 +1,111 @@
+use axum::{
+    extract::{Extension, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Redirect, Response},
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+
+use crate::{auth, domains::Domain, errors, snapshot, theme::ViewerTheme, tz::ViewerTz, AppState, BasePath};
+
+const CODE_LENGTH: usize = 7;
+const MAX_TARGET_LENGTH: usize = 2048;
+
+#[derive(Deserialize)]
+pub struct CreateShortLink {
+    target: String,
+}
+
+#[derive(Serialize)]
+pub struct ShortLink {
+    code: String,
+    path: String,
+}
+
+/// Only paths on this server can be shortened, so `/s/...` can never be
+/// used as an open redirect.
+fn is_local_path(target: &str) -> bool {
+    target.starts_with('/')
+        && !target.starts_with("//")
+        && !target.contains('\\')
+        && !target.chars().any(char::is_control)
+}
+
+pub async fn create_short_link(
+    State(state): State<AppState>,
+    Json(request): Json<CreateShortLink>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let target = request.target;
+    if !is_local_path(&target) || target.len() > MAX_TARGET_LENGTH {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    // Shortening the same URL twice hands back the same code
+    let existing = sqlx::query!("SELECT code FROM short_links WHERE target = ?", target)
+        .fetch_optional(&state.pool)
+        .await
+        .map_err(errors::internal)?;
+
+    let code = match existing {
+        Some(row) => row.code,
+        None => {
+            let created_at = Utc::now().timestamp();
+            let mut attempts = 0;
+            loop {
+                let code = auth::random_string(CODE_LENGTH);
+
+                let (pool, code_ref, target) = (&state.pool, &code, &target);
+                let result = state
+                    .write(|| async move {
+                        sqlx::query!(
+                            "INSERT INTO short_links (code, target, created_at) VALUES (?, ?, ?)",
+                            code_ref,
+                            target,
+                            created_at
+                        )
+                        .execute(pool)
+                        .await
+                    })
+                    .await;
+
+                match result {
+                    Ok(_) => break code,
+                    Err(sqlx::Error::Database(err)) if err.is_unique_violation() && attempts < 3 => {
+                        attempts += 1;
+                    }
+                    Err(err) => return Err(errors::internal(err)),
+                }
+            }
+        }
+    };
+
+    Ok(Json(ShortLink {
+        path: format!("{}/s/{}", state.base_path(), code),
+        code,
+    }))
+}
+
+/// Redirects a short link, or shows the snapshot with that token.
+pub async fn follow_short_link(
+    Path(code): Path<String>,
+    State(pool): State<SqlitePool>,
+    State(base_path): State<BasePath>,
+    domain: Option<Extension<Domain>>,
+    ViewerTheme(theme): ViewerTheme,
+    tz: ViewerTz,
+) -> Result<Response, StatusCode> {
+    let row = sqlx::query!("SELECT target FROM short_links WHERE code = ?", code)
+        .fetch_optional(&pool)
+        .await
+        .map_err(errors::internal)?;
+    if let Some(row) = row {
+        return Ok(Redirect::permanent(&row.target).into_response());
+    }
+
+    snapshot::render(&pool, base_path, &code, domain.as_deref(), theme, &tz)
+        .await?
+        .map(IntoResponse::into_response)
+        .ok_or(StatusCode::NOT_FOUND)
+}
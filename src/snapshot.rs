@@ -0,0 +1,185 @@
+//! Frozen charts: `POST /{namespace}/{id}/snapshot` copies the series as the
+//! chart page would show it, and `/s/{token}` keeps showing exactly that copy
+//! however much data arrives afterwards, for "before my optimization" links.
+//!
+//! Snapshots share `/s/` with short links; their tokens are longer than
+//! short link codes, so the two can't collide.
+
+use askama::Template;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse},
+    Json,
+};
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::{json, Value};
+use sqlx::sqlite::SqlitePool;
+
+use crate::{
+    auth,
+    domains::Domain,
+    errors,
+    ids::SeriesPath,
+    limits::ReadError,
+    load_window_json,
+    meta::{self, ChartType, Scale},
+    theme::PageTheme,
+    traces,
+    tz::ViewerTz,
+    AppState, BasePath, ChartPageQuery,
+};
+
+const TOKEN_LENGTH: usize = 16;
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(json!({ "error": message })))
+}
+
+fn database_error<E: std::fmt::Display>(err: E) -> ApiError {
+    crate::errors::report(&err);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+#[derive(Serialize)]
+struct CreatedSnapshot {
+    token: String,
+    path: String,
+    namespace: String,
+    id: String,
+    points: usize,
+    created_at: i64,
+}
+
+#[derive(Template)]
+#[template(path = "snapshot.html")]
+struct SnapshotTemplate {
+    base_path: BasePath,
+    namespace: String,
+    id: String,
+    data_json: String,
+    point_count: usize,
+    /// When the snapshot was taken, in the viewer's timezone
+    taken: String,
+    scale: &'static str,
+    chart_type: &'static str,
+    /// Site title when served from a custom domain
+    brand: Option<String>,
+    theme: &'static str,
+    /// Minutes east of UTC to draw the axis in, or `null` for the browser's zone
+    tz_json: String,
+    tz_label: String,
+}
+
+/// Freezes the series, honouring the chart page's `?since=`, `?until=`,
+/// `?scale=` and `?type=`, and answers with the snapshot's path.
+pub async fn post_snapshot(
+    SeriesPath(namespace, id): SeriesPath,
+    Query(view): Query<ChartPageQuery>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, ApiError> {
+    let bounds = view.resolve().map_err(|(status, message)| error(status, message))?;
+    let points = load_window_json(&state, &namespace, &id, bounds)
+        .await
+        .map_err(ReadError::api)?;
+    let point_count = serde_json::from_str::<Vec<Value>>(&points).map_err(database_error)?.len();
+    if point_count == 0 {
+        return Err(error(StatusCode::NOT_FOUND, "no points to snapshot"));
+    }
+    let meta = meta::load(&state.pool, &namespace, &id).await.map_err(database_error)?;
+    let scale = view.scale.or(meta.scale).unwrap_or_default().as_str();
+    let chart_type = view.chart_type.or(meta.chart_type).unwrap_or_default().as_str();
+    let created_at = Utc::now().timestamp();
+
+    let mut attempts = 0;
+    let token = loop {
+        let token = auth::random_string(TOKEN_LENGTH);
+        let (pool, token_ref, namespace, id, points) = (&state.pool, &token, &namespace, &id, &*points);
+        let result = state
+            .write(|| async move {
+                sqlx::query!(
+                    "INSERT INTO snapshots (token, namespace, id, points, scale, chart_type, created_at)
+                     VALUES (?, ?, ?, ?, ?, ?, ?)",
+                    token_ref,
+                    namespace,
+                    id,
+                    points,
+                    scale,
+                    chart_type,
+                    created_at
+                )
+                .execute(pool)
+                .await
+            })
+            .await;
+
+        match result {
+            Ok(_) => break token,
+            Err(sqlx::Error::Database(err)) if err.is_unique_violation() && attempts < 3 => {
+                attempts += 1;
+            }
+            Err(err) => return Err(database_error(err)),
+        }
+    };
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedSnapshot {
+            path: format!("{}/s/{}", state.base_path(), token),
+            token,
+            namespace,
+            id,
+            points: point_count,
+            created_at,
+        }),
+    ))
+}
+
+/// The page for snapshot `token`, or `None` if there is no such snapshot.
+pub async fn render(
+    pool: &SqlitePool,
+    base_path: BasePath,
+    token: &str,
+    domain: Option<&Domain>,
+    theme: PageTheme,
+    tz: &ViewerTz,
+) -> Result<Option<Html<String>>, StatusCode> {
+    let Some(row) = sqlx::query!(
+        "SELECT namespace, id, points, scale, chart_type, created_at FROM snapshots WHERE token = ?",
+        token
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(errors::internal)?
+    else {
+        return Ok(None);
+    };
+    // A custom domain only shows snapshots of its own namespace
+    if domain.is_some_and(|domain| domain.namespace != row.namespace) {
+        return Ok(None);
+    }
+
+    let point_count = serde_json::from_str::<Vec<Value>>(&row.points)
+        .map_err(errors::internal)?
+        .len();
+    let template = SnapshotTemplate {
+        base_path,
+        namespace: row.namespace,
+        id: row.id,
+        data_json: row.points,
+        point_count,
+        taken: tz.format_timestamp(row.created_at).unwrap_or_default(),
+        scale: Scale::parse(&row.scale).unwrap_or_default().as_str(),
+        chart_type: ChartType::parse(&row.chart_type).unwrap_or_default().as_str(),
+        brand: domain.map(|d| d.title.clone().unwrap_or_else(|| d.namespace.clone())),
+        theme: theme.as_str(),
+        tz_json: tz.script_json(),
+        tz_label: tz.label_or("your browser's timezone"),
+    };
+    traces::render(&template)
+        .map(|html| Some(Html(html)))
+        .map_err(errors::internal)
+}
@@ -0,0 +1,341 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sqlx::sqlite::SqlitePool;
+
+use crate::{
+    badge::RenderedBadge,
+    cache::{SeriesCache, Weighted},
+    AppState,
+};
+
+/// Upper bounds of the latency histograms' buckets, in seconds
+const LATENCY_BUCKETS: [f64; 12] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+/// Counters describing the server's own behaviour, exposed in Prometheus
+/// text format at `/internal/metrics` once `ENABLED_FEATURES` includes
+/// `self-metrics`.
+#[derive(Default)]
+pub struct SelfMetrics {
+    pub db_busy_retries: AtomicU64,
+    pub db_busy_failures: AtomicU64,
+    pub ingest_accepted: AtomicU64,
+    pub ingest_rejected: AtomicU64,
+    pub ingest_points: AtomicU64,
+    pub retention_pruned: AtomicU64,
+    pub archived_points: AtomicU64,
+    pub inactive_expired: AtomicU64,
+    pub maintenance_reclaimed_bytes: AtomicU64,
+    pub alert_notifications: AtomicU64,
+    pub alert_notification_failures: AtomicU64,
+    pub webhook_deliveries: AtomicU64,
+    pub webhook_delivery_failures: AtomicU64,
+    pub webhook_points_dropped: AtomicU64,
+    pub github_statuses: AtomicU64,
+    pub github_status_failures: AtomicU64,
+    pub digest_deliveries: AtomicU64,
+    pub digest_delivery_failures: AtomicU64,
+    pub anomalies_detected: AtomicU64,
+    pub badge_render: Histogram,
+    /// Responses by route template, method and status
+    requests: Mutex<BTreeMap<(String, String, u16), u64>>,
+    request_duration: Histogram,
+}
+
+/// Observations counted into [`LATENCY_BUCKETS`], with one more bucket for
+/// anything slower.
+#[derive(Default)]
+pub struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let bucket = LATENCY_BUCKETS.partition_point(|&bound| bound < seconds);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+impl SelfMetrics {
+    fn render(
+        &self,
+        chart_cache: &SeriesCache,
+        badge_cache: &SeriesCache<RenderedBadge>,
+        pool: &SqlitePool,
+    ) -> String {
+        let mut out = String::new();
+        requests(&mut out, &self.requests.lock().unwrap());
+        histogram(
+            &mut out,
+            "somnial_http_request_duration_seconds",
+            "Time taken to start answering requests",
+            &self.request_duration,
+        );
+        counter(
+            &mut out,
+            "somnial_db_busy_retries_total",
+            "Writes retried because the database was locked",
+            self.db_busy_retries.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "somnial_db_busy_failures_total",
+            "Writes that failed after exhausting the busy retry budget",
+            self.db_busy_failures.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "somnial_ingest_accepted_total",
+            "Write requests that were stored",
+            self.ingest_accepted.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "somnial_ingest_rejected_total",
+            "Write requests that were refused or failed",
+            self.ingest_rejected.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "somnial_ingest_points_total",
+            "Points stored by write requests",
+            self.ingest_points.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "somnial_retention_pruned_points_total",
+            "Points deleted for being older than their retention",
+            self.retention_pruned.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "somnial_archived_points_total",
+            "Points uploaded to object storage before retention deleted them",
+            self.archived_points.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "somnial_inactive_metrics_expired_total",
+            "Metrics deleted after going without writes for too long",
+            self.inactive_expired.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "somnial_maintenance_reclaimed_bytes_total",
+            "Bytes the database file shrank by during maintenance",
+            self.maintenance_reclaimed_bytes.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "somnial_alert_notifications_total",
+            "Alert notifications that were delivered",
+            self.alert_notifications.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "somnial_alert_notification_failures_total",
+            "Alert notifications that failed or were refused",
+            self.alert_notification_failures.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "somnial_webhook_deliveries_total",
+            "Batches of points delivered to outgoing webhooks",
+            self.webhook_deliveries.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "somnial_webhook_delivery_failures_total",
+            "Batches of points outgoing webhooks failed or refused",
+            self.webhook_delivery_failures.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "somnial_webhook_points_dropped_total",
+            "Points dropped because an outgoing webhook fell too far behind",
+            self.webhook_points_dropped.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "somnial_github_statuses_total",
+            "Commit statuses posted to GitHub",
+            self.github_statuses.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "somnial_github_status_failures_total",
+            "Commit statuses GitHub failed or refused",
+            self.github_status_failures.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "somnial_digest_deliveries_total",
+            "Namespace digests sent",
+            self.digest_deliveries.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "somnial_digest_delivery_failures_total",
+            "Namespace digests that failed to send",
+            self.digest_delivery_failures.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "somnial_anomalies_detected_total",
+            "Points the background detector recorded as anomalies",
+            self.anomalies_detected.load(Ordering::Relaxed),
+        );
+        histogram(
+            &mut out,
+            "somnial_badge_render_seconds",
+            "Time spent drawing badges that weren't cached",
+            &self.badge_render,
+        );
+        cache(&mut out, "chart", "Chart data", chart_cache);
+        cache(&mut out, "badge", "Badge", badge_cache);
+        gauge(
+            &mut out,
+            "somnial_db_pool_connections",
+            "Database connections currently open",
+            pool.size() as u64,
+        );
+        gauge(
+            &mut out,
+            "somnial_db_pool_idle_connections",
+            "Open database connections not in use",
+            pool.num_idle() as u64,
+        );
+        gauge(
+            &mut out,
+            "somnial_db_pool_max_connections",
+            "Most database connections the pool will open",
+            pool.options().get_max_connections() as u64,
+        );
+        out
+    }
+}
+
+/// Label values can't hold raw quotes, backslashes or line breaks.
+fn label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn requests(out: &mut String, requests: &BTreeMap<(String, String, u16), u64>) {
+    let name = "somnial_http_requests_total";
+    let _ = writeln!(out, "# HELP {} Responses by route, method and status", name);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    for ((route, method, status), count) in requests {
+        let _ = writeln!(
+            out,
+            "{}{{route=\"{}\",method=\"{}\",status=\"{}\"}} {}",
+            name,
+            label(route),
+            label(method),
+            status,
+            count
+        );
+    }
+}
+
+fn histogram(out: &mut String, name: &str, help: &str, histogram: &Histogram) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} histogram", name);
+    let mut cumulative = 0;
+    for (i, bucket) in histogram.buckets.iter().enumerate() {
+        cumulative += bucket.load(Ordering::Relaxed);
+        match LATENCY_BUCKETS.get(i) {
+            Some(bound) => {
+                let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+            }
+            None => {
+                let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, cumulative);
+            }
+        }
+    }
+    let sum = histogram.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+    let _ = writeln!(out, "{}_sum {}", name, sum);
+    let _ = writeln!(out, "{}_count {}", name, cumulative);
+}
+
+/// Hit, miss, eviction and size series for one [`SeriesCache`].
+fn cache<V: Weighted>(out: &mut String, name: &str, what: &str, cache: &SeriesCache<V>) {
+    counter(
+        out,
+        &format!("somnial_{}_cache_hits_total", name),
+        &format!("{} requests served from the cache", what),
+        cache.hits.load(Ordering::Relaxed),
+    );
+    counter(
+        out,
+        &format!("somnial_{}_cache_misses_total", name),
+        &format!("{} requests that had to be computed", what),
+        cache.misses.load(Ordering::Relaxed),
+    );
+    counter(
+        out,
+        &format!("somnial_{}_cache_evictions_total", name),
+        &format!("{} cache entries evicted to stay within the memory budget", what),
+        cache.evictions.load(Ordering::Relaxed),
+    );
+    gauge(
+        out,
+        &format!("somnial_{}_cache_bytes", name),
+        &format!("Bytes currently held in the {} cache", name),
+        cache.bytes() as u64,
+    );
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// Counts every response by the route that answered it, so the label
+/// stays the template like `/{namespace}/{id}` rather than each path.
+pub async fn track(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", MatchedPath::as_str)
+        .to_string();
+    let method = request.method().as_str().to_string();
+    let started = Instant::now();
+    let response = next.run(request).await;
+
+    let metrics = &state.metrics;
+    metrics.request_duration.observe(started.elapsed());
+    *metrics
+        .requests
+        .lock()
+        .unwrap()
+        .entry((route, method, response.status().as_u16()))
+        .or_default() += 1;
+    response
+}
+
+pub async fn get_self_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        StatusCode::OK,
+        [("content-type", "text/plain; version=0.0.4")],
+        state.metrics.render(&state.chart_cache, &state.badge_cache, &state.pool),
+    )
+}
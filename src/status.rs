@@ -0,0 +1,148 @@
+//! `/{namespace}/{id}/status`: a series' latest value held against warning
+//! and critical thresholds, for uptime checkers and status pages that can
+//! only look at a URL. The level is in the body as `ok`, `warn` or `crit`
+//! and in the status code as `200`, `409` or `503`, so a checker that only
+//! knows "2xx is up" still notices.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{ids::SeriesPath, meta, AppState};
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(json!({ "error": message })))
+}
+
+fn database_error<E: std::fmt::Display>(err: E) -> ApiError {
+    crate::errors::report(&err);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+/// `?warn_above=80&crit_above=95`, with `_below` for metrics that are bad
+/// when low. Any of them can be left out, but not all.
+#[derive(Deserialize, Serialize)]
+pub struct Thresholds {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warn_above: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crit_above: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warn_below: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crit_below: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Level {
+    Ok,
+    Warn,
+    Crit,
+}
+
+impl Level {
+    fn status_code(self) -> StatusCode {
+        match self {
+            Level::Ok => StatusCode::OK,
+            Level::Warn => StatusCode::CONFLICT,
+            Level::Crit => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+impl Thresholds {
+    /// The worst level `value` reaches, along with the side and threshold
+    /// it passed.
+    fn level(&self, value: f64) -> (Level, Option<(&'static str, f64)>) {
+        let checks = [
+            (Level::Crit, "above", self.crit_above),
+            (Level::Crit, "below", self.crit_below),
+            (Level::Warn, "above", self.warn_above),
+            (Level::Warn, "below", self.warn_below),
+        ];
+        for (level, side, threshold) in checks {
+            let Some(threshold) = threshold else {
+                continue;
+            };
+            let passed = if side == "above" { value > threshold } else { value < threshold };
+            if passed {
+                return (level, Some((side, threshold)));
+            }
+        }
+        (Level::Ok, None)
+    }
+}
+
+#[derive(Serialize)]
+struct StatusResponse {
+    namespace: String,
+    id: String,
+    status: Level,
+    /// One line for a status page, with the value as the chart shows it
+    message: String,
+    value: f64,
+    timestamp: i64,
+    thresholds: Thresholds,
+}
+
+pub async fn get_status(
+    SeriesPath(namespace, id): SeriesPath,
+    Query(thresholds): Query<Thresholds>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, ApiError> {
+    let given = [
+        thresholds.warn_above,
+        thresholds.crit_above,
+        thresholds.warn_below,
+        thresholds.crit_below,
+    ];
+    if given.iter().all(Option::is_none) {
+        return Err(error(
+            StatusCode::BAD_REQUEST,
+            "give at least one of warn_above, crit_above, warn_below and crit_below",
+        ));
+    }
+    if given.iter().flatten().any(|threshold| !threshold.is_finite()) {
+        return Err(error(StatusCode::BAD_REQUEST, "thresholds must be numbers"));
+    }
+
+    let latest = state
+        .store
+        .latest(&namespace, &id)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "series has no points"))?;
+    let meta = meta::load(&state.pool, &namespace, &id).await.map_err(database_error)?;
+
+    let (level, passed) = thresholds.level(latest.value);
+    let message = match passed {
+        Some((side, threshold)) => format!(
+            "{}/{} is {}, {} the {} threshold of {}",
+            namespace,
+            id,
+            meta.format(latest.value),
+            side,
+            if level == Level::Crit { "critical" } else { "warning" },
+            meta.format(threshold)
+        ),
+        None => format!("{}/{} is {}", namespace, id, meta.format(latest.value)),
+    };
+    let response = StatusResponse {
+        namespace,
+        id,
+        status: level,
+        message,
+        value: latest.value,
+        timestamp: latest.timestamp,
+        thresholds,
+    };
+    Ok((level.status_code(), Json(response)))
+}
@@ -0,0 +1,1733 @@
+//! The point storage behind the handlers: writing points, reading series
+//! back, listing namespaces and their metrics, and the bulk moves, copies
+//! and deletions that renames, clones, retention and rollups make.
+//!
+//! Nothing outside this module queries the points itself, so another
+//! backend only has to implement [`MetricStore`]. [`SqliteStore`] is the
+//! one the server runs on by default, a table of points per month,
+//! [`PostgresStore`] the one for a `postgres:` `DATABASE_URL`, and
+//! [`MemoryStore`] keeps points in a map for tests. Settings such as metadata, dashboards
+//! and rollups live in SQLite whichever store holds the points, so
+//! operations touching both (renaming a metric, cloning a namespace)
+//! change the points first and the settings after, rather than in one
+//! transaction. Deleting a namespace does both in one where
+//! [`MetricStore::settings_pool`] says the points are in SQLite too. Backups and `/api/v1/query` work on the SQLite file
+//! itself, so they only see points a [`SqliteStore`] keeps there; queries
+//! read them through the `metrics` view over every month's table.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fmt::{self, Write};
+use std::future::Future;
+use std::pin::Pin;
+
+use chrono::{DateTime, Datelike, NaiveDate};
+use futures_util::{
+    stream::{self, BoxStream},
+    StreamExt, TryStreamExt,
+};
+use serde::Serialize;
+use sqlx::sqlite::{SqliteConnection, SqlitePool};
+use tracing::Instrument;
+
+use crate::{db, traces, MetricPoint};
+
+pub use crate::memory_store::MemoryStore;
+pub use crate::postgres_store::PostgresStore;
+
+/// What store methods return; boxed so the trait can be used as `dyn`.
+pub type StoreFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, StoreError>> + Send + 'a>>;
+
+/// `(id, point)` pairs read a row at a time, for exports and archives.
+pub type PointStream<'a> = BoxStream<'a, Result<(String, MetricPoint), StoreError>>;
+
+/// Why a store call failed, whatever the backend.
+#[derive(Debug)]
+pub struct StoreError {
+    source: Box<dyn Error + Send + Sync>,
+    busy: bool,
+}
+
+impl StoreError {
+    /// Wraps a backend's error. `busy` ones are retried by writes, as they
+    /// mean another writer briefly held a lock.
+    pub fn new(source: impl Into<Box<dyn Error + Send + Sync>>, busy: bool) -> Self {
+        StoreError {
+            source: source.into(),
+            busy,
+        }
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.busy
+    }
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.source.fmt(f)
+    }
+}
+
+impl Error for StoreError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&*self.source)
+    }
+}
+
+impl From<sqlx::Error> for StoreError {
+    fn from(err: sqlx::Error) -> Self {
+        let busy = db::is_busy(&err);
+        StoreError::new(err, busy)
+    }
+}
+
+impl db::Retryable for StoreError {
+    fn is_busy(&self) -> bool {
+        self.busy
+    }
+}
+
+/// A metric's place in a namespace listing, which runs from the most
+/// recently written metric to the least, by id among those last written in
+/// the same second.
+#[derive(Clone, Copy, Debug)]
+pub struct ListPosition<'a> {
+    pub last_timestamp: i64,
+    pub id: &'a str,
+}
+
+/// Where a [`MetricStore::list`] page starts.
+#[derive(Clone, Copy, Debug)]
+pub enum ListFrom<'a> {
+    /// The most recently written metric
+    Start,
+    /// After skipping this many, for the page numbers of older links
+    Offset(i64),
+    /// The metrics after this one, in listing order
+    After(ListPosition<'a>),
+    /// The metrics before this one, nearest first
+    Before(ListPosition<'a>),
+}
+
+/// One metric in a namespace listing.
+#[derive(Debug)]
+pub struct MetricListing {
+    pub id: String,
+    pub point_count: i64,
+    pub last_timestamp: Option<i64>,
+}
+
+/// A stored point along with the id it can be addressed by.
+#[derive(Debug, Serialize)]
+pub struct StoredPoint {
+    pub point_id: i64,
+    pub timestamp: i64,
+    pub value: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+}
+
+/// A deleted series waiting in the trash.
+#[derive(Debug, Serialize)]
+pub struct TrashedSeries {
+    pub namespace: String,
+    pub id: String,
+    pub points: i64,
+    pub deleted_at: i64,
+}
+
+/// Which of a series' points a correction applies to.
+#[derive(Clone, Copy, Debug)]
+pub enum PointSelector {
+    /// One point, by the id [`MetricStore::points`] reports
+    Id(i64),
+    /// Every point recorded at this timestamp
+    Timestamp(i64),
+}
+
+/// Figures over a window of a series.
+#[derive(Debug)]
+pub struct Aggregate {
+    pub count: i64,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    /// Mean of the squared values, for the standard deviation
+    pub mean_square: f64,
+    /// The newest value, the later-inserted one on a tied timestamp
+    pub latest: f64,
+    pub last_timestamp: i64,
+}
+
+/// How much a namespace holds and where it was last written.
+#[derive(Debug)]
+pub struct NamespaceSummary {
+    pub metrics: i64,
+    pub points: i64,
+    pub latest: Option<LatestWrite>,
+}
+
+/// The newest point in a namespace, the later-inserted one on a tied
+/// timestamp.
+#[derive(Debug)]
+pub struct LatestWrite {
+    pub point_id: i64,
+    pub id: String,
+    pub timestamp: i64,
+}
+
+/// Which series [`MetricStore::buckets`] covers.
+#[derive(Clone, Copy, Debug)]
+pub enum BucketScope<'a> {
+    All,
+    Namespace(&'a str),
+    Series(&'a str, &'a str),
+}
+
+/// Figures over one series' points in one rollup bucket.
+#[derive(Debug)]
+pub struct Bucket {
+    pub namespace: String,
+    pub id: String,
+    /// The bucket's start: a timestamp less its remainder by the resolution
+    pub bucket: i64,
+    pub count: i64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+pub trait MetricStore: Send + Sync {
+    /// Writes `(id, point)` pairs into `namespace` atomically and returns how
+    /// many were stored.
+    fn insert<'a>(&'a self, namespace: &'a str, points: &'a [(&'a str, MetricPoint)]) -> StoreFuture<'a, u64>;
+
+    /// Like [`insert`](MetricStore::insert), but skips points the series
+    /// already had with the same timestamp and value, so writing the same
+    /// points twice stores them once.
+    fn insert_new<'a>(&'a self, namespace: &'a str, points: &'a [(&'a str, MetricPoint)]) -> StoreFuture<'a, u64>;
+
+    /// Removes every point of a series and returns how many there were.
+    fn delete<'a>(&'a self, namespace: &'a str, id: &'a str) -> StoreFuture<'a, u64>;
+
+    /// Moves every point of a series into the trash, marked as deleted at
+    /// `deleted_at`, and returns how many there were.
+    fn trash<'a>(&'a self, namespace: &'a str, id: &'a str, deleted_at: i64) -> StoreFuture<'a, u64>;
+
+    /// Moves a trashed series' points back and returns their timestamps, or
+    /// `None` when the series isn't in the trash.
+    fn untrash<'a>(&'a self, namespace: &'a str, id: &'a str) -> StoreFuture<'a, Option<Vec<i64>>>;
+
+    /// Every series in the trash, oldest deletion first.
+    fn trashed(&self) -> StoreFuture<'_, Vec<TrashedSeries>>;
+
+    /// Permanently removes series deleted before `cutoff` and returns them as
+    /// `(namespace, id)`.
+    fn empty_trash(&self, cutoff: i64) -> StoreFuture<'_, Vec<(String, String)>>;
+
+    /// Up to `limit` of a series' points with timestamps in `since..=until`,
+    /// oldest first.
+    fn range<'a>(
+        &'a self,
+        namespace: &'a str,
+        id: &'a str,
+        since: i64,
+        until: i64,
+        limit: i64,
+    ) -> StoreFuture<'a, Vec<MetricPoint>>;
+
+    /// A series' newest point, the later-inserted one on a tied timestamp.
+    fn latest<'a>(&'a self, namespace: &'a str, id: &'a str) -> StoreFuture<'a, Option<MetricPoint>>;
+
+    /// Up to `limit` points with timestamps in `window` (inclusive) and their ids,
+    /// oldest first.
+    fn points<'a>(
+        &'a self,
+        namespace: &'a str,
+        id: &'a str,
+        window: (i64, i64),
+        limit: i64,
+    ) -> StoreFuture<'a, Vec<StoredPoint>>;
+
+    /// Removes the selected points and returns their timestamps.
+    fn delete_points<'a>(
+        &'a self,
+        namespace: &'a str,
+        id: &'a str,
+        selector: PointSelector,
+    ) -> StoreFuture<'a, Vec<i64>>;
+
+    /// Sets the selected points to `value` and returns their timestamps.
+    fn update_points<'a>(
+        &'a self,
+        namespace: &'a str,
+        id: &'a str,
+        selector: PointSelector,
+        value: f64,
+    ) -> StoreFuture<'a, Vec<i64>>;
+
+    /// Removes all but the newest `keep` points of a series and returns the
+    /// timestamps of those removed.
+    fn trim<'a>(&'a self, namespace: &'a str, id: &'a str, keep: i64) -> StoreFuture<'a, Vec<i64>>;
+
+    /// Moves the points of series `from` into series `into`, dropping those
+    /// at a timestamp `into` already has a point at. Returns the timestamps
+    /// moved and how many were dropped; `from` has no points afterwards.
+    fn merge<'a>(&'a self, namespace: &'a str, from: &'a str, into: &'a str) -> StoreFuture<'a, (Vec<i64>, u64)>;
+
+    /// Up to `limit` metrics in `namespace` from `from`, in listing order
+    /// (see [`ListPosition`]) unless walking backwards.
+    fn list<'a>(&'a self, namespace: &'a str, from: ListFrom<'a>, limit: i64) -> StoreFuture<'a, Vec<MetricListing>>;
+
+    /// Figures over `since..=until`, or `None` when the window has no points.
+    fn aggregate<'a>(
+        &'a self,
+        namespace: &'a str,
+        id: &'a str,
+        since: i64,
+        until: i64,
+    ) -> StoreFuture<'a, Option<Aggregate>>;
+
+    /// The newest `limit` of a series' points at or before `until`, oldest
+    /// first.
+    fn recent<'a>(&'a self, namespace: &'a str, id: &'a str, until: i64, limit: i64) -> StoreFuture<'a, Vec<MetricPoint>>;
+
+    /// Every namespace with points, alphabetically.
+    fn namespaces(&self) -> StoreFuture<'_, Vec<String>>;
+
+    /// Every metric in `namespace` with points, alphabetically.
+    fn ids<'a>(&'a self, namespace: &'a str) -> StoreFuture<'a, Vec<String>>;
+
+    fn namespace_summary<'a>(&'a self, namespace: &'a str) -> StoreFuture<'a, NamespaceSummary>;
+
+    /// Moves every point of series `from` to series `to` and returns how many
+    /// there were, or `None` without moving any when `to` already has
+    /// points.
+    fn rename<'a>(&'a self, namespace: &'a str, from: &'a str, to: &'a str) -> StoreFuture<'a, Option<u64>>;
+
+    /// Copies the points of namespace `from` with timestamps from `since` on
+    /// into namespace `to` and returns how many there were, or `None`
+    /// without copying any when `to` already has points.
+    fn copy_namespace<'a>(&'a self, from: &'a str, to: &'a str, since: i64) -> StoreFuture<'a, Option<u64>>;
+
+    /// Removes every point of a namespace, trashed ones included, and
+    /// returns how many live points there were.
+    fn delete_namespace<'a>(&'a self, namespace: &'a str) -> StoreFuture<'a, u64>;
+
+    /// The settings database's pool when the points are kept in it too, so
+    /// changes to both can go in one transaction.
+    fn settings_pool(&self) -> Option<&SqlitePool> {
+        None
+    }
+
+    /// Removes every point of a series unless it has one from `cutoff` on,
+    /// and returns how many there were.
+    fn expire<'a>(&'a self, namespace: &'a str, id: &'a str, cutoff: i64) -> StoreFuture<'a, u64>;
+
+    /// The highest point id stored so far. Ids only grow, so a point stored
+    /// later always has a higher one.
+    fn watermark(&self) -> StoreFuture<'_, i64>;
+
+    /// The points of `namespace` with timestamps before `before` and ids up
+    /// to `through`, by metric id, then timestamp, then insertion.
+    fn scan<'a>(&'a self, namespace: &'a str, before: i64, through: i64) -> PointStream<'a>;
+
+    /// Removes up to `limit` of the points [`scan`](MetricStore::scan)
+    /// would return for the same arguments, and returns how many went.
+    fn prune<'a>(&'a self, namespace: &'a str, before: i64, through: i64, limit: i64) -> StoreFuture<'a, u64>;
+
+    /// Removes, where the backend can do it wholesale, points from before
+    /// their namespace's cutoff in `cutoffs` with ids up to `through`, and
+    /// returns how many went from each namespace. What it leaves is for
+    /// [`prune`](MetricStore::prune); by default that's everything.
+    fn drop_expired<'a>(
+        &'a self,
+        _cutoffs: &'a HashMap<String, i64>,
+        _through: i64,
+    ) -> StoreFuture<'a, HashMap<String, u64>> {
+        Box::pin(async { Ok(HashMap::new()) })
+    }
+
+    /// The oldest timestamp of any point.
+    fn first_timestamp(&self) -> StoreFuture<'_, Option<i64>>;
+
+    /// The `resolution`-second buckets of the series in `scope` with points
+    /// in `since..until`, by namespace, id and bucket.
+    fn buckets<'a>(
+        &'a self,
+        scope: BucketScope<'a>,
+        resolution: i64,
+        since: i64,
+        until: i64,
+    ) -> StoreFuture<'a, Vec<Bucket>>;
+}
+
+impl PointSelector {
+    /// `(rowid, timestamp)` to match, with `NULL` for the one not in use.
+    pub(crate) fn columns(self) -> (Option<i64>, Option<i64>) {
+        match self {
+            PointSelector::Id(point_id) => (Some(point_id), None),
+            PointSelector::Timestamp(timestamp) => (None, Some(timestamp)),
+        }
+    }
+}
+
+/// [`MetricStore`] over a table of points per calendar month (UTC), named
+/// `points_YYYYMM` and created as writes need them. Reads only visit the
+/// months their window covers, and retention drops a month's table once
+/// everything in it has expired. Point ids come from `point_sequence`, so
+/// they stay unique and only grow across the tables.
+pub struct SqliteStore {
+    pool: SqlitePool,
+}
+
+/// Points one step of a [`MetricStore::scan`] reads
+const SCAN_PAGE: i64 = 1000;
+/// What [`PointRow`] is selected as
+const POINT_COLUMNS: &str = "point_id, timestamp, value, sha, branch";
+
+/// `(point_id, timestamp, value, sha, branch)`
+type PointRow = (i64, i64, f64, Option<String>, Option<String>);
+
+/// A series' `(count, min, max, sum, sum of squares, last timestamp)` in one
+/// month, all but the count `NULL` when it has no points there
+type MonthAggregate = (i64, Option<f64>, Option<f64>, Option<f64>, Option<f64>, Option<i64>);
+
+fn to_point((_, timestamp, value, sha, branch): PointRow) -> MetricPoint {
+    MetricPoint {
+        timestamp,
+        value,
+        sha,
+        branch,
+    }
+}
+
+/// A calendar month, UTC, whose points share a table. Years before 0 and
+/// after 9999 don't get months of their own: their points go in the first
+/// or last one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Month {
+    year: i32,
+    month: u32,
+}
+
+impl Month {
+    const FIRST: Month = Month { year: 0, month: 1 };
+    const LAST: Month = Month { year: 9999, month: 12 };
+
+    fn of(timestamp: i64) -> Month {
+        let timestamp = timestamp.clamp(Month::FIRST.starts(), Month::LAST.next().starts() - 1);
+        let time = DateTime::from_timestamp(timestamp, 0).unwrap_or_default();
+        Month {
+            year: time.year(),
+            month: time.month(),
+        }
+    }
+
+    fn parse(table: &str) -> Option<Month> {
+        let digits = table.strip_prefix("points_")?;
+        if digits.len() != 6 {
+            return None;
+        }
+        let month = Month {
+            year: digits[..4].parse().ok()?,
+            month: digits[4..].parse().ok()?,
+        };
+        (1..=12).contains(&month.month).then_some(month)
+    }
+
+    fn table(self) -> String {
+        format!("points_{:04}{:02}", self.year, self.month)
+    }
+
+    fn next(self) -> Month {
+        match self.month {
+            12 => Month {
+                year: self.year + 1,
+                month: 1,
+            },
+            month => Month {
+                year: self.year,
+                month: month + 1,
+            },
+        }
+    }
+
+    /// Midnight on the month's first day.
+    fn starts(self) -> i64 {
+        NaiveDate::from_ymd_opt(self.year, self.month, 1)
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .map_or(0, |time| time.and_utc().timestamp())
+    }
+
+    /// The earliest timestamp the month's table holds.
+    fn first(self) -> i64 {
+        if self == Month::FIRST { i64::MIN } else { self.starts() }
+    }
+
+    /// The latest timestamp the month's table holds.
+    fn last(self) -> i64 {
+        if self == Month::LAST { i64::MAX } else { self.next().starts() - 1 }
+    }
+
+    /// Whether the month holds any timestamps in `since..=until`.
+    fn overlaps(self, since: i64, until: i64) -> bool {
+        self.first() <= until && self.last() >= since
+    }
+}
+
+/// The month tables in `schema`, oldest first.
+async fn months_in(conn: &mut SqliteConnection, schema: &str) -> Result<Vec<Month>, sqlx::Error> {
+    let names: Vec<String> = sqlx::query_scalar(&format!(
+        "SELECT name FROM {schema}.sqlite_master
+         WHERE type = 'table' AND name GLOB 'points_[0-9][0-9][0-9][0-9][0-9][0-9]'"
+    ))
+    .fetch_all(&mut *conn)
+    .await?;
+    let mut months: Vec<Month> = names.iter().filter_map(|name| Month::parse(name)).collect();
+    months.sort();
+    Ok(months)
+}
+
+async fn months(conn: &mut SqliteConnection) -> Result<Vec<Month>, sqlx::Error> {
+    months_in(conn, "main").await
+}
+
+/// Creates the table for `month` unless `months` says it's there already.
+async fn create_month(conn: &mut SqliteConnection, months: &mut Vec<Month>, month: Month) -> Result<(), sqlx::Error> {
+    let Err(at) = months.binary_search(&month) else {
+        return Ok(());
+    };
+    let table = month.table();
+    sqlx::query(&format!(
+        "CREATE TABLE IF NOT EXISTS main.{table} (
+             point_id INTEGER PRIMARY KEY,
+             namespace TEXT NOT NULL,
+             id TEXT NOT NULL,
+             value REAL NOT NULL,
+             timestamp INTEGER NOT NULL,
+             sha TEXT,
+             branch TEXT
+         )"
+    ))
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(&format!(
+        "CREATE INDEX IF NOT EXISTS main.{table}_series ON {table} (namespace, id, timestamp)"
+    ))
+    .execute(&mut *conn)
+    .await?;
+    months.insert(at, month);
+    recreate_view(conn, months).await
+}
+
+/// Points the `metrics` view at every month's table.
+async fn recreate_view(conn: &mut SqliteConnection, months: &[Month]) -> Result<(), sqlx::Error> {
+    let mut select = String::from(
+        "SELECT rowid AS point_id, namespace, id, value, timestamp, sha, branch FROM unpartitioned_points",
+    );
+    for month in months {
+        let _ = write!(
+            select,
+            " UNION ALL SELECT point_id, namespace, id, value, timestamp, sha, branch FROM {}",
+            month.table()
+        );
+    }
+    sqlx::query("DROP VIEW IF EXISTS main.metrics").execute(&mut *conn).await?;
+    sqlx::query(&format!("CREATE VIEW main.metrics AS {}", select))
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+/// Makes `main`'s month tables the ones the attached `restore` database
+/// has, empty, so restoring a backup can copy them like any other table.
+pub(crate) async fn restore_months(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+    let theirs = months_in(conn, "restore").await?;
+    let mut ours = months(conn).await?;
+    for month in ours.clone() {
+        if !theirs.contains(&month) {
+            sqlx::query(&format!("DROP TABLE main.{}", month.table()))
+                .execute(&mut *conn)
+                .await?;
+            ours.retain(|kept| *kept != month);
+        }
+    }
+    for month in theirs {
+        create_month(conn, &mut ours, month).await?;
+    }
+    recreate_view(conn, &ours).await
+}
+
+/// Hands out `count` new point ids and returns the first.
+async fn reserve_ids(conn: &mut SqliteConnection, count: i64) -> Result<i64, sqlx::Error> {
+    let last: i64 = sqlx::query_scalar("UPDATE point_sequence SET last_id = last_id + ? RETURNING last_id")
+        .bind(count)
+        .fetch_one(&mut *conn)
+        .await?;
+    Ok(last - count + 1)
+}
+
+/// Writes `(id, point)` pairs into their months' tables, creating those as
+/// needed, and returns how many there were.
+async fn insert_points(
+    conn: &mut SqliteConnection,
+    namespace: &str,
+    points: &[(&str, &MetricPoint)],
+) -> Result<u64, sqlx::Error> {
+    if points.is_empty() {
+        return Ok(0);
+    }
+    let mut months = months(conn).await?;
+    let mut point_id = reserve_ids(conn, points.len() as i64).await?;
+    for (id, point) in points {
+        let month = Month::of(point.timestamp);
+        create_month(conn, &mut months, month).await?;
+        sqlx::query(&format!(
+            "INSERT INTO {} (point_id, namespace, id, value, timestamp, sha, branch) VALUES (?, ?, ?, ?, ?, ?, ?)",
+            month.table()
+        ))
+        .bind(point_id)
+        .bind(namespace)
+        .bind(id)
+        .bind(point.value)
+        .bind(point.timestamp)
+        .bind(&point.sha)
+        .bind(&point.branch)
+        .execute(&mut *conn)
+        .await?;
+        point_id += 1;
+    }
+    Ok(points.len() as u64)
+}
+
+/// Up to `limit` of a series' points with timestamps in `since..=until`,
+/// oldest first or, with `newest_first`, newest first. Only the months the
+/// window covers are read, and only until the limit is reached.
+async fn series_points(
+    conn: &mut SqliteConnection,
+    months: &[Month],
+    (namespace, id): (&str, &str),
+    (since, until): (i64, i64),
+    limit: i64,
+    newest_first: bool,
+) -> Result<Vec<PointRow>, sqlx::Error> {
+    // A negative limit is none at all, as in SQL
+    let limit = if limit < 0 { i64::MAX } else { limit };
+    let order = if newest_first { "DESC" } else { "ASC" };
+    let covered = months.iter().filter(|month| month.overlaps(since, until));
+    let covered: Vec<&Month> = if newest_first { covered.rev().collect() } else { covered.collect() };
+    let mut rows = Vec::new();
+    for month in covered {
+        let remaining = limit - rows.len() as i64;
+        if remaining <= 0 {
+            break;
+        }
+        let sql = format!(
+            "SELECT {POINT_COLUMNS} FROM {} WHERE namespace = ? AND id = ? AND timestamp BETWEEN ? AND ?
+             ORDER BY timestamp {order}, point_id {order} LIMIT ?",
+            month.table()
+        );
+        rows.extend(
+            sqlx::query_as::<_, PointRow>(&sql)
+                .bind(namespace)
+                .bind(id)
+                .bind(since)
+                .bind(until)
+                .bind(remaining)
+                .fetch_all(&mut *conn)
+                .await?,
+        );
+    }
+    Ok(rows)
+}
+
+/// Whether any month after `cutoff` starts has a point of the series from
+/// `cutoff` on.
+/// [`SqliteStore::delete_namespace`] on a connection the caller has in a
+/// transaction.
+pub(crate) async fn delete_namespace_points(conn: &mut SqliteConnection, namespace: &str) -> Result<u64, sqlx::Error> {
+    let mut deleted = 0;
+    for month in months(conn).await? {
+        let sql = format!("DELETE FROM {} WHERE namespace = ?", month.table());
+        deleted += sqlx::query(&sql).bind(namespace).execute(&mut *conn).await?.rows_affected();
+    }
+    sqlx::query!("DELETE FROM deleted_points WHERE namespace = ?", namespace)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!("DELETE FROM deleted_metrics WHERE namespace = ?", namespace)
+        .execute(&mut *conn)
+        .await?;
+    Ok(deleted)
+}
+
+async fn has_points_from(
+    conn: &mut SqliteConnection,
+    months: &[Month],
+    (namespace, id): (&str, &str),
+    cutoff: i64,
+) -> Result<bool, sqlx::Error> {
+    for month in months.iter().filter(|month| month.last() >= cutoff) {
+        let sql = format!(
+            "SELECT EXISTS(SELECT 1 FROM {} WHERE namespace = ? AND id = ? AND timestamp >= ?)",
+            month.table()
+        );
+        let found: bool = sqlx::query_scalar(&sql)
+            .bind(namespace)
+            .bind(id)
+            .bind(cutoff)
+            .fetch_one(&mut *conn)
+            .await?;
+        if found {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Where a [`MetricStore::scan`] has got to.
+struct ScanState<'a> {
+    pool: &'a SqlitePool,
+    namespace: &'a str,
+    before: i64,
+    through: i64,
+    /// The `(id, month)` pairs still to read, in output order, once known
+    queue: Option<VecDeque<(String, Month)>>,
+    /// The `(timestamp, point_id)` read up to in the pair at the front
+    cursor: (i64, i64),
+}
+
+impl ScanState<'_> {
+    /// The next page of points, or `None` when the scan is done.
+    async fn next_page(&mut self) -> Result<Option<Vec<(String, MetricPoint)>>, sqlx::Error> {
+        let mut conn = self.pool.acquire().await?;
+        if self.queue.is_none() {
+            let months: Vec<Month> = months(&mut conn)
+                .await?
+                .into_iter()
+                .filter(|month| month.first() < self.before)
+                .collect();
+            let mut ids = BTreeSet::new();
+            for month in &months {
+                let sql = format!("SELECT DISTINCT id FROM {} WHERE namespace = ?", month.table());
+                let found: Vec<String> = sqlx::query_scalar(&sql).bind(self.namespace).fetch_all(&mut *conn).await?;
+                ids.extend(found);
+            }
+            self.queue = Some(
+                ids.into_iter()
+                    .flat_map(|id| months.iter().map(move |month| (id.clone(), *month)))
+                    .collect(),
+            );
+        }
+        let queue = self.queue.as_mut().expect("the queue was just filled");
+        let Some((id, month)) = queue.front().cloned() else {
+            return Ok(None);
+        };
+        let sql = format!(
+            "SELECT {POINT_COLUMNS} FROM {}
+             WHERE namespace = ? AND id = ? AND timestamp < ? AND point_id <= ? AND (timestamp, point_id) > (?, ?)
+             ORDER BY timestamp, point_id LIMIT ?",
+            month.table()
+        );
+        let rows: Vec<PointRow> = sqlx::query_as(&sql)
+            .bind(self.namespace)
+            .bind(&id)
+            .bind(self.before)
+            .bind(self.through)
+            .bind(self.cursor.0)
+            .bind(self.cursor.1)
+            .bind(SCAN_PAGE)
+            .fetch_all(&mut *conn)
+            .await?;
+        match rows.last() {
+            Some(&(point_id, timestamp, ..)) if rows.len() as i64 == SCAN_PAGE => self.cursor = (timestamp, point_id),
+            _ => {
+                queue.pop_front();
+                self.cursor = (i64::MIN, i64::MIN);
+            }
+        }
+        Ok(Some(rows.into_iter().map(|row| (id.clone(), to_point(row))).collect()))
+    }
+}
+
+/// Boxes a store method's query in a trace span named after the method.
+fn traced<'a, T>(
+    operation: &'static str,
+    query: impl Future<Output = Result<T, sqlx::Error>> + Send + 'a,
+) -> StoreFuture<'a, T> {
+    Box::pin(async move {
+        let span = traces::query("sqlite", operation);
+        let result = query.instrument(span.clone()).await;
+        if result.is_err() {
+            traces::fail(&span);
+        }
+        Ok(result?)
+    })
+}
+
+impl SqliteStore {
+    pub fn new(pool: SqlitePool) -> Self {
+        SqliteStore { pool }
+    }
+
+    /// Moves the points a server from before the month tables kept in one
+    /// table into them, a month at a time, and returns how many there were.
+    pub async fn move_unpartitioned(&self) -> Result<u64, StoreError> {
+        let keys: Vec<i64> = sqlx::query_scalar(
+            "SELECT DISTINCT CAST(strftime('%Y%m', MIN(MAX(timestamp, ?), ?), 'unixepoch') AS INTEGER)
+             FROM unpartitioned_points",
+        )
+        .bind(Month::FIRST.starts())
+        .bind(Month::LAST.next().starts() - 1)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut moved = 0;
+        for key in keys {
+            let month = Month {
+                year: (key / 100) as i32,
+                month: (key % 100) as u32,
+            };
+            let mut tx = self.pool.begin().await?;
+            let mut months = months(&mut tx).await?;
+            create_month(&mut tx, &mut months, month).await?;
+            // Rows keep their rowids as point ids, which the sequence started after
+            moved += sqlx::query(&format!(
+                "INSERT INTO {} (point_id, namespace, id, value, timestamp, sha, branch)
+                 SELECT rowid, namespace, id, value, timestamp, sha, branch FROM unpartitioned_points
+                 WHERE timestamp BETWEEN ? AND ?",
+                month.table()
+            ))
+            .bind(month.first())
+            .bind(month.last())
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+            sqlx::query("DELETE FROM unpartitioned_points WHERE timestamp BETWEEN ? AND ?")
+                .bind(month.first())
+                .bind(month.last())
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+        }
+        Ok(moved)
+    }
+}
+
+impl MetricStore for SqliteStore {
+    fn insert<'a>(&'a self, namespace: &'a str, points: &'a [(&'a str, MetricPoint)]) -> StoreFuture<'a, u64> {
+        traced("insert", async move {
+            let points: Vec<(&str, &MetricPoint)> = points.iter().map(|(id, point)| (*id, point)).collect();
+            let mut tx = self.pool.begin().await?;
+            let inserted = insert_points(&mut tx, namespace, &points).await?;
+            tx.commit().await?;
+            Ok(inserted)
+        })
+    }
+
+    fn insert_new<'a>(&'a self, namespace: &'a str, points: &'a [(&'a str, MetricPoint)]) -> StoreFuture<'a, u64> {
+        traced("insert_new", async move {
+            let mut tx = self.pool.begin().await?;
+            // Only points stored before this call count, so repeats within
+            // `points` are all kept
+            let before = sqlx::query_scalar!("SELECT last_id FROM point_sequence")
+                .fetch_one(&mut *tx)
+                .await?;
+            let months = months(&mut tx).await?;
+            let mut new = Vec::new();
+            for (id, point) in points {
+                let month = Month::of(point.timestamp);
+                if months.binary_search(&month).is_ok() {
+                    let sql = format!(
+                        "SELECT EXISTS(SELECT 1 FROM {} WHERE namespace = ? AND id = ? AND timestamp = ? AND value = ?
+                             AND point_id <= ?)",
+                        month.table()
+                    );
+                    let stored: bool = sqlx::query_scalar(&sql)
+                        .bind(namespace)
+                        .bind(id)
+                        .bind(point.timestamp)
+                        .bind(point.value)
+                        .bind(before)
+                        .fetch_one(&mut *tx)
+                        .await?;
+                    if stored {
+                        continue;
+                    }
+                }
+                new.push((*id, point));
+            }
+            let inserted = insert_points(&mut tx, namespace, &new).await?;
+            tx.commit().await?;
+            Ok(inserted)
+        })
+    }
+
+    fn delete<'a>(&'a self, namespace: &'a str, id: &'a str) -> StoreFuture<'a, u64> {
+        traced("delete", async move {
+            let mut tx = self.pool.begin().await?;
+            let mut deleted = 0;
+            for month in months(&mut tx).await? {
+                let sql = format!("DELETE FROM {} WHERE namespace = ? AND id = ?", month.table());
+                deleted += sqlx::query(&sql)
+                    .bind(namespace)
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+            }
+            tx.commit().await?;
+            Ok(deleted)
+        })
+    }
+
+    fn trash<'a>(&'a self, namespace: &'a str, id: &'a str, deleted_at: i64) -> StoreFuture<'a, u64> {
+        traced("trash", async move {
+            let mut tx = self.pool.begin().await?;
+            let mut moved = 0;
+            for month in months(&mut tx).await? {
+                let table = month.table();
+                sqlx::query(&format!(
+                    "INSERT INTO deleted_points (namespace, id, value, timestamp, sha, branch)
+                     SELECT namespace, id, value, timestamp, sha, branch FROM {table} WHERE namespace = ? AND id = ?
+                     ORDER BY timestamp, point_id"
+                ))
+                .bind(namespace)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+                moved += sqlx::query(&format!("DELETE FROM {table} WHERE namespace = ? AND id = ?"))
+                    .bind(namespace)
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+            }
+            if moved > 0 {
+                sqlx::query!(
+                    "INSERT INTO deleted_metrics (namespace, id, deleted_at) VALUES (?, ?, ?)
+                     ON CONFLICT (namespace, id) DO UPDATE SET deleted_at = excluded.deleted_at",
+                    namespace,
+                    id,
+                    deleted_at
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            tx.commit().await?;
+            Ok(moved)
+        })
+    }
+
+    fn untrash<'a>(&'a self, namespace: &'a str, id: &'a str) -> StoreFuture<'a, Option<Vec<i64>>> {
+        traced("untrash", async move {
+            let mut tx = self.pool.begin().await?;
+            let found = sqlx::query!("DELETE FROM deleted_metrics WHERE namespace = ? AND id = ?", namespace, id)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+            if found == 0 {
+                return Ok(None);
+            }
+            let points: Vec<MetricPoint> = sqlx::query!(
+                "SELECT value, timestamp, sha, branch FROM deleted_points WHERE namespace = ? AND id = ? ORDER BY rowid",
+                namespace,
+                id
+            )
+            .fetch_all(&mut *tx)
+            .await?
+            .into_iter()
+            .map(|row| MetricPoint {
+                timestamp: row.timestamp,
+                value: row.value,
+                sha: row.sha,
+                branch: row.branch,
+            })
+            .collect();
+            let restored: Vec<(&str, &MetricPoint)> = points.iter().map(|point| (id, point)).collect();
+            insert_points(&mut tx, namespace, &restored).await?;
+            sqlx::query!("DELETE FROM deleted_points WHERE namespace = ? AND id = ?", namespace, id)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            Ok(Some(points.iter().map(|point| point.timestamp).collect()))
+        })
+    }
+
+    fn trashed(&self) -> StoreFuture<'_, Vec<TrashedSeries>> {
+        traced("trashed", async move {
+            sqlx::query_as!(
+                TrashedSeries,
+                r#"SELECT d.namespace, d.id, d.deleted_at,
+                       (SELECT COUNT(*) FROM deleted_points p WHERE p.namespace = d.namespace AND p.id = d.id)
+                           as "points!: i64"
+                   FROM deleted_metrics d
+                   ORDER BY d.deleted_at ASC"#
+            )
+            .fetch_all(&self.pool)
+            .await
+        })
+    }
+
+    fn empty_trash(&self, cutoff: i64) -> StoreFuture<'_, Vec<(String, String)>> {
+        traced("empty_trash", async move {
+            let mut tx = self.pool.begin().await?;
+            let expired = sqlx::query!("DELETE FROM deleted_metrics WHERE deleted_at < ? RETURNING namespace, id", cutoff)
+                .fetch_all(&mut *tx)
+                .await?;
+            for row in &expired {
+                sqlx::query!("DELETE FROM deleted_points WHERE namespace = ? AND id = ?", row.namespace, row.id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            tx.commit().await?;
+            Ok(expired.into_iter().map(|row| (row.namespace, row.id)).collect())
+        })
+    }
+
+    fn range<'a>(
+        &'a self,
+        namespace: &'a str,
+        id: &'a str,
+        since: i64,
+        until: i64,
+        limit: i64,
+    ) -> StoreFuture<'a, Vec<MetricPoint>> {
+        traced("range", async move {
+            let mut conn = self.pool.acquire().await?;
+            let months = months(&mut conn).await?;
+            let rows = series_points(&mut conn, &months, (namespace, id), (since, until), limit, false).await?;
+            Ok(rows.into_iter().map(to_point).collect())
+        })
+    }
+
+    fn latest<'a>(&'a self, namespace: &'a str, id: &'a str) -> StoreFuture<'a, Option<MetricPoint>> {
+        traced("latest", async move {
+            let mut conn = self.pool.acquire().await?;
+            let months = months(&mut conn).await?;
+            let rows = series_points(&mut conn, &months, (namespace, id), (i64::MIN, i64::MAX), 1, true).await?;
+            Ok(rows.into_iter().next().map(to_point))
+        })
+    }
+
+    fn points<'a>(
+        &'a self,
+        namespace: &'a str,
+        id: &'a str,
+        window: (i64, i64),
+        limit: i64,
+    ) -> StoreFuture<'a, Vec<StoredPoint>> {
+        traced("points", async move {
+            let mut conn = self.pool.acquire().await?;
+            let months = months(&mut conn).await?;
+            let rows = series_points(&mut conn, &months, (namespace, id), window, limit, false).await?;
+            Ok(rows
+                .into_iter()
+                .map(|(point_id, timestamp, value, sha, branch)| StoredPoint {
+                    point_id,
+                    timestamp,
+                    value,
+                    sha,
+                    branch,
+                })
+                .collect())
+        })
+    }
+
+    fn delete_points<'a>(
+        &'a self,
+        namespace: &'a str,
+        id: &'a str,
+        selector: PointSelector,
+    ) -> StoreFuture<'a, Vec<i64>> {
+        traced("delete_points", async move {
+            let (point_id, timestamp) = selector.columns();
+            let mut tx = self.pool.begin().await?;
+            let mut deleted = Vec::new();
+            for month in months(&mut tx).await? {
+                // Points at one timestamp are all in its month
+                if timestamp.is_some_and(|timestamp| Month::of(timestamp) != month) {
+                    continue;
+                }
+                let sql = format!(
+                    "DELETE FROM {} WHERE namespace = ? AND id = ? AND (point_id = ? OR timestamp = ?) RETURNING timestamp",
+                    month.table()
+                );
+                let found: Vec<i64> = sqlx::query_scalar(&sql)
+                    .bind(namespace)
+                    .bind(id)
+                    .bind(point_id)
+                    .bind(timestamp)
+                    .fetch_all(&mut *tx)
+                    .await?;
+                deleted.extend(found);
+            }
+            tx.commit().await?;
+            Ok(deleted)
+        })
+    }
+
+    fn update_points<'a>(
+        &'a self,
+        namespace: &'a str,
+        id: &'a str,
+        selector: PointSelector,
+        value: f64,
+    ) -> StoreFuture<'a, Vec<i64>> {
+        traced("update_points", async move {
+            let (point_id, timestamp) = selector.columns();
+            let mut tx = self.pool.begin().await?;
+            let mut updated = Vec::new();
+            for month in months(&mut tx).await? {
+                if timestamp.is_some_and(|timestamp| Month::of(timestamp) != month) {
+                    continue;
+                }
+                let sql = format!(
+                    "UPDATE {} SET value = ? WHERE namespace = ? AND id = ? AND (point_id = ? OR timestamp = ?)
+                     RETURNING timestamp",
+                    month.table()
+                );
+                let found: Vec<i64> = sqlx::query_scalar(&sql)
+                    .bind(value)
+                    .bind(namespace)
+                    .bind(id)
+                    .bind(point_id)
+                    .bind(timestamp)
+                    .fetch_all(&mut *tx)
+                    .await?;
+                updated.extend(found);
+            }
+            tx.commit().await?;
+            Ok(updated)
+        })
+    }
+
+    fn trim<'a>(&'a self, namespace: &'a str, id: &'a str, keep: i64) -> StoreFuture<'a, Vec<i64>> {
+        traced("trim", async move {
+            let mut tx = self.pool.begin().await?;
+            let months = months(&mut tx).await?;
+            // The oldest point kept, or none when nothing is
+            let oldest_kept = if keep > 0 {
+                let kept = series_points(&mut tx, &months, (namespace, id), (i64::MIN, i64::MAX), keep, true).await?;
+                if (kept.len() as i64) < keep {
+                    return Ok(Vec::new());
+                }
+                kept.last().map(|&(point_id, timestamp, ..)| (timestamp, point_id))
+            } else {
+                None
+            };
+            let mut removed = Vec::new();
+            for month in &months {
+                let found: Vec<i64> = match oldest_kept {
+                    Some((timestamp, _)) if month.first() > timestamp => continue,
+                    Some((timestamp, point_id)) => {
+                        let sql = format!(
+                            "DELETE FROM {} WHERE namespace = ? AND id = ? AND (timestamp, point_id) < (?, ?)
+                             RETURNING timestamp",
+                            month.table()
+                        );
+                        sqlx::query_scalar(&sql)
+                            .bind(namespace)
+                            .bind(id)
+                            .bind(timestamp)
+                            .bind(point_id)
+                            .fetch_all(&mut *tx)
+                            .await?
+                    }
+                    None => {
+                        let sql = format!("DELETE FROM {} WHERE namespace = ? AND id = ? RETURNING timestamp", month.table());
+                        sqlx::query_scalar(&sql).bind(namespace).bind(id).fetch_all(&mut *tx).await?
+                    }
+                };
+                removed.extend(found);
+            }
+            tx.commit().await?;
+            Ok(removed)
+        })
+    }
+
+    fn merge<'a>(&'a self, namespace: &'a str, from: &'a str, into: &'a str) -> StoreFuture<'a, (Vec<i64>, u64)> {
+        traced("merge", async move {
+            let mut tx = self.pool.begin().await?;
+            let (mut moved, mut dropped) = (Vec::new(), 0);
+            // A timestamp's points are all in one month, so each month's
+            // table can be merged on its own
+            for month in months(&mut tx).await? {
+                let table = month.table();
+                let found: Vec<i64> = sqlx::query_scalar(&format!(
+                    "UPDATE {table} SET id = ?3
+                     WHERE namespace = ?1 AND id = ?2
+                       AND timestamp NOT IN (SELECT timestamp FROM {table} WHERE namespace = ?1 AND id = ?3)
+                     RETURNING timestamp"
+                ))
+                .bind(namespace)
+                .bind(from)
+                .bind(into)
+                .fetch_all(&mut *tx)
+                .await?;
+                moved.extend(found);
+                dropped += sqlx::query(&format!("DELETE FROM {table} WHERE namespace = ? AND id = ?"))
+                    .bind(namespace)
+                    .bind(from)
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+            }
+            tx.commit().await?;
+            Ok((moved, dropped))
+        })
+    }
+
+    fn list<'a>(&'a self, namespace: &'a str, from: ListFrom<'a>, limit: i64) -> StoreFuture<'a, Vec<MetricListing>> {
+        traced("list", async move {
+            let mut conn = self.pool.acquire().await?;
+            let mut totals: HashMap<String, (i64, i64)> = HashMap::new();
+            for month in months(&mut conn).await? {
+                let sql = format!(
+                    "SELECT id, COUNT(*), MAX(timestamp) FROM {} WHERE namespace = ? GROUP BY id",
+                    month.table()
+                );
+                let rows: Vec<(String, i64, i64)> = sqlx::query_as(&sql).bind(namespace).fetch_all(&mut *conn).await?;
+                for (id, count, last_timestamp) in rows {
+                    let total = totals.entry(id).or_insert((0, i64::MIN));
+                    *total = (total.0 + count, total.1.max(last_timestamp));
+                }
+            }
+
+            // Listing order: most recently written first, then by id
+            let mut metrics: Vec<(i64, String, i64)> =
+                totals.into_iter().map(|(id, (count, last_timestamp))| (last_timestamp, id, count)).collect();
+            metrics.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+            let limit = usize::try_from(limit).unwrap_or(usize::MAX);
+            let page: Vec<(i64, String, i64)> = match from {
+                ListFrom::Start => metrics.into_iter().take(limit).collect(),
+                ListFrom::Offset(offset) => metrics
+                    .into_iter()
+                    .skip(usize::try_from(offset).unwrap_or(0))
+                    .take(limit)
+                    .collect(),
+                ListFrom::After(after) => metrics
+                    .into_iter()
+                    .filter(|(last, id, _)| {
+                        *last < after.last_timestamp || (*last == after.last_timestamp && id.as_str() > after.id)
+                    })
+                    .take(limit)
+                    .collect(),
+                ListFrom::Before(before) => metrics
+                    .into_iter()
+                    .rev()
+                    .filter(|(last, id, _)| {
+                        *last > before.last_timestamp || (*last == before.last_timestamp && id.as_str() < before.id)
+                    })
+                    .take(limit)
+                    .collect(),
+            };
+            Ok(page
+                .into_iter()
+                .map(|(last_timestamp, id, point_count)| MetricListing {
+                    id,
+                    point_count,
+                    last_timestamp: Some(last_timestamp),
+                })
+                .collect())
+        })
+    }
+
+    fn aggregate<'a>(
+        &'a self,
+        namespace: &'a str,
+        id: &'a str,
+        since: i64,
+        until: i64,
+    ) -> StoreFuture<'a, Option<Aggregate>> {
+        traced("aggregate", async move {
+            let mut conn = self.pool.acquire().await?;
+            let months = months(&mut conn).await?;
+            let (mut count, mut min, mut max) = (0, f64::INFINITY, f64::NEG_INFINITY);
+            let (mut sum, mut sum_squares, mut last_timestamp) = (0.0, 0.0, i64::MIN);
+            for month in months.iter().filter(|month| month.overlaps(since, until)) {
+                let sql = format!(
+                    "SELECT COUNT(*), MIN(value), MAX(value), SUM(value), SUM(value * value), MAX(timestamp)
+                     FROM {} WHERE namespace = ? AND id = ? AND timestamp BETWEEN ? AND ?",
+                    month.table()
+                );
+                let (points, low, high, total, squares, last): MonthAggregate = sqlx::query_as(&sql)
+                    .bind(namespace)
+                    .bind(id)
+                    .bind(since)
+                    .bind(until)
+                    .fetch_one(&mut *conn)
+                    .await?;
+                let (Some(low), Some(high), Some(total), Some(squares), Some(last)) = (low, high, total, squares, last)
+                else {
+                    continue;
+                };
+                count += points;
+                (min, max) = (min.min(low), max.max(high));
+                (sum, sum_squares) = (sum + total, sum_squares + squares);
+                last_timestamp = last_timestamp.max(last);
+            }
+            if count == 0 {
+                return Ok(None);
+            }
+            let newest = series_points(&mut conn, &months, (namespace, id), (since, until), 1, true).await?;
+            let Some(&(_, _, latest, ..)) = newest.first() else {
+                return Ok(None);
+            };
+            Ok(Some(Aggregate {
+                count,
+                min,
+                max,
+                mean: sum / count as f64,
+                mean_square: sum_squares / count as f64,
+                latest,
+                last_timestamp,
+            }))
+        })
+    }
+
+    fn recent<'a>(&'a self, namespace: &'a str, id: &'a str, until: i64, limit: i64) -> StoreFuture<'a, Vec<MetricPoint>> {
+        traced("recent", async move {
+            let mut conn = self.pool.acquire().await?;
+            let months = months(&mut conn).await?;
+            let rows = series_points(&mut conn, &months, (namespace, id), (i64::MIN, until), limit, true).await?;
+            Ok(rows.into_iter().rev().map(to_point).collect())
+        })
+    }
+
+    fn namespaces(&self) -> StoreFuture<'_, Vec<String>> {
+        traced("namespaces", async move {
+            let mut conn = self.pool.acquire().await?;
+            let mut namespaces = BTreeSet::new();
+            for month in months(&mut conn).await? {
+                let sql = format!("SELECT DISTINCT namespace FROM {}", month.table());
+                let found: Vec<String> = sqlx::query_scalar(&sql).fetch_all(&mut *conn).await?;
+                namespaces.extend(found);
+            }
+            Ok(namespaces.into_iter().collect())
+        })
+    }
+
+    fn ids<'a>(&'a self, namespace: &'a str) -> StoreFuture<'a, Vec<String>> {
+        traced("ids", async move {
+            let mut conn = self.pool.acquire().await?;
+            let mut ids = BTreeSet::new();
+            for month in months(&mut conn).await? {
+                let sql = format!("SELECT DISTINCT id FROM {} WHERE namespace = ?", month.table());
+                let found: Vec<String> = sqlx::query_scalar(&sql).bind(namespace).fetch_all(&mut *conn).await?;
+                ids.extend(found);
+            }
+            Ok(ids.into_iter().collect())
+        })
+    }
+
+    fn namespace_summary<'a>(&'a self, namespace: &'a str) -> StoreFuture<'a, NamespaceSummary> {
+        traced("namespace_summary", async move {
+            let mut conn = self.pool.acquire().await?;
+            let months = months(&mut conn).await?;
+            let (mut ids, mut points) = (HashSet::new(), 0);
+            for month in &months {
+                let sql = format!("SELECT id, COUNT(*) FROM {} WHERE namespace = ? GROUP BY id", month.table());
+                let rows: Vec<(String, i64)> = sqlx::query_as(&sql).bind(namespace).fetch_all(&mut *conn).await?;
+                for (id, count) in rows {
+                    points += count;
+                    ids.insert(id);
+                }
+            }
+            let mut latest = None;
+            for month in months.iter().rev() {
+                let sql = format!(
+                    "SELECT point_id, id, timestamp FROM {} WHERE namespace = ?
+                     ORDER BY timestamp DESC, point_id DESC LIMIT 1",
+                    month.table()
+                );
+                let row: Option<(i64, String, i64)> = sqlx::query_as(&sql).bind(namespace).fetch_optional(&mut *conn).await?;
+                if let Some((point_id, id, timestamp)) = row {
+                    latest = Some(LatestWrite { point_id, id, timestamp });
+                    break;
+                }
+            }
+            Ok(NamespaceSummary {
+                metrics: ids.len() as i64,
+                points,
+                latest,
+            })
+        })
+    }
+
+    fn rename<'a>(&'a self, namespace: &'a str, from: &'a str, to: &'a str) -> StoreFuture<'a, Option<u64>> {
+        traced("rename", async move {
+            let mut tx = self.pool.begin().await?;
+            let months = months(&mut tx).await?;
+            if has_points_from(&mut tx, &months, (namespace, to), i64::MIN).await? {
+                return Ok(None);
+            }
+            let mut moved = 0;
+            for month in &months {
+                let sql = format!("UPDATE {} SET id = ? WHERE namespace = ? AND id = ?", month.table());
+                moved += sqlx::query(&sql)
+                    .bind(to)
+                    .bind(namespace)
+                    .bind(from)
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+            }
+            tx.commit().await?;
+            Ok(Some(moved))
+        })
+    }
+
+    fn copy_namespace<'a>(&'a self, from: &'a str, to: &'a str, since: i64) -> StoreFuture<'a, Option<u64>> {
+        traced("copy_namespace", async move {
+            let mut tx = self.pool.begin().await?;
+            let months = months(&mut tx).await?;
+            for month in &months {
+                let sql = format!("SELECT EXISTS(SELECT 1 FROM {} WHERE namespace = ?)", month.table());
+                let taken: bool = sqlx::query_scalar(&sql).bind(to).fetch_one(&mut *tx).await?;
+                if taken {
+                    return Ok(None);
+                }
+            }
+            let mut copied = 0;
+            for month in months.iter().filter(|month| month.last() >= since) {
+                let table = month.table();
+                let count: i64 = sqlx::query_scalar(&format!(
+                    "SELECT COUNT(*) FROM {table} WHERE namespace = ? AND timestamp >= ?"
+                ))
+                .bind(from)
+                .bind(since)
+                .fetch_one(&mut *tx)
+                .await?;
+                if count == 0 {
+                    continue;
+                }
+                // Copies are numbered in the order the originals were stored
+                let first = reserve_ids(&mut tx, count).await?;
+                copied += sqlx::query(&format!(
+                    "INSERT INTO {table} (point_id, namespace, id, value, timestamp, sha, branch)
+                     SELECT ? - 1 + ROW_NUMBER() OVER (ORDER BY point_id), ?, id, value, timestamp, sha, branch
+                     FROM {table} WHERE namespace = ? AND timestamp >= ?"
+                ))
+                .bind(first)
+                .bind(to)
+                .bind(from)
+                .bind(since)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+            }
+            tx.commit().await?;
+            Ok(Some(copied))
+        })
+    }
+
+    fn delete_namespace<'a>(&'a self, namespace: &'a str) -> StoreFuture<'a, u64> {
+        traced("delete_namespace", async move {
+            let mut tx = self.pool.begin().await?;
+            let deleted = delete_namespace_points(&mut tx, namespace).await?;
+            tx.commit().await?;
+            Ok(deleted)
+        })
+    }
+
+    fn settings_pool(&self) -> Option<&SqlitePool> {
+        Some(&self.pool)
+    }
+
+    fn expire<'a>(&'a self, namespace: &'a str, id: &'a str, cutoff: i64) -> StoreFuture<'a, u64> {
+        traced("expire", async move {
+            let mut tx = self.pool.begin().await?;
+            let months = months(&mut tx).await?;
+            if has_points_from(&mut tx, &months, (namespace, id), cutoff).await? {
+                return Ok(0);
+            }
+            let mut deleted = 0;
+            for month in &months {
+                let sql = format!("DELETE FROM {} WHERE namespace = ? AND id = ?", month.table());
+                deleted += sqlx::query(&sql)
+                    .bind(namespace)
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+            }
+            tx.commit().await?;
+            Ok(deleted)
+        })
+    }
+
+    fn watermark(&self) -> StoreFuture<'_, i64> {
+        traced("watermark", async move {
+            sqlx::query_scalar!("SELECT last_id FROM point_sequence")
+                .fetch_one(&self.pool)
+                .await
+        })
+    }
+
+    fn scan<'a>(&'a self, namespace: &'a str, before: i64, through: i64) -> PointStream<'a> {
+        let state = ScanState {
+            pool: &self.pool,
+            namespace,
+            before,
+            through,
+            queue: None,
+            cursor: (i64::MIN, i64::MIN),
+        };
+        stream::try_unfold(state, |mut state| async move {
+            let page = state.next_page().await.map_err(StoreError::from)?;
+            Ok::<_, StoreError>(page.map(|page| (page, state)))
+        })
+        .map_ok(|page| stream::iter(page.into_iter().map(Ok)))
+        .try_flatten()
+        .boxed()
+    }
+
+    fn prune<'a>(&'a self, namespace: &'a str, before: i64, through: i64, limit: i64) -> StoreFuture<'a, u64> {
+        traced("prune", async move {
+            let mut conn = self.pool.acquire().await?;
+            let mut pruned = 0;
+            for month in months(&mut conn).await?.into_iter().filter(|month| month.first() < before) {
+                let remaining = limit - pruned as i64;
+                if remaining <= 0 {
+                    break;
+                }
+                let table = month.table();
+                pruned += sqlx::query(&format!(
+                    "DELETE FROM {table} WHERE point_id IN (
+                         SELECT point_id FROM {table} WHERE namespace = ? AND timestamp < ? AND point_id <= ? LIMIT ?
+                     )"
+                ))
+                .bind(namespace)
+                .bind(before)
+                .bind(through)
+                .bind(remaining)
+                .execute(&mut *conn)
+                .await?
+                .rows_affected();
+            }
+            Ok(pruned)
+        })
+    }
+
+    fn drop_expired<'a>(&'a self, cutoffs: &'a HashMap<String, i64>, through: i64) -> StoreFuture<'a, HashMap<String, u64>> {
+        traced("drop_expired", async move {
+            let mut dropped = HashMap::new();
+            let Some(&latest_cutoff) = cutoffs.values().max() else {
+                return Ok(dropped);
+            };
+            let mut tx = self.pool.begin().await?;
+            let mut months = months(&mut tx).await?;
+            let mut gone = Vec::new();
+            for &month in months.iter().filter(|month| month.last() < latest_cutoff) {
+                let table = month.table();
+                let held: Vec<(String, i64, i64)> =
+                    sqlx::query_as(&format!("SELECT namespace, COUNT(*), MAX(point_id) FROM {table} GROUP BY namespace"))
+                        .fetch_all(&mut *tx)
+                        .await?;
+                let expired = held.iter().all(|(namespace, _, newest)| {
+                    *newest <= through && cutoffs.get(namespace).is_some_and(|&cutoff| month.last() < cutoff)
+                });
+                if !expired {
+                    continue;
+                }
+                sqlx::query(&format!("DROP TABLE {table}")).execute(&mut *tx).await?;
+                for (namespace, points, _) in held {
+                    *dropped.entry(namespace).or_insert(0) += points as u64;
+                }
+                gone.push(month);
+            }
+            if !gone.is_empty() {
+                months.retain(|month| !gone.contains(month));
+                recreate_view(&mut tx, &months).await?;
+            }
+            tx.commit().await?;
+            Ok(dropped)
+        })
+    }
+
+    fn first_timestamp(&self) -> StoreFuture<'_, Option<i64>> {
+        traced("first_timestamp", async move {
+            let mut conn = self.pool.acquire().await?;
+            for month in months(&mut conn).await? {
+                let sql = format!("SELECT MIN(timestamp) FROM {}", month.table());
+                let first: Option<i64> = sqlx::query_scalar(&sql).fetch_one(&mut *conn).await?;
+                if first.is_some() {
+                    return Ok(first);
+                }
+            }
+            Ok(None)
+        })
+    }
+
+    fn buckets<'a>(
+        &'a self,
+        scope: BucketScope<'a>,
+        resolution: i64,
+        since: i64,
+        until: i64,
+    ) -> StoreFuture<'a, Vec<Bucket>> {
+        traced("buckets", async move {
+            let series = match scope {
+                BucketScope::All => "",
+                BucketScope::Namespace(_) => "namespace = ? AND",
+                BucketScope::Series(..) => "namespace = ? AND id = ? AND",
+            };
+            let mut conn = self.pool.acquire().await?;
+            // Hours and days never straddle months, but buckets of other
+            // sizes can, so each month's share is added up
+            let mut buckets: BTreeMap<(String, String, i64), Bucket> = BTreeMap::new();
+            for month in months(&mut conn).await? {
+                if !month.overlaps(since, until.saturating_sub(1)) {
+                    continue;
+                }
+                let sql = format!(
+                    "SELECT namespace, id, timestamp - (timestamp % ?) AS bucket, COUNT(*), SUM(value), MIN(value), MAX(value)
+                     FROM {} WHERE {series} timestamp >= ? AND timestamp < ?
+                     GROUP BY namespace, id, bucket",
+                    month.table()
+                );
+                let mut query = sqlx::query_as::<_, (String, String, i64, i64, f64, f64, f64)>(&sql).bind(resolution);
+                match scope {
+                    BucketScope::All => {}
+                    BucketScope::Namespace(namespace) => query = query.bind(namespace),
+                    BucketScope::Series(namespace, id) => query = query.bind(namespace).bind(id),
+                }
+                let rows = query.bind(since).bind(until).fetch_all(&mut *conn).await?;
+                for (namespace, id, bucket, count, sum, min, max) in rows {
+                    buckets
+                        .entry((namespace.clone(), id.clone(), bucket))
+                        .and_modify(|rolled| {
+                            rolled.count += count;
+                            rolled.sum += sum;
+                            rolled.min = rolled.min.min(min);
+                            rolled.max = rolled.max.max(max);
+                        })
+                        .or_insert(Bucket {
+                            namespace,
+                            id,
+                            bucket,
+                            count,
+                            sum,
+                            min,
+                            max,
+                        });
+                }
+            }
+            Ok(buckets.into_values().collect())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use super::*;
+
+    // 2024-01-15, 2024-03-15 and 2024-04-15, UTC
+    const JANUARY: i64 = 1_705_276_800;
+    const MARCH: i64 = 1_710_460_800;
+    const APRIL: i64 = 1_713_139_200;
+
+    async fn store() -> SqliteStore {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+        SqliteStore::new(pool)
+    }
+
+    async fn insert(store: &SqliteStore, namespace: &str, timestamps: &[i64]) {
+        let points: Vec<(&str, MetricPoint)> = timestamps
+            .iter()
+            .map(|&timestamp| {
+                let point = MetricPoint {
+                    timestamp,
+                    value: timestamp as f64,
+                    sha: None,
+                    branch: None,
+                };
+                ("build_time", point)
+            })
+            .collect();
+        store.insert(namespace, &points).await.unwrap();
+    }
+
+    async fn tables(store: &SqliteStore) -> Vec<String> {
+        let mut conn = store.pool.acquire().await.unwrap();
+        months(&mut conn).await.unwrap().into_iter().map(Month::table).collect()
+    }
+
+    #[test]
+    fn months_cover_every_timestamp() {
+        assert_eq!(Month::of(0), Month { year: 1970, month: 1 });
+        assert_eq!(Month::of(JANUARY).table(), "points_202401");
+        assert_eq!(Month::of(i64::MIN), Month::FIRST);
+        assert_eq!(Month::of(i64::MAX), Month::LAST);
+        assert_eq!(Month::parse("points_202401"), Some(Month::of(JANUARY)));
+        assert_eq!(Month::parse("points_202413"), None);
+        assert_eq!(Month::parse("points_2024011"), None);
+
+        let december = Month { year: 2023, month: 12 };
+        assert_eq!(december.next(), Month::of(JANUARY));
+        assert_eq!(december.last() + 1, Month::of(JANUARY).first());
+        assert_eq!((Month::FIRST.first(), Month::LAST.last()), (i64::MIN, i64::MAX));
+        assert!(Month::of(MARCH).overlaps(JANUARY, MARCH));
+        assert!(!Month::of(APRIL).overlaps(JANUARY, MARCH));
+    }
+
+    #[tokio::test]
+    async fn points_go_in_their_months_table() {
+        let store = store().await;
+        insert(&store, "ci", &[MARCH, JANUARY, MARCH + 60]).await;
+        assert_eq!(tables(&store).await, ["points_202401", "points_202403"]);
+
+        let range = store.range("ci", "build_time", JANUARY, MARCH, 10).await.unwrap();
+        let timestamps: Vec<i64> = range.iter().map(|point| point.timestamp).collect();
+        assert_eq!(timestamps, [JANUARY, MARCH]);
+        assert_eq!(store.latest("ci", "build_time").await.unwrap().unwrap().timestamp, MARCH + 60);
+        // Queries read every month through the view
+        let viewed: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM metrics").fetch_one(&store.pool).await.unwrap();
+        assert_eq!(viewed, 3);
+    }
+
+    #[tokio::test]
+    async fn retention_drops_months_only_once_no_one_needs_them() {
+        let store = store().await;
+        insert(&store, "ci", &[JANUARY, MARCH, APRIL]).await;
+        insert(&store, "kept", &[JANUARY]).await;
+
+        // January still holds a namespace without a cutoff, and April is current
+        let cutoffs = HashMap::from([("ci".to_string(), APRIL)]);
+        let dropped = store.drop_expired(&cutoffs, i64::MAX).await.unwrap();
+        assert_eq!(dropped, HashMap::from([("ci".to_string(), 1)]));
+        assert_eq!(tables(&store).await, ["points_202401", "points_202404"]);
+
+        // What's left goes point by point
+        assert_eq!(store.prune("ci", APRIL, i64::MAX, 100).await.unwrap(), 1);
+        let left = store.range("ci", "build_time", i64::MIN, i64::MAX, 10).await.unwrap();
+        assert_eq!(left.iter().map(|point| point.timestamp).collect::<Vec<_>>(), [APRIL]);
+        assert!(store.latest("kept", "build_time").await.unwrap().is_some());
+        let viewed: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM metrics").fetch_one(&store.pool).await.unwrap();
+        assert_eq!(viewed, 2);
+    }
+
+    #[tokio::test]
+    async fn months_with_points_newer_than_the_watermark_stay() {
+        let store = store().await;
+        insert(&store, "ci", &[JANUARY]).await;
+        let through = store.watermark().await.unwrap();
+        insert(&store, "ci", &[JANUARY + 60]).await;
+
+        let cutoffs = HashMap::from([("ci".to_string(), APRIL)]);
+        assert!(store.drop_expired(&cutoffs, through).await.unwrap().is_empty());
+        assert_eq!(tables(&store).await, ["points_202401"]);
+    }
+}
@@ -0,0 +1,50 @@
+//! Metric id autocompletion for the namespace page's quick-jump box.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::Deserialize;
+
+use crate::{errors, ids::NamespacePath, store::MetricStore};
+
+const DEFAULT_LIMIT: i64 = 10;
+const MAX_LIMIT: i64 = 50;
+
+#[derive(Deserialize)]
+pub struct SuggestQuery {
+    #[serde(default)]
+    q: String,
+    limit: Option<i64>,
+}
+
+/// Ids in the namespace containing `q`, ignoring ASCII case. Ids starting
+/// with it come first, then the rest alphabetically.
+pub async fn get_suggestions(
+    NamespacePath(namespace): NamespacePath,
+    Query(query): Query<SuggestQuery>,
+    State(store): State<Arc<dyn MetricStore>>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let term = query.q.trim().to_ascii_lowercase();
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT) as usize;
+
+    let mut ids: Vec<(bool, String)> = store
+        .ids(&namespace)
+        .await
+        .map_err(errors::internal)?
+        .into_iter()
+        .filter_map(|id| {
+            let lower = id.to_ascii_lowercase();
+            lower.contains(&term).then(|| (!lower.starts_with(&term), id))
+        })
+        .collect();
+    // Ids come alphabetically, so a stable sort keeps them that way after the prefix matches
+    ids.sort_by_key(|(later, _)| *later);
+    let ids: Vec<String> = ids.into_iter().take(limit).map(|(_, id)| id).collect();
+
+    Ok(Json(ids))
+}
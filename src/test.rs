@@ -0,0 +1,191 @@
+//! Helpers for exercising the full application in integration tests.
+//!
+//! ```no_run
+//! # async fn example() {
+//! use somnial::test::TestServer;
+//!
+//! let server = TestServer::new().await;
+//! server.seed("ci", "build_time", &[(1_700_000_000, 41.0), (1_700_000_060, 42.0)]).await;
+//!
+//! let page = server.get("/ci/build_time").await;
+//! assert_eq!(page.status, 200);
+//! assert!(page.text().contains("build_time"));
+//!
+//! let badge = server.get("/ci/build_time/badge.png").await;
+//! assert_eq!(badge.header("content-type"), Some("image/png"));
+//! # }
+//! ```
+
+use std::sync::Arc;
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    http::{HeaderMap, Method, Request, StatusCode},
+    Router,
+};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use tower::ServiceExt;
+
+use crate::{
+    config::{Config, Sources},
+    router,
+    store::MetricStore,
+    AppState, MetricPoint,
+};
+
+/// The application router backed by a private in-memory database.
+///
+/// Requests are dispatched straight into the router, so no port is bound
+/// and servers can be created freely in parallel tests.
+pub struct TestServer {
+    router: Router,
+    state: AppState,
+}
+
+/// A fully buffered response.
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl TestResponse {
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    pub fn json<T: serde::de::DeserializeOwned>(&self) -> T {
+        serde_json::from_slice(&self.body).expect("response body is not valid JSON")
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|v| v.to_str().ok())
+    }
+}
+
+impl TestServer {
+    /// Starts a server with the default configuration.
+    pub async fn new() -> Self {
+        Self::with_config(Config::default()).await
+    }
+
+    /// Starts a server with `config`; its `database_url` is ignored in favour
+    /// of a fresh in-memory database.
+    pub async fn with_config(config: Config) -> Self {
+        let state = Self::state(config).await;
+        TestServer {
+            router: router(state.clone()),
+            state,
+        }
+    }
+
+    /// Starts a server with `config` that keeps points in `store`, for
+    /// exercising a [`MetricStore`] through the whole application.
+    pub async fn with_store(config: Config, store: Arc<dyn MetricStore>) -> Self {
+        let state = Self::state(config)
+            .await
+            .with_store(store)
+            .await
+            .expect("failed to load state from the store");
+        TestServer {
+            router: router(state.clone()),
+            state,
+        }
+    }
+
+    async fn state(config: Config) -> AppState {
+        // Each `sqlite::memory:` parse names a new shared-cache database, which
+        // lives as long as one connection to it stays open.
+        let options: SqliteConnectOptions = "sqlite::memory:".parse().unwrap();
+        let pool = SqlitePoolOptions::new()
+            .min_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(options.clone())
+            .await
+            .expect("failed to open in-memory database");
+        let read_only_pool = SqlitePoolOptions::new()
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(options.read_only(true))
+            .await
+            .expect("failed to open read-only connection");
+
+        AppState::new(pool, read_only_pool, config, Sources::default())
+            .await
+            .expect("failed to migrate in-memory database")
+    }
+
+    /// The underlying database, for assertions the HTTP surface doesn't expose.
+    pub fn pool(&self) -> &SqlitePool {
+        &self.state.pool
+    }
+
+    /// Where the server keeps points.
+    pub fn store(&self) -> &dyn MetricStore {
+        self.state.store.as_ref()
+    }
+
+    /// The server's state, for unit tests of background work no request
+    /// triggers.
+    #[cfg(test)]
+    pub(crate) fn app_state(&self) -> &AppState {
+        &self.state
+    }
+
+    /// Inserts `(timestamp, value)` points directly, bypassing ingestion.
+    pub async fn seed(&self, namespace: &str, id: &str, points: &[(i64, f64)]) {
+        let points: Vec<(&str, MetricPoint)> = points
+            .iter()
+            .map(|&(timestamp, value)| {
+                let point = MetricPoint {
+                    timestamp,
+                    value,
+                    sha: None,
+                    branch: None,
+                };
+                (id, point)
+            })
+            .collect();
+        self.state.store.insert(namespace, &points).await.expect("failed to seed point");
+    }
+
+    pub async fn get(&self, uri: &str) -> TestResponse {
+        self.send(Method::GET, uri, Body::empty()).await
+    }
+
+    pub async fn post(&self, uri: &str, body: impl Into<Body>) -> TestResponse {
+        self.send(Method::POST, uri, body.into()).await
+    }
+
+    pub async fn send(&self, method: Method, uri: &str, body: Body) -> TestResponse {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(body)
+            .expect("invalid request");
+        self.request(request).await
+    }
+
+    /// Dispatches an arbitrary request, e.g. one carrying custom headers.
+    pub async fn request(&self, request: Request<Body>) -> TestResponse {
+        let response = self
+            .router
+            .clone()
+            .oneshot(request)
+            .await
+            .expect("router is infallible");
+
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("failed to read response body");
+
+        TestResponse {
+            status,
+            headers,
+            body,
+        }
+    }
+}
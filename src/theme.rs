@@ -0,0 +1,149 @@
+//! Page colour schemes. Each viewer's choice is kept in a cookie set by
+//! `POST /preferences` (which also keeps their timezone, see `tz`); without
+//! one, pages use the custom domain's theme and then the server-wide
+//! `DEFAULT_THEME`.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{FromRef, FromRequestParts, State},
+    http::{request::Parts, HeaderMap, StatusCode},
+    response::{AppendHeaders, IntoResponse, Response},
+    Form,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, domains::Domain, tz, BasePath};
+
+const THEME_COOKIE: &str = "theme";
+/// Preference cookies last a year.
+const COOKIE_MAX_AGE: i64 = 365 * 86400;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PageTheme {
+    #[default]
+    Light,
+    Dark,
+    /// Black on white with solid borders, for low vision and glare
+    HighContrast,
+}
+
+impl PageTheme {
+    pub const ALL: &'static [PageTheme] = &[PageTheme::Light, PageTheme::Dark, PageTheme::HighContrast];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PageTheme::Light => "light",
+            PageTheme::Dark => "dark",
+            PageTheme::HighContrast => "high-contrast",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|theme| theme.as_str() == name)
+    }
+}
+
+impl std::str::FromStr for PageTheme {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, ()> {
+        Self::parse(name).ok_or(())
+    }
+}
+
+/// A named cookie from the request, if present.
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all("cookie")
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// The theme this request's page should use.
+pub struct ViewerTheme(pub PageTheme);
+
+impl<S> FromRequestParts<S> for ViewerTheme
+where
+    S: Send + Sync,
+    Arc<Config>: FromRef<S>,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let theme = cookie(&parts.headers, THEME_COOKIE)
+            .and_then(PageTheme::parse)
+            .or_else(|| parts.extensions.get::<Domain>().map(|domain| domain.theme))
+            .unwrap_or_else(|| Arc::<Config>::from_ref(state).default_theme);
+        Ok(ViewerTheme(theme))
+    }
+}
+
+/// Fields left out of the form keep their current value.
+#[derive(Deserialize)]
+pub struct PreferencesForm {
+    /// A theme name, or empty to go back to the site default
+    theme: Option<String>,
+    /// A UTC offset such as `+05:30`, or empty to go back to the default
+    tz: Option<String>,
+}
+
+fn set_cookie(name: &str, value: &str, base_path: &str) -> String {
+    format!("{}={}; Path={}/; Max-Age={}; SameSite=Lax", name, value, base_path, COOKIE_MAX_AGE)
+}
+
+fn clear_cookie(name: &str, base_path: &str) -> String {
+    format!("{}=; Path={}/; Max-Age=0; SameSite=Lax", name, base_path)
+}
+
+/// Where to send the viewer afterwards: back to the page they came from
+/// when it is on this host, otherwise home.
+fn return_path(headers: &HeaderMap, base_path: &str) -> String {
+    let host = headers.get("host").and_then(|v| v.to_str().ok());
+    headers
+        .get("referer")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|referer| referer.split_once("://"))
+        .and_then(|(_, rest)| {
+            let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+            (Some(authority) == host).then(|| path.to_string())
+        })
+        .filter(|path| path.starts_with('/') && !path.starts_with("//"))
+        .unwrap_or_else(|| format!("{}/", base_path))
+}
+
+/// Sets (or clears) the viewer's theme and timezone cookies and redirects back.
+pub async fn post_preferences(
+    State(base_path): State<BasePath>,
+    headers: HeaderMap,
+    Form(form): Form<PreferencesForm>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    let mut cookies = Vec::new();
+    match form.theme.as_deref() {
+        None => {}
+        Some("") => cookies.push(("set-cookie", clear_cookie(THEME_COOKIE, &base_path))),
+        Some(name) => {
+            let theme = PageTheme::parse(name)
+                .ok_or((StatusCode::BAD_REQUEST, "theme must be light, dark or high-contrast"))?;
+            cookies.push(("set-cookie", set_cookie(THEME_COOKIE, theme.as_str(), &base_path)));
+        }
+    }
+    match form.tz.as_deref() {
+        None => {}
+        Some("") => cookies.push(("set-cookie", clear_cookie(tz::TZ_COOKIE, &base_path))),
+        Some(value) => {
+            let zone = tz::parse(value).ok_or((StatusCode::BAD_REQUEST, tz::INVALID))?;
+            cookies.push(("set-cookie", set_cookie(tz::TZ_COOKIE, &zone.name(), &base_path)));
+        }
+    }
+    Ok((
+        StatusCode::SEE_OTHER,
+        AppendHeaders(cookies),
+        [("location", return_path(&headers, &base_path))],
+    ))
+}
@@ -0,0 +1,237 @@
+//! Optional HTTPS without a reverse proxy. Once `TLS_CERT_FILE` and
+//! `TLS_KEY_FILE` name a PEM certificate chain and private key, the server
+//! speaks TLS, over rustls, on its usual address instead of plain HTTP.
+//! Sending the process `SIGHUP` reads both files again, so a renewed
+//! certificate is picked up by new connections without a restart; if the
+//! new files don't load, the old certificate stays in use.
+//!
+//! Handshakes run through tokio-rustls on their own tasks, under a timeout,
+//! so a client that connects and says nothing doesn't hold up anyone
+//! else's; past `MAX_HANDSHAKES` at once, new connections wait to be
+//! accepted. Clients can speak HTTP/2 or HTTP/1.1, agreed on with ALPN.
+
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use rustls::ServerConfig;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Semaphore};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+
+/// How long a client gets to finish its handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Finished handshakes waiting for the server to pick them up at most
+const MAX_READY_CONNECTIONS: usize = 64;
+/// Handshakes in progress at most
+const MAX_HANDSHAKES: usize = 256;
+
+/// Where the certificate and key are read from.
+#[derive(Clone, Debug)]
+pub struct TlsFiles {
+    /// PEM certificate chain, the server's own certificate first
+    pub cert_file: PathBuf,
+    /// PEM private key, in PKCS#8, PKCS#1 or SEC1 form
+    pub key_file: PathBuf,
+}
+
+impl TlsFiles {
+    /// Reads both files into a rustls configuration.
+    pub fn load(&self) -> Result<Arc<ServerConfig>, String> {
+        let chain = rustls_pemfile::certs(&mut open(&self.cert_file)?)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| format!("couldn't read a certificate from {}: {}", self.cert_file.display(), err))?;
+        if chain.is_empty() {
+            return Err(format!("{} holds no certificates", self.cert_file.display()));
+        }
+        let key = rustls_pemfile::private_key(&mut open(&self.key_file)?)
+            .map_err(|err| format!("couldn't read a private key from {}: {}", self.key_file.display(), err))?
+            .ok_or_else(|| format!("{} holds no private key", self.key_file.display()))?;
+        let mut config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|err| err.to_string())?
+            .with_no_client_auth()
+            .with_single_cert(chain, key)
+            .map_err(|err| {
+                format!("{} doesn't go with {}: {}", self.key_file.display(), self.cert_file.display(), err)
+            })?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(Arc::new(config))
+    }
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, String> {
+    let file = File::open(path).map_err(|err| format!("couldn't open {}: {}", path.display(), err))?;
+    Ok(BufReader::new(file))
+}
+
+/// Accepts connections and hands them on once their handshake is done, for
+/// `axum::serve`.
+pub struct TlsListener {
+    ready: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+    local_addr: SocketAddr,
+}
+
+impl TlsListener {
+    /// Starts accepting on `tcp`, and reloading `files` on `SIGHUP`. It
+    /// stops accepting, closing `tcp`, once the listener is dropped.
+    pub fn new(tcp: TcpListener, files: TlsFiles) -> Result<TlsListener, String> {
+        let config = Arc::new(RwLock::new(files.load()?));
+        let local_addr = tcp.local_addr().map_err(|err| err.to_string())?;
+        reload_on_hangup(files, config.clone());
+
+        let (sender, ready) = mpsc::channel(MAX_READY_CONNECTIONS);
+        let handshakes = Arc::new(Semaphore::new(MAX_HANDSHAKES));
+        tokio::spawn(async move {
+            loop {
+                // The server has shut down once nothing is taking connections
+                let permit = tokio::select! {
+                    _ = sender.closed() => break,
+                    permit = handshakes.clone().acquire_owned() => permit.expect("the semaphore is never closed"),
+                };
+                let accepted = tokio::select! {
+                    _ = sender.closed() => break,
+                    accepted = tcp.accept() => accepted,
+                };
+                let (tcp, peer) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        // Usually out of file descriptors; give some a chance to close
+                        tracing::warn!("Couldn't accept a connection: {}", err);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                };
+                let acceptor = TlsAcceptor::from(config.read().unwrap().clone());
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    let _permit = permit;
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(tcp)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = sender.send((stream, peer)).await;
+                        }
+                        Ok(Err(err)) => tracing::debug!("TLS handshake with {} failed: {}", peer, err),
+                        Err(_) => tracing::debug!("TLS handshake with {} timed out", peer),
+                    }
+                });
+            }
+        });
+        Ok(TlsListener { ready, local_addr })
+    }
+}
+
+impl axum::serve::Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (TlsStream<TcpStream>, SocketAddr) {
+        match self.ready.recv().await {
+            Some(connection) => connection,
+            // The accepting task only stops if it panicked
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+}
+
+#[cfg(unix)]
+fn reload_on_hangup(files: TlsFiles, config: Arc<RwLock<Arc<ServerConfig>>>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(err) => {
+            tracing::warn!("Couldn't listen for SIGHUP, so the TLS certificate won't be reloaded: {}", err);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            match files.load() {
+                Ok(loaded) => {
+                    *config.write().unwrap() = loaded;
+                    tracing::info!("Reloaded the TLS certificate from {}", files.cert_file.display());
+                }
+                Err(err) => tracing::error!("Kept the old TLS certificate: {}", err),
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn reload_on_hangup(_files: TlsFiles, _config: Arc<RwLock<Arc<ServerConfig>>>) {}
+
+#[cfg(test)]
+mod tests {
+    use rustls::pki_types::ServerName;
+    use tokio_rustls::TlsConnector;
+
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+    }
+
+    fn files(cert: &str, key: &str) -> TlsFiles {
+        TlsFiles {
+            cert_file: fixture(cert),
+            key_file: fixture(key),
+        }
+    }
+
+    #[test]
+    fn loading_says_which_file_is_wrong() {
+        assert!(files("localhost.crt", "localhost.key").load().is_ok());
+
+        let err = files("missing.crt", "localhost.key").load().unwrap_err();
+        assert!(err.starts_with("couldn't open ") && err.contains("missing.crt"), "{}", err);
+        let err = files("localhost.key", "localhost.key").load().unwrap_err();
+        assert!(err.ends_with("localhost.key holds no certificates"), "{}", err);
+        let err = files("localhost.crt", "localhost.crt").load().unwrap_err();
+        assert!(err.ends_with("localhost.crt holds no private key"), "{}", err);
+        let err = files("localhost.crt", "other.key").load().unwrap_err();
+        assert!(err.contains("other.key doesn't go with "), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn hands_on_connections_after_the_handshake() {
+        let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut listener = TlsListener::new(tcp, files("localhost.crt", "localhost.key")).unwrap();
+        let address = axum::serve::Listener::local_addr(&listener).unwrap();
+
+        let mut roots = rustls::RootCertStore::empty();
+        for cert in rustls_pemfile::certs(&mut open(&fixture("localhost.crt")).unwrap()) {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        let mut config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        config.alpn_protocols = vec![b"h2".to_vec()];
+        let client = tokio::spawn(async move {
+            let tcp = TcpStream::connect(address).await.unwrap();
+            let name = ServerName::try_from("localhost").unwrap();
+            TlsConnector::from(Arc::new(config)).connect(name, tcp).await.unwrap()
+        });
+
+        // A client that never starts its handshake doesn't hold up the next
+        let _silent = TcpStream::connect(address).await.unwrap();
+        let accepted = axum::serve::Listener::accept(&mut listener);
+        let (stream, peer) = tokio::time::timeout(Duration::from_secs(5), accepted).await.unwrap();
+        assert_eq!(peer.ip(), address.ip());
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+        client.await.unwrap();
+
+        // Dropping the listener, as shutting down does, closes the port
+        drop(listener);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(TcpStream::connect(address).await.is_err());
+    }
+}
@@ -0,0 +1,143 @@
+//! Per-token bookkeeping for writes, so an integrator can ask whether their
+//! points are landing and, if not, why.
+//!
+//! Writes don't need a token, but any `Authorization: Bearer` value sent with
+//! one is used to group its outcomes. Nothing is persisted: the counts start
+//! again from zero when the server restarts.
+
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use serde::Serialize;
+
+use crate::{auth, AppState};
+
+/// Tokens beyond this many are forgotten, least recently seen first.
+const MAX_TOKENS: usize = 10_000;
+/// Error bodies longer than this are cut short in `last_error`.
+const MAX_REASON_BYTES: usize = 512;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct WriteError {
+    pub status: u16,
+    pub reason: String,
+    pub path: String,
+    pub at: i64,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TokenStatus {
+    pub accepted: u64,
+    pub rejected: u64,
+    pub last_accepted_at: Option<i64>,
+    pub last_error: Option<WriteError>,
+    #[serde(skip)]
+    last_seen: i64,
+}
+
+#[derive(Default)]
+pub struct TokenLog {
+    tokens: Mutex<HashMap<String, TokenStatus>>,
+}
+
+impl TokenLog {
+    fn record(&self, token: &str, error: Option<WriteError>) {
+        let now = Utc::now().timestamp();
+        let mut tokens = self.tokens.lock().unwrap();
+        if !tokens.contains_key(token) && tokens.len() >= MAX_TOKENS
+            && let Some(oldest) = tokens
+                .iter()
+                .min_by_key(|(_, status)| status.last_seen)
+                .map(|(token, _)| token.clone())
+        {
+            tokens.remove(&oldest);
+        }
+
+        let status = tokens.entry(token.to_string()).or_default();
+        status.last_seen = now;
+        match error {
+            Some(error) => {
+                status.rejected += 1;
+                status.last_error = Some(error);
+            }
+            None => {
+                status.accepted += 1;
+                status.last_accepted_at = Some(now);
+            }
+        }
+    }
+
+    pub fn get(&self, token: &str) -> Option<TokenStatus> {
+        self.tokens.lock().unwrap().get(token).cloned()
+    }
+}
+
+/// Why a write was refused: the response body if it has one, otherwise the
+/// status line.
+fn reason(status: StatusCode, body: &[u8]) -> String {
+    let text = String::from_utf8_lossy(body);
+    let text = text.trim();
+    if text.is_empty() {
+        return status.canonical_reason().unwrap_or("error").to_string();
+    }
+    let mut end = text.len().min(MAX_REASON_BYTES);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text[..end].to_string()
+}
+
+/// Counts the outcome of a write against the server totals and, when the
+/// request carries a bearer token, against that token.
+pub async fn track(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let token = auth::bearer_token(request.headers())
+        .filter(|token| !token.is_empty())
+        .map(str::to_string);
+    let path = request.uri().path().to_string();
+    let response = next.run(request).await;
+
+    let status = response.status();
+    if status.is_success() {
+        state.metrics.ingest_accepted.fetch_add(1, Ordering::Relaxed);
+        if let Some(token) = token {
+            state.tokens.record(&token, None);
+        }
+        return response;
+    }
+
+    state.metrics.ingest_rejected.fetch_add(1, Ordering::Relaxed);
+    let Some(token) = token else {
+        return response;
+    };
+    let (parts, body) = response.into_parts();
+    let body = to_bytes(body, usize::MAX).await.unwrap_or_default();
+    let error = WriteError {
+        status: status.as_u16(),
+        reason: reason(status, &body),
+        path,
+        at: Utc::now().timestamp(),
+    };
+    state.tokens.record(&token, Some(error));
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// Write outcomes for the token the request is authenticated with.
+pub async fn get_self_status(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    let token = auth::bearer_token(&headers)
+        .filter(|token| !token.is_empty())
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    Ok(Json(state.tokens.get(token).unwrap_or_default()))
+}
@@ -0,0 +1,196 @@
+//! Optional trace export over OTLP/HTTP to any OpenTelemetry collector or
+//! backend, through `tracing-opentelemetry`. Exporting starts once
+//! `OTEL_EXPORTER_OTLP_ENDPOINT` (the collector's base URL) or
+//! `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT` (the full `/v1/traces` URL) is set;
+//! `OTEL_EXPORTER_OTLP_HEADERS` adds headers such as an API key, written
+//! `name=value,name=value`, and `OTEL_SERVICE_NAME` renames the service.
+//!
+//! Each request is a server span named after its route, with a child span
+//! for every store query, badge render and page template it ran, so a slow
+//! request shows whether the time went on the database, resvg or askama.
+//! A `traceparent` header from upstream is continued, and followed on
+//! whether to sample; otherwise `OTEL_TRACES_SAMPLER_ARG` is the share of
+//! requests traced, 1 by default. The SDK sends finished spans in batches
+//! and drops them if the collector falls far behind.
+//!
+//! The spans exported are only the ones started here, which the log lines
+//! leave out; the rest of the server's `tracing` spans are for logging.
+
+use std::sync::OnceLock;
+
+use axum::{
+    extract::{MatchedPath, Request},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response,
+};
+use opentelemetry::{
+    propagation::{Extractor, TextMapPropagator},
+    trace::{TraceContextExt, TracerProvider},
+    KeyValue,
+};
+use opentelemetry_otlp::{Protocol, SpanExporter, WithExportConfig, WithHttpConfig};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    trace::{Sampler, SdkTracerProvider},
+    Resource,
+};
+use tracing::{field::Empty, Instrument, Metadata, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{filter::filter_fn, registry::LookupSpan, Layer};
+
+use crate::request_log;
+
+/// Where spans go and which requests are traced.
+#[derive(Clone, Debug)]
+pub struct TraceExport {
+    /// The full URL, ending `/v1/traces` for a standard collector
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub service_name: String,
+    /// Share of requests without a sampled parent that are traced
+    pub sample_ratio: f64,
+}
+
+/// Kept for flushing on shutdown
+static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+/// Whether `metadata` is one of the spans exported, which are all started
+/// in this module.
+pub fn is_trace_span(metadata: &Metadata) -> bool {
+    metadata.is_span() && metadata.target() == module_path!()
+}
+
+/// The `tracing` layer that exports the spans started here to `config`'s
+/// collector.
+pub fn layer<S>(config: &TraceExport) -> Result<impl Layer<S>, Box<dyn std::error::Error>>
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_protocol(Protocol::HttpBinary)
+        .with_endpoint(config.url.clone())
+        .with_headers(config.headers.iter().cloned().collect())
+        .build()?;
+    let resource = Resource::builder()
+        .with_service_name(config.service_name.clone())
+        .with_attribute(KeyValue::new("service.version", env!("CARGO_PKG_VERSION")))
+        .build();
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio))))
+        .with_resource(resource)
+        .build();
+    let tracer = provider.tracer("somnial");
+    let _ = PROVIDER.set(provider);
+    Ok(tracing_opentelemetry::layer()
+        .with_tracer(tracer)
+        .with_location(false)
+        .with_threads(false)
+        .with_target(false)
+        .with_tracked_inactivity(false)
+        .with_filter(filter_fn(is_trace_span)))
+}
+
+/// Whether this is part of a request being traced; work outside one, such
+/// as the background jobs', isn't.
+fn in_trace() -> bool {
+    opentelemetry::Context::current().has_active_span()
+}
+
+/// A span for work done in-process, such as rendering.
+pub fn span(name: &'static str) -> Span {
+    if !in_trace() {
+        return Span::none();
+    }
+    tracing::info_span!("span", otel.name = name)
+}
+
+/// A span for a database query, named after the store operation, which
+/// [`fail`] marks if the query fails.
+pub fn query(system: &'static str, operation: &'static str) -> Span {
+    if !in_trace() {
+        return Span::none();
+    }
+    tracing::info_span!(
+        "query",
+        otel.name = format!("store.{}", operation),
+        otel.kind = "client",
+        db.system.name = system,
+        db.operation.name = operation,
+        otel.status_code = Empty
+    )
+}
+
+/// Marks the span's work as having failed.
+pub fn fail(span: &Span) {
+    span.record("otel.status_code", "ERROR");
+}
+
+/// Renders a page template inside a span saying which template it was.
+pub fn render<T: askama::Template>(template: &T) -> askama::Result<String> {
+    if !in_trace() {
+        return template.render();
+    }
+    let name = std::any::type_name::<T>().rsplit("::").next().unwrap_or_default();
+    let _span = tracing::info_span!("askama.render", template = name).entered();
+    template.render()
+}
+
+/// Reads a `traceparent` from the request's headers.
+struct Headers<'a>(&'a HeaderMap);
+
+impl Extractor for Headers<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+/// Traces a request as a server span named after its route, which spans
+/// started while handling it are children of.
+pub async fn trace_request(request: Request, next: Next) -> Response {
+    let route = request.extensions().get::<MatchedPath>().map(|path| path.as_str().to_string());
+    let method = request.method().to_string();
+    let name = match &route {
+        Some(route) => format!("{} {}", method, route),
+        None => method.clone(),
+    };
+    let span = tracing::info_span!(
+        "request",
+        otel.name = name,
+        otel.kind = "server",
+        http.request.method = method,
+        url.path = request.uri().path(),
+        http.route = route,
+        http.request.id = request_log::current_id(),
+        http.response.status_code = Empty,
+        otel.status_code = Empty
+    );
+    let parent = TraceContextPropagator::new().extract(&Headers(request.headers()));
+    // Only fails when nothing is exporting
+    let _ = span.set_parent(parent);
+
+    let response = next.run(request).instrument(span.clone()).await;
+    let status = response.status().as_u16();
+    span.record("http.response.status_code", status);
+    if status >= 500 {
+        fail(&span);
+    }
+    response
+}
+
+/// Sends the spans still queued, for shutting down.
+pub async fn flush() {
+    let Some(provider) = PROVIDER.get().cloned() else {
+        return;
+    };
+    // The exporter's client blocks
+    if let Ok(Err(err)) = tokio::task::spawn_blocking(move || provider.force_flush()).await {
+        tracing::warn!("Couldn't export the last spans: {}", err);
+    }
+}
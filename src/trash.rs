@@ -0,0 +1,143 @@
+//! Undoable metric deletes. With `DELETE_GRACE_DAYS` set (the default),
+//! `DELETE /{namespace}/{id}` moves the series' points into the trash
+//! instead of dropping them, and admins can bring it back with
+//! `POST /admin/deleted/{namespace}/{id}/restore` until the grace period is
+//! up. The retention task empties the trash as series age out of it.
+//!
+//! Trashed points are out of the point tables, so nothing that reads a
+//! series sees them. The metric's settings stay put until it is purged, and
+//! are only dropped then if nothing has been written to it since.
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{
+    auth,
+    ids::SeriesPath,
+    rollup,
+    store::{StoreError, TrashedSeries},
+    AppState,
+};
+
+const DAY: i64 = 86400;
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(json!({ "error": message })))
+}
+
+fn database_error<E: std::fmt::Display>(err: E) -> ApiError {
+    crate::errors::report(&err);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+#[derive(Serialize)]
+struct Deleted {
+    #[serde(flatten)]
+    series: TrashedSeries,
+    /// When the series is deleted for good
+    purge_at: i64,
+}
+
+/// Moves a series into the trash and returns how many points it had.
+pub async fn trash_metric(state: &AppState, namespace: &str, id: &str) -> Result<u64, StoreError> {
+    let now = Utc::now().timestamp();
+    let moved = state.write_store(|| state.store.trash(namespace, id, now)).await?;
+    // Rollups would otherwise keep drawing the old points
+    let pool = &state.pool;
+    state
+        .write(|| async move {
+            sqlx::query!("DELETE FROM metric_rollups WHERE namespace = ? AND id = ?", namespace, id)
+                .execute(pool)
+                .await
+        })
+        .await?;
+    state.invalidate_series(namespace, id);
+    Ok(moved)
+}
+
+pub async fn list_deleted(State(state): State<AppState>, headers: HeaderMap) -> Result<impl IntoResponse, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+
+    let grace = i64::from(state.config().delete_grace_days.unwrap_or(0)) * DAY;
+    let deleted: Vec<Deleted> = state
+        .store
+        .trashed()
+        .await
+        .map_err(database_error)?
+        .into_iter()
+        .map(|series| Deleted {
+            purge_at: series.deleted_at + grace,
+            series,
+        })
+        .collect();
+    Ok(Json(json!({ "deleted": deleted })))
+}
+
+/// Puts a trashed series back. Points written to it since it was deleted
+/// are kept alongside the restored ones.
+pub async fn restore_deleted(
+    SeriesPath(namespace, id): SeriesPath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+
+    let (namespace, id) = (&namespace, &id);
+    let timestamps = state
+        .write_store(|| state.store.untrash(namespace, id))
+        .await
+        .map_err(database_error)?
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "no deleted metric with that id"))?;
+    state.invalidate_series(namespace, id);
+    rollup::rebuild_buckets(&state, namespace, id, &timestamps)
+        .await
+        .map_err(database_error)?;
+
+    Ok(Json(json!({ "namespace": namespace, "id": id, "points": timestamps.len() })))
+}
+
+/// Permanently deletes series whose grace period is over, along with their
+/// settings, and returns how many went.
+pub async fn empty(state: &AppState) -> Result<u64, StoreError> {
+    let Some(days) = state.config().delete_grace_days else {
+        return Ok(0);
+    };
+    let cutoff = Utc::now().timestamp() - i64::from(days) * DAY;
+    let purged = state.write_store(|| state.store.empty_trash(cutoff)).await?;
+
+    let pool = &state.pool;
+    for (namespace, id) in &purged {
+        // A metric written to again since has a life of its own
+        if state.store.latest(namespace, id).await?.is_some() {
+            continue;
+        }
+        state
+            .write(|| async move {
+                let mut tx = pool.begin().await?;
+                sqlx::query!("DELETE FROM metric_meta WHERE namespace = ? AND id = ?", namespace, id)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query!("DELETE FROM metric_precision WHERE namespace = ? AND id = ?", namespace, id)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query!("DELETE FROM metric_point_caps WHERE namespace = ? AND id = ?", namespace, id)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query!("DELETE FROM github_baselines WHERE namespace = ? AND id = ?", namespace, id)
+                    .execute(&mut *tx)
+                    .await?;
+                tx.commit().await
+            })
+            .await?;
+    }
+    Ok(purged.len() as u64)
+}
@@ -0,0 +1,127 @@
+//! Trend series drawn over a chart: a least-squares line for slow drift, or
+//! a rolling median that follows the level while ignoring one-off spikes.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{badge::format_value, MetricPoint};
+
+/// Points on each side of the one a rolling median is centred on.
+const MEDIAN_RADIUS: usize = 3;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Trend {
+    Linear,
+    Median,
+}
+
+impl Trend {
+    pub const ALL: &'static [Trend] = &[Trend::Linear, Trend::Median];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Trend::Linear => "linear",
+            Trend::Median => "median",
+        }
+    }
+
+    /// The trend series for `data` with a legend label, or `None` when
+    /// there are too few points to show one.
+    pub fn compute(self, data: &[MetricPoint]) -> Option<(String, Vec<MetricPoint>)> {
+        match self {
+            Trend::Linear => {
+                let (slope, line) = least_squares(data)?;
+                let sign = if slope >= 0.0 { "+" } else { "" };
+                Some((format!("trend {}{}/day", sign, format_value(slope * 86400.0)), line))
+            }
+            Trend::Median => {
+                (data.len() > 2 * MEDIAN_RADIUS).then(|| {
+                    (format!("median of {}", 2 * MEDIAN_RADIUS + 1), rolling_median(data))
+                })
+            }
+        }
+    }
+}
+
+/// A least-squares line through a series. Times are centred on their mean
+/// so large Unix timestamps don't swamp the sums.
+pub struct Line {
+    pub mean_t: f64,
+    pub mean_v: f64,
+    /// Change per second
+    pub slope: f64,
+    /// Sum of squared distances of the timestamps from their mean
+    pub variance: f64,
+}
+
+impl Line {
+    /// The line through `data`, or `None` when its points don't span any
+    /// time.
+    pub fn fit(data: &[MetricPoint]) -> Option<Line> {
+        if data.is_empty() {
+            return None;
+        }
+        let n = data.len() as f64;
+        let mean_t = data.iter().map(|p| p.timestamp as f64).sum::<f64>() / n;
+        let mean_v = data.iter().map(|p| p.value).sum::<f64>() / n;
+        let (mut covariance, mut variance) = (0.0, 0.0);
+        for point in data {
+            let dt = point.timestamp as f64 - mean_t;
+            covariance += dt * (point.value - mean_v);
+            variance += dt * dt;
+        }
+        if variance == 0.0 {
+            return None;
+        }
+        Some(Line {
+            mean_t,
+            mean_v,
+            slope: covariance / variance,
+            variance,
+        })
+    }
+
+    pub fn at(&self, timestamp: i64) -> f64 {
+        self.mean_v + self.slope * (timestamp as f64 - self.mean_t)
+    }
+}
+
+/// The fitted slope per second, and the fitted line at the first and last
+/// timestamps.
+fn least_squares(data: &[MetricPoint]) -> Option<(f64, Vec<MetricPoint>)> {
+    let (first, last) = (data.first()?, data.last()?);
+    let line = Line::fit(data)?;
+    let at = |timestamp: i64| MetricPoint {
+        timestamp,
+        value: line.at(timestamp),
+        sha: None,
+        branch: None,
+    };
+    Some((line.slope, vec![at(first.timestamp), at(last.timestamp)]))
+}
+
+/// Median of each point's neighbourhood, narrowing at the ends of the series.
+fn rolling_median(data: &[MetricPoint]) -> Vec<MetricPoint> {
+    let mut window = Vec::with_capacity(2 * MEDIAN_RADIUS + 1);
+    data.iter()
+        .enumerate()
+        .map(|(i, point)| {
+            let range = i.saturating_sub(MEDIAN_RADIUS)..(i + MEDIAN_RADIUS + 1).min(data.len());
+            window.clear();
+            window.extend(data[range].iter().map(|p| p.value));
+            window.sort_by(f64::total_cmp);
+            let mid = window.len() / 2;
+            let value = if window.len() % 2 == 0 {
+                (window[mid - 1] + window[mid]) / 2.0
+            } else {
+                window[mid]
+            };
+            MetricPoint {
+                timestamp: point.timestamp,
+                value,
+                sha: None,
+                branch: None,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,241 @@
+//! Viewer timezones. Pages format times in UTC, and chart axes in the
+//! browser's own zone, unless `?tz=` or a `tz` cookie set by
+//! `POST /preferences` pins them to one.
+//!
+//! A zone is a name from the tz database such as `Europe/London`, which
+//! follows its daylight saving changes, or a fixed offset such as `+05:30`.
+
+use axum::{
+    extract::FromRequestParts,
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, FixedOffset, Offset, TimeZone};
+use chrono_tz::Tz;
+use serde_json::json;
+
+use crate::theme;
+
+pub const TZ_COOKIE: &str = "tz";
+/// Offsets in use around the world run from UTC-12 to UTC+14.
+const MAX_OFFSET_MINUTES: i32 = 14 * 60;
+const DAY: i64 = 86400;
+
+/// What `?tz=` and the preferences form say when they can't read a zone.
+pub const INVALID: &str = "tz takes UTC, a zone name like Europe/London or an offset like +05:30";
+
+/// A timezone a viewer can pick.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Zone {
+    Fixed(FixedOffset),
+    Named(Tz),
+}
+
+impl Zone {
+    pub const UTC: Zone = Zone::Fixed(FixedOffset::east_opt(0).unwrap());
+
+    /// The zone's offset from UTC at a Unix timestamp.
+    pub fn offset_at(self, timestamp: i64) -> FixedOffset {
+        match self {
+            Zone::Fixed(offset) => offset,
+            Zone::Named(tz) => match DateTime::from_timestamp(timestamp, 0) {
+                Some(time) => tz.offset_from_utc_datetime(&time.naive_utc()).fix(),
+                None => FixedOffset::east_opt(0).unwrap(),
+            },
+        }
+    }
+
+    /// Days since the epoch by the zone's calendar, for a Unix timestamp.
+    pub fn local_day(self, timestamp: i64) -> i64 {
+        (timestamp + self.offset_at(timestamp).local_minus_utc() as i64).div_euclid(DAY)
+    }
+
+    /// When a day from [`local_day`](Self::local_day) starts, as a Unix
+    /// timestamp: local midnight, or the first moment after it where a
+    /// daylight saving change skips midnight.
+    pub fn day_start(self, day: i64) -> i64 {
+        let midnight = day * DAY;
+        let Zone::Named(tz) = self else {
+            return midnight - self.offset_at(midnight).local_minus_utc() as i64;
+        };
+        let start = DateTime::from_timestamp(midnight, 0).map(|time| time.date_naive()).and_then(|date| {
+            (0..24)
+                .filter_map(|hour| date.and_hms_opt(hour, 0, 0))
+                .find_map(|local| tz.from_local_datetime(&local).earliest())
+        });
+        start.map_or(midnight, |start| start.timestamp())
+    }
+
+    /// `Europe/London` or `+05:30`, the form zones are stored and linked in.
+    pub fn name(self) -> String {
+        match self {
+            Zone::Fixed(offset) => format_offset(offset),
+            Zone::Named(tz) => tz.name().to_string(),
+        }
+    }
+
+    /// `UTC`, `UTC+05:30` or `Europe/London`, for showing next to times.
+    pub fn label(self) -> String {
+        match self {
+            Zone::Fixed(offset) => label(offset),
+            Zone::Named(tz) => tz.name().to_string(),
+        }
+    }
+}
+
+/// Reads `UTC`, `Z`, a zone name like `Europe/London`, or an offset like
+/// `+05:30`, `-0800`, `+2` or `UTC+05:30`.
+pub fn parse(value: &str) -> Option<Zone> {
+    let value = value.trim();
+    let offset = value
+        .strip_prefix("UTC")
+        .or_else(|| value.strip_prefix("utc"))
+        .unwrap_or(value);
+    if offset.is_empty() || offset == "Z" {
+        return Some(Zone::UTC);
+    }
+    if !offset.starts_with(['+', '-']) {
+        return value.parse().ok().map(Zone::Named);
+    }
+    parse_offset(offset).map(Zone::Fixed)
+}
+
+/// An offset after its `UTC`, such as `+05:30`, `-0800` or `+2`.
+fn parse_offset(offset: &str) -> Option<FixedOffset> {
+    if !offset.is_ascii() {
+        return None;
+    }
+    let (sign, rest) = match offset.as_bytes()[0] {
+        b'+' => (1, &offset[1..]),
+        b'-' => (-1, &offset[1..]),
+        _ => return None,
+    };
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None if rest.len() > 2 => rest.split_at(rest.len() - 2),
+        None => (rest, "0"),
+    };
+    if hours.is_empty() || hours.len() > 2 || !hours.chars().chain(minutes.chars()).all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes): (i32, i32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    let total = hours * 60 + minutes;
+    if minutes >= 60 || total > MAX_OFFSET_MINUTES {
+        return None;
+    }
+    FixedOffset::east_opt(sign * total * 60)
+}
+
+/// `+05:30`, the form offsets are stored and linked in.
+fn format_offset(offset: FixedOffset) -> String {
+    let seconds = offset.local_minus_utc();
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let minutes = seconds.abs() / 60;
+    format!("{}{:02}:{:02}", sign, minutes / 60, minutes % 60)
+}
+
+/// `UTC` or `UTC+05:30`, for showing next to times.
+fn label(offset: FixedOffset) -> String {
+    match offset.local_minus_utc() {
+        0 => "UTC".to_string(),
+        _ => format!("UTC{}", format_offset(offset)),
+    }
+}
+
+/// The zone this request's times should be shown in, if the viewer chose one.
+pub struct ViewerTz(pub Option<Zone>);
+
+impl ViewerTz {
+    /// A Unix timestamp as `2024-01-02 15:04:05 UTC+01:00`, with the zone's
+    /// offset at that moment, in UTC when the viewer hasn't picked a zone.
+    pub fn format_timestamp(&self, timestamp: i64) -> Option<String> {
+        let offset = self.0.unwrap_or(Zone::UTC).offset_at(timestamp);
+        let time = DateTime::from_timestamp(timestamp, 0)?.with_timezone(&offset);
+        Some(format!("{} {}", time.format("%Y-%m-%d %H:%M:%S"), label(offset)))
+    }
+
+    /// The zone for page scripts: `{"name": "Europe/London"}` for them to
+    /// look up, `{"offset": 330}` in minutes east of UTC, or `null` to leave
+    /// them on the browser's zone.
+    pub fn script_json(&self) -> String {
+        match self.0 {
+            None => "null".to_string(),
+            Some(Zone::Named(tz)) => json!({ "name": tz.name() }).to_string(),
+            Some(Zone::Fixed(offset)) => json!({ "offset": offset.local_minus_utc() / 60 }).to_string(),
+        }
+    }
+
+    /// `label` for pages, or `otherwise` when the viewer hasn't picked a zone.
+    pub fn label_or(&self, otherwise: &str) -> String {
+        self.0.map_or_else(|| otherwise.to_string(), Zone::label)
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ViewerTz {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let requested = parts.uri.query().and_then(|query| {
+            form_urlencoded::parse(query.as_bytes())
+                .find(|(key, _)| key == "tz")
+                .map(|(_, value)| value.into_owned())
+        });
+        // A bad cookie is ignored, but a bad link should say what's wrong
+        if let Some(value) = requested.filter(|value| !value.is_empty()) {
+            return parse(&value)
+                .map(|zone| ViewerTz(Some(zone)))
+                .ok_or_else(|| (StatusCode::BAD_REQUEST, INVALID).into_response());
+        }
+        Ok(ViewerTz(theme::cookie(&parts.headers, TZ_COOKIE).and_then(parse)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timestamp(text: &str) -> i64 {
+        DateTime::parse_from_rfc3339(text).unwrap().timestamp()
+    }
+
+    #[test]
+    fn parses_offsets_and_names() {
+        assert_eq!(parse("UTC"), Some(Zone::UTC));
+        assert_eq!(parse("Z"), Some(Zone::UTC));
+        assert_eq!(parse("+05:30").map(Zone::name).as_deref(), Some("+05:30"));
+        assert_eq!(parse("UTC-0800").map(Zone::name).as_deref(), Some("-08:00"));
+        assert_eq!(parse("+2").map(Zone::name).as_deref(), Some("+02:00"));
+        assert_eq!(parse("Europe/London"), Some(Zone::Named(Tz::Europe__London)));
+        assert_eq!(parse("+15:00"), None);
+        assert_eq!(parse("+05:60"), None);
+        assert_eq!(parse("Mars/Olympus_Mons"), None);
+    }
+
+    #[test]
+    fn named_zones_follow_daylight_saving() {
+        let london = Zone::Named(Tz::Europe__London);
+        assert_eq!(london.offset_at(timestamp("2024-01-15T12:00:00Z")).local_minus_utc(), 0);
+        assert_eq!(london.offset_at(timestamp("2024-07-15T12:00:00Z")).local_minus_utc(), 3600);
+        let viewer = ViewerTz(Some(london));
+        assert_eq!(
+            viewer.format_timestamp(timestamp("2024-07-15T12:00:00Z")).as_deref(),
+            Some("2024-07-15 13:00:00 UTC+01:00")
+        );
+        assert_eq!(viewer.script_json(), r#"{"name":"Europe/London"}"#);
+    }
+
+    #[test]
+    fn days_start_at_local_midnight() {
+        let london = Zone::Named(Tz::Europe__London);
+        // 23:30 UTC in summer is already the next day in London
+        let day = london.local_day(timestamp("2024-07-15T23:30:00Z"));
+        assert_eq!(london.day_start(day), timestamp("2024-07-15T23:00:00Z"));
+        // Havana springs forward at midnight, so that day starts at 01:00
+        let havana = Zone::Named(Tz::America__Havana);
+        let day = havana.local_day(timestamp("2024-03-10T12:00:00Z"));
+        assert_eq!(havana.day_start(day), timestamp("2024-03-10T05:00:00Z"));
+        let fixed = parse("+05:30").unwrap();
+        let day = fixed.local_day(timestamp("2024-07-15T20:00:00Z"));
+        assert_eq!(fixed.day_start(day), timestamp("2024-07-15T18:30:00Z"));
+    }
+}
@@ -0,0 +1,457 @@
+//! Outgoing webhooks: URLs registered on a namespace that are `POST`ed its
+//! new points, so other systems can react to them without polling. A hook
+//! gets each write as it happens or, with `batch_seconds`, everything written
+//! in that window in one request. Either way the body is
+//! `{"namespace": ..., "webhook": ..., "points": [{"id", "value", "timestamp"}]}`.
+//!
+//! Delivery is best effort, like alert notifications. Points wait in memory
+//! and go out in the background, one request at a time per hook so they
+//! arrive in order. A request that fails is logged and its points dropped;
+//! a hook that falls more than 10,000 points behind loses the oldest. Points
+//! still waiting when the server shuts down go out then, batched or not;
+//! ones waiting when it's killed are lost.
+//!
+//! Hooks make the server send requests wherever they say, so only the admin
+//! token may list, register or remove them.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::sqlite::SqlitePool;
+use tokio::sync::Notify;
+use tokio::time::{self, Instant};
+
+use crate::{
+    auth, client,
+    ids::NamespacePath,
+    AppState,
+};
+
+const MAX_WEBHOOKS_PER_NAMESPACE: usize = 20;
+const MAX_URL_LENGTH: usize = 2048;
+const MAX_BATCH_SECONDS: u32 = 3600;
+/// Points a hook can fall behind by before the oldest are dropped
+const MAX_PENDING: usize = 10_000;
+/// How often batched hooks are checked for being due
+const TICK: Duration = Duration::from_secs(1);
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(json!({ "error": message })))
+}
+
+fn database_error<E: std::fmt::Display>(err: E) -> ApiError {
+    crate::errors::report(&err);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Webhook {
+    id: i64,
+    url: String,
+    /// 0 sends each write on its own
+    batch_seconds: u32,
+    created_at: i64,
+}
+
+/// `{"url": "https://example.com/hook", "batch_seconds": 60}`
+#[derive(Deserialize)]
+pub struct WebhookRequest {
+    url: String,
+    #[serde(default)]
+    batch_seconds: u32,
+}
+
+#[derive(Serialize)]
+struct QueuedPoint {
+    id: String,
+    value: f64,
+    timestamp: i64,
+}
+
+/// A hook and the points waiting for it.
+struct Queue {
+    namespace: String,
+    webhook: Webhook,
+    pending: Mutex<VecDeque<QueuedPoint>>,
+    last_sent: Mutex<Instant>,
+    /// Whether a request to the hook is under way
+    sending: AtomicBool,
+}
+
+impl Queue {
+    /// Takes everything waiting if the hook is due a request.
+    fn take_due(&self, now: Instant) -> Option<Vec<QueuedPoint>> {
+        if self.sending.load(Ordering::Acquire) {
+            return None;
+        }
+        let mut last_sent = self.last_sent.lock().unwrap();
+        if now.duration_since(*last_sent) < Duration::from_secs(self.webhook.batch_seconds.into()) {
+            return None;
+        }
+        let mut pending = self.pending.lock().unwrap();
+        if pending.is_empty() {
+            return None;
+        }
+        *last_sent = now;
+        self.sending.store(true, Ordering::Release);
+        Some(pending.drain(..).collect())
+    }
+}
+
+/// Hooks by namespace, with the points queued for each.
+pub struct Webhooks {
+    queues: RwLock<HashMap<String, Vec<Arc<Queue>>>>,
+    /// Wakes the sender when points arrive for a hook that isn't batched
+    wake: Notify,
+}
+
+impl Webhooks {
+    pub async fn load(pool: &SqlitePool) -> Result<Self, sqlx::Error> {
+        let webhooks = Webhooks {
+            queues: RwLock::new(HashMap::new()),
+            wake: Notify::new(),
+        };
+        webhooks.reload(pool).await?;
+        Ok(webhooks)
+    }
+
+    /// Rereads the hooks, keeping the queues of those that are still there.
+    pub async fn reload(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"SELECT webhook_id as "webhook_id!", namespace, url, batch_seconds as "batch_seconds: u32", created_at
+               FROM namespace_webhooks ORDER BY webhook_id"#
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let mut queues = self.queues.write().unwrap();
+        let mut existing: HashMap<i64, Arc<Queue>> = queues
+            .values()
+            .flatten()
+            .map(|queue| (queue.webhook.id, queue.clone()))
+            .collect();
+        let mut reloaded: HashMap<String, Vec<Arc<Queue>>> = HashMap::new();
+        for row in rows {
+            let queue = existing.remove(&row.webhook_id).unwrap_or_else(|| {
+                Arc::new(Queue {
+                    namespace: row.namespace.clone(),
+                    webhook: Webhook {
+                        id: row.webhook_id,
+                        url: row.url,
+                        batch_seconds: row.batch_seconds,
+                        created_at: row.created_at,
+                    },
+                    pending: Mutex::new(VecDeque::new()),
+                    last_sent: Mutex::new(Instant::now()),
+                    sending: AtomicBool::new(false),
+                })
+            });
+            reloaded.entry(row.namespace).or_default().push(queue);
+        }
+        *queues = reloaded;
+        Ok(())
+    }
+
+    fn list(&self, namespace: &str) -> Vec<Webhook> {
+        self.queues
+            .read()
+            .unwrap()
+            .get(namespace)
+            .map(|queues| queues.iter().map(|queue| queue.webhook.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Queues points for the namespace's hooks and returns how many had to
+    /// be dropped to make room.
+    fn push(&self, namespace: &str, points: &[(&str, f64)], timestamp: i64) -> u64 {
+        let queues = self.queues.read().unwrap();
+        let Some(queues) = queues.get(namespace) else {
+            return 0;
+        };
+        let mut dropped = 0;
+        let mut immediate = false;
+        for queue in queues {
+            let mut pending = queue.pending.lock().unwrap();
+            for &(id, value) in points {
+                if pending.len() >= MAX_PENDING {
+                    pending.pop_front();
+                    dropped += 1;
+                }
+                pending.push_back(QueuedPoint {
+                    id: id.to_string(),
+                    value,
+                    timestamp,
+                });
+            }
+            immediate |= queue.webhook.batch_seconds == 0;
+        }
+        if immediate {
+            self.wake.notify_one();
+        }
+        dropped
+    }
+
+    fn due(&self) -> Vec<(Arc<Queue>, Vec<QueuedPoint>)> {
+        let now = Instant::now();
+        self.queues
+            .read()
+            .unwrap()
+            .values()
+            .flatten()
+            .filter_map(|queue| Some((queue.clone(), queue.take_due(now)?)))
+            .collect()
+    }
+}
+
+/// Queues freshly recorded points for the namespace's outgoing webhooks.
+pub fn publish(state: &AppState, namespace: &str, points: &[(&str, f64)], timestamp: i64) {
+    if !state.config().features.webhooks {
+        return;
+    }
+    let dropped = state.webhooks.push(namespace, points, timestamp);
+    if dropped > 0 {
+        state.metrics.webhook_points_dropped.fetch_add(dropped, Ordering::Relaxed);
+    }
+}
+
+/// Sends queued points as hooks come due, for as long as the server runs.
+pub fn spawn(state: AppState) {
+    if !state.config().features.webhooks {
+        return;
+    }
+    tokio::spawn(async move {
+        loop {
+            // Woken early for unbatched hooks, and otherwise every tick
+            let _ = time::timeout(TICK, state.webhooks.wake.notified()).await;
+            for (queue, points) in state.webhooks.due() {
+                tokio::spawn(deliver(state.clone(), queue, points));
+            }
+        }
+    });
+}
+
+/// Sends every hook whatever is waiting for it, however it's batched, and
+/// waits for those requests, for shutting down.
+pub async fn flush(state: &AppState) {
+    let queues: Vec<Arc<Queue>> = state.webhooks.queues.read().unwrap().values().flatten().cloned().collect();
+    let mut deliveries = Vec::new();
+    for queue in queues {
+        // A request already under way goes first, so points stay in order
+        while queue
+            .sending
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            time::sleep(Duration::from_millis(50)).await;
+        }
+        let points: Vec<QueuedPoint> = queue.pending.lock().unwrap().drain(..).collect();
+        if points.is_empty() {
+            queue.sending.store(false, Ordering::Release);
+            continue;
+        }
+        deliveries.push(deliver(state.clone(), queue, points));
+    }
+    futures_util::future::join_all(deliveries).await;
+}
+
+async fn deliver(state: AppState, queue: Arc<Queue>, points: Vec<QueuedPoint>) {
+    let body = json!({
+        "namespace": queue.namespace,
+        "webhook": queue.webhook.id,
+        "points": points,
+    });
+    let allow_private = state.config().allow_private_webhook_urls;
+    let sent = match serde_json::to_vec(&body) {
+        Ok(body) => client::post_webhook(&queue.webhook.url, &body, allow_private).await,
+        Err(err) => Err(err.into()),
+    };
+    let counter = match sent {
+        Ok(()) => &state.metrics.webhook_deliveries,
+        Err(err) => {
+            tracing::warn!(
+                "Sending {} points to webhook {} failed: {}",
+                points.len(),
+                queue.webhook.url,
+                err
+            );
+            &state.metrics.webhook_delivery_failures
+        }
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+    queue.sending.store(false, Ordering::Release);
+    // Points that arrived meanwhile are due at once
+    if queue.webhook.batch_seconds == 0 && !queue.pending.lock().unwrap().is_empty() {
+        state.webhooks.wake.notify_one();
+    }
+}
+
+/// Webhook URLs often carry a secret, so only the admin token sees them.
+pub async fn list_webhooks(
+    NamespacePath(namespace): NamespacePath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+    Ok(Json(json!({ "webhooks": state.webhooks.list(&namespace) })))
+}
+
+pub async fn create_webhook(
+    NamespacePath(namespace): NamespacePath,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<WebhookRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+    if request.url.len() > MAX_URL_LENGTH {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "url must be at most 2048 characters"));
+    }
+    client::check_public(&request.url, state.config().allow_private_webhook_urls)
+        .await
+        .map_err(|err| error(StatusCode::UNPROCESSABLE_ENTITY, &err))?;
+    if request.batch_seconds > MAX_BATCH_SECONDS {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "batch_seconds must be at most 3600"));
+    }
+    if state.webhooks.list(&namespace).len() >= MAX_WEBHOOKS_PER_NAMESPACE {
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, "a namespace can have at most 20 webhooks"));
+    }
+
+    let now = Utc::now().timestamp();
+    let (pool, namespace_ref, url) = (&state.pool, &namespace, &request.url);
+    let batch_seconds = request.batch_seconds;
+    let id = state
+        .write(|| async move {
+            sqlx::query_scalar!(
+                r#"INSERT INTO namespace_webhooks (namespace, url, batch_seconds, created_at)
+                   VALUES (?, ?, ?, ?)
+                   RETURNING webhook_id as "webhook_id!""#,
+                namespace_ref,
+                url,
+                batch_seconds,
+                now
+            )
+            .fetch_one(pool)
+            .await
+        })
+        .await
+        .map_err(database_error)?;
+    state.webhooks.reload(pool).await.map_err(database_error)?;
+
+    let webhook = Webhook {
+        id,
+        url: request.url,
+        batch_seconds,
+        created_at: now,
+    };
+    Ok((StatusCode::CREATED, Json(webhook)))
+}
+
+pub async fn delete_webhook(
+    Path((namespace, webhook_id)): Path<(String, i64)>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let namespace = state.config().id_policy.normalize(&namespace);
+    auth::require_admin(&state.config(), &headers).map_err(|status| error(status, "unauthorized"))?;
+
+    let (pool, namespace_ref) = (&state.pool, &namespace);
+    let result = state
+        .write(|| async move {
+            sqlx::query!(
+                "DELETE FROM namespace_webhooks WHERE namespace = ? AND webhook_id = ?",
+                namespace_ref,
+                webhook_id
+            )
+            .execute(pool)
+            .await
+        })
+        .await
+        .map_err(database_error)?;
+    state.webhooks.reload(pool).await.map_err(database_error)?;
+    if result.rows_affected() == 0 {
+        return Err(error(StatusCode::NOT_FOUND, "no webhook with that id"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn webhooks(batches: &[u32]) -> Webhooks {
+        let queues = batches
+            .iter()
+            .enumerate()
+            .map(|(id, &batch_seconds)| {
+                Arc::new(Queue {
+                    namespace: "ci".to_string(),
+                    webhook: Webhook {
+                        id: id as i64,
+                        url: "https://example.com/hook".to_string(),
+                        batch_seconds,
+                        created_at: 0,
+                    },
+                    pending: Mutex::new(VecDeque::new()),
+                    last_sent: Mutex::new(Instant::now()),
+                    sending: AtomicBool::new(false),
+                })
+            })
+            .collect();
+        Webhooks {
+            queues: RwLock::new(HashMap::from([("ci".to_string(), queues)])),
+            wake: Notify::new(),
+        }
+    }
+
+    fn due_ids(webhooks: &Webhooks, now: Instant) -> Vec<(i64, usize)> {
+        let queues: Vec<Arc<Queue>> = webhooks.queues.read().unwrap().values().flatten().cloned().collect();
+        queues
+            .iter()
+            .filter_map(|queue| Some((queue.webhook.id, queue.take_due(now)?.len())))
+            .collect()
+    }
+
+    #[test]
+    fn unbatched_hooks_are_due_at_once_and_batched_ones_wait() {
+        let webhooks = webhooks(&[0, 60]);
+        assert_eq!(webhooks.push("ci", &[("build_time", 1.0), ("binary_size", 2.0)], 100), 0);
+        assert_eq!(webhooks.push("other", &[("build_time", 1.0)], 100), 0);
+
+        let now = Instant::now();
+        assert_eq!(due_ids(&webhooks, now), [(0, 2)]);
+        // Nothing more goes to a hook until its request is done
+        webhooks.push("ci", &[("build_time", 3.0)], 101);
+        assert_eq!(due_ids(&webhooks, now), []);
+        webhooks.queues.read().unwrap()["ci"][0].sending.store(false, Ordering::Release);
+        assert_eq!(due_ids(&webhooks, now), [(0, 1)]);
+
+        webhooks.queues.read().unwrap()["ci"][0].sending.store(false, Ordering::Release);
+        assert_eq!(due_ids(&webhooks, now + Duration::from_secs(60)), [(1, 3)]);
+    }
+
+    #[test]
+    fn hooks_that_fall_behind_lose_the_oldest_points() {
+        let webhooks = webhooks(&[3600]);
+        let points: Vec<(&str, f64)> = (0..MAX_PENDING).map(|i| ("build_time", i as f64)).collect();
+        assert_eq!(webhooks.push("ci", &points, 100), 0);
+        assert_eq!(webhooks.push("ci", &[("build_time", -1.0), ("build_time", -2.0)], 101), 2);
+
+        let queue = webhooks.queues.read().unwrap()["ci"][0].clone();
+        let pending = queue.pending.lock().unwrap();
+        assert_eq!(pending.len(), MAX_PENDING);
+        assert_eq!(pending.front().unwrap().value, 2.0);
+        assert_eq!(pending.back().unwrap().value, -2.0);
+    }
+}
@@ -0,0 +1,122 @@
+use std::time::Duration;
+
+use axum::{
+    body::Body,
+    http::{Method, Request},
+    routing::post,
+    Json, Router,
+};
+use serde_json::{json, Value};
+use somnial::{config::Config, test::TestServer};
+use tokio::sync::mpsc;
+
+const ADMIN_TOKEN: &str = "secret";
+
+/// A webhook on the loopback that passes on every body posted to it.
+async fn receiver() -> (String, mpsc::UnboundedReceiver<Value>) {
+    let (sender, received) = mpsc::unbounded_channel();
+    let app = Router::new().route(
+        "/hook",
+        post(move |Json(body): Json<Value>| async move {
+            let _ = sender.send(body);
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    (url, received)
+}
+
+async fn next(received: &mut mpsc::UnboundedReceiver<Value>) -> Value {
+    tokio::time::timeout(Duration::from_secs(5), received.recv())
+        .await
+        .expect("no notification arrived")
+        .unwrap()
+}
+
+async fn server(allow_private_webhook_urls: bool) -> TestServer {
+    TestServer::with_config(Config {
+        admin_token: Some(ADMIN_TOKEN.to_string()),
+        allow_private_webhook_urls,
+        ..Default::default()
+    })
+    .await
+}
+
+fn admin(method: Method, uri: &str, body: Value) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("authorization", format!("Bearer {}", ADMIN_TOKEN))
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn rule(url: &str) -> Value {
+    json!({ "metric": "disk_used", "condition": "above", "threshold": 90, "webhook_url": url })
+}
+
+#[tokio::test]
+async fn rules_notify_on_firing_and_recovery() {
+    let server = server(true).await;
+    let (url, mut received) = receiver().await;
+    let created = server.request(admin(Method::POST, "/api/v1/namespaces/ops/alerts", rule(&url))).await;
+    assert_eq!(created.status, 201, "{}", created.text());
+    assert_eq!(created.json::<Value>()["firing"], false);
+
+    assert_eq!(server.post("/ops/disk_used?value=50", Body::empty()).await.status, 200);
+    assert_eq!(server.post("/ops/disk_used?value=95", Body::empty()).await.status, 200);
+    let firing = next(&mut received).await;
+    assert_eq!(firing["status"], "firing");
+    assert_eq!(firing["metric"], "disk_used");
+    assert_eq!(firing["value"], 95.0);
+
+    // Staying above the threshold doesn't notify again
+    assert_eq!(server.post("/ops/disk_used?value=97", Body::empty()).await.status, 200);
+    assert_eq!(server.post("/ops/disk_used?value=80", Body::empty()).await.status, 200);
+    let resolved = next(&mut received).await;
+    assert_eq!(resolved["status"], "resolved");
+    assert_eq!(resolved["value"], 80.0);
+
+    let listed: Value = server.request(admin(Method::GET, "/api/v1/namespaces/ops/alerts", json!({}))).await.json();
+    assert_eq!(listed["rules"][0]["firing"], false);
+    let events: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM alert_events WHERE namespace = 'ops'")
+        .fetch_one(server.pool())
+        .await
+        .unwrap();
+    assert_eq!(events, 2);
+}
+
+#[tokio::test]
+async fn rules_take_the_admin_token() {
+    let server = server(true).await;
+    let mut anonymous = admin(Method::POST, "/api/v1/namespaces/ops/alerts", rule("https://hooks.example.com/x"));
+    anonymous.headers_mut().remove("authorization");
+    assert_eq!(server.request(anonymous).await.status, 401);
+    assert_eq!(server.get("/api/v1/namespaces/ops/alerts").await.status, 401);
+
+    let created = server.request(admin(Method::POST, "/api/v1/namespaces/ops/alerts", rule("https://hooks.example.com/x")));
+    let id = created.await.json::<Value>()["id"].as_i64().unwrap();
+    let uri = format!("/api/v1/namespaces/ops/alerts/{}", id);
+    assert_eq!(server.send(Method::DELETE, &uri, Body::empty()).await.status, 401);
+    assert_eq!(server.request(admin(Method::DELETE, &uri, json!({}))).await.status, 204);
+    assert_eq!(server.request(admin(Method::DELETE, &uri, json!({}))).await.status, 404);
+}
+
+#[tokio::test]
+async fn rules_are_checked_before_they_are_saved() {
+    let server = server(false).await;
+    for body in [
+        rule("http://127.0.0.1:9/hook"),
+        rule("http://[::ffff:10.0.0.1]/hook"),
+        json!({ "metric": "disk_used", "condition": "above", "threshold": 90 }),
+        json!({ "metric": "disk_used", "condition": "silent", "threshold": 5, "webhook_url": "https://hooks.example.com/x" }),
+        json!({ "metric": "", "condition": "above", "threshold": 90, "webhook_url": "https://hooks.example.com/x" }),
+    ] {
+        let created = server.request(admin(Method::POST, "/api/v1/namespaces/ops/alerts", body.clone())).await;
+        assert_eq!(created.status, 422, "{}", body);
+    }
+    let listed: Value = server.request(admin(Method::GET, "/api/v1/namespaces/ops/alerts", json!({}))).await.json();
+    assert_eq!(listed["rules"], json!([]));
+}
@@ -0,0 +1,88 @@
+use axum::{
+    body::Body,
+    http::{Method, Request},
+};
+use serde_json::{json, Value};
+use somnial::{config::Config, test::TestServer};
+
+const ADMIN_TOKEN: &str = "secret";
+
+async fn server() -> TestServer {
+    let server = TestServer::with_config(Config {
+        admin_token: Some(ADMIN_TOKEN.to_string()),
+        ..Default::default()
+    })
+    .await;
+    server.seed("ci", "build_time", &[(1_700_000_000, 41.0), (1_700_000_060, 42.0)]).await;
+    server
+}
+
+fn admin(method: Method, uri: &str, body: Value) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("authorization", format!("Bearer {}", ADMIN_TOKEN))
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+async fn rename(server: &TestServer, to: &str) {
+    let renamed = server.request(admin(Method::POST, "/ci/build_time/rename", json!({ "to": to }))).await;
+    assert_eq!(renamed.status, 200, "{}", renamed.text());
+    assert_eq!(renamed.json::<Value>()["points"], 2);
+}
+
+#[tokio::test]
+async fn renamed_metrics_redirect_from_their_old_id() {
+    let server = server().await;
+    rename(&server, "compile_time").await;
+    assert!(server.store().latest("ci", "build_time").await.unwrap().is_none());
+    assert_eq!(server.store().latest("ci", "compile_time").await.unwrap().unwrap().value, 42.0);
+
+    for (old, new) in [
+        ("/ci/build_time", "/ci/compile_time"),
+        ("/ci/build_time/badge.svg?label=build", "/ci/compile_time/badge.svg?label=build"),
+        ("/embed/ci/build_time", "/embed/ci/compile_time"),
+    ] {
+        let redirect = server.get(old).await;
+        assert_eq!(redirect.status, 307, "{}", old);
+        assert_eq!(redirect.header("location"), Some(new));
+    }
+
+    let aliases: Value = server.get("/api/v1/namespaces/ci/aliases").await.json();
+    assert_eq!(aliases["aliases"][0]["id"], "build_time");
+    assert_eq!(aliases["aliases"][0]["target"], "compile_time");
+}
+
+#[tokio::test]
+async fn writing_to_an_old_id_makes_it_a_metric_again() {
+    let server = server().await;
+    rename(&server, "compile_time").await;
+    assert_eq!(server.post("/ci/build_time?value=7", Body::empty()).await.status, 200);
+    assert_eq!(server.get("/ci/build_time").await.status, 200);
+    let aliases: Value = server.get("/api/v1/namespaces/ci/aliases").await.json();
+    assert_eq!(aliases["aliases"], json!([]));
+}
+
+#[tokio::test]
+async fn renaming_and_dropping_aliases_take_the_admin_token() {
+    let server = server().await;
+    let mut anonymous = admin(Method::POST, "/ci/build_time/rename", json!({ "to": "compile_time" }));
+    anonymous.headers_mut().remove("authorization");
+    assert_eq!(server.request(anonymous).await.status, 401);
+
+    server.seed("ci", "taken", &[(1_700_000_000, 1.0)]).await;
+    let taken = server.request(admin(Method::POST, "/ci/build_time/rename", json!({ "to": "taken" }))).await;
+    assert_eq!(taken.status, 409);
+    rename(&server, "compile_time").await;
+
+    let mut anonymous = admin(Method::DELETE, "/api/v1/namespaces/ci/aliases/build_time", json!({}));
+    anonymous.headers_mut().remove("authorization");
+    assert_eq!(server.request(anonymous).await.status, 401);
+    let dropped = server.request(admin(Method::DELETE, "/api/v1/namespaces/ci/aliases/build_time", json!({}))).await;
+    assert_eq!(dropped.status, 204);
+    assert_eq!(server.get("/ci/build_time").await.status, 200);
+    let again = server.request(admin(Method::DELETE, "/api/v1/namespaces/ci/aliases/build_time", json!({}))).await;
+    assert_eq!(again.status, 404);
+}
@@ -0,0 +1,65 @@
+use axum::{
+    body::Body,
+    http::{Method, Request},
+};
+use somnial::{
+    config::{Config, Features},
+    test::TestServer,
+};
+
+const ADMIN_TOKEN: &str = "secret";
+
+async fn server() -> TestServer {
+    TestServer::with_config(Config {
+        admin_token: Some(ADMIN_TOKEN.to_string()),
+        features: Features { backups: true, ..Default::default() },
+        ..Default::default()
+    })
+    .await
+}
+
+fn admin(method: Method, uri: &str, body: Body) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("authorization", format!("Bearer {}", ADMIN_TOKEN))
+        .body(body)
+        .unwrap()
+}
+
+#[tokio::test]
+async fn backup_of_the_in_memory_database_restores_elsewhere() {
+    let source = server().await;
+    source.seed("ci", "build_time", &[(1_700_000_000, 41.0), (1_700_000_060, 42.0)]).await;
+
+    let backup = source.request(admin(Method::GET, "/admin/backup", Body::empty())).await;
+    assert_eq!(backup.status, 200, "{}", backup.text());
+    assert_eq!(backup.header("content-type"), Some("application/vnd.sqlite3"));
+    assert!(backup.body.starts_with(b"SQLite format 3\0"));
+
+    let target = server().await;
+    let restored = target.request(admin(Method::POST, "/admin/restore", Body::from(backup.body))).await;
+    assert!(restored.status.is_success(), "{}", restored.text());
+    let latest = target.store().latest("ci", "build_time").await.unwrap().expect("restored point");
+    assert_eq!(latest.value, 42.0);
+}
+
+#[tokio::test]
+async fn backups_take_the_admin_token() {
+    let server = server().await;
+    assert_eq!(server.get("/admin/backup").await.status, 401);
+    let request = admin(Method::POST, "/admin/restore", Body::from("not a database"));
+    assert_eq!(server.request(request).await.status, 422);
+}
+
+#[tokio::test]
+async fn backups_are_off_unless_enabled() {
+    let server = TestServer::with_config(Config {
+        admin_token: Some(ADMIN_TOKEN.to_string()),
+        ..Default::default()
+    })
+    .await;
+    // Without the route it's just the page for a metric called backup
+    let response = server.request(admin(Method::GET, "/admin/backup", Body::empty())).await;
+    assert!(!response.body.starts_with(b"SQLite format 3"));
+}
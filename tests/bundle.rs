@@ -0,0 +1,124 @@
+use std::io::Read;
+
+use axum::{
+    body::{Body, Bytes},
+    http::{Method, Request},
+    routing::get,
+    Router,
+};
+use flate2::read::GzDecoder;
+use serde_json::{json, Value};
+use somnial::{config::Config, test::TestServer};
+
+const ADMIN_TOKEN: &str = "secret";
+
+async fn server() -> TestServer {
+    TestServer::with_config(Config {
+        admin_token: Some(ADMIN_TOKEN.to_string()),
+        ..Default::default()
+    })
+    .await
+}
+
+async fn execute(server: &TestServer, sql: &str) {
+    sqlx::query(sql).execute(server.pool()).await.unwrap();
+}
+
+async fn scalar(server: &TestServer, sql: &str) -> Option<String> {
+    sqlx::query_scalar(sql).fetch_optional(server.pool()).await.unwrap()
+}
+
+/// A namespace with a setting of every kind a bundle carries, and a
+/// dashboard that also pins another namespace's chart.
+async fn source() -> TestServer {
+    let server = server().await;
+    server.seed("ci", "build_time", &[(1_700_000_000, 41.0), (1_700_000_060, 42.0)]).await;
+    server.seed("other", "build_time", &[(1_700_000_000, 7.0)]).await;
+    execute(&server, "INSERT INTO metric_meta (namespace, id, scale, unit) VALUES ('ci', 'build_time', 'log', 'ms')").await;
+    execute(
+        &server,
+        "INSERT INTO markers (namespace, timestamp, label, url, source) VALUES ('ci', 1700000030, 'v1.0', NULL, 'api')",
+    )
+    .await;
+    execute(&server, "INSERT INTO namespace_readmes (namespace, body, updated_at) VALUES ('ci', '# CI', 1700000000)").await;
+    for (slug, charts) in [
+        ("ci-glance", json!([{ "namespace": "ci", "id": "build_time" }])),
+        ("everything", json!([{ "namespace": "ci", "id": "build_time" }, { "namespace": "other", "id": "build_time" }])),
+    ] {
+        let body = json!({ "slug": slug, "title": slug, "charts": charts }).to_string();
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/api/v1/dashboards")
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .unwrap();
+        assert_eq!(server.request(request).await.status, 201);
+    }
+    server
+}
+
+async fn bundle(server: &TestServer) -> Bytes {
+    let bundle = server.get("/api/v1/namespaces/ci/bundle").await;
+    assert_eq!(bundle.status, 200, "{}", bundle.text());
+    bundle.body
+}
+
+async fn assert_settings_restored(server: &TestServer, namespace: &str) {
+    let meta = format!("SELECT scale || ' ' || unit FROM metric_meta WHERE namespace = '{}'", namespace);
+    assert_eq!(scalar(server, &meta).await.as_deref(), Some("log ms"));
+    let markers = format!("SELECT label FROM markers WHERE namespace = '{}'", namespace);
+    assert_eq!(scalar(server, &markers).await.as_deref(), Some("v1.0"));
+    let chart = scalar(server, "SELECT namespace || '/' || id FROM dashboard_charts WHERE slug = 'ci-glance'");
+    assert_eq!(chart.await, Some(format!("{}/build_time", namespace)));
+    // Dashboards pinning other namespaces stay where they are
+    assert_eq!(scalar(server, "SELECT slug FROM dashboards WHERE slug = 'everything'").await, None);
+}
+
+#[tokio::test]
+async fn bundles_carry_settings_markers_the_readme_and_dashboards() {
+    let source = source().await;
+    let bundle = bundle(&source).await;
+    // The edit token stays behind with the old server
+    let token = scalar(&source, "SELECT edit_token FROM dashboards WHERE slug = 'ci-glance'").await.unwrap();
+    let mut json = String::new();
+    GzDecoder::new(&bundle[..]).read_to_string(&mut json).unwrap();
+    assert!(json.contains("ci-glance") && !json.contains(&token));
+
+    let target = server().await;
+    let restored = target.post("/api/v1/namespaces/restored/bundle", bundle).await;
+    assert_eq!(restored.status, 200, "{}", restored.text());
+    assert_eq!(restored.json::<Value>()["points"], 2);
+    assert_settings_restored(&target, "restored").await;
+    let readme = scalar(&target, "SELECT body FROM namespace_readmes WHERE namespace = 'restored'").await;
+    assert_eq!(readme.as_deref(), Some("# CI"));
+}
+
+#[tokio::test]
+async fn importing_keeps_what_the_namespace_has() {
+    let bundle = bundle(&source().await).await;
+    let app = Router::new().route("/api/v1/namespaces/ci/bundle", get(move || async move { bundle }));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let target = server().await;
+    execute(&target, "INSERT INTO namespace_readmes (namespace, body, updated_at) VALUES ('ci', '# Ours', 1)").await;
+    for inserted in [2, 0] {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/api/v1/namespaces/ci/import")
+            .header("authorization", format!("Bearer {}", ADMIN_TOKEN))
+            .header("content-type", "application/json")
+            .body(Body::from(json!({ "source": format!("http://{}", address) }).to_string()))
+            .unwrap();
+        let imported = target.request(request).await;
+        assert_eq!(imported.status, 200, "{}", imported.text());
+        assert_eq!(imported.json::<Value>()["inserted"], inserted);
+    }
+
+    assert_settings_restored(&target, "ci").await;
+    let markers = scalar(&target, "SELECT CAST(COUNT(*) AS TEXT) FROM markers WHERE namespace = 'ci'").await;
+    assert_eq!(markers.as_deref(), Some("1"), "importing again doesn't add markers twice");
+    let readme = scalar(&target, "SELECT body FROM namespace_readmes WHERE namespace = 'ci'").await;
+    assert_eq!(readme.as_deref(), Some("# Ours"));
+}
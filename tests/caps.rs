@@ -0,0 +1,91 @@
+use axum::{
+    body::Body,
+    http::{Method, Request},
+};
+use serde_json::{json, Value};
+use somnial::{config::Config, test::TestServer};
+
+const ADMIN_TOKEN: &str = "secret";
+
+fn config() -> Config {
+    Config {
+        admin_token: Some(ADMIN_TOKEN.to_string()),
+        ..Default::default()
+    }
+}
+
+fn put_cap(uri: &str, max_points: i64) -> Request<Body> {
+    Request::builder()
+        .method(Method::PUT)
+        .uri(uri)
+        .header("authorization", format!("Bearer {}", ADMIN_TOKEN))
+        .header("content-type", "application/json")
+        .body(Body::from(json!({ "max_points": max_points }).to_string()))
+        .unwrap()
+}
+
+async fn values(server: &TestServer, id: &str) -> Vec<f64> {
+    let points = server.store().range("ci", id, i64::MIN, i64::MAX, i64::MAX).await.unwrap();
+    points.iter().map(|point| point.value).collect()
+}
+
+#[tokio::test]
+async fn writes_trim_capped_series_to_their_newest_points() {
+    let server = TestServer::with_config(config()).await;
+    server.seed("ci", "build_time", &[(1, 1.0), (2, 2.0), (3, 3.0)]).await;
+    server.seed("ci", "size", &[(1, 1.0), (2, 2.0), (3, 3.0)]).await;
+    assert_eq!(server.request(put_cap("/api/v1/namespaces/ci/point-cap", 3)).await.status, 200);
+    assert_eq!(server.request(put_cap("/api/v1/namespaces/ci/metrics/size/point-cap", 2)).await.status, 200);
+    // Setting a cap leaves the points until the next write
+    assert_eq!(values(&server, "size").await, [1.0, 2.0, 3.0]);
+
+    for id in ["build_time", "size"] {
+        let posted = server.post(&format!("/ci/{}?value=4", id), Body::empty()).await;
+        assert_eq!(posted.status, 200, "{}", posted.text());
+    }
+    assert_eq!(values(&server, "build_time").await, [2.0, 3.0, 4.0]);
+    assert_eq!(values(&server, "size").await, [3.0, 4.0]);
+
+    let fanned = server.post("/ci?m=build_time:5&m=size:5", Body::empty()).await;
+    assert_eq!(fanned.status, 200, "{}", fanned.text());
+    assert_eq!(values(&server, "build_time").await, [3.0, 4.0, 5.0]);
+    assert_eq!(values(&server, "size").await, [4.0, 5.0]);
+}
+
+#[tokio::test]
+async fn metric_caps_fall_back_to_the_namespace() {
+    let server = TestServer::with_config(config()).await;
+    let cap: Value = server.get("/api/v1/namespaces/ci/metrics/size/point-cap").await.json();
+    assert_eq!(cap, json!({ "max_points": null, "inherited": true }));
+
+    server.request(put_cap("/api/v1/namespaces/ci/point-cap", 100)).await;
+    let cap: Value = server.get("/api/v1/namespaces/ci/metrics/size/point-cap").await.json();
+    assert_eq!(cap, json!({ "max_points": 100, "inherited": true }));
+
+    server.request(put_cap("/api/v1/namespaces/ci/metrics/size/point-cap", 10)).await;
+    let cap: Value = server.get("/api/v1/namespaces/ci/metrics/size/point-cap").await.json();
+    assert_eq!(cap, json!({ "max_points": 10, "inherited": false }));
+
+    let delete = Request::builder()
+        .method(Method::DELETE)
+        .uri("/api/v1/namespaces/ci/metrics/size/point-cap")
+        .header("authorization", format!("Bearer {}", ADMIN_TOKEN))
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(server.request(delete).await.status, 204);
+    let cap: Value = server.get("/api/v1/namespaces/ci/metrics/size/point-cap").await.json();
+    assert_eq!(cap["max_points"], 100);
+}
+
+#[tokio::test]
+async fn caps_are_checked_and_need_the_admin_token() {
+    let server = TestServer::with_config(config()).await;
+    assert_eq!(server.request(put_cap("/api/v1/namespaces/ci/point-cap", 0)).await.status, 422);
+    assert_eq!(server.request(put_cap("/api/v1/namespaces/ci/point-cap", 10_000_001)).await.status, 422);
+
+    let mut anonymous = put_cap("/api/v1/namespaces/ci/point-cap", 10);
+    anonymous.headers_mut().remove("authorization");
+    assert_eq!(server.request(anonymous).await.status, 401);
+    let cap: Value = server.get("/api/v1/namespaces/ci/point-cap").await.json();
+    assert_eq!(cap["max_points"], Value::Null);
+}
@@ -0,0 +1,40 @@
+use axum::{body::Body, http::Request};
+use serde_json::Value;
+use somnial::test::TestServer;
+
+#[tokio::test]
+async fn check_fails_a_regression_against_the_median() {
+    let server = TestServer::new().await;
+    let now = chrono::Utc::now().timestamp();
+    server
+        .seed("ci", "build_time", &[(now - 300, 10.0), (now - 200, 11.0), (now - 100, 10.0), (now, 20.0)])
+        .await;
+
+    let failed = server.get("/ci/build_time/check?max_increase=5%25").await;
+    assert_eq!(failed.status, 409);
+    let body: Value = failed.json();
+    assert_eq!(body["verdict"], "fail");
+    assert_eq!(body["baseline"]["value"], 10.0);
+    assert_eq!(body["baseline"]["points"], 3);
+
+    let passed = server.get("/ci/build_time/check?max_increase=200%25").await;
+    assert_eq!(passed.status, 200);
+    assert_eq!(passed.json::<Value>()["verdict"], "pass");
+
+    let request = Request::get("/ci/missing/check?max_increase=5%25").body(Body::empty()).unwrap();
+    assert_eq!(server.request(request).await.status, 404);
+}
+
+#[tokio::test]
+async fn check_judges_the_last_point_recorded_at_the_latest_time() {
+    let server = TestServer::new().await;
+    let now = chrono::Utc::now().timestamp();
+    // Both at `now`; the second one written is the result being checked
+    server.seed("ci", "build_time", &[(now - 100, 10.0), (now, 30.0), (now, 10.5)]).await;
+
+    let response = server.get("/ci/build_time/check?max_increase=10%25&baseline=previous").await;
+    assert_eq!(response.status, 200, "{}", response.text());
+    let body: Value = response.json();
+    assert_eq!(body["latest"]["value"], 10.5);
+    assert_eq!(body["baseline"]["value"], 30.0);
+}
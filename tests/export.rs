@@ -0,0 +1,39 @@
+use std::io::{Cursor, Read};
+
+use serde_json::Value;
+use somnial::test::TestServer;
+
+#[tokio::test]
+async fn exports_a_namespace_as_a_zip() {
+    let server = TestServer::new().await;
+    server.seed("ci", "build_time", &[(1_700_000_000, 41.5), (1_700_000_060, 42.0)]).await;
+    server.seed("ci", "Build_Time", &[(1_700_000_000, 1.0)]).await;
+
+    let response = server.get("/ci/export.zip").await;
+    assert_eq!(response.status, 200, "{}", response.text());
+    assert_eq!(response.header("content-type"), Some("application/zip"));
+
+    let mut archive = zip::ZipArchive::new(Cursor::new(response.body)).unwrap();
+    let mut read = |name: &str| {
+        let mut text = String::new();
+        archive.by_name(name).unwrap().read_to_string(&mut text).unwrap();
+        text
+    };
+    let metadata: Value = serde_json::from_str(&read("metadata.json")).unwrap();
+    let metrics = metadata["metrics"].as_array().unwrap();
+    assert_eq!(metrics.len(), 2);
+    let csv_for = |id: &str| {
+        let metric = metrics.iter().find(|metric| metric["id"] == id).unwrap();
+        metric["csv"].as_str().unwrap().to_string()
+    };
+    // Names only differing by case get their own files
+    assert_ne!(csv_for("build_time").to_lowercase(), csv_for("Build_Time").to_lowercase());
+    let csv = read(&csv_for("build_time"));
+    assert!(csv.contains("1700000000,41.5") && csv.contains("1700000060,42"), "{}", csv);
+}
+
+#[tokio::test]
+async fn empty_namespaces_have_nothing_to_export() {
+    let server = TestServer::new().await;
+    assert_eq!(server.get("/nothing/export.zip").await.status, 404);
+}
@@ -0,0 +1,67 @@
+use axum::{body::Body, http::Request};
+use serde_json::{json, Value};
+use somnial::test::TestServer;
+
+async fn server() -> TestServer {
+    let server = TestServer::new().await;
+    server.seed("ci", "build.linux", &[(1_700_000_000, 40.0), (1_700_000_060, 42.0)]).await;
+    server.seed("ci", "build.mac", &[(1_700_000_000, 60.0)]).await;
+    server.seed("web", "p99", &[(1_700_000_000, 120.0)]).await;
+    server
+}
+
+#[tokio::test]
+async fn renders_targets_as_json_and_csv() {
+    let server = server().await;
+    let response = server
+        .get("/graphite/render?target=sumSeries(ci.build.*)&target=alias(web.p99,'latency')&from=1699999999&until=now")
+        .await;
+    assert_eq!(response.status, 200, "{}", response.text());
+    assert_eq!(
+        response.json::<Value>(),
+        json!([
+            { "target": "sumSeries(ci.build.linux,ci.build.mac)", "datapoints": [[100.0, 1_700_000_000], [42.0, 1_700_000_060]] },
+            { "target": "latency", "datapoints": [[120.0, 1_700_000_000]] },
+        ])
+    );
+
+    let csv = server.get("/graphite/render?target=ci.build.mac&from=1699999999&format=csv").await;
+    assert_eq!(csv.header("content-type"), Some("text/csv"));
+    assert_eq!(csv.text(), "ci.build.mac,2023-11-14 22:13:20,60\n");
+}
+
+#[tokio::test]
+async fn renders_form_posts() {
+    let server = server().await;
+    let request = Request::post("/graphite/render")
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(Body::from("target=scale(web.p99%2C0.5)&from=1699999999"))
+        .unwrap();
+    let response = server.request(request).await;
+    assert_eq!(response.json::<Value>()[0]["datapoints"], json!([[60.0, 1_700_000_000]]));
+}
+
+#[tokio::test]
+async fn rejects_bad_requests() {
+    let server = server().await;
+    for uri in [
+        "/graphite/render?target=median(ci.build.linux)",
+        "/graphite/render?target=nodot",
+        "/graphite/render?target=ci.build.linux&from=yesterday",
+        "/graphite/render?target=ci.build.linux&format=pickle",
+    ] {
+        assert_eq!(server.get(uri).await.status, 400, "{}", uri);
+    }
+}
+
+#[tokio::test]
+async fn finds_namespaces_then_ids() {
+    let server = server().await;
+    let namespaces = server.get("/graphite/metrics/find?query=*").await.json::<Value>();
+    let texts: Vec<&str> = namespaces.as_array().unwrap().iter().map(|node| node["text"].as_str().unwrap()).collect();
+    assert_eq!(texts, ["ci", "web"]);
+    assert_eq!(namespaces[0]["leaf"], 0);
+
+    let ids = server.get("/graphite/metrics/find?query=ci.build.*").await.json::<Value>();
+    assert_eq!(ids[1], json!({ "id": "ci.build.mac", "text": "build.mac", "leaf": 1, "expandable": 0, "allowChildren": 0 }));
+}
@@ -0,0 +1,43 @@
+use axum::{body::Body, http::Request};
+use serde_json::Value;
+use somnial::test::TestServer;
+
+#[tokio::test]
+async fn posted_points_show_up_in_the_points_api() {
+    let server = TestServer::new().await;
+    assert_eq!(server.post("/ci/build_time?value=41.5", Body::empty()).await.status, 200);
+    assert_eq!(server.post("/ci/build_time?value=42", Body::empty()).await.status, 200);
+
+    let request = Request::get("/ci/build_time").header("accept", "application/json").body(Body::empty()).unwrap();
+    let chart = server.request(request).await;
+    assert_eq!(chart.status, 200);
+    let body: Value = chart.json();
+    let values: Vec<f64> = body["points"]
+        .as_array()
+        .expect("points array")
+        .iter()
+        .map(|point| point["value"].as_f64().unwrap())
+        .collect();
+    assert_eq!(values, [41.5, 42.0]);
+}
+
+#[tokio::test]
+async fn fan_out_records_every_series() {
+    let server = TestServer::new().await;
+    let response = server.post("/ci?m=build_time:12&m=binary.size:3.5&sha=4b825dc642cb6eb9a060e54bf8d69288fbee4904", Body::empty()).await;
+    assert_eq!(response.status, 200, "{}", response.text());
+
+    let latest = server.store().latest("ci", "binary.size").await.unwrap().expect("a point");
+    assert_eq!(latest.value, 3.5);
+    assert_eq!(latest.sha.as_deref(), Some("4b825dc642cb6eb9a060e54bf8d69288fbee4904"));
+    assert!(server.store().latest("ci", "build_time").await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn bad_writes_are_rejected() {
+    let server = TestServer::new().await;
+    assert_eq!(server.post("/ci/build_time?value=fast", Body::empty()).await.status, 400);
+    assert_eq!(server.post("/ci?m=no_value", Body::empty()).await.status, 400);
+    assert_eq!(server.post("/ci?m=:1", Body::empty()).await.status, 400);
+    assert!(server.store().latest("ci", "build_time").await.unwrap().is_none());
+}
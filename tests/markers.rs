@@ -0,0 +1,78 @@
+use axum::{
+    body::Body,
+    http::{Method, Request},
+};
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use somnial::{config::Config, test::TestServer};
+
+const ADMIN_TOKEN: &str = "secret";
+const WEBHOOK_SECRET: &str = "shared";
+
+fn signature(body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(WEBHOOK_SECRET.as_bytes()).unwrap();
+    mac.update(body.as_bytes());
+    let digest: String = mac.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("sha256={}", digest)
+}
+
+fn marker(body: &str, headers: &[(&str, &str)]) -> Request<Body> {
+    let mut request = Request::builder()
+        .method(Method::POST)
+        .uri("/ci/markers")
+        .header("content-type", "application/json");
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    request.body(Body::from(body.to_string())).unwrap()
+}
+
+async fn labels(server: &TestServer) -> Vec<String> {
+    sqlx::query_scalar("SELECT label FROM markers WHERE namespace = 'ci' ORDER BY marker_id")
+        .fetch_all(server.pool())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn anyone_may_post_markers_without_a_secret() {
+    let server = TestServer::new().await;
+    let body = json!({ "label": "deploy", "timestamp": 1_700_000_000 }).to_string();
+    let posted = server.request(marker(&body, &[])).await;
+    assert_eq!(posted.status, 201, "{}", posted.text());
+    assert_eq!(posted.json::<Value>()["label"], "deploy");
+    assert_eq!(labels(&server).await, ["deploy"]);
+}
+
+#[tokio::test]
+async fn a_secret_needs_a_signature_or_a_token() {
+    let server = TestServer::with_config(Config {
+        admin_token: Some(ADMIN_TOKEN.to_string()),
+        github_webhook_secret: Some(WEBHOOK_SECRET.to_string()),
+        ..Default::default()
+    })
+    .await;
+    let plain = json!({ "label": "deploy" }).to_string();
+    let bearer = format!("Bearer {}", ADMIN_TOKEN);
+
+    assert_eq!(server.request(marker(&plain, &[])).await.status, 401);
+    assert_eq!(server.request(marker(&plain, &[("x-hub-signature-256", "sha256=00")])).await.status, 401);
+    assert_eq!(server.request(marker(&plain, &[("authorization", &bearer)])).await.status, 201);
+    let signed = signature(&plain);
+    assert_eq!(server.request(marker(&plain, &[("x-hub-signature-256", &signed)])).await.status, 201);
+
+    let release = json!({
+        "action": "published",
+        "release": { "tag_name": "v1.2.0", "html_url": "https://github.com/o/r/releases/v1.2.0" },
+    })
+    .to_string();
+    let github = ("x-github-event", "release");
+    // Tokens stand in for a signature on plain bodies alone
+    assert_eq!(server.request(marker(&release, &[github, ("authorization", &bearer)])).await.status, 401);
+    let signed = signature(&release);
+    let delivered = server.request(marker(&release, &[github, ("x-hub-signature-256", &signed)])).await;
+    assert_eq!(delivered.status, 201, "{}", delivered.text());
+
+    assert_eq!(labels(&server).await, ["deploy", "deploy", "release v1.2.0"]);
+}
@@ -0,0 +1,104 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    http::{Method, Request},
+};
+use serde_json::{json, Value};
+use somnial::{config::Config, store::MemoryStore, test::TestServer};
+
+const ADMIN_TOKEN: &str = "secret";
+const MERGE: &str = "/api/v1/namespaces/ci/metrics/build_secs/merge";
+
+fn config() -> Config {
+    Config {
+        admin_token: Some(ADMIN_TOKEN.to_string()),
+        ..Default::default()
+    }
+}
+
+async fn seed(server: &TestServer) {
+    server.seed("ci", "build_secs", &[(1_700_000_000, 1.0), (1_700_000_060, 2.0)]).await;
+    server.seed("ci", "build_time", &[(1_700_000_060, 20.0), (1_700_000_120, 30.0)]).await;
+    for id in ["build_secs", "build_time"] {
+        sqlx::query("INSERT INTO metric_meta (namespace, id, unit) VALUES ('ci', ?, ?)")
+            .bind(id)
+            .bind(id)
+            .execute(server.pool())
+            .await
+            .unwrap();
+    }
+}
+
+fn merge(into: &str) -> Request<Body> {
+    Request::builder()
+        .method(Method::POST)
+        .uri(MERGE)
+        .header("authorization", format!("Bearer {}", ADMIN_TOKEN))
+        .header("content-type", "application/json")
+        .body(Body::from(json!({ "into": into }).to_string()))
+        .unwrap()
+}
+
+async fn points(server: &TestServer, id: &str) -> Vec<(i64, f64)> {
+    let request = Request::get(format!("/ci/{}", id)).header("accept", "application/json").body(Body::empty()).unwrap();
+    let chart: Value = server.request(request).await.json();
+    chart["points"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|point| (point["timestamp"].as_i64().unwrap(), point["value"].as_f64().unwrap()))
+        .collect()
+}
+
+async fn assert_merges(server: TestServer) {
+    seed(&server).await;
+    let merged = server.request(merge("build_time")).await;
+    assert_eq!(merged.status, 200, "{}", merged.text());
+    let merged: Value = merged.json();
+    assert_eq!((merged["moved"].as_i64(), merged["dropped"].as_i64()), (Some(1), Some(1)));
+
+    // Where both had a point the target's stays
+    assert_eq!(points(&server, "build_time").await, [(1_700_000_000, 1.0), (1_700_000_060, 20.0), (1_700_000_120, 30.0)]);
+    assert!(server.store().latest("ci", "build_secs").await.unwrap().is_none());
+    let units: Vec<String> = sqlx::query_scalar("SELECT unit FROM metric_meta WHERE namespace = 'ci' ORDER BY id")
+        .fetch_all(server.pool())
+        .await
+        .unwrap();
+    assert_eq!(units, ["build_time"]);
+
+    let redirect = server.get("/ci/build_secs").await;
+    assert_eq!(redirect.status, 307);
+    assert_eq!(redirect.header("location"), Some("/ci/build_time"));
+}
+
+#[tokio::test]
+async fn merging_folds_one_series_into_another() {
+    assert_merges(TestServer::with_config(config()).await).await;
+}
+
+#[tokio::test]
+async fn merging_works_with_points_kept_in_memory() {
+    assert_merges(TestServer::with_store(config(), Arc::new(MemoryStore::new())).await).await;
+}
+
+#[tokio::test]
+async fn merges_are_checked_first() {
+    let server = TestServer::with_config(config()).await;
+    seed(&server).await;
+    let mut anonymous = merge("build_time");
+    anonymous.headers_mut().remove("authorization");
+    assert_eq!(server.request(anonymous).await.status, 401);
+    assert_eq!(server.request(merge("build_secs")).await.status, 422);
+    assert_eq!(server.request(merge("")).await.status, 422);
+
+    let missing = Request::builder()
+        .method(Method::POST)
+        .uri("/api/v1/namespaces/ci/metrics/missing/merge")
+        .header("authorization", format!("Bearer {}", ADMIN_TOKEN))
+        .header("content-type", "application/json")
+        .body(Body::from(json!({ "into": "build_time" }).to_string()))
+        .unwrap();
+    assert_eq!(server.request(missing).await.status, 404);
+    assert_eq!(points(&server, "build_secs").await.len(), 2);
+}
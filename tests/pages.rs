@@ -0,0 +1,72 @@
+use axum::{body::Body, http::Request};
+use serde_json::Value;
+use somnial::test::{TestResponse, TestServer};
+
+async fn get_accepting(server: &TestServer, uri: &str, accept: &str) -> TestResponse {
+    let request = Request::get(uri).header("accept", accept).body(Body::empty()).unwrap();
+    server.request(request).await
+}
+
+#[tokio::test]
+async fn chart_page_negotiates_html_and_json() {
+    let server = TestServer::new().await;
+    server.seed("ci", "build_time", &[(1_700_000_000, 41.0), (1_700_000_060, 42.0)]).await;
+
+    let page = get_accepting(&server, "/ci/build_time", "text/html").await;
+    assert_eq!(page.status, 200);
+    assert!(page.header("content-type").unwrap().starts_with("text/html"));
+    assert!(page.text().contains("build_time"));
+
+    let json = get_accepting(&server, "/ci/build_time", "application/json").await;
+    assert_eq!(json.status, 200);
+    assert!(json.header("content-type").unwrap().starts_with("application/json"));
+    assert!(json.text().contains("42"));
+}
+
+#[tokio::test]
+async fn badges_render_as_png_and_svg() {
+    let server = TestServer::new().await;
+    server.seed("ci", "build_time", &[(1_700_000_000, 41.0), (1_700_000_060, 42.0)]).await;
+
+    let png = server.get("/ci/build_time/badge.png").await;
+    assert_eq!(png.status, 200);
+    assert_eq!(png.header("content-type"), Some("image/png"));
+    assert!(png.body.starts_with(b"\x89PNG"));
+
+    let svg = server.get("/ci/build_time/badge.svg?theme=dark").await;
+    assert_eq!(svg.status, 200);
+    assert!(svg.text().starts_with("<svg"));
+
+    // Served again from the cache, and not at all to a client that has it
+    let etag = svg.header("etag").unwrap().to_string();
+    let request = Request::get("/ci/build_time/badge.svg?theme=dark")
+        .header("if-none-match", etag)
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(server.request(request).await.status, 304);
+
+    assert_eq!(server.get("/ci/build_time/badge.svg?window=1y").await.status, 400);
+}
+
+#[tokio::test]
+async fn daily_view_splits_days_in_the_viewers_zone() {
+    let server = TestServer::new().await;
+    // 23:30 UTC in July, which is already the next day in London
+    server.seed("ci", "build_time", &[(1_721_086_200, 5.0)]).await;
+
+    let utc = get_accepting(&server, "/ci/build_time/daily?since=0", "application/json").await;
+    assert_eq!(utc.json::<Value>()["days"][0]["day"], "2024-07-15");
+    let london = get_accepting(&server, "/ci/build_time/daily?since=0&tz=Europe/London", "application/json").await;
+    assert_eq!(london.json::<Value>()["days"][0]["day"], "2024-07-16");
+
+    let bad = get_accepting(&server, "/ci/build_time/daily?tz=Mars/Olympus_Mons", "application/json").await;
+    assert_eq!(bad.status, 400);
+}
+
+#[tokio::test]
+async fn series_with_no_points_are_empty() {
+    let server = TestServer::new().await;
+    let response = get_accepting(&server, "/ci/nothing", "application/json").await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.json::<Value>()["points"], serde_json::json!([]));
+}
@@ -0,0 +1,97 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    http::{Method, Request},
+};
+use serde_json::Value;
+use somnial::{config::Config, store::MemoryStore, test::TestServer};
+
+const ADMIN_TOKEN: &str = "secret";
+
+fn config() -> Config {
+    Config { admin_token: Some(ADMIN_TOKEN.to_string()), ..Default::default() }
+}
+
+fn delete(uri: &str) -> Request<Body> {
+    Request::builder()
+        .method(Method::DELETE)
+        .uri(uri)
+        .header("authorization", format!("Bearer {}", ADMIN_TOKEN))
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn seed(server: &TestServer) {
+    server.seed("ci", "build_time", &[(1_700_000_000, 41.0), (1_700_000_060, 42.0)]).await;
+    server.seed("ci", "binary_size", &[(1_700_000_000, 1.0)]).await;
+    server.seed("other", "build_time", &[(1_700_000_000, 7.0)]).await;
+    sqlx::query("INSERT INTO metric_meta (namespace, id, scale) VALUES ('ci', 'build_time', 'log')")
+        .execute(server.pool())
+        .await
+        .unwrap();
+}
+
+/// Asks for a confirmation token, checks what it says would go, and
+/// redeems it.
+async fn purge(server: &TestServer) {
+    let pending = server.request(delete("/ci")).await;
+    assert_eq!(pending.status, 200, "{}", pending.text());
+    let pending: Value = pending.json();
+    assert_eq!(pending["metrics"], 2);
+    assert_eq!(pending["points"], 3);
+    assert!(server.store().latest("ci", "build_time").await.unwrap().is_some());
+
+    let confirm = pending["confirm"].as_str().unwrap();
+    let deleted = server.request(delete(&format!("/ci?confirm={}", confirm))).await;
+    assert_eq!(deleted.status, 204, "{}", deleted.text());
+}
+
+async fn assert_only_other_namespace_left(server: &TestServer) {
+    assert!(server.store().latest("ci", "build_time").await.unwrap().is_none());
+    assert!(server.store().latest("ci", "binary_size").await.unwrap().is_none());
+    assert!(server.store().latest("other", "build_time").await.unwrap().is_some());
+    let meta: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM metric_meta WHERE namespace = 'ci'")
+        .fetch_one(server.pool())
+        .await
+        .unwrap();
+    assert_eq!(meta, 0);
+}
+
+#[tokio::test]
+async fn purging_takes_points_and_settings() {
+    let server = TestServer::with_config(config()).await;
+    seed(&server).await;
+    purge(&server).await;
+    assert_only_other_namespace_left(&server).await;
+}
+
+#[tokio::test]
+async fn purging_works_with_points_kept_elsewhere() {
+    let server = TestServer::with_store(config(), Arc::new(MemoryStore::new())).await;
+    seed(&server).await;
+    purge(&server).await;
+    assert_only_other_namespace_left(&server).await;
+}
+
+#[tokio::test]
+async fn purging_needs_the_admin_token_and_a_current_confirmation() {
+    let server = TestServer::with_config(config()).await;
+    seed(&server).await;
+
+    let anonymous = Request::builder().method(Method::DELETE).uri("/ci").body(Body::empty()).unwrap();
+    assert_eq!(server.request(anonymous).await.status, 401);
+
+    let first: Value = server.request(delete("/ci")).await.json();
+    let second: Value = server.request(delete("/ci")).await.json();
+    let stale = server.request(delete(&format!("/ci?confirm={}", first["confirm"].as_str().unwrap()))).await;
+    assert_eq!(stale.status, 409);
+    assert_eq!(server.request(delete("/ci?confirm=wrong")).await.status, 409);
+    assert!(server.store().latest("ci", "build_time").await.unwrap().is_some());
+
+    let confirmed = server.request(delete(&format!("/ci?confirm={}", second["confirm"].as_str().unwrap()))).await;
+    assert_eq!(confirmed.status, 204);
+    // A token only works once
+    let again = server.request(delete(&format!("/ci?confirm={}", second["confirm"].as_str().unwrap()))).await;
+    assert_eq!(again.status, 409);
+}
@@ -0,0 +1,173 @@
+//! The same checks against each [`MetricStore`]: SQLite, the in-memory one
+//! and PostgreSQL, which runs only when `SOMNIAL_TEST_POSTGRES_URL` names a
+//! database the tests may write to.
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{body::Body, http::Request};
+use serde_json::Value;
+use somnial::{
+    config::Config,
+    store::{MemoryStore, MetricStore, PostgresStore},
+    test::TestServer,
+    MetricPoint,
+};
+
+const T: i64 = 1_700_000_000;
+
+async fn postgres() -> Option<PostgresStore> {
+    let url = std::env::var("SOMNIAL_TEST_POSTGRES_URL").ok()?;
+    Some(PostgresStore::connect(&url).await.expect("failed to connect to SOMNIAL_TEST_POSTGRES_URL"))
+}
+
+/// A namespace no earlier run left points in, since the database outlives
+/// the tests.
+fn namespace(name: &str) -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+    format!("{}_{}", name, nanos)
+}
+
+fn point(timestamp: i64, value: f64) -> MetricPoint {
+    MetricPoint {
+        timestamp,
+        value,
+        sha: None,
+        branch: None,
+    }
+}
+
+fn timestamps(points: &[MetricPoint]) -> Vec<i64> {
+    points.iter().map(|point| point.timestamp).collect()
+}
+
+async fn check_series(store: &dyn MetricStore, ns: &str) {
+    let points = [("build_time", point(T, 1.0)), ("build_time", point(T + 60, 2.0)), ("size", point(T, 9.0))];
+    assert_eq!(store.insert(ns, &points).await.unwrap(), 3);
+    // Writing the same points again stores nothing new
+    assert_eq!(store.insert_new(ns, &points).await.unwrap(), 0);
+
+    assert_eq!(timestamps(&store.range(ns, "build_time", T, T + 60, 10).await.unwrap()), [T, T + 60]);
+    assert_eq!(timestamps(&store.range(ns, "build_time", T, T + 60, 1).await.unwrap()), [T]);
+    assert_eq!(store.latest(ns, "build_time").await.unwrap().unwrap().value, 2.0);
+    assert_eq!(store.ids(ns).await.unwrap(), ["build_time", "size"]);
+    let summary = store.namespace_summary(ns).await.unwrap();
+    assert_eq!((summary.metrics, summary.points), (2, 3));
+
+    let aggregate = store.aggregate(ns, "build_time", T, T + 60).await.unwrap().unwrap();
+    assert_eq!((aggregate.count, aggregate.min, aggregate.max), (2, 1.0, 2.0));
+    assert!(store.aggregate(ns, "build_time", T + 120, T + 180).await.unwrap().is_none());
+
+    // Renames refuse to land on a series with points
+    assert_eq!(store.rename(ns, "size", "build_time").await.unwrap(), None);
+    assert_eq!(store.rename(ns, "size", "binary_size").await.unwrap(), Some(1));
+    assert!(store.latest(ns, "size").await.unwrap().is_none());
+
+    let (moved, dropped) = store.merge(ns, "binary_size", "build_time").await.unwrap();
+    assert_eq!((moved, dropped), (vec![], 1));
+    assert_eq!(store.trim(ns, "build_time", 1).await.unwrap(), [T]);
+    assert_eq!(store.delete_namespace(ns).await.unwrap(), 1);
+    assert!(store.ids(ns).await.unwrap().is_empty());
+}
+
+async fn check_trash(store: &dyn MetricStore, ns: &str) {
+    store.insert(ns, &[("build_time", point(T, 1.0)), ("build_time", point(T + 60, 2.0))]).await.unwrap();
+    assert_eq!(store.trash(ns, "build_time", T + 120).await.unwrap(), 2);
+    assert!(store.latest(ns, "build_time").await.unwrap().is_none());
+    let trashed: Vec<_> = store.trashed().await.unwrap().into_iter().filter(|series| series.namespace == ns).collect();
+    assert_eq!(trashed.len(), 1);
+    assert_eq!((trashed[0].points, trashed[0].deleted_at), (2, T + 120));
+
+    assert_eq!(store.untrash(ns, "build_time").await.unwrap(), Some(vec![T, T + 60]));
+    assert_eq!(store.untrash(ns, "build_time").await.unwrap(), None);
+    assert_eq!(timestamps(&store.range(ns, "build_time", i64::MIN, i64::MAX, i64::MAX).await.unwrap()), [T, T + 60]);
+    store.delete_namespace(ns).await.unwrap();
+}
+
+async fn check_retention(store: &dyn MetricStore, ns: &str) {
+    let old = [("build_time", point(T, 1.0)), ("stale", point(T, 5.0))];
+    store.insert(ns, &old).await.unwrap();
+    let through = store.watermark().await.unwrap();
+    store.insert(ns, &[("build_time", point(T + 60, 2.0)), ("late", point(T - 60, 3.0))]).await.unwrap();
+
+    // A series with a point from the cutoff on is kept whole
+    assert_eq!(store.expire(ns, "build_time", T + 60).await.unwrap(), 0);
+    assert_eq!(store.expire(ns, "stale", T + 60).await.unwrap(), 1);
+
+    // Points stored after the watermark wait for the next pass
+    let cutoffs = HashMap::from([(ns.to_string(), T + 60)]);
+    let dropped: u64 = store.drop_expired(&cutoffs, through).await.unwrap().values().sum();
+    let pruned = store.prune(ns, T + 60, through, 100).await.unwrap();
+    assert_eq!(dropped + pruned, 1);
+    assert_eq!(timestamps(&store.range(ns, "build_time", i64::MIN, i64::MAX, i64::MAX).await.unwrap()), [T + 60]);
+    assert!(store.latest(ns, "late").await.unwrap().is_some());
+    store.delete_namespace(ns).await.unwrap();
+}
+
+#[tokio::test]
+async fn sqlite_keeps_series() {
+    check_series(TestServer::new().await.store(), "ci").await;
+}
+
+#[tokio::test]
+async fn sqlite_trashes_and_restores_series() {
+    check_trash(TestServer::new().await.store(), "ci").await;
+}
+
+#[tokio::test]
+async fn sqlite_expires_old_points() {
+    check_retention(TestServer::new().await.store(), "ci").await;
+}
+
+#[tokio::test]
+async fn memory_keeps_series() {
+    check_series(&MemoryStore::new(), "ci").await;
+}
+
+#[tokio::test]
+async fn memory_trashes_and_restores_series() {
+    check_trash(&MemoryStore::new(), "ci").await;
+}
+
+#[tokio::test]
+async fn memory_expires_old_points() {
+    check_retention(&MemoryStore::new(), "ci").await;
+}
+
+#[tokio::test]
+async fn postgres_keeps_series() {
+    let Some(store) = postgres().await else { return };
+    check_series(&store, &namespace("series")).await;
+}
+
+#[tokio::test]
+async fn postgres_trashes_and_restores_series() {
+    let Some(store) = postgres().await else { return };
+    check_trash(&store, &namespace("trash")).await;
+}
+
+#[tokio::test]
+async fn postgres_expires_old_points() {
+    let Some(store) = postgres().await else { return };
+    check_retention(&store, &namespace("retention")).await;
+}
+
+#[tokio::test]
+async fn the_server_reads_and_writes_points_in_postgres() {
+    let Some(store) = postgres().await else { return };
+    let ns = namespace("server");
+    let server = TestServer::with_store(Config::default(), Arc::new(store)).await;
+    let posted = server.post(&format!("/{}/build_time?value=41.5", ns), Body::empty()).await;
+    assert_eq!(posted.status, 200, "{}", posted.text());
+
+    let request = Request::get(format!("/{}/build_time", ns)).header("accept", "application/json").body(Body::empty());
+    let chart: Value = server.request(request.unwrap()).await.json();
+    assert_eq!(chart["points"][0]["value"].as_f64(), Some(41.5));
+    // Nothing went to the settings database
+    let local: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM metrics").fetch_one(server.pool()).await.unwrap();
+    assert_eq!(local, 0);
+    server.store().delete_namespace(&ns).await.unwrap();
+}
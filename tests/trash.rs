@@ -0,0 +1,84 @@
+use axum::{
+    body::Body,
+    http::{Method, Request},
+};
+use serde_json::Value;
+use somnial::{config::Config, test::TestServer};
+
+const ADMIN_TOKEN: &str = "secret";
+const WEEK: i64 = 7 * 86400;
+
+async fn server(delete_grace_days: Option<u32>) -> TestServer {
+    let server = TestServer::with_config(Config {
+        admin_token: Some(ADMIN_TOKEN.to_string()),
+        delete_grace_days,
+        ..Default::default()
+    })
+    .await;
+    server.seed("ci", "build_time", &[(1_700_000_000, 41.0), (1_700_000_060, 42.0)]).await;
+    sqlx::query("INSERT INTO metric_meta (namespace, id, unit) VALUES ('ci', 'build_time', 'ms')")
+        .execute(server.pool())
+        .await
+        .unwrap();
+    server
+}
+
+fn admin(method: Method, uri: &str) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("authorization", format!("Bearer {}", ADMIN_TOKEN))
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn unit(server: &TestServer) -> Option<String> {
+    sqlx::query_scalar("SELECT unit FROM metric_meta WHERE namespace = 'ci' AND id = 'build_time'")
+        .fetch_optional(server.pool())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn deleted_metrics_can_be_restored_from_the_trash() {
+    let server = server(Some(7)).await;
+    assert_eq!(server.request(admin(Method::DELETE, "/ci/build_time")).await.status, 204);
+    assert!(server.store().latest("ci", "build_time").await.unwrap().is_none());
+    // Settings wait in case the metric comes back
+    assert_eq!(unit(&server).await.as_deref(), Some("ms"));
+
+    let listed: Value = server.request(admin(Method::GET, "/admin/deleted")).await.json();
+    let deleted = &listed["deleted"][0];
+    assert_eq!(deleted["namespace"], "ci");
+    assert_eq!(deleted["id"], "build_time");
+    assert_eq!(deleted["purge_at"].as_i64().unwrap() - deleted["deleted_at"].as_i64().unwrap(), WEEK);
+
+    let restored = server.request(admin(Method::POST, "/admin/deleted/ci/build_time/restore")).await;
+    assert_eq!(restored.status, 200, "{}", restored.text());
+    assert_eq!(restored.json::<Value>()["points"], 2);
+    assert_eq!(server.store().latest("ci", "build_time").await.unwrap().unwrap().value, 42.0);
+    let listed: Value = server.request(admin(Method::GET, "/admin/deleted")).await.json();
+    assert_eq!(listed["deleted"].as_array().unwrap().len(), 0);
+}
+
+#[tokio::test]
+async fn without_a_grace_period_deletes_are_final() {
+    let server = server(None).await;
+    assert_eq!(server.request(admin(Method::DELETE, "/ci/build_time")).await.status, 204);
+    assert!(server.store().latest("ci", "build_time").await.unwrap().is_none());
+    assert_eq!(unit(&server).await, None);
+    let restored = server.request(admin(Method::POST, "/admin/deleted/ci/build_time/restore")).await;
+    assert_eq!(restored.status, 404);
+}
+
+#[tokio::test]
+async fn the_trash_takes_the_admin_token() {
+    let server = server(Some(7)).await;
+    assert_eq!(server.send(Method::DELETE, "/ci/build_time", Body::empty()).await.status, 401);
+    assert_eq!(server.get("/admin/deleted").await.status, 401);
+    assert_eq!(server.post("/admin/deleted/ci/build_time/restore", Body::empty()).await.status, 401);
+    assert!(server.store().latest("ci", "build_time").await.unwrap().is_some());
+
+    assert_eq!(server.request(admin(Method::DELETE, "/ci/missing")).await.status, 404);
+    assert_eq!(server.request(admin(Method::POST, "/admin/deleted/ci/missing/restore")).await.status, 404);
+}
@@ -0,0 +1,78 @@
+use axum::{
+    body::Body,
+    http::{Method, Request},
+};
+use serde_json::{json, Value};
+use somnial::{config::Config, test::TestServer};
+
+const ADMIN_TOKEN: &str = "secret";
+const HOOKS: &str = "/api/v1/namespaces/ci/webhooks";
+
+async fn server(allow_private_webhook_urls: bool) -> TestServer {
+    TestServer::with_config(Config {
+        admin_token: Some(ADMIN_TOKEN.to_string()),
+        allow_private_webhook_urls,
+        ..Default::default()
+    })
+    .await
+}
+
+fn admin(method: Method, uri: &str, body: Value) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("authorization", format!("Bearer {}", ADMIN_TOKEN))
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn webhooks_are_registered_listed_and_removed() {
+    let server = server(true).await;
+    let body = json!({ "url": "http://127.0.0.1:9/hook", "batch_seconds": 60 });
+    let created = server.request(admin(Method::POST, HOOKS, body)).await;
+    assert_eq!(created.status, 201, "{}", created.text());
+    let id = created.json::<Value>()["id"].as_i64().unwrap();
+
+    let listed: Value = server.request(admin(Method::GET, HOOKS, json!({}))).await.json();
+    assert_eq!(listed["webhooks"][0]["url"], "http://127.0.0.1:9/hook");
+    assert_eq!(listed["webhooks"][0]["batch_seconds"], 60);
+
+    let uri = format!("{}/{}", HOOKS, id);
+    assert_eq!(server.request(admin(Method::DELETE, &uri, json!({}))).await.status, 204);
+    assert_eq!(server.request(admin(Method::DELETE, &uri, json!({}))).await.status, 404);
+    let listed: Value = server.request(admin(Method::GET, HOOKS, json!({}))).await.json();
+    assert_eq!(listed["webhooks"], json!([]));
+}
+
+#[tokio::test]
+async fn webhooks_take_the_admin_token() {
+    let server = server(true).await;
+    let mut anonymous = admin(Method::POST, HOOKS, json!({ "url": "https://example.com/hook" }));
+    anonymous.headers_mut().remove("authorization");
+    assert_eq!(server.request(anonymous).await.status, 401);
+    assert_eq!(server.get(HOOKS).await.status, 401);
+
+    let created = server.request(admin(Method::POST, HOOKS, json!({ "url": "https://example.com/hook" }))).await;
+    let uri = format!("{}/{}", HOOKS, created.json::<Value>()["id"]);
+    assert_eq!(server.send(Method::DELETE, &uri, Body::empty()).await.status, 401);
+}
+
+#[tokio::test]
+async fn private_and_malformed_hooks_are_refused() {
+    let server = server(false).await;
+    for body in [
+        json!({ "url": "http://127.0.0.1:9/hook" }),
+        json!({ "url": "http://[::1]/hook" }),
+        json!({ "url": "http://[64:ff9b::a00:1]/hook" }),
+        json!({ "url": "http://[2002:c0a8:101::1]/hook" }),
+        json!({ "url": "ftp://example.com/hook" }),
+        json!({ "url": "https://8.8.8.8/hook", "batch_seconds": 3601 }),
+    ] {
+        let created = server.request(admin(Method::POST, HOOKS, body.clone())).await;
+        assert_eq!(created.status, 422, "{}", body);
+    }
+    let listed: Value = server.request(admin(Method::GET, HOOKS, json!({}))).await.json();
+    assert_eq!(listed["webhooks"], json!([]));
+}